@@ -22,6 +22,7 @@ use serde_json::{
 
 use std::{
     convert::Infallible,
+    net::SocketAddr,
     sync::{
         Arc,
         RwLock,
@@ -32,7 +33,22 @@ use std::{
 use sled::Db;
 
 use crate::{
-    admin::methods::execute_method,
+    admin::{
+        audit::{
+            AuditContext,
+            AuditLog,
+        },
+        auth::{
+            authenticate,
+            AdminRole,
+        },
+        error::AdminError,
+        listener::Trackers,
+        methods::{
+            execute_method,
+            required_role,
+        },
+    },
     balancer::format::incoming_to_value,
     Rpc,
     Settings,
@@ -67,21 +83,21 @@ macro_rules! get_response {
         $poverty_list_rwlock:expr,
         $config:expr,
         $cache:expr,
+        $trackers:expr,
     ) => {{
-        // Execute the request and store it into rx
-        let mut rx = match execute_method(
+        // Execute the request and store it into rx, along with the outcome for the audit log
+        let (mut rx, outcome) = match execute_method(
             $tx,
             $rpc_list_rwlock,
             $poverty_list_rwlock,
             Arc::clone(&$config),
             Arc::clone(&$cache),
-        ).await {
-            Ok(rx) => rx,
-            Err(err) => json!({
-                "id": Null,
-                "jsonrpc": "2.0",
-                "result": err.to_string(),
-            }),
+            $trackers,
+        )
+        .await
+        {
+            Ok(rx) => (rx, "ok".to_string()),
+            Err(err) => (error_response(&err), format!("error: {}", err)),
         };
 
         // Set the id to whatever it was
@@ -89,10 +105,18 @@ macro_rules! get_response {
 
         let rx_str = rx.to_string();
 
-        rx_str
+        (rx_str, outcome)
     }};
 }
 
+fn error_response(err: &AdminError) -> Value {
+    json!({
+        "id": Null,
+        "jsonrpc": "2.0",
+        "result": err.to_string(),
+    })
+}
+
 // Execute request and construct a HTTP response
 async fn forward_body(
     mut tx: Value,
@@ -100,6 +124,8 @@ async fn forward_body(
     poverty_list_rwlock: &Arc<RwLock<Vec<Rpc>>>,
     cache: Arc<Db>,
     config: Arc<RwLock<Settings>>,
+    trackers: Trackers,
+    audit: Option<(&AuditLog, &AuditContext)>,
 ) -> Result<hyper::Response<Full<Bytes>>, Infallible> {
     // Get the id of the request and set it to 0 for caching
     //
@@ -107,9 +133,22 @@ async fn forward_body(
     // whole request and we don't want the ID as it's arbitrary
     // and does not impact the request result.
     let id = tx["id"].take().as_u64().unwrap_or(0);
+    let (method, params) = (tx["method"].clone(), tx["params"].clone());
 
     // Get the response from either the DB or from a RPC. If it timeouts, retry.
-    let rax = get_response!(tx, id, rpc_list_rwlock, poverty_list_rwlock, config, cache,);
+    let (rax, outcome) = get_response!(
+        tx,
+        id,
+        rpc_list_rwlock,
+        poverty_list_rwlock,
+        config,
+        cache,
+        trackers,
+    );
+
+    if let Some((audit, context)) = audit {
+        audit.record(context, &method, &params, &outcome);
+    }
 
     // Convert rx to bytes and but it in a Buf
     let body = hyper::body::Bytes::from(rax);
@@ -130,7 +169,36 @@ pub async fn accept_admin_request(
     poverty_list_rwlock: Arc<RwLock<Vec<Rpc>>>,
     cache: Arc<Db>,
     config: Arc<RwLock<Settings>>,
+    trackers: Trackers,
+    (audit, remote): (Arc<AuditLog>, SocketAddr),
 ) -> Result<hyper::Response<Full<Bytes>>, Infallible> {
+    // If admin keys are set, every request needs one. Without them everyone is a writer.
+    let (principal, role) = {
+        let config_guard = config.read().unwrap();
+        let keys = &config_guard.admin.keys;
+
+        if keys.is_empty() {
+            ("anonymous".to_string(), AdminRole::Write)
+        } else {
+            match authenticate(tx.headers(), keys) {
+                Some(key) => (key.name.clone(), key.role),
+                None => {
+                    let context = AuditContext {
+                        remote,
+                        principal: "unknown".to_string(),
+                    };
+                    audit.record(&context, &Null, &Null, "unauthorized");
+
+                    return Ok(hyper::Response::builder()
+                        .status(401)
+                        .body(Full::new(Bytes::from("Unauthorized or invalid admin key")))
+                        .unwrap());
+                }
+            }
+        }
+    };
+    let context = AuditContext { remote, principal };
+
     let mut tx = incoming_to_value(tx).await.unwrap();
 
     // If we have JWT enabled check that tx is valid
@@ -164,9 +232,30 @@ pub async fn accept_admin_request(
         });
     }
 
+    // Read-only keys can't call methods that change anything
+    if role < required_role(tx["method"].as_str().unwrap_or_default()) {
+        audit.record(&context, &tx["method"], &tx["params"], "denied");
+
+        let mut rx = error_response(&AdminError::Forbidden);
+        rx["id"] = tx["id"].clone();
+        return Ok(hyper::Response::builder()
+            .status(403)
+            .body(Full::new(Bytes::from(rx.to_string())))
+            .unwrap());
+    }
+
     // Send the request off to be processed
     let time = Instant::now();
-    let response = forward_body(tx, &rpc_list_rwlock, &poverty_list_rwlock, cache, config).await;
+    let response = forward_body(
+        tx,
+        &rpc_list_rwlock,
+        &poverty_list_rwlock,
+        cache,
+        config,
+        trackers,
+        Some((&audit, &context)),
+    )
+    .await;
     let time = time.elapsed();
     println!("\x1b[35mInfo:\x1b[0m Request time: {:?}", time);
 
@@ -180,8 +269,10 @@ mod tests {
 
     // Helper function to create a test Settings config
     fn create_test_settings() -> Arc<RwLock<Settings>> {
-        let mut config = Settings::default();
-        config.do_clear = true;
+        let mut config = Settings {
+            do_clear: true,
+            ..Default::default()
+        };
         config.admin.key = DecodingKey::from_secret(b"some-key");
         Arc::new(RwLock::new(config))
     }
@@ -216,6 +307,8 @@ mod tests {
             &poverty_list,
             cache.clone(),
             settings,
+            Trackers::default(),
+            None,
         )
         .await;
 
@@ -0,0 +1,102 @@
+use std::{
+    fs::{
+        File,
+        OpenOptions,
+    },
+    io::Write,
+    net::SocketAddr,
+    path::Path,
+    sync::Mutex,
+};
+
+use chrono::Utc;
+use serde_json::{
+    json,
+    Value,
+};
+
+// Record of every admin request, who made it and what came of it.
+//
+// Entries are always printed, and appended to a file as JSON lines if one is set.
+#[derive(Debug, Default)]
+pub struct AuditLog {
+    file: Option<Mutex<File>>,
+}
+
+// Who is making an admin request
+#[derive(Debug, Clone)]
+pub struct AuditContext {
+    pub remote: SocketAddr,
+    // Name of the admin key used, `anonymous` if auth is disabled
+    pub principal: String,
+}
+
+impl AuditLog {
+    pub fn new(path: Option<&Path>) -> std::io::Result<Self> {
+        let file = match path {
+            Some(path) => {
+                Some(Mutex::new(
+                    OpenOptions::new().create(true).append(true).open(path)?,
+                ))
+            }
+            None => None,
+        };
+
+        Ok(Self { file })
+    }
+
+    pub fn record(&self, context: &AuditContext, method: &Value, params: &Value, outcome: &str) {
+        let entry = json!({
+            "time": Utc::now().to_rfc3339(),
+            "remote": context.remote.to_string(),
+            "principal": context.principal,
+            "method": method,
+            "params": params,
+            "outcome": outcome,
+        });
+
+        println!("\x1b[35mInfo:\x1b[0m Admin audit: {}", entry);
+
+        if let Some(file) = &self.file {
+            let mut file = file.lock().unwrap_or_else(|e| e.into_inner());
+            if let Err(err) = writeln!(file, "{}", entry) {
+                println!(
+                    "\x1b[31mErr:\x1b[0m Could not write to admin audit log: {}",
+                    err
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_audit_log_file() {
+        let path = std::env::temp_dir().join(format!("blutgang-audit-{}.log", std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        let audit = AuditLog::new(Some(&path)).unwrap();
+        let context = AuditContext {
+            remote: "127.0.0.1:1234".parse().unwrap(),
+            principal: "ops".to_string(),
+        };
+        audit.record(&context, &json!("blutgang_flush_cache"), &json!([]), "ok");
+        audit.record(&context, &json!("blutgang_quit"), &Value::Null, "denied");
+
+        let lines: Vec<Value> = fs::read_to_string(&path)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["principal"], "ops");
+        assert_eq!(lines[0]["method"], "blutgang_flush_cache");
+        assert_eq!(lines[1]["outcome"], "denied");
+    }
+}
@@ -0,0 +1,100 @@
+use hyper::header::HeaderMap;
+
+// What an admin key is allowed to do
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum AdminRole {
+    // Status and config methods
+    Read,
+    // Everything, including changing the RPC lists, TTLs and the cache
+    Write,
+}
+
+impl AdminRole {
+    pub fn from_config(role: &str) -> Option<Self> {
+        match role {
+            "read" => Some(AdminRole::Read),
+            "write" => Some(AdminRole::Write),
+            _ => None,
+        }
+    }
+}
+
+// A named admin credential, as set in the `[admin.keys]` table
+#[derive(Clone)]
+pub struct AdminKey {
+    pub name: String,
+    pub key: String,
+    pub role: AdminRole,
+}
+
+// Compare in constant time so keys can't be guessed byte by byte
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+
+    a.iter().zip(b).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+// Find the admin key sent in the `Authorization: Bearer <key>` header.
+//
+// Returns None if the header is missing or the key is unknown.
+pub fn authenticate<'a>(headers: &HeaderMap, keys: &'a [AdminKey]) -> Option<&'a AdminKey> {
+    let header = headers.get("authorization")?.to_str().ok()?;
+    let key = header.strip_prefix("Bearer ")?.trim();
+
+    keys.iter()
+        .find(|admin_key| constant_time_eq(admin_key.key.as_bytes(), key.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::header::HeaderValue;
+
+    fn keys() -> Vec<AdminKey> {
+        vec![
+            AdminKey {
+                name: "dashboard".to_string(),
+                key: "read-key".to_string(),
+                role: AdminRole::Read,
+            },
+            AdminKey {
+                name: "ops".to_string(),
+                key: "write-key".to_string(),
+                role: AdminRole::Write,
+            },
+        ]
+    }
+
+    #[test]
+    fn test_authenticate() {
+        let keys = keys();
+        let mut headers = HeaderMap::new();
+        assert!(authenticate(&headers, &keys).is_none());
+
+        headers.insert(
+            "authorization",
+            HeaderValue::from_static("Bearer write-key"),
+        );
+        let key = authenticate(&headers, &keys).unwrap();
+        assert_eq!(key.name, "ops");
+        assert_eq!(key.role, AdminRole::Write);
+
+        headers.insert(
+            "authorization",
+            HeaderValue::from_static("Bearer wrong-key"),
+        );
+        assert!(authenticate(&headers, &keys).is_none());
+
+        headers.insert("authorization", HeaderValue::from_static("read-key"));
+        assert!(authenticate(&headers, &keys).is_none());
+    }
+
+    #[test]
+    fn test_role_order() {
+        assert!(AdminRole::Read < AdminRole::Write);
+        assert_eq!(AdminRole::from_config("read"), Some(AdminRole::Read));
+        assert_eq!(AdminRole::from_config("root"), None);
+    }
+}
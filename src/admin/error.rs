@@ -13,7 +13,16 @@ pub enum AdminError {
     RwError,
     Inaccessible,
     OutOfBounds,
+    SloDisabled,
+    TalkersDisabled,
+    RateLimitingDisabled,
+    MetricsDisabled,
+    EventStoreDisabled,
+    StandbyDisabled,
+    SchemaChecksDisabled,
+    Forbidden,
     InvalidResponse(String),
+    InvalidConfig(String),
 }
 
 impl std::fmt::Display for AdminError {
@@ -30,7 +39,16 @@ impl std::fmt::Display for AdminError {
             AdminError::OutOfBounds => {
                 write!(f, "Request out of bounds.")
             }
+            AdminError::SloDisabled => write!(f, "SLO tracking is disabled"),
+            AdminError::TalkersDisabled => write!(f, "Top talkers tracking is disabled"),
+            AdminError::RateLimitingDisabled => write!(f, "Rate limiting is disabled"),
+            AdminError::MetricsDisabled => write!(f, "Latency metrics are disabled"),
+            AdminError::EventStoreDisabled => write!(f, "The event store is disabled"),
+            AdminError::StandbyDisabled => write!(f, "Standby verification is disabled"),
+            AdminError::SchemaChecksDisabled => write!(f, "Schema checks are disabled"),
+            AdminError::Forbidden => write!(f, "Admin key is not allowed to call this method"),
             AdminError::InvalidResponse(reason) => write!(f, "Invalid RPC response: {}", reason),
+            AdminError::InvalidConfig(reason) => write!(f, "Invalid config: {}", reason),
         }
     }
 }
@@ -6,7 +6,22 @@ use std::sync::{
 use sled::Db;
 
 use crate::{
-    admin::accept::accept_admin_request,
+    admin::{
+        accept::accept_admin_request,
+        audit::AuditLog,
+    },
+    balancer::{
+        codec::CacheCodec,
+        schema::SchemaChecker,
+    },
+    health::standby::StandbyTracker,
+    metrics::{
+        histogram::LatencyHistograms,
+        store::EventStore,
+        talkers::TopTalkers,
+    },
+    ratelimit::types::RateLimiter,
+    slo::types::SloTracker,
     Rpc,
     Settings,
 };
@@ -18,6 +33,19 @@ use hyper::{
 use hyper_util_blutgang::rt::TokioIo;
 use tokio::net::TcpListener;
 
+// Stats admin methods report on, each only present if enabled
+#[derive(Debug, Clone, Default)]
+pub struct Trackers {
+    pub slo: Option<Arc<SloTracker>>,
+    pub talkers: Option<Arc<TopTalkers>>,
+    pub rate_limiter: Option<Arc<RateLimiter>>,
+    pub latency: Option<Arc<LatencyHistograms>>,
+    pub events: Option<Arc<EventStore>>,
+    pub standby: Option<Arc<StandbyTracker>>,
+    pub codec: Option<Arc<CacheCodec>>,
+    pub schema: Option<Arc<SchemaChecker>>,
+}
+
 macro_rules! accept_admin {
     (
         $io:expr,
@@ -25,6 +53,9 @@ macro_rules! accept_admin {
         $poverty_list_rwlock:expr,
         $cache:expr,
         $config:expr,
+        $trackers:expr,
+        $audit:expr,
+        $socketaddr:expr,
     ) => {
         // Bind the incoming connection to our service
         if let Err(err) = http1::Builder::new()
@@ -38,6 +69,8 @@ macro_rules! accept_admin {
                         Arc::clone($poverty_list_rwlock),
                         Arc::clone($cache),
                         Arc::clone($config),
+                        $trackers.clone(),
+                        (Arc::clone($audit), $socketaddr),
                     );
                     response
                 }),
@@ -57,11 +90,14 @@ pub async fn listen_for_admin_requests(
     poverty_list_rwlock: Arc<RwLock<Vec<Rpc>>>,
     cache: Arc<Db>,
     config: Arc<RwLock<Settings>>,
+    trackers: Trackers,
 ) -> Result<(), Box<dyn std::error::Error>> {
     let address;
+    let audit;
     {
         let config_guard = config.read().unwrap();
         address = config_guard.admin.address;
+        audit = Arc::new(AuditLog::new(config_guard.admin.audit_log.as_deref())?);
     }
 
     // Create a listener and bind to it
@@ -80,6 +116,8 @@ pub async fn listen_for_admin_requests(
         let poverty_list_rwlock_clone = Arc::clone(&poverty_list_rwlock);
         let cache_clone = Arc::clone(&cache);
         let config_clone = Arc::clone(&config);
+        let trackers_clone = trackers.clone();
+        let audit_clone = Arc::clone(&audit);
 
         // Spawn a tokio task to serve multiple connections concurrently
         tokio::task::spawn(async move {
@@ -89,6 +127,9 @@ pub async fn listen_for_admin_requests(
                 &poverty_list_rwlock_clone,
                 &cache_clone,
                 &config_clone,
+                &trackers_clone,
+                &audit_clone,
+                socketaddr,
             );
         });
     }
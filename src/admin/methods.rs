@@ -1,5 +1,37 @@
 use crate::{
-    admin::error::AdminError,
+    admin::{
+        auth::AdminRole,
+        error::AdminError,
+        listener::Trackers,
+    },
+    balancer::{
+        block_index::flush_range,
+        codec::CacheCodec,
+        schema::SchemaChecker,
+    },
+    config::reload::reload_config,
+    health::{
+        standby::StandbyTracker,
+        tombstone::{
+            invalidated,
+            list,
+        },
+    },
+    logging::filter::{
+        log_enabled,
+        log_filter,
+        set_log_level,
+        set_sample_rate,
+        LogLevel,
+    },
+    metrics::{
+        histogram::LatencyHistograms,
+        store::EventStore,
+        talkers::TopTalkers,
+    },
+    ratelimit::types::RateLimiter,
+    rpc::types::Protocol,
+    slo::types::SloTracker,
     Rpc,
     Settings,
 };
@@ -20,6 +52,25 @@ use serde_json::{
 
 use sled::Db;
 
+// Role an admin key needs to call `method`
+pub fn required_role(method: &str) -> AdminRole {
+    match method {
+        "blutgang_quit"
+        | "blutgang_flush_cache"
+        | "blutgang_flushCacheRange"
+        | "blutgang_set_ttl"
+        | "blutgang_set_health_check_ttl"
+        | "blutgang_add_to_rpc_list"
+        | "blutgang_add_to_poverty_list"
+        | "blutgang_remove_from_rpc_list"
+        | "blutgang_remove_from_poverty_list"
+        | "blutgang_setLogLevel"
+        | "blutgang_setLogSampling"
+        | "blutgang_reloadConfig" => AdminRole::Write,
+        _ => AdminRole::Read,
+    }
+}
+
 // Extract the method, call the appropriate function and return the response
 pub async fn execute_method(
     tx: Value,
@@ -27,12 +78,16 @@ pub async fn execute_method(
     poverty_list: &Arc<RwLock<Vec<Rpc>>>,
     config: Arc<RwLock<Settings>>,
     cache: Arc<Db>,
+    trackers: Trackers,
 ) -> Result<Value, AdminError> {
     let method = tx["method"].as_str();
-    println!("Method: {:?}", method.unwrap_or("None"));
+    if log_enabled("admin", LogLevel::Info) {
+        println!("Method: {:?}", method.unwrap_or("None"));
+    }
 
     // Check if write protection is enabled
     let write_protection_enabled = config.read().unwrap().admin.readonly;
+    let protocol = config.read().unwrap().protocol();
 
     match method {
         Some("blutgang_quit") => {
@@ -50,8 +105,56 @@ pub async fn execute_method(
                 admin_flush_cache(cache).await
             }
         }
+        Some("blutgang_flushCacheRange") => {
+            if write_protection_enabled {
+                Err(AdminError::WriteProtectionEnabled)
+            } else {
+                let lookback = config.read().unwrap().logs_cache_chunk.saturating_sub(1);
+                admin_flush_cache_range(&cache, tx["params"].as_array(), lookback)
+            }
+        }
         Some("blutgang_config") => admin_config(config),
+        Some("blutgang_slo") => admin_slo(trackers.slo),
+        Some("blutgang_topTalkers") => admin_top_talkers(trackers.talkers, tx["params"].as_array()),
+        Some("blutgang_rateLimits") => {
+            admin_rate_limits(trackers.rate_limiter, tx["params"].as_array())
+        }
+        Some("blutgang_methodLatency") => {
+            admin_method_latency(trackers.latency, tx["params"].as_array())
+        }
+        Some("blutgang_requestsPerHour") => {
+            admin_events(
+                trackers.events,
+                tx["params"].as_array(),
+                EventStore::requests_per_hour,
+            )
+        }
+        Some("blutgang_errorRates") => {
+            admin_events(
+                trackers.events,
+                tx["params"].as_array(),
+                EventStore::error_rates,
+            )
+        }
+        Some("blutgang_healthEvents") => {
+            admin_events(
+                trackers.events,
+                tx["params"].as_array(),
+                EventStore::health_events,
+            )
+        }
+        Some("blutgang_standby") => admin_standby(trackers.standby),
+        Some("blutgang_schema_violations") => admin_schema_violations(trackers.schema),
+        Some("blutgang_cache_stats") => admin_cache_stats(&cache, config, trackers.codec),
         Some("blutgang_poverty_list") => admin_list_rpc(poverty_list),
+        Some("blutgang_connections") => admin_connections(rpc_list, poverty_list),
+        Some("blutgang_rpc_status") => {
+            admin_rpc_status(rpc_list, poverty_list, tx["params"].as_array())
+        }
+        Some("blutgang_status") => admin_status(rpc_list, poverty_list, tx["params"].as_array()),
+        Some("blutgang_inclusion") => admin_inclusion(rpc_list, poverty_list),
+        Some("blutgang_tombstones") => admin_tombstones(&cache),
+        Some("blutgang_versions") => admin_versions(rpc_list, poverty_list),
         Some("blutgang_ttl") => admin_blutgang_ttl(config),
         Some("blutgang_health_check_ttl") => admin_blutgang_health_check_ttl(config),
         Some("blutgang_set_ttl") => {
@@ -72,14 +175,14 @@ pub async fn execute_method(
             if write_protection_enabled {
                 Err(AdminError::WriteProtectionEnabled)
             } else {
-                admin_add_rpc(rpc_list, tx["params"].as_array())
+                admin_add_rpc(rpc_list, tx["params"].as_array(), protocol)
             }
         }
         Some("blutgang_add_to_poverty_list") => {
             if write_protection_enabled {
                 Err(AdminError::WriteProtectionEnabled)
             } else {
-                admin_add_rpc(poverty_list, tx["params"].as_array())
+                admin_add_rpc(poverty_list, tx["params"].as_array(), protocol)
             }
         }
         Some("blutgang_remove_from_rpc_list") => {
@@ -96,6 +199,29 @@ pub async fn execute_method(
                 admin_remove_rpc(poverty_list, tx["params"].as_array())
             }
         }
+        Some("blutgang_logLevel") => admin_log_level(),
+        Some("blutgang_setLogLevel") => {
+            if write_protection_enabled {
+                Err(AdminError::WriteProtectionEnabled)
+            } else {
+                admin_set_log_level(tx["params"].as_array())
+            }
+        }
+        Some("blutgang_setLogSampling") => {
+            if write_protection_enabled {
+                Err(AdminError::WriteProtectionEnabled)
+            } else {
+                admin_set_log_sampling(tx["params"].as_array())
+            }
+        }
+        Some("blutgang_reloadConfig") => {
+            let dry_run = is_dry_run(&tx["params"]);
+            if write_protection_enabled && !dry_run {
+                Err(AdminError::WriteProtectionEnabled)
+            } else {
+                admin_reload_config(rpc_list, poverty_list, config, dry_run).await
+            }
+        }
         _ => Err(AdminError::InvalidMethod),
     }
 }
@@ -131,6 +257,53 @@ async fn admin_flush_cache(cache: Arc<Db>) -> Result<Value, AdminError> {
     Ok(rx)
 }
 
+// Block number param, as an int or hex string
+fn block_param(param: &Value) -> Result<u64, AdminError> {
+    match param {
+        Value::Number(number) => number.as_u64().ok_or(AdminError::ParseError),
+        Value::String(number) => {
+            let number = number.strip_prefix("0x").ok_or(AdminError::ParseError)?;
+            u64::from_str_radix(number, 16).map_err(|_| AdminError::ParseError)
+        }
+        _ => Err(AdminError::InvalidParams),
+    }
+}
+
+// Remove the cached responses for a range of blocks, optionally only for methods
+// matching a pattern like `eth_getLogs` or `trace_*`.
+//
+// Entries can span up to `lookback` blocks, like chunks of logs.
+fn admin_flush_cache_range(
+    cache: &Db,
+    params: Option<&Vec<Value>>,
+    lookback: u64,
+) -> Result<Value, AdminError> {
+    let (from, to, pattern) = match params.map(Vec::as_slice).unwrap_or_default() {
+        [from, to] => (block_param(from)?, block_param(to)?, None),
+        [from, to, pattern] => {
+            let pattern = pattern.as_str().ok_or(AdminError::InvalidParams)?;
+            (block_param(from)?, block_param(to)?, Some(pattern))
+        }
+        _ => return Err(AdminError::InvalidLen),
+    };
+    if from > to {
+        return Err(AdminError::InvalidParams);
+    }
+
+    let removed =
+        flush_range(cache, from, to, lookback, pattern).map_err(|_| AdminError::RwError)?;
+
+    let rx = json!({
+        "id": Null,
+        "jsonrpc": "2.0",
+        "result": {
+            "removed": removed,
+        },
+    });
+
+    Ok(rx)
+}
+
 // Respond with the config we started blutgang with
 fn admin_config(config: Arc<RwLock<Settings>>) -> Result<Value, AdminError> {
     let guard = config.read().unwrap();
@@ -147,6 +320,437 @@ fn admin_config(config: Arc<RwLock<Settings>>) -> Result<Value, AdminError> {
             },
             "ttl": guard.ttl,
             "health_check_ttl": guard.health_check_ttl,
+            "pin": guard.pin,
+            "namespace_pools": guard.namespace_pools,
+        },
+    });
+
+    Ok(rx)
+}
+
+// Respond with the connection pool stats of every RPC
+fn admin_connections(
+    rpc_list: &Arc<RwLock<Vec<Rpc>>>,
+    poverty_list: &Arc<RwLock<Vec<Rpc>>>,
+) -> Result<Value, AdminError> {
+    let rpc_list = rpc_list.read().map_err(|_| AdminError::Inaccessible)?;
+    let poverty_list = poverty_list.read().map_err(|_| AdminError::Inaccessible)?;
+
+    let connections: Vec<Value> = rpc_list
+        .iter()
+        .map(|rpc| (rpc, false))
+        .chain(poverty_list.iter().map(|rpc| (rpc, true)))
+        .map(|(rpc, poverty)| {
+            let mut stats = rpc.pool_stats().to_json();
+            stats["name"] = rpc.name.clone().into();
+            stats["prewarm"] = rpc.prewarm.into();
+            stats["ip_preference"] = format!("{:?}", rpc.ip_preference).into();
+            stats["families"] = rpc.family_health().to_json();
+            stats["poverty"] = poverty.into();
+            stats
+        })
+        .collect();
+
+    let rx = json!({
+        "id": Null,
+        "jsonrpc": "2.0",
+        "result": connections,
+    });
+
+    Ok(rx)
+}
+
+// Respond with the health status of every RPC, or only the one named in param[0]
+// Name of the RPC a method was asked about, None for all of them
+fn rpc_name(params: Option<&Vec<Value>>) -> Result<Option<&str>, AdminError> {
+    match params.map(Vec::as_slice).unwrap_or_default() {
+        [] => Ok(None),
+        [name] => Ok(Some(name.as_str().ok_or(AdminError::InvalidParams)?)),
+        _ => Err(AdminError::InvalidLen),
+    }
+}
+
+fn admin_rpc_status(
+    rpc_list: &Arc<RwLock<Vec<Rpc>>>,
+    poverty_list: &Arc<RwLock<Vec<Rpc>>>,
+    params: Option<&Vec<Value>>,
+) -> Result<Value, AdminError> {
+    let name = rpc_name(params)?;
+
+    let rpc_list = rpc_list.read().map_err(|_| AdminError::Inaccessible)?;
+    let poverty_list = poverty_list.read().map_err(|_| AdminError::Inaccessible)?;
+
+    let status: Vec<Value> = rpc_list
+        .iter()
+        .map(|rpc| (rpc, false))
+        .chain(poverty_list.iter().map(|rpc| (rpc, true)))
+        .filter(|(rpc, _)| name.map_or(true, |name| rpc.name == name))
+        .map(|(rpc, poverty)| {
+            // Latencies are kept in ns
+            json!({
+                "name": rpc.name,
+                "url": rpc.url,
+                "poverty": poverty,
+                "paused": rpc.paused,
+                "quarantined": rpc.quarantined,
+                "degraded": rpc.degraded,
+                "is_erroring": rpc.status.is_erroring,
+                "last_error": rpc.status.last_error,
+                "latency_ms": rpc.status.latency / 1e6,
+                "latency_samples": rpc.status.latency_data.len(),
+                "latency_window": rpc.status.ma_length(),
+                "timings_ms": rpc.timings().to_json(),
+                "weight": rpc.status.weight,
+                "head": rpc.pool_stats().head(),
+                "consecutive": rpc.consecutive,
+                "max_consecutive": rpc.max_consecutive,
+            })
+        })
+        .collect();
+
+    if name.is_some() && status.is_empty() {
+        return Err(AdminError::InvalidParams);
+    }
+
+    let rx = json!({
+        "id": Null,
+        "jsonrpc": "2.0",
+        "result": status,
+    });
+
+    Ok(rx)
+}
+
+// Tombstones we show at most
+const TOMBSTONES: usize = 100;
+
+// Respond with how many cached responses reorgs invalidated by reorg depth, and the
+// newest tombstones of the ones we kept
+fn admin_tombstones(cache: &Db) -> Result<Value, AdminError> {
+    let tombstones = list(cache, TOMBSTONES).map_err(|_| AdminError::RwError)?;
+    let tombstones: Vec<Value> = tombstones
+        .into_iter()
+        .map(|(key, tombstone)| {
+            let key: String = key.iter().map(|byte| format!("{:02x}", byte)).collect();
+            json!({
+                "key": key,
+                "buried": tombstone.buried,
+                "depth": tombstone.depth,
+                "bytes": tombstone.data.len(),
+            })
+        })
+        .collect();
+    let invalidated: serde_json::Map<String, Value> = invalidated()
+        .into_iter()
+        .map(|(depth, count)| (depth.to_string(), count.into()))
+        .collect();
+
+    let rx = json!({
+        "id": Null,
+        "jsonrpc": "2.0",
+        "result": {
+            "invalidated": invalidated,
+            "tombstones": tombstones,
+        },
+    });
+
+    Ok(rx)
+}
+
+// Respond with percentiles of the latency and size of recent responses from every RPC,
+// and how many requests and bytes per second they served
+fn admin_status(
+    rpc_list: &Arc<RwLock<Vec<Rpc>>>,
+    poverty_list: &Arc<RwLock<Vec<Rpc>>>,
+    params: Option<&Vec<Value>>,
+) -> Result<Value, AdminError> {
+    let name = rpc_name(params)?;
+
+    let rpc_list = rpc_list.read().map_err(|_| AdminError::Inaccessible)?;
+    let poverty_list = poverty_list.read().map_err(|_| AdminError::Inaccessible)?;
+
+    let status: Vec<Value> = rpc_list
+        .iter()
+        .map(|rpc| (rpc, false))
+        .chain(poverty_list.iter().map(|rpc| (rpc, true)))
+        .filter(|(rpc, _)| name.map_or(true, |name| rpc.name == name))
+        .map(|(rpc, poverty)| {
+            let mut status = rpc.status.responses.to_json();
+            status["name"] = rpc.name.clone().into();
+            status["poverty"] = poverty.into();
+            status["weight"] = rpc.status.weight.into();
+            status["ma_length"] = rpc.status.ma_length().into();
+            status
+        })
+        .collect();
+
+    if name.is_some() && status.is_empty() {
+        return Err(AdminError::InvalidParams);
+    }
+
+    let rx = json!({
+        "id": Null,
+        "jsonrpc": "2.0",
+        "result": status,
+    });
+
+    Ok(rx)
+}
+
+// Respond with how fast transactions broadcast through each RPC got included, fastest first
+fn admin_inclusion(
+    rpc_list: &Arc<RwLock<Vec<Rpc>>>,
+    poverty_list: &Arc<RwLock<Vec<Rpc>>>,
+) -> Result<Value, AdminError> {
+    let rpc_list = rpc_list.read().map_err(|_| AdminError::Inaccessible)?;
+    let poverty_list = poverty_list.read().map_err(|_| AdminError::Inaccessible)?;
+
+    let mut rpcs: Vec<&Rpc> = rpc_list.iter().chain(poverty_list.iter()).collect();
+    // RPCs we have no inclusions for go last
+    rpcs.sort_by_key(|rpc| rpc.inclusion_stats().median_ms().unwrap_or(u64::MAX));
+
+    let inclusion: Vec<Value> = rpcs
+        .into_iter()
+        .map(|rpc| {
+            let mut stats = rpc.inclusion_stats().to_json();
+            stats["name"] = rpc.name.clone().into();
+            stats
+        })
+        .collect();
+
+    let rx = json!({
+        "id": Null,
+        "jsonrpc": "2.0",
+        "result": inclusion,
+    });
+
+    Ok(rx)
+}
+
+// Respond with what client and modules every RPC runs, and when that changed
+fn admin_versions(
+    rpc_list: &Arc<RwLock<Vec<Rpc>>>,
+    poverty_list: &Arc<RwLock<Vec<Rpc>>>,
+) -> Result<Value, AdminError> {
+    let rpc_list = rpc_list.read().map_err(|_| AdminError::Inaccessible)?;
+    let poverty_list = poverty_list.read().map_err(|_| AdminError::Inaccessible)?;
+
+    let versions: Vec<Value> = rpc_list
+        .iter()
+        .chain(poverty_list.iter())
+        .map(|rpc| {
+            let mut versions = rpc.fingerprints().to_json();
+            versions["name"] = rpc.name.clone().into();
+            versions
+        })
+        .collect();
+
+    let rx = json!({
+        "id": Null,
+        "jsonrpc": "2.0",
+        "result": versions,
+    });
+
+    Ok(rx)
+}
+
+// Respond with the SLO status of the endpoint and every RPC
+fn admin_slo(slo: Option<Arc<SloTracker>>) -> Result<Value, AdminError> {
+    let slo = match slo {
+        Some(slo) => slo,
+        None => return Err(AdminError::SloDisabled),
+    };
+
+    let rx = json!({
+        "id": Null,
+        "jsonrpc": "2.0",
+        "result": slo.report(),
+    });
+
+    Ok(rx)
+}
+
+// Busiest clients, optionally with how many we want and what to rank them by:
+// `rate` (requests per second, default), `requests`, `errors` or `bytes`
+fn admin_top_talkers(
+    talkers: Option<Arc<TopTalkers>>,
+    params: Option<&Vec<Value>>,
+) -> Result<Value, AdminError> {
+    let talkers = match talkers {
+        Some(talkers) => talkers,
+        None => return Err(AdminError::TalkersDisabled),
+    };
+
+    let params = params.map(Vec::as_slice).unwrap_or_default();
+    if params.len() > 2 {
+        return Err(AdminError::InvalidLen);
+    }
+
+    let limit = match params.first() {
+        Some(limit) => {
+            match limit.to_string().replace('\"', "").parse::<usize>() {
+                Ok(limit) => limit,
+                Err(_) => return Err(AdminError::ParseError),
+            }
+        }
+        None => 10,
+    };
+    let by = match params.get(1).map(|by| by.as_str()) {
+        Some(Some(by)) if ["rate", "requests", "errors", "bytes"].contains(&by) => by,
+        Some(_) => return Err(AdminError::InvalidParams),
+        None => "rate",
+    };
+
+    let rx = json!({
+        "id": Null,
+        "jsonrpc": "2.0",
+        "result": talkers.top(limit, by),
+    });
+
+    Ok(rx)
+}
+
+// Rate limiter settings and rejections, with the clients closest to their quota
+fn admin_rate_limits(
+    rate_limiter: Option<Arc<RateLimiter>>,
+    params: Option<&Vec<Value>>,
+) -> Result<Value, AdminError> {
+    let rate_limiter = match rate_limiter {
+        Some(rate_limiter) => rate_limiter,
+        None => return Err(AdminError::RateLimitingDisabled),
+    };
+
+    let params = params.map(Vec::as_slice).unwrap_or_default();
+    if params.len() > 1 {
+        return Err(AdminError::InvalidLen);
+    }
+
+    let limit = match params.first() {
+        Some(limit) => {
+            match limit.to_string().replace('\"', "").parse::<usize>() {
+                Ok(limit) => limit,
+                Err(_) => return Err(AdminError::ParseError),
+            }
+        }
+        None => 10,
+    };
+
+    let rx = json!({
+        "id": Null,
+        "jsonrpc": "2.0",
+        "result": rate_limiter.report(limit),
+    });
+
+    Ok(rx)
+}
+
+// Latency of every method on every RPC, or only of the method in params
+fn admin_method_latency(
+    latency: Option<Arc<LatencyHistograms>>,
+    params: Option<&Vec<Value>>,
+) -> Result<Value, AdminError> {
+    let latency = match latency {
+        Some(latency) => latency,
+        None => return Err(AdminError::MetricsDisabled),
+    };
+
+    let params = params.map(Vec::as_slice).unwrap_or_default();
+    let method = match params {
+        [] => None,
+        [method] => Some(method.as_str().ok_or(AdminError::InvalidParams)?),
+        _ => return Err(AdminError::InvalidLen),
+    };
+
+    let rx = json!({
+        "id": Null,
+        "jsonrpc": "2.0",
+        "result": latency.method_stats(method),
+    });
+
+    Ok(rx)
+}
+
+// Aggregate the event store over the last `hours`, 24 if not specified.
+//
+// Used for `blutgang_requestsPerHour`, `blutgang_errorRates` and `blutgang_healthEvents`
+fn admin_events(
+    events: Option<Arc<EventStore>>,
+    params: Option<&Vec<Value>>,
+    aggregate: fn(&EventStore, u64) -> Result<Value, sled::Error>,
+) -> Result<Value, AdminError> {
+    let events = match events {
+        Some(events) => events,
+        None => return Err(AdminError::EventStoreDisabled),
+    };
+
+    let params = params.map(Vec::as_slice).unwrap_or_default();
+    let hours = match params {
+        [] => 24,
+        [hours] => hours.as_u64().ok_or(AdminError::InvalidParams)?,
+        _ => return Err(AdminError::InvalidLen),
+    };
+
+    let rx = json!({
+        "id": Null,
+        "jsonrpc": "2.0",
+        "result": aggregate(&events, hours).map_err(|_| AdminError::RwError)?,
+    });
+
+    Ok(rx)
+}
+
+// Failover readiness of every standby RPC
+fn admin_standby(standby: Option<Arc<StandbyTracker>>) -> Result<Value, AdminError> {
+    let standby = standby.ok_or(AdminError::StandbyDisabled)?;
+
+    let rx = json!({
+        "id": Null,
+        "jsonrpc": "2.0",
+        "result": standby.report(),
+    });
+
+    Ok(rx)
+}
+
+// Malformed responses we got from every RPC, and the latest reason why
+fn admin_schema_violations(schema: Option<Arc<SchemaChecker>>) -> Result<Value, AdminError> {
+    let schema = schema.ok_or(AdminError::SchemaChecksDisabled)?;
+
+    let rx = json!({
+        "id": Null,
+        "jsonrpc": "2.0",
+        "result": schema.report(),
+    });
+
+    Ok(rx)
+}
+
+// Respond with how big the cache is, how big it can get, and what got evicted
+fn admin_cache_stats(
+    cache: &Db,
+    config: Arc<RwLock<Settings>>,
+    codec: Option<Arc<CacheCodec>>,
+) -> Result<Value, AdminError> {
+    let codec = codec.ok_or(AdminError::Inaccessible)?;
+    let stats = codec.stats();
+    let (max_entries, max_size) = {
+        let config_guard = config.read().unwrap();
+        (config_guard.cache_max_entries, config_guard.cache_max_size)
+    };
+
+    let rx = json!({
+        "id": Null,
+        "jsonrpc": "2.0",
+        "result": {
+            "entries": cache.len(),
+            "size_bytes": stats.size(),
+            "size_on_disk": cache.size_on_disk().map_err(|_| AdminError::RwError)?,
+            "max_entries": max_entries,
+            "max_size_bytes": max_size * 1024 * 1024,
+            "hits": stats.hits(),
+            "misses": stats.misses(),
+            "evictions": stats.evictions(),
+            "evicted_bytes": stats.evicted_bytes(),
         },
     });
 
@@ -166,8 +770,8 @@ fn admin_list_rpc(rpc_list: &Arc<RwLock<Vec<Rpc>>>) -> Result<Value, AdminError>
     // Iterate over the RPC list and format each RPC
     for rpc in rpc_list.iter() {
         rpc_list_str.push_str(&format!(
-            "{{\"url\": \"{}\", \"max_consecutive\": {}, \"last_error\": {}}}",
-            rpc.url, rpc.max_consecutive, rpc.status.last_error
+            "{{\"name\": \"{}\", \"url\": \"{}\", \"max_consecutive\": {}, \"last_error\": {}}}",
+            rpc.name, rpc.url, rpc.max_consecutive, rpc.status.last_error
         ));
     }
 
@@ -192,6 +796,7 @@ fn admin_list_rpc(rpc_list: &Arc<RwLock<Vec<Rpc>>>) -> Result<Value, AdminError>
 fn admin_add_rpc(
     rpc_list: &Arc<RwLock<Vec<Rpc>>>,
     params: Option<&Vec<Value>>,
+    protocol: Protocol,
 ) -> Result<Value, AdminError> {
     let params = match params {
         Some(params) => params,
@@ -220,7 +825,9 @@ fn admin_add_rpc(
 
     let mut rpc_list = rpc_list.write().map_err(|_| AdminError::Inaccessible)?;
 
-    rpc_list.push(Rpc::new(rpc.to_string(), max_consecutive, ma_len));
+    let mut new_rpc = Rpc::new(rpc.to_string(), max_consecutive, ma_len);
+    new_rpc.protocol = protocol;
+    rpc_list.push(new_rpc);
 
     let rx = json!({
         "id": Null,
@@ -363,10 +970,130 @@ fn admin_blutgang_set_ttl(
     Ok(rx)
 }
 
+// `dry_run` can be passed as `{"dry_run": true}` or `[{"dry_run": true}]`
+fn is_dry_run(params: &Value) -> bool {
+    let options = match params {
+        Value::Array(params) => params.first().unwrap_or(&Null),
+        options => options,
+    };
+
+    options["dry_run"].as_bool().unwrap_or(false)
+}
+
+// Read the config file we started with again and apply what changed.
+//
+// Responds with what changed, including the settings that need a restart to take effect.
+// With `dry_run` nothing is applied.
+async fn admin_reload_config(
+    rpc_list: &Arc<RwLock<Vec<Rpc>>>,
+    poverty_list: &Arc<RwLock<Vec<Rpc>>>,
+    config: Arc<RwLock<Settings>>,
+    dry_run: bool,
+) -> Result<Value, AdminError> {
+    let diff = reload_config(rpc_list, poverty_list, &config, dry_run)
+        .await
+        .map_err(AdminError::InvalidConfig)?;
+
+    let mut result = diff.to_json();
+    result["unchanged"] = diff.is_empty().into();
+    result["dry_run"] = dry_run.into();
+    result["applied"] = (!dry_run).into();
+
+    let rx = json!({
+        "id": Null,
+        "jsonrpc": "2.0",
+        "result": result,
+    });
+
+    Ok(rx)
+}
+
+// Responds with the current log filter and request log sample rate
+fn admin_log_level() -> Result<Value, AdminError> {
+    let filter = log_filter();
+    let rx = json!({
+        "id": Null,
+        "jsonrpc": "2.0",
+        "result": {
+            "filter": filter.to_string(),
+            "sample_rate": filter.sample_rate,
+            "format": filter.format.to_string(),
+        },
+    });
+
+    Ok(rx)
+}
+
+// Sets the log level of a module, or the default level
+//
+// param[0] - level, or the module if there are 2 params
+// param[1] - level
+fn admin_set_log_level(params: Option<&Vec<Value>>) -> Result<Value, AdminError> {
+    let params = match params {
+        Some(params) => params,
+        None => return Err(AdminError::InvalidParams),
+    };
+
+    let (module, level) = match params.len() {
+        1 => (None, &params[0]),
+        2 => (params[0].as_str(), &params[1]),
+        _ => return Err(AdminError::InvalidLen),
+    };
+
+    let level = match level.as_str().and_then(LogLevel::from_config) {
+        Some(level) => level,
+        None => return Err(AdminError::ParseError),
+    };
+    if params.len() == 2 && module.is_none() {
+        return Err(AdminError::ParseError);
+    }
+
+    set_log_level(module, level);
+
+    let rx = json!({
+        "id": Null,
+        "jsonrpc": "2.0",
+        "result": log_filter().to_string(),
+    });
+
+    Ok(rx)
+}
+
+// Sets the share of per-request log lines that get printed
+//
+// param[0] - sample rate, between 0 and 1
+fn admin_set_log_sampling(params: Option<&Vec<Value>>) -> Result<Value, AdminError> {
+    let params = match params {
+        Some(params) => params,
+        None => return Err(AdminError::InvalidParams),
+    };
+
+    if params.len() != 1 {
+        return Err(AdminError::InvalidLen);
+    }
+
+    let sample_rate = match params[0].to_string().replace('\"', "").parse::<f64>() {
+        Ok(sample_rate) if (0.0..=1.0).contains(&sample_rate) => sample_rate,
+        _ => return Err(AdminError::ParseError),
+    };
+
+    set_sample_rate(sample_rate);
+
+    let rx = json!({
+        "id": Null,
+        "jsonrpc": "2.0",
+        "result": log_filter().sample_rate,
+    });
+
+    Ok(rx)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::balancer::block_index::record;
     use jsonwebtoken::DecodingKey;
+    use std::time::Duration;
 
     // Helper function to create a test RPC list
     fn create_test_rpc_list() -> Arc<RwLock<Vec<Rpc>>> {
@@ -388,8 +1115,10 @@ mod tests {
 
     // Helper function to create a test Settings config
     fn create_test_settings_config() -> Arc<RwLock<Settings>> {
-        let mut config = Settings::default();
-        config.do_clear = true;
+        let mut config = Settings {
+            do_clear: true,
+            ..Default::default()
+        };
         config.admin.key = DecodingKey::from_secret(b"some-key");
         Arc::new(RwLock::new(config))
     }
@@ -415,6 +1144,7 @@ mod tests {
             &create_test_poverty_list(),
             create_test_settings_config(),
             cache,
+            Trackers::default(),
         )
         .await;
 
@@ -422,6 +1152,223 @@ mod tests {
         assert!(result.is_ok());
     }
 
+    #[tokio::test]
+    async fn test_execute_method_blutgang_rpc_status() {
+        let rpc_list = create_test_rpc_list();
+        let poverty_list = create_test_poverty_list();
+        let name = rpc_list.read().unwrap()[0].name.clone();
+
+        let tx = json!({"id": 1, "method": "blutgang_rpc_status"});
+        let result = execute_method(
+            tx,
+            &rpc_list,
+            &poverty_list,
+            create_test_settings_config(),
+            create_test_cache(),
+            Trackers::default(),
+        )
+        .await
+        .unwrap();
+        let all = result["result"].as_array().unwrap();
+        assert_eq!(
+            all.len(),
+            rpc_list.read().unwrap().len() + poverty_list.read().unwrap().len()
+        );
+        assert_eq!(all[0]["poverty"], false);
+
+        let tx = json!({"id": 1, "method": "blutgang_rpc_status", "params": [name]});
+        let result = execute_method(
+            tx,
+            &rpc_list,
+            &poverty_list,
+            create_test_settings_config(),
+            create_test_cache(),
+            Trackers::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(result["result"][0]["name"], name);
+
+        let tx = json!({"id": 1, "method": "blutgang_rpc_status", "params": ["nope"]});
+        let result = execute_method(
+            tx,
+            &rpc_list,
+            &poverty_list,
+            create_test_settings_config(),
+            create_test_cache(),
+            Trackers::default(),
+        )
+        .await;
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_execute_method_blutgang_status() {
+        let rpc_list = create_test_rpc_list();
+        let poverty_list = create_test_poverty_list();
+        let name = rpc_list.read().unwrap()[0].name.clone();
+        rpc_list.read().unwrap()[0]
+            .status
+            .responses
+            .record(Duration::from_millis(20), 100);
+
+        let tx = json!({"id": 1, "method": "blutgang_status", "params": [name]});
+        let result = execute_method(
+            tx,
+            &rpc_list,
+            &poverty_list,
+            create_test_settings_config(),
+            create_test_cache(),
+            Trackers::default(),
+        )
+        .await
+        .unwrap();
+        let status = &result["result"][0];
+        assert_eq!(status["name"], name);
+        assert_eq!(status["samples"], 1);
+        assert_eq!(status["latency_ms"]["p99"], 20.0);
+        assert_eq!(status["size_bytes"]["p50"], 100);
+
+        let tx = json!({"id": 1, "method": "blutgang_status", "params": [1, 2]});
+        let result = execute_method(
+            tx,
+            &rpc_list,
+            &poverty_list,
+            create_test_settings_config(),
+            create_test_cache(),
+            Trackers::default(),
+        )
+        .await;
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_execute_method_blutgang_cache_stats() {
+        let cache = create_test_cache();
+        let codec = Arc::new(CacheCodec::new(0, false));
+        codec.insert(&cache, b"a", b"rx").unwrap();
+        codec.shrink(&cache, 1024).unwrap();
+
+        let tx = json!({"id": 1, "method": "blutgang_cache_stats"});
+        let trackers = Trackers {
+            codec: Some(codec),
+            ..Default::default()
+        };
+        let result = execute_method(
+            tx,
+            &create_test_rpc_list(),
+            &create_test_poverty_list(),
+            create_test_settings_config(),
+            cache,
+            trackers,
+        )
+        .await
+        .unwrap();
+        assert_eq!(result["result"]["entries"], 1);
+        assert_eq!(result["result"]["size_bytes"], 3);
+        assert_eq!(result["result"]["evictions"], 0);
+    }
+
+    #[tokio::test]
+    async fn test_execute_method_blutgang_schema_violations() {
+        let schema = Arc::new(SchemaChecker::new(1.0));
+        schema.record("rpc1", "result is missing");
+
+        let tx = json!({"id": 1, "method": "blutgang_schema_violations"});
+        let trackers = Trackers {
+            schema: Some(schema),
+            ..Default::default()
+        };
+        let result = execute_method(
+            tx.clone(),
+            &create_test_rpc_list(),
+            &create_test_poverty_list(),
+            create_test_settings_config(),
+            create_test_cache(),
+            trackers,
+        )
+        .await
+        .unwrap();
+        assert_eq!(result["result"]["rpc1"]["violations"], 1);
+        assert_eq!(result["result"]["rpc1"]["last"], "result is missing");
+
+        let result = execute_method(
+            tx,
+            &create_test_rpc_list(),
+            &create_test_poverty_list(),
+            create_test_settings_config(),
+            create_test_cache(),
+            Trackers::default(),
+        )
+        .await;
+        assert!(matches!(result, Err(AdminError::SchemaChecksDisabled)));
+    }
+
+    #[tokio::test]
+    async fn test_execute_method_blutgang_tombstones() {
+        let cache = create_test_cache();
+        cache.insert("a", "rx").unwrap();
+        crate::health::tombstone::bury(&cache, &[b"a".to_vec()], 2, true).unwrap();
+
+        let tx = json!({"id": 1, "method": "blutgang_tombstones"});
+        let result = execute_method(
+            tx,
+            &create_test_rpc_list(),
+            &create_test_poverty_list(),
+            create_test_settings_config(),
+            cache,
+            Trackers::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(result["result"]["tombstones"][0]["key"], "61");
+        assert_eq!(result["result"]["tombstones"][0]["depth"], 2);
+        assert!(result["result"]["invalidated"]["2"].as_u64().unwrap() >= 1);
+    }
+
+    #[tokio::test]
+    async fn test_execute_method_blutgang_flush_cache_range() {
+        let cache = create_test_cache();
+        cache.insert("a", "rx").unwrap();
+        record(&cache, b"a", "eth_getLogs", 100, 199).unwrap();
+        cache.insert("b", "rx").unwrap();
+        record(&cache, b"b", "eth_call", 150, 150).unwrap();
+
+        let tx = json!({
+            "id": 1,
+            "method": "blutgang_flushCacheRange",
+            "params": [150, "0x96", "eth_getLogs"],
+        });
+        // Log chunks span 100 blocks
+        let config = create_test_settings_config();
+        config.write().unwrap().logs_cache_chunk = 100;
+        let result = execute_method(
+            tx,
+            &create_test_rpc_list(),
+            &create_test_poverty_list(),
+            config,
+            Arc::clone(&cache),
+            Trackers::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(result["result"]["removed"], 1);
+        assert!(cache.get("a").unwrap().is_none());
+        assert!(cache.get("b").unwrap().is_some());
+
+        let tx = json!({"id": 1, "method": "blutgang_flushCacheRange", "params": [2, 1]});
+        let result = execute_method(
+            tx,
+            &create_test_rpc_list(),
+            &create_test_poverty_list(),
+            create_test_settings_config(),
+            cache,
+            Trackers::default(),
+        )
+        .await;
+        assert!(result.is_err());
+    }
+
     #[tokio::test]
     async fn test_execute_method_blutgang_rpc_list() {
         // Arrange
@@ -435,6 +1382,7 @@ mod tests {
             &create_test_poverty_list(),
             create_test_settings_config(),
             cache,
+            Trackers::default(),
         )
         .await;
 
@@ -455,6 +1403,7 @@ mod tests {
             &create_test_poverty_list(),
             create_test_settings_config(),
             cache,
+            Trackers::default(),
         )
         .await;
 
@@ -475,6 +1424,7 @@ mod tests {
             &create_test_poverty_list(),
             create_test_settings_config(),
             cache,
+            Trackers::default(),
         )
         .await;
 
@@ -495,6 +1445,7 @@ mod tests {
             &create_test_poverty_list(),
             create_test_settings_config(),
             cache,
+            Trackers::default(),
         )
         .await;
 
@@ -515,6 +1466,7 @@ mod tests {
             &create_test_poverty_list(),
             create_test_settings_config(),
             cache,
+            Trackers::default(),
         )
         .await;
 
@@ -522,6 +1474,142 @@ mod tests {
         assert!(result.is_ok());
     }
 
+    #[tokio::test]
+    async fn test_execute_method_blutgang_top_talkers() {
+        let talkers = Arc::new(TopTalkers::new(100, 10));
+        talkers.record("indexer", 100, 1000, false);
+        talkers.record("indexer", 100, 1000, false);
+        talkers.record("wallet", 100, 10, true);
+
+        let tx = json!({"id": 1, "method": "blutgang_topTalkers", "params": [1, "errors"]});
+        let rx = execute_method(
+            tx,
+            &create_test_rpc_list(),
+            &create_test_poverty_list(),
+            create_test_settings_config(),
+            create_test_cache(),
+            Trackers {
+                talkers: Some(Arc::clone(&talkers)),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        assert_eq!(rx["result"].as_array().unwrap().len(), 1);
+        assert_eq!(rx["result"][0]["client"], "wallet");
+
+        let tx = json!({"id": 1, "method": "blutgang_topTalkers", "params": [1, "nope"]});
+        let result = execute_method(
+            tx,
+            &create_test_rpc_list(),
+            &create_test_poverty_list(),
+            create_test_settings_config(),
+            create_test_cache(),
+            Trackers {
+                talkers: Some(talkers),
+                ..Default::default()
+            },
+        )
+        .await;
+        assert!(matches!(result, Err(AdminError::InvalidParams)));
+
+        // Disabled
+        let tx = json!({"id": 1, "method": "blutgang_topTalkers"});
+        let result = execute_method(
+            tx,
+            &create_test_rpc_list(),
+            &create_test_poverty_list(),
+            create_test_settings_config(),
+            create_test_cache(),
+            Trackers::default(),
+        )
+        .await;
+        assert!(matches!(result, Err(AdminError::TalkersDisabled)));
+    }
+
+    #[tokio::test]
+    async fn test_execute_method_blutgang_rate_limits() {
+        let rate_limiter = Arc::new(RateLimiter::new(0.0, 1.0, 10));
+        assert!(rate_limiter.check("indexer", 1).is_ok());
+        assert!(rate_limiter.check("indexer", 1).is_err());
+        assert!(rate_limiter.check("wallet", 1).is_ok());
+
+        let tx = json!({"id": 1, "method": "blutgang_rateLimits", "params": [1]});
+        let rx = execute_method(
+            tx,
+            &create_test_rpc_list(),
+            &create_test_poverty_list(),
+            create_test_settings_config(),
+            create_test_cache(),
+            Trackers {
+                rate_limiter: Some(rate_limiter),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        assert_eq!(rx["result"]["clients"], 2);
+        assert_eq!(rx["result"]["rateLimited"], 1);
+        assert_eq!(rx["result"]["quotaExceeded"], 0);
+        assert_eq!(rx["result"]["top"].as_array().unwrap().len(), 1);
+        assert_eq!(rx["result"]["top"][0]["client"], "indexer");
+        assert_eq!(rx["result"]["top"][0]["quotaLeft"], 9);
+
+        // Disabled
+        let tx = json!({"id": 1, "method": "blutgang_rateLimits"});
+        let result = execute_method(
+            tx,
+            &create_test_rpc_list(),
+            &create_test_poverty_list(),
+            create_test_settings_config(),
+            create_test_cache(),
+            Trackers::default(),
+        )
+        .await;
+        assert!(matches!(result, Err(AdminError::RateLimitingDisabled)));
+    }
+
+    #[tokio::test]
+    async fn test_execute_method_blutgang_method_latency() {
+        let latency = Arc::new(LatencyHistograms::default());
+        latency.record_method("eth_getLogs", "llama", Duration::from_millis(30));
+        latency.record_method("eth_getLogs", "drpc", Duration::from_secs(3));
+        latency.record_method("eth_call", "llama", Duration::from_millis(3));
+
+        let tx = json!({"id": 1, "method": "blutgang_methodLatency", "params": ["eth_getLogs"]});
+        let rx = execute_method(
+            tx,
+            &create_test_rpc_list(),
+            &create_test_poverty_list(),
+            create_test_settings_config(),
+            create_test_cache(),
+            Trackers {
+                latency: Some(latency),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        let result = rx["result"].as_array().unwrap();
+        assert_eq!(result.len(), 2);
+        assert_eq!(result[0]["upstream"], "drpc");
+        assert_eq!(result[0]["p50"], 5.0);
+        assert_eq!(result[1]["p50"], 0.05);
+
+        // Disabled
+        let tx = json!({"id": 1, "method": "blutgang_methodLatency"});
+        let result = execute_method(
+            tx,
+            &create_test_rpc_list(),
+            &create_test_poverty_list(),
+            create_test_settings_config(),
+            create_test_cache(),
+            Trackers::default(),
+        )
+        .await;
+        assert!(matches!(result, Err(AdminError::MetricsDisabled)));
+    }
+
     #[tokio::test]
     async fn test_execute_method_blutgang_health_check_ttl() {
         // Arrange
@@ -535,6 +1623,7 @@ mod tests {
             &create_test_poverty_list(),
             create_test_settings_config(),
             cache,
+            Trackers::default(),
         )
         .await;
 
@@ -555,6 +1644,7 @@ mod tests {
             &create_test_poverty_list(),
             create_test_settings_config(),
             cache,
+            Trackers::default(),
         )
         .await;
 
@@ -578,6 +1668,7 @@ mod tests {
             &create_test_poverty_list(),
             create_test_settings_config(),
             cache,
+            Trackers::default(),
         )
         .await;
 
@@ -608,6 +1699,7 @@ mod tests {
             &create_test_poverty_list(),
             create_test_settings_config(),
             cache.clone(),
+            Trackers::default(),
         )
         .await;
 
@@ -625,6 +1717,7 @@ mod tests {
             &binding,
             create_test_settings_config(),
             cache,
+            Trackers::default(),
         )
         .await;
 
@@ -649,6 +1742,7 @@ mod tests {
             &create_test_poverty_list(),
             Arc::clone(&config),
             cache,
+            Trackers::default(),
         )
         .await;
 
@@ -674,6 +1768,7 @@ mod tests {
             &create_test_poverty_list(),
             Arc::clone(&config),
             cache,
+            Trackers::default(),
         )
         .await;
 
@@ -699,6 +1794,7 @@ mod tests {
             &create_test_poverty_list(),
             Arc::clone(&config),
             cache.clone(),
+            Trackers::default(),
         )
         .await;
 
@@ -713,10 +1809,72 @@ mod tests {
             &create_test_poverty_list(),
             Arc::clone(&config),
             cache,
+            Trackers::default(),
         )
         .await;
 
         // Assert
         assert!(result.is_ok());
     }
+
+    #[test]
+    fn test_required_role() {
+        assert_eq!(required_role("blutgang_rpc_list"), AdminRole::Read);
+        assert_eq!(required_role("blutgang_config"), AdminRole::Read);
+        assert_eq!(required_role("blutgang_flush_cache"), AdminRole::Write);
+        assert_eq!(required_role("blutgang_flushCacheRange"), AdminRole::Write);
+        assert_eq!(
+            required_role("blutgang_remove_from_rpc_list"),
+            AdminRole::Write
+        );
+        assert_eq!(required_role("blutgang_reloadConfig"), AdminRole::Write);
+    }
+
+    #[test]
+    fn test_is_dry_run() {
+        assert!(is_dry_run(&json!({"dry_run": true})));
+        assert!(is_dry_run(&json!([{"dry_run": true}])));
+        assert!(!is_dry_run(&json!([{"dry_run": false}])));
+        assert!(!is_dry_run(&json!([])));
+        assert!(!is_dry_run(&Null));
+    }
+
+    #[tokio::test]
+    async fn test_reload_config_dry_run() {
+        let path = std::env::temp_dir().join("blutgang_test_reload_config.toml");
+        std::fs::write(
+            &path,
+            include_str!("../../example_config.toml")
+                .replace("sort_on_startup = true", "sort_on_startup = false"),
+        )
+        .unwrap();
+
+        let config = create_test_settings_config();
+        config.write().unwrap().config_path = Some(path);
+        let rpc_list = create_test_rpc_list();
+        let rpcs_before = rpc_list.read().unwrap().len();
+
+        let tx = json!({"id": 1, "method": "blutgang_reloadConfig", "params": [{"dry_run": true}]});
+        let result = execute_method(
+            tx,
+            &rpc_list,
+            &create_test_poverty_list(),
+            Arc::clone(&config),
+            create_test_cache(),
+            Trackers::default(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(result["result"]["dry_run"], true);
+        assert_eq!(result["result"]["applied"], false);
+        assert!(!result["result"]["rpcs"]["added"]
+            .as_array()
+            .unwrap()
+            .is_empty());
+
+        // Nothing was applied
+        assert_eq!(rpc_list.read().unwrap().len(), rpcs_before);
+        assert_eq!(config.read().unwrap().ttl, Settings::default().ttl);
+    }
 }
@@ -1,4 +1,7 @@
 mod accept;
+mod audit;
+pub mod auth;
 mod error;
 pub mod listener;
 mod methods;
+pub mod status;
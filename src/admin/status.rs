@@ -0,0 +1,357 @@
+// `blutgang status` asks a running instance how it's doing over the admin namespace and
+// prints it as tables, so checking on it over SSH doesn't take curl and jq.
+use std::time::Duration;
+
+use reqwest::Client;
+use serde_json::{
+    json,
+    Value,
+};
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Call `method` on the admin namespace at `url` and get its result
+async fn call(
+    client: &Client,
+    url: &str,
+    key: Option<&str>,
+    method: &str,
+) -> Result<Value, String> {
+    let mut request = client.post(url).json(&json!({
+        "id": 1,
+        "jsonrpc": "2.0",
+        "method": method,
+        "params": [],
+    }));
+    if let Some(key) = key {
+        request = request.bearer_auth(key);
+    }
+
+    let response = request.send().await.map_err(|err| err.to_string())?;
+    let status = response.status();
+    let body = response.text().await.map_err(|err| err.to_string())?;
+    if !status.is_success() {
+        return Err(format!("{}: {}", status, body));
+    }
+
+    let rx: Value = serde_json::from_str(&body).map_err(|err| err.to_string())?;
+    match rx.get("result") {
+        // Admin errors come back as a string result
+        Some(Value::String(err)) => Err(err.clone()),
+        Some(result) => Ok(result.clone()),
+        None => Err(format!("no result in {}", body)),
+    }
+}
+
+// Columns padded to their widest cell
+fn table(header: &[&str], rows: &[Vec<String>]) -> String {
+    let mut widths: Vec<usize> = header.iter().map(|name| name.len()).collect();
+    for row in rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.len());
+        }
+    }
+
+    let line = |cells: Vec<&str>| {
+        let cells: Vec<String> = cells
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{:<width$}", cell, width = width))
+            .collect();
+        cells.join("  ").trim_end().to_string()
+    };
+
+    let mut table = line(header.to_vec());
+    for row in rows {
+        table.push('\n');
+        table.push_str(&line(row.iter().map(String::as_str).collect()));
+    }
+    table
+}
+
+fn ms(value: &Value) -> String {
+    match value.as_f64() {
+        Some(ms) => format!("{:.1}ms", ms),
+        None => "-".to_string(),
+    }
+}
+
+fn bytes(value: &Value) -> String {
+    let bytes = value.as_u64().unwrap_or_default() as f64;
+    match bytes {
+        b if b >= 1e9 => format!("{:.1}GB", b / 1e9),
+        b if b >= 1e6 => format!("{:.1}MB", b / 1e6),
+        b if b >= 1e3 => format!("{:.1}kB", b / 1e3),
+        b => format!("{}B", b),
+    }
+}
+
+// Why an RPC isn't getting requests, or `ok`
+fn state(rpc: &Value) -> &'static str {
+    let flag = |name: &str| rpc[name].as_bool().unwrap_or_default();
+    match () {
+        _ if flag("paused") => "paused",
+        _ if flag("quarantined") => "quarantined",
+        _ if flag("poverty") => "poverty",
+        _ if flag("degraded") => "degraded",
+        _ if flag("is_erroring") => "erroring",
+        _ => "ok",
+    }
+}
+
+// Upstreams from `blutgang_rpc_status`, with the percentiles of `blutgang_status`
+fn upstreams(rpcs: &Value, responses: &Value) -> String {
+    let rpcs = rpcs.as_array().cloned().unwrap_or_default();
+    let best_head = rpcs
+        .iter()
+        .filter_map(|rpc| rpc["head"].as_u64())
+        .max()
+        .unwrap_or_default();
+
+    let rows: Vec<Vec<String>> = rpcs
+        .iter()
+        .map(|rpc| {
+            let name = rpc["name"].as_str().unwrap_or_default();
+            let percentiles = responses
+                .as_array()
+                .and_then(|responses| responses.iter().find(|rx| rx["name"] == name))
+                .map(|rx| rx["latency_ms"].clone())
+                .unwrap_or_default();
+            // Heads are 0 until the first health check, or when it fails
+            let (head, lag) = match rpc["head"].as_u64() {
+                Some(head) if head > 0 => (head.to_string(), (best_head - head).to_string()),
+                _ => ("-".to_string(), "-".to_string()),
+            };
+
+            vec![
+                name.to_string(),
+                state(rpc).to_string(),
+                ms(&rpc["latency_ms"]),
+                ms(&percentiles["p50"]),
+                ms(&percentiles["p99"]),
+                head,
+                lag,
+                format!("{:.2}", rpc["weight"].as_f64().unwrap_or_default()),
+            ]
+        })
+        .collect();
+
+    table(
+        &[
+            "NAME", "STATE", "LATENCY", "P50", "P99", "HEAD", "LAG", "WEIGHT",
+        ],
+        &rows,
+    )
+}
+
+// Cache from `blutgang_cache_stats`
+fn cache(stats: &Value) -> String {
+    let hits = stats["hits"].as_u64().unwrap_or_default();
+    let misses = stats["misses"].as_u64().unwrap_or_default();
+    let hit_rate = match hits + misses {
+        0 => "-".to_string(),
+        total => format!("{:.1}%", hits as f64 * 100.0 / total as f64),
+    };
+    let limit = |value: &Value, format: fn(&Value) -> String| {
+        match value.as_u64() {
+            Some(0) | None => "unbounded".to_string(),
+            Some(_) => format(value),
+        }
+    };
+
+    let row = vec![
+        stats["entries"].to_string(),
+        limit(&stats["max_entries"], |value| value.to_string()),
+        bytes(&stats["size_bytes"]),
+        limit(&stats["max_size_bytes"], bytes),
+        bytes(&stats["size_on_disk"]),
+        hit_rate,
+        stats["evictions"].to_string(),
+    ];
+
+    table(
+        &[
+            "ENTRIES",
+            "MAX",
+            "SIZE",
+            "MAX SIZE",
+            "ON DISK",
+            "HIT RATE",
+            "EVICTIONS",
+        ],
+        &[row],
+    )
+}
+
+// Tables of the instance whose admin namespace is at `url`, Err if we couldn't get its
+// upstreams
+async fn status(url: &str, key: Option<&str>) -> Result<String, String> {
+    let client = Client::builder().timeout(REQUEST_TIMEOUT).build().unwrap();
+
+    let rpcs = call(&client, url, key, "blutgang_rpc_status").await?;
+    // Percentiles are nice to have, don't fail without them
+    let responses = call(&client, url, key, "blutgang_status")
+        .await
+        .unwrap_or_default();
+
+    let cache = match call(&client, url, key, "blutgang_cache_stats").await {
+        Ok(stats) => cache(&stats),
+        Err(err) => format!("\x1b[93mWrn:\x1b[0m Could not get cache stats: {}", err),
+    };
+    Ok(format!("{}\n\n{}", upstreams(&rpcs, &responses), cache))
+}
+
+// Print the status of the instance whose admin namespace is at `url`. `key` is sent as
+// a bearer token if admin keys are set. Returns false if we couldn't get it.
+pub async fn print_status(url: &str, key: Option<&str>) -> bool {
+    match status(url, key).await {
+        Ok(status) => {
+            println!("{}", status);
+            true
+        }
+        Err(err) => {
+            println!(
+                "\x1b[31mErr:\x1b[0m Could not get status from {}: {}",
+                url, err
+            );
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http_body_util::{
+        BodyExt,
+        Full,
+    };
+    use hyper::{
+        body::Bytes,
+        server::conn::http1,
+        service::service_fn,
+        Request,
+        Response,
+    };
+    use hyper_util_blutgang::rt::TokioIo;
+    use std::convert::Infallible;
+    use tokio::net::TcpListener;
+
+    // Admin namespace answering every method with what `results` has for it, and the
+    // string result admin errors come back as for the rest
+    async fn admin(results: Value) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::task::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                let results = results.clone();
+                tokio::task::spawn(async move {
+                    let service = service_fn(|req: Request<hyper::body::Incoming>| {
+                        let results = results.clone();
+                        async move {
+                            let body = req.into_body().collect().await.unwrap().to_bytes();
+                            let tx: Value = serde_json::from_slice(&body).unwrap();
+                            let result = match results.get(tx["method"].as_str().unwrap()) {
+                                Some(result) => result.clone(),
+                                None => json!("Method not found"),
+                            };
+                            let rx = json!({"jsonrpc": "2.0", "id": tx["id"], "result": result});
+                            Ok::<_, Infallible>(Response::new(Full::new(Bytes::from(
+                                rx.to_string(),
+                            ))))
+                        }
+                    });
+                    let _ = http1::Builder::new()
+                        .serve_connection(TokioIo::new(stream), service)
+                        .await;
+                });
+            }
+        });
+        format!("http://{}", address)
+    }
+
+    #[test]
+    fn test_upstreams() {
+        let rpcs = json!([
+            {"name": "a", "head": 110, "latency_ms": 12.34, "weight": 0.75},
+            {"name": "b", "head": 100, "poverty": true, "latency_ms": 80.0, "weight": 0.25},
+            {"name": "c", "head": 0, "paused": true, "weight": 0.0},
+        ]);
+        let responses = json!([{"name": "a", "latency_ms": {"p50": 10.0, "p99": 40.0}}]);
+
+        assert_eq!(
+            upstreams(&rpcs, &responses),
+            "NAME  STATE    LATENCY  P50     P99     HEAD  LAG  WEIGHT\n\
+             a     ok       12.3ms   10.0ms  40.0ms  110   0    0.75\n\
+             b     poverty  80.0ms   -       -       100   10   0.25\n\
+             c     paused   -        -       -       -     -    0.00"
+        );
+    }
+
+    #[test]
+    fn test_cache() {
+        let stats = json!({
+            "entries": 1000,
+            "size_bytes": 2_500_000,
+            "size_on_disk": 4_000_000,
+            "max_entries": 0,
+            "max_size_bytes": 10_485_760,
+            "hits": 3,
+            "misses": 1,
+            "evictions": 7,
+        });
+
+        assert_eq!(
+            cache(&stats),
+            "ENTRIES  MAX        SIZE   MAX SIZE  ON DISK  HIT RATE  EVICTIONS\n\
+             1000     unbounded  2.5MB  10.5MB    4.0MB    75.0%     7"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_status() {
+        let url = admin(json!({
+            "blutgang_rpc_status": [{"name": "a", "head": 5, "latency_ms": 2.0, "weight": 1.0}],
+            "blutgang_status": [{"name": "a", "latency_ms": {"p50": 1.0, "p99": 3.0}}],
+            "blutgang_cache_stats": {
+                "entries": 10,
+                "size_bytes": 2000,
+                "size_on_disk": 4000,
+                "max_entries": 100,
+                "max_size_bytes": 0,
+                "hits": 1,
+                "misses": 1,
+                "evictions": 0,
+            },
+        }))
+        .await;
+
+        assert_eq!(
+            status(&url, None).await.unwrap(),
+            "NAME  STATE  LATENCY  P50    P99    HEAD  LAG  WEIGHT\n\
+             a     ok     2.0ms    1.0ms  3.0ms  5     0    1.00\n\
+             \n\
+             ENTRIES  MAX  SIZE   MAX SIZE   ON DISK  HIT RATE  EVICTIONS\n\
+             10       100  2.0kB  unbounded  4.0kB    50.0%     0"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_status_errors() {
+        // Upstreams without the rest
+        let url = admin(json!({"blutgang_rpc_status": []})).await;
+        assert_eq!(
+            status(&url, None).await.unwrap(),
+            "NAME  STATE  LATENCY  P50  P99  HEAD  LAG  WEIGHT\n\
+             \n\
+             \x1b[93mWrn:\x1b[0m Could not get cache stats: Method not found"
+        );
+
+        let url = admin(json!({})).await;
+        assert_eq!(
+            status(&url, None).await,
+            Err("Method not found".to_string())
+        );
+    }
+}
@@ -0,0 +1,91 @@
+use crate::anomaly::types::{
+    Anomaly,
+    AnomalyKind,
+};
+
+use reqwest::Client;
+use serde_json::{
+    json,
+    Value,
+};
+use tokio::sync::mpsc;
+
+fn describe(anomaly: &Anomaly) -> String {
+    let mut description = match &anomaly.kind {
+        AnomalyKind::Volume { requests, baseline } => {
+            format!(
+                "{} sent {} requests, usually sends {:.0}",
+                anomaly.client, requests, baseline
+            )
+        }
+        AnomalyKind::MethodMix {
+            distance,
+            top_method,
+        } => {
+            format!(
+                "{} changed its method mix by {:.0}%, mostly calling {}",
+                anomaly.client,
+                distance * 100.0,
+                top_method
+            )
+        }
+    };
+
+    if anomaly.throttled {
+        description.push_str(", throttling");
+    }
+    description
+}
+
+fn alert_body(anomaly: &Anomaly) -> Value {
+    json!({
+        "source": "blutgang",
+        "alert": "usage_anomaly",
+        "client": anomaly.client,
+        "description": describe(anomaly),
+        "throttled": anomaly.throttled,
+    })
+}
+
+// Print every anomaly we detect, and send it to `webhook` if set
+pub async fn anomaly_alerts(mut alerts_rx: mpsc::Receiver<Anomaly>, webhook: Option<String>) {
+    let client = Client::new();
+
+    while let Some(anomaly) = alerts_rx.recv().await {
+        println!("\x1b[93mWrn:\x1b[0m Usage anomaly: {}", describe(&anomaly));
+
+        if let Some(webhook) = &webhook {
+            if let Err(err) = client
+                .post(webhook)
+                .json(&alert_body(&anomaly))
+                .send()
+                .await
+            {
+                println!("\x1b[31mErr:\x1b[0m Could not send anomaly alert: {}", err);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_describe() {
+        let anomaly = Anomaly {
+            client: "leaked-key".to_string(),
+            kind: AnomalyKind::MethodMix {
+                distance: 0.9,
+                top_method: "eth_getLogs".to_string(),
+            },
+            throttled: true,
+        };
+
+        assert_eq!(
+            describe(&anomaly),
+            "leaked-key changed its method mix by 90%, mostly calling eth_getLogs, throttling"
+        );
+        assert_eq!(alert_body(&anomaly)["client"], "leaked-key");
+    }
+}
@@ -0,0 +1,295 @@
+use crate::config::types::AnomalySettings;
+
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{
+        Duration,
+        Instant,
+    },
+};
+
+use tokio::sync::mpsc;
+
+// How much a finished window moves the baseline
+const BASELINE_WEIGHT: f64 = 0.2;
+
+// Windows we need to see before we know what normal looks like for a client
+const WARMUP_WINDOWS: u32 = 5;
+
+// Forget clients we haven't seen in this long once we track too many of them
+const IDLE_TIMEOUT: Duration = Duration::from_secs(3600);
+const PRUNE_THRESHOLD: usize = 16384;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AnomalyKind {
+    // Requests in the window, and the usual amount
+    Volume { requests: u64, baseline: f64 },
+    // How far the method mix moved, and the most used method in the window
+    MethodMix { distance: f64, top_method: String },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Anomaly {
+    pub client: String,
+    pub kind: AnomalyKind,
+    pub throttled: bool,
+}
+
+#[derive(Debug)]
+struct ClientUsage {
+    window_start: Instant,
+    requests: u64,
+    methods: HashMap<String, u64>,
+    // Usual requests per window and the usual share of each method
+    baseline_requests: f64,
+    baseline_methods: HashMap<String, f64>,
+    windows: u32,
+    throttled_until: Option<Instant>,
+}
+
+impl ClientUsage {
+    fn new(now: Instant) -> Self {
+        Self {
+            window_start: now,
+            requests: 0,
+            methods: HashMap::new(),
+            baseline_requests: 0.0,
+            baseline_methods: HashMap::new(),
+            windows: 0,
+            throttled_until: None,
+        }
+    }
+
+    fn is_throttled(&self, now: Instant) -> bool {
+        self.throttled_until.is_some_and(|until| until > now)
+    }
+
+    // Total variation distance between this window's method mix and the baseline
+    fn mix_distance(&self) -> f64 {
+        let requests = self.requests as f64;
+        let mut distance: f64 = self
+            .methods
+            .iter()
+            .map(|(method, count)| {
+                let share = *count as f64 / requests;
+                (share - self.baseline_methods.get(method).unwrap_or(&0.0)).abs()
+            })
+            .sum();
+        distance += self
+            .baseline_methods
+            .iter()
+            .filter(|(method, _)| !self.methods.contains_key(*method))
+            .map(|(_, share)| share)
+            .sum::<f64>();
+
+        distance / 2.0
+    }
+
+    // Check the window we just finished against the baseline, then fold it into it
+    fn finish_window(&mut self, settings: &AnomalySettings) -> Option<AnomalyKind> {
+        let mut anomaly = None;
+
+        if self.windows >= WARMUP_WINDOWS && self.requests >= settings.min_requests {
+            let distance = self.mix_distance();
+
+            if self.requests as f64 > self.baseline_requests * settings.volume_factor {
+                anomaly = Some(AnomalyKind::Volume {
+                    requests: self.requests,
+                    baseline: self.baseline_requests,
+                });
+            } else if distance > settings.mix_threshold {
+                let top_method = self
+                    .methods
+                    .iter()
+                    .max_by_key(|(_, count)| **count)
+                    .map(|(method, _)| method.clone())
+                    .unwrap_or_default();
+                anomaly = Some(AnomalyKind::MethodMix {
+                    distance,
+                    top_method,
+                });
+            }
+        }
+
+        // Sustained changes become the new normal over time
+        let weight = if self.windows == 0 {
+            1.0
+        } else {
+            BASELINE_WEIGHT
+        };
+        let requests = self.requests as f64;
+        self.baseline_requests += (requests - self.baseline_requests) * weight;
+        for share in self.baseline_methods.values_mut() {
+            *share *= 1.0 - weight;
+        }
+        for (method, count) in self.methods.drain() {
+            *self.baseline_methods.entry(method).or_insert(0.0) += count as f64 / requests * weight;
+        }
+        self.baseline_methods.retain(|_, share| *share > 0.001);
+
+        self.windows += 1;
+        self.requests = 0;
+
+        anomaly
+    }
+}
+
+// Flags clients whose request volume or method mix suddenly changes,
+// e.g. a leaked key that starts scraping `eth_getLogs`.
+#[derive(Debug)]
+pub struct AnomalyDetector {
+    settings: AnomalySettings,
+    clients: Mutex<HashMap<String, ClientUsage>>,
+    alerts: mpsc::Sender<Anomaly>,
+}
+
+impl AnomalyDetector {
+    pub fn new(settings: AnomalySettings) -> (Self, mpsc::Receiver<Anomaly>) {
+        let (alerts, alerts_rx) = mpsc::channel(1024);
+        let detector = Self {
+            settings,
+            clients: Mutex::new(HashMap::new()),
+            alerts,
+        };
+
+        (detector, alerts_rx)
+    }
+
+    // Record a request by `client`. Returns false if the client is throttled
+    // and already used up its usual volume for this window.
+    pub fn record(&self, client: &str, method: &str) -> bool {
+        self.record_at(client, method, Instant::now())
+    }
+
+    fn record_at(&self, client: &str, method: &str, now: Instant) -> bool {
+        let mut clients = self.clients.lock().unwrap_or_else(|e| e.into_inner());
+        if clients.len() > PRUNE_THRESHOLD {
+            clients.retain(|_, usage| now.duration_since(usage.window_start) < IDLE_TIMEOUT);
+        }
+
+        let usage = clients
+            .entry(client.to_string())
+            .or_insert_with(|| ClientUsage::new(now));
+
+        let elapsed = now.duration_since(usage.window_start);
+        if elapsed >= self.settings.window {
+            // Idle windows aren't counted, clients coming back after a break aren't anomalous
+            if let Some(kind) = usage.finish_window(&self.settings) {
+                let throttled = self.settings.throttle;
+                if throttled {
+                    usage.throttled_until = Some(now + self.settings.throttle_duration);
+                }
+
+                let _ = self.alerts.try_send(Anomaly {
+                    client: client.to_string(),
+                    kind,
+                    throttled,
+                });
+            }
+            usage.window_start = now;
+        }
+
+        if usage.is_throttled(now) && usage.requests as f64 >= usage.baseline_requests.max(1.0) {
+            return false;
+        }
+
+        usage.requests += 1;
+        *usage.methods.entry(method.to_string()).or_insert(0) += 1;
+
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings() -> AnomalySettings {
+        AnomalySettings {
+            enabled: true,
+            window: Duration::from_secs(60),
+            min_requests: 10,
+            volume_factor: 5.0,
+            mix_threshold: 0.5,
+            throttle: true,
+            throttle_duration: Duration::from_secs(300),
+            alert_webhook: None,
+        }
+    }
+
+    // Send `count` requests of `method` within one window
+    fn window(
+        detector: &AnomalyDetector,
+        start: Instant,
+        index: u32,
+        method: &str,
+        count: u64,
+    ) -> u64 {
+        let now = start + Duration::from_secs(60) * index;
+        (0..count)
+            .filter(|_| detector.record_at("key", method, now))
+            .count() as u64
+    }
+
+    #[test]
+    fn test_volume_anomaly() {
+        let (detector, mut alerts) = AnomalyDetector::new(settings());
+        let start = Instant::now();
+
+        for i in 0..6 {
+            window(&detector, start, i, "eth_call", 20);
+        }
+        assert!(alerts.try_recv().is_err());
+
+        // 10x the usual volume doesn't get flagged until the window is over
+        window(&detector, start, 6, "eth_call", 200);
+        assert!(alerts.try_recv().is_err());
+
+        // Throttled down to the usual volume
+        let allowed = window(&detector, start, 7, "eth_call", 200);
+        let alert = alerts.try_recv().unwrap();
+        assert!(matches!(
+            alert.kind,
+            AnomalyKind::Volume { requests: 200, .. }
+        ));
+        assert!(alert.throttled);
+        assert!(allowed < 100);
+    }
+
+    #[test]
+    fn test_method_mix_anomaly() {
+        let (detector, mut alerts) = AnomalyDetector::new(settings());
+        let start = Instant::now();
+
+        for i in 0..6 {
+            window(&detector, start, i, "eth_call", 20);
+        }
+        window(&detector, start, 6, "eth_getLogs", 20);
+        window(&detector, start, 7, "eth_call", 1);
+
+        let alert = alerts.try_recv().unwrap();
+        match alert.kind {
+            AnomalyKind::MethodMix {
+                distance,
+                top_method,
+            } => {
+                assert!(distance > 0.9);
+                assert_eq!(top_method, "eth_getLogs");
+            }
+            _ => panic!("expected a method mix anomaly"),
+        }
+    }
+
+    #[test]
+    fn test_warmup() {
+        let (detector, mut alerts) = AnomalyDetector::new(settings());
+        let start = Instant::now();
+
+        // New clients have no baseline to compare against
+        window(&detector, start, 0, "eth_call", 20);
+        window(&detector, start, 1, "eth_getLogs", 500);
+        window(&detector, start, 2, "eth_call", 1);
+        assert!(alerts.try_recv().is_err());
+    }
+}
@@ -1,50 +1,326 @@
 use crate::{
+    anomaly::types::AnomalyDetector,
+    balancer::await_block::{
+        await_block,
+        parse_query,
+        PATH as AWAIT_BLOCK_PATH,
+    },
+    balancer::block_cache::{
+        assemble_block,
+        decompose_block,
+        request_hash,
+        request_key,
+    },
+    balancer::block_index::record as record_block,
+    balancer::block_range::{
+        block_request,
+        parse_block_range,
+        range_error,
+        range_response,
+        BlockRange,
+        CONCURRENCY,
+    },
+    balancer::broadcast::broadcast,
+    balancer::classify::{
+        classify,
+        l2_family,
+        wallet_method_reason,
+        MethodClass,
+    },
+    balancer::coalesce::{
+        Coalescer,
+        Joined,
+    },
+    balancer::codec::CacheCodec,
+    balancer::encoding::{
+        encode_response,
+        read_body,
+        Encoding,
+    },
+    balancer::filters::{
+        creates_filter,
+        filter_id,
+        is_filter_method,
+        FilterTracker,
+    },
     balancer::format::{
         get_block_number_from_request,
         incoming_to_value,
+        is_notification,
+        limited_incoming_to_value,
+        normalize_response_id,
+        set_cached_id,
+    },
+    balancer::hardened::{
+        check_hardened,
+        get_api_key,
+        get_upstream_override,
+        is_listed_key,
+    },
+    balancer::head_gate::{
+        HeadGate,
+        WAIT,
+    },
+    balancer::hedge::{
+        can_hedge,
+        pick_hedge,
+        send_hedged,
+        HedgeDelay,
+    },
+    balancer::ids::next_id,
+    balancer::immutable::{
+        compact,
+        expand,
+        immutable_key,
+        indexed_block,
+        is_immutable,
+    },
+    balancer::inclusion::InclusionTracker,
+    balancer::logs_cache::{
+        gaps,
+        logs_error,
+        logs_request,
+        logs_response,
+        parse_logs_query,
+        segments,
+        split_logs,
+        LogsQuery,
+        Segment,
+        CONCURRENCY as LOGS_CONCURRENCY,
+    },
+    balancer::overrides::{
+        any_supports_overrides,
+        canonicalize_overrides,
+        has_overrides,
+        strip_overrides,
+        StateOverridePolicy,
+    },
+    balancer::pacing::BackfillPacer,
+    balancer::panic_guard::{
+        catch_panic,
+        internal_error,
+    },
+    balancer::provenance::{
+        annotate_response,
+        wants_provenance,
+        Provenance,
+    },
+    balancer::quorum::{
+        majority_read,
+        parse_quorum_call,
+        quorum_call,
+        quorum_error,
+        quorum_rpcs,
+    },
+    balancer::rewrite::rewrite,
+    balancer::schema::{
+        check as check_schema,
+        SchemaChecker,
     },
     balancer::selection::cache_rules::{
         cache_method,
         cache_result,
+        has_expired,
+        is_expired,
+        result_block_number,
+        set_expiry,
+        CachePolicy,
+        EXPIRY_TREE,
+    },
+    balancer::selection::select::{
+        pick_route,
+        Route,
+        Strategy,
+    },
+    balancer::send_queue::{
+        get_priority,
+        Priority,
+        SendQueue,
+    },
+    balancer::stale::{
+        is_latest_query,
+        penalize,
+        served_block,
+        Staleness,
+    },
+    balancer::static_responses::StaticResponses,
+    balancer::strict::{
+        is_legacy,
+        strict_response,
+        validate_request,
+    },
+    balancer::tx_queue::{
+        queued_response,
+        raw_transaction,
+        wants_queue,
+        TxQueue,
+        QUEUED_HEADER,
+    },
+    balancer::tx_tracker::{
+        lookup_hash,
+        read_hash,
+        TxTracker,
+    },
+    balancer::upstream_batch::UpstreamBatcher,
+    bitcoin::cache::{
+        cache_bitcoin_result,
+        get_height_from_request,
     },
-    balancer::selection::select::pick,
     cache_error,
+    config::types::HardenedSettings,
+    gas::{
+        methods::{
+            enrich_fee_history,
+            execute_fee_method,
+            fallback_priority_fee,
+            is_method_not_found,
+            priority_fee_response,
+            PRIORITY_FEE_METHOD,
+        },
+        types::FeeOracle,
+    },
+    health::{
+        export::healthy_upstreams,
+        head_cache::HeadCache,
+        watchdog::{
+            Watchdog,
+            READY_PATH,
+        },
+    },
+    logging::{
+        filter::{
+            log_enabled,
+            log_sampled,
+            LogLevel,
+        },
+        format::{
+            log_line,
+            log_request,
+            request_id,
+            RequestLog,
+            REQUEST_ID_HEADER,
+        },
+    },
+    malformed_response,
+    method_not_allowed,
+    metrics::{
+        cache_usage::{
+            execute_cache_stats_method,
+            CacheUsage,
+        },
+        histogram::{
+            LatencyHistograms,
+            CONTENT_TYPE as METRICS_CONTENT_TYPE,
+            NO_UPSTREAM,
+            PATH as METRICS_PATH,
+        },
+        store::EventStore,
+        talkers::TopTalkers,
+        trace::sampled_trace_id,
+    },
     no_rpc_available,
+    pinned_rpc_unavailable,
     print_cache_error,
-    rpc::types::Rpc,
+    quota_exceeded,
+    rate_limited,
+    ratelimit::types::{
+        Limited,
+        RateLimiter,
+    },
+    request_too_large,
+    rpc::types::{
+        Protocol,
+        Rpc,
+    },
+    slo::types::SloTracker,
+    solana::{
+        batch::{
+            status_request,
+            StatusRequest,
+        },
+        cache::{
+            cache_solana_result,
+            get_slot_from_request,
+        },
+    },
+    stale_response,
+    subscriptions::{
+        methods::execute_subscription_method,
+        sse::{
+            parse_query as parse_events_query,
+            EventHub,
+            PATH as EVENTS_PATH,
+        },
+        types::SubscriptionData,
+    },
     timed_out,
+    upstream_overloaded,
+    wallet::{
+        manager::{
+            take_replacements,
+            TxManager,
+        },
+        types::{
+            Upstream,
+            Wallet,
+            WALLET_METHODS,
+        },
+    },
     NamedBlocknumbers,
     Settings,
 };
 
 use serde_json::{
+    json,
     to_vec,
     Value,
 };
-use simd_json;
-
-// Select either blake3 or xxhash based on the features
-#[cfg(not(feature = "xxhash"))]
-use blake3::hash;
 
-#[cfg(feature = "xxhash")]
-use xxhash_rust::xxh3::xxh3_64;
 #[cfg(feature = "xxhash")]
 use zerocopy::AsBytes; // Impls AsBytes trait for u64
 
-use http_body_util::Full;
+use http_body_util::{
+    combinators::BoxBody,
+    BodyExt,
+    Full,
+    StreamBody,
+};
 use hyper::{
-    body::Bytes,
+    body::{
+        Body,
+        Bytes,
+        Frame,
+    },
     header::HeaderValue,
+    Method,
     Request,
+    StatusCode,
+};
+use sled::{
+    Db,
+    IVec,
 };
-use sled::Db;
 
-use tokio::time::timeout;
+use tokio::{
+    sync::{
+        mpsc,
+        watch,
+        Semaphore,
+    },
+    task::JoinSet,
+    time::{
+        sleep,
+        timeout,
+    },
+};
+use tokio_stream::{
+    wrappers::ReceiverStream,
+    StreamExt,
+};
 
 use std::{
-    collections::BTreeMap,
     convert::Infallible,
+    net::SocketAddr,
     println,
     sync::{
         Arc,
@@ -56,9 +332,128 @@ use std::{
     },
 };
 
+// Name of our endpoint in SLO reports
+const DEFAULT_ENDPOINT: &str = "default";
+
+// Requests of a batch we serve at once, the rest wait for one of them to finish
+const BATCH_CONCURRENCY: usize = 32;
+
+#[derive(Clone)]
 struct RequestParams {
     ttl: u128,
     max_retries: u32,
+    // Only present in hardened mode
+    hardened: Option<HardenedSettings>,
+    export_upstreams: bool,
+    strategy: Strategy,
+    strict_jsonrpc: bool,
+    legacy_jsonrpc: bool,
+    logs_cache_chunk: u64,
+    stale_latest_delta: u64,
+    max_batch_size: usize,
+    upstream_override_keys: Vec<String>,
+    // RPC a privileged client asked for with `x-blutgang-upstream`
+    upstream: Option<String>,
+    // Annotate responses with where they came from. Starts out as whether it's allowed.
+    provenance: bool,
+    // Only present if responses for unfinalized blocks expire
+    unfinalized_ttl: Option<Duration>,
+    // Only present if slow requests get hedged
+    hedge_delay: Option<HedgeDelay>,
+    // What we log the request as, and send back in `x-request-id`
+    request_id: String,
+}
+
+impl RequestParams {
+    fn new(config: &Settings, request_id: String) -> Self {
+        RequestParams {
+            ttl: config.ttl,
+            max_retries: config.max_retries,
+            hardened: config.hardened.enabled.then(|| config.hardened.clone()),
+            export_upstreams: config.export_upstreams,
+            strategy: config.strategy,
+            strict_jsonrpc: config.strict_jsonrpc,
+            legacy_jsonrpc: config.legacy_jsonrpc,
+            logs_cache_chunk: config.logs_cache_chunk,
+            stale_latest_delta: config.stale_latest_delta,
+            max_batch_size: config.max_batch_size,
+            upstream_override_keys: config.upstream_override_keys.clone(),
+            upstream: None,
+            provenance: config.expose_provenance,
+            unfinalized_ttl: unfinalized_ttl(config),
+            hedge_delay: config.hedge_delay,
+            request_id,
+        }
+    }
+}
+
+fn unfinalized_ttl(config: &Settings) -> Option<Duration> {
+    (config.unfinalized_ttl > 0).then(|| Duration::from_millis(config.unfinalized_ttl))
+}
+
+// Shared state every connection needs in order to process requests
+#[derive(Debug, Clone)]
+pub struct ConnectionParams {
+    pub rpc_list_rwlock: Arc<RwLock<Vec<Rpc>>>,
+    pub finalized_rx: Arc<watch::Receiver<u64>>,
+    // Latest block from the head tracker
+    pub blocknum_rx: watch::Receiver<u64>,
+    pub named_numbers: Arc<RwLock<NamedBlocknumbers>>,
+    pub head_cache: Arc<HeadCache>,
+    pub sub_data: Arc<SubscriptionData>,
+    // Shares new blocks with SSE streams
+    pub events: Arc<EventHub>,
+    pub cache: Arc<Db>,
+    pub config: Arc<RwLock<Settings>>,
+    // Only present in hardened mode
+    pub rate_limiter: Option<Arc<RateLimiter>>,
+    // Limits how many heavy methods can be in flight at once
+    pub heavy_semaphore: Arc<Semaphore>,
+    // Only present in Solana mode
+    pub signature_batcher: Option<mpsc::Sender<StatusRequest>>,
+    // Only present if upstream batching is enabled
+    pub upstream_batcher: Option<UpstreamBatcher>,
+    // Only present if SLO tracking is enabled
+    pub slo: Option<Arc<SloTracker>>,
+    // Only present if the transaction negative cache or read-your-writes is enabled
+    pub tx_tracker: Option<Arc<TxTracker>>,
+    pub filters: Arc<FilterTracker>,
+    // Only present if static responses are enabled
+    pub static_responses: Option<Arc<StaticResponses>>,
+    // Only present if requests for the head get coalesced
+    pub coalescer: Option<Arc<Coalescer>>,
+    // Only present if `latest` queries are gated on the head
+    pub head_gate: Option<Arc<HeadGate>>,
+    // Only present if responses get checked against their schema
+    pub schema: Option<Arc<SchemaChecker>>,
+    // Only present if transactions can be queued while every RPC is down
+    pub tx_queue: Option<Arc<TxQueue>>,
+    // Compresses cached responses if enabled
+    pub codec: Arc<CacheCodec>,
+    // Only present if upstream concurrency is limited
+    pub send_queue: Option<Arc<SendQueue>>,
+    // Only present if backfill pacing is enabled
+    pub backfill_pacer: Option<Arc<BackfillPacer>>,
+    // Only present if anomaly detection is enabled
+    pub anomaly: Option<Arc<AnomalyDetector>>,
+    // Only present if the local wallet is enabled
+    pub wallet: Option<Arc<Wallet>>,
+    // Only present if the tx manager is enabled
+    pub tx_manager: Option<Arc<TxManager>>,
+    // Only present if the fee oracle is enabled
+    pub fee_oracle: Option<Arc<FeeOracle>>,
+    // Only present if time-to-inclusion tracking is enabled
+    pub inclusion: Option<Arc<InclusionTracker>>,
+    // Only present if metrics are enabled
+    pub metrics: Option<Arc<LatencyHistograms>>,
+    // Only present if top talkers are tracked
+    pub talkers: Option<Arc<TopTalkers>>,
+    // Only present if cache hits and misses are counted per client
+    pub cache_usage: Option<Arc<CacheUsage>>,
+    // Only present if the watchdog is enabled
+    pub watchdog: Option<Arc<Watchdog>>,
+    // Only present if the event store is enabled
+    pub event_store: Option<Arc<EventStore>>,
 }
 
 // Macros for accepting requests
@@ -66,12 +461,8 @@ struct RequestParams {
 macro_rules! accept {
     (
         $io:expr,
-        $rpc_list_rwlock:expr,
-        $cache:expr,
-        $finalized_rx:expr,
-        $named_numbers:expr,
-        $head_cache:expr,
-        $config:expr
+        $router:expr,
+        $socketaddr:expr
     ) => {
         // Bind the incoming connection to our service
         if let Err(err) = http1::Builder::new()
@@ -79,15 +470,7 @@ macro_rules! accept {
             .serve_connection(
                 $io,
                 service_fn(|req| {
-                    let response = accept_request(
-                        req,
-                        Arc::clone($rpc_list_rwlock),
-                        $finalized_rx,
-                        $named_numbers,
-                        $head_cache,
-                        Arc::clone($cache),
-                        $config,
-                    );
+                    let response = serve_routed(req, $router, $socketaddr);
                     response
                 }),
             )
@@ -105,262 +488,2485 @@ macro_rules! get_response {
         $cache:expr,
         $tx_hash:expr,
         $rpc_position:expr,
+        $cached:expr,
+        $retries:expr,
         $id:expr,
         $rpc_list_rwlock:expr,
         $finalized_rx:expr,
         $named_numbers:expr,
         $head_cache:expr,
         $ttl:expr,
-        $max_retries:expr
+        $max_retries:expr,
+        $route:expr,
+        $strategy:expr,
+        $heavy_semaphore:expr,
+        $protocol:expr,
+        $upstream_batcher:expr,
+        $slo:expr,
+        $codec:expr,
+        $send_queue:expr,
+        $pacer:expr,
+        $priority:expr,
+        $staleness:expr,
+        $cache_policy:expr,
+        $unfinalized_ttl:expr,
+        $hedge_delay:expr,
+        $quorum:expr,
+        $broadcast:expr,
+        $schema:expr
     ) => {
-        match $cache.get($tx_hash.as_bytes()) {
-            Ok(rax) => {
-                if let Some(mut rax) = rax {
-                    $rpc_position = None;
+        {
+            let cached = match $cache_policy {
+                CachePolicy::Never => Ok(None),
+                // Expired entries are misses, they get replaced once we have a new response
+                CachePolicy::Ttl(_) => {
+                    match $cache.open_tree(EXPIRY_TREE).and_then(|expiry| is_expired(&expiry, $tx_hash.as_bytes())) {
+                        Ok(true) => Ok(None),
+                        Ok(false) => $codec.get(&$cache, $tx_hash.as_bytes()),
+                        Err(err) => Err(err),
+                    }
+                },
+                // Entries for blocks that weren't finalized yet might expire too
+                CachePolicy::Default if $unfinalized_ttl.is_some() => {
+                    match $cache.open_tree(EXPIRY_TREE).and_then(|expiry| has_expired(&expiry, $tx_hash.as_bytes())) {
+                        Ok(true) => Ok(None),
+                        Ok(false) => $codec.get(&$cache, $tx_hash.as_bytes()),
+                        Err(err) => Err(err),
+                    }
+                },
+                _ => $codec.get(&$cache, $tx_hash.as_bytes()),
+            };
+            match cached {
+                Ok(cached) => {
+                    // Full blocks can also be put together from cached pieces
+                    let rax = match (cached, $protocol) {
+                        (None, Protocol::Evm) if $cache_policy == CachePolicy::Default => {
+                            assemble_block(&$tx, &$cache, $codec).map(IVec::from)
+                        },
+                        (rax, _) => rax,
+                    };
 
-                    // Reconstruct ID
-                    let mut cached: Value = simd_json::serde::from_slice(&mut rax).unwrap();
-
-                    cached["id"] = $id.into();
-                    cached.to_string()
-                } else {
-                    // Kinda jank but set the id back to what it was before
-                    $tx["id"] = $id.into();
-
-                    let tx_string = $tx.to_string();
-
-                    // Loop until we get a response
-                    let rx;
-                    let mut retries = 0;
-                    loop {
-                        // Get the next Rpc in line.
-                        let mut rpc;
-                        {
-                            let mut rpc_list = $rpc_list_rwlock.write().unwrap();
-                            (rpc, $rpc_position) = pick(&mut rpc_list);
-                        }
-                        println!("\x1b[35mInfo:\x1b[0m Forwarding to: {}", rpc.url);
+                    $codec.stats().record(rax.is_some());
+                    if let Some(rax) = rax {
+                        $rpc_position = None;
+                        $cached = true;
 
-                        // Check if we have any RPCs in the list, if not return error
-                        if $rpc_position == None {
-                            return (no_rpc_available!(), None);
-                        }
+                        // Reconstruct ID
+                        set_cached_id(&rax, &$id)
+                    } else {
+                        // Send the request with an internal id so clients can use whatever ids
+                        // they want, we restore the original one in the response.
+                        let upstream_id = next_id();
+                        $tx["id"] = upstream_id.into();
 
-                        // Send the request. And return a timeout if it takes too long
-                        //
-                        // Check if it contains any errors or if its `latest` and insert it if it isn't
-                        match timeout(
-                            Duration::from_millis($ttl.try_into().unwrap()),
-                            rpc.send_request($tx.clone()),
-                        )
-                        .await
-                        {
-                            Ok(rxa) => {
-                                rx = rxa.unwrap();
-                                break;
+                        // Serialize once, the same bytes get sent to every RPC we retry with
+                        let tx_bytes = Bytes::from(to_vec(&$tx).unwrap());
+
+                        // Heavy methods wait for a free slot before going upstream
+                        let _permit = match $heavy_semaphore {
+                            Some(semaphore) => Some(semaphore.acquire().await.unwrap()),
+                            None => None,
+                        };
+
+                        // Quorum reads and broadcast transactions go to several RPCs at once. If
+                        // none of them answer, we try again one RPC at a time like any other request.
+                        let fanout_rx = match ($quorum, $broadcast) {
+                            (Some(size), _) => {
+                                let rpcs = quorum_rpcs(&$rpc_list_rwlock.read().unwrap());
+                                let method = $tx["method"].as_str().unwrap_or_default().to_string();
+                                let ttl = Duration::from_millis($ttl.try_into().unwrap());
+                                majority_read(&method, tx_bytes.clone(), rpcs.into_iter().take(size).collect(), ttl).await
                             },
-                            Err(_) => {
-                                println!("\x1b[93mWrn:\x1b[0m An RPC request has timed out, picking new RPC and retrying.");
-                                rpc.update_latency($ttl as f64);
-                                retries += 1;
+                            (None, true) => {
+                                let rpcs = quorum_rpcs(&$rpc_list_rwlock.read().unwrap());
+                                let ttl = Duration::from_millis($ttl.try_into().unwrap());
+                                broadcast(tx_bytes.clone(), rpcs, ttl).await
                             },
+                            (None, false) => None,
                         };
 
-                        if retries == $max_retries {
-                            return (timed_out!(), $rpc_position,);
-                        }
-                    }
+                        // Loop until we get a response
+                        let rx;
+                        $retries = 0;
+                        let mut stale = false;
+                        let mut malformed = false;
+                        if let Some(rxa) = fanout_rx {
+                            // No single RPC to credit the latency to
+                            $rpc_position = None;
+                            rx = rxa;
+                        } else {
+                            loop {
+                                // Get the next Rpc in line.
+                                let mut rpc;
+                                {
+                                    let mut rpc_list = $rpc_list_rwlock.write().unwrap();
+                                    let strategy = $strategy.for_request(request_key(&$tx_hash), $retries);
+                                    (rpc, $rpc_position) = pick_route(&mut rpc_list, $route, strategy);
+                                }
 
-                    let mut rx_str = rx.as_str().to_string();
+                                // Check if we have any RPCs in the list, if not return error
+                                //
+                                // Pinned methods never fall back to other RPCs.
+                                if $rpc_position == None {
+                                    if let Route::Pinned(name) = $route {
+                                        return (pinned_rpc_unavailable!($id, name), None);
+                                    }
+                                    return (no_rpc_available!($id), None);
+                                }
+                                if log_sampled("balancer", LogLevel::Info) {
+                                    log_line(LogLevel::Info, "balancer", &format!("Forwarding to: {}", rpc.url));
+                                }
 
-                    // Don't cache responses that contain errors or missing trie nodes
-                    if cache_method(&tx_string) && cache_result(&rx) {
-                        // Insert the response hash into the head_cache
-                        let num = get_block_number_from_request($tx, $named_numbers);
+                                // Backfill waits until it fits under the rate limit of the RPC
+                                if let Some(pacer) = $pacer {
+                                    pacer.pace(&rpc, $priority).await;
+                                }
 
-                        // Insert the key of the request we made into our `head_cache`
-                        // so we can invalidate it and remove it from the DB if it reorgs.
-                        if let Some(num) = num {
-                            if num > *$finalized_rx.borrow() {
-                                let mut head_cache = $head_cache.write().unwrap();
-                                head_cache
-                                    .entry(num)
-                                    .or_insert_with(Vec::new)
-                                    .push($tx_hash.to_string());
+                                // Wait our turn if the RPC already has too many requests in flight.
+                                // Waiting doesn't count towards the timeout, it says nothing about the RPC.
+                                let _send_permit = match $send_queue {
+                                    Some(queue) => match queue.acquire(&rpc.name, $priority).await {
+                                        Ok(permit) => Some(permit),
+                                        Err(err) => return (upstream_overloaded!(err), None),
+                                    },
+                                    None => None,
+                                };
 
+                                // Send the request. And return a timeout if it takes too long
+                                //
+                                // Check if it contains any errors or if its `latest` and insert it if it isn't
+                                let ttl = Duration::from_millis($ttl.try_into().unwrap());
+                                let hedge_delay = $hedge_delay.and_then(|delay: HedgeDelay| delay.for_rpc(&rpc));
+                                let response = match ($upstream_batcher, hedge_delay) {
+                                    (Some(batcher), _) => timeout(ttl, batcher.send(&rpc, $tx.clone())).await,
+                                    // Slow requests also go to the next best RPC, and whoever answers first wins
+                                    (None, Some(delay)) => {
+                                        let pick = || pick_hedge(&$rpc_list_rwlock.read().unwrap(), &rpc.name, $route);
+                                        match timeout(ttl, send_hedged(&rpc, pick, tx_bytes.clone(), delay)).await {
+                                            Ok((rxa, Some((hedge, position)))) => {
+                                                rpc = hedge;
+                                                $rpc_position = Some(position);
+                                                Ok(rxa)
+                                            },
+                                            Ok((rxa, None)) => Ok(rxa),
+                                            Err(elapsed) => Err(elapsed),
+                                        }
+                                    },
+                                    (None, None) => timeout(ttl, rpc.send_raw(tx_bytes.clone())).await,
+                                };
+                                if let (Some(pacer), Ok(Ok(rxa))) = ($pacer, &response) {
+                                    pacer.report(&rpc.name, rxa);
+                                }
+                                let behind = match (&response, $staleness) {
+                                    (Ok(Ok(rxa)), Some(staleness)) => staleness.behind(&$tx, rxa),
+                                    _ => None,
+                                };
+                                let violation = match (&response, $schema) {
+                                    (Ok(Ok(rxa)), Some(schema)) if behind.is_none() => {
+                                        let method = $tx["method"].as_str().unwrap_or_default();
+                                        match schema.samples(method) {
+                                            true => check_schema(method, rxa),
+                                            false => None,
+                                        }
+                                    },
+                                    _ => None,
+                                };
+                                match response {
+                                    // Stale answers to `latest` queries are retried on a fresher RPC
+                                    Ok(Ok(_)) if behind.is_some() => {
+                                        if log_enabled("balancer", LogLevel::Warn) {
+                                            log_line(LogLevel::Warn, "balancer", &format!("{} answered {} blocks behind the head, picking new RPC and retrying.", rpc.name, behind.unwrap()));
+                                        }
+                                        if let Some(slo) = $slo {
+                                            slo.record_upstream(&rpc.name, false);
+                                        }
+                                        penalize(&$rpc_list_rwlock, &rpc.name, ttl.as_nanos() as f64);
+                                        stale = true;
+                                        $retries += 1;
+                                        if let Some(staleness) = $staleness {
+                                            if !staleness.wait.is_zero() && $retries < $max_retries {
+                                                sleep(staleness.wait).await;
+                                            }
+                                        }
+                                    },
+                                    // So are answers that don't look like what the spec says
+                                    Ok(Ok(_)) if violation.is_some() => {
+                                        let violation = violation.unwrap();
+                                        if log_enabled("balancer", LogLevel::Warn) {
+                                            log_line(LogLevel::Warn, "balancer", &format!("{} sent a malformed response: {}, picking new RPC and retrying.", rpc.name, violation));
+                                        }
+                                        if let Some(schema) = $schema {
+                                            schema.record(&rpc.name, &violation);
+                                        }
+                                        if let Some(slo) = $slo {
+                                            slo.record_upstream(&rpc.name, false);
+                                        }
+                                        malformed = true;
+                                        $retries += 1;
+                                    },
+                                    Ok(Ok(rxa)) => {
+                                        if let Some(slo) = $slo {
+                                            slo.record_upstream(&rpc.name, true);
+                                        }
+                                        rx = rxa;
+                                        break;
+                                    },
+                                    Ok(Err(err)) => {
+                                        if log_enabled("balancer", LogLevel::Warn) {
+                                            log_line(LogLevel::Warn, "balancer", &format!("An RPC request has failed: {}, picking new RPC and retrying.", err));
+                                        }
+                                        if let Some(slo) = $slo {
+                                            slo.record_upstream(&rpc.name, false);
+                                        }
+                                        $retries += 1;
+                                    },
+                                    Err(_) => {
+                                        if log_enabled("balancer", LogLevel::Warn) {
+                                            log_line(LogLevel::Warn, "balancer", "An RPC request has timed out, picking new RPC and retrying.");
+                                        }
+                                        if let Some(slo) = $slo {
+                                            slo.record_upstream(&rpc.name, false);
+                                        }
+                                        rpc.update_latency($ttl as f64);
+                                        $retries += 1;
+                                    },
+                                };
+
+                                if $retries == $max_retries {
+                                    if stale {
+                                        return (stale_response!($id), $rpc_position);
+                                    }
+                                    if malformed {
+                                        return (malformed_response!($id), $rpc_position);
+                                    }
+                                    return (timed_out!($id), $rpc_position,);
+                                }
                             }
+                        }
+
+                        // Replace our internal id with null. This is both what we cache
+                        // and what we put the original id back into.
+                        let normalized = normalize_response_id(&rx, upstream_id);
+
+                        // Full blocks also get their transactions cached, unless they expire
+                        let pieces = match (&normalized, $protocol, $cache_policy) {
+                            (_, _, CachePolicy::Never | CachePolicy::Ttl(_)) => Vec::new(),
+                            (Some(normalized), Protocol::Evm, _) => decompose_block(&$tx, normalized),
+                            _ => Vec::new(),
+                        };
+
+                        // Don't cache responses that contain errors or missing trie nodes
+                        //
+                        // Solana requests are cached depending on their commitment level,
+                        // and Bitcoin requests if they reference a block or transaction hash.
+                        // Methods with a cache rule are cached however it says.
+                        let method = $tx["method"].as_str().unwrap_or_default().to_string();
+                        let num = match ($protocol, $cache_policy) {
+                            (Protocol::Evm, CachePolicy::Default) if cache_method(&tx_bytes) && cache_result(&rx) => {
+                                get_block_number_from_request($tx, $named_numbers)
+                            },
+                            (Protocol::Solana, CachePolicy::Default) if cache_solana_result(&rx) => get_slot_from_request(&$tx),
+                            (Protocol::Bitcoin, CachePolicy::Default) if cache_bitcoin_result(&rx) => get_height_from_request(&$tx),
+                            (_, CachePolicy::Finalized) if cache_result(&rx) => {
+                                get_block_number_from_request($tx, $named_numbers)
+                                    .or_else(|| result_block_number(&rx))
+                                    .filter(|num| *num <= *$finalized_rx.borrow())
+                            },
+                            _ => None,
+                        };
+                        // Entries that don't reference a block are never dropped on reorgs
+                        let untracked = matches!($cache_policy, CachePolicy::Forever | CachePolicy::Ttl(_))
+                            && cache_result(&rx);
 
-                            // Replace the id with Value::Null and insert the request
-                            let mut rx_value: Value = unsafe {
-                                simd_json::serde::from_str(&mut rx_str).unwrap()
-                            };
-                            rx_value["id"] = Value::Null;
+                        // Responses we can't make sense of are passed through as they are.
+                        match normalized {
+                            Some(normalized) => {
+                                if num.is_some() || untracked {
+                                    // Insert the key of the request we made into our `head_cache`
+                                    // so we can invalidate it and remove it from the DB if it reorgs.
+                                    if let Some(num) = num.filter(|num| *num > *$finalized_rx.borrow()) {
+                                        let mut head_cache = $head_cache.write().unwrap();
+                                        let keys = head_cache.entry(num).or_insert_with(Vec::new);
+                                        keys.push($tx_hash.as_bytes().to_vec());
+                                        keys.extend(pieces.iter().map(|(key, _)| key.as_bytes().to_vec()));
+                                    }
 
-                            $cache.insert($tx_hash.as_bytes(), to_vec(&rx_value).unwrap().as_slice()).unwrap();
+                                    $codec.insert(&$cache, $tx_hash.as_bytes(), &normalized).unwrap();
+                                    for (key, rx) in &pieces {
+                                        $codec.insert(&$cache, key.as_bytes(), rx).unwrap();
+                                    }
+                                    // So they can be flushed by block later
+                                    if let Some(num) = num {
+                                        let keys = std::iter::once($tx_hash.as_bytes()).chain(pieces.iter().map(|(key, _)| key.as_bytes()));
+                                        for key in keys {
+                                            let _ = record_block(&$cache, key, &method, num, num);
+                                        }
+                                    }
+                                    if let CachePolicy::Ttl(ttl) = $cache_policy {
+                                        if let Ok(expiry) = $cache.open_tree(EXPIRY_TREE) {
+                                            let _ = set_expiry(&expiry, $tx_hash.as_bytes(), ttl);
+                                        }
+                                    }
+                                    // Only immutable once their block is finalized
+                                    if let (Some(ttl), Some(num), CachePolicy::Default) = ($unfinalized_ttl, num, $cache_policy) {
+                                        if let Ok(expiry) = $cache.open_tree(EXPIRY_TREE) {
+                                            let _ = match num > *$finalized_rx.borrow() {
+                                                true => set_expiry(&expiry, $tx_hash.as_bytes(), ttl),
+                                                false => expiry.remove($tx_hash.as_bytes()).map(|_| ()),
+                                            };
+                                        }
+                                    }
+                                }
+
+                                set_cached_id(&normalized, &$id)
+                            },
+                            None => rx,
                         }
                     }
-
-                    rx_str
+                }
+                Err(_) => {
+                    // If anything errors send an rpc request and see if it works, if not then gg
+                    print_cache_error!();
+                    $rpc_position = None;
+                    return (cache_error!($id), $rpc_position);
                 }
             }
-            Err(_) => {
-                // If anything errors send an rpc request and see if it works, if not then gg
-                print_cache_error!();
-                $rpc_position = None;
-                return (cache_error!(), $rpc_position);
+        }
+    };
+}
+
+// In hardened mode, rate limit clients by their API key, or IP if they don't have one
+// of the `allowed_keys`. Anyone can make up keys, so unlisted ones don't get their own bucket.
+// `weight` is how many requests to charge them for.
+fn is_rate_limited(
+    connection_params: &ConnectionParams,
+    api_key: Option<&str>,
+    socketaddr: SocketAddr,
+    weight: u64,
+) -> Option<Limited> {
+    let rate_limiter = connection_params.rate_limiter.as_ref()?;
+
+    let listed = api_key.filter(|key| {
+        is_listed_key(
+            key,
+            &connection_params
+                .config
+                .read()
+                .unwrap()
+                .hardened
+                .allowed_keys,
+        )
+    });
+    let client = listed
+        .map(str::to_string)
+        .unwrap_or_else(|| socketaddr.ip().to_string());
+    match rate_limiter.check(&client, weight) {
+        Ok(()) => None,
+        Err(limited) => {
+            if log_sampled("balancer", LogLevel::Warn) {
+                // Never log keys, only enough of their hash to tell them apart
+                let client = match listed {
+                    Some(key) => format!("key {}", &blake3::hash(key.as_bytes()).to_hex()[..8]),
+                    None => client,
+                };
+                let reason = match limited {
+                    Limited::Rate => "Rate limited",
+                    Limited::Quota => "Quota exceeded",
+                };
+                log_line(
+                    LogLevel::Warn,
+                    "balancer",
+                    &format!("{}: {}", reason, client),
+                );
             }
+            Some(limited)
         }
+    }
+}
+
+// How many requests `tx` is worth to the rate limiter. `blutgang_getBlocks` fetches every
+// block of its page, so it counts as one request per block.
+fn request_weight(tx: &Value, named_numbers: &NamedBlocknumbers) -> u64 {
+    match parse_block_range(tx, named_numbers) {
+        Some(Ok(range)) => range.page().0.count() as u64,
+        _ => 1,
+    }
+}
+
+// Stream new heads and logs over SSE
+fn accept_events(
+    tx: &Request<hyper::body::Incoming>,
+    connection_params: &ConnectionParams,
+    socketaddr: SocketAddr,
+) -> hyper::Response<BoxBody<Bytes, Infallible>> {
+    let api_key = get_api_key(tx.headers());
+    if let Some(limited) = is_rate_limited(connection_params, api_key.as_deref(), socketaddr, 1) {
+        let rx: Result<hyper::Response<Full<Bytes>>, Infallible> = match limited {
+            Limited::Rate => rate_limited!(),
+            Limited::Quota => quota_exceeded!(),
+        };
+        return rx.unwrap().map(BodyExt::boxed);
+    }
+
+    let request = match connection_params.config.read().unwrap().protocol() {
+        Protocol::Evm => parse_events_query(tx.uri().query()),
+        _ => Err("only available for EVM chains".to_string()),
     };
+    match request {
+        Ok(request) => {
+            let events = ReceiverStream::new(connection_params.events.stream(request))
+                .map(|event| Ok(Frame::data(event)));
+            hyper::Response::builder()
+                .status(200)
+                .header("Content-Type", "text/event-stream")
+                .header("Cache-Control", "no-cache")
+                // Stops nginx from buffering the stream
+                .header("X-Accel-Buffering", "no")
+                .body(StreamBody::new(events).boxed())
+                .unwrap()
+        }
+        Err(err) => {
+            hyper::Response::builder()
+                .status(400)
+                .body(Full::new(Bytes::from(err)).boxed())
+                .unwrap()
+        }
+    }
 }
 
 // Pick RPC and send request to it. In case the result is cached,
 // read and return from the cache.
 async fn forward_body(
     tx: Request<hyper::body::Incoming>,
-    rpc_list_rwlock: &Arc<RwLock<Vec<Rpc>>>,
-    finalized_rx: &tokio::sync::watch::Receiver<u64>,
-    named_numbers: &Arc<RwLock<NamedBlocknumbers>>,
-    head_cache: &Arc<RwLock<BTreeMap<u64, Vec<String>>>>,
-    cache: Arc<Db>,
-    params: RequestParams,
+    connection_params: &ConnectionParams,
+    socketaddr: SocketAddr,
+    mut params: RequestParams,
 ) -> (
     Result<hyper::Response<Full<Bytes>>, Infallible>,
     Option<usize>,
 ) {
-    // Check if body has application/json
-    if tx.headers().get("content-type") != Some(&HeaderValue::from_static("application/json")) {
+    // Serve the list of healthy upstreams if enabled
+    if params.export_upstreams && tx.method() == Method::GET && tx.uri().path() == "/upstreams" {
+        let rx = healthy_upstreams(&connection_params.rpc_list_rwlock.read().unwrap());
         return (
             Ok(hyper::Response::builder()
-                .status(400)
-                .body(Full::new(Bytes::from("Improper content-type header")))
+                .status(200)
+                .header("Content-Type", "application/json")
+                .body(Full::new(Bytes::from(rx.to_string())))
                 .unwrap()),
             None,
         );
     }
 
-    // Convert incoming body to serde value
-    let mut tx = incoming_to_value(tx).await.unwrap();
+    let priority = get_priority(tx.headers());
+    let queue_consent = wants_queue(tx.headers());
 
-    // Get the id of the request and set it to 0 for caching
-    //
-    // We're doing this ID gymnastics because we're hashing the
-    // whole request and we don't want the ID as it's arbitrary
-    // and does not impact the request result.
-    let id = tx["id"].take().as_u64().unwrap_or(0);
+    let api_key = get_api_key(tx.headers());
 
-    // Hash the request with either blake3 or xxhash depending on the enabled feature
-    let tx_hash;
-    #[cfg(not(feature = "xxhash"))]
-    {
-        tx_hash = hash(to_vec(&tx).unwrap().as_slice());
-    }
-    #[cfg(feature = "xxhash")]
-    {
-        tx_hash = xxh3_64(to_vec(&tx).unwrap().as_slice());
+    // Privileged clients can pick the RPC, e.g. to compare what providers respond with
+    match get_upstream_override(
+        tx.headers(),
+        api_key.as_deref(),
+        &params.upstream_override_keys,
+    ) {
+        Ok(upstream) => params.upstream = upstream,
+        Err(err) => return (method_not_allowed!(Value::Null, err), None),
     }
+    params.provenance = params.provenance && wants_provenance(tx.headers());
 
-    // RPC used to get the response, we use it to update the latency for it later.
-    let mut rpc_position;
+    // Long-poll for the next block
+    if tx.method() == Method::GET && tx.uri().path() == AWAIT_BLOCK_PATH {
+        match is_rate_limited(connection_params, api_key.as_deref(), socketaddr, 1) {
+            Some(Limited::Rate) => return (rate_limited!(), None),
+            Some(Limited::Quota) => return (quota_exceeded!(), None),
+            None => {}
+        }
+        let rx = match parse_query(tx.uri().query()) {
+            Ok((after, wait)) => {
+                let rx = await_block(connection_params.blocknum_rx.clone(), after, wait).await;
+                hyper::Response::builder()
+                    .status(200)
+                    .header("Content-Type", "application/json")
+                    .body(Full::new(Bytes::from(rx.to_string())))
+            }
+            Err(err) => {
+                hyper::Response::builder()
+                    .status(400)
+                    .body(Full::new(Bytes::from(err)))
+            }
+        };
+        return (Ok(rx.unwrap()), None);
+    }
 
-    // Get the response from either the DB or from a RPC. If it timeouts, retry.
-    let rax = get_response!(
-        tx,
-        cache,
-        tx_hash,
-        rpc_position,
-        id,
-        rpc_list_rwlock,
-        finalized_rx,
-        named_numbers,
-        head_cache,
-        params.ttl,
-        params.max_retries
-    );
+    // Bodies can be JSON, or MessagePack/CBOR we translate to and from JSON
+    let encoding = match Encoding::from_content_type(tx.headers().get("content-type")) {
+        Some(encoding) => encoding,
+        None => {
+            return (
+                Ok(hyper::Response::builder()
+                    .status(400)
+                    .body(Full::new(Bytes::from("Improper content-type header")))
+                    .unwrap()),
+                None,
+            );
+        }
+    };
 
-    // Convert rx to bytes and but it in a Buf
-    let body = hyper::body::Bytes::from(rax);
+    // Convert incoming body to serde value
+    let tx = if encoding != Encoding::Json {
+        let limit = params
+            .hardened
+            .as_ref()
+            .map_or(usize::MAX, |hardened| hardened.max_body_size);
+        let body = match read_body(tx, limit).await {
+            Ok(body) => body,
+            Err(_) => return (request_too_large!(), None),
+        };
+        match encoding.decode(&body) {
+            Ok(tx) => tx,
+            Err(err) => {
+                return (
+                    Ok(hyper::Response::builder()
+                        .status(400)
+                        .body(Full::new(Bytes::from(format!(
+                            "Invalid request body: {}",
+                            err
+                        ))))
+                        .unwrap()),
+                    None,
+                );
+            }
+        }
+    } else if let Some(hardened) = &params.hardened {
+        match limited_incoming_to_value(tx, hardened.max_body_size).await {
+            Ok(tx) => tx,
+            Err(_) => return (request_too_large!(), None),
+        }
+    } else {
+        incoming_to_value(tx).await.unwrap()
+    };
 
-    // Put it in a http_body_util::Full
-    let body = Full::new(body);
+    // Batches count as every request in them
+    let weight = {
+        let named_numbers = connection_params.named_numbers.read().unwrap();
+        match &tx {
+            Value::Array(batch) => {
+                batch
+                    .iter()
+                    .map(|tx| request_weight(tx, &named_numbers))
+                    .sum::<u64>()
+                    .max(1)
+            }
+            tx => request_weight(tx, &named_numbers),
+        }
+    };
+    match is_rate_limited(connection_params, api_key.as_deref(), socketaddr, weight) {
+        Some(Limited::Rate) => return (rate_limited!(), None),
+        Some(Limited::Quota) => return (quota_exceeded!(), None),
+        None => {}
+    }
 
-    // Build the response
-    let res = hyper::Response::builder()
-        .status(200)
-        .header("Content-Type", "application/json")
-        .body(body)
-        .unwrap();
+    // Every request of a batch is served on its own
+    let (rx, rpc_position) = if let Value::Array(batch) = tx {
+        let rx = forward_batch(
+            batch,
+            connection_params,
+            socketaddr,
+            params,
+            api_key,
+            priority,
+            queue_consent,
+        );
+        (rx.await, None)
+    } else {
+        let provenance = params.provenance;
+        let (rx, rpc_position) = forward_value(
+            tx,
+            connection_params,
+            socketaddr,
+            params,
+            api_key,
+            priority,
+            queue_consent,
+        )
+        .await;
+        let Ok(rx) = rx;
+        let rx = match provenance {
+            true => annotate_response(rx).await,
+            false => rx,
+        };
+        (Ok(rx), rpc_position)
+    };
 
-    (Ok(res), rpc_position)
+    let Ok(rx) = rx;
+    (Ok(encode_response(rx, encoding).await), rpc_position)
 }
 
-// Forward the request to *a* RPC picked by the algo set by the user.
-// Measures the time needed for a request, and updates the respective
-// RPC lself.
-// In case of a timeout, returns an error.
-pub async fn accept_request(
-    tx: Request<hyper::body::Incoming>,
-    rpc_list_rwlock: Arc<RwLock<Vec<Rpc>>>,
-    finalized_rx: &tokio::sync::watch::Receiver<u64>,
-    named_numbers: &Arc<RwLock<NamedBlocknumbers>>,
-    head_cache: &Arc<RwLock<BTreeMap<u64, Vec<String>>>>,
-    cache: Arc<Db>,
-    config: &Arc<RwLock<Settings>>,
-) -> Result<hyper::Response<Full<Bytes>>, Infallible> {
-    // Send request and measure time
-    let response: Result<hyper::Response<Full<Bytes>>, Infallible>;
-    let rpc_position: Option<usize>;
+// Serve a single request, from the cache or the RPCs
+async fn forward_value(
+    mut tx: Value,
+    connection_params: &ConnectionParams,
+    socketaddr: SocketAddr,
+    params: RequestParams,
+    api_key: Option<String>,
+    priority: Priority,
+    // Client is fine with `eth_sendRawTransaction` being queued if every RPC is down
+    queue_consent: bool,
+) -> (
+    Result<hyper::Response<Full<Bytes>>, Infallible>,
+    Option<usize>,
+) {
+    let started = Instant::now();
 
-    // RequestParams from config
-    let params = {
-        let config_guard = config.read().unwrap();
-        RequestParams {
-            ttl: config_guard.ttl,
-            max_retries: config_guard.max_retries,
+    // In strict mode, only requests that follow the spec get through
+    let legacy = params.strict_jsonrpc && is_legacy(&tx);
+    if params.strict_jsonrpc {
+        if let Err(err) = validate_request(&tx, params.legacy_jsonrpc) {
+            return (
+                Ok(hyper::Response::builder()
+                    .status(400)
+                    .header("Content-Type", "application/json")
+                    .body(Full::new(Bytes::from(err.to_response(&tx).to_string())))
+                    .unwrap()),
+                None,
+            );
         }
-    };
+        // RPCs might not accept legacy requests, and they share the cache with current ones
+        if legacy {
+            tx["jsonrpc"] = "2.0".into();
+        }
+    }
 
-    // Check if we have the response hashed, and if not forward it
-    // to the best available RPC.
-    //
-    // Also handle cache insertions.
-    let time = Instant::now();
-    (response, rpc_position) = forward_body(
-        tx,
-        &rpc_list_rwlock,
-        finalized_rx,
-        named_numbers,
-        head_cache,
-        cache,
-        params,
-    )
-    .await;
-    let time = time.elapsed();
-    println!("\x1b[35mInfo:\x1b[0m Request time: {:?}", time);
+    // Reject denied methods
+    if let Some(hardened) = &params.hardened {
+        if let Err(err) = check_hardened(&tx, api_key.as_deref(), hardened) {
+            println!(
+                "\x1b[93mWrn:\x1b[0m Hardened mode rejected request: {}",
+                err
+            );
+            return (method_not_allowed!(tx["id"].clone(), err), None);
+        }
+    }
 
-    // `rpc_position` is an Option<> that either contains the index of the RPC
-    // we forwarded our request to, or is None if the result was cached.
+    // Clients are identified by their API key, or IP if they have none
+    let client = api_key
+        .clone()
+        .unwrap_or_else(|| socketaddr.ip().to_string());
+    if let Some(talkers) = &connection_params.talkers {
+        talkers.record_method(&client, tx["method"].as_str());
+    }
+
+    // Look out for clients suddenly changing how they use us, and throttle them if configured
+    if let Some(anomaly) = &connection_params.anomaly {
+        if !anomaly.record(&client, tx["method"].as_str().unwrap_or_default()) {
+            println!("\x1b[93mWrn:\x1b[0m Throttled: {}", client);
+            return (rate_limited!(), None);
+        }
+    }
+
+    // Rewrite the request if the operator set rules for it
+    {
+        let head = *connection_params.blocknum_rx.borrow();
+        let config_guard = connection_params.config.read().unwrap();
+        let applied = rewrite(&config_guard.rewrite, &mut tx, api_key.as_deref(), head);
+        if !applied.is_empty() && log_enabled("balancer", LogLevel::Debug) {
+            log_line(
+                LogLevel::Debug,
+                "balancer",
+                &format!("Rewrote request with {:?}: {}", applied, tx),
+            );
+        }
+    }
+
+    // Subscriptions are handled by blutgang itself and never reach the RPCs
+    if let Some(rx) = execute_subscription_method(&tx, &connection_params.sub_data, &client) {
+        return (
+            Ok(hyper::Response::builder()
+                .status(200)
+                .header("Content-Type", "application/json")
+                .body(Full::new(Bytes::from(rx.to_string())))
+                .unwrap()),
+            None,
+        );
+    }
+
+    // Fee suggestions come from our own oracle, so they're the same whichever RPC is used
+    if let Some(rx) = execute_fee_method(&tx, connection_params.fee_oracle.as_deref()) {
+        return (
+            Ok(hyper::Response::builder()
+                .status(200)
+                .header("Content-Type", "application/json")
+                .body(Full::new(Bytes::from(rx.to_string())))
+                .unwrap()),
+            None,
+        );
+    }
+
+    // Clients can only see how well their own requests cache
+    let cache_usage = connection_params.cache_usage.as_deref();
+    if let Some(rx) = execute_cache_stats_method(&tx, cache_usage, &client) {
+        return (
+            Ok(hyper::Response::builder()
+                .status(200)
+                .header("Content-Type", "application/json")
+                .body(Full::new(Bytes::from(rx.to_string())))
+                .unwrap()),
+            None,
+        );
+    }
+
+    // Quorum reads go to several RPCs at once, whatever the strategy
+    if let Some(call) = parse_quorum_call(&tx) {
+        let rx = match call {
+            Ok(call) => {
+                // The wrapped request has to be allowed on its own
+                let denied = params.hardened.as_ref().and_then(|hardened| {
+                    check_hardened(&call.request, api_key.as_deref(), hardened).err()
+                });
+                match denied {
+                    Some(err) => quorum_error(&tx["id"], &err.to_string(), Value::Null),
+                    None => {
+                        let rpcs = quorum_rpcs(&connection_params.rpc_list_rwlock.read().unwrap());
+                        let ttl = Duration::from_millis(params.ttl.try_into().unwrap());
+                        quorum_call(&tx["id"], call, rpcs, ttl).await
+                    }
+                }
+            }
+            Err(err) => quorum_error(&tx["id"], &err, Value::Null),
+        };
+        return (
+            Ok(hyper::Response::builder()
+                .status(200)
+                .header("Content-Type", "application/json")
+                .body(Full::new(Bytes::from(rx.to_string())))
+                .unwrap()),
+            None,
+        );
+    }
+
+    // Ranges of blocks are fetched block by block, each one like a normal request
+    let range = parse_block_range(&tx, &connection_params.named_numbers.read().unwrap());
+    if let Some(range) = range {
+        let protocol = connection_params.config.read().unwrap().protocol();
+        let rx = match (range, protocol) {
+            (Ok(range), Protocol::Evm) => {
+                get_blocks(&tx["id"], range, connection_params, &params).await
+            }
+            (Ok(_), _) => range_error(&tx["id"], "only available for EVM chains"),
+            (Err(err), _) => range_error(&tx["id"], &err),
+        };
+        return (
+            Ok(hyper::Response::builder()
+                .status(200)
+                .header("Content-Type", "application/json")
+                .body(Full::new(Bytes::from(rx.to_string())))
+                .unwrap()),
+            None,
+        );
+    }
+
+    // Decide which RPCs can serve this request.
     //
-    // Here, we update the latency of the RPC that was used to process the request
-    // if `rpc_position` is Some.
-    if let Some(rpc_position) = rpc_position {
-        let mut rpc_list_guard = rpc_list_rwlock.write().unwrap_or_else(|e| {
-            // Handle the case where the RwLock is poisoned
-            e.into_inner()
-        });
+    // Pinned methods always go to their RPC and namespaces with a pool to its RPCs,
+    // everything else is subject to the state override policy.
+    let (
+        limited,
+        wallet_upstream,
+        state_override_policy,
+        chain,
+        protocol,
+        cache_policy,
+        quorum,
+        broadcast_transactions,
+        tagged,
+    ) = {
+        let config_guard = connection_params.config.read().unwrap();
+        let method = tx["method"].as_str().unwrap_or_default();
+        let head = *connection_params.blocknum_rx.borrow();
+        (
+            match &params.upstream {
+                Some(name) => Some(Route::Pinned(name.clone())),
+                None => config_guard.route_for(method),
+            },
+            config_guard.wallet_upstream.clone(),
+            config_guard.state_override_policy,
+            config_guard.chain,
+            config_guard.protocol(),
+            match params.upstream {
+                // We want to see what the RPC says, not what we cached
+                Some(_) => CachePolicy::Never,
+                // Filter changes depend on when they're polled
+                None if is_filter_method(method) => CachePolicy::Never,
+                None => config_guard.cache_rules.policy(method),
+            },
+            config_guard.quorum.size_for(method),
+            config_guard.broadcast_transactions,
+            config_guard
+                .routing
+                .tags_for(&tx, head, &connection_params.named_numbers),
+        )
+    };
 
-        // Handle weird edge cases ¯\_(ツ)_/¯
-        if rpc_list_guard.is_empty() {
-            println!("LA {}", rpc_list_guard[rpc_position].status.latency);
-        } else {
-            let index = if rpc_position >= rpc_list_guard.len() {
-                rpc_list_guard.len() - 1
-            } else {
-                rpc_position
+    // Reject rollup methods that don't exist on the chain we're in front of
+    let method = tx["method"].as_str().unwrap_or_default();
+    if let (Some(chain), Some(family)) = (chain, l2_family(method)) {
+        if !chain.allows(family) {
+            let reason = format!("{} is not supported on {:?}", method, chain);
+            return (method_not_allowed!(tx["id"].clone(), reason), None);
+        }
+    }
+
+    // Methods that never change get answered from memory, unless a specific RPC was asked
+    if let (Some(responses), None) = (&connection_params.static_responses, &params.upstream) {
+        if let Some(rx) = responses.response(&tx["id"], method) {
+            return (
+                Ok(hyper::Response::builder()
+                    .status(200)
+                    .header("Content-Type", "application/json")
+                    .body(Full::new(Bytes::from(rx.to_string())))
+                    .unwrap()),
+                None,
+            );
+        }
+    }
+
+    // With a local wallet, we fill in, sign and send transactions ourselves
+    if let Some(wallet) = &connection_params.wallet {
+        if WALLET_METHODS.contains(&method) {
+            // Not passed on to `wallet_upstream` either, that would be a way around the keys
+            if !wallet.allows(api_key.as_deref()) {
+                let reason = format!("{} needs an API key allowed to use the wallet", method);
+                return (method_not_allowed!(tx["id"].clone(), reason), None);
+            }
+            let upstream = PoolUpstream {
+                connection_params,
+                params: &params,
             };
-            rpc_list_guard[index].update_latency(time.as_nanos() as f64);
-            println!("LA {}", rpc_list_guard[index].status.latency);
+            let rx = wallet.execute(&tx, &upstream).await;
+            return (
+                Ok(hyper::Response::builder()
+                    .status(200)
+                    .header("Content-Type", "application/json")
+                    .body(Full::new(Bytes::from(rx.to_string())))
+                    .unwrap()),
+                None,
+            );
         }
     }
 
-    response
+    // Lookups for transactions we sent go to RPCs we know have them, so clients can read
+    // their own writes before the transactions get gossiped everywhere
+    let seen = connection_params
+        .tx_tracker
+        .as_ref()
+        .filter(|_| connection_params.config.read().unwrap().read_your_writes)
+        .and_then(|tracker| read_hash(&tx).map(|hash| (tracker, hash)));
+    let known_by = seen
+        .as_ref()
+        .and_then(|(tracker, hash)| tracker.known_by(hash));
+
+    // Filters only exist on the RPC that installed them
+    let filter = filter_id(&tx).map(|id| {
+        let method = tx["method"].as_str().unwrap_or_default();
+        (id, method.to_string())
+    });
+    let filter_owner = filter
+        .as_ref()
+        .and_then(|(id, _)| connection_params.filters.owner(id));
+
+    let method_class = classify(tx["method"].as_str().unwrap_or_default());
+    let route = match limited {
+        Some(route) => route,
+        None if filter_owner.is_some() => Route::Pinned(filter_owner.unwrap()),
+        // Only private deployments with a signer-enabled RPC should forward these
+        None if method_class == MethodClass::Wallet => {
+            match wallet_upstream {
+                Some(name) => Route::Pinned(name),
+                None => {
+                    return (
+                        method_not_allowed!(tx["id"].clone(), wallet_method_reason(method)),
+                        None,
+                    )
+                }
+            }
+        }
+        None if has_overrides(&tx) => {
+            match state_override_policy {
+                StateOverridePolicy::Passthrough => Route::Any,
+                // Only stripped if no RPC would take them as they are
+                StateOverridePolicy::Strip => {
+                    let rpc_list = connection_params.rpc_list_rwlock.read().unwrap();
+                    match any_supports_overrides(&rpc_list) {
+                        true => Route::StateOverrides,
+                        false => {
+                            strip_overrides(&mut tx);
+                            Route::Any
+                        }
+                    }
+                }
+                StateOverridePolicy::Reject => {
+                    return (
+                        method_not_allowed!(
+                            tx["id"].clone(),
+                            "eth_call state and block overrides are not supported"
+                        ),
+                        None,
+                    )
+                }
+                StateOverridePolicy::Route => Route::StateOverrides,
+            }
+        }
+        None if known_by.is_some() => Route::Preferred(known_by.unwrap()),
+        None if tagged.is_some() => Route::Tagged(tagged.unwrap()),
+        None if method_class == MethodClass::Heavy => Route::Archive,
+        None => Route::Any,
+    };
+
+    // Merge `getSignatureStatuses` calls with other in-flight ones, if they could go to any RPC.
+    //
+    // If the batch fails, we fall back to sending the request on its own.
+    if let (Some(batcher), Route::Any) = (&connection_params.signature_batcher, &route) {
+        if let Some((request, reply_rx)) = status_request(&tx) {
+            if batcher.send(request).await.is_ok() {
+                if let Ok(Some(result)) = reply_rx.await {
+                    let rx = serde_json::json!({
+                        "id": tx["id"],
+                        "jsonrpc": "2.0",
+                        "result": result,
+                    });
+                    return (
+                        Ok(hyper::Response::builder()
+                            .status(200)
+                            .header("Content-Type", "application/json")
+                            .body(Full::new(Bytes::from(rx.to_string())))
+                            .unwrap()),
+                        None,
+                    );
+                }
+            }
+        }
+    }
+
+    let heavy_semaphore =
+        (method_class == MethodClass::Heavy).then_some(&connection_params.heavy_semaphore);
+    // Requests limited to some RPCs can't be checked against the others,
+    // and we'd lose track of filters installed on several of them
+    let installs_filter = creates_filter(tx["method"].as_str().unwrap_or_default());
+    let quorum = quorum.filter(|_| route == Route::Any && !installs_filter);
+    let broadcast =
+        broadcast_transactions && route == Route::Any && tx["method"] == "eth_sendRawTransaction";
+    // Pinned requests have nowhere else to go, and some methods aren't safe to send twice
+    let hedge_delay = params
+        .hedge_delay
+        .filter(|_| !matches!(route, Route::Pinned(_)))
+        .filter(|_| can_hedge(tx["method"].as_str().unwrap_or_default()));
+
+    // Overrides are part of the cache key, so equivalent ones should look the same
+    canonicalize_overrides(&mut tx);
+
+    // Notifications are forwarded without waiting for a response,
+    // and the client doesn't get a body back
+    if is_notification(&tx) {
+        forward_notification(
+            tx,
+            &connection_params.rpc_list_rwlock,
+            &route,
+            params.strategy,
+        );
+        return (
+            Ok(hyper::Response::builder()
+                .status(204)
+                .body(Full::new(Bytes::new()))
+                .unwrap()),
+            None,
+        );
+    }
+
+    // Logs are served from cached chunks where possible, if any part of the range can be
+    let logs_query = match (protocol, params.logs_cache_chunk) {
+        (Protocol::Evm, chunk) if chunk > 0 && params.upstream.is_none() => {
+            parse_logs_query(&tx, &connection_params.named_numbers.read().unwrap())
+        }
+        _ => None,
+    };
+    if let Some(query) = logs_query {
+        let finalized = *connection_params.finalized_rx.borrow();
+        let segments = segments(&query, params.logs_cache_chunk, finalized);
+        if segments.iter().any(|segment| segment.key.is_some()) {
+            let rx = get_logs(&tx["id"], &query, segments, connection_params, &params).await;
+            return (
+                Ok(hyper::Response::builder()
+                    .status(200)
+                    .header("Content-Type", "application/json")
+                    .body(Full::new(Bytes::from(rx.to_string())))
+                    .unwrap()),
+                None,
+            );
+        }
+    }
+
+    // Immutable data is served straight from the cache, without looking at the rest of it,
+    // unless a cache rule says how to cache it
+    let immutable = match (protocol, cache_policy) {
+        (Protocol::Evm, CachePolicy::Default | CachePolicy::Forever) => immutable_key(&tx),
+        _ => None,
+    };
+    if let Some(key) = &immutable {
+        let cached = connection_params
+            .codec
+            .get(&connection_params.cache, key)
+            .ok()
+            .flatten();
+        if let Some(cached) = cached {
+            connection_params.codec.stats().record(true);
+            let mut rx = hyper::Response::builder()
+                .status(200)
+                .header("Content-Type", "application/json")
+                .body(Full::new(expand(&cached, &tx["id"])))
+                .unwrap();
+            if params.provenance {
+                rx.extensions_mut().insert(Provenance::Cache);
+            }
+            return (Ok(rx), None);
+        }
+    }
+
+    // Lookups of transactions we recently couldn't find get answered right away,
+    // unless we sent them ourselves
+    let lookup = connection_params
+        .tx_tracker
+        .as_ref()
+        .and_then(|tracker| lookup_hash(&tx).map(|hash| (tracker, hash)));
+    if let Some((tracker, hash)) = &lookup {
+        if tracker.is_missing(hash) {
+            let rx = json!({
+                "id": tx["id"],
+                "jsonrpc": "2.0",
+                "result": null,
+            });
+            return (
+                Ok(hyper::Response::builder()
+                    .status(200)
+                    .header("Content-Type", "application/json")
+                    .body(Full::new(Bytes::from(to_vec(&rx).unwrap())))
+                    .unwrap()),
+                None,
+            );
+        }
+    }
+    let is_send = tx["method"] == "eth_sendRawTransaction";
+    // Replacements the tx manager sends if this one takes too long
+    let replacements = match (&connection_params.tx_manager, is_send) {
+        (Some(_), true) => take_replacements(&mut tx),
+        _ => None,
+    };
+
+    // While every RPC is down, transactions clients let us queue go out once one is back
+    if let (Some(queue), true) = (&connection_params.tx_queue, queue_consent) {
+        let all_down = connection_params
+            .rpc_list_rwlock
+            .read()
+            .unwrap()
+            .iter()
+            .all(|rpc| rpc.paused);
+        if let Some((raw, hash)) = raw_transaction(&tx).filter(|_| all_down) {
+            match queue.push(&raw) {
+                Ok(()) => {
+                    println!(
+                        "\x1b[93mWrn:\x1b[0m No RPC available, queued transaction {}",
+                        hash
+                    );
+                    let rx = queued_response(&tx["id"], &hash);
+                    return (
+                        Ok(hyper::Response::builder()
+                            .status(200)
+                            .header("Content-Type", "application/json")
+                            .header(QUEUED_HEADER, "true")
+                            .body(Full::new(Bytes::from(rx.to_string())))
+                            .unwrap()),
+                        None,
+                    );
+                }
+                Err(err) => {
+                    println!("\x1b[31mErr:\x1b[0m Could not queue transaction: {}", err);
+                }
+            }
+        }
+    }
+    let is_fee_history = tx["method"] == "eth_feeHistory";
+    // Only needed for the per-method latency histograms, the event store and request logs
+    let method_label = match connection_params.metrics.is_some()
+        || connection_params.event_store.is_some()
+        || log_enabled("request", LogLevel::Info)
+    {
+        true => tx["method"].as_str().unwrap_or_default().to_string(),
+        false => String::new(),
+    };
+    let is_priority_fee = tx["method"] == PRIORITY_FEE_METHOD && protocol == Protocol::Evm;
+
+    // Clients `latest` can't go backwards for, and what they asked for
+    let gated = match &connection_params.head_gate {
+        Some(gate) if protocol == Protocol::Evm && is_latest_query(&tx) => {
+            let client = api_key
+                .clone()
+                .unwrap_or_else(|| socketaddr.ip().to_string());
+            let method = tx["method"].as_str().unwrap_or_default().to_string();
+            Some((gate, client, method))
+        }
+        _ => None,
+    };
+
+    // Method to count the cache hit or miss under, the request is gone by then
+    let usage = cache_usage.map(|usage| {
+        let method = tx["method"].as_str().unwrap_or_default().to_string();
+        (usage, method)
+    });
+
+    // Only checked if the response tells us what block it's from
+    let head = *connection_params.blocknum_rx.borrow();
+    let staleness = match (protocol, params.stale_latest_delta, &gated) {
+        // Not even one block behind what the client already got
+        (_, _, Some((gate, client, _))) => {
+            Some(Staleness {
+                head: head.max(gate.floor(client)),
+                max_delta: 0,
+                wait: WAIT,
+            })
+        }
+        (Protocol::Evm, max_delta, None) if max_delta > 0 && is_latest_query(&tx) => {
+            Some(Staleness {
+                head,
+                max_delta,
+                wait: Duration::ZERO,
+            })
+        }
+        _ => None,
+    };
+
+    // Take the id of the request and set it to null for caching
+    //
+    // We're doing this ID gymnastics because we're hashing the
+    // whole request and we don't want the ID as it's arbitrary
+    // and does not impact the request result.
+    let id = tx["id"].take();
+
+    // Hash the request with either blake3 or xxhash depending on the enabled feature
+    let tx_hash = request_hash(&tx);
+
+    // RPC used to get the response, we use it to update the latency for it later.
+    let mut rpc_position;
+    let mut cached = false;
+    let mut retries = 0;
+
+    // Identical requests arriving together share one upstream call
+    let coalescer = connection_params.coalescer.as_ref().filter(|coalescer| {
+        protocol == Protocol::Evm
+            && route == Route::Any
+            && quorum.is_none()
+            && !broadcast
+            && coalescer.coalesces(&tx)
+    });
+    let (lead, shared) = match coalescer {
+        Some(coalescer) => {
+            match coalescer.join(tx_hash.to_string(), head).await {
+                Joined::Lead(lead) => (Some(lead), None),
+                Joined::Shared(rx) => (None, Some(rx)),
+                Joined::Alone => (None, None),
+            }
+        }
+        None => (None, None),
+    };
+    // Shared responses are held to the same standard as the ones we get ourselves
+    let shared = shared.filter(|rx| {
+        staleness.map_or(true, |staleness: Staleness| {
+            staleness.behind(&tx, rx).is_none()
+        })
+    });
+
+    // Get the response from either the DB or from a RPC. If it timeouts, retry.
+    let rax = match shared {
+        // Didn't go upstream, same as if it was cached
+        Some(rx) => {
+            rpc_position = None;
+            cached = true;
+            set_cached_id(&rx, &id)
+        }
+        None => {
+            get_response!(
+                tx,
+                connection_params.cache,
+                tx_hash,
+                rpc_position,
+                cached,
+                retries,
+                id,
+                connection_params.rpc_list_rwlock,
+                connection_params.finalized_rx,
+                &connection_params.named_numbers,
+                connection_params.head_cache,
+                params.ttl,
+                params.max_retries,
+                &route,
+                params.strategy,
+                heavy_semaphore,
+                protocol,
+                &connection_params.upstream_batcher,
+                &connection_params.slo,
+                &connection_params.codec,
+                &connection_params.send_queue,
+                &connection_params.backfill_pacer,
+                priority,
+                staleness,
+                cache_policy,
+                params.unfinalized_ttl,
+                hedge_delay,
+                quorum,
+                broadcast,
+                &connection_params.schema
+            )
+        }
+    };
+    if let Some(lead) = lead {
+        lead.finish(&rax);
+    }
+    if let Some((usage, method)) = &usage {
+        usage.record(&client, method, cached);
+    }
+    if let Some((gate, client, method)) = &gated {
+        if let Some(block) = served_block(method, &rax) {
+            gate.advance(client, block);
+        }
+    }
+
+    // Cache responses that can't change anymore forever
+    if let Some(key) = immutable {
+        if let Ok(rx) = serde_json::from_slice::<Value>(&rax) {
+            if is_immutable(&key, &rx, *connection_params.finalized_rx.borrow()) {
+                let compact = compact(&rx);
+                let _ = connection_params
+                    .codec
+                    .insert(&connection_params.cache, &key, &compact);
+                if let Some((method, num)) = indexed_block(&key, &rx) {
+                    let _ = record_block(&connection_params.cache, &key, method, num, num);
+                }
+            }
+        }
+    }
+
+    // Name of the RPC that answered, only needed if we track transactions or a filter
+    let answered_by = match rpc_position {
+        Some(position) if connection_params.tx_tracker.is_some() || installs_filter => {
+            connection_params
+                .rpc_list_rwlock
+                .read()
+                .unwrap()
+                .get(position)
+                .map(|rpc| rpc.name.clone())
+        }
+        _ => None,
+    };
+    if let (Some((tracker, hash)), Some(rpc), false) = (&seen, &answered_by, cached) {
+        tracker.record_seen(hash, rpc, &rax);
+    }
+    match (&connection_params.tx_tracker, lookup) {
+        (_, Some((tracker, hash))) => tracker.record_lookup(hash, &rax),
+        (Some(tracker), None) if is_send => tracker.record_sent(&rax, answered_by.as_deref()),
+        _ => {}
+    }
+    if let (Some(manager), Some(replacements)) = (&connection_params.tx_manager, replacements) {
+        manager.track_sent(&rax, replacements);
+    }
+    if let (true, Some(rpc)) = (installs_filter, &answered_by) {
+        connection_params.filters.record_created(&rax, rpc);
+    }
+    if let Some((id, method)) = &filter {
+        connection_params.filters.record_used(id, method, &rax);
+    }
+
+    if let (Some(tracker), Some(position), true) =
+        (&connection_params.inclusion, rpc_position, is_send)
+    {
+        let stats = connection_params
+            .rpc_list_rwlock
+            .read()
+            .unwrap()
+            .get(position)
+            .map(|rpc| Arc::clone(rpc.inclusion_stats()));
+        if let Some(stats) = stats {
+            tracker.record_broadcast(&rax, &stats);
+        }
+    }
+
+    let rax = match &connection_params.fee_oracle {
+        Some(oracle) if is_fee_history => enrich_fee_history(rax, oracle),
+        _ => rax,
+    };
+    // Work out a priority fee ourselves for RPCs that don't implement it
+    let rax = match is_priority_fee && is_method_not_found(&rax) {
+        true => {
+            let tip = fallback_priority_fee(
+                connection_params.fee_oracle.as_deref(),
+                &connection_params.rpc_list_rwlock,
+                Duration::from_millis(params.ttl.try_into().unwrap()),
+            )
+            .await;
+            match tip {
+                Some(tip) => priority_fee_response(&id, tip),
+                None => rax,
+            }
+        }
+        false => rax,
+    };
+    let rax = match params.strict_jsonrpc {
+        true => strict_response(&rax, &id, legacy),
+        false => rax,
+    };
+
+    let logged = log_sampled("request", LogLevel::Info);
+    let is_error = (connection_params.event_store.is_some() || logged)
+        && memchr::memmem::find(&rax, b"\"error\"").is_some();
+
+    // Put it in a http_body_util::Full
+    let body = Full::new(rax);
+
+    // Build the response
+    let mut res = hyper::Response::builder()
+        .status(200)
+        .header("Content-Type", "application/json")
+        .body(body)
+        .unwrap();
+
+    let wants_upstream = params.provenance
+        || connection_params.metrics.is_some()
+        || connection_params.event_store.is_some()
+        || logged;
+    let upstream = match (rpc_position, wants_upstream) {
+        (Some(position), true) => {
+            connection_params
+                .rpc_list_rwlock
+                .read()
+                .unwrap()
+                .get(position)
+                .map(|rpc| rpc.name.clone())
+        }
+        _ => None,
+    };
+    if let Some(metrics) = &connection_params.metrics {
+        metrics.record_method(
+            &method_label,
+            upstream.as_deref().unwrap_or(NO_UPSTREAM),
+            started.elapsed(),
+        );
+    }
+    if let Some(event_store) = &connection_params.event_store {
+        let upstream = match cached {
+            true => "cache",
+            false => upstream.as_deref().unwrap_or(NO_UPSTREAM),
+        };
+        event_store.record_request(&method_label, upstream, started.elapsed(), is_error);
+    }
+    if logged {
+        log_request(&RequestLog {
+            id: &params.request_id,
+            method: &method_label,
+            upstream: upstream.as_deref().filter(|_| !cached),
+            cached,
+            retries,
+            latency: started.elapsed(),
+            error: is_error,
+        });
+    }
+    if params.provenance {
+        let provenance = match (rpc_position, upstream) {
+            _ if cached => Some(Provenance::Cache),
+            (Some(_), upstream) => upstream.map(Provenance::Upstream),
+            (None, _) => Some(Provenance::Fanout),
+        };
+        if let Some(provenance) = provenance {
+            res.extensions_mut().insert(provenance);
+        }
+    }
+
+    (Ok(res), rpc_position)
+}
+
+// Serve every request of `batch` concurrently, and answer with their responses in the
+// same order. Notifications don't get one, so a batch of only notifications gets no body.
+async fn forward_batch(
+    batch: Vec<Value>,
+    connection_params: &ConnectionParams,
+    socketaddr: SocketAddr,
+    params: RequestParams,
+    api_key: Option<String>,
+    priority: Priority,
+    queue_consent: bool,
+) -> Result<hyper::Response<Full<Bytes>>, Infallible> {
+    let invalid = match batch.len() {
+        0 => Some("error: Empty batch".to_string()),
+        len if params.max_batch_size > 0 && len > params.max_batch_size => {
+            Some(format!(
+                "error: Batch too large, at most {} requests are allowed",
+                params.max_batch_size
+            ))
+        }
+        _ => None,
+    };
+    if let Some(message) = invalid {
+        let rx = json!({
+            "jsonrpc": "2.0",
+            "id": null,
+            "error": {
+                "code": -32600,
+                "message": message,
+            },
+        });
+        return Ok(hyper::Response::builder()
+            .status(400)
+            .header("Content-Type", "application/json")
+            .body(Full::new(Bytes::from(rx.to_string())))
+            .unwrap());
+    }
+
+    let notifications: Vec<bool> = batch.iter().map(is_notification).collect();
+    let slots = Arc::new(Semaphore::new(BATCH_CONCURRENCY));
+    let mut tasks = JoinSet::new();
+    for (index, tx) in batch.into_iter().enumerate() {
+        let connection_params = connection_params.clone();
+        let params = params.clone();
+        let api_key = api_key.clone();
+        let provenance = params.provenance;
+        let slot = Arc::clone(&slots).acquire_owned().await.unwrap();
+
+        tasks.spawn(async move {
+            let _slot = slot;
+            let time = Instant::now();
+            let (rx, rpc_position) = forward_value(
+                tx,
+                &connection_params,
+                socketaddr,
+                params,
+                api_key,
+                priority,
+                queue_consent,
+            )
+            .await;
+            record_latency(&connection_params, rpc_position, time.elapsed(), None);
+
+            let rx = match rx {
+                Ok(rx) if provenance => {
+                    let rx = annotate_response(rx).await;
+                    rx.into_body().collect().await.ok().map(|rx| rx.to_bytes())
+                }
+                Ok(rx) => rx.into_body().collect().await.ok().map(|rx| rx.to_bytes()),
+                Err(_) => None,
+            };
+            (index, rx)
+        });
+    }
+
+    let mut responses = vec![None; notifications.len()];
+    while let Some(joined) = tasks.join_next().await {
+        if let Ok((index, rx)) = joined {
+            responses[index] = rx;
+        }
+    }
+
+    // Requests we couldn't get a response for, e.g. because they panicked, get an internal error
+    let responses: Vec<Value> = responses
+        .into_iter()
+        .zip(notifications)
+        .filter(|(_, notification)| !notification)
+        .map(|(rx, _)| {
+            rx.and_then(|rx| serde_json::from_slice(&rx).ok())
+                .unwrap_or_else(|| serde_json::from_str(&internal_error()).unwrap())
+        })
+        .collect();
+
+    if responses.is_empty() {
+        return Ok(hyper::Response::builder()
+            .status(204)
+            .body(Full::new(Bytes::new()))
+            .unwrap());
+    }
+
+    Ok(hyper::Response::builder()
+        .status(200)
+        .header("Content-Type", "application/json")
+        .body(Full::new(Bytes::from(to_vec(&responses).unwrap())))
+        .unwrap())
+}
+
+// Get a single response from either the cache or the RPCs, for requests we make ourselves
+async fn fetch(
+    mut tx: Value,
+    connection_params: ConnectionParams,
+    ttl: u128,
+    max_retries: u32,
+    strategy: Strategy,
+    priority: Priority,
+) -> (
+    Result<hyper::Response<Full<Bytes>>, Infallible>,
+    Option<usize>,
+) {
+    let (route, protocol, cache_policy, unfinalized_ttl) = {
+        let config_guard = connection_params.config.read().unwrap();
+        let method = tx["method"].as_str().unwrap_or_default();
+        let head = *connection_params.blocknum_rx.borrow();
+        let tagged = config_guard
+            .routing
+            .tags_for(&tx, head, &connection_params.named_numbers)
+            .map(Route::Tagged);
+        (
+            config_guard
+                .route_for(method)
+                .or(tagged)
+                .unwrap_or(Route::Any),
+            config_guard.protocol(),
+            config_guard.cache_rules.policy(method),
+            unfinalized_ttl(&config_guard),
+        )
+    };
+
+    let id = tx["id"].take();
+    let tx_hash = request_hash(&tx);
+    let mut rpc_position;
+    let mut _cached = false;
+    let mut _retries = 0;
+
+    let rax = get_response!(
+        tx,
+        connection_params.cache,
+        tx_hash,
+        rpc_position,
+        _cached,
+        _retries,
+        id,
+        connection_params.rpc_list_rwlock,
+        connection_params.finalized_rx,
+        &connection_params.named_numbers,
+        connection_params.head_cache,
+        ttl,
+        max_retries,
+        &route,
+        strategy,
+        None::<&Semaphore>,
+        protocol,
+        &connection_params.upstream_batcher,
+        &connection_params.slo,
+        &connection_params.codec,
+        &connection_params.send_queue,
+        &connection_params.backfill_pacer,
+        priority,
+        None::<Staleness>,
+        cache_policy,
+        unfinalized_ttl,
+        None::<HedgeDelay>,
+        None::<usize>,
+        false,
+        &connection_params.schema
+    );
+
+    (
+        Ok(hyper::Response::builder()
+            .status(200)
+            .header("Content-Type", "application/json")
+            .body(Full::new(rax))
+            .unwrap()),
+        rpc_position,
+    )
+}
+
+// Lets the wallet fill in transactions through the RPCs we balance between
+struct PoolUpstream<'a> {
+    connection_params: &'a ConnectionParams,
+    params: &'a RequestParams,
+}
+
+impl Upstream for PoolUpstream<'_> {
+    async fn call(&self, method: &str, params: Value) -> Result<Value, String> {
+        let tx = json!({"id": 1, "jsonrpc": "2.0", "method": method, "params": params});
+        let rx = match fetch(
+            tx,
+            self.connection_params.clone(),
+            self.params.ttl,
+            self.params.max_retries,
+            self.params.strategy,
+            Priority::Interactive,
+        )
+        .await
+        {
+            (Ok(rx), _) => {
+                rx.into_body()
+                    .collect()
+                    .await
+                    .map_err(|err| err.to_string())?
+            }
+            (Err(err), _) => return Err(err.to_string()),
+        };
+        let mut rx: Value =
+            serde_json::from_slice(&rx.to_bytes()).map_err(|err| err.to_string())?;
+
+        match rx["error"].is_null() {
+            true => Ok(rx["result"].take()),
+            false => {
+                Err(rx["error"]["message"]
+                    .as_str()
+                    .unwrap_or("unknown error")
+                    .to_string())
+            }
+        }
+    }
+}
+
+// Get a page of `range`, fetching up to `CONCURRENCY` blocks at once
+async fn get_blocks(
+    id: &Value,
+    range: BlockRange,
+    connection_params: &ConnectionParams,
+    params: &RequestParams,
+) -> Value {
+    let (numbers, next) = range.page();
+    let semaphore = Arc::new(Semaphore::new(CONCURRENCY));
+
+    let mut tasks = JoinSet::new();
+    for (index, number) in numbers.clone().enumerate() {
+        let semaphore = Arc::clone(&semaphore);
+        let connection_params = connection_params.clone();
+        let (ttl, max_retries, strategy) = (params.ttl, params.max_retries, params.strategy);
+
+        tasks.spawn(async move {
+            let _permit = semaphore.acquire().await.unwrap();
+            let tx = block_request(number, range.full);
+            let rx = match fetch(
+                tx,
+                connection_params,
+                ttl,
+                max_retries,
+                strategy,
+                Priority::Backfill,
+            )
+            .await
+            {
+                (Ok(rx), _) if rx.status() == StatusCode::OK => {
+                    rx.into_body().collect().await.ok().map(|rx| rx.to_bytes())
+                }
+                _ => None,
+            };
+
+            (
+                index,
+                number,
+                rx.and_then(|rx| serde_json::from_slice::<Value>(&rx).ok()),
+            )
+        });
+    }
+
+    // Blocks that don't exist yet are null, just like `eth_getBlockByNumber` would return
+    let mut blocks = vec![Value::Null; numbers.count()];
+    while let Some(joined) = tasks.join_next().await {
+        match joined {
+            Ok((index, _, Some(mut rx))) if rx["error"].is_null() => {
+                blocks[index] = rx["result"].take();
+            }
+            Ok((_, number, _)) => {
+                return range_error(id, &format!("could not get block {:#x}", number));
+            }
+            Err(_) => return range_error(id, "could not get blocks"),
+        }
+    }
+
+    range_response(id, blocks, next)
+}
+
+// Serve `query` from the cached chunks in `segments`, fetching the gaps between them
+async fn get_logs(
+    id: &Value,
+    query: &LogsQuery,
+    segments: Vec<Segment>,
+    connection_params: &ConnectionParams,
+    params: &RequestParams,
+) -> Value {
+    let mut parts: Vec<Option<Vec<Value>>> = segments
+        .iter()
+        .map(|segment| {
+            let key = segment.key.as_ref()?;
+            let cached = connection_params
+                .codec
+                .get(&connection_params.cache, key)
+                .ok()??;
+            serde_json::from_slice(&cached).ok()
+        })
+        .collect();
+
+    let semaphore = Arc::new(Semaphore::new(LOGS_CONCURRENCY));
+    let mut tasks = JoinSet::new();
+    for gap in gaps(&parts) {
+        let semaphore = Arc::clone(&semaphore);
+        let connection_params = connection_params.clone();
+        let (ttl, max_retries, strategy) = (params.ttl, params.max_retries, params.strategy);
+        let tx = logs_request(
+            &query.filter,
+            segments[gap.start].from,
+            segments[gap.end - 1].to,
+        );
+
+        tasks.spawn(async move {
+            let _permit = semaphore.acquire().await.unwrap();
+            let rx = match fetch(
+                tx,
+                connection_params,
+                ttl,
+                max_retries,
+                strategy,
+                Priority::Interactive,
+            )
+            .await
+            {
+                (Ok(rx), _) => rx.into_body().collect().await.ok().map(|rx| rx.to_bytes()),
+                _ => None,
+            };
+
+            (
+                gap,
+                rx.and_then(|rx| serde_json::from_slice::<Value>(&rx).ok()),
+            )
+        });
+    }
+
+    while let Some(joined) = tasks.join_next().await {
+        let (gap, mut rx) = match joined {
+            Ok((gap, Some(rx))) => (gap, rx),
+            _ => return logs_error(id, "could not get logs"),
+        };
+        // Errors are passed on as they are, e.g. when the range has too many logs
+        if !rx["error"].is_null() {
+            rx["id"] = id.clone();
+            return rx;
+        }
+        let logs = match rx["result"].take() {
+            Value::Array(logs) => logs,
+            _ => return logs_error(id, "invalid eth_getLogs response"),
+        };
+
+        match split_logs(logs, &segments[gap.clone()]) {
+            Ok(split) => {
+                for (index, logs) in gap.zip(split) {
+                    if let Some(key) = &segments[index].key {
+                        let _ = connection_params.codec.insert(
+                            &connection_params.cache,
+                            key,
+                            &to_vec(&logs).unwrap(),
+                        );
+                        let segment = &segments[index];
+                        let _ = record_block(
+                            &connection_params.cache,
+                            key,
+                            "eth_getLogs",
+                            segment.from,
+                            segment.to,
+                        );
+                    }
+                    parts[index] = Some(logs);
+                }
+            }
+            // Serve them, but don't cache anything we can't place
+            Err(logs) => {
+                parts[gap.start] = Some(logs);
+                for index in gap.skip(1) {
+                    parts[index] = Some(Vec::new());
+                }
+            }
+        }
+    }
+
+    logs_response(id, parts.into_iter().flatten().flatten().collect())
+}
+
+// Send a notification to an RPC in the background, ignoring the response
+fn forward_notification(
+    tx: Value,
+    rpc_list_rwlock: &Arc<RwLock<Vec<Rpc>>>,
+    route: &Route,
+    strategy: Strategy,
+) {
+    let strategy = strategy.for_request(request_key(&request_hash(&tx)), 0);
+    let (rpc, rpc_position) = pick_route(&mut rpc_list_rwlock.write().unwrap(), route, strategy);
+    if rpc_position.is_none() {
+        println!("\x1b[93mWrn:\x1b[0m No RPC available, dropping notification");
+        return;
+    }
+
+    tokio::task::spawn(async move {
+        if let Err(err) = rpc.send_request(tx).await {
+            println!("\x1b[93mWrn:\x1b[0m Error forwarding notification: {}", err);
+        }
+    });
+}
+
+// Record how long serving a request took, for the RPC that served it
+fn record_latency(
+    connection_params: &ConnectionParams,
+    rpc_position: Option<usize>,
+    time: Duration,
+    trace_id: Option<&str>,
+) {
+    // `rpc_position` is an Option<> that either contains the index of the RPC
+    // we forwarded our request to, or is None if the result was cached.
+    //
+    // Here, we update the latency of the RPC that was used to process the request
+    // if `rpc_position` is Some.
+    if let Some(rpc_position) = rpc_position {
+        let mut rpc_list_guard = connection_params
+            .rpc_list_rwlock
+            .write()
+            .unwrap_or_else(|e| {
+                // Handle the case where the RwLock is poisoned
+                e.into_inner()
+            });
+
+        // Handle weird edge cases ¯\_(ツ)_/¯
+        if rpc_list_guard.is_empty() {
+            if log_enabled("balancer", LogLevel::Debug) {
+                println!("LA {}", rpc_list_guard[rpc_position].status.latency);
+            }
+        } else {
+            let index = if rpc_position >= rpc_list_guard.len() {
+                rpc_list_guard.len() - 1
+            } else {
+                rpc_position
+            };
+            rpc_list_guard[index].update_latency(time.as_nanos() as f64);
+            if let Some(metrics) = &connection_params.metrics {
+                metrics.record(&rpc_list_guard[index].name, time, trace_id);
+            }
+            if rpc_list_guard[index].sla.is_some() {
+                rpc_list_guard[index].sla_stats().record(time);
+            }
+            if log_enabled("balancer", LogLevel::Debug) {
+                println!("LA {}", rpc_list_guard[index].status.latency);
+            }
+        }
+    }
+
+    if let (Some(metrics), None) = (&connection_params.metrics, rpc_position) {
+        metrics.record(NO_UPSTREAM, time, trace_id);
+    }
+}
+
+// `accept_request`, except a panic only fails the request that caused it
+pub async fn serve_request(
+    tx: Request<hyper::body::Incoming>,
+    connection_params: &ConnectionParams,
+    socketaddr: SocketAddr,
+) -> Result<hyper::Response<BoxBody<Bytes, Infallible>>, Infallible> {
+    match catch_panic(accept_request(tx, connection_params, socketaddr)).await {
+        Ok(response) => response,
+        Err(reason) => {
+            println!(
+                "\x1b[31mErr:\x1b[0m Panicked while serving a request: {}",
+                reason
+            );
+            Ok(hyper::Response::builder()
+                .status(500)
+                .header("Content-Type", "application/json")
+                .body(Full::new(Bytes::from(internal_error())).boxed())
+                .unwrap())
+        }
+    }
+}
+
+// Forward the request to *a* RPC picked by the algo set by the user.
+// Measures the time needed for a request, and updates the respective
+// RPC lself.
+// In case of a timeout, returns an error.
+pub async fn accept_request(
+    tx: Request<hyper::body::Incoming>,
+    connection_params: &ConnectionParams,
+    socketaddr: SocketAddr,
+) -> Result<hyper::Response<BoxBody<Bytes, Infallible>>, Infallible> {
+    // Event streams stay open, so they're served outside of the usual request flow
+    if tx.method() == Method::GET && tx.uri().path() == EVENTS_PATH {
+        return Ok(accept_events(&tx, connection_params, socketaddr));
+    }
+
+    if let Some(watchdog) = &connection_params.watchdog {
+        if tx.method() == Method::GET && tx.uri().path() == READY_PATH {
+            let (ready, body) = watchdog.readiness();
+            return Ok(hyper::Response::builder()
+                .status(match ready {
+                    true => StatusCode::OK,
+                    false => StatusCode::SERVICE_UNAVAILABLE,
+                })
+                .body(Full::new(Bytes::from(body)).boxed())
+                .unwrap());
+        }
+    }
+
+    // Scrapes aren't requests we serve, so they aren't measured either
+    if let Some(metrics) = &connection_params.metrics {
+        if tx.method() == Method::GET && tx.uri().path() == METRICS_PATH {
+            return Ok(hyper::Response::builder()
+                .header("Content-Type", METRICS_CONTENT_TYPE)
+                .body(
+                    Full::new(Bytes::from(
+                        metrics.to_openmetrics(connection_params.talkers.as_deref()),
+                    ))
+                    .boxed(),
+                )
+                .unwrap());
+        }
+    }
+
+    // Send request and measure time
+    let response: Result<hyper::Response<Full<Bytes>>, Infallible>;
+    let rpc_position: Option<usize>;
+
+    // RequestParams from config
+    let params = RequestParams::new(
+        &connection_params.config.read().unwrap(),
+        request_id(tx.headers()),
+    );
+    let request_id = params.request_id.clone();
+
+    // Has to be read before the request is consumed
+    let trace_id = connection_params
+        .metrics
+        .as_ref()
+        .and_then(|_| sampled_trace_id(tx.headers()));
+    let talker = connection_params.talkers.as_ref().map(|_| {
+        let client = get_api_key(tx.headers()).unwrap_or_else(|| socketaddr.ip().to_string());
+        (client, tx.body().size_hint().lower())
+    });
+
+    // Check if we have the response hashed, and if not forward it
+    // to the best available RPC.
+    //
+    // Also handle cache insertions.
+    let time = Instant::now();
+    (response, rpc_position) = forward_body(tx, connection_params, socketaddr, params).await;
+    let time = time.elapsed();
+    if log_sampled("balancer", LogLevel::Info) {
+        log_line(
+            LogLevel::Info,
+            "balancer",
+            &format!("Request time: {:?}", time),
+        );
+    }
+
+    if let (Some(talkers), Some((client, bytes_in)), Ok(response)) =
+        (&connection_params.talkers, talker, &response)
+    {
+        let bytes_out = response.body().size_hint().lower();
+        talkers.record(
+            &client,
+            bytes_in,
+            bytes_out,
+            response.status().is_client_error() || response.status().is_server_error(),
+        );
+    }
+
+    // Requests we couldn't serve count against the error budget
+    if let (Some(slo), Ok(response)) = (&connection_params.slo, &response) {
+        let status = response.status();
+        slo.record_endpoint(
+            DEFAULT_ENDPOINT,
+            !status.is_server_error() && status != StatusCode::REQUEST_TIMEOUT,
+        );
+    }
+
+    record_latency(connection_params, rpc_position, time, trace_id.as_deref());
+
+    response.map(|mut response| {
+        if let Ok(request_id) = HeaderValue::from_str(&request_id) {
+            response.headers_mut().insert(REQUEST_ID_HEADER, request_id);
+        }
+        response.map(BodyExt::boxed)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::{
+        server::conn::http1,
+        service::service_fn,
+    };
+    use hyper_util_blutgang::rt::TokioIo;
+    use tokio::net::TcpListener;
+
+    // RPC that answers every request with `<name>:<method>`
+    async fn upstream(name: &str) -> Rpc {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let name = name.to_string();
+        let rpc_name = name.clone();
+        tokio::task::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                let name = name.clone();
+                tokio::task::spawn(async move {
+                    let service = service_fn(|req: Request<hyper::body::Incoming>| {
+                        let name = name.clone();
+                        async move {
+                            let body = req.into_body().collect().await.unwrap().to_bytes();
+                            let tx: Value = serde_json::from_slice(&body).unwrap();
+                            let rx = json!({
+                                "jsonrpc": "2.0",
+                                "id": tx["id"],
+                                "result": format!("{}:{}", name, tx["method"].as_str().unwrap()),
+                            });
+                            Ok::<_, Infallible>(hyper::Response::new(Full::new(Bytes::from(
+                                rx.to_string(),
+                            ))))
+                        }
+                    });
+                    let _ = http1::Builder::new()
+                        .serve_connection(TokioIo::new(stream), service)
+                        .await;
+                });
+            }
+        });
+
+        let mut rpc = Rpc::new(format!("http://{}", address), 10, 10.0);
+        rpc.name = rpc_name;
+        rpc
+    }
+
+    fn params_for(rpcs: Vec<Rpc>, config: Settings, finalized: u64) -> ConnectionParams {
+        let (_, finalized_rx) = watch::channel(finalized);
+        let (_, blocknum_rx) = watch::channel(finalized + 100);
+        ConnectionParams {
+            rpc_list_rwlock: Arc::new(RwLock::new(rpcs)),
+            finalized_rx: Arc::new(finalized_rx),
+            blocknum_rx,
+            named_numbers: Arc::new(RwLock::new(NamedBlocknumbers::default())),
+            head_cache: Arc::new(HeadCache::default()),
+            sub_data: Arc::new(SubscriptionData::new()),
+            events: Arc::new(EventHub::default()),
+            cache: Arc::new(sled::Config::new().temporary(true).open().unwrap()),
+            heavy_semaphore: Arc::new(Semaphore::new(config.heavy_concurrency)),
+            config: Arc::new(RwLock::new(config)),
+            rate_limiter: None,
+            signature_batcher: None,
+            upstream_batcher: None,
+            slo: None,
+            tx_tracker: None,
+            filters: Arc::new(FilterTracker::default()),
+            static_responses: None,
+            coalescer: None,
+            head_gate: None,
+            schema: None,
+            tx_queue: None,
+            codec: Arc::new(CacheCodec::new(0, false)),
+            send_queue: None,
+            backfill_pacer: None,
+            anomaly: None,
+            wallet: None,
+            tx_manager: None,
+            fee_oracle: None,
+            inclusion: None,
+            metrics: None,
+            talkers: None,
+            cache_usage: None,
+            watchdog: None,
+            event_store: None,
+        }
+    }
+
+    fn client() -> SocketAddr {
+        "127.0.0.1:1234".parse().unwrap()
+    }
+
+    // Serve `tx` as if a client without an API key sent it
+    async fn serve(connection_params: &ConnectionParams, tx: Value) -> (Value, Option<usize>) {
+        serve_as(connection_params, tx, None).await
+    }
+
+    async fn serve_as(
+        connection_params: &ConnectionParams,
+        tx: Value,
+        api_key: Option<&str>,
+    ) -> (Value, Option<usize>) {
+        let params = RequestParams::new(
+            &connection_params.config.read().unwrap(),
+            "test".to_string(),
+        );
+        let (rx, rpc_position) = forward_value(
+            tx,
+            connection_params,
+            client(),
+            params,
+            api_key.map(str::to_string),
+            Priority::default(),
+            false,
+        )
+        .await;
+        let body = rx.unwrap().into_body().collect().await.unwrap().to_bytes();
+        (serde_json::from_slice(&body).unwrap(), rpc_position)
+    }
+
+    async fn serve_batch(
+        connection_params: &ConnectionParams,
+        batch: Vec<Value>,
+    ) -> (StatusCode, Option<Value>) {
+        let params = RequestParams::new(
+            &connection_params.config.read().unwrap(),
+            "test".to_string(),
+        );
+        let rx = forward_batch(
+            batch,
+            connection_params,
+            client(),
+            params,
+            None,
+            Priority::default(),
+            false,
+        )
+        .await
+        .unwrap();
+        let status = rx.status();
+        let body = rx.into_body().collect().await.unwrap().to_bytes();
+        (status, serde_json::from_slice(&body).ok())
+    }
+
+    fn get_balance(block: u64) -> Value {
+        json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": "eth_getBalance",
+            "params": ["0x0000000000000000000000000000000000000000", format!("0x{:x}", block)],
+        })
+    }
+
+    #[tokio::test]
+    async fn test_finalized_responses_never_expire() {
+        let config = Settings {
+            unfinalized_ttl: 1,
+            ..Settings::default()
+        };
+        let connection_params = params_for(vec![upstream("reth").await], config, 100);
+
+        let (rx, rpc_position) = serve(&connection_params, get_balance(50)).await;
+        assert_eq!(rx["result"], "reth:eth_getBalance");
+        assert_eq!(rx["id"], 1);
+        assert!(rpc_position.is_some());
+
+        sleep(Duration::from_millis(10)).await;
+        let (rx, rpc_position) = serve(&connection_params, get_balance(50)).await;
+        assert_eq!(rx["result"], "reth:eth_getBalance");
+        assert_eq!(rpc_position, None);
+    }
+
+    #[tokio::test]
+    async fn test_immutable_cache_rules() {
+        use crate::balancer::selection::cache_rules::parse_cache_rules;
+
+        let tx = json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": "eth_getBlockByHash",
+            "params": [format!("0x{}", "ab".repeat(32)), false],
+        });
+        let block = json!({"result": {"number": "0x1"}});
+        let serve_cached = |config: Settings| {
+            let tx = tx.clone();
+            let block = block.clone();
+            async move {
+                let connection_params = params_for(vec![upstream("reth").await], config, 100);
+                let key = immutable_key(&tx).unwrap();
+                connection_params
+                    .codec
+                    .insert(&connection_params.cache, &key, &compact(&block))
+                    .unwrap();
+                serve(&connection_params, tx).await
+            }
+        };
+
+        let (rx, rpc_position) = serve_cached(Settings::default()).await;
+        assert_eq!(rx["result"], block["result"]);
+        assert_eq!(rpc_position, None);
+
+        // Cache rules apply to immutable methods too
+        let rules: toml::Table = "eth_getBlockByHash = \"never\"".parse().unwrap();
+        let config = Settings {
+            cache_rules: parse_cache_rules(&rules),
+            ..Settings::default()
+        };
+        let (rx, rpc_position) = serve_cached(config).await;
+        assert_eq!(rx["result"], "reth:eth_getBlockByHash");
+        assert!(rpc_position.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_signature_batching_routes() {
+        let tx = json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": "getSignatureStatuses",
+            "params": [["sig"]],
+        });
+        let batched = |config: Settings| {
+            async move {
+                let mut connection_params = params_for(vec![upstream("reth").await], config, 100);
+                let (batcher, mut requests) = mpsc::channel::<StatusRequest>(8);
+                tokio::task::spawn(async move {
+                    while let Some(request) = requests.recv().await {
+                        let _ = request.reply.send(Some(json!("batched")));
+                    }
+                });
+                connection_params.signature_batcher = Some(batcher);
+                connection_params
+            }
+        };
+
+        let connection_params = batched(Settings::default()).await;
+        let (rx, _) = serve(&connection_params, tx.clone()).await;
+        assert_eq!(rx["result"], "batched");
+
+        // Pinned requests go to their RPC, not whichever the batch goes to
+        let config = Settings {
+            pin: std::collections::HashMap::from([(
+                "getSignatureStatuses".to_string(),
+                "reth".to_string(),
+            )]),
+            ..Settings::default()
+        };
+        let connection_params = batched(config).await;
+        let (rx, rpc_position) = serve(&connection_params, tx).await;
+        assert_eq!(rx["result"], "reth:getSignatureStatuses");
+        assert!(rpc_position.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_unfinalized_responses_expire() {
+        let config = Settings {
+            unfinalized_ttl: 1,
+            ..Settings::default()
+        };
+        let connection_params = params_for(vec![upstream("reth").await], config, 100);
+
+        let (_, rpc_position) = serve(&connection_params, get_balance(150)).await;
+        assert!(rpc_position.is_some());
+
+        // Fetched again once it expired
+        sleep(Duration::from_millis(10)).await;
+        let (rx, rpc_position) = serve(&connection_params, get_balance(150)).await;
+        assert_eq!(rx["result"], "reth:eth_getBalance");
+        assert!(rpc_position.is_some());
+
+        // Kept as long as we want if the expiry is off
+        let config = Settings {
+            unfinalized_ttl: 0,
+            ..Settings::default()
+        };
+        let connection_params = params_for(vec![upstream("reth").await], config, 100);
+        serve(&connection_params, get_balance(150)).await;
+        sleep(Duration::from_millis(10)).await;
+        assert_eq!(serve(&connection_params, get_balance(150)).await.1, None);
+    }
+
+    #[tokio::test]
+    async fn test_batch() {
+        let connection_params = params_for(vec![upstream("reth").await], Settings::default(), 100);
+
+        // Every request is served on its own, and answered in the order it came in
+        let batch: Vec<Value> = (0..BATCH_CONCURRENCY * 2)
+            .map(|id| {
+                let method = match id % 2 {
+                    0 => "eth_blockNumber",
+                    _ => "eth_gasPrice",
+                };
+                json!({"jsonrpc": "2.0", "id": id, "method": method, "params": []})
+            })
+            .collect();
+        let (status, rx) = serve_batch(&connection_params, batch).await;
+        assert_eq!(status, StatusCode::OK);
+        let rx = rx.unwrap();
+        let rx = rx.as_array().unwrap();
+        assert_eq!(rx.len(), BATCH_CONCURRENCY * 2);
+        for (id, rx) in rx.iter().enumerate() {
+            assert_eq!(rx["id"], id);
+            let method = match id % 2 {
+                0 => "eth_blockNumber",
+                _ => "eth_gasPrice",
+            };
+            assert_eq!(rx["result"], format!("reth:{}", method));
+        }
+
+        // Notifications don't get a response
+        let batch = vec![
+            json!({"jsonrpc": "2.0", "method": "eth_blockNumber", "params": []}),
+            json!({"jsonrpc": "2.0", "id": 7, "method": "eth_gasPrice", "params": []}),
+        ];
+        let (_, rx) = serve_batch(&connection_params, batch).await;
+        assert_eq!(
+            rx.unwrap(),
+            json!([{"jsonrpc": "2.0", "id": 7, "result": "reth:eth_gasPrice"}])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_notification_batch() {
+        let connection_params = params_for(vec![upstream("reth").await], Settings::default(), 100);
+
+        let batch = vec![
+            json!({"jsonrpc": "2.0", "method": "eth_blockNumber", "params": []}),
+            json!({"jsonrpc": "2.0", "method": "eth_gasPrice", "params": []}),
+        ];
+        let (status, rx) = serve_batch(&connection_params, batch).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        assert_eq!(rx, None);
+    }
+
+    #[tokio::test]
+    async fn test_max_batch_size() {
+        let config = Settings {
+            max_batch_size: 2,
+            ..Settings::default()
+        };
+        let connection_params = params_for(vec![upstream("reth").await], config, 100);
+
+        let tx = json!({"jsonrpc": "2.0", "id": 1, "method": "eth_blockNumber", "params": []});
+        let (status, rx) = serve_batch(&connection_params, vec![tx.clone(); 3]).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(rx.unwrap()["error"]["code"], -32600);
+
+        let (status, rx) = serve_batch(&connection_params, vec![tx.clone(); 2]).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(rx.unwrap().as_array().unwrap().len(), 2);
+
+        let (status, _) = serve_batch(&connection_params, Vec::new()).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_batch_rate_limit() {
+        let mut connection_params = params_for(Vec::new(), Settings::default(), 100);
+        connection_params.rate_limiter = Some(Arc::new(RateLimiter::new(0.0, 10.0, 0)));
+
+        // A batch takes as many tokens as it has requests
+        assert!(is_rate_limited(&connection_params, None, client(), 8).is_none());
+        assert!(matches!(
+            is_rate_limited(&connection_params, None, client(), 3),
+            Some(Limited::Rate)
+        ));
+        // Made up keys share the bucket of their IP
+        assert!(matches!(
+            is_rate_limited(&connection_params, Some("key"), client(), 3),
+            Some(Limited::Rate)
+        ));
+    }
+
+    #[test]
+    fn test_request_weight() {
+        let named_numbers = NamedBlocknumbers::default();
+        let range = |from: &str, to: &str| json!({"jsonrpc": "2.0", "id": 1, "method": "blutgang_getBlocks", "params": [from, to]});
+
+        assert_eq!(request_weight(&get_balance(1), &named_numbers), 1);
+        assert_eq!(request_weight(&range("0x1", "0xa"), &named_numbers), 10);
+        // Only the page we fetch counts
+        assert_eq!(
+            request_weight(&range("0x0", "0x10000"), &named_numbers),
+            100
+        );
+        assert_eq!(request_weight(&range("0xa", "0x1"), &named_numbers), 1);
+    }
+
+    #[tokio::test]
+    async fn test_rate_limit_listed_keys() {
+        let mut config = Settings::default();
+        config.hardened.allowed_keys = vec!["listed".to_string()];
+        let mut connection_params = params_for(Vec::new(), config, 100);
+        connection_params.rate_limiter = Some(Arc::new(RateLimiter::new(0.0, 1.0, 0)));
+
+        assert!(is_rate_limited(&connection_params, None, client(), 1).is_none());
+        assert!(is_rate_limited(&connection_params, Some("other"), client(), 1).is_some());
+        // Allowed keys have their own bucket
+        assert!(is_rate_limited(&connection_params, Some("listed"), client(), 1).is_none());
+        assert!(is_rate_limited(&connection_params, Some("listed"), client(), 1).is_some());
+    }
+
+    fn wallet_request(method: &str) -> Value {
+        json!({"jsonrpc": "2.0", "id": 1, "method": method, "params": []})
+    }
+
+    #[tokio::test]
+    async fn test_wallet_needs_key() {
+        use crate::config::types::WalletSettings;
+
+        let account = "0x00000000000000000000000000000000000000aa";
+        let config = Settings {
+            wallet_upstream: Some("signer-node".to_string()),
+            ..Settings::default()
+        };
+        let mut connection_params = params_for(vec![upstream("signer-node").await], config, 100);
+        connection_params.wallet = Some(Arc::new(Wallet::new(WalletSettings {
+            enabled: true,
+            accounts: vec![account.to_string()],
+            keys: vec!["ops".to_string()],
+            ..Default::default()
+        })));
+
+        // Neither the wallet nor `wallet_upstream` serve clients without a key
+        for api_key in [None, Some("user")] {
+            for method in WALLET_METHODS {
+                let (rx, rpc_position) =
+                    serve_as(&connection_params, wallet_request(method), api_key).await;
+                assert_eq!(rx["error"]["code"], -32601);
+                assert!(rx["error"]["message"].as_str().unwrap().contains("API key"));
+                assert_eq!(rpc_position, None);
+            }
+        }
+
+        let (rx, _) = serve_as(
+            &connection_params,
+            wallet_request("eth_accounts"),
+            Some("ops"),
+        )
+        .await;
+        assert_eq!(rx["result"], json!([account]));
+    }
+
+    #[tokio::test]
+    async fn test_wallet_method_route() {
+        let rpcs = vec![upstream("reth").await, upstream("signer-node").await];
+
+        // Rejected without a wallet RPC
+        let connection_params = params_for(rpcs.clone(), Settings::default(), 100);
+        let (rx, rpc_position) =
+            serve(&connection_params, wallet_request("eth_sendTransaction")).await;
+        assert_eq!(rx["error"]["code"], -32601);
+        assert_eq!(
+            rx["error"]["message"],
+            format!("error: {}", wallet_method_reason("eth_sendTransaction"))
+        );
+        assert_eq!(rpc_position, None);
+
+        // Always sent to the wallet RPC
+        let config = Settings {
+            wallet_upstream: Some("signer-node".to_string()),
+            ..Settings::default()
+        };
+        let connection_params = params_for(rpcs.clone(), config, 100);
+        for _ in 0..4 {
+            let (rx, rpc_position) = serve(&connection_params, wallet_request("eth_sign")).await;
+            assert_eq!(rx["result"], "signer-node:eth_sign");
+            assert_eq!(rpc_position, Some(1));
+        }
+
+        // Pins win over the wallet RPC
+        let config = Settings {
+            wallet_upstream: Some("signer-node".to_string()),
+            pin: std::collections::HashMap::from([(
+                "personal_sign".to_string(),
+                "reth".to_string(),
+            )]),
+            ..Settings::default()
+        };
+        let connection_params = params_for(rpcs, config, 100);
+        let (rx, _) = serve(&connection_params, wallet_request("personal_sign")).await;
+        assert_eq!(rx["result"], "reth:personal_sign");
+        let (rx, _) = serve(&connection_params, wallet_request("eth_accounts")).await;
+        assert_eq!(rx["result"], "signer-node:eth_accounts");
+    }
 }
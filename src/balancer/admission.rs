@@ -0,0 +1,425 @@
+// Bounded cache with a W-TinyLFU admission policy.
+//
+// Without a bound the cache grows forever, and with a plain LRU one archival scan
+// pushes out the recent blocks and popular calls everyone keeps asking for. W-TinyLFU
+// keeps both: new entries go into a small LRU window, and once they fall out of it
+// they only get into the main cache if they were requested more often than what
+// they'd replace. How often keys get requested is estimated with a count-min sketch,
+// which is halved regularly so popularity fades over time.
+//
+// The main cache is a segmented LRU. Entries start out on probation and get
+// protected once they're requested again, so one-off entries never push out
+// ones that keep getting hit.
+use std::{
+    collections::{
+        hash_map::DefaultHasher,
+        HashMap,
+    },
+    hash::{
+        Hash,
+        Hasher,
+    },
+};
+
+// Share of the main cache that's protected
+const PROTECTED_SHARE: f64 = 0.8;
+
+// Saturating counters, so halving keeps them meaningful
+const MAX_COUNT: u8 = 15;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Admission {
+    // Entries falling out of the window replace less popular ones
+    TinyLfu,
+    // Every entry is admitted, which makes the cache a plain segmented LRU
+    Lru,
+}
+
+impl Admission {
+    pub fn from_config(admission: &str) -> Option<Self> {
+        match admission {
+            "tinylfu" => Some(Admission::TinyLfu),
+            "lru" => Some(Admission::Lru),
+            _ => None,
+        }
+    }
+}
+
+// Estimates how often keys were seen, with 4 rows of counters
+#[derive(Debug)]
+struct FrequencySketch {
+    rows: [Vec<u8>; 4],
+    mask: usize,
+    // Increments since we last halved every counter
+    additions: usize,
+    sample_size: usize,
+}
+
+impl FrequencySketch {
+    fn new(capacity: usize) -> Self {
+        let width = capacity.max(16).next_power_of_two();
+        Self {
+            rows: std::array::from_fn(|_| vec![0; width]),
+            mask: width - 1,
+            additions: 0,
+            sample_size: capacity.max(16) * 10,
+        }
+    }
+
+    fn indexes(&self, hash: u64) -> [usize; 4] {
+        // Double hashing, every row gets a different index out of the same hash
+        let (low, high) = (hash as u32 as usize, (hash >> 32) as usize | 1);
+        std::array::from_fn(|row| low.wrapping_add(row.wrapping_mul(high)) & self.mask)
+    }
+
+    fn increment(&mut self, hash: u64) {
+        let indexes = self.indexes(hash);
+        let mut added = false;
+        for (row, index) in self.rows.iter_mut().zip(indexes) {
+            if row[index] < MAX_COUNT {
+                row[index] += 1;
+                added = true;
+            }
+        }
+
+        if added {
+            self.additions += 1;
+            if self.additions >= self.sample_size {
+                self.halve();
+            }
+        }
+    }
+
+    fn frequency(&self, hash: u64) -> u8 {
+        self.rows
+            .iter()
+            .zip(self.indexes(hash))
+            .map(|(row, index)| row[index])
+            .min()
+            .unwrap_or_default()
+    }
+
+    fn halve(&mut self) {
+        for row in self.rows.iter_mut() {
+            for count in row.iter_mut() {
+                *count /= 2;
+            }
+        }
+        self.additions /= 2;
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Segment {
+    Window,
+    Probation,
+    Protected,
+}
+
+#[derive(Debug)]
+struct Node {
+    key: Vec<u8>,
+    segment: Segment,
+    prev: Option<usize>,
+    next: Option<usize>,
+}
+
+// Most recently used first
+#[derive(Debug, Default, Clone, Copy)]
+struct List {
+    head: Option<usize>,
+    tail: Option<usize>,
+    len: usize,
+}
+
+// Keys in the cache, and which segment they're in.
+//
+// The lists are linked through `nodes`, so moving keys around is O(1).
+#[derive(Debug)]
+pub struct TinyLfu {
+    admission: Admission,
+    window_capacity: usize,
+    protected_capacity: usize,
+    main_capacity: usize,
+    sketch: FrequencySketch,
+    index: HashMap<Vec<u8>, usize>,
+    nodes: Vec<Node>,
+    free: Vec<usize>,
+    window: List,
+    probation: List,
+    protected: List,
+}
+
+fn hash_key(key: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    hasher.finish()
+}
+
+impl TinyLfu {
+    // Cache of up to `capacity` entries, `window` being the share of it
+    // new entries go into before being considered for admission.
+    pub fn new(capacity: usize, window: f64, admission: Admission) -> Self {
+        let capacity = capacity.max(2);
+        let window_capacity = ((capacity as f64 * window) as usize).clamp(1, capacity - 1);
+        let main_capacity = capacity - window_capacity;
+
+        Self {
+            admission,
+            window_capacity,
+            protected_capacity: (main_capacity as f64 * PROTECTED_SHARE) as usize,
+            main_capacity,
+            sketch: FrequencySketch::new(capacity),
+            index: HashMap::new(),
+            nodes: Vec::new(),
+            free: Vec::new(),
+            window: List::default(),
+            probation: List::default(),
+            protected: List::default(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    fn list(&mut self, segment: Segment) -> &mut List {
+        match segment {
+            Segment::Window => &mut self.window,
+            Segment::Probation => &mut self.probation,
+            Segment::Protected => &mut self.protected,
+        }
+    }
+
+    fn unlink(&mut self, node: usize) {
+        let (segment, prev, next) = {
+            let node = &self.nodes[node];
+            (node.segment, node.prev, node.next)
+        };
+
+        match prev {
+            Some(prev) => self.nodes[prev].next = next,
+            None => self.list(segment).head = next,
+        }
+        match next {
+            Some(next) => self.nodes[next].prev = prev,
+            None => self.list(segment).tail = prev,
+        }
+        self.list(segment).len -= 1;
+    }
+
+    fn push_front(&mut self, node: usize, segment: Segment) {
+        let head = self.list(segment).head;
+        {
+            let node = &mut self.nodes[node];
+            node.segment = segment;
+            node.prev = None;
+            node.next = head;
+        }
+        if let Some(head) = head {
+            self.nodes[head].prev = Some(node);
+        }
+
+        let list = self.list(segment);
+        list.head = Some(node);
+        if list.tail.is_none() {
+            list.tail = Some(node);
+        }
+        list.len += 1;
+    }
+
+    fn move_to(&mut self, node: usize, segment: Segment) {
+        self.unlink(node);
+        self.push_front(node, segment);
+    }
+
+    // Stop tracking `node` and return its key
+    fn remove_node(&mut self, node: usize) -> Vec<u8> {
+        self.unlink(node);
+        let key = std::mem::take(&mut self.nodes[node].key);
+        self.index.remove(&key);
+        self.free.push(node);
+        key
+    }
+
+    // Record a hit on `key`
+    pub fn touch(&mut self, key: &[u8]) {
+        self.sketch.increment(hash_key(key));
+
+        let node = match self.index.get(key) {
+            Some(node) => *node,
+            None => return,
+        };
+        match self.nodes[node].segment {
+            Segment::Window => self.move_to(node, Segment::Window),
+            Segment::Protected => self.move_to(node, Segment::Protected),
+            Segment::Probation => {
+                self.move_to(node, Segment::Protected);
+                // Make room by demoting the least recently used protected entry
+                if self.protected.len > self.protected_capacity {
+                    if let Some(demoted) = self.protected.tail {
+                        self.move_to(demoted, Segment::Probation);
+                    }
+                }
+            }
+        }
+    }
+
+    // Stop tracking the entry we'd evict first and return its key, so the cache can
+    // be shrunk below capacity. Entries on probation go first, then the window.
+    pub fn evict(&mut self) -> Option<Vec<u8>> {
+        let victim = self
+            .probation
+            .tail
+            .or(self.window.tail)
+            .or(self.protected.tail)?;
+        Some(self.remove_node(victim))
+    }
+
+    // Track a newly cached `key`. Returns the keys that have to be removed from
+    // the cache to stay within capacity, which can be `key` itself.
+    pub fn insert(&mut self, key: &[u8]) -> Vec<Vec<u8>> {
+        if self.index.contains_key(key) {
+            self.touch(key);
+            return Vec::new();
+        }
+        self.sketch.increment(hash_key(key));
+
+        let node = match self.free.pop() {
+            Some(node) => {
+                self.nodes[node].key = key.to_vec();
+                node
+            }
+            None => {
+                self.nodes.push(Node {
+                    key: key.to_vec(),
+                    segment: Segment::Window,
+                    prev: None,
+                    next: None,
+                });
+                self.nodes.len() - 1
+            }
+        };
+        self.index.insert(key.to_vec(), node);
+        self.push_front(node, Segment::Window);
+
+        let mut evicted = Vec::new();
+        while self.window.len > self.window_capacity {
+            let candidate = match self.window.tail {
+                Some(candidate) => candidate,
+                None => break,
+            };
+
+            // There's still room in the main cache
+            if self.probation.len + self.protected.len < self.main_capacity {
+                self.move_to(candidate, Segment::Probation);
+                continue;
+            }
+
+            let victim = match self.probation.tail.or(self.protected.tail) {
+                Some(victim) => victim,
+                None => break,
+            };
+            let admit = match self.admission {
+                Admission::Lru => true,
+                Admission::TinyLfu => {
+                    self.sketch.frequency(hash_key(&self.nodes[candidate].key))
+                        > self.sketch.frequency(hash_key(&self.nodes[victim].key))
+                }
+            };
+
+            match admit {
+                true => {
+                    evicted.push(self.remove_node(victim));
+                    self.move_to(candidate, Segment::Probation);
+                }
+                false => evicted.push(self.remove_node(candidate)),
+            }
+        }
+
+        evicted
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(i: usize) -> Vec<u8> {
+        format!("key{}", i).into_bytes()
+    }
+
+    #[test]
+    fn test_sketch() {
+        let mut sketch = FrequencySketch::new(64);
+        for _ in 0..5 {
+            sketch.increment(hash_key(b"hot"));
+        }
+        sketch.increment(hash_key(b"cold"));
+
+        assert_eq!(sketch.frequency(hash_key(b"hot")), 5);
+        assert_eq!(sketch.frequency(hash_key(b"cold")), 1);
+        assert_eq!(sketch.frequency(hash_key(b"never")), 0);
+
+        sketch.halve();
+        assert_eq!(sketch.frequency(hash_key(b"hot")), 2);
+    }
+
+    #[test]
+    fn test_capacity() {
+        let mut cache = TinyLfu::new(100, 0.01, Admission::TinyLfu);
+        let mut evicted = 0;
+        for i in 0..1000 {
+            evicted += cache.insert(&key(i)).len();
+        }
+
+        assert_eq!(cache.len(), 100);
+        assert_eq!(evicted, 900);
+    }
+
+    #[test]
+    fn test_scan_resistance() {
+        let mut cache = TinyLfu::new(100, 0.01, Admission::TinyLfu);
+
+        // Hot working set that keeps getting hit. Key 50 pushes the last one out of the window.
+        for i in 0..=50 {
+            cache.insert(&key(i));
+        }
+        for _ in 0..5 {
+            for i in 0..50 {
+                cache.touch(&key(i));
+            }
+        }
+
+        // One-off scan, much bigger than the cache
+        let evicted: Vec<Vec<u8>> = (1000..3000).flat_map(|i| cache.insert(&key(i))).collect();
+        assert!((0..50).all(|i| !evicted.contains(&key(i))));
+
+        // Scanned entries never made it past the window
+        assert!(evicted.contains(&key(2000)));
+    }
+
+    #[test]
+    fn test_lru() {
+        let mut cache = TinyLfu::new(100, 0.01, Admission::Lru);
+        let evicted: Vec<Vec<u8>> = (0..200).flat_map(|i| cache.insert(&key(i))).collect();
+
+        // Least recently used first, whatever their frequency
+        assert_eq!(evicted, (0..100).map(key).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_evict() {
+        let mut cache = TinyLfu::new(100, 0.01, Admission::Lru);
+        for i in 0..10 {
+            cache.insert(&key(i));
+        }
+        // Hit again, so it's protected
+        cache.touch(&key(0));
+
+        assert_eq!(cache.evict(), Some(key(1)));
+        let evicted: Vec<Vec<u8>> = std::iter::from_fn(|| cache.evict()).collect();
+        assert_eq!(evicted.last(), Some(&key(0)));
+        assert_eq!(cache.len(), 0);
+    }
+}
@@ -0,0 +1,127 @@
+// Long-poll for new blocks.
+//
+// `GET /await-block?after=N&timeout=30s` returns as soon as our head is past block N, or
+// after the timeout with the head we have. Lets clients that can't use WebSockets follow
+// the head without polling in a tight loop.
+use serde_json::{
+    json,
+    Value,
+};
+use std::time::Duration;
+use tokio::{
+    sync::watch,
+    time::timeout,
+};
+
+pub const PATH: &str = "/await-block";
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+// So clients can't hold connections open forever
+const MAX_TIMEOUT: Duration = Duration::from_secs(120);
+
+// Parse durations like `30s`, `500ms` or `30`, which is in seconds
+fn parse_timeout(value: &str) -> Result<Duration, String> {
+    let invalid = || format!("invalid timeout: {}", value);
+    let duration = match value.strip_suffix("ms") {
+        Some(ms) => Duration::from_millis(ms.parse().map_err(|_| invalid())?),
+        None => {
+            let secs = value.strip_suffix('s').unwrap_or(value);
+            Duration::from_secs(secs.parse().map_err(|_| invalid())?)
+        }
+    };
+
+    Ok(duration.min(MAX_TIMEOUT))
+}
+
+fn parse_number(value: &str) -> Result<u64, String> {
+    let parsed = match value.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => value.parse(),
+    };
+
+    parsed.map_err(|_| format!("invalid block number: {}", value))
+}
+
+// Block we wait to be past, if any, and how long we wait for it
+pub fn parse_query(query: Option<&str>) -> Result<(Option<u64>, Duration), String> {
+    let mut after = None;
+    let mut wait = DEFAULT_TIMEOUT;
+
+    for pair in query
+        .unwrap_or_default()
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+    {
+        match pair.split_once('=') {
+            Some(("after", value)) => after = Some(parse_number(value)?),
+            Some(("timeout", value)) => wait = parse_timeout(value)?,
+            _ => return Err(format!("unknown parameter: {}", pair)),
+        }
+    }
+
+    Ok((after, wait))
+}
+
+// Wait until the head is past `after`, or the current head if None
+pub async fn await_block(
+    mut blocknum_rx: watch::Receiver<u64>,
+    after: Option<u64>,
+    wait: Duration,
+) -> Value {
+    let after = after.unwrap_or_else(|| *blocknum_rx.borrow_and_update());
+    let new_block = timeout(wait, blocknum_rx.wait_for(|head| *head > after))
+        .await
+        .is_ok_and(|head| head.is_ok());
+    let head = *blocknum_rx.borrow();
+
+    json!({
+        "number": format!("{:#x}", head),
+        "timedOut": !new_block,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_query() {
+        assert_eq!(parse_query(None), Ok((None, DEFAULT_TIMEOUT)));
+        assert_eq!(
+            parse_query(Some("after=100&timeout=500ms")),
+            Ok((Some(100), Duration::from_millis(500)))
+        );
+        assert_eq!(
+            parse_query(Some("timeout=10&after=0x10")),
+            Ok((Some(16), Duration::from_secs(10)))
+        );
+        assert_eq!(
+            parse_query(Some("timeout=1h")),
+            Err("invalid timeout: 1h".to_string())
+        );
+        assert_eq!(parse_query(Some("timeout=9000s")), Ok((None, MAX_TIMEOUT)));
+        assert!(parse_query(Some("after=latest")).is_err());
+        assert!(parse_query(Some("block=1")).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_await_block() {
+        let (blocknum_tx, blocknum_rx) = watch::channel(10);
+
+        // Already past it
+        let rx = await_block(blocknum_rx.clone(), Some(5), Duration::from_secs(1)).await;
+        assert_eq!(rx, json!({"number": "0xa", "timedOut": false}));
+
+        let rx = await_block(blocknum_rx.clone(), None, Duration::from_millis(10)).await;
+        assert_eq!(rx, json!({"number": "0xa", "timedOut": true}));
+
+        let waiting = tokio::spawn(await_block(blocknum_rx, Some(10), Duration::from_secs(5)));
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        blocknum_tx.send(11).unwrap();
+        assert_eq!(
+            waiting.await.unwrap(),
+            json!({"number": "0xb", "timedOut": false})
+        );
+    }
+}
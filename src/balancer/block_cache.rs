@@ -0,0 +1,259 @@
+// Full blocks (`eth_getBlockBy*` with `true` as the 2nd param) contain every
+// transaction object, which are also what `eth_getTransactionByHash` returns.
+//
+// When we cache a full block we also cache its transactions and the hashes-only
+// version of the block. The other way around, a full block can be assembled from a
+// cached hashes-only block as long as all of its transactions are cached.
+use crate::balancer::codec::CacheCodec;
+
+use serde_json::{
+    json,
+    to_vec,
+    Value,
+};
+use sled::Db;
+
+// Select either blake3 or xxhash based on the features
+#[cfg(not(feature = "xxhash"))]
+use blake3::{
+    hash,
+    Hash,
+};
+
+#[cfg(feature = "xxhash")]
+use xxhash_rust::xxh3::xxh3_64;
+#[cfg(feature = "xxhash")]
+use zerocopy::AsBytes; // Impls AsBytes trait for u64
+
+#[cfg(not(feature = "xxhash"))]
+pub type RequestHash = Hash;
+#[cfg(feature = "xxhash")]
+pub type RequestHash = u64;
+
+// Key a request is cached under. The id has to be null.
+#[cfg(not(feature = "xxhash"))]
+pub fn request_hash(tx: &Value) -> RequestHash {
+    hash(to_vec(tx).unwrap().as_slice())
+}
+
+#[cfg(feature = "xxhash")]
+pub fn request_hash(tx: &Value) -> RequestHash {
+    xxh3_64(to_vec(tx).unwrap().as_slice())
+}
+
+// First 8 bytes of a request hash, used to spread requests over RPCs
+#[cfg(not(feature = "xxhash"))]
+pub fn request_key(hash: &RequestHash) -> u64 {
+    let mut key = [0; 8];
+    key.copy_from_slice(&hash.as_bytes()[..8]);
+    u64::from_le_bytes(key)
+}
+
+#[cfg(feature = "xxhash")]
+pub fn request_key(hash: &RequestHash) -> u64 {
+    *hash
+}
+
+fn request(method: &str, params: Value) -> Value {
+    json!({
+        "id": null,
+        "jsonrpc": "2.0",
+        "method": method,
+        "params": params,
+    })
+}
+
+// Same format as the responses we cache, id is set once we reply
+fn response(result: &Value) -> Vec<u8> {
+    to_vec(&json!({
+        "id": null,
+        "jsonrpc": "2.0",
+        "result": result,
+    }))
+    .unwrap()
+}
+
+fn is_block_request(tx: &Value) -> bool {
+    matches!(
+        tx["method"].as_str(),
+        Some("eth_getBlockByNumber") | Some("eth_getBlockByHash")
+    )
+}
+
+// Split a cached full block into its transactions and the hashes-only block,
+// along with the same block requested by number and by hash.
+//
+// Returns `(cache key, response)` pairs. The request that got us the block itself
+// is not included.
+pub fn decompose_block(tx: &Value, rx: &[u8]) -> Vec<(RequestHash, Vec<u8>)> {
+    if !is_block_request(tx) || tx["params"][1] != true {
+        return Vec::new();
+    }
+
+    let rx: Value = match serde_json::from_slice(rx) {
+        Ok(rx) => rx,
+        Err(_) => return Vec::new(),
+    };
+    let block = &rx["result"];
+    let (number, block_hash, transactions) = match (
+        block["number"].as_str(),
+        block["hash"].as_str(),
+        block["transactions"].as_array(),
+    ) {
+        (Some(number), Some(block_hash), Some(transactions)) => (number, block_hash, transactions),
+        _ => return Vec::new(),
+    };
+
+    let mut pieces = Vec::with_capacity(transactions.len() + 3);
+    let mut hashes = Vec::with_capacity(transactions.len());
+
+    for transaction in transactions {
+        let tx_hash = match transaction["hash"].as_str() {
+            Some(tx_hash) => tx_hash,
+            None => return Vec::new(),
+        };
+        hashes.push(Value::from(tx_hash));
+
+        let key = request_hash(&request("eth_getTransactionByHash", json!([tx_hash])));
+        pieces.push((key, response(transaction)));
+    }
+
+    let mut hashes_block = block.clone();
+    hashes_block["transactions"] = Value::Array(hashes);
+    let full = response(block);
+    let hashes_only = response(&hashes_block);
+
+    for (method, id) in [
+        ("eth_getBlockByNumber", number),
+        ("eth_getBlockByHash", block_hash),
+    ] {
+        for (full_transactions, rx) in [(true, &full), (false, &hashes_only)] {
+            let params = json!([id, full_transactions]);
+            if tx["method"] == method && tx["params"] == params {
+                continue;
+            }
+            pieces.push((request_hash(&request(method, params)), rx.clone()));
+        }
+    }
+
+    pieces
+}
+
+// Try to assemble a full block from a cached hashes-only block and cached transactions
+pub fn assemble_block(tx: &Value, cache: &Db, codec: &CacheCodec) -> Option<Vec<u8>> {
+    if !is_block_request(tx) || tx["params"][1] != true {
+        return None;
+    }
+
+    let mut hashes_request = tx.clone();
+    hashes_request["params"][1] = false.into();
+
+    let cached = codec
+        .get(cache, request_hash(&hashes_request).as_bytes())
+        .ok()??;
+    let cached: Value = serde_json::from_slice(&cached).ok()?;
+    let mut block = cached["result"].clone();
+
+    let mut transactions = Vec::new();
+    for tx_hash in block["transactions"].as_array()? {
+        let key = request_hash(&request("eth_getTransactionByHash", json!([tx_hash])));
+        let cached = codec.get(cache, key.as_bytes()).ok()??;
+        let cached: Value = serde_json::from_slice(&cached).ok()?;
+        transactions.push(cached["result"].clone());
+    }
+    block["transactions"] = Value::Array(transactions);
+
+    Some(response(&block))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn full_block() -> Value {
+        json!({
+            "id": null,
+            "jsonrpc": "2.0",
+            "result": {
+                "number": "0x10",
+                "hash": "0xb10c",
+                "transactions": [
+                    {"hash": "0xaa", "blockNumber": "0x10", "value": "0x1"},
+                    {"hash": "0xbb", "blockNumber": "0x10", "value": "0x2"},
+                ],
+            },
+        })
+    }
+
+    fn find(pieces: &[(RequestHash, Vec<u8>)], tx: &Value) -> Vec<u8> {
+        let key = request_hash(tx);
+        pieces
+            .iter()
+            .find(|(piece_key, _)| *piece_key == key)
+            .map(|(_, rx)| rx.clone())
+            .unwrap()
+    }
+
+    // Cache only the pieces for `requests`
+    fn insert(cache: &Db, pieces: &[(RequestHash, Vec<u8>)], requests: &[Value]) {
+        for tx in requests {
+            cache
+                .insert(request_hash(tx).as_bytes(), find(pieces, tx))
+                .unwrap();
+        }
+    }
+
+    #[test]
+    fn test_decompose_block() {
+        let tx = request("eth_getBlockByNumber", json!(["0x10", true]));
+        let pieces = decompose_block(&tx, &to_vec(&full_block()).unwrap());
+
+        // 2 transactions and 3 other ways of requesting the block
+        assert_eq!(pieces.len(), 5);
+
+        let rx = find(
+            &pieces,
+            &request("eth_getTransactionByHash", json!(["0xbb"])),
+        );
+        let rx: Value = serde_json::from_slice(&rx).unwrap();
+        assert_eq!(rx["result"]["value"], "0x2");
+        assert_eq!(rx["id"], Value::Null);
+
+        let rx = find(
+            &pieces,
+            &request("eth_getBlockByHash", json!(["0xb10c", false])),
+        );
+        let rx: Value = serde_json::from_slice(&rx).unwrap();
+        assert_eq!(rx["result"]["transactions"], json!(["0xaa", "0xbb"]));
+
+        // Only full blocks get decomposed
+        let tx = request("eth_getBlockByNumber", json!(["0x10", false]));
+        assert!(decompose_block(&tx, &to_vec(&full_block()).unwrap()).is_empty());
+    }
+
+    #[test]
+    fn test_assemble_block() {
+        let cache = sled::Config::new().temporary(true).open().unwrap();
+        let tx = request("eth_getBlockByHash", json!(["0xb10c", true]));
+        let pieces = decompose_block(
+            &request("eth_getBlockByNumber", json!(["0x10", true])),
+            &to_vec(&full_block()).unwrap(),
+        );
+
+        // Nothing cached yet
+        assert_eq!(assemble_block(&tx, &cache, &CacheCodec::default()), None);
+
+        // Missing one of the transactions
+        let hashes_request = request("eth_getBlockByHash", json!(["0xb10c", false]));
+        let tx_a = request("eth_getTransactionByHash", json!(["0xaa"]));
+        insert(&cache, &pieces, &[hashes_request, tx_a]);
+        assert_eq!(assemble_block(&tx, &cache, &CacheCodec::default()), None);
+
+        let tx_b = request("eth_getTransactionByHash", json!(["0xbb"]));
+        insert(&cache, &pieces, &[tx_b]);
+        let assembled: Value =
+            serde_json::from_slice(&assemble_block(&tx, &cache, &CacheCodec::default()).unwrap())
+                .unwrap();
+        assert_eq!(assembled, full_block());
+    }
+}
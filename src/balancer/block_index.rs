@@ -0,0 +1,143 @@
+// Index of cached responses by the blocks they're for.
+//
+// Cache keys are hashes of requests, so there's no telling which entries are for which
+// blocks from the cache alone. Responses we cache for a block also get an entry here,
+// keyed by the first block and the cache key, with the last block and the method. That
+// way entries for a range of blocks can be flushed, say after a provider served bad
+// data for them, without clearing the whole cache.
+//
+// Index entries are left behind when their responses get removed some other way, like
+// on reorgs. Flushing those is harmless, and removes them from the index too.
+use sled::{
+    Batch,
+    Db,
+};
+
+pub const INDEX_TREE: &str = "blutgang_block_index";
+
+fn index_key(from: u64, key: &[u8]) -> Vec<u8> {
+    let mut index_key = Vec::with_capacity(8 + key.len());
+    index_key.extend_from_slice(&from.to_be_bytes());
+    index_key.extend_from_slice(key);
+    index_key
+}
+
+fn index_value(to: u64, method: &str) -> Vec<u8> {
+    let mut value = Vec::with_capacity(8 + method.len());
+    value.extend_from_slice(&to.to_be_bytes());
+    value.extend_from_slice(method.as_bytes());
+    value
+}
+
+// Last block and method of an index entry
+fn parse_value(value: &[u8]) -> Option<(u64, &str)> {
+    let to = u64::from_be_bytes(value.get(..8)?.try_into().ok()?);
+    let method = std::str::from_utf8(&value[8..]).ok()?;
+    Some((to, method))
+}
+
+// If `method` is `pattern`, or in the namespace of a pattern ending in `*`
+pub fn matches_pattern(pattern: &str, method: &str) -> bool {
+    match pattern.strip_suffix('*') {
+        Some(prefix) => method.starts_with(prefix),
+        None => method == pattern,
+    }
+}
+
+// Remember that the response cached under `key` for `method` is for blocks `from..=to`
+pub fn record(cache: &Db, key: &[u8], method: &str, from: u64, to: u64) -> Result<(), sled::Error> {
+    cache
+        .open_tree(INDEX_TREE)?
+        .insert(index_key(from, key), index_value(to, method))?;
+    Ok(())
+}
+
+// Remove the cached responses touching blocks `from..=to` whose method matches
+// `pattern`, or all of them if it's None. Returns how many we removed.
+//
+// Entries can span several blocks, so entries starting up to `lookback` blocks
+// before `from` are checked too.
+pub fn flush_range(
+    cache: &Db,
+    from: u64,
+    to: u64,
+    lookback: u64,
+    pattern: Option<&str>,
+) -> Result<usize, sled::Error> {
+    let index = cache.open_tree(INDEX_TREE)?;
+    let start = from.saturating_sub(lookback).to_be_bytes();
+    let entries = match to.checked_add(1) {
+        Some(end) => index.range(start..end.to_be_bytes()),
+        None => index.range(start..),
+    };
+
+    let mut removed = Batch::default();
+    let mut unindexed = Batch::default();
+    let mut count = 0;
+    for entry in entries {
+        let (index_key, value) = entry?;
+        let flushed = match parse_value(&value) {
+            Some((last, method)) => {
+                last >= from && pattern.map_or(true, |pattern| matches_pattern(pattern, method))
+            }
+            None => false,
+        };
+        if flushed {
+            removed.remove(&index_key[8..]);
+            unindexed.remove(index_key);
+            count += 1;
+        }
+    }
+
+    cache.apply_batch(removed)?;
+    index.apply_batch(unindexed)?;
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cache() -> Db {
+        sled::Config::new().temporary(true).open().unwrap()
+    }
+
+    #[test]
+    fn test_flush_range() {
+        let cache = cache();
+        for (key, method, from, to) in [
+            ("a", "eth_getBlockByNumber", 10, 10),
+            ("b", "eth_getBlockByNumber", 20, 20),
+            ("c", "eth_getLogs", 0, 9),
+            ("d", "eth_getLogs", 10, 19),
+            ("e", "trace_block", 15, 15),
+        ] {
+            cache.insert(key, "rx").unwrap();
+            record(&cache, key.as_bytes(), method, from, to).unwrap();
+        }
+
+        // Chunk `d` starts before 12 but goes past it
+        assert_eq!(
+            flush_range(&cache, 12, 16, 9, Some("eth_getLogs")).unwrap(),
+            1
+        );
+        assert!(cache.get("d").unwrap().is_none());
+        assert!(cache.get("e").unwrap().is_some());
+
+        assert_eq!(flush_range(&cache, 10, 20, 9, Some("eth_*")).unwrap(), 2);
+        assert!(cache.get("c").unwrap().is_some());
+        assert!(cache.get("e").unwrap().is_some());
+
+        // Flushed entries are gone from the index too
+        assert_eq!(flush_range(&cache, 0, u64::MAX, 0, None).unwrap(), 2);
+        assert!(cache.open_tree(INDEX_TREE).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_matches_pattern() {
+        assert!(matches_pattern("eth_getLogs", "eth_getLogs"));
+        assert!(!matches_pattern("eth_getLogs", "eth_getLogsAndMore"));
+        assert!(matches_pattern("trace_*", "trace_block"));
+        assert!(matches_pattern("*", "eth_call"));
+    }
+}
@@ -0,0 +1,192 @@
+// `blutgang_getBlocks(from, to, full)` gets a whole range of blocks in one request,
+// so indexers don't have to send thousands of `eth_getBlockByNumber` calls.
+//
+// Every block is fetched just like an `eth_getBlockByNumber` request for it would be,
+// so they're served from the cache when possible and cached otherwise. Ranges are
+// paginated: we return up to `MAX_BLOCKS` blocks starting at `from`, and `next` is
+// where to continue from, or null once the range is done.
+use crate::NamedBlocknumbers;
+
+use std::ops::RangeInclusive;
+
+use serde_json::{
+    json,
+    Value,
+};
+
+pub const METHOD: &str = "blutgang_getBlocks";
+
+// Blocks we return per page
+pub const MAX_BLOCKS: u64 = 100;
+
+// Blocks we fetch at the same time
+pub const CONCURRENCY: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BlockRange {
+    pub from: u64,
+    pub to: u64,
+    pub full: bool,
+}
+
+impl BlockRange {
+    // Blocks on this page, and where the next one starts
+    pub fn page(&self) -> (RangeInclusive<u64>, Option<u64>) {
+        let end = self.to.min(self.from.saturating_add(MAX_BLOCKS - 1));
+        let next = (end < self.to).then_some(end + 1);
+
+        (self.from..=end, next)
+    }
+}
+
+// Parse a hex block number or a named block
+pub fn parse_block(param: &Value, named_numbers: &NamedBlocknumbers) -> Result<u64, String> {
+    let param = param
+        .as_str()
+        .ok_or_else(|| format!("invalid block number: {}", param))?;
+
+    match param {
+        "latest" => Ok(named_numbers.latest),
+        "safe" => Ok(named_numbers.safe),
+        "finalized" => Ok(named_numbers.finalized),
+        "earliest" => Ok(0),
+        _ => {
+            let number = param
+                .strip_prefix("0x")
+                .ok_or_else(|| format!("invalid block number: {}", param))?;
+            u64::from_str_radix(number, 16).map_err(|_| format!("invalid block number: {}", param))
+        }
+    }
+}
+
+// Returns None if `tx` isn't a `blutgang_getBlocks` request
+pub fn parse_block_range(
+    tx: &Value,
+    named_numbers: &NamedBlocknumbers,
+) -> Option<Result<BlockRange, String>> {
+    if tx["method"] != METHOD {
+        return None;
+    }
+
+    let params = &tx["params"];
+    let range = parse_block(&params[0], named_numbers).and_then(|from| {
+        let to = parse_block(&params[1], named_numbers)?;
+        if from > to {
+            return Err("from is past to".to_string());
+        }
+
+        let full = match &params[2] {
+            Value::Null => false,
+            Value::Bool(full) => *full,
+            full => return Err(format!("invalid full transactions flag: {}", full)),
+        };
+
+        Ok(BlockRange { from, to, full })
+    });
+
+    Some(range)
+}
+
+// `eth_getBlockByNumber` request for a single block in the range
+pub fn block_request(number: u64, full: bool) -> Value {
+    json!({
+        "id": null,
+        "jsonrpc": "2.0",
+        "method": "eth_getBlockByNumber",
+        "params": [format!("{:#x}", number), full],
+    })
+}
+
+pub fn range_response(id: &Value, blocks: Vec<Value>, next: Option<u64>) -> Value {
+    json!({
+        "id": id,
+        "jsonrpc": "2.0",
+        "result": {
+            "blocks": blocks,
+            "next": next.map(|next| format!("{:#x}", next)),
+        },
+    })
+}
+
+pub fn range_error(id: &Value, message: &str) -> Value {
+    json!({
+        "id": id,
+        "jsonrpc": "2.0",
+        "error": {
+            "code": -32602,
+            "message": message,
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn named_numbers() -> NamedBlocknumbers {
+        NamedBlocknumbers {
+            latest: 1000,
+            earliest: 0,
+            safe: 990,
+            finalized: 980,
+            pending: 1001,
+            number: 1000,
+        }
+    }
+
+    fn request(params: Value) -> Value {
+        json!({"id": 1, "jsonrpc": "2.0", "method": METHOD, "params": params})
+    }
+
+    #[test]
+    fn test_parse_block_range() {
+        let range = parse_block_range(&request(json!(["0x10", "latest", true])), &named_numbers());
+        assert_eq!(
+            range,
+            Some(Ok(BlockRange {
+                from: 16,
+                to: 1000,
+                full: true
+            }))
+        );
+
+        // Hashes only by default
+        let range = parse_block_range(&request(json!(["0x10", "0x20"])), &named_numbers());
+        assert!(!range.unwrap().unwrap().full);
+
+        assert!(
+            parse_block_range(&request(json!(["0x20", "0x10"])), &named_numbers())
+                .unwrap()
+                .is_err()
+        );
+        assert!(
+            parse_block_range(&request(json!(["pending", "0x10"])), &named_numbers())
+                .unwrap()
+                .is_err()
+        );
+
+        let tx = json!({"id": 1, "jsonrpc": "2.0", "method": "eth_getBlockByNumber", "params": ["0x10", true]});
+        assert_eq!(parse_block_range(&tx, &named_numbers()), None);
+    }
+
+    #[test]
+    fn test_page() {
+        let range = BlockRange {
+            from: 0,
+            to: 250,
+            full: false,
+        };
+        let (blocks, next) = range.page();
+        assert_eq!(blocks, 0..=99);
+        assert_eq!(next, Some(100));
+
+        let range = BlockRange {
+            from: 200,
+            to: 250,
+            full: false,
+        };
+        let (blocks, next) = range.page();
+        assert_eq!(blocks, 200..=250);
+        assert_eq!(next, None);
+    }
+}
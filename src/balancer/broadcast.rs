@@ -0,0 +1,155 @@
+// Broadcasting `eth_sendRawTransaction` to every healthy RPC at once.
+//
+// Each RPC gossips to different peers, so sending to all of them gets a transaction
+// into more mempools sooner. The client gets the first hash we get back. The rest of
+// the responses are still collected afterwards, and RPCs that answered differently
+// get a warning. Most of them agree, so identical responses are only logged once.
+use crate::Rpc;
+
+use std::time::Duration;
+
+use hyper::body::Bytes;
+use serde_json::Value;
+use tokio::{
+    sync::oneshot,
+    task::JoinSet,
+    time::timeout,
+};
+
+// Errors that only mean the RPC has the transaction already, usually from a peer
+const ALREADY_KNOWN: [&str; 3] = ["already known", "already imported", "known transaction"];
+
+// Returns true if `rx` is a hash and not an error
+fn is_success(rx: &[u8]) -> bool {
+    serde_json::from_slice::<Value>(rx).is_ok_and(|rx| rx["result"].is_string())
+}
+
+// What an RPC answered, with errors about knowing the transaction counting as success
+fn outcome(rx: &[u8]) -> String {
+    let rx: Value = match serde_json::from_slice(rx) {
+        Ok(rx) => rx,
+        Err(_) => return "invalid response".to_string(),
+    };
+    match rx["error"]["message"].as_str() {
+        Some(message) => {
+            let lowercase = message.to_lowercase();
+            match ALREADY_KNOWN.iter().any(|known| lowercase.contains(known)) {
+                true => "success".to_string(),
+                false => message.to_string(),
+            }
+        }
+        None => "success".to_string(),
+    }
+}
+
+// Distinct outcomes of `responses` and the RPCs that had them, in the order we got them
+fn group(responses: &[(String, Bytes)]) -> Vec<(String, Vec<&str>)> {
+    let mut groups: Vec<(String, Vec<&str>)> = Vec::new();
+    for (name, rx) in responses {
+        let outcome = outcome(rx);
+        match groups.iter_mut().find(|(seen, _)| *seen == outcome) {
+            Some((_, names)) => names.push(name),
+            None => groups.push((outcome, vec![name])),
+        }
+    }
+    groups
+}
+
+// Send `tx` to all of `rpcs` at once, returning the first successful response. If none
+// of them succeed, it's the first error we got instead.
+//
+// None if none of them answered.
+pub async fn broadcast(tx: Bytes, rpcs: Vec<Rpc>, ttl: Duration) -> Option<Bytes> {
+    let (first_tx, first_rx) = oneshot::channel();
+    tokio::task::spawn(collect(tx, rpcs, ttl, first_tx));
+    first_rx.await.ok()
+}
+
+async fn collect(tx: Bytes, rpcs: Vec<Rpc>, ttl: Duration, first_tx: oneshot::Sender<Bytes>) {
+    let mut requests = JoinSet::new();
+    for rpc in rpcs {
+        let tx = tx.clone();
+        requests.spawn(async move {
+            let rx = match timeout(ttl, rpc.send_raw(tx)).await {
+                Ok(Ok(rx)) => Some(rx),
+                _ => None,
+            };
+            (rpc.name, rx)
+        });
+    }
+
+    let mut first_tx = Some(first_tx);
+    let mut responses = Vec::new();
+    while let Some(response) = requests.join_next().await {
+        if let Ok((name, Some(rx))) = response {
+            if is_success(&rx) {
+                if let Some(first_tx) = first_tx.take() {
+                    let _ = first_tx.send(rx.clone());
+                }
+            }
+            responses.push((name, rx));
+        }
+    }
+    if let (Some(first_tx), Some((_, rx))) = (first_tx, responses.first()) {
+        let _ = first_tx.send(rx.clone());
+    }
+
+    let groups = group(&responses);
+    if groups.len() > 1 {
+        let summary: Vec<String> = groups
+            .iter()
+            .map(|(outcome, names)| format!("{} ({})", outcome, names.join(", ")))
+            .collect();
+        println!(
+            "\x1b[93mWrn:\x1b[0m RPCs disagreed on a broadcast transaction: {}",
+            summary.join("; ")
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response(name: &str, rx: &str) -> (String, Bytes) {
+        (name.to_string(), Bytes::from(rx.to_string()))
+    }
+
+    #[test]
+    fn test_group() {
+        let responses = vec![
+            response("a", r#"{"id":1,"jsonrpc":"2.0","result":"0xabc"}"#),
+            response(
+                "b",
+                r#"{"id":1,"jsonrpc":"2.0","error":{"code":-32000,"message":"already known"}}"#,
+            ),
+            response(
+                "c",
+                r#"{"id":1,"jsonrpc":"2.0","error":{"code":-32000,"message":"nonce too low"}}"#,
+            ),
+            response("d", r#"{"id":1,"jsonrpc":"2.0","result":"0xabc"}"#),
+        ];
+
+        assert_eq!(
+            group(&responses),
+            vec![
+                ("success".to_string(), vec!["a", "b", "d"]),
+                ("nonce too low".to_string(), vec!["c"]),
+            ]
+        );
+        assert!(is_success(&responses[0].1));
+        assert!(!is_success(&responses[1].1));
+    }
+
+    #[tokio::test]
+    async fn test_broadcast_unreachable() {
+        let rpcs = vec![
+            Rpc::new("http://127.0.0.1:1".to_string(), 0, 0.0),
+            Rpc::new("http://127.0.0.1:2".to_string(), 0, 0.0),
+        ];
+        let tx = Bytes::from(
+            r#"{"id":1,"jsonrpc":"2.0","method":"eth_sendRawTransaction","params":["0x"]}"#,
+        );
+        assert_eq!(broadcast(tx, rpcs, Duration::from_millis(500)).await, None);
+    }
+}
@@ -0,0 +1,286 @@
+// Picks which chain a request is for, when we serve more than one.
+//
+// Every chain in `[chains]` has a config file of its own, so its own RPCs, cache and
+// health checks, and all of them share our listener. Requests go to a chain by naming
+// it in `x-blutgang-chain`, or by being under its path, which gets stripped so
+// `/op/events` is `/events` to the chain. Everything else goes to the main config.
+use crate::{
+    balancer::accept_http::{
+        serve_request,
+        ConnectionParams,
+    },
+    config::types::{
+        ChainRoute,
+        Settings,
+    },
+};
+
+use std::{
+    convert::Infallible,
+    fs,
+    net::SocketAddr,
+    path::PathBuf,
+};
+
+use http_body_util::{
+    combinators::BoxBody,
+    BodyExt,
+    Full,
+};
+use hyper::{
+    body::Bytes,
+    Request,
+};
+use serde_json::json;
+
+pub const CHAIN_HEADER: &str = "x-blutgang-chain";
+
+#[derive(Debug, Clone)]
+pub struct ChainRouter {
+    default: ConnectionParams,
+    routes: Vec<ChainRoute>,
+    // Indexed like `routes`
+    chains: Vec<ConnectionParams>,
+}
+
+impl ChainRouter {
+    pub fn new(default: ConnectionParams, chains: Vec<(ChainRoute, ConnectionParams)>) -> Self {
+        let (routes, chains) = chains.into_iter().unzip();
+        Self {
+            default,
+            routes,
+            chains,
+        }
+    }
+}
+
+// `path` relative to `prefix`, None if it isn't under it
+fn relative(path: &str, prefix: &str) -> Option<String> {
+    match path.strip_prefix(prefix)? {
+        "" => Some("/".to_string()),
+        rest if rest.starts_with('/') => Some(rest.to_string()),
+        // `/optimism` isn't under `/op`
+        _ => None,
+    }
+}
+
+// Index of the chain a request to `path` is for and the path relative to the chain.
+// None for the main chain, and an error if `header` names a chain we don't serve.
+fn select(
+    routes: &[ChainRoute],
+    path: &str,
+    header: Option<&str>,
+) -> Result<Option<(usize, String)>, String> {
+    if let Some(name) = header {
+        let index = routes
+            .iter()
+            .position(|route| route.name == name)
+            .ok_or_else(|| format!("Unknown chain: {}", name))?;
+        let path = relative(path, &routes[index].path).unwrap_or_else(|| path.to_string());
+        return Ok(Some((index, path)));
+    }
+
+    Ok(routes
+        .iter()
+        .enumerate()
+        .find_map(|(index, route)| Some((index, relative(path, &route.path)?))))
+}
+
+// `serve_request`, with the params of the chain the request is for
+pub async fn serve_routed(
+    mut tx: Request<hyper::body::Incoming>,
+    router: &ChainRouter,
+    socketaddr: SocketAddr,
+) -> Result<hyper::Response<BoxBody<Bytes, Infallible>>, Infallible> {
+    let header = tx
+        .headers()
+        .get(CHAIN_HEADER)
+        .map(|chain| chain.to_str().unwrap_or_default());
+
+    match select(&router.routes, tx.uri().path(), header) {
+        Ok(None) => serve_request(tx, &router.default, socketaddr).await,
+        Ok(Some((index, path))) => {
+            let uri = match tx.uri().query() {
+                Some(query) => format!("{}?{}", path, query),
+                None => path,
+            };
+            if let Ok(uri) = uri.parse() {
+                *tx.uri_mut() = uri;
+            }
+            serve_request(tx, &router.chains[index], socketaddr).await
+        }
+        Err(err) => {
+            let body = json!({
+                "jsonrpc": "2.0",
+                "id": null,
+                "error": {
+                    "code": -32600,
+                    "message": err,
+                },
+            });
+            Ok(hyper::Response::builder()
+                .status(404)
+                .header("Content-Type", "application/json")
+                .body(Full::new(Bytes::from(body.to_string())).boxed())
+                .unwrap())
+        }
+    }
+}
+
+// Something two chains would both open, they'd fight over the sled lock or the port.
+//
+// `main` is the config we started with, its `address` is the one every chain is served at.
+fn shared_resource(main: &Settings, chains: &[(&str, &Settings)]) -> Option<String> {
+    let mut db_paths: Vec<(PathBuf, &str)> = Vec::new();
+    let mut addresses: Vec<(SocketAddr, String)> = vec![(main.address, "main".to_string())];
+
+    for (name, settings) in std::iter::once(&("main", main)).chain(chains) {
+        let db_path = settings.sled_config.get_path();
+        let db_path = fs::canonicalize(&db_path).unwrap_or(db_path);
+        if let Some((_, other)) = db_paths.iter().find(|(path, _)| *path == db_path) {
+            return Some(format!(
+                "Chains {} and {} use the same db_path {}",
+                other,
+                name,
+                db_path.display()
+            ));
+        }
+        db_paths.push((db_path, name));
+
+        let listeners = [
+            (settings.admin.enabled, settings.admin.address, "admin"),
+            (
+                settings.websocket.enabled,
+                settings.websocket.address,
+                "websocket",
+            ),
+            (
+                settings.prometheus.enabled,
+                settings.prometheus.address,
+                "prometheus",
+            ),
+        ];
+        for (_, address, listener) in listeners.into_iter().filter(|listener| listener.0) {
+            let listener = format!("{} {}", name, listener);
+            if let Some((_, other)) = addresses.iter().find(|(other, _)| *other == address) {
+                return Some(format!(
+                    "The {} and {} listeners are both at {}",
+                    other, listener, address
+                ));
+            }
+            addresses.push((address, listener));
+        }
+    }
+
+    None
+}
+
+// Make sure `chains` don't share a DB or listener with each other or `main`
+pub fn check_chains(main: &Settings, chains: &[(&str, &Settings)]) {
+    if let Some(shared) = shared_resource(main, chains) {
+        panic!("\x1b[31mErr:\x1b[0m {}!", shared);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn routes() -> Vec<ChainRoute> {
+        ["op", "base"]
+            .into_iter()
+            .map(|name| {
+                ChainRoute {
+                    name: name.to_string(),
+                    path: format!("/{}", name),
+                    config: PathBuf::from(format!("{}.toml", name)),
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_select_path() {
+        let routes = routes();
+        assert_eq!(select(&routes, "/", None), Ok(None));
+        assert_eq!(select(&routes, "/op", None), Ok(Some((0, "/".to_string()))));
+        assert_eq!(
+            select(&routes, "/base/events", None),
+            Ok(Some((1, "/events".to_string())))
+        );
+        // Only whole segments count
+        assert_eq!(select(&routes, "/optimism", None), Ok(None));
+        assert_eq!(select(&routes, "/events", None), Ok(None));
+    }
+
+    #[test]
+    fn test_select_header() {
+        let routes = routes();
+        assert_eq!(
+            select(&routes, "/", Some("base")),
+            Ok(Some((1, "/".to_string())))
+        );
+        assert_eq!(
+            select(&routes, "/base/ready", Some("base")),
+            Ok(Some((1, "/ready".to_string())))
+        );
+        // The header wins over the path
+        assert_eq!(
+            select(&routes, "/op/ready", Some("base")),
+            Ok(Some((1, "/op/ready".to_string())))
+        );
+        assert_eq!(
+            select(&routes, "/", Some("eth")),
+            Err("Unknown chain: eth".to_string())
+        );
+    }
+
+    fn settings(db_path: &str) -> Settings {
+        let mut settings = Settings {
+            sled_config: sled::Config::new().path(db_path),
+            ..Settings::default()
+        };
+        settings.admin.enabled = false;
+        settings.websocket.enabled = false;
+        settings.prometheus.enabled = false;
+        settings
+    }
+
+    #[test]
+    fn test_shared_resource() {
+        let main = settings("main-db");
+        let op = settings("op-db");
+        assert_eq!(shared_resource(&main, &[("op", &op)]), None);
+
+        let shared_db = settings("main-db");
+        assert_eq!(
+            shared_resource(&main, &[("op", &shared_db)]),
+            Some("Chains main and op use the same db_path main-db".to_string())
+        );
+
+        // Listeners only count if they're enabled
+        let mut admin = settings("admin-db");
+        admin.admin.address = main.address;
+        assert_eq!(shared_resource(&main, &[("op", &admin)]), None);
+        admin.admin.enabled = true;
+        assert_eq!(
+            shared_resource(&main, &[("op", &admin)]),
+            Some(format!(
+                "The main and op admin listeners are both at {}",
+                main.address
+            ))
+        );
+
+        let mut op = settings("op-db");
+        op.prometheus.enabled = true;
+        let mut base = settings("base-db");
+        base.prometheus.enabled = true;
+        assert!(shared_resource(&main, &[("op", &op), ("base", &base)]).is_some());
+        base.prometheus.address = "127.0.0.1:4003".parse().unwrap();
+        assert_eq!(
+            shared_resource(&main, &[("op", &op), ("base", &base)]),
+            None
+        );
+    }
+}
@@ -0,0 +1,162 @@
+// Classify methods so we can treat them differently when routing and caching
+use crate::rpc::types::Protocol;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MethodClass {
+    // Regular methods, routed by the selection algo
+    Standard,
+    // Simulation and tracing methods that need archive state and are expensive
+    // to execute. Routed to archive RPCs and concurrency limited.
+    Heavy,
+    // Methods that need the node to hold keys. Rejected unless we have a wallet RPC.
+    Wallet,
+}
+
+const HEAVY_METHODS: [&str; 3] = ["eth_simulateV1", "eth_callMany", "debug_traceCall"];
+
+const WALLET_METHODS: [&str; 9] = [
+    "eth_accounts",
+    "eth_requestAccounts",
+    "eth_sign",
+    "eth_signTransaction",
+    "eth_sendTransaction",
+    "eth_signTypedData",
+    "eth_signTypedData_v3",
+    "eth_signTypedData_v4",
+    "personal_sign",
+];
+
+pub fn classify(method: &str) -> MethodClass {
+    // `arbtrace_*` is served by classic arbitrum archive nodes
+    if HEAVY_METHODS.contains(&method) || method.starts_with("arbtrace_") {
+        return MethodClass::Heavy;
+    }
+    if WALLET_METHODS.contains(&method) {
+        return MethodClass::Wallet;
+    }
+
+    MethodClass::Standard
+}
+
+// Why we reject a wallet method, and what to do instead
+pub fn wallet_method_reason(method: &str) -> String {
+    format!(
+        "{} needs an account on the node, but blutgang only reads and broadcasts. \
+         Sign transactions locally and send them with eth_sendRawTransaction.",
+        method
+    )
+}
+
+// Rollup specific method namespaces
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum L2Family {
+    Optimism,
+    Arbitrum,
+    ZkSync,
+    Starknet,
+}
+
+const L2_NAMESPACES: [(&str, L2Family); 4] = [
+    ("optimism_", L2Family::Optimism),
+    ("arbtrace_", L2Family::Arbitrum),
+    ("zks_", L2Family::ZkSync),
+    ("starknet_", L2Family::Starknet),
+];
+
+// Return the L2 family a method belongs to, None for regular methods
+pub fn l2_family(method: &str) -> Option<L2Family> {
+    L2_NAMESPACES
+        .iter()
+        .find(|(namespace, _)| method.starts_with(namespace))
+        .map(|(_, family)| *family)
+}
+
+// Chain blutgang is in front of. Decides which L2 methods are allowed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Chain {
+    Ethereum,
+    Optimism,
+    Arbitrum,
+    ZkSync,
+    Starknet,
+    Solana,
+    Bitcoin,
+}
+
+impl Chain {
+    pub fn from_config(chain: &str) -> Option<Self> {
+        match chain {
+            "ethereum" => Some(Chain::Ethereum),
+            "optimism" => Some(Chain::Optimism),
+            "arbitrum" => Some(Chain::Arbitrum),
+            "zksync" => Some(Chain::ZkSync),
+            "starknet" => Some(Chain::Starknet),
+            "solana" => Some(Chain::Solana),
+            "bitcoin" => Some(Chain::Bitcoin),
+            _ => None,
+        }
+    }
+
+    // JSON-RPC dialect the RPCs of this chain speak
+    pub fn protocol(&self) -> Protocol {
+        match self {
+            Chain::Solana => Protocol::Solana,
+            Chain::Bitcoin => Protocol::Bitcoin,
+            _ => Protocol::Evm,
+        }
+    }
+
+    // Return true if methods of `family` exist on this chain
+    pub fn allows(&self, family: L2Family) -> bool {
+        matches!(
+            (self, family),
+            (Chain::Optimism, L2Family::Optimism)
+                | (Chain::Arbitrum, L2Family::Arbitrum)
+                | (Chain::ZkSync, L2Family::ZkSync)
+                | (Chain::Starknet, L2Family::Starknet)
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_classify() {
+        assert_eq!(classify("eth_simulateV1"), MethodClass::Heavy);
+        assert_eq!(classify("eth_callMany"), MethodClass::Heavy);
+        assert_eq!(classify("debug_traceCall"), MethodClass::Heavy);
+        assert_eq!(classify("arbtrace_block"), MethodClass::Heavy);
+        assert_eq!(classify("eth_call"), MethodClass::Standard);
+        assert_eq!(classify("debug_traceCallMany"), MethodClass::Standard);
+        assert_eq!(classify("eth_sendTransaction"), MethodClass::Wallet);
+        assert_eq!(classify("eth_accounts"), MethodClass::Wallet);
+        assert_eq!(classify("eth_sendRawTransaction"), MethodClass::Standard);
+    }
+
+    #[test]
+    fn test_l2_family() {
+        assert_eq!(
+            l2_family("optimism_outputAtBlock"),
+            Some(L2Family::Optimism)
+        );
+        assert_eq!(l2_family("arbtrace_call"), Some(L2Family::Arbitrum));
+        assert_eq!(l2_family("zks_getBlockDetails"), Some(L2Family::ZkSync));
+        assert_eq!(l2_family("starknet_call"), Some(L2Family::Starknet));
+        assert_eq!(l2_family("eth_call"), None);
+    }
+
+    #[test]
+    fn test_chain_allows() {
+        assert!(Chain::Optimism.allows(L2Family::Optimism));
+        assert!(!Chain::Optimism.allows(L2Family::ZkSync));
+        assert!(!Chain::Ethereum.allows(L2Family::Arbitrum));
+        assert_eq!(Chain::from_config("starknet"), Some(Chain::Starknet));
+        assert_eq!(Chain::from_config("solana"), Some(Chain::Solana));
+        assert!(!Chain::Solana.allows(L2Family::Optimism));
+        assert_eq!(Chain::from_config("bitcoin"), Some(Chain::Bitcoin));
+        assert_eq!(Chain::Bitcoin.protocol(), Protocol::Bitcoin);
+        assert_eq!(Chain::from_config("dogechain"), None);
+    }
+}
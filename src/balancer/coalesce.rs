@@ -0,0 +1,210 @@
+// Coalescing of identical requests for the head.
+//
+// Bots polling `eth_blockNumber` or `eth_call` at `latest` tend to do it all at once,
+// right after every block. The answer is the same for all of them, so the first request
+// goes upstream and every identical one arriving within `window` of it gets its response.
+// Requests are keyed on the head we tracked when they came in too, so once we see a new
+// block nobody gets an answer to a request made before it.
+//
+// With single flight on, the same goes for any request that's safe to send twice, but
+// only while the call answering it is in flight. Ones that come in after it's done go
+// through the cache like usual.
+use crate::balancer::{
+    format::set_cached_id,
+    hedge::can_hedge,
+};
+
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{
+        Duration,
+        Instant,
+    },
+};
+
+use hyper::body::Bytes;
+use serde_json::Value;
+use tokio::sync::watch;
+
+// If `tx` asks about the head, by tagging a param with `latest` or asking for its number
+pub fn is_head_tagged(tx: &Value) -> bool {
+    tx["method"] == "eth_blockNumber"
+        || tx["params"]
+            .as_array()
+            .is_some_and(|params| params.iter().any(|param| param == "latest"))
+}
+
+#[derive(Debug)]
+struct Call {
+    started: Instant,
+    // None until the first request of the window gets its response
+    rx: watch::Receiver<Option<Bytes>>,
+}
+
+impl Call {
+    // Waiting on a response that hasn't come yet, and won't fail without us noticing
+    fn in_flight(&self) -> bool {
+        self.rx.has_changed().is_ok() && self.rx.borrow().is_none()
+    }
+}
+
+pub enum Joined {
+    // First request of its window, has to share its response with `Lead::finish`
+    Lead(Lead),
+    // Response to an identical request, with the id set to null
+    Shared(Bytes),
+    // The request we waited for failed, so we're on our own
+    Alone,
+}
+
+pub struct Lead {
+    tx: watch::Sender<Option<Bytes>>,
+}
+
+impl Lead {
+    pub fn finish(self, rx: &[u8]) {
+        let _ = self.tx.send(Some(set_cached_id(rx, &Value::Null)));
+    }
+}
+
+#[derive(Debug)]
+pub struct Coalescer {
+    window: Duration,
+    single_flight: bool,
+    // Request hash and head -> the call answering it
+    calls: Mutex<HashMap<(String, u64), Call>>,
+}
+
+impl Coalescer {
+    pub fn new(window: Duration, single_flight: bool) -> Self {
+        Self {
+            window,
+            single_flight,
+            calls: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // If identical requests to `tx` should share a call
+    pub fn coalesces(&self, tx: &Value) -> bool {
+        (!self.window.is_zero() && is_head_tagged(tx))
+            || (self.single_flight && can_hedge(tx["method"].as_str().unwrap_or_default()))
+    }
+
+    // Join the call for the request hashed to `key` at the `head`, or start one
+    pub async fn join(&self, key: String, head: u64) -> Joined {
+        let mut rx = {
+            let mut calls = self.calls.lock().unwrap();
+            calls.retain(|_, call| call.started.elapsed() < self.window || call.in_flight());
+
+            match calls.get(&(key.clone(), head)) {
+                Some(call) => call.rx.clone(),
+                None => {
+                    let (tx, rx) = watch::channel(None);
+                    let call = Call {
+                        started: Instant::now(),
+                        rx,
+                    };
+                    calls.insert((key, head), call);
+                    return Joined::Lead(Lead { tx });
+                }
+            }
+        };
+
+        // The sender is gone without a response if the request it made failed
+        let shared = match rx.wait_for(|rx| rx.is_some()).await {
+            Ok(rx) => rx.clone(),
+            Err(_) => None,
+        };
+        match shared {
+            Some(rx) => Joined::Shared(rx),
+            None => Joined::Alone,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_is_head_tagged() {
+        assert!(is_head_tagged(
+            &json!({"method": "eth_blockNumber", "params": []})
+        ));
+        assert!(is_head_tagged(&json!({
+            "method": "eth_getBalance",
+            "params": ["0x0000000000000000000000000000000000000000", "latest"],
+        })));
+        assert!(!is_head_tagged(&json!({
+            "method": "eth_getBlockByNumber",
+            "params": ["0x1", false],
+        })));
+    }
+
+    #[tokio::test]
+    async fn test_join() {
+        let coalescer = Coalescer::new(Duration::from_secs(60), false);
+        let lead = match coalescer.join("a".to_string(), 1).await {
+            Joined::Lead(lead) => lead,
+            _ => panic!("First request should lead"),
+        };
+        // A new head doesn't share with the old one
+        assert!(matches!(
+            coalescer.join("a".to_string(), 2).await,
+            Joined::Lead(_)
+        ));
+
+        let follower = coalescer.join("a".to_string(), 1);
+        lead.finish(br#"{"id":7,"jsonrpc":"2.0","result":"0x1"}"#);
+        match follower.await {
+            Joined::Shared(rx) => {
+                assert_eq!(&rx[..], br#"{"id":null,"jsonrpc":"2.0","result":"0x1"}"#)
+            }
+            _ => panic!("Should share the response"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_join_failed() {
+        let coalescer = Coalescer::new(Duration::from_secs(60), false);
+        let lead = coalescer.join("a".to_string(), 1).await;
+        drop(lead);
+        assert!(matches!(
+            coalescer.join("a".to_string(), 1).await,
+            Joined::Alone
+        ));
+
+        // Entries are gone once the window is over and they got their response
+        let coalescer = Coalescer::new(Duration::ZERO, true);
+        match coalescer.join("a".to_string(), 1).await {
+            Joined::Lead(lead) => lead.finish(br#"{"id":1,"jsonrpc":"2.0","result":"0x1"}"#),
+            _ => panic!("First request should lead"),
+        }
+        assert!(matches!(
+            coalescer.join("a".to_string(), 1).await,
+            Joined::Lead(_)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_single_flight() {
+        let coalescer = Coalescer::new(Duration::ZERO, true);
+        assert!(coalescer.coalesces(&json!({"method": "eth_getBalance", "params": []})));
+        assert!(!coalescer.coalesces(&json!({"method": "eth_newFilter", "params": []})));
+        assert!(!Coalescer::new(Duration::ZERO, false)
+            .coalesces(&json!({"method": "eth_blockNumber", "params": []})));
+
+        // Calls in flight are shared past the window
+        let lead = match coalescer.join("a".to_string(), 1).await {
+            Joined::Lead(lead) => lead,
+            _ => panic!("First request should lead"),
+        };
+        let (follower, _) = tokio::join!(coalescer.join("a".to_string(), 1), async move {
+            tokio::task::yield_now().await;
+            lead.finish(br#"{"id":7,"jsonrpc":"2.0","result":"0x2"}"#);
+        });
+        assert!(matches!(follower, Joined::Shared(_)));
+    }
+}
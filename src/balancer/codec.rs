@@ -0,0 +1,703 @@
+// Cached responses can be stored zstd compressed.
+//
+// Compressed entries are recognized by the zstd magic number, while plain entries are
+// JSON and always start with `{`. This way compression can be turned on or off without
+// having to clear the cache.
+//
+// Small responses like receipts don't compress well on their own, so we can also
+// train a dictionary on the responses we cache and compress with that instead.
+// Frames record the id of the dictionary they were compressed with, and every
+// dictionary we trained is kept in its own tree so older entries stay readable
+// after the dictionary gets rebuilt.
+//
+// Everything that goes in or out of the cache should go through `get` and `insert`,
+// which also take care of deduplicating identical responses.
+use crate::{
+    balancer::{
+        admission::TinyLfu,
+        dedup::{
+            reference_hash,
+            BlobStore,
+        },
+    },
+    config::cache_setup::is_setup_key,
+    metrics::prometheus::CacheStats,
+};
+
+use std::{
+    borrow::Cow,
+    fmt,
+    sync::{
+        Arc,
+        Mutex,
+        RwLock,
+    },
+    time::{
+        Duration,
+        SystemTime,
+        UNIX_EPOCH,
+    },
+};
+
+use sled::{
+    Db,
+    IVec,
+    Tree,
+};
+use tokio::time::sleep;
+use zstd::zstd_safe::{
+    self,
+    CCtx,
+    CDict,
+    DCtx,
+    DDict,
+};
+
+// Name of the sled tree we keep trained dictionaries in, keyed by when they were trained.
+//
+// Separate from the cache so clearing it doesn't make us start over.
+pub const DICTIONARY_TREE: &str = "blutgang_dictionaries";
+
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+// Not worth compressing anything smaller than this
+const MIN_COMPRESS_SIZE: usize = 128;
+// With a dictionary even tiny responses get smaller
+const MIN_DICT_COMPRESS_SIZE: usize = 32;
+
+// Responses we keep around to train on. Once we have enough we only replace
+// every `SAMPLE_EVERY`th one, so we don't copy every response we cache.
+const MAX_SAMPLES: usize = 4096;
+const MIN_SAMPLES: usize = 256;
+const MAX_SAMPLE_SIZE: usize = 16 * 1024;
+const SAMPLE_EVERY: usize = 8;
+
+const DICTIONARY_SIZE: usize = 64 * 1024;
+// Entries compressed with dictionaries older than this many rebuilds become cache misses
+const MAX_DICTIONARIES: usize = 16;
+
+// How often we check the cache is under `cache_max_size`
+pub const CACHE_SIZE_INTERVAL: Duration = Duration::from_secs(30);
+
+// Refuse to decompress frames claiming to be bigger than this.
+// Also covers the unknown and error sizes zstd reports.
+const MAX_DECODED_SIZE: u64 = 1 << 30;
+
+struct Dictionary {
+    id: u32,
+    cdict: CDict<'static>,
+}
+
+#[derive(Default)]
+struct Samples {
+    samples: Vec<Vec<u8>>,
+    seen: usize,
+}
+
+#[derive(Default)]
+pub struct CacheCodec {
+    // zstd compression level, 0 disables compression
+    level: i32,
+    // Collect samples of what we cache to train dictionaries on
+    training: bool,
+    // Dictionary new entries get compressed with
+    current: RwLock<Option<Arc<Dictionary>>>,
+    // Every dictionary we can decode with, by id.
+    // Ordered oldest first so we know which ones to drop.
+    decoders: RwLock<Vec<(u32, Arc<DDict<'static>>)>>,
+    samples: Mutex<Samples>,
+    // Blobs deduplicated entries point to. Entries pointing to blobs
+    // are cache misses without it.
+    blobs: Option<BlobStore>,
+    // Store new entries as blobs
+    dedup: bool,
+    // Keeps the cache bounded, unbounded if None
+    admission: Option<Mutex<TinyLfu>>,
+    // Hits and misses of requests, recorded by whoever serves them
+    stats: Arc<CacheStats>,
+}
+
+impl fmt::Debug for CacheCodec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CacheCodec")
+            .field("level", &self.level)
+            .field("training", &self.training)
+            .field("dedup", &self.dedup)
+            .field(
+                "entries",
+                &self
+                    .admission
+                    .as_ref()
+                    .map(|admission| admission.lock().unwrap().len()),
+            )
+            .field(
+                "dictionary",
+                &self.current.read().unwrap().as_ref().map(|dict| dict.id),
+            )
+            .finish()
+    }
+}
+
+impl CacheCodec {
+    pub fn new(level: i32, training: bool) -> Self {
+        Self {
+            level,
+            training: training && level > 0,
+            ..Default::default()
+        }
+    }
+
+    // Keep deduplicated responses in `blobs`. New entries are only deduplicated if `dedup` is set.
+    pub fn with_blobs(mut self, blobs: BlobStore, dedup: bool) -> Self {
+        self.blobs = Some(blobs);
+        self.dedup = dedup;
+        self
+    }
+
+    // Bound the cache, evicting entries as `admission` decides
+    pub fn with_admission(mut self, admission: TinyLfu) -> Self {
+        self.admission = Some(Mutex::new(admission));
+        self
+    }
+
+    pub fn stats(&self) -> &Arc<CacheStats> {
+        &self.stats
+    }
+
+    // Start tracking entries cached before we started, evicting what doesn't fit.
+    // Returns how many entries were evicted.
+    pub fn track_cached(&self, cache: &Db) -> Result<usize, sled::Error> {
+        if self.admission.is_none() {
+            return Ok(0);
+        }
+
+        let mut evicted = 0;
+        for key in cache.iter().keys() {
+            let key = key?;
+            if !is_setup_key(&key) {
+                evicted += self.admit(cache, &key)?;
+            }
+        }
+
+        Ok(evicted)
+    }
+
+    // Track a newly cached `key` and evict whatever doesn't fit anymore
+    fn admit(&self, cache: &Db, key: &[u8]) -> Result<usize, sled::Error> {
+        let evicted = match &self.admission {
+            Some(admission) => admission.lock().unwrap().insert(key),
+            None => return Ok(0),
+        };
+
+        for key in &evicted {
+            self.evict(cache, key)?;
+        }
+
+        Ok(evicted.len())
+    }
+
+    // Remove the entry under `key`, returning how many bytes it took
+    fn evict(&self, cache: &Db, key: &[u8]) -> Result<usize, sled::Error> {
+        let removed = match cache.remove(key)? {
+            Some(removed) => removed,
+            None => return Ok(0),
+        };
+        if let Some(blobs) = &self.blobs {
+            blobs.release(&removed)?;
+        }
+
+        let bytes = key.len() + removed.len();
+        self.stats.record_eviction(bytes);
+        Ok(bytes)
+    }
+
+    // Evict entries until what's cached is below `max_bytes`, the ones the admission
+    // policy cares about least first, or in key order if the cache isn't bounded by
+    // entries. Returns how big the cache was, and how many bytes we evicted.
+    //
+    // Only what's in the cache itself counts, not the blobs deduplicated entries point to.
+    pub fn shrink(&self, cache: &Db, max_bytes: u64) -> Result<(u64, u64), sled::Error> {
+        let mut size = 0;
+        for entry in cache.iter() {
+            let (key, value) = entry?;
+            if !is_setup_key(&key) {
+                size += (key.len() + value.len()) as u64;
+            }
+        }
+        self.stats.set_size(size);
+        if size <= max_bytes {
+            return Ok((size, 0));
+        }
+
+        // Leave some room, so we don't have to evict again on the next insert
+        let target = max_bytes - max_bytes / 10;
+        let mut freed = 0;
+        let mut keys = cache.iter().keys();
+        while size - freed > target {
+            let coldest = match &self.admission {
+                Some(admission) => admission.lock().unwrap().evict(),
+                None => None,
+            };
+            let key = match coldest {
+                Some(key) => key,
+                None => {
+                    match keys.next() {
+                        Some(key) => key?.to_vec(),
+                        None => break,
+                    }
+                }
+            };
+            if !is_setup_key(&key) {
+                freed += self.evict(cache, &key)? as u64;
+            }
+        }
+
+        self.stats.set_size(size - freed);
+        Ok((size, freed))
+    }
+
+    // Get the response cached under `key`. Corrupted entries are treated as missing.
+    pub fn get(&self, cache: &Db, key: &[u8]) -> Result<Option<IVec>, sled::Error> {
+        // Misses count too, entries that keep getting requested are worth admitting
+        if let Some(admission) = &self.admission {
+            admission.lock().unwrap().touch(key);
+        }
+
+        let cached = match cache.get(key)? {
+            Some(cached) => cached,
+            None => return Ok(None),
+        };
+
+        let (cached, hash) = match reference_hash(&cached) {
+            Some(hash) => {
+                let blobs = match &self.blobs {
+                    Some(blobs) => blobs,
+                    None => return Ok(None),
+                };
+                match blobs.resolve(hash)? {
+                    Some(blob) => (blob, Some(IVec::from(hash))),
+                    None => return Ok(None),
+                }
+            }
+            None => (cached, None),
+        };
+
+        let decoded = self.decode(&cached).map(|decoded| {
+            match decoded {
+                Cow::Owned(decoded) => Some(decoded),
+                Cow::Borrowed(_) => None,
+            }
+        });
+
+        match (decoded, hash, &self.blobs) {
+            (Some(Some(decoded)), _, _) => Ok(Some(IVec::from(decoded))),
+            (Some(None), _, _) => Ok(Some(cached)),
+            // Blobs compressed with a dictionary we dropped would never be readable again
+            (None, Some(hash), Some(blobs)) => {
+                blobs.forget(&hash)?;
+                Ok(None)
+            }
+            (None, _, _) => Ok(None),
+        }
+    }
+
+    // Encode `rx` and cache it under `key`
+    pub fn insert(&self, cache: &Db, key: &[u8], rx: &[u8]) -> Result<(), sled::Error> {
+        let encoded = self.encode(rx);
+        let replaced = match (&self.blobs, self.dedup) {
+            (Some(blobs), true) => cache.insert(key, &*blobs.store(rx, encoded)?)?,
+            _ => cache.insert(key, &*encoded)?,
+        };
+
+        if let (Some(blobs), Some(replaced)) = (&self.blobs, replaced) {
+            blobs.release(&replaced)?;
+        }
+        self.admit(cache, key)?;
+
+        Ok(())
+    }
+
+    // Encode a response before it goes into the cache
+    pub fn encode<'a>(&self, rx: &'a [u8]) -> Cow<'a, [u8]> {
+        if self.level == 0 {
+            return Cow::Borrowed(rx);
+        }
+        self.sample(rx);
+
+        let compressed = match self.current.read().unwrap().clone() {
+            Some(dict) if rx.len() >= MIN_DICT_COMPRESS_SIZE => {
+                let mut compressed = Vec::with_capacity(zstd_safe::compress_bound(rx.len()));
+                CCtx::create()
+                    .compress_using_cdict(&mut compressed, rx, &dict.cdict)
+                    .ok()
+                    .map(|_| compressed)
+            }
+            None if rx.len() >= MIN_COMPRESS_SIZE => zstd::block::compress(rx, self.level).ok(),
+            _ => None,
+        };
+
+        match compressed {
+            Some(compressed) if compressed.len() < rx.len() => Cow::Owned(compressed),
+            _ => Cow::Borrowed(rx),
+        }
+    }
+
+    // Decode a cached response. Returns None if it's corrupted,
+    // or was compressed with a dictionary we no longer have.
+    pub fn decode<'a>(&self, cached: &'a [u8]) -> Option<Cow<'a, [u8]>> {
+        if !cached.starts_with(&ZSTD_MAGIC) {
+            return Some(Cow::Borrowed(cached));
+        }
+
+        let id = zstd_safe::get_dict_id_from_frame(cached);
+        if id == 0 {
+            return zstd::stream::decode_all(cached).ok().map(Cow::Owned);
+        }
+
+        let ddict = self
+            .decoders
+            .read()
+            .unwrap()
+            .iter()
+            .find(|(dict_id, _)| *dict_id == id)
+            .map(|(_, ddict)| Arc::clone(ddict))?;
+        let size = zstd_safe::get_frame_content_size(cached);
+        if size > MAX_DECODED_SIZE {
+            return None;
+        }
+
+        let mut decoded = Vec::with_capacity(size as usize);
+        DCtx::create()
+            .decompress_using_ddict(&mut decoded, cached, &ddict)
+            .ok()?;
+
+        Some(Cow::Owned(decoded))
+    }
+
+    fn sample(&self, rx: &[u8]) {
+        if !self.training || rx.len() > MAX_SAMPLE_SIZE {
+            return;
+        }
+
+        let mut samples = self.samples.lock().unwrap_or_else(|e| e.into_inner());
+        let seen = samples.seen;
+        samples.seen = seen.wrapping_add(1);
+
+        if samples.samples.len() < MAX_SAMPLES {
+            samples.samples.push(rx.to_vec());
+        } else if seen % SAMPLE_EVERY == 0 {
+            samples.samples[(seen / SAMPLE_EVERY) % MAX_SAMPLES] = rx.to_vec();
+        }
+    }
+
+    // Train a dictionary on the samples we collected.
+    //
+    // Returns None if we don't have enough of them yet. This is slow, don't call it
+    // from async code directly.
+    fn train(&self) -> Option<Vec<u8>> {
+        let samples = {
+            let samples = self.samples.lock().unwrap_or_else(|e| e.into_inner());
+            if samples.samples.len() < MIN_SAMPLES {
+                return None;
+            }
+            samples.samples.clone()
+        };
+
+        match zstd::dict::from_samples(&samples, DICTIONARY_SIZE) {
+            Ok(dictionary) => Some(dictionary),
+            Err(err) => {
+                println!(
+                    "\x1b[93mWrn:\x1b[0m Could not train cache compression dictionary: {}",
+                    err
+                );
+                None
+            }
+        }
+    }
+
+    // Start compressing new entries with `dictionary`. Returns its id.
+    fn add_dictionary(&self, dictionary: &[u8]) -> Option<u32> {
+        let id = zstd_safe::get_dict_id_from_dict(dictionary);
+        if id == 0 {
+            return None;
+        }
+
+        {
+            let mut decoders = self.decoders.write().unwrap();
+            decoders.retain(|(dict_id, _)| *dict_id != id);
+            decoders.push((id, Arc::new(DDict::create(dictionary))));
+            if decoders.len() > MAX_DICTIONARIES {
+                let excess = decoders.len() - MAX_DICTIONARIES;
+                decoders.drain(..excess);
+            }
+        }
+
+        let cdict = zstd_safe::create_cdict(dictionary, self.level);
+        *self.current.write().unwrap() = Some(Arc::new(Dictionary { id, cdict }));
+
+        Some(id)
+    }
+
+    // Load the dictionaries we trained before, the newest one is used for new entries
+    pub fn load_dictionaries(&self, tree: &Tree) -> Result<(), sled::Error> {
+        let mut loaded = 0;
+        for entry in tree.iter() {
+            let (_, dictionary) = entry?;
+            if self.add_dictionary(&dictionary).is_some() {
+                loaded += 1;
+            }
+        }
+
+        if loaded > 0 {
+            println!(
+                "\x1b[35mInfo:\x1b[0m Loaded {} cache compression dictionaries",
+                loaded
+            );
+        }
+
+        Ok(())
+    }
+}
+
+// Persist `dictionary` and drop the ones we no longer keep
+fn save_dictionary(tree: &Tree, dictionary: &[u8]) -> Result<(), sled::Error> {
+    let trained_at = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64;
+    tree.insert(trained_at.to_be_bytes(), dictionary)?;
+
+    while tree.len() > MAX_DICTIONARIES {
+        tree.pop_min()?;
+    }
+    tree.flush()?;
+
+    Ok(())
+}
+
+// Keep the cache under `max_bytes`, checking every `interval`
+pub async fn bound_cache_size(
+    codec: Arc<CacheCodec>,
+    cache: Arc<Db>,
+    max_bytes: u64,
+    interval: Duration,
+) -> Result<(), sled::Error> {
+    loop {
+        let shrinker = Arc::clone(&codec);
+        let db = Arc::clone(&cache);
+        let shrunk = tokio::task::spawn_blocking(move || shrinker.shrink(&db, max_bytes)).await;
+        match shrunk {
+            Ok(Ok((_, 0))) => {}
+            Ok(Ok((size, freed))) => {
+                println!(
+                    "\x1b[35mInfo:\x1b[0m Evicted {} of {} cached bytes over cache_max_size",
+                    freed, size
+                );
+            }
+            Ok(Err(err)) => return Err(err),
+            Err(_) => {}
+        }
+
+        sleep(interval).await;
+    }
+}
+
+// Periodically retrain the dictionary on what we've been caching lately
+pub async fn rebuild_dictionaries(
+    codec: Arc<CacheCodec>,
+    tree: Tree,
+    interval: Duration,
+) -> Result<(), sled::Error> {
+    loop {
+        sleep(interval).await;
+
+        let trainer = Arc::clone(&codec);
+        let dictionary = match tokio::task::spawn_blocking(move || trainer.train()).await {
+            Ok(Some(dictionary)) => dictionary,
+            _ => continue,
+        };
+
+        // Saved first, so nothing gets compressed with a dictionary we lose on restart
+        save_dictionary(&tree, &dictionary)?;
+        if let Some(id) = codec.add_dictionary(&dictionary) {
+            println!(
+                "\x1b[35mInfo:\x1b[0m Trained new cache compression dictionary {}",
+                id
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::balancer::dedup::BLOB_TREE;
+
+    fn response() -> Vec<u8> {
+        let transactions: Vec<String> = (0..64).map(|i| format!("\"0x{:064x}\"", i)).collect();
+        format!(
+            r#"{{"id":null,"jsonrpc":"2.0","result":{{"transactions":[{}]}}}}"#,
+            transactions.join(",")
+        )
+        .into_bytes()
+    }
+
+    // Something like a receipt, small enough that it barely compresses on its own
+    fn receipt(i: usize) -> Vec<u8> {
+        format!(
+            r#"{{"id":null,"jsonrpc":"2.0","result":{{"blockHash":"0x{:064x}","blockNumber":"{:#x}","contractAddress":null,"cumulativeGasUsed":"{:#x}","effectiveGasPrice":"{:#x}","from":"0x{:040x}","gasUsed":"0x5208","logs":[],"logsBloom":"0x{:0512}","status":"0x1","to":"0x{:040x}","transactionHash":"0x{:064x}","transactionIndex":"{:#x}","type":"0x2"}}}}"#,
+            i * 7919,
+            i,
+            i * 21000,
+            i * 1_000_003,
+            i * 31,
+            0,
+            i * 17,
+            i * 104_729,
+            i % 200,
+        )
+        .into_bytes()
+    }
+
+    fn trained_codec() -> (CacheCodec, Vec<u8>) {
+        let codec = CacheCodec::new(3, true);
+        for i in 0..MIN_SAMPLES * 2 {
+            codec.encode(&receipt(i));
+        }
+        let dictionary = codec.train().unwrap();
+        codec.add_dictionary(&dictionary).unwrap();
+
+        (codec, dictionary)
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let codec = CacheCodec::new(3, false);
+        let rx = response();
+
+        let encoded = codec.encode(&rx);
+        assert!(encoded.len() < rx.len());
+        assert!(encoded.starts_with(&ZSTD_MAGIC));
+        assert_eq!(codec.decode(&encoded).unwrap(), rx.as_slice());
+    }
+
+    #[test]
+    fn test_plain_entries() {
+        // Small responses and disabled compression are stored as they are
+        let small = br#"{"id":null,"jsonrpc":"2.0","result":"0x1"}"#;
+        assert_eq!(CacheCodec::new(3, false).encode(small), small.as_slice());
+        let rx = response();
+        assert_eq!(CacheCodec::new(0, false).encode(&rx), rx.as_slice());
+
+        // Entries cached before compression was turned on are still readable
+        assert_eq!(
+            CacheCodec::new(3, false).decode(&rx).unwrap(),
+            rx.as_slice()
+        );
+
+        // Corrupted entries are treated as missing
+        let mut corrupted = CacheCodec::new(3, false).encode(&rx).into_owned();
+        corrupted.truncate(16);
+        assert_eq!(CacheCodec::new(3, false).decode(&corrupted), None);
+    }
+
+    #[test]
+    fn test_dictionary() {
+        let (codec, _) = trained_codec();
+        let rx = receipt(MIN_SAMPLES * 3);
+
+        let plain = CacheCodec::new(3, false).encode(&rx).len();
+        let encoded = codec.encode(&rx);
+        assert!(encoded.len() * 2 < plain);
+        assert_eq!(codec.decode(&encoded).unwrap(), rx.as_slice());
+
+        // Entries compressed without a dictionary are still readable
+        let rx = response();
+        let encoded = CacheCodec::new(3, false).encode(&rx).into_owned();
+        assert_eq!(codec.decode(&encoded).unwrap(), rx.as_slice());
+
+        // We can't decode entries compressed with a dictionary we don't have
+        let encoded = codec.encode(&receipt(1)).into_owned();
+        assert_eq!(CacheCodec::new(3, false).decode(&encoded), None);
+    }
+
+    #[test]
+    fn test_dedup() {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let blobs = BlobStore::new(db.open_tree(BLOB_TREE).unwrap());
+        let codec = CacheCodec::new(3, false).with_blobs(blobs, true);
+        let rx = response();
+
+        codec.insert(&db, b"first", &rx).unwrap();
+        codec.insert(&db, b"second", &rx).unwrap();
+        assert_eq!(db.get(b"first").unwrap(), db.get(b"second").unwrap());
+        assert_eq!(codec.get(&db, b"first").unwrap().unwrap(), rx.as_slice());
+        assert_eq!(codec.get(&db, b"second").unwrap().unwrap(), rx.as_slice());
+
+        // References are misses without the blobs
+        assert_eq!(CacheCodec::new(3, false).get(&db, b"first").unwrap(), None);
+        assert_eq!(codec.get(&db, b"third").unwrap(), None);
+    }
+
+    #[test]
+    fn test_admission() {
+        use crate::balancer::admission::Admission;
+
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        db.insert(b"old", response()).unwrap();
+        db.insert(b"blake3", b"true").unwrap();
+
+        let codec =
+            CacheCodec::new(0, false).with_admission(TinyLfu::new(3, 0.01, Admission::TinyLfu));
+        assert_eq!(codec.track_cached(&db).unwrap(), 0);
+
+        for key in [b"a", b"b", b"c", b"d"] {
+            codec.insert(&db, key, &response()).unwrap();
+        }
+        // Only as many entries as fit are left, and setup keys are never evicted
+        let cached = [&b"old"[..], b"a", b"b", b"c", b"d"]
+            .iter()
+            .filter(|key| db.get(key).unwrap().is_some())
+            .count();
+        assert_eq!(cached, 3);
+        assert!(db.get(b"blake3").unwrap().is_some());
+    }
+
+    #[test]
+    fn test_shrink() {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        db.insert(b"blake3", b"true").unwrap();
+        let codec = CacheCodec::new(0, false);
+        for key in [b"a", b"b", b"c", b"d"] {
+            codec.insert(&db, key, &response()).unwrap();
+        }
+        let entry = (1 + response().len()) as u64;
+
+        // Fits, nothing to do
+        assert_eq!(codec.shrink(&db, entry * 4).unwrap(), (entry * 4, 0));
+        assert_eq!(codec.stats().size(), entry * 4);
+
+        // Evicted down to below 90% of the limit
+        let (_, freed) = codec.shrink(&db, entry * 3).unwrap();
+        assert_eq!(freed, entry * 2);
+        assert_eq!(db.len(), 3);
+        assert!(db.get(b"blake3").unwrap().is_some());
+        assert_eq!(codec.stats().evictions(), 2);
+        assert_eq!(codec.stats().size(), entry * 2);
+    }
+
+    #[test]
+    fn test_load_dictionaries() {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let tree = db.open_tree(DICTIONARY_TREE).unwrap();
+
+        let (codec, dictionary) = trained_codec();
+        save_dictionary(&tree, &dictionary).unwrap();
+        let encoded = codec.encode(&receipt(1)).into_owned();
+
+        let restarted = CacheCodec::new(3, true);
+        restarted.load_dictionaries(&tree).unwrap();
+        assert_eq!(restarted.decode(&encoded).unwrap(), receipt(1).as_slice());
+        assert_eq!(restarted.encode(&receipt(1)), encoded.as_slice());
+    }
+}
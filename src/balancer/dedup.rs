@@ -0,0 +1,181 @@
+// Lots of cached responses are byte for byte the same even though the requests
+// aren't, e.g. overlapping `eth_getLogs` ranges around the same events, or the same
+// receipts requested through different methods.
+//
+// Instead of storing every copy, cache entries can point to a blob keyed by the hash
+// of the response. Blobs count how many entries point to them and get removed once
+// the last one is replaced.
+use std::borrow::Cow;
+
+use blake3::hash;
+use sled::{
+    IVec,
+    Tree,
+};
+
+// Name of the sled tree blobs are kept in
+pub const BLOB_TREE: &str = "blutgang_blobs";
+
+// Entries pointing to a blob are this marker followed by the hash of the response.
+// Plain entries are JSON and compressed ones start with the zstd magic number,
+// so neither of them can start with it.
+const BLOB_MARKER: u8 = b'&';
+const REFERENCE_SIZE: usize = 33;
+
+// Not worth pointing to anything smaller than this
+const MIN_DEDUP_SIZE: usize = 256;
+
+// Blobs start with the number of entries pointing to them
+const REFCOUNT_SIZE: usize = 8;
+
+fn refcount(blob: &[u8]) -> u64 {
+    let mut count = [0; REFCOUNT_SIZE];
+    count.copy_from_slice(&blob[..REFCOUNT_SIZE]);
+    u64::from_be_bytes(count)
+}
+
+fn with_refcount(count: u64, contents: &[u8]) -> Vec<u8> {
+    let mut blob = Vec::with_capacity(REFCOUNT_SIZE + contents.len());
+    blob.extend_from_slice(&count.to_be_bytes());
+    blob.extend_from_slice(contents);
+    blob
+}
+
+// Hash of the blob `cached` points to, if it's a reference
+pub fn reference_hash(cached: &[u8]) -> Option<&[u8]> {
+    match cached.len() == REFERENCE_SIZE && cached[0] == BLOB_MARKER {
+        true => Some(&cached[1..]),
+        false => None,
+    }
+}
+
+#[derive(Debug)]
+pub struct BlobStore {
+    tree: Tree,
+}
+
+impl BlobStore {
+    pub fn new(tree: Tree) -> Self {
+        Self { tree }
+    }
+
+    // Store `encoded`, the cached form of `rx`, as a blob.
+    //
+    // Returns what should go into the cache. Small responses are stored as they are.
+    pub fn store<'a>(
+        &self,
+        rx: &[u8],
+        encoded: Cow<'a, [u8]>,
+    ) -> Result<Cow<'a, [u8]>, sled::Error> {
+        if rx.len() < MIN_DEDUP_SIZE {
+            return Ok(encoded);
+        }
+
+        // Hash what we got, so entries encoded differently still end up with the same blob
+        let hash = hash(rx);
+        self.tree.update_and_fetch(hash.as_bytes(), |blob| {
+            match blob {
+                Some(blob) if blob.len() >= REFCOUNT_SIZE => {
+                    Some(with_refcount(refcount(blob) + 1, &blob[REFCOUNT_SIZE..]))
+                }
+                _ => Some(with_refcount(1, &encoded)),
+            }
+        })?;
+
+        let mut reference = Vec::with_capacity(REFERENCE_SIZE);
+        reference.push(BLOB_MARKER);
+        reference.extend_from_slice(hash.as_bytes());
+
+        Ok(Cow::Owned(reference))
+    }
+
+    // Get the contents of the blob with `hash`. Returns None if it's gone.
+    pub fn resolve(&self, hash: &[u8]) -> Result<Option<IVec>, sled::Error> {
+        Ok(self
+            .tree
+            .get(hash)?
+            .filter(|blob| blob.len() >= REFCOUNT_SIZE)
+            .map(|blob| blob.subslice(REFCOUNT_SIZE, blob.len() - REFCOUNT_SIZE)))
+    }
+
+    // Drop the reference `cached` held, if it's one
+    pub fn release(&self, cached: &[u8]) -> Result<(), sled::Error> {
+        let hash = match reference_hash(cached) {
+            Some(hash) => hash,
+            None => return Ok(()),
+        };
+
+        self.tree.update_and_fetch(hash, |blob| {
+            let blob = blob.filter(|blob| blob.len() >= REFCOUNT_SIZE)?;
+            match refcount(blob) {
+                0 | 1 => None,
+                count => Some(with_refcount(count - 1, &blob[REFCOUNT_SIZE..])),
+            }
+        })?;
+
+        Ok(())
+    }
+
+    // Remove a blob we can't read anymore, so it gets stored again the next time
+    pub fn forget(&self, hash: &[u8]) -> Result<(), sled::Error> {
+        self.tree.remove(hash)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn blobs() -> BlobStore {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        BlobStore::new(db.open_tree(BLOB_TREE).unwrap())
+    }
+
+    fn logs() -> Vec<u8> {
+        let logs: Vec<String> = (0..8)
+            .map(|i| format!(r#"{{"data":"0x{:064x}","logIndex":"{:#x}"}}"#, i, i))
+            .collect();
+        format!(
+            r#"{{"id":null,"jsonrpc":"2.0","result":[{}]}}"#,
+            logs.join(",")
+        )
+        .into_bytes()
+    }
+
+    #[test]
+    fn test_refcount() {
+        let blobs = blobs();
+        let rx = logs();
+
+        let first = blobs.store(&rx, Cow::Borrowed(&rx)).unwrap().into_owned();
+        let second = blobs.store(&rx, Cow::Borrowed(&rx)).unwrap().into_owned();
+        assert_eq!(first, second);
+        assert_eq!(first.len(), REFERENCE_SIZE);
+        assert_eq!(blobs.tree.len(), 1);
+
+        let hash = reference_hash(&first).unwrap();
+        assert_eq!(blobs.resolve(hash).unwrap().unwrap(), rx.as_slice());
+
+        // Still referenced by the second entry
+        blobs.release(&first).unwrap();
+        assert_eq!(blobs.resolve(hash).unwrap().unwrap(), rx.as_slice());
+
+        blobs.release(&second).unwrap();
+        assert_eq!(blobs.resolve(hash).unwrap(), None);
+    }
+
+    #[test]
+    fn test_small_entries() {
+        let blobs = blobs();
+        let rx = br#"{"id":null,"jsonrpc":"2.0","result":"0x1"}"#;
+
+        let stored = blobs.store(rx, Cow::Borrowed(rx)).unwrap();
+        assert_eq!(stored, rx.as_slice());
+        assert_eq!(reference_hash(&stored), None);
+        assert_eq!(blobs.tree.len(), 0);
+
+        // Releasing entries that aren't references does nothing
+        blobs.release(rx).unwrap();
+    }
+}
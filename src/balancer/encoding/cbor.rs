@@ -0,0 +1,335 @@
+// CBOR, as described in RFC 8949
+//
+// Tags are skipped and their content decoded as is. Simple values other than
+// true/false/null/undefined have no JSON equivalent and are rejected.
+use super::{
+    bytes_to_hex,
+    utf8,
+    Reader,
+    MAX_DEPTH,
+};
+
+use serde_json::{
+    Map,
+    Number,
+    Value,
+};
+
+const UNSIGNED: u8 = 0;
+const NEGATIVE: u8 = 1;
+const BYTES: u8 = 2;
+const TEXT: u8 = 3;
+const ARRAY: u8 = 4;
+const MAP: u8 = 5;
+const TAG: u8 = 6;
+const SIMPLE: u8 = 7;
+
+// Ends indefinite length items
+const BREAK: u8 = 0xff;
+
+pub fn encode(value: &Value) -> Vec<u8> {
+    let mut out = Vec::new();
+    write_value(value, &mut out);
+    out
+}
+
+fn write_value(value: &Value, out: &mut Vec<u8>) {
+    match value {
+        Value::Null => out.push(0xf6),
+        Value::Bool(false) => out.push(0xf4),
+        Value::Bool(true) => out.push(0xf5),
+        Value::Number(number) => write_number(number, out),
+        Value::String(string) => {
+            write_head(TEXT, string.len() as u64, out);
+            out.extend_from_slice(string.as_bytes());
+        }
+        Value::Array(array) => {
+            write_head(ARRAY, array.len() as u64, out);
+            for value in array {
+                write_value(value, out);
+            }
+        }
+        Value::Object(map) => {
+            write_head(MAP, map.len() as u64, out);
+            for (key, value) in map {
+                write_head(TEXT, key.len() as u64, out);
+                out.extend_from_slice(key.as_bytes());
+                write_value(value, out);
+            }
+        }
+    }
+}
+
+// Major type in the top 3 bits, the argument in the rest if it fits or after if not
+fn write_head(major: u8, arg: u64, out: &mut Vec<u8>) {
+    let major = major << 5;
+    match arg {
+        0..=23 => out.push(major | arg as u8),
+        24..=0xff => out.extend_from_slice(&[major | 24, arg as u8]),
+        0x100..=0xffff => {
+            out.push(major | 25);
+            out.extend_from_slice(&(arg as u16).to_be_bytes());
+        }
+        0x10000..=0xffff_ffff => {
+            out.push(major | 26);
+            out.extend_from_slice(&(arg as u32).to_be_bytes());
+        }
+        _ => {
+            out.push(major | 27);
+            out.extend_from_slice(&arg.to_be_bytes());
+        }
+    }
+}
+
+fn write_number(number: &Number, out: &mut Vec<u8>) {
+    if let Some(n) = number.as_u64() {
+        write_head(UNSIGNED, n, out);
+    } else if let Some(n) = number.as_i64() {
+        // Negative numbers are stored as -1 - n
+        write_head(NEGATIVE, !n as u64, out);
+    } else {
+        out.push(0xfb);
+        out.extend_from_slice(&number.as_f64().unwrap_or_default().to_be_bytes());
+    }
+}
+
+pub fn decode(body: &[u8]) -> Result<Value, String> {
+    let mut reader = Reader::new(body);
+    let value = read_value(&mut reader, 0)?;
+    reader.finish()?;
+    Ok(value)
+}
+
+// Argument of an item, `None` if its length is indefinite
+fn read_arg(reader: &mut Reader, info: u8) -> Result<Option<u64>, String> {
+    let arg = match info {
+        0..=23 => info as u64,
+        24 => reader.u8()? as u64,
+        25 => reader.u16()? as u64,
+        26 => reader.u32()? as u64,
+        27 => reader.u64()?,
+        31 => return Ok(None),
+        _ => return Err(format!("reserved additional info {}", info)),
+    };
+    Ok(Some(arg))
+}
+
+fn read_value(reader: &mut Reader, depth: usize) -> Result<Value, String> {
+    if depth > MAX_DEPTH {
+        return Err("nested too deep".to_string());
+    }
+
+    let initial = reader.u8()?;
+    let (major, info) = (initial >> 5, initial & 0x1f);
+
+    // Floats and simple values aren't lengths, so they don't go through `read_arg`
+    if major == SIMPLE {
+        return match info {
+            20 => Ok(Value::Bool(false)),
+            21 => Ok(Value::Bool(true)),
+            22 | 23 => Ok(Value::Null),
+            25 => Ok(Value::from(half_to_f64(reader.u16()?))),
+            26 => Ok(Value::from(f32::from_bits(reader.u32()?) as f64)),
+            27 => Ok(Value::from(f64::from_bits(reader.u64()?))),
+            31 => Err("unexpected break".to_string()),
+            _ => Err(format!("unsupported simple value {}", info)),
+        };
+    }
+
+    let arg = read_arg(reader, info)?;
+    let value = match (major, arg) {
+        (UNSIGNED, Some(n)) => Value::from(n),
+        (NEGATIVE, Some(n)) => {
+            match i64::try_from(n) {
+                Ok(n) => Value::from(-1 - n),
+                // Too small for an i64, this is as close as JSON gets
+                Err(_) => Value::from(-1.0 - n as f64),
+            }
+        }
+        (BYTES, arg) => Value::String(bytes_to_hex(&read_bytes(reader, BYTES, arg)?)),
+        (TEXT, arg) => Value::String(utf8(&read_bytes(reader, TEXT, arg)?)?),
+        (ARRAY, arg) => {
+            let mut array = Vec::new();
+            match arg {
+                Some(len) => {
+                    array.reserve(reader.capacity(len as usize));
+                    for _ in 0..len {
+                        array.push(read_value(reader, depth + 1)?);
+                    }
+                }
+                None => {
+                    while !at_break(reader)? {
+                        array.push(read_value(reader, depth + 1)?);
+                    }
+                }
+            }
+            Value::Array(array)
+        }
+        (MAP, arg) => {
+            let mut map = Map::new();
+            let mut remaining = arg;
+            loop {
+                match &mut remaining {
+                    Some(0) => break,
+                    Some(n) => *n -= 1,
+                    None if at_break(reader)? => break,
+                    None => {}
+                }
+                let key = match read_value(reader, depth + 1)? {
+                    Value::String(key) => key,
+                    _ => return Err("map keys must be strings".to_string()),
+                };
+                map.insert(key, read_value(reader, depth + 1)?);
+            }
+            Value::Object(map)
+        }
+        (TAG, Some(_)) => read_value(reader, depth + 1)?,
+        _ => {
+            return Err(format!(
+                "major type {} can't have an indefinite length",
+                major
+            ))
+        }
+    };
+
+    Ok(value)
+}
+
+// Consumes the break if it's next
+fn at_break(reader: &mut Reader) -> Result<bool, String> {
+    match reader.peek() {
+        Some(BREAK) => {
+            reader.u8()?;
+            Ok(true)
+        }
+        Some(_) => Ok(false),
+        None => Err("unexpected end of body".to_string()),
+    }
+}
+
+// Byte and text strings, which can also come as a series of definite length chunks
+fn read_bytes(reader: &mut Reader, major: u8, arg: Option<u64>) -> Result<Vec<u8>, String> {
+    if let Some(len) = arg {
+        return Ok(reader.take(len as usize)?.to_vec());
+    }
+
+    let mut bytes = Vec::new();
+    while !at_break(reader)? {
+        let initial = reader.u8()?;
+        if initial >> 5 != major {
+            return Err("string chunk of a different type".to_string());
+        }
+        match read_arg(reader, initial & 0x1f)? {
+            Some(len) => bytes.extend_from_slice(reader.take(len as usize)?),
+            None => return Err("nested indefinite string".to_string()),
+        }
+    }
+    Ok(bytes)
+}
+
+fn half_to_f64(half: u16) -> f64 {
+    let exponent = ((half >> 10) & 0x1f) as i32;
+    let mantissa = (half & 0x3ff) as f64;
+    let value = match exponent {
+        0 => mantissa * 2f64.powi(-24),
+        31 if mantissa == 0.0 => f64::INFINITY,
+        31 => f64::NAN,
+        _ => (mantissa + 1024.0) * 2f64.powi(exponent - 25),
+    };
+
+    match half & 0x8000 {
+        0 => value,
+        _ => -value,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_roundtrip() {
+        let values = [
+            json!(null),
+            json!(false),
+            json!(23),
+            json!(24),
+            json!(1000),
+            json!(u64::MAX),
+            json!(-1),
+            json!(-500),
+            json!(i64::MIN),
+            json!(-0.25),
+            json!("x".repeat(300)),
+            json!([1, [2, [3]], {}]),
+            json!({
+                "jsonrpc": "2.0",
+                "id": "abc",
+                "result": {"number": "0x10", "transactions": []},
+            }),
+        ];
+        for value in values {
+            assert_eq!(decode(&encode(&value)).unwrap(), value);
+        }
+    }
+
+    #[test]
+    fn test_encode() {
+        // RFC 8949 appendix A
+        assert_eq!(encode(&json!(100)), vec![0x18, 0x64]);
+        assert_eq!(encode(&json!(-1000)), vec![0x39, 0x03, 0xe7]);
+        assert_eq!(encode(&json!("IETF")), vec![0x64, 0x49, 0x45, 0x54, 0x46]);
+        assert_eq!(
+            encode(&json!({"a": 1, "b": [2, 3]})),
+            vec![0xa2, 0x61, 0x61, 0x01, 0x61, 0x62, 0x82, 0x02, 0x03]
+        );
+    }
+
+    #[test]
+    fn test_decode() {
+        // RFC 8949 appendix A
+        assert_eq!(decode(&[0xf9, 0x3e, 0x00]).unwrap(), json!(1.5));
+        assert_eq!(
+            decode(&[0xf9, 0x00, 0x01]).unwrap(),
+            json!(5.960464477539063e-8)
+        );
+        assert_eq!(
+            decode(&[0x44, 0x01, 0x02, 0x03, 0x04]).unwrap(),
+            json!("0x01020304")
+        );
+        assert_eq!(
+            decode(&[0x9f, 0x01, 0x82, 0x02, 0x03, 0x9f, 0x04, 0x05, 0xff, 0xff]).unwrap(),
+            json!([1, [2, 3], [4, 5]])
+        );
+        assert_eq!(
+            decode(&[0xbf, 0x61, 0x61, 0x01, 0x61, 0x62, 0x9f, 0x02, 0x03, 0xff, 0xff]).unwrap(),
+            json!({"a": 1, "b": [2, 3]})
+        );
+        assert_eq!(
+            decode(&[0x7f, 0x65, 0x73, 0x74, 0x72, 0x65, 0x61, 0x64, 0x6d, 0x69, 0x6e, 0x67, 0xff])
+                .unwrap(),
+            json!("streaming")
+        );
+        // Tag 1 (epoch time) is skipped
+        assert_eq!(
+            decode(&[0xc1, 0x1a, 0x51, 0x4b, 0x67, 0xb0]).unwrap(),
+            json!(1363896240)
+        );
+        assert_eq!(
+            decode(&[0x3b, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]).unwrap(),
+            json!(-18446744073709551616.0)
+        );
+
+        assert!(decode(&[]).is_err());
+        assert!(decode(&[0xff]).is_err());
+        assert!(decode(&[0x1f]).is_err());
+        assert!(decode(&[0xa1, 0x01, 0x01]).is_err());
+        assert!(decode(&[0x9f, 0x01]).is_err());
+        assert!(decode(&[0xf0]).is_err());
+        // Bogus length doesn't allocate
+        assert!(decode(&[0x9b, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]).is_err());
+        // Too deep
+        assert!(decode(&[0x81; 200]).is_err());
+    }
+}
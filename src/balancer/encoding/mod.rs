@@ -0,0 +1,243 @@
+// Requests can be sent as MessagePack or CBOR instead of JSON, picked by Content-Type.
+//
+// Only the client ever sees them. Requests get decoded to a `Value` as soon as we read
+// them and responses get encoded right before they go out, so everything in between,
+// the cache and the RPCs included, still speaks JSON.
+//
+// Byte strings have no JSON equivalent, so they're decoded to 0x prefixed hex like
+// every other blob in JSON-RPC.
+pub mod cbor;
+pub mod msgpack;
+
+use http_body_util::{
+    BodyExt,
+    Full,
+    Limited,
+};
+use hyper::{
+    body::{
+        Bytes,
+        Incoming,
+    },
+    header::HeaderValue,
+    Request,
+    Response,
+};
+use serde_json::Value;
+
+// Nesting deeper than this is rejected instead of risking the stack
+const MAX_DEPTH: usize = 128;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Json,
+    MessagePack,
+    Cbor,
+}
+
+impl Encoding {
+    // Parameters like `charset` are ignored, none of the encodings care
+    pub fn from_content_type(content_type: Option<&HeaderValue>) -> Option<Self> {
+        let content_type = content_type?.to_str().ok()?;
+        let essence = content_type.split(';').next()?.trim().to_ascii_lowercase();
+
+        match essence.as_str() {
+            "application/json" => Some(Encoding::Json),
+            "application/msgpack" | "application/x-msgpack" | "application/vnd.msgpack" => {
+                Some(Encoding::MessagePack)
+            }
+            "application/cbor" => Some(Encoding::Cbor),
+            _ => None,
+        }
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            Encoding::Json => "application/json",
+            Encoding::MessagePack => "application/msgpack",
+            Encoding::Cbor => "application/cbor",
+        }
+    }
+
+    pub fn decode(&self, body: &[u8]) -> Result<Value, String> {
+        match self {
+            Encoding::Json => serde_json::from_slice(body).map_err(|err| err.to_string()),
+            Encoding::MessagePack => msgpack::decode(body),
+            Encoding::Cbor => cbor::decode(body),
+        }
+    }
+
+    pub fn encode(&self, value: &Value) -> Vec<u8> {
+        match self {
+            Encoding::Json => serde_json::to_vec(value).unwrap_or_default(),
+            Encoding::MessagePack => msgpack::encode(value),
+            Encoding::Cbor => cbor::encode(value),
+        }
+    }
+}
+
+// Read the whole body of `tx`, erroring if it's larger than `limit` bytes
+pub async fn read_body(
+    tx: Request<Incoming>,
+    limit: usize,
+) -> Result<Bytes, Box<dyn std::error::Error + Send + Sync>> {
+    Ok(Limited::new(tx.into_body(), limit)
+        .collect()
+        .await?
+        .to_bytes())
+}
+
+// Encode a JSON response the way its request was. Anything that isn't JSON, like
+// plain text errors, is left alone.
+pub async fn encode_response(
+    rx: Response<Full<Bytes>>,
+    encoding: Encoding,
+) -> Response<Full<Bytes>> {
+    let is_json =
+        rx.headers().get("Content-Type") == Some(&HeaderValue::from_static("application/json"));
+    if encoding == Encoding::Json || !is_json {
+        return rx;
+    }
+
+    let (mut parts, body) = rx.into_parts();
+    let body = match body.collect().await {
+        Ok(body) => body.to_bytes(),
+        Err(never) => match never {},
+    };
+    let body = match serde_json::from_slice::<Value>(&body) {
+        Ok(value) => {
+            parts.headers.insert(
+                "Content-Type",
+                HeaderValue::from_static(encoding.content_type()),
+            );
+            Bytes::from(encoding.encode(&value))
+        }
+        Err(_) => body,
+    };
+
+    Response::from_parts(parts, Full::new(body))
+}
+
+// Cursor over a body being decoded
+pub struct Reader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(buf: &'a [u8]) -> Self {
+        Reader { buf, pos: 0 }
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], String> {
+        match self.pos.checked_add(len) {
+            Some(end) if end <= self.buf.len() => {
+                let bytes = &self.buf[self.pos..end];
+                self.pos = end;
+                Ok(bytes)
+            }
+            _ => Err("unexpected end of body".to_string()),
+        }
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.buf.get(self.pos).copied()
+    }
+
+    fn remaining(&self) -> usize {
+        self.buf.len() - self.pos
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, String> {
+        Ok(u16::from_be_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_be_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64, String> {
+        Ok(u64::from_be_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    // Lengths are checked against what's left so a bogus one can't make us allocate
+    fn capacity(&self, len: usize) -> usize {
+        len.min(self.remaining())
+    }
+
+    fn finish(self) -> Result<(), String> {
+        match self.remaining() {
+            0 => Ok(()),
+            n => Err(format!("{} trailing bytes", n)),
+        }
+    }
+}
+
+fn bytes_to_hex(bytes: &[u8]) -> String {
+    let mut hex = String::with_capacity(2 + bytes.len() * 2);
+    hex.push_str("0x");
+    for byte in bytes {
+        hex.push_str(&format!("{:02x}", byte));
+    }
+    hex
+}
+
+fn utf8(bytes: &[u8]) -> Result<String, String> {
+    String::from_utf8(bytes.to_vec()).map_err(|_| "string is not valid UTF-8".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_from_content_type() {
+        let encoding = |value: &'static str| {
+            Encoding::from_content_type(Some(&HeaderValue::from_static(value)))
+        };
+        assert_eq!(encoding("application/json"), Some(Encoding::Json));
+        assert_eq!(
+            encoding("application/json; charset=utf-8"),
+            Some(Encoding::Json)
+        );
+        assert_eq!(encoding("application/msgpack"), Some(Encoding::MessagePack));
+        assert_eq!(
+            encoding("Application/X-MsgPack"),
+            Some(Encoding::MessagePack)
+        );
+        assert_eq!(encoding("application/cbor"), Some(Encoding::Cbor));
+        assert_eq!(encoding("text/plain"), None);
+        assert_eq!(Encoding::from_content_type(None), None);
+    }
+
+    #[tokio::test]
+    async fn test_encode_response() {
+        let rx = Response::builder()
+            .header("Content-Type", "application/json")
+            .body(Full::new(Bytes::from(
+                r#"{"jsonrpc":"2.0","id":1,"result":"0x1"}"#,
+            )))
+            .unwrap();
+        let rx = encode_response(rx, Encoding::Cbor).await;
+        assert_eq!(rx.headers()["Content-Type"], "application/cbor");
+        let body = rx.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(
+            cbor::decode(&body).unwrap(),
+            json!({"jsonrpc": "2.0", "id": 1, "result": "0x1"})
+        );
+
+        // Plain text stays plain text
+        let rx = Response::builder()
+            .status(400)
+            .body(Full::new(Bytes::from("Improper content-type header")))
+            .unwrap();
+        let rx = encode_response(rx, Encoding::MessagePack).await;
+        let body = rx.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(body, "Improper content-type header");
+    }
+}
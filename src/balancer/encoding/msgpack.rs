@@ -0,0 +1,273 @@
+// MessagePack, as described in https://github.com/msgpack/msgpack/blob/master/spec.md
+//
+// Extension types have no JSON equivalent and are rejected.
+use super::{
+    bytes_to_hex,
+    utf8,
+    Reader,
+    MAX_DEPTH,
+};
+
+use serde_json::{
+    Map,
+    Number,
+    Value,
+};
+
+pub fn encode(value: &Value) -> Vec<u8> {
+    let mut out = Vec::new();
+    write_value(value, &mut out);
+    out
+}
+
+fn write_value(value: &Value, out: &mut Vec<u8>) {
+    match value {
+        Value::Null => out.push(0xc0),
+        Value::Bool(false) => out.push(0xc2),
+        Value::Bool(true) => out.push(0xc3),
+        Value::Number(number) => write_number(number, out),
+        Value::String(string) => {
+            write_len(string.len(), 0xa0, 32, [0xd9, 0xda, 0xdb], out);
+            out.extend_from_slice(string.as_bytes());
+        }
+        Value::Array(array) => {
+            write_len(array.len(), 0x90, 16, [0x00, 0xdc, 0xdd], out);
+            for value in array {
+                write_value(value, out);
+            }
+        }
+        Value::Object(map) => {
+            write_len(map.len(), 0x80, 16, [0x00, 0xde, 0xdf], out);
+            for (key, value) in map {
+                write_len(key.len(), 0xa0, 32, [0xd9, 0xda, 0xdb], out);
+                out.extend_from_slice(key.as_bytes());
+                write_value(value, out);
+            }
+        }
+    }
+}
+
+// Fixed size if it fits, 8/16/32 bit lengths otherwise. Arrays and maps have no
+// 8 bit form.
+fn write_len(len: usize, fixed: u8, fixed_max: usize, markers: [u8; 3], out: &mut Vec<u8>) {
+    if len < fixed_max {
+        out.push(fixed | len as u8);
+    } else if markers[0] != 0x00 && len <= u8::MAX as usize {
+        out.push(markers[0]);
+        out.push(len as u8);
+    } else if len <= u16::MAX as usize {
+        out.push(markers[1]);
+        out.extend_from_slice(&(len as u16).to_be_bytes());
+    } else {
+        out.push(markers[2]);
+        out.extend_from_slice(&(len as u32).to_be_bytes());
+    }
+}
+
+fn write_number(number: &Number, out: &mut Vec<u8>) {
+    if let Some(n) = number.as_u64() {
+        match n {
+            0..=0x7f => out.push(n as u8),
+            0x80..=0xff => out.extend_from_slice(&[0xcc, n as u8]),
+            0x100..=0xffff => {
+                out.push(0xcd);
+                out.extend_from_slice(&(n as u16).to_be_bytes());
+            }
+            0x10000..=0xffff_ffff => {
+                out.push(0xce);
+                out.extend_from_slice(&(n as u32).to_be_bytes());
+            }
+            _ => {
+                out.push(0xcf);
+                out.extend_from_slice(&n.to_be_bytes());
+            }
+        }
+    } else if let Some(n) = number.as_i64() {
+        // Only negative numbers make it here
+        if n >= -32 {
+            out.push(n as i8 as u8);
+        } else if n >= i8::MIN as i64 {
+            out.extend_from_slice(&[0xd0, n as i8 as u8]);
+        } else if n >= i16::MIN as i64 {
+            out.push(0xd1);
+            out.extend_from_slice(&(n as i16).to_be_bytes());
+        } else if n >= i32::MIN as i64 {
+            out.push(0xd2);
+            out.extend_from_slice(&(n as i32).to_be_bytes());
+        } else {
+            out.push(0xd3);
+            out.extend_from_slice(&n.to_be_bytes());
+        }
+    } else {
+        out.push(0xcb);
+        out.extend_from_slice(&number.as_f64().unwrap_or_default().to_be_bytes());
+    }
+}
+
+pub fn decode(body: &[u8]) -> Result<Value, String> {
+    let mut reader = Reader::new(body);
+    let value = read_value(&mut reader, 0)?;
+    reader.finish()?;
+    Ok(value)
+}
+
+fn read_value(reader: &mut Reader, depth: usize) -> Result<Value, String> {
+    if depth > MAX_DEPTH {
+        return Err("nested too deep".to_string());
+    }
+
+    let marker = reader.u8()?;
+    let value = match marker {
+        0x00..=0x7f => Value::from(marker),
+        0x80..=0x8f => read_map(reader, (marker & 0x0f) as usize, depth)?,
+        0x90..=0x9f => read_array(reader, (marker & 0x0f) as usize, depth)?,
+        0xa0..=0xbf => read_str(reader, (marker & 0x1f) as usize)?,
+        0xc0 => Value::Null,
+        0xc2 => Value::Bool(false),
+        0xc3 => Value::Bool(true),
+        0xc4 => {
+            let len = reader.u8()? as usize;
+            Value::String(bytes_to_hex(reader.take(len)?))
+        }
+        0xc5 => {
+            let len = reader.u16()? as usize;
+            Value::String(bytes_to_hex(reader.take(len)?))
+        }
+        0xc6 => {
+            let len = reader.u32()? as usize;
+            Value::String(bytes_to_hex(reader.take(len)?))
+        }
+        0xca => Value::from(f32::from_bits(reader.u32()?) as f64),
+        0xcb => Value::from(f64::from_bits(reader.u64()?)),
+        0xcc => Value::from(reader.u8()?),
+        0xcd => Value::from(reader.u16()?),
+        0xce => Value::from(reader.u32()?),
+        0xcf => Value::from(reader.u64()?),
+        0xd0 => Value::from(reader.u8()? as i8),
+        0xd1 => Value::from(reader.u16()? as i16),
+        0xd2 => Value::from(reader.u32()? as i32),
+        0xd3 => Value::from(reader.u64()? as i64),
+        0xd9 => {
+            let len = reader.u8()? as usize;
+            read_str(reader, len)?
+        }
+        0xda => {
+            let len = reader.u16()? as usize;
+            read_str(reader, len)?
+        }
+        0xdb => {
+            let len = reader.u32()? as usize;
+            read_str(reader, len)?
+        }
+        0xdc => {
+            let len = reader.u16()? as usize;
+            read_array(reader, len, depth)?
+        }
+        0xdd => {
+            let len = reader.u32()? as usize;
+            read_array(reader, len, depth)?
+        }
+        0xde => {
+            let len = reader.u16()? as usize;
+            read_map(reader, len, depth)?
+        }
+        0xdf => {
+            let len = reader.u32()? as usize;
+            read_map(reader, len, depth)?
+        }
+        0xe0..=0xff => Value::from(marker as i8),
+        0xd4..=0xd8 | 0xc7..=0xc9 => return Err("extension types are not supported".to_string()),
+        0xc1 => return Err("invalid marker 0xc1".to_string()),
+    };
+
+    Ok(value)
+}
+
+fn read_str(reader: &mut Reader, len: usize) -> Result<Value, String> {
+    Ok(Value::String(utf8(reader.take(len)?)?))
+}
+
+fn read_array(reader: &mut Reader, len: usize, depth: usize) -> Result<Value, String> {
+    let mut array = Vec::with_capacity(reader.capacity(len));
+    for _ in 0..len {
+        array.push(read_value(reader, depth + 1)?);
+    }
+    Ok(Value::Array(array))
+}
+
+fn read_map(reader: &mut Reader, len: usize, depth: usize) -> Result<Value, String> {
+    let mut map = Map::new();
+    for _ in 0..len {
+        let key = match read_value(reader, depth + 1)? {
+            Value::String(key) => key,
+            _ => return Err("map keys must be strings".to_string()),
+        };
+        map.insert(key, read_value(reader, depth + 1)?);
+    }
+    Ok(Value::Object(map))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_roundtrip() {
+        let values = [
+            json!(null),
+            json!(true),
+            json!(0),
+            json!(127),
+            json!(128),
+            json!(65536),
+            json!(u64::MAX),
+            json!(-1),
+            json!(-33),
+            json!(-40000),
+            json!(i64::MIN),
+            json!(1.5),
+            json!("x".repeat(300)),
+            json!([1, [2, [3]], {}]),
+            json!({
+                "jsonrpc": "2.0",
+                "id": 1,
+                "method": "eth_getBalance",
+                "params": ["0x95222290dd7278aa3ddd389cc1e1d165cc4bafe5", "latest"],
+            }),
+        ];
+        for value in values {
+            assert_eq!(decode(&encode(&value)).unwrap(), value);
+        }
+    }
+
+    #[test]
+    fn test_encode() {
+        // {"id": 1, "a": [true, null]}, keys keep serde_json's order
+        let value = json!({"a": [true, null], "id": 1});
+        assert_eq!(
+            encode(&value),
+            vec![0x82, 0xa1, b'a', 0x92, 0xc3, 0xc0, 0xa2, b'i', b'd', 0x01]
+        );
+        assert_eq!(encode(&json!(-1)), vec![0xff]);
+        assert_eq!(encode(&json!(200)), vec![0xcc, 200]);
+    }
+
+    #[test]
+    fn test_decode() {
+        // bin 8 goes to hex
+        assert_eq!(decode(&[0xc4, 0x02, 0xde, 0xad]).unwrap(), json!("0xdead"));
+        // float 32
+        assert_eq!(decode(&[0xca, 0x3f, 0xc0, 0x00, 0x00]).unwrap(), json!(1.5));
+
+        assert!(decode(&[]).is_err());
+        assert!(decode(&[0x92, 0x01]).is_err());
+        assert!(decode(&[0x01, 0x02]).is_err());
+        assert!(decode(&[0x81, 0x01, 0x01]).is_err());
+        assert!(decode(&[0xd4, 0x01, 0x00]).is_err());
+        // Bogus length doesn't allocate
+        assert!(decode(&[0xdd, 0xff, 0xff, 0xff, 0xff]).is_err());
+        // Too deep
+        assert!(decode(&[0x91; 200]).is_err());
+    }
+}
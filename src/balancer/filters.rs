@@ -0,0 +1,159 @@
+// Keeps filters on the RPC that created them.
+//
+// Filters only exist on the node that installed them, so `eth_getFilterChanges` sent
+// anywhere else fails with "filter not found". We remember which RPC answered each
+// `eth_new*Filter`, and pin the calls using the id it returned to that RPC. If it
+// leaves the pool the filter is gone with it, and the client has to install a new one
+// like it would with a node that restarted.
+use memchr::memmem;
+use serde_json::Value;
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{
+        Duration,
+        Instant,
+    },
+};
+
+// Nodes drop filters that haven't been polled for 5 minutes
+const FILTER_TTL: Duration = Duration::from_secs(5 * 60);
+
+// Prune expired filters once we track more than this
+const PRUNE_THRESHOLD: usize = 16384;
+
+const CREATE_METHODS: [&str; 3] = [
+    "eth_newFilter",
+    "eth_newBlockFilter",
+    "eth_newPendingTransactionFilter",
+];
+const USE_METHODS: [&str; 3] = [
+    "eth_getFilterChanges",
+    "eth_getFilterLogs",
+    "eth_uninstallFilter",
+];
+
+// If `method` installs a filter or uses one
+pub fn is_filter_method(method: &str) -> bool {
+    CREATE_METHODS.contains(&method) || USE_METHODS.contains(&method)
+}
+
+pub fn creates_filter(method: &str) -> bool {
+    CREATE_METHODS.contains(&method)
+}
+
+// Filter id a request uses, None if it doesn't use one
+pub fn filter_id(tx: &Value) -> Option<String> {
+    if !USE_METHODS.contains(&tx["method"].as_str().unwrap_or_default()) {
+        return None;
+    }
+
+    tx["params"][0].as_str().map(|id| id.to_lowercase())
+}
+
+#[derive(Debug, Default)]
+pub struct FilterTracker {
+    // Filter id -> when it was last used, and the RPC that has it
+    filters: Mutex<HashMap<String, (Instant, String)>>,
+}
+
+impl FilterTracker {
+    // Remember that `rpc` has the filter whose id `rx` returned
+    pub fn record_created(&self, rx: &[u8], rpc: &str) {
+        let rx: Value = match serde_json::from_slice(rx) {
+            Ok(rx) => rx,
+            Err(_) => return,
+        };
+        let id = match rx["result"].as_str() {
+            Some(id) => id.to_lowercase(),
+            None => return,
+        };
+
+        let mut filters = self.filters.lock().unwrap();
+        if filters.len() > PRUNE_THRESHOLD {
+            filters.retain(|_, (used, _)| used.elapsed() < FILTER_TTL);
+        }
+        filters.insert(id, (Instant::now(), rpc.to_string()));
+    }
+
+    // RPC that has filter `id`, None if we don't know it or it expired
+    pub fn owner(&self, id: &str) -> Option<String> {
+        let mut filters = self.filters.lock().unwrap();
+        let (used, rpc) = filters.get_mut(id)?;
+        if used.elapsed() >= FILTER_TTL {
+            filters.remove(id);
+            return None;
+        }
+
+        *used = Instant::now();
+        Some(rpc.clone())
+    }
+
+    // Forget filter `id` once it's uninstalled, or its RPC doesn't know it anymore
+    pub fn record_used(&self, id: &str, method: &str, rx: &[u8]) {
+        let uninstalled =
+            method == "eth_uninstallFilter" && memmem::find(rx, b"\"error\"").is_none();
+        if uninstalled || memmem::find(rx, b"filter not found").is_some() {
+            self.filters.lock().unwrap().remove(id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_owner() {
+        let tracker = FilterTracker::default();
+        tracker.record_created(br#"{"id":null,"jsonrpc":"2.0","result":"0xABC"}"#, "llama");
+        assert_eq!(tracker.owner("0xabc"), Some("llama".to_string()));
+        assert_eq!(tracker.owner("0xdef"), None);
+
+        // Errors don't install anything
+        tracker.record_created(
+            br#"{"id":null,"jsonrpc":"2.0","error":{"code":-32000,"message":"oops"}}"#,
+            "infura",
+        );
+        assert_eq!(tracker.filters.lock().unwrap().len(), 1);
+
+        // Still there while it's polled
+        tracker.record_used("0xabc", "eth_getFilterChanges", br#"{"result":[]}"#);
+        assert_eq!(tracker.owner("0xabc"), Some("llama".to_string()));
+
+        tracker.record_used("0xabc", "eth_uninstallFilter", br#"{"result":true}"#);
+        assert_eq!(tracker.owner("0xabc"), None);
+    }
+
+    #[test]
+    fn test_filter_gone() {
+        let tracker = FilterTracker::default();
+        tracker.record_created(br#"{"result":"0x1"}"#, "llama");
+        tracker.record_used(
+            "0x1",
+            "eth_getFilterChanges",
+            br#"{"error":{"code":-32000,"message":"filter not found"}}"#,
+        );
+        assert_eq!(tracker.owner("0x1"), None);
+
+        // Expired filters are gone too
+        tracker.record_created(br#"{"result":"0x2"}"#, "llama");
+        if let Some(used) = Instant::now().checked_sub(FILTER_TTL) {
+            tracker.filters.lock().unwrap().get_mut("0x2").unwrap().0 = used;
+            assert_eq!(tracker.owner("0x2"), None);
+        }
+    }
+
+    #[test]
+    fn test_filter_id() {
+        let tx = json!({"method": "eth_getFilterChanges", "params": ["0xABC"]});
+        assert_eq!(filter_id(&tx), Some("0xabc".to_string()));
+        let tx = json!({"method": "eth_newFilter", "params": [{"fromBlock": "0x1"}]});
+        assert_eq!(filter_id(&tx), None);
+
+        assert!(is_filter_method("eth_uninstallFilter"));
+        assert!(creates_filter("eth_newBlockFilter"));
+        assert!(!is_filter_method("eth_getLogs"));
+    }
+}
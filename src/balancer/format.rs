@@ -1,7 +1,13 @@
 use crate::NamedBlocknumbers;
-use http_body_util::BodyExt;
+use http_body_util::{
+    BodyExt,
+    Limited,
+};
 use hyper::{
-    body::Incoming,
+    body::{
+        Bytes,
+        Incoming,
+    },
     Request,
 };
 use memchr::memmem;
@@ -11,16 +17,19 @@ use serde_json::{
     Value,
     Value::Null,
 };
-use simd_json::serde::from_str;
-use std::{
-    str::from_utf8,
-    sync::{
-        Arc,
-        RwLock,
-    },
+use simd_json::serde::from_slice;
+use std::sync::{
+    Arc,
+    RwLock,
 };
 
-use crate::rpc::error::RpcError;
+use crate::{
+    logging::filter::{
+        log_enabled,
+        LogLevel,
+    },
+    rpc::error::RpcError,
+};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 enum NamedNumber {
@@ -81,11 +90,46 @@ pub fn get_block_number_from_request(
         Some("eth_getBlockByNumber") => 0,
         Some("eth_getTransactionByBlockNumberAndIndex") => 0,
         Some("eth_getUncleByBlockNumberAndIndex") => 0,
+        Some("eth_simulateV1") => 1,
+        Some("eth_callMany") => 1,
+        Some("debug_traceCall") => 1,
+        // L2 namespaces
+        Some("optimism_outputAtBlock") => 0,
+        Some("arbtrace_block") => 0,
+        Some("arbtrace_replayBlockTransactions") => 0,
+        Some("arbtrace_call") => 2,
+        Some("zks_getBlockDetails") => 0,
+        Some("zks_getRawBlockTransactions") => 0,
+        Some("starknet_getBlockWithTxHashes") => 0,
+        Some("starknet_getBlockWithTxs") => 0,
+        Some("starknet_getStateUpdate") => 0,
+        Some("starknet_getBlockTransactionCount") => 0,
+        Some("starknet_getTransactionByBlockIdAndIndex") => 0,
+        Some("starknet_getClass") => 0,
+        Some("starknet_getClassAt") => 0,
+        Some("starknet_getClassHashAt") => 0,
+        Some("starknet_getNonce") => 0,
+        Some("starknet_call") => 1,
+        Some("starknet_getStorageAt") => 2,
         _ => return None,
     };
 
     // Get the corresponding blockbumber from the params
-    let block_number = tx["params"][position].to_string().replace('\"', "");
+    //
+    // `eth_callMany` takes a state context object instead of a plain block number
+    let mut block_number = &tx["params"][position];
+    if tx["method"].as_str() == Some("eth_callMany") {
+        block_number = &block_number["blockNumber"];
+    }
+    // Starknet wraps it in a `block_id` object
+    if let Some(number) = block_number.get("block_number") {
+        block_number = number;
+    }
+    // Some L2s (zksync, starknet) use plain integers instead of hex strings
+    if let Some(number) = block_number.as_u64() {
+        return Some(number);
+    }
+    let block_number = block_number.to_string().replace('\"', "");
 
     // If `null` return None
     if block_number == "null" {
@@ -117,27 +161,109 @@ pub fn get_block_number_from_request(
 }
 
 pub async fn incoming_to_value(tx: Request<Incoming>) -> Result<Value, hyper::Error> {
-    #[cfg(feature = "debug-verbose")]
-    println!("Incoming request: {:?}", tx);
+    if cfg!(feature = "debug-verbose") || log_enabled("balancer", LogLevel::Debug) {
+        println!("Incoming request: {:?}", tx);
+    }
 
-    let tx = tx.collect().await?.to_bytes().clone();
-    let mut tx = from_utf8(&tx).unwrap().to_owned();
+    let tx = tx.collect().await?.to_bytes();
 
-    let ret = match unsafe { from_str(&mut tx) } {
+    Ok(bytes_to_value(tx))
+}
+
+// Same as `incoming_to_value`, but errors if the body is larger than `limit` bytes
+pub async fn limited_incoming_to_value(
+    tx: Request<Incoming>,
+    limit: usize,
+) -> Result<Value, Box<dyn std::error::Error + Send + Sync>> {
+    if cfg!(feature = "debug-verbose") || log_enabled("balancer", LogLevel::Debug) {
+        println!("Incoming request: {:?}", tx);
+    }
+
+    let tx = Limited::new(tx.into_body(), limit)
+        .collect()
+        .await?
+        .to_bytes();
+
+    Ok(bytes_to_value(tx))
+}
+
+fn bytes_to_value(tx: Bytes) -> Value {
+    // simd_json parses in place, this only copies if the body is shared
+    let mut tx = Vec::from(tx);
+
+    match from_slice(&mut tx) {
         Ok(ret) => ret,
         Err(_) => {
             // Insane error handling
-            let ret = json!({
+            json!({
                 "id": Null,
                 "jsonrpc": "2.0",
                 "result": "Invalid JSON",
-            });
-
-            return Ok(ret);
+            })
         }
+    }
+}
+
+// Returns true if `tx` is what `bytes_to_value` returns for requests that aren't JSON
+pub fn is_parse_error(tx: &Value) -> bool {
+    tx.get("method").is_none() && tx["result"] == "Invalid JSON"
+}
+
+// Notifications are requests without an id. Per spec, they never get a response.
+//
+// Note that `"id": null` is a regular request, not a notification.
+pub fn is_notification(tx: &Value) -> bool {
+    tx.is_object() && tx.get("id").is_none()
+}
+
+// Cached responses are stored with `"id":null` as their first field.
+//
+// Replace it with `id` without parsing the (potentially huge) rest of the response.
+// Falls back to parsing if the response doesn't look like that.
+pub fn set_cached_id(cached: &[u8], id: &Value) -> Bytes {
+    const NULL_ID: &[u8] = br#"{"id":null"#;
+
+    if let Some(rest) = cached.strip_prefix(NULL_ID) {
+        let id = serde_json::to_vec(id).unwrap();
+        let mut rx = Vec::with_capacity(cached.len() + id.len());
+        rx.extend_from_slice(br#"{"id":"#);
+        rx.extend_from_slice(&id);
+        rx.extend_from_slice(rest);
+        return Bytes::from(rx);
+    }
+
+    let mut cached: Value = match serde_json::from_slice(cached) {
+        Ok(cached) => cached,
+        Err(_) => return Bytes::copy_from_slice(cached),
     };
+    cached["id"] = id.clone();
+    Bytes::from(serde_json::to_vec(&cached).unwrap())
+}
+
+// Replace the id of a response we got from an RPC with null, so it can be cached.
+//
+// Most clients return `{"jsonrpc":"2.0","id":1,...`, so we can move some bytes around
+// instead of parsing and serializing the whole response. Falls back to parsing otherwise.
+pub fn normalize_response_id(rx: &[u8], id: u64) -> Option<Vec<u8>> {
+    let prefixes = [
+        format!(r#"{{"jsonrpc":"2.0","id":{},"#, id),
+        format!(r#"{{"id":{},"jsonrpc":"2.0","#, id),
+    ];
+
+    for prefix in prefixes.iter() {
+        if let Some(rest) = rx.strip_prefix(prefix.as_bytes()) {
+            const NORMALIZED: &[u8] = br#"{"id":null,"jsonrpc":"2.0","#;
+            let mut normalized = Vec::with_capacity(NORMALIZED.len() + rest.len());
+            normalized.extend_from_slice(NORMALIZED);
+            normalized.extend_from_slice(rest);
+            return Some(normalized);
+        }
+    }
 
-    Ok(ret)
+    let mut rx = rx.to_vec();
+    let mut rx: Value = from_slice(&mut rx).ok()?;
+    rx["id"] = Null;
+    serde_json::to_vec(&rx).ok()
 }
 
 pub fn _extract_id(request: &str) -> Option<String> {
@@ -188,6 +314,63 @@ mod tests {
         assert_eq!(has_named_number("0"), NamedNumber::Null);
     }
 
+    #[test]
+    fn test_is_notification() {
+        assert!(is_notification(
+            &json!({"jsonrpc": "2.0", "method": "eth_sendRawTransaction", "params": ["0x00"]})
+        ));
+        assert!(!is_notification(
+            &json!({"jsonrpc": "2.0", "id": null, "method": "eth_chainId"})
+        ));
+        assert!(!is_notification(
+            &json!({"jsonrpc": "2.0", "id": 1, "method": "eth_chainId"})
+        ));
+    }
+
+    #[test]
+    fn test_set_cached_id() {
+        let cached = br#"{"id":null,"jsonrpc":"2.0","result":"0x1"}"#;
+        assert_eq!(
+            &set_cached_id(cached, &json!(42))[..],
+            br#"{"id":42,"jsonrpc":"2.0","result":"0x1"}"#
+        );
+        assert_eq!(
+            &set_cached_id(cached, &json!("abc"))[..],
+            br#"{"id":"abc","jsonrpc":"2.0","result":"0x1"}"#
+        );
+
+        // Entries that don't start with a null id get parsed instead
+        let cached = br#"{"jsonrpc":"2.0","id":null,"result":"0x1"}"#;
+        let rx: Value = serde_json::from_slice(&set_cached_id(cached, &json!(42))).unwrap();
+        assert_eq!(rx["id"], 42);
+        assert_eq!(rx["result"], "0x1");
+    }
+
+    #[test]
+    fn test_normalize_response_id() {
+        let rx = br#"{"jsonrpc":"2.0","id":42,"result":"0x1"}"#;
+        assert_eq!(
+            normalize_response_id(rx, 42).unwrap(),
+            br#"{"id":null,"jsonrpc":"2.0","result":"0x1"}"#
+        );
+
+        let rx = br#"{"id":42,"jsonrpc":"2.0","result":"0x1"}"#;
+        assert_eq!(
+            normalize_response_id(rx, 42).unwrap(),
+            br#"{"id":null,"jsonrpc":"2.0","result":"0x1"}"#
+        );
+
+        // Anything else gets parsed
+        let rx = br#"{"result":"0x1", "id": 42, "jsonrpc":"2.0"}"#;
+        let normalized = normalize_response_id(rx, 42).unwrap();
+        assert_eq!(
+            set_cached_id(&normalized, &json!(7)),
+            &br#"{"id":7,"jsonrpc":"2.0","result":"0x1"}"#[..]
+        );
+
+        assert!(normalize_response_id(b"not json", 42).is_none());
+    }
+
     #[test]
     fn get_block_number_from_request_test() {
         // Set up a fake NamedBlocknumbers
@@ -393,6 +576,124 @@ mod tests {
         );
     }
 
+    #[test]
+    fn get_block_number_from_heavy_request_test() {
+        let named_blocknumbers = Arc::new(RwLock::new(NamedBlocknumbers {
+            latest: 1,
+            ..Default::default()
+        }));
+
+        let request = json!({
+            "id":1,
+            "jsonrpc":"2.0",
+            "method":"eth_simulateV1",
+            "params":[{"blockStateCalls": []}, "0x10"]
+        });
+        assert_eq!(
+            get_block_number_from_request(request, &named_blocknumbers),
+            Some(16)
+        );
+
+        // No block means `latest`, which we can't pin
+        let request = json!({
+            "id":1,
+            "jsonrpc":"2.0",
+            "method":"eth_simulateV1",
+            "params":[{"blockStateCalls": []}]
+        });
+        assert_eq!(
+            get_block_number_from_request(request, &named_blocknumbers),
+            None
+        );
+
+        let request = json!({
+            "id":1,
+            "jsonrpc":"2.0",
+            "method":"eth_callMany",
+            "params":[[], {"blockNumber": "0x10", "transactionIndex": 1}]
+        });
+        assert_eq!(
+            get_block_number_from_request(request, &named_blocknumbers),
+            Some(16)
+        );
+
+        let request = json!({
+            "id":1,
+            "jsonrpc":"2.0",
+            "method":"debug_traceCall",
+            "params":[{"to": "0x00"}, "latest", {"tracer": "callTracer"}]
+        });
+        assert_eq!(
+            get_block_number_from_request(request, &named_blocknumbers),
+            Some(1)
+        );
+    }
+
+    #[test]
+    fn get_block_number_from_l2_request_test() {
+        let named_blocknumbers = Arc::new(RwLock::new(NamedBlocknumbers {
+            latest: 1,
+            pending: 5,
+            ..Default::default()
+        }));
+
+        let request = json!({
+            "id":1,
+            "jsonrpc":"2.0",
+            "method":"optimism_outputAtBlock",
+            "params":["0x10"]
+        });
+        assert_eq!(
+            get_block_number_from_request(request, &named_blocknumbers),
+            Some(16)
+        );
+
+        let request = json!({
+            "id":1,
+            "jsonrpc":"2.0",
+            "method":"zks_getBlockDetails",
+            "params":[16]
+        });
+        assert_eq!(
+            get_block_number_from_request(request, &named_blocknumbers),
+            Some(16)
+        );
+
+        let request = json!({
+            "id":1,
+            "jsonrpc":"2.0",
+            "method":"starknet_getBlockWithTxs",
+            "params":[{"block_number": 16}]
+        });
+        assert_eq!(
+            get_block_number_from_request(request, &named_blocknumbers),
+            Some(16)
+        );
+
+        let request = json!({
+            "id":1,
+            "jsonrpc":"2.0",
+            "method":"starknet_call",
+            "params":[{"contract_address": "0x1"}, "pending"]
+        });
+        assert_eq!(
+            get_block_number_from_request(request, &named_blocknumbers),
+            Some(5)
+        );
+
+        // Block hashes can't be turned into a number
+        let request = json!({
+            "id":1,
+            "jsonrpc":"2.0",
+            "method":"starknet_getStateUpdate",
+            "params":[{"block_hash": "0x1234"}]
+        });
+        assert_eq!(
+            get_block_number_from_request(request, &named_blocknumbers),
+            None
+        );
+    }
+
     #[test]
     fn replace_id_test() {
         let tx = r#"{"id":1,"jsonrpc":"2.0","method":"eth_call","params":...}"#;
@@ -450,4 +751,72 @@ mod tests {
         let tx = _extract_id(tx);
         assert_eq!(tx, None);
     }
+    // A block with `count` transactions, about 1KB each, like eth_getBlockByNumber returns
+    fn block_response(count: usize) -> Vec<u8> {
+        let transactions: Vec<Value> = (0..count)
+            .map(|i| {
+                json!({
+                    "hash": format!("0x{:064x}", i),
+                    "from": format!("0x{:040x}", i),
+                    "to": format!("0x{:040x}", i + 1),
+                    "input": format!("0x{}", "ab".repeat(400)),
+                    "nonce": format!("{:#x}", i),
+                    "value": "0x0",
+                })
+            })
+            .collect();
+        serde_json::to_vec(&json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "result": {"number": "0x10", "transactions": transactions},
+        }))
+        .unwrap()
+    }
+
+    // Time `f` over `iterations` runs, in microseconds per run
+    fn time_per_run(iterations: u32, mut f: impl FnMut()) -> f64 {
+        let start = std::time::Instant::now();
+        for _ in 0..iterations {
+            f();
+        }
+        start.elapsed().as_secs_f64() * 1e6 / iterations as f64
+    }
+
+    // Compares splicing ids in with parsing and serializing whole responses, like we
+    // used to. Run with `cargo test --release bench_response_ids -- --ignored --nocapture`.
+    #[test]
+    #[ignore]
+    fn bench_response_ids() {
+        const ITERATIONS: u32 = 2000;
+        let rx = block_response(170);
+        let cached = normalize_response_id(&rx, 1).unwrap();
+        let id = json!(7);
+
+        let parse_hit = time_per_run(ITERATIONS, || {
+            let mut cached: Value = serde_json::from_slice(&cached).unwrap();
+            cached["id"] = id.clone();
+            std::hint::black_box(cached.to_string());
+        });
+        let splice_hit = time_per_run(ITERATIONS, || {
+            std::hint::black_box(set_cached_id(&cached, &id));
+        });
+
+        let parse_insert = time_per_run(ITERATIONS, || {
+            let mut rx: Value = serde_json::from_slice(&rx).unwrap();
+            rx["id"] = Null;
+            std::hint::black_box(rx.to_string());
+        });
+        let splice_insert = time_per_run(ITERATIONS, || {
+            std::hint::black_box(normalize_response_id(&rx, 1));
+        });
+
+        println!("{}KB response, {} iterations", rx.len() / 1000, ITERATIONS);
+        println!("cache hit:    {:.1}us -> {:.1}us", parse_hit, splice_hit);
+        println!(
+            "cache insert: {:.1}us -> {:.1}us",
+            parse_insert, splice_insert
+        );
+        assert!(splice_hit < parse_hit);
+        assert!(splice_insert < parse_insert);
+    }
 }
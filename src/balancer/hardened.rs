@@ -0,0 +1,180 @@
+use crate::{
+    admin::auth::constant_time_eq,
+    config::types::HardenedSettings,
+};
+
+use hyper::HeaderMap;
+use serde_json::Value;
+
+use std::fmt;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum HardenedError {
+    MethodDenied(String),
+    KeyRequired(String),
+}
+
+impl fmt::Display for HardenedError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HardenedError::MethodDenied(method) => write!(f, "Method not allowed: {}", method),
+            HardenedError::KeyRequired(method) => {
+                write!(f, "An allow-listed API key is required for {}", method)
+            }
+        }
+    }
+}
+
+// Get the API key from the `x-api-key` header if present
+pub fn get_api_key(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get("x-api-key")
+        .and_then(|key| key.to_str().ok())
+        .map(|key| key.to_string())
+}
+
+// Check if `key` is one of `keys`, without leaking how much of it matched
+pub fn is_listed_key(key: &str, keys: &[String]) -> bool {
+    keys.iter()
+        .any(|listed| constant_time_eq(listed.as_bytes(), key.as_bytes()))
+}
+
+pub const UPSTREAM_HEADER: &str = "x-blutgang-upstream";
+
+// RPC the client wants its request sent to, from the `x-blutgang-upstream` header.
+// Only `privileged_keys` are allowed to pick one.
+pub fn get_upstream_override(
+    headers: &HeaderMap,
+    api_key: Option<&str>,
+    privileged_keys: &[String],
+) -> Result<Option<String>, HardenedError> {
+    let upstream = match headers
+        .get(UPSTREAM_HEADER)
+        .and_then(|name| name.to_str().ok())
+    {
+        Some(upstream) => upstream.trim(),
+        None => return Ok(None),
+    };
+
+    match api_key {
+        Some(key) if is_listed_key(key, privileged_keys) => Ok(Some(upstream.to_string())),
+        _ => Err(HardenedError::KeyRequired(UPSTREAM_HEADER.to_string())),
+    }
+}
+
+// Check if a single request is allowed to go through under hardened mode
+fn check_request(
+    tx: &Value,
+    api_key: Option<&str>,
+    settings: &HardenedSettings,
+) -> Result<(), HardenedError> {
+    let method = tx["method"].as_str().unwrap_or_default();
+
+    if settings
+        .denied_namespaces
+        .iter()
+        .any(|namespace| method.starts_with(namespace.as_str()))
+    {
+        return Err(HardenedError::MethodDenied(method.to_string()));
+    }
+
+    if method == "eth_sendRawTransaction"
+        && !api_key.is_some_and(|key| is_listed_key(key, &settings.allowed_keys))
+    {
+        return Err(HardenedError::KeyRequired(method.to_string()));
+    }
+
+    Ok(())
+}
+
+// Check if the request (or every request in a batch) is allowed
+pub fn check_hardened(
+    tx: &Value,
+    api_key: Option<&str>,
+    settings: &HardenedSettings,
+) -> Result<(), HardenedError> {
+    match tx.as_array() {
+        Some(batch) => {
+            batch
+                .iter()
+                .try_for_each(|tx| check_request(tx, api_key, settings))
+        }
+        None => check_request(tx, api_key, settings),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn settings() -> HardenedSettings {
+        HardenedSettings {
+            enabled: true,
+            allowed_keys: vec!["good-key".to_string()],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_upstream_override() {
+        let keys = vec!["admin".to_string()];
+        let mut headers = HeaderMap::new();
+        assert_eq!(
+            get_upstream_override(&headers, Some("admin"), &keys),
+            Ok(None)
+        );
+
+        headers.insert(UPSTREAM_HEADER, "my-reth".parse().unwrap());
+        assert_eq!(
+            get_upstream_override(&headers, Some("admin"), &keys),
+            Ok(Some("my-reth".to_string()))
+        );
+        assert!(get_upstream_override(&headers, Some("user"), &keys).is_err());
+        assert!(get_upstream_override(&headers, None, &keys).is_err());
+        // Prefixes of a key aren't the key
+        assert!(get_upstream_override(&headers, Some("adm"), &keys).is_err());
+        assert!(get_upstream_override(&headers, Some("admin2"), &keys).is_err());
+    }
+
+    #[test]
+    fn test_denied_namespaces() {
+        let settings = settings();
+
+        let tx =
+            json!({"id": 1, "jsonrpc": "2.0", "method": "debug_traceTransaction", "params": []});
+        assert_eq!(
+            check_hardened(&tx, None, &settings),
+            Err(HardenedError::MethodDenied(
+                "debug_traceTransaction".to_string()
+            ))
+        );
+
+        let tx = json!({"id": 1, "jsonrpc": "2.0", "method": "admin_peers", "params": []});
+        assert!(check_hardened(&tx, None, &settings).is_err());
+
+        let tx = json!({"id": 1, "jsonrpc": "2.0", "method": "eth_blockNumber", "params": []});
+        assert!(check_hardened(&tx, None, &settings).is_ok());
+    }
+
+    #[test]
+    fn test_send_raw_transaction_keys() {
+        let settings = settings();
+        let tx = json!({"id": 1, "jsonrpc": "2.0", "method": "eth_sendRawTransaction", "params": ["0x00"]});
+
+        assert!(check_hardened(&tx, None, &settings).is_err());
+        assert!(check_hardened(&tx, Some("bad-key"), &settings).is_err());
+        assert!(check_hardened(&tx, Some("good-key"), &settings).is_ok());
+    }
+
+    #[test]
+    fn test_batch() {
+        let settings = settings();
+        let tx = json!([
+            {"id": 1, "jsonrpc": "2.0", "method": "eth_blockNumber", "params": []},
+            {"id": 2, "jsonrpc": "2.0", "method": "debug_traceCall", "params": []},
+        ]);
+
+        assert!(check_hardened(&tx, None, &settings).is_err());
+    }
+}
@@ -0,0 +1,98 @@
+// Keeps `latest` from going backwards for a client.
+//
+// RPCs see new blocks at slightly different times, and which one answers changes from one
+// request to the next. So a client can get block 100 from one RPC and then 99 from the
+// next, which breaks anything polling for new blocks. We remember the newest block every
+// client got for `latest`, and answers older than it or the head we track get retried on
+// another RPC after a short wait. Clients are told apart by their API key, or their IP.
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{
+        Duration,
+        Instant,
+    },
+};
+
+// How long a client is remembered after its last `latest` query
+const SESSION_TTL: Duration = Duration::from_secs(300);
+
+// Wait before retrying an answer that's behind, so RPCs get a chance to catch up
+pub const WAIT: Duration = Duration::from_millis(50);
+
+#[derive(Debug, Clone, Copy)]
+struct Session {
+    // Newest block the client got for `latest`
+    block: u64,
+    seen: Instant,
+}
+
+#[derive(Debug)]
+pub struct HeadGate {
+    sessions: Mutex<HashMap<String, Session>>,
+    pruned: Mutex<Instant>,
+}
+
+impl Default for HeadGate {
+    fn default() -> Self {
+        Self {
+            sessions: Mutex::new(HashMap::new()),
+            pruned: Mutex::new(Instant::now()),
+        }
+    }
+}
+
+impl HeadGate {
+    // Oldest block `client` can get for `latest`, 0 if we don't know it
+    pub fn floor(&self, client: &str) -> u64 {
+        let sessions = self.sessions.lock().unwrap_or_else(|e| e.into_inner());
+        match sessions.get(client) {
+            Some(session) if session.seen.elapsed() < SESSION_TTL => session.block,
+            _ => 0,
+        }
+    }
+
+    // Remember that `client` got `block` for `latest`
+    pub fn advance(&self, client: &str, block: u64) {
+        let mut sessions = self.sessions.lock().unwrap_or_else(|e| e.into_inner());
+
+        // Forget clients we haven't seen in a while every now and then
+        let mut pruned = self.pruned.lock().unwrap_or_else(|e| e.into_inner());
+        if pruned.elapsed() > SESSION_TTL {
+            sessions.retain(|_, session| session.seen.elapsed() < SESSION_TTL);
+            *pruned = Instant::now();
+        }
+
+        let session = sessions.entry(client.to_string()).or_insert(Session {
+            block,
+            seen: Instant::now(),
+        });
+        if session.seen.elapsed() >= SESSION_TTL {
+            session.block = block;
+        }
+        session.block = session.block.max(block);
+        session.seen = Instant::now();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_advance() {
+        let gate = HeadGate::default();
+        assert_eq!(gate.floor("a"), 0);
+
+        gate.advance("a", 100);
+        assert_eq!(gate.floor("a"), 100);
+        // Never goes back
+        gate.advance("a", 99);
+        assert_eq!(gate.floor("a"), 100);
+        gate.advance("a", 101);
+        assert_eq!(gate.floor("a"), 101);
+
+        // Every client has its own
+        assert_eq!(gate.floor("b"), 0);
+    }
+}
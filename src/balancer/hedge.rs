@@ -0,0 +1,300 @@
+// Hedged requests, for when the RPC we picked is being slow.
+//
+// If it hasn't answered within the hedge delay, the same request goes to the next best
+// RPC too and we take whichever answer comes first. The other request gets dropped,
+// which cancels it. The delay is either fixed, or a latency quantile of the RPC we
+// picked, so we only hedge the requests that are slow for that RPC.
+//
+// Only requests that are safe to send twice get hedged.
+use crate::{
+    balancer::selection::select::{
+        pick_route,
+        Route,
+        Strategy,
+    },
+    rpc::error::RpcError,
+    Rpc,
+};
+
+use std::time::Duration;
+
+use hyper::body::Bytes;
+use memchr::memmem;
+use tokio::time::sleep;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HedgeDelay {
+    Fixed(Duration),
+    // Quantile of the latency samples of the RPC we picked, e.g. 0.95
+    Quantile(f64),
+}
+
+impl HedgeDelay {
+    // Parse a delay in ms, or a quantile like `"p95"`
+    pub fn from_config(value: &toml::Value) -> Option<Self> {
+        if let Some(ms) = value.as_integer() {
+            return (ms > 0).then(|| HedgeDelay::Fixed(Duration::from_millis(ms as u64)));
+        }
+
+        let quantile: f64 = value.as_str()?.strip_prefix('p')?.parse().ok()?;
+        (quantile > 0.0 && quantile < 100.0).then(|| HedgeDelay::Quantile(quantile / 100.0))
+    }
+
+    // How long we wait on `rpc` before hedging. None if we don't know enough about it yet.
+    pub fn for_rpc(&self, rpc: &Rpc) -> Option<Duration> {
+        match self {
+            HedgeDelay::Fixed(delay) => Some(*delay),
+            HedgeDelay::Quantile(quantile) => {
+                let mut samples: Vec<f64> = rpc.status.latency_data.iter().copied().collect();
+                if samples.is_empty() {
+                    return None;
+                }
+                samples.sort_unstable_by(f64::total_cmp);
+                let rank = (quantile * samples.len() as f64).ceil() as usize;
+                let latency = samples[rank.saturating_sub(1).min(samples.len() - 1)];
+                // Latencies are in ns
+                Some(Duration::from_nanos(latency.max(0.0) as u64))
+            }
+        }
+    }
+}
+
+// Methods that change state or depend on state kept by a single node
+const UNSAFE_PREFIXES: [&str; 5] = [
+    "eth_send",
+    "eth_sign",
+    "personal_",
+    "eth_subscribe",
+    "eth_unsubscribe",
+];
+const UNSAFE_METHODS: [&str; 9] = [
+    "eth_newFilter",
+    "eth_newBlockFilter",
+    "eth_newPendingTransactionFilter",
+    "eth_getFilterChanges",
+    "eth_getFilterLogs",
+    "eth_uninstallFilter",
+    // Solana and Bitcoin
+    "sendTransaction",
+    "sendrawtransaction",
+    "requestAirdrop",
+];
+
+// If sending `method` to two RPCs is the same as sending it to one
+pub fn can_hedge(method: &str) -> bool {
+    !UNSAFE_METHODS.contains(&method)
+        && !UNSAFE_PREFIXES
+            .iter()
+            .any(|prefix| method.starts_with(prefix))
+}
+
+// Next best RPC for `route` besides `primary`, None if there's no other one
+pub fn pick_hedge(list: &[Rpc], primary: &str, route: &Route) -> Option<(Rpc, usize)> {
+    // Picking from copies, so picking a hedge doesn't count as a pick
+    let (indices, mut others): (Vec<usize>, Vec<Rpc>) = list
+        .iter()
+        .cloned()
+        .enumerate()
+        .filter(|(_, rpc)| rpc.name != primary)
+        .unzip();
+
+    let (rpc, index) = pick_route(&mut others, route, Strategy::Latency);
+    Some((rpc, indices[index?]))
+}
+
+fn is_error(rx: &Result<Bytes, RpcError>) -> bool {
+    match rx {
+        Ok(rx) => memmem::find(rx, b"\"error\"").is_some(),
+        Err(_) => true,
+    }
+}
+
+// Send `tx` to `primary`, and to `hedge` as well if `primary` takes longer than `delay`.
+//
+// Returns the first answer that isn't an error, along with the hedge and its position
+// if the answer came from it. If both fail we return what `primary` said.
+pub async fn send_hedged(
+    primary: &Rpc,
+    pick: impl FnOnce() -> Option<(Rpc, usize)>,
+    tx: Bytes,
+    delay: Duration,
+) -> (Result<Bytes, RpcError>, Option<(Rpc, usize)>) {
+    let first = primary.send_raw(tx.clone());
+    tokio::pin!(first);
+
+    tokio::select! {
+        rx = &mut first => return (rx, None),
+        _ = sleep(delay) => {},
+    }
+
+    let (hedge, position) = match pick() {
+        Some(hedge) => hedge,
+        None => return (first.await, None),
+    };
+    let second = hedge.send_raw(tx);
+    tokio::pin!(second);
+
+    tokio::select! {
+        rx = &mut first => {
+            if !is_error(&rx) {
+                return (rx, None);
+            }
+            let hedged = second.await;
+            match is_error(&hedged) {
+                true => (rx, None),
+                false => (hedged, Some((hedge.clone(), position))),
+            }
+        }
+        hedged = &mut second => {
+            if !is_error(&hedged) {
+                return (hedged, Some((hedge.clone(), position)));
+            }
+            (first.await, None)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_config() {
+        assert_eq!(
+            HedgeDelay::from_config(&toml::Value::Integer(50)),
+            Some(HedgeDelay::Fixed(Duration::from_millis(50)))
+        );
+        assert_eq!(
+            HedgeDelay::from_config(&toml::Value::String("p95".to_string())),
+            Some(HedgeDelay::Quantile(0.95))
+        );
+        assert_eq!(HedgeDelay::from_config(&toml::Value::Integer(0)), None);
+        assert_eq!(
+            HedgeDelay::from_config(&toml::Value::String("p100".to_string())),
+            None
+        );
+        assert_eq!(
+            HedgeDelay::from_config(&toml::Value::String("fast".to_string())),
+            None
+        );
+    }
+
+    #[test]
+    fn test_for_rpc() {
+        let mut rpc = Rpc::default();
+        assert_eq!(HedgeDelay::Quantile(0.9).for_rpc(&rpc), None);
+
+        rpc.status.latency_data = (1..=10).map(|ms| ms as f64 * 1e6).collect();
+        assert_eq!(
+            HedgeDelay::Quantile(0.9).for_rpc(&rpc),
+            Some(Duration::from_millis(9))
+        );
+        assert_eq!(
+            HedgeDelay::Fixed(Duration::from_millis(3)).for_rpc(&rpc),
+            Some(Duration::from_millis(3))
+        );
+    }
+
+    #[test]
+    fn test_can_hedge() {
+        assert!(can_hedge("eth_call"));
+        assert!(can_hedge("eth_getLogs"));
+        assert!(!can_hedge("eth_sendRawTransaction"));
+        assert!(!can_hedge("eth_getFilterChanges"));
+        assert!(!can_hedge("sendTransaction"));
+    }
+
+    // RPC that answers every request with `result` after `delay`
+    async fn upstream(name: &str, result: &'static str, delay: Duration) -> Rpc {
+        use tokio::{
+            io::{
+                AsyncReadExt,
+                AsyncWriteExt,
+            },
+            net::TcpListener,
+        };
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::task::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                tokio::task::spawn(async move {
+                    let mut buf = vec![0; 4096];
+                    let _ = stream.read(&mut buf).await;
+                    sleep(delay).await;
+                    let body = format!(r#"{{"jsonrpc":"2.0","id":1,"result":"{}"}}"#, result);
+                    let rx = format!(
+                        "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\n\r\n{}",
+                        body.len(),
+                        body
+                    );
+                    let _ = stream.write_all(rx.as_bytes()).await;
+                });
+            }
+        });
+
+        let mut rpc = Rpc::new(format!("http://{}", address), 10, 10.0);
+        rpc.name = name.to_string();
+        rpc
+    }
+
+    #[tokio::test]
+    async fn test_send_hedged() {
+        let tx = Bytes::from_static(br#"{"jsonrpc":"2.0","id":1,"method":"eth_call","params":[]}"#);
+        let slow = upstream("slow", "slow", Duration::from_millis(500)).await;
+        let fast = upstream("fast", "fast", Duration::ZERO).await;
+
+        let (rx, hedge) = send_hedged(
+            &slow,
+            || Some((fast.clone(), 1)),
+            tx.clone(),
+            Duration::from_millis(20),
+        )
+        .await;
+        assert!(memmem::find(&rx.unwrap(), b"\"fast\"").is_some());
+        assert_eq!(
+            hedge.map(|(rpc, position)| (rpc.name, position)),
+            Some(("fast".to_string(), 1))
+        );
+
+        // Answers before the delay never get hedged
+        let (rx, hedge) = send_hedged(
+            &fast,
+            || panic!("hedged a fast request"),
+            tx.clone(),
+            Duration::from_millis(200),
+        )
+        .await;
+        assert!(memmem::find(&rx.unwrap(), b"\"fast\"").is_some());
+        assert!(hedge.is_none());
+
+        // Nothing to hedge with, we wait for the primary
+        let (rx, hedge) = send_hedged(&slow, || None, tx, Duration::from_millis(20)).await;
+        assert!(memmem::find(&rx.unwrap(), b"\"slow\"").is_some());
+        assert!(hedge.is_none());
+    }
+
+    #[test]
+    fn test_pick_hedge() {
+        let mut fast = Rpc::default();
+        fast.name = "fast".to_string();
+        fast.status.latency = 1.0;
+        let mut slow = Rpc::default();
+        slow.name = "slow".to_string();
+        slow.status.latency = 5.0;
+        let mut slower = Rpc::default();
+        slower.name = "slower".to_string();
+        slower.status.latency = 10.0;
+        let mut list = vec![fast, slow, slower];
+        for rpc in list.iter_mut() {
+            rpc.max_consecutive = 10;
+        }
+
+        let (rpc, index) = pick_hedge(&list, "fast", &Route::Any).unwrap();
+        assert_eq!((rpc.name.as_str(), index), ("slow", 1));
+        let (rpc, index) = pick_hedge(&list, "slow", &Route::Any).unwrap();
+        assert_eq!((rpc.name.as_str(), index), ("fast", 0));
+        assert!(pick_hedge(&list[..1], "fast", &Route::Any).is_none());
+    }
+}
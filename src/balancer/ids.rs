@@ -0,0 +1,161 @@
+// JSON-RPC ids can be numbers, strings or null, and clients pick them freely.
+// Two clients using the same id can't share an upstream request, so every request we
+// send gets an internal id and the original one gets restored in the response.
+use serde_json::{
+    json,
+    Value,
+};
+use std::{
+    collections::HashMap,
+    sync::atomic::{
+        AtomicU64,
+        Ordering,
+    },
+};
+
+// Internal ids are never reused, so they can't collide with each other.
+//
+// Starts at 1 so we never send id 0, which some clients treat as missing.
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+// Allocate a new internal id
+pub fn next_id() -> u64 {
+    NEXT_ID.fetch_add(1, Ordering::Relaxed)
+}
+
+// Keeps track of the original ids of a batch of requests
+#[derive(Debug, Default)]
+pub struct IdRemap {
+    // Internal id -> (position in the batch, original id)
+    original: HashMap<u64, (usize, Value)>,
+    len: usize,
+}
+
+impl IdRemap {
+    // Replace the id of every request in `batch` with an internal one.
+    //
+    // Notifications (requests without an id) are sent as they are, since they have no response.
+    pub fn remap(batch: &mut [Value]) -> Self {
+        let mut remap = IdRemap {
+            original: HashMap::with_capacity(batch.len()),
+            len: batch.len(),
+        };
+
+        for (position, request) in batch.iter_mut().enumerate() {
+            let original = match request.get_mut("id") {
+                Some(id) => id.take(),
+                None => continue,
+            };
+
+            let internal = next_id();
+            request["id"] = internal.into();
+            remap.original.insert(internal, (position, original));
+        }
+
+        remap
+    }
+
+    // Restore the original ids of `responses`, in the same order the requests were in.
+    //
+    // Responses with ids we didn't send are dropped. Requests that never got a
+    // response get an error so the client doesn't wait for them forever.
+    pub fn restore(mut self, responses: Vec<Value>) -> Vec<Value> {
+        let mut restored: Vec<Option<Value>> = vec![None; self.len];
+
+        for mut response in responses {
+            let internal = match response["id"].as_u64() {
+                Some(internal) => internal,
+                None => continue,
+            };
+
+            if let Some((position, original)) = self.original.remove(&internal) {
+                response["id"] = original;
+                restored[position] = Some(response);
+            }
+        }
+
+        for (_, (position, original)) in self.original.drain() {
+            restored[position] = Some(json!({
+                "jsonrpc": "2.0",
+                "id": original,
+                "error": {
+                    "code": -32603,
+                    "message": "error: No response from RPC",
+                },
+            }));
+        }
+
+        restored.into_iter().flatten().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_next_id_unique() {
+        let a = next_id();
+        let b = next_id();
+        assert_ne!(a, b);
+        assert_ne!(a, 0);
+    }
+
+    #[test]
+    fn test_remap_restore() {
+        // Colliding, string, null and missing ids
+        let mut batch = vec![
+            json!({"jsonrpc": "2.0", "id": 1, "method": "eth_chainId"}),
+            json!({"jsonrpc": "2.0", "id": 1, "method": "eth_blockNumber"}),
+            json!({"jsonrpc": "2.0", "id": "abc", "method": "eth_gasPrice"}),
+            json!({"jsonrpc": "2.0", "id": null, "method": "net_version"}),
+            json!({"jsonrpc": "2.0", "method": "eth_subscribe"}),
+        ];
+        let remap = IdRemap::remap(&mut batch);
+
+        let internal: Vec<u64> = batch[..4]
+            .iter()
+            .map(|request| request["id"].as_u64().unwrap())
+            .collect();
+        assert_ne!(internal[0], internal[1]);
+        assert!(batch[4].get("id").is_none());
+
+        // Responses come back out of order, with one we didn't ask for
+        let responses = vec![
+            json!({"jsonrpc": "2.0", "id": internal[2], "result": "0x3"}),
+            json!({"jsonrpc": "2.0", "id": internal[0], "result": "0x1"}),
+            json!({"jsonrpc": "2.0", "id": 999_999_999_999u64, "result": "0x9"}),
+            json!({"jsonrpc": "2.0", "id": internal[3], "result": "0x4"}),
+            json!({"jsonrpc": "2.0", "id": internal[1], "result": "0x2"}),
+        ];
+        let restored = remap.restore(responses);
+
+        assert_eq!(restored.len(), 4);
+        assert_eq!(
+            restored[0],
+            json!({"jsonrpc": "2.0", "id": 1, "result": "0x1"})
+        );
+        assert_eq!(
+            restored[1],
+            json!({"jsonrpc": "2.0", "id": 1, "result": "0x2"})
+        );
+        assert_eq!(
+            restored[2],
+            json!({"jsonrpc": "2.0", "id": "abc", "result": "0x3"})
+        );
+        assert_eq!(
+            restored[3],
+            json!({"jsonrpc": "2.0", "id": null, "result": "0x4"})
+        );
+    }
+
+    #[test]
+    fn test_restore_missing() {
+        let mut batch = vec![json!({"jsonrpc": "2.0", "id": "a", "method": "eth_chainId"})];
+        let remap = IdRemap::remap(&mut batch);
+
+        let restored = remap.restore(vec![]);
+        assert_eq!(restored[0]["id"], "a");
+        assert_eq!(restored[0]["error"]["code"], -32603);
+    }
+}
@@ -0,0 +1,219 @@
+// Some responses never change once they exist: a block looked up by its hash, or a
+// transaction and its receipt once the block they're in is finalized.
+//
+// These are keyed by the hash they're looked up with instead of by the request,
+// and we only store the result, since that's all that matters for them. They're never
+// added to the head cache, so they stay cached until the DB gets flushed.
+use crate::rpc::types::hex_to_decimal;
+
+use hyper::body::Bytes;
+use serde_json::Value;
+
+// Prefix of immutable entries. Request hashes are 32 or 8 bytes,
+// so our 34 byte keys can't collide with them.
+const PREFIX: u8 = b'i';
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ImmutableMethod {
+    TransactionByHash = 1,
+    TransactionReceipt = 2,
+    BlockByHash = 3,
+    FullBlockByHash = 4,
+    BlockReceipts = 5,
+}
+
+impl ImmutableMethod {
+    fn from_key(key: &[u8]) -> Option<Self> {
+        match key.get(1)? {
+            1 => Some(ImmutableMethod::TransactionByHash),
+            2 => Some(ImmutableMethod::TransactionReceipt),
+            3 => Some(ImmutableMethod::BlockByHash),
+            4 => Some(ImmutableMethod::FullBlockByHash),
+            5 => Some(ImmutableMethod::BlockReceipts),
+            _ => None,
+        }
+    }
+}
+
+fn immutable_method(tx: &Value) -> Option<ImmutableMethod> {
+    match tx["method"].as_str()? {
+        "eth_getTransactionByHash" => Some(ImmutableMethod::TransactionByHash),
+        "eth_getTransactionReceipt" => Some(ImmutableMethod::TransactionReceipt),
+        "eth_getBlockByHash" if tx["params"][1] == true => Some(ImmutableMethod::FullBlockByHash),
+        "eth_getBlockByHash" if tx["params"][1] == false => Some(ImmutableMethod::BlockByHash),
+        "eth_getBlockReceipts" => Some(ImmutableMethod::BlockReceipts),
+        _ => None,
+    }
+}
+
+// Decode a `0x` prefixed 32 byte hash
+fn decode_hash(hash: &str) -> Option<[u8; 32]> {
+    let hash = hash.strip_prefix("0x")?;
+    if hash.len() != 64 {
+        return None;
+    }
+
+    let mut bytes = [0; 32];
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(hash.get(i * 2..i * 2 + 2)?, 16).ok()?;
+    }
+
+    Some(bytes)
+}
+
+// Key of the immutable entry for `tx`, if it's a request whose result can be immutable.
+//
+// Only requests looked up by a hash qualify, e.g. `eth_getBlockReceipts` by number doesn't.
+pub fn immutable_key(tx: &Value) -> Option<Vec<u8>> {
+    if cfg!(feature = "no-cache") {
+        return None;
+    }
+
+    let method = immutable_method(tx)?;
+    let hash = decode_hash(tx["params"][0].as_str()?)?;
+
+    let mut key = Vec::with_capacity(34);
+    key.push(PREFIX);
+    key.push(method as u8);
+    key.extend_from_slice(&hash);
+
+    Some(key)
+}
+
+// Returns true if `rx`, the response to the request with the immutable `key`, can be cached forever.
+//
+// Blocks are committed to by their hash, so they can't change once they exist.
+// Transactions can still end up in a different block until the one they're in is finalized.
+pub fn is_immutable(key: &[u8], rx: &Value, finalized: u64) -> bool {
+    if !rx["error"].is_null() {
+        return false;
+    }
+
+    let result = &rx["result"];
+    match ImmutableMethod::from_key(key) {
+        Some(ImmutableMethod::BlockByHash)
+        | Some(ImmutableMethod::FullBlockByHash)
+        | Some(ImmutableMethod::BlockReceipts) => {
+            result.is_object()
+                || result
+                    .as_array()
+                    .is_some_and(|receipts| !receipts.is_empty())
+        }
+        Some(ImmutableMethod::TransactionByHash) | Some(ImmutableMethod::TransactionReceipt) => {
+            result["blockNumber"]
+                .as_str()
+                .and_then(|number| hex_to_decimal(number).ok())
+                .is_some_and(|number| number <= finalized)
+        }
+        None => false,
+    }
+}
+
+// Method and block of the immutable entry `key` for `rx`, so it can be flushed by block
+pub fn indexed_block(key: &[u8], rx: &Value) -> Option<(&'static str, u64)> {
+    let method = match ImmutableMethod::from_key(key)? {
+        ImmutableMethod::TransactionByHash => "eth_getTransactionByHash",
+        ImmutableMethod::TransactionReceipt => "eth_getTransactionReceipt",
+        ImmutableMethod::BlockByHash | ImmutableMethod::FullBlockByHash => "eth_getBlockByHash",
+        ImmutableMethod::BlockReceipts => "eth_getBlockReceipts",
+    };
+
+    let result = &rx["result"];
+    let number = result["blockNumber"]
+        .as_str()
+        .or_else(|| result["number"].as_str())
+        .or_else(|| result[0]["blockNumber"].as_str())?;
+    Some((method, hex_to_decimal(number).ok()?))
+}
+
+// Only the result is stored
+pub fn compact(rx: &Value) -> Vec<u8> {
+    serde_json::to_vec(&rx["result"]).unwrap()
+}
+
+// Turn a stored result back into a response with `id`
+pub fn expand(compact: &[u8], id: &Value) -> Bytes {
+    let id = serde_json::to_vec(id).unwrap();
+
+    let mut rx = Vec::with_capacity(compact.len() + id.len() + 34);
+    rx.extend_from_slice(br#"{"id":"#);
+    rx.extend_from_slice(&id);
+    rx.extend_from_slice(br#","jsonrpc":"2.0","result":"#);
+    rx.extend_from_slice(compact);
+    rx.push(b'}');
+
+    Bytes::from(rx)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const HASH: &str = "0x88df016429689c079f3b2f6ad39fa052532c56795b733da78a91ebe6a713944b";
+
+    fn request(method: &str, params: Value) -> Value {
+        json!({"id": 1, "jsonrpc": "2.0", "method": method, "params": params})
+    }
+
+    #[test]
+    fn test_immutable_key() {
+        let tx = request("eth_getTransactionByHash", json!([HASH]));
+        let key = immutable_key(&tx).unwrap();
+        assert_eq!(key.len(), 34);
+        assert_eq!(key[2], 0x88);
+
+        // Hashes-only and full blocks are different entries
+        let hashes = immutable_key(&request("eth_getBlockByHash", json!([HASH, false])));
+        let full = immutable_key(&request("eth_getBlockByHash", json!([HASH, true])));
+        assert_ne!(hashes, full);
+
+        assert_eq!(
+            immutable_key(&request("eth_getBlockReceipts", json!(["0x10"]))),
+            None
+        );
+        assert_eq!(
+            immutable_key(&request("eth_getBalance", json!([HASH, "0x10"]))),
+            None
+        );
+    }
+
+    #[test]
+    fn test_is_immutable() {
+        let key = immutable_key(&request("eth_getTransactionReceipt", json!([HASH]))).unwrap();
+        let rx = json!({"id": 1, "jsonrpc": "2.0", "result": {"blockNumber": "0x10"}});
+        assert!(is_immutable(&key, &rx, 16));
+        assert!(!is_immutable(&key, &rx, 15));
+
+        // Not mined yet
+        let rx = json!({"id": 1, "jsonrpc": "2.0", "result": null});
+        assert!(!is_immutable(&key, &rx, 16));
+
+        // Blocks are immutable regardless of finality
+        let key = immutable_key(&request("eth_getBlockByHash", json!([HASH, false]))).unwrap();
+        let rx = json!({"id": 1, "jsonrpc": "2.0", "result": {"number": "0x10"}});
+        assert!(is_immutable(&key, &rx, 0));
+    }
+
+    #[test]
+    fn test_indexed_block() {
+        let key = immutable_key(&request("eth_getBlockByHash", json!([HASH, true]))).unwrap();
+        let rx = json!({"id": 1, "jsonrpc": "2.0", "result": {"number": "0x10"}});
+        assert_eq!(indexed_block(&key, &rx), Some(("eth_getBlockByHash", 16)));
+
+        let key = immutable_key(&request("eth_getBlockReceipts", json!([HASH]))).unwrap();
+        let rx = json!({"id": 1, "jsonrpc": "2.0", "result": [{"blockNumber": "0x11"}]});
+        assert_eq!(indexed_block(&key, &rx), Some(("eth_getBlockReceipts", 17)));
+    }
+
+    #[test]
+    fn test_compact_roundtrip() {
+        let rx = json!({"id": null, "jsonrpc": "2.0", "result": {"blockNumber": "0x10"}});
+        let expanded = expand(&compact(&rx), &json!("abc"));
+        let expanded: Value = serde_json::from_slice(&expanded).unwrap();
+        assert_eq!(
+            expanded,
+            json!({"id": "abc", "jsonrpc": "2.0", "result": {"blockNumber": "0x10"}})
+        );
+    }
+}
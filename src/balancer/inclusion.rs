@@ -0,0 +1,176 @@
+// Time-to-inclusion of transactions broadcast through us.
+//
+// We remember which RPC each `eth_sendRawTransaction` went through, and look for the
+// transactions in every new block. Times are measured until we see the block, so they
+// include up to one `health_check_ttl` of delay on our side, the same for every RPC.
+use crate::{
+    rpc::{
+        inclusion::InclusionStats,
+        types::first_result,
+    },
+    Rpc,
+};
+
+use std::{
+    collections::HashMap,
+    sync::{
+        Arc,
+        Mutex,
+        RwLock,
+    },
+    time::{
+        Duration,
+        Instant,
+    },
+};
+
+use serde_json::{
+    json,
+    Value,
+};
+use tokio::sync::watch;
+
+// Transactions not included after this long count as dropped
+const PENDING_TTL: Duration = Duration::from_secs(30 * 60);
+
+// Blocks we look through at most when the head jumps ahead
+const MAX_BLOCKS: u64 = 8;
+
+#[derive(Debug)]
+struct Broadcast {
+    sent: Instant,
+    stats: Arc<InclusionStats>,
+}
+
+#[derive(Debug, Default)]
+pub struct InclusionTracker {
+    // Transaction hash -> when and where we broadcast it
+    pending: Mutex<HashMap<String, Broadcast>>,
+}
+
+impl InclusionTracker {
+    // Start timing the transaction `eth_sendRawTransaction` returned the hash of.
+    //
+    // Broadcasting the same transaction again keeps timing from the first broadcast.
+    pub fn record_broadcast(&self, rx: &[u8], stats: &Arc<InclusionStats>) {
+        let rx: Value = match serde_json::from_slice(rx) {
+            Ok(rx) => rx,
+            Err(_) => return,
+        };
+        let hash = match rx["result"].as_str() {
+            Some(hash) => hash.to_lowercase(),
+            None => return,
+        };
+
+        self.pending.lock().unwrap().entry(hash).or_insert_with(|| {
+            Broadcast {
+                sent: Instant::now(),
+                stats: Arc::clone(stats),
+            }
+        });
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.lock().unwrap().is_empty()
+    }
+
+    // Record the transactions we broadcast that are in a block we saw at `seen`
+    pub fn record_block(&self, hashes: &[Value], seen: Instant) {
+        let mut pending = self.pending.lock().unwrap();
+        for hash in hashes.iter().filter_map(|hash| hash.as_str()) {
+            if let Some(broadcast) = pending.remove(&hash.to_lowercase()) {
+                broadcast
+                    .stats
+                    .record_included(seen.saturating_duration_since(broadcast.sent));
+            }
+        }
+    }
+
+    // Give up on transactions that have been pending for too long
+    pub fn expire(&self) {
+        self.pending.lock().unwrap().retain(|_, broadcast| {
+            let keep = broadcast.sent.elapsed() < PENDING_TTL;
+            if !keep {
+                broadcast.stats.record_dropped();
+            }
+            keep
+        });
+    }
+}
+
+// Hashes of the transactions in block `number`, from the first RPC that has it
+async fn block_transactions(
+    rpc_list: &Arc<RwLock<Vec<Rpc>>>,
+    number: u64,
+    ttl: Duration,
+) -> Option<Vec<Value>> {
+    let tx = json!({
+        "id": 1,
+        "jsonrpc": "2.0",
+        "method": "eth_getBlockByNumber",
+        "params": [format!("{:#x}", number), false],
+    });
+
+    match first_result(rpc_list, &tx, ttl).await?["transactions"].take() {
+        Value::Array(hashes) => Some(hashes),
+        _ => None,
+    }
+}
+
+pub async fn track_inclusion(
+    tracker: Arc<InclusionTracker>,
+    rpc_list: Arc<RwLock<Vec<Rpc>>>,
+    mut blocknum_rx: watch::Receiver<u64>,
+    ttl: Duration,
+) {
+    let mut last = *blocknum_rx.borrow();
+    while blocknum_rx.changed().await.is_ok() {
+        let head = *blocknum_rx.borrow_and_update();
+        let seen = Instant::now();
+
+        tracker.expire();
+        if head > last && last > 0 && !tracker.is_empty() {
+            let first = (last + 1).max(head.saturating_sub(MAX_BLOCKS - 1));
+            for number in first..=head {
+                match block_transactions(&rpc_list, number, ttl).await {
+                    Some(hashes) => tracker.record_block(&hashes, seen),
+                    None => {
+                        println!(
+                            "\x1b[93mWrn:\x1b[0m Could not get block {} to look for broadcast transactions!",
+                            number
+                        );
+                    }
+                }
+            }
+        }
+
+        last = head;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_block() {
+        let tracker = InclusionTracker::default();
+        let fast = Arc::new(InclusionStats::default());
+        let slow = Arc::new(InclusionStats::default());
+
+        tracker.record_broadcast(br#"{"id":1,"jsonrpc":"2.0","result":"0xAA"}"#, &fast);
+        tracker.record_broadcast(br#"{"id":1,"jsonrpc":"2.0","result":"0xbb"}"#, &slow);
+        // Rebroadcasts and errors don't change anything
+        tracker.record_broadcast(br#"{"id":1,"jsonrpc":"2.0","result":"0xaa"}"#, &slow);
+        tracker.record_broadcast(br#"{"id":1,"jsonrpc":"2.0","error":{"code":-1}}"#, &slow);
+
+        tracker.record_block(&[json!("0xaa"), json!("0xcc")], Instant::now());
+        assert_eq!(fast.to_json()["included"], 1);
+        assert_eq!(slow.to_json()["included"], 0);
+        assert!(!tracker.is_empty());
+
+        tracker.record_block(&[json!("0xBB")], Instant::now());
+        assert_eq!(slow.to_json()["included"], 1);
+        assert!(tracker.is_empty());
+    }
+}
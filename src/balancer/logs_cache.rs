@@ -0,0 +1,277 @@
+// `eth_getLogs` results are cached in chunks of blocks, so queries that only partly
+// overlap with ones we've seen before are served from the cache where they overlap.
+//
+// Ranges are split on `logs_cache_chunk` aligned boundaries. Whole chunks at or below
+// the finalized block are looked up in the cache, and everything else is fetched in
+// as few upstream requests as possible, one for every gap between cached chunks. Logs
+// of finalized blocks can't change, so chunks are never added to the head cache.
+use crate::{
+    balancer::block_range::parse_block,
+    NamedBlocknumbers,
+};
+
+use serde_json::{
+    json,
+    Map,
+    Value,
+};
+
+// Prefix of log chunk entries. Their keys are 41 bytes, so they can't collide
+// with request hashes (32 or 8 bytes) or immutable entries (34 bytes).
+const PREFIX: u8 = b'l';
+
+// Gaps we fetch at the same time
+pub const CONCURRENCY: usize = 8;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LogsQuery {
+    // The filter without `fromBlock` and `toBlock`
+    pub filter: Map<String, Value>,
+    pub from: u64,
+    pub to: u64,
+}
+
+// Part of a query, either a whole cacheable chunk or a piece we always fetch
+#[derive(Debug, Clone, PartialEq)]
+pub struct Segment {
+    pub from: u64,
+    pub to: u64,
+    // Cache key, if this is a whole finalized chunk
+    pub key: Option<Vec<u8>>,
+}
+
+// Returns None if `tx` isn't an `eth_getLogs` request over a range of blocks we know
+pub fn parse_logs_query(tx: &Value, named_numbers: &NamedBlocknumbers) -> Option<LogsQuery> {
+    if cfg!(feature = "no-cache") || tx["method"] != "eth_getLogs" {
+        return None;
+    }
+
+    let mut filter = tx["params"][0].as_object()?.clone();
+    // Filters by block hash are a single block, there's nothing to split
+    if filter.contains_key("blockHash") {
+        return None;
+    }
+
+    let latest = Value::from("latest");
+    let from = filter.remove("fromBlock").unwrap_or_else(|| latest.clone());
+    let to = filter.remove("toBlock").unwrap_or(latest);
+    let from = parse_block(&from, named_numbers).ok()?;
+    let to = parse_block(&to, named_numbers).ok()?;
+    if from > to {
+        return None;
+    }
+
+    Some(LogsQuery { filter, from, to })
+}
+
+// Key of the chunk of `chunk` blocks starting at `start` for `filter`
+fn chunk_key(filter: &Map<String, Value>, chunk: u64, start: u64) -> Vec<u8> {
+    let filter = json!({"filter": filter, "chunk": chunk});
+    let hash = blake3::hash(&serde_json::to_vec(&filter).unwrap());
+
+    let mut key = Vec::with_capacity(41);
+    key.push(PREFIX);
+    key.extend_from_slice(hash.as_bytes());
+    key.extend_from_slice(&start.to_be_bytes());
+    key
+}
+
+// Split `query` into chunks of `chunk` blocks. Chunks the query doesn't fully
+// cover, or past `finalized`, don't get a key.
+pub fn segments(query: &LogsQuery, chunk: u64, finalized: u64) -> Vec<Segment> {
+    let mut segments = Vec::new();
+    let mut from = query.from;
+
+    loop {
+        let start = from - from % chunk;
+        let end = start.saturating_add(chunk - 1);
+        let to = end.min(query.to);
+        let whole = from == start && to == end && end <= finalized;
+
+        segments.push(Segment {
+            from,
+            to,
+            key: whole.then(|| chunk_key(&query.filter, chunk, start)),
+        });
+
+        if to == query.to {
+            return segments;
+        }
+        from = to + 1;
+    }
+}
+
+// Ranges of consecutive segments we don't have, as `start..end` indexes into `cached`
+pub fn gaps<T>(cached: &[Option<T>]) -> Vec<std::ops::Range<usize>> {
+    let mut gaps = Vec::new();
+    let mut start = None;
+
+    for (index, part) in cached.iter().enumerate() {
+        match (part, start) {
+            (None, None) => start = Some(index),
+            (Some(_), Some(gap)) => {
+                gaps.push(gap..index);
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    if let Some(gap) = start {
+        gaps.push(gap..cached.len());
+    }
+
+    gaps
+}
+
+// `eth_getLogs` request for `filter` over `from..=to`
+pub fn logs_request(filter: &Map<String, Value>, from: u64, to: u64) -> Value {
+    let mut filter = filter.clone();
+    filter.insert("fromBlock".to_string(), format!("{:#x}", from).into());
+    filter.insert("toBlock".to_string(), format!("{:#x}", to).into());
+
+    json!({
+        "id": null,
+        "jsonrpc": "2.0",
+        "method": "eth_getLogs",
+        "params": [filter],
+    })
+}
+
+// Index of the segment `log` is in
+fn segment_of(log: &Value, segments: &[Segment]) -> Option<usize> {
+    let number = parse_block(&log["blockNumber"], &NamedBlocknumbers::default()).ok()?;
+    let index = segments.partition_point(|segment| segment.to < number);
+    (segments.get(index)?.from <= number).then_some(index)
+}
+
+// Split the logs of a gap between its `segments`.
+//
+// Gives the logs back if one isn't in any of them, since we'd cache wrong results then.
+pub fn split_logs(logs: Vec<Value>, segments: &[Segment]) -> Result<Vec<Vec<Value>>, Vec<Value>> {
+    let indexes: Option<Vec<usize>> = logs.iter().map(|log| segment_of(log, segments)).collect();
+    let indexes = match indexes {
+        Some(indexes) => indexes,
+        None => return Err(logs),
+    };
+
+    let mut parts = vec![Vec::new(); segments.len()];
+    for (log, index) in logs.into_iter().zip(indexes) {
+        parts[index].push(log);
+    }
+
+    Ok(parts)
+}
+
+pub fn logs_response(id: &Value, logs: Vec<Value>) -> Value {
+    json!({
+        "id": id,
+        "jsonrpc": "2.0",
+        "result": logs,
+    })
+}
+
+pub fn logs_error(id: &Value, message: &str) -> Value {
+    json!({
+        "id": id,
+        "jsonrpc": "2.0",
+        "error": {
+            "code": -32603,
+            "message": message,
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn named_numbers() -> NamedBlocknumbers {
+        NamedBlocknumbers {
+            latest: 1000,
+            earliest: 0,
+            safe: 990,
+            finalized: 980,
+            pending: 1001,
+            number: 1000,
+        }
+    }
+
+    fn query(from: u64, to: u64) -> LogsQuery {
+        LogsQuery {
+            filter: Map::new(),
+            from,
+            to,
+        }
+    }
+
+    #[test]
+    fn test_parse_logs_query() {
+        let tx = json!({"id": 1, "jsonrpc": "2.0", "method": "eth_getLogs", "params": [{
+            "address": "0xabc",
+            "fromBlock": "0x10",
+        }]});
+        let query = parse_logs_query(&tx, &named_numbers()).unwrap();
+        assert_eq!((query.from, query.to), (16, 1000));
+        assert_eq!(Value::Object(query.filter), json!({"address": "0xabc"}));
+
+        for params in [
+            json!([{"blockHash": "0x01"}]),
+            json!([{"fromBlock": "pending"}]),
+            json!([{"fromBlock": "0x20", "toBlock": "0x10"}]),
+            json!([]),
+        ] {
+            let tx = json!({"id": 1, "jsonrpc": "2.0", "method": "eth_getLogs", "params": params});
+            assert_eq!(parse_logs_query(&tx, &named_numbers()), None);
+        }
+    }
+
+    #[test]
+    fn test_segments() {
+        let segments = segments(&query(150, 420), 100, 350);
+        let ranges: Vec<(u64, u64, bool)> = segments
+            .iter()
+            .map(|segment| (segment.from, segment.to, segment.key.is_some()))
+            .collect();
+        assert_eq!(
+            ranges,
+            [
+                (150, 199, false),
+                (200, 299, true),
+                // Not finalized yet
+                (300, 399, false),
+                (400, 420, false),
+            ]
+        );
+
+        // Same chunk of a different filter
+        let mut other = query(200, 299);
+        other.filter.insert("address".to_string(), "0xabc".into());
+        assert_ne!(super::segments(&other, 100, 350)[0].key, segments[1].key);
+        assert_eq!(super::segments(&query(200, 299), 100, 350)[0], segments[1]);
+
+        assert_eq!(super::segments(&query(5, 5), 100, 350).len(), 1);
+    }
+
+    #[test]
+    fn test_gaps() {
+        assert_eq!(gaps(&[None, Some(1), None, None, Some(2)]), [0..1, 2..4]);
+        assert_eq!(gaps(&[Some(1), None, Some(2), None]), [1..2, 3..4]);
+        assert!(gaps(&[Some(1), Some(2)]).is_empty());
+    }
+
+    #[test]
+    fn test_split_logs() {
+        let segments = segments(&query(100, 299), 100, 1000);
+        let logs = vec![
+            json!({"blockNumber": "0x64", "logIndex": "0x0"}),
+            json!({"blockNumber": "0x12b", "logIndex": "0x0"}),
+        ];
+
+        let parts = split_logs(logs, &segments).unwrap();
+        assert_eq!(parts[0].len(), 1);
+        assert_eq!(parts[1].len(), 1);
+
+        let outside = vec![json!({"blockNumber": "0x1000"})];
+        assert_eq!(split_logs(outside.clone(), &segments), Err(outside));
+    }
+}
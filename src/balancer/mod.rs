@@ -1,4 +1,38 @@
 pub mod accept_http;
+pub mod admission;
+pub mod await_block;
+pub mod block_cache;
+pub mod block_index;
+pub mod block_range;
+pub mod broadcast;
+pub mod chains;
+pub mod classify;
+pub mod coalesce;
+pub mod codec;
+pub mod dedup;
+pub mod encoding;
+pub mod filters;
 pub mod format;
+pub mod hardened;
+pub mod head_gate;
+pub mod hedge;
+pub mod ids;
+pub mod immutable;
+pub mod inclusion;
+pub mod logs_cache;
+pub mod overrides;
+pub mod pacing;
+pub mod panic_guard;
+pub mod provenance;
+pub mod quorum;
 mod response_errors;
+pub mod rewrite;
+pub mod schema;
 pub mod selection;
+pub mod send_queue;
+pub mod stale;
+pub mod static_responses;
+pub mod strict;
+pub mod tx_queue;
+pub mod tx_tracker;
+pub mod upstream_batch;
@@ -0,0 +1,199 @@
+use crate::Rpc;
+
+use serde_json::{
+    Map,
+    Value,
+};
+
+// What to do with `eth_call` requests that carry state or block overrides
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum StateOverridePolicy {
+    // Forward as-is
+    #[default]
+    Passthrough,
+    // Forward to RPCs that support overrides, or a plain `eth_call` to the rest
+    // if none of them do
+    Strip,
+    // Return an error to the client
+    Reject,
+    // Only forward to RPCs that support overrides
+    Route,
+}
+
+impl StateOverridePolicy {
+    pub fn from_config(policy: &str) -> Option<Self> {
+        match policy {
+            "passthrough" => Some(StateOverridePolicy::Passthrough),
+            "strip" => Some(StateOverridePolicy::Strip),
+            "reject" => Some(StateOverridePolicy::Reject),
+            "route" => Some(StateOverridePolicy::Route),
+            _ => None,
+        }
+    }
+}
+
+// Return true if `tx` is an `eth_call` with state overrides (params[2])
+// or block overrides (params[3])
+pub fn has_overrides(tx: &Value) -> bool {
+    if tx["method"].as_str() != Some("eth_call") {
+        return false;
+    }
+
+    match tx["params"].as_array() {
+        Some(params) => params.iter().skip(2).any(|param| !param.is_null()),
+        None => false,
+    }
+}
+
+// Remove everything past the block param
+pub fn strip_overrides(tx: &mut Value) {
+    if let Some(params) = tx["params"].as_array_mut() {
+        params.truncate(2);
+    }
+}
+
+// Return true if any RPC we could send to accepts overrides. If one does, stripping
+// them would send a different call than the client asked for for no reason.
+pub fn any_supports_overrides(list: &[Rpc]) -> bool {
+    list.iter()
+        .any(|rpc| !rpc.paused && rpc.supports_state_overrides)
+}
+
+fn is_empty_override(param: &Value) -> bool {
+    param.is_null() || param.as_object().is_some_and(Map::is_empty)
+}
+
+// Lowercase the keys of `object`. They're hex, so their case doesn't change what they mean.
+fn lowercase_keys(object: &mut Map<String, Value>) {
+    *object = std::mem::take(object)
+        .into_iter()
+        .map(|(key, value)| (key.to_lowercase(), value))
+        .collect();
+}
+
+// Put the overrides of an `eth_call` in a canonical form.
+//
+// Requests are cached under the hash of the whole request, so calls with overrides
+// never share an entry with plain calls. This makes equivalent overrides share one:
+// empty overrides at the end are dropped, and addresses and storage slots lowercased.
+pub fn canonicalize_overrides(tx: &mut Value) {
+    if tx["method"].as_str() != Some("eth_call") {
+        return;
+    }
+    let params = match tx["params"].as_array_mut() {
+        Some(params) => params,
+        None => return,
+    };
+
+    while params.len() > 2 && params.last().is_some_and(is_empty_override) {
+        params.pop();
+    }
+
+    if let Some(Value::Object(state)) = params.get_mut(2) {
+        lowercase_keys(state);
+        for account in state.values_mut() {
+            for field in ["state", "stateDiff"] {
+                if let Some(Value::Object(slots)) = account.get_mut(field) {
+                    lowercase_keys(slots);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::balancer::block_cache::request_hash;
+    use serde_json::json;
+
+    #[test]
+    fn test_has_overrides() {
+        let tx = json!({"method": "eth_call", "params": [{"to": "0x00"}, "0x1"]});
+        assert!(!has_overrides(&tx));
+
+        let tx = json!({"method": "eth_call", "params": [{"to": "0x00"}, "0x1", null]});
+        assert!(!has_overrides(&tx));
+
+        let tx = json!({"method": "eth_call", "params": [{"to": "0x00"}, "0x1", {"0x01": {"balance": "0x1"}}]});
+        assert!(has_overrides(&tx));
+
+        let tx = json!({"method": "eth_call", "params": [{"to": "0x00"}, "0x1", null, {"number": "0x2"}]});
+        assert!(has_overrides(&tx));
+
+        let tx = json!({"method": "eth_getBalance", "params": ["0x00", "0x1", {}]});
+        assert!(!has_overrides(&tx));
+    }
+
+    #[test]
+    fn test_canonicalize_overrides() {
+        let mut tx = json!({"method": "eth_call", "params": [{"to": "0x00"}, "0x1", null]});
+        canonicalize_overrides(&mut tx);
+        assert_eq!(tx["params"], json!([{"to": "0x00"}, "0x1"]));
+
+        let mut tx = json!({"method": "eth_call", "params": [{"to": "0x00"}, "0x1", {}, null]});
+        canonicalize_overrides(&mut tx);
+        assert_eq!(tx["params"], json!([{"to": "0x00"}, "0x1"]));
+
+        // Block overrides keep the state override param in place
+        let mut tx = json!({"method": "eth_call", "params": [{"to": "0x00"}, "0x1", null, {"number": "0x2"}]});
+        canonicalize_overrides(&mut tx);
+        assert_eq!(
+            tx["params"],
+            json!([{"to": "0x00"}, "0x1", null, {"number": "0x2"}])
+        );
+
+        let mut tx = json!({"method": "eth_call", "params": [{"to": "0x00"}, "0x1", {
+            "0xAbCd": {"balance": "0x1", "stateDiff": {"0xFF": "0xAA"}},
+        }]});
+        canonicalize_overrides(&mut tx);
+        assert_eq!(
+            tx["params"][2],
+            json!({"0xabcd": {"balance": "0x1", "stateDiff": {"0xff": "0xAA"}}})
+        );
+
+        let mut tx = json!({"method": "eth_getBalance", "params": ["0xAA", "0x1", null]});
+        canonicalize_overrides(&mut tx);
+        assert_eq!(tx["params"], json!(["0xAA", "0x1", null]));
+    }
+
+    #[test]
+    fn test_override_cache_keys() {
+        let plain = json!({"id": null, "method": "eth_call", "params": [{"to": "0x00"}, "0x1"]});
+        let mut overridden = json!({"id": null, "method": "eth_call", "params": [
+            {"to": "0x00"}, "0x1", {"0x01": {"balance": "0x1"}},
+        ]});
+        let mut equivalent = json!({"id": null, "method": "eth_call", "params": [
+            {"to": "0x00"}, "0x1", {"0x01": {"balance": "0x1"}}, null,
+        ]});
+        canonicalize_overrides(&mut overridden);
+        canonicalize_overrides(&mut equivalent);
+
+        assert_ne!(request_hash(&plain), request_hash(&overridden));
+        assert_eq!(request_hash(&overridden), request_hash(&equivalent));
+    }
+
+    #[test]
+    fn test_strip_overrides() {
+        let mut tx =
+            json!({"method": "eth_call", "params": [{"to": "0x00"}, "0x1", {"0x01": {}}, {}]});
+        strip_overrides(&mut tx);
+
+        assert_eq!(tx["params"], json!([{"to": "0x00"}, "0x1"]));
+        assert!(!has_overrides(&tx));
+    }
+
+    #[test]
+    fn test_any_supports_overrides() {
+        let mut rpc1 = Rpc::default();
+        let mut rpc2 = Rpc::default();
+        rpc1.supports_state_overrides = false;
+        assert!(any_supports_overrides(&[rpc1.clone(), rpc2.clone()]));
+
+        // Paused RPCs don't get requests
+        rpc2.paused = true;
+        assert!(!any_supports_overrides(&[rpc1.clone(), rpc2]));
+        assert!(!any_supports_overrides(&[rpc1]));
+        assert!(!any_supports_overrides(&[]));
+    }
+}
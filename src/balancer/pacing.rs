@@ -0,0 +1,266 @@
+// Paces backfill requests so they stay under the rate limits of each provider.
+//
+// Every upstream has a budget, its `max_per_second` or the default backfill rate. Whatever
+// interactive and subscription traffic used in the last second is taken off the top,
+// and backfill requests are spaced out evenly over what's left. When a provider tells
+// us we're sending too much we halve the backfill rate, and creep back up as long as
+// it stops complaining.
+use crate::{
+    balancer::send_queue::Priority,
+    rpc::types::Rpc,
+};
+
+use std::{
+    collections::{
+        HashMap,
+        VecDeque,
+    },
+    sync::Mutex,
+    time::{
+        Duration,
+        Instant,
+    },
+};
+
+use hyper::body::Bytes;
+
+// How far back we look at the regular traffic of an upstream
+const WINDOW: Duration = Duration::from_secs(1);
+
+// Added to the backfill rate factor after every response that wasn't rate limited
+const RECOVERY: f64 = 0.01;
+
+// Error responses are short, anything longer than this is not a rate limit
+const MAX_ERROR_SIZE: usize = 1024;
+
+#[derive(Debug)]
+struct Pace {
+    // When we sent the regular requests of the last `WINDOW`
+    sent: VecDeque<Instant>,
+    // Earliest time the next backfill request can go out
+    next: Instant,
+    // Share of the budget backfill gets, halved every time we get rate limited
+    factor: f64,
+}
+
+impl Pace {
+    fn new(now: Instant) -> Self {
+        Self {
+            sent: VecDeque::new(),
+            next: now,
+            factor: 1.0,
+        }
+    }
+
+    fn expire(&mut self, now: Instant) {
+        while self
+            .sent
+            .front()
+            .is_some_and(|sent| now.duration_since(*sent) > WINDOW)
+        {
+            self.sent.pop_front();
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct BackfillPacer {
+    // Requests per second we assume upstreams without `max_per_second` allow
+    rate: f64,
+    // Backfill never goes slower than this, so it always makes progress
+    min_rate: f64,
+    upstreams: Mutex<HashMap<String, Pace>>,
+}
+
+impl BackfillPacer {
+    pub fn new(rate: f64, min_rate: f64) -> Self {
+        Self {
+            rate,
+            min_rate,
+            upstreams: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // Requests per second backfill can send to `rpc` right now
+    fn backfill_rate(&self, rpc: &Rpc, pace: &Pace) -> f64 {
+        let limit = if rpc.max_per_second > 0.0 {
+            rpc.max_per_second
+        } else {
+            self.rate
+        };
+        let regular = pace.sent.len() as f64 / WINDOW.as_secs_f64();
+
+        ((limit - regular) * pace.factor).max(self.min_rate)
+    }
+
+    // Reserve a slot for a request to `rpc`, returning how long it has to wait for it.
+    //
+    // Only backfill waits, everything else is counted so backfill can make room for it.
+    fn reserve(&self, rpc: &Rpc, priority: Priority, now: Instant) -> Duration {
+        let mut upstreams = self.upstreams.lock().unwrap();
+        let pace = upstreams
+            .entry(rpc.name.clone())
+            .or_insert_with(|| Pace::new(now));
+        pace.expire(now);
+
+        if priority != Priority::Backfill {
+            pace.sent.push_back(now);
+            return Duration::ZERO;
+        }
+
+        let interval = Duration::from_secs_f64(1.0 / self.backfill_rate(rpc, pace));
+        let slot = pace.next.max(now);
+        pace.next = slot + interval;
+
+        slot - now
+    }
+
+    // Wait until `rpc` has room for a request of `priority`
+    pub async fn pace(&self, rpc: &Rpc, priority: Priority) {
+        let wait = self.reserve(rpc, priority, Instant::now());
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    // Adjust the backfill rate of `upstream` to the response we got from it
+    pub fn report(&self, upstream: &str, rx: &Bytes) {
+        let mut upstreams = self.upstreams.lock().unwrap();
+        let Some(pace) = upstreams.get_mut(upstream) else {
+            return;
+        };
+
+        if looks_rate_limited(rx) {
+            pace.factor /= 2.0;
+        } else {
+            pace.factor = (pace.factor + RECOVERY).min(1.0);
+        }
+    }
+}
+
+// Returns true if `rx` looks like a provider telling us to slow down.
+//
+// There's no standard for this, so we look for the usual codes and messages.
+pub fn looks_rate_limited(rx: &Bytes) -> bool {
+    if rx.len() > MAX_ERROR_SIZE {
+        return false;
+    }
+
+    let rx = String::from_utf8_lossy(rx).to_lowercase();
+    rx.contains("rate limit")
+        || rx.contains("too many requests")
+        || rx.contains("\"code\":-32005")
+        || rx.contains("\"code\":429")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rpc(max_per_second: f64) -> Rpc {
+        let mut rpc = Rpc::default();
+        rpc.name = "node".to_string();
+        rpc.max_per_second = max_per_second;
+        rpc
+    }
+
+    #[test]
+    fn test_backfill_is_spaced_out() {
+        let pacer = BackfillPacer::new(10.0, 1.0);
+        let rpc = rpc(0.0);
+        let now = Instant::now();
+
+        assert_eq!(pacer.reserve(&rpc, Priority::Backfill, now), Duration::ZERO);
+        assert_eq!(
+            pacer.reserve(&rpc, Priority::Backfill, now),
+            Duration::from_millis(100)
+        );
+        assert_eq!(
+            pacer.reserve(&rpc, Priority::Backfill, now),
+            Duration::from_millis(200)
+        );
+
+        // Regular requests never wait
+        assert_eq!(
+            pacer.reserve(&rpc, Priority::Interactive, now),
+            Duration::ZERO
+        );
+    }
+
+    #[test]
+    fn test_yields_to_interactive() {
+        let pacer = BackfillPacer::new(0.0, 1.0);
+        let rpc = rpc(20.0);
+        let now = Instant::now();
+
+        for _ in 0..10 {
+            pacer.reserve(&rpc, Priority::Interactive, now);
+        }
+        pacer.reserve(&rpc, Priority::Backfill, now);
+        // 20 allowed, 10 used by interactive traffic
+        assert_eq!(
+            pacer.reserve(&rpc, Priority::Backfill, now),
+            Duration::from_millis(100)
+        );
+
+        // Interactive traffic using the whole budget leaves backfill with the minimum
+        for _ in 0..20 {
+            pacer.reserve(&rpc, Priority::Interactive, now);
+        }
+        let next = now + Duration::from_millis(200);
+        assert_eq!(
+            pacer.reserve(&rpc, Priority::Backfill, now),
+            Duration::from_millis(200)
+        );
+        assert_eq!(
+            pacer.reserve(&rpc, Priority::Backfill, next),
+            Duration::from_secs(1)
+        );
+    }
+
+    #[test]
+    fn test_rate_limited_responses_slow_down() {
+        let pacer = BackfillPacer::new(10.0, 1.0);
+        let rpc = rpc(0.0);
+        let now = Instant::now();
+        pacer.reserve(&rpc, Priority::Backfill, now);
+
+        let limited = Bytes::from_static(
+            br#"{"jsonrpc":"2.0","id":1,"error":{"code":-32005,"message":"limit exceeded"}}"#,
+        );
+        pacer.report("node", &limited);
+        assert_eq!(
+            pacer.reserve(&rpc, Priority::Backfill, now),
+            Duration::from_millis(100)
+        );
+        // Half the rate now
+        assert_eq!(
+            pacer.reserve(&rpc, Priority::Backfill, now),
+            Duration::from_millis(300)
+        );
+
+        for _ in 0..100 {
+            pacer.report("node", &Bytes::from_static(br#"{"result":"0x1"}"#));
+        }
+        assert_eq!(
+            pacer.reserve(&rpc, Priority::Backfill, now),
+            Duration::from_millis(500)
+        );
+    }
+
+    #[test]
+    fn test_looks_rate_limited() {
+        assert!(looks_rate_limited(&Bytes::from_static(
+            b"Too Many Requests"
+        )));
+        assert!(looks_rate_limited(&Bytes::from_static(
+            br#"{"error":{"code":429,"message":"slow down"}}"#
+        )));
+        assert!(looks_rate_limited(&Bytes::from_static(
+            br#"{"error":{"code":-32000,"message":"daily rate limit reached"}}"#
+        )));
+        assert!(!looks_rate_limited(&Bytes::from_static(
+            br#"{"result":"0x10"}"#
+        )));
+    }
+}
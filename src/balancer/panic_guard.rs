@@ -0,0 +1,109 @@
+// Keeps a panic while serving one request from taking the connection, or anything
+// else, down with it.
+//
+// The request gets a JSON-RPC internal error instead and the panic gets counted.
+// This only works as long as we unwind on panic, so no profile may set `panic = "abort"`.
+use std::{
+    any::Any,
+    future::Future,
+    panic::{
+        catch_unwind,
+        AssertUnwindSafe,
+    },
+    pin::Pin,
+    sync::atomic::{
+        AtomicU64,
+        Ordering,
+    },
+    task::{
+        Context,
+        Poll,
+    },
+};
+
+use serde_json::json;
+
+// Requests that panicked since we started
+static PANICS: AtomicU64 = AtomicU64::new(0);
+
+pub fn panics() -> u64 {
+    PANICS.load(Ordering::Relaxed)
+}
+
+// Message of a caught panic, if it has one we can read
+pub fn panic_reason(panic: &(dyn Any + Send)) -> Option<&str> {
+    match (panic.downcast_ref::<String>(), panic.downcast_ref::<&str>()) {
+        (Some(reason), _) => Some(reason),
+        (_, Some(reason)) => Some(reason),
+        _ => None,
+    }
+}
+
+// Polls `F`, resolving to the reason it panicked with if it did
+pub struct CatchPanic<F> {
+    inner: Pin<Box<F>>,
+}
+
+impl<F: Future> Future for CatchPanic<F> {
+    type Output = Result<F::Output, String>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // We never poll `inner` again after it panics, so whatever it left behind is never seen
+        let inner = &mut self.inner;
+        match catch_unwind(AssertUnwindSafe(|| inner.as_mut().poll(cx))) {
+            Ok(Poll::Ready(output)) => Poll::Ready(Ok(output)),
+            Ok(Poll::Pending) => Poll::Pending,
+            Err(panic) => {
+                PANICS.fetch_add(1, Ordering::Relaxed);
+                Poll::Ready(Err(panic_reason(&*panic)
+                    .unwrap_or("unknown panic")
+                    .to_string()))
+            }
+        }
+    }
+}
+
+pub fn catch_panic<F: Future>(future: F) -> CatchPanic<F> {
+    CatchPanic {
+        inner: Box::pin(future),
+    }
+}
+
+// What the client gets instead of the response to the request that panicked.
+//
+// The body might not have been parsed yet, so we don't know the id.
+pub fn internal_error() -> String {
+    json!({
+        "jsonrpc": "2.0",
+        "id": null,
+        "error": {
+            "code": -32603,
+            "message": "error: Internal error while processing the request",
+        },
+    })
+    .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_catch_panic() {
+        assert_eq!(catch_panic(async { 1 }).await, Ok(1));
+
+        let before = panics();
+        let caught =
+            catch_panic(async { u64::from_str_radix("nope", 16).expect("no number") }).await;
+        assert!(caught.unwrap_err().starts_with("no number"));
+        assert!(panics() > before);
+    }
+
+    #[test]
+    fn test_panic_reason() {
+        let panic = catch_unwind(|| panic!("formatted {}", 1)).unwrap_err();
+        assert_eq!(panic_reason(&*panic), Some("formatted 1"));
+        let panic = catch_unwind(|| std::panic::panic_any(1)).unwrap_err();
+        assert_eq!(panic_reason(&*panic), None);
+    }
+}
@@ -0,0 +1,118 @@
+// Telling clients where their responses came from, for debugging and for clients that
+// need to know which provider they're trusting.
+//
+// Clients opt in per request with the `x-blutgang-provenance` header, and only if the
+// operator enabled `expose_provenance`, since it exposes the names of our RPCs. Every
+// response then gets a `blutgang` member next to `result`, batches included, saying if
+// it came from the cache, a single RPC, several of them, or from blutgang itself.
+use http_body_util::{
+    BodyExt,
+    Full,
+};
+use hyper::{
+    body::Bytes,
+    HeaderMap,
+    Response,
+};
+use serde_json::{
+    json,
+    Value,
+};
+
+pub const PROVENANCE_HEADER: &str = "x-blutgang-provenance";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Provenance {
+    Cache,
+    Upstream(String),
+    // Quorum reads and broadcast transactions, answered by several RPCs at once
+    Fanout,
+}
+
+fn to_value(provenance: Option<&Provenance>) -> Value {
+    match provenance {
+        Some(Provenance::Cache) => json!({"source": "cache"}),
+        Some(Provenance::Upstream(name)) => json!({"source": "upstream", "upstream": name}),
+        Some(Provenance::Fanout) => json!({"source": "fanout"}),
+        // Anything we answer ourselves, errors included
+        None => json!({"source": "blutgang"}),
+    }
+}
+
+// Returns true if the client wants to know where responses came from
+pub fn wants_provenance(headers: &HeaderMap) -> bool {
+    headers
+        .get(PROVENANCE_HEADER)
+        .and_then(|wanted| wanted.to_str().ok())
+        .is_some_and(|wanted| wanted == "1" || wanted.eq_ignore_ascii_case("true"))
+}
+
+// Add where `rx` came from to it. Anything that isn't a JSON-RPC response is left alone.
+fn annotate(rx: &mut Value, provenance: Option<&Provenance>) {
+    if let Some(rx) = rx.as_object_mut() {
+        rx.insert("blutgang".to_string(), to_value(provenance));
+    }
+}
+
+// Add where a response came from to its body. Whatever served it records that as an
+// extension of the response, responses without one are ours.
+pub async fn annotate_response(rx: Response<Full<Bytes>>) -> Response<Full<Bytes>> {
+    let (parts, body) = rx.into_parts();
+    let body = match body.collect().await {
+        Ok(body) => body.to_bytes(),
+        Err(never) => match never {},
+    };
+    let body = match serde_json::from_slice::<Value>(&body) {
+        Ok(mut value) => {
+            annotate(&mut value, parts.extensions.get::<Provenance>());
+            Bytes::from(value.to_string())
+        }
+        Err(_) => body,
+    };
+
+    Response::from_parts(parts, Full::new(body))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::header::HeaderValue;
+
+    #[test]
+    fn test_annotate() {
+        let mut rx = json!({"jsonrpc": "2.0", "id": 1, "result": "0x1"});
+        annotate(&mut rx, Some(&Provenance::Upstream("llama".to_string())));
+        assert_eq!(
+            rx["blutgang"],
+            json!({"source": "upstream", "upstream": "llama"})
+        );
+        annotate(&mut rx, None);
+        assert_eq!(rx["blutgang"], json!({"source": "blutgang"}));
+
+        let mut rx = json!("not a response");
+        annotate(&mut rx, Some(&Provenance::Cache));
+        assert_eq!(rx, json!("not a response"));
+
+        let mut headers = HeaderMap::new();
+        assert!(!wants_provenance(&headers));
+        headers.insert(PROVENANCE_HEADER, HeaderValue::from_static("true"));
+        assert!(wants_provenance(&headers));
+    }
+
+    #[tokio::test]
+    async fn test_annotate_response() {
+        let mut rx = Response::builder()
+            .header("Content-Type", "application/json")
+            .body(Full::new(Bytes::from(
+                r#"{"jsonrpc":"2.0","id":1,"result":"0x1"}"#,
+            )))
+            .unwrap();
+        rx.extensions_mut().insert(Provenance::Cache);
+
+        let rx = annotate_response(rx).await;
+        let body = rx.into_body().collect().await.unwrap().to_bytes();
+        let rx: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(rx["blutgang"], json!({"source": "cache"}));
+        assert_eq!(rx["result"], "0x1");
+    }
+}
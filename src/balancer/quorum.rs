@@ -0,0 +1,378 @@
+// `blutgang_quorumCall`, a one-off quorum read for calls that need extra assurance.
+//
+// The inner request goes to `n` RPCs at once, and we only answer if at least
+// `threshold` of them return the same thing. Clients pay for the extra requests
+// only on the calls they wrap, whatever the strategy is for everything else.
+//
+// Quorum mode does the same for every request to the methods it's configured for, but
+// always answers with what most RPCs agree on and only warns about the ones that don't.
+use crate::Rpc;
+
+use std::time::Duration;
+
+use hyper::body::Bytes;
+
+use serde_json::{
+    json,
+    Map,
+    Value,
+};
+use tokio::{
+    task::JoinSet,
+    time::timeout,
+};
+
+pub const METHOD: &str = "blutgang_quorumCall";
+
+// Methods that change state, sending them to several RPCs isn't a read
+const WRITE_METHODS: [&str; 2] = ["eth_sendRawTransaction", "eth_sendTransaction"];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct QuorumCall {
+    // The wrapped request, without an id
+    pub request: Value,
+    // RPCs we ask, all of them if None
+    pub n: Option<usize>,
+    // Responses that have to agree, a majority of the ones we ask if None
+    pub threshold: Option<usize>,
+}
+
+impl QuorumCall {
+    // How many RPCs we ask and how many have to agree, with `available` RPCs to ask
+    pub fn sizes(&self, available: usize) -> Result<(usize, usize), String> {
+        let n = self.n.unwrap_or(available).min(available);
+        let threshold = self.threshold.unwrap_or(n / 2 + 1);
+        if threshold > n {
+            return Err(format!(
+                "Threshold of {} is more than the {} RPCs available",
+                threshold, n
+            ));
+        }
+        Ok((n, threshold))
+    }
+}
+
+// Parse `tx` as a `blutgang_quorumCall`, `params` being the inner request and
+// optionally `{"n": .., "threshold": ..}`.
+//
+// Returns None if `tx` is some other method.
+pub fn parse_quorum_call(tx: &Value) -> Option<Result<QuorumCall, String>> {
+    if tx["method"] != METHOD {
+        return None;
+    }
+
+    let parse = || {
+        let inner = tx["params"][0]
+            .as_object()
+            .ok_or("The first param has to be the request to make")?;
+        let method = inner["method"]
+            .as_str()
+            .ok_or("The request to make has no method")?;
+        if method == METHOD || WRITE_METHODS.contains(&method) {
+            return Err(format!("{} can't be called with a quorum", method));
+        }
+
+        let options = &tx["params"][1];
+        let size = |name: &str| {
+            match &options[name] {
+                Value::Null => Ok(None),
+                size => {
+                    size.as_u64()
+                        .filter(|size| *size > 0)
+                        .map(|size| Some(size as usize))
+                        .ok_or(format!("{} has to be a positive integer", name))
+                }
+            }
+        };
+
+        Ok(QuorumCall {
+            request: json!({
+                "id": null,
+                "jsonrpc": "2.0",
+                "method": method,
+                "params": inner.get("params").cloned().unwrap_or(Value::Array(Vec::new())),
+            }),
+            n: size("n")?,
+            threshold: size("threshold")?,
+        })
+    };
+
+    Some(parse())
+}
+
+// What a response says, `result` or `error`
+fn outcome(rx: &[u8]) -> Option<Value> {
+    let mut rx: Map<String, Value> = serde_json::from_slice(rx).ok()?;
+    match rx.remove("error") {
+        Some(error) => Some(json!({"error": error})),
+        None => Some(json!({"result": rx.remove("result")?})),
+    }
+}
+
+// Returns the outcome at least `threshold` of `outcomes` agree on, RPCs that
+// didn't answer being None
+fn tally(outcomes: &[(String, Option<Value>)], threshold: usize) -> Result<Value, String> {
+    let mut counts: Vec<(&Value, usize)> = Vec::new();
+    for outcome in outcomes.iter().filter_map(|(_, outcome)| outcome.as_ref()) {
+        match counts.iter_mut().find(|(counted, _)| *counted == outcome) {
+            Some((_, count)) => *count += 1,
+            None => counts.push((outcome, 1)),
+        }
+    }
+
+    let most = counts.iter().max_by_key(|(_, count)| *count);
+    match most {
+        Some((outcome, count)) if *count >= threshold => Ok((*outcome).clone()),
+        _ => {
+            Err(format!(
+                "No quorum: {} of {} RPCs agreed, {} needed",
+                most.map(|(_, count)| *count).unwrap_or_default(),
+                outcomes.len(),
+                threshold
+            ))
+        }
+    }
+}
+
+// Send `call` to the first `n` of `rpcs` and answer request `id` with what `threshold` agree on
+pub async fn quorum_call(id: &Value, call: QuorumCall, rpcs: Vec<Rpc>, ttl: Duration) -> Value {
+    let (n, threshold) = match call.sizes(rpcs.len()) {
+        Ok(sizes) => sizes,
+        Err(err) => return quorum_error(id, &err, Value::Null),
+    };
+
+    let mut requests = JoinSet::new();
+    for rpc in rpcs.into_iter().take(n) {
+        let request = call.request.clone();
+        requests.spawn(async move {
+            let rx = match timeout(ttl, rpc.send_request(request)).await {
+                Ok(Ok(rx)) => outcome(&rx),
+                _ => None,
+            };
+            (rpc.name, rx)
+        });
+    }
+
+    let mut outcomes = Vec::with_capacity(n);
+    while let Some(outcome) = requests.join_next().await {
+        if let Ok(outcome) = outcome {
+            outcomes.push(outcome);
+        }
+    }
+    outcomes.sort_by(|a, b| a.0.cmp(&b.0));
+
+    match tally(&outcomes, threshold) {
+        Ok(mut outcome) => {
+            outcome["id"] = id.clone();
+            outcome["jsonrpc"] = "2.0".into();
+            outcome
+        }
+        Err(err) => {
+            let responses: Map<String, Value> = outcomes
+                .into_iter()
+                .map(|(name, outcome)| (name, outcome.unwrap_or(Value::Null)))
+                .collect();
+            quorum_error(id, &err, Value::Object(responses))
+        }
+    }
+}
+
+// RPCs a quorum goes to, fastest first since they're the ones we ask if we don't ask everyone
+pub fn quorum_rpcs(rpc_list: &[Rpc]) -> Vec<Rpc> {
+    let mut rpcs: Vec<Rpc> = rpc_list.iter().filter(|rpc| !rpc.paused).cloned().collect();
+    rpcs.sort_by(|a, b| a.status.latency.total_cmp(&b.status.latency));
+    rpcs
+}
+
+// Send `tx` to all of `rpcs` at once and return the response most of them agree on.
+//
+// None if none of them answered.
+pub async fn majority_read(
+    method: &str,
+    tx: Bytes,
+    rpcs: Vec<Rpc>,
+    ttl: Duration,
+) -> Option<Bytes> {
+    let mut requests = JoinSet::new();
+    for (rank, rpc) in rpcs.into_iter().enumerate() {
+        let tx = tx.clone();
+        requests.spawn(async move {
+            let rx = match timeout(ttl, rpc.send_raw(tx)).await {
+                Ok(Ok(rx)) => Some(rx),
+                _ => None,
+            };
+            (rank, rpc.name, rx)
+        });
+    }
+
+    let mut responses = Vec::new();
+    while let Some(response) = requests.join_next().await {
+        if let Ok((rank, name, Some(rx))) = response {
+            responses.push((rank, name, rx));
+        }
+    }
+    responses.sort_by_key(|(rank, _, _)| *rank);
+
+    let responses = responses
+        .into_iter()
+        .map(|(_, name, rx)| (name, rx))
+        .collect();
+    pick_majority(method, responses)
+}
+
+// Response most of `responses` agree on, ties going to the first one. RPCs that
+// disagree with it get a warning.
+fn pick_majority(method: &str, responses: Vec<(String, Bytes)>) -> Option<Bytes> {
+    let outcomes: Vec<Option<Value>> = responses.iter().map(|(_, rx)| outcome(rx)).collect();
+
+    // First response with each outcome, and how many share it
+    let mut counts: Vec<(usize, usize)> = Vec::new();
+    for (i, outcome) in outcomes.iter().enumerate() {
+        match counts
+            .iter_mut()
+            .find(|(first, _)| outcomes[*first] == *outcome)
+        {
+            Some((_, count)) => *count += 1,
+            None => counts.push((i, 1)),
+        }
+    }
+    let (majority, count) = counts
+        .into_iter()
+        .reduce(|best, next| if next.1 > best.1 { next } else { best })?;
+
+    if count < responses.len() {
+        let disagreeing: Vec<&str> = responses
+            .iter()
+            .zip(&outcomes)
+            .filter(|(_, outcome)| **outcome != outcomes[majority])
+            .map(|((name, _), _)| name.as_str())
+            .collect();
+        println!(
+            "\x1b[93mWrn:\x1b[0m Quorum mismatch on {}: {} disagreed with {} of {} RPCs",
+            method,
+            disagreeing.join(", "),
+            count,
+            responses.len()
+        );
+    }
+
+    responses.into_iter().nth(majority).map(|(_, rx)| rx)
+}
+
+pub fn quorum_error(id: &Value, message: &str, data: Value) -> Value {
+    let mut error = json!({
+        "code": -32000,
+        "message": message,
+    });
+    if !data.is_null() {
+        error["data"] = data;
+    }
+
+    json!({
+        "id": id,
+        "jsonrpc": "2.0",
+        "error": error,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_quorum_call() {
+        let tx = json!({"id": 1, "jsonrpc": "2.0", "method": METHOD, "params": [
+            {"method": "eth_getBalance", "params": ["0xabc", "latest"]},
+            {"n": 3, "threshold": 2},
+        ]});
+        let call = parse_quorum_call(&tx).unwrap().unwrap();
+        assert_eq!(call.request["method"], "eth_getBalance");
+        assert_eq!(call.request["params"], json!(["0xabc", "latest"]));
+        assert_eq!((call.n, call.threshold), (Some(3), Some(2)));
+
+        let tx = json!({"id": 1, "jsonrpc": "2.0", "method": METHOD, "params": [
+            {"method": "eth_blockNumber"},
+        ]});
+        let call = parse_quorum_call(&tx).unwrap().unwrap();
+        assert_eq!((call.n, call.threshold), (None, None));
+        assert_eq!(call.sizes(4), Ok((4, 3)));
+
+        for params in [
+            json!([]),
+            json!([{"method": "eth_sendRawTransaction", "params": ["0x00"]}]),
+            json!([{"method": "eth_blockNumber"}, {"n": 0}]),
+        ] {
+            let tx = json!({"id": 1, "jsonrpc": "2.0", "method": METHOD, "params": params});
+            assert!(parse_quorum_call(&tx).unwrap().is_err());
+        }
+
+        let tx = json!({"id": 1, "jsonrpc": "2.0", "method": "eth_blockNumber"});
+        assert_eq!(parse_quorum_call(&tx), None);
+    }
+
+    #[test]
+    fn test_sizes() {
+        let call = QuorumCall {
+            request: Value::Null,
+            n: Some(5),
+            threshold: Some(3),
+        };
+        // Fewer RPCs than asked for
+        assert_eq!(call.sizes(4), Ok((4, 3)));
+        assert!(call.sizes(2).is_err());
+    }
+
+    #[test]
+    fn test_tally() {
+        let result = |value: &str| Some(json!({"result": value}));
+        let outcomes = vec![
+            ("a".to_string(), result("0x1")),
+            ("b".to_string(), result("0x2")),
+            ("c".to_string(), result("0x1")),
+            ("d".to_string(), None),
+        ];
+
+        assert_eq!(tally(&outcomes, 2), Ok(json!({"result": "0x1"})));
+        assert_eq!(
+            tally(&outcomes, 3),
+            Err("No quorum: 2 of 4 RPCs agreed, 3 needed".to_string())
+        );
+    }
+
+    #[test]
+    fn test_pick_majority() {
+        let response = |name: &str, result: &str| {
+            let rx = json!({"id": 7, "jsonrpc": "2.0", "result": result, "from": name});
+            (name.to_string(), Bytes::from(rx.to_string()))
+        };
+
+        // The disagreeing RPC is outvoted
+        let rx = pick_majority(
+            "eth_getBalance",
+            vec![
+                response("a", "0x2"),
+                response("b", "0x1"),
+                response("c", "0x1"),
+            ],
+        )
+        .unwrap();
+        assert_eq!(outcome(&rx), Some(json!({"result": "0x1"})));
+        // Ties go to the first, fastest one
+        let rx = pick_majority("eth_call", vec![response("a", "0x2"), response("b", "0x1")]);
+        assert_eq!(outcome(&rx.unwrap()), Some(json!({"result": "0x2"})));
+        assert_eq!(pick_majority("eth_call", Vec::new()), None);
+    }
+
+    #[test]
+    fn test_outcome() {
+        assert_eq!(
+            outcome(br#"{"id":1,"jsonrpc":"2.0","result":"0x1"}"#),
+            Some(json!({"result": "0x1"}))
+        );
+        assert_eq!(
+            outcome(br#"{"id":1,"jsonrpc":"2.0","error":{"code":3,"message":"reverted"}}"#),
+            Some(json!({"error": {"code": 3, "message": "reverted"}}))
+        );
+        assert_eq!(outcome(b"not json"), None);
+    }
+}
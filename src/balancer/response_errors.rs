@@ -3,12 +3,22 @@
 
 #[macro_export]
 macro_rules! no_rpc_available {
-    () => {
+    (
+        $id:expr
+    ) => {
         Ok(hyper::Response::builder()
             .status(500)
+            .header("Content-Type", "application/json")
             .body(Full::new(Bytes::from(
-                "{code:-32002, message:\"error: No working RPC available! Try again later...\"}"
-                    .to_string(),
+                serde_json::json!({
+                    "jsonrpc": "2.0",
+                    "id": $id,
+                    "error": {
+                        "code": -32002,
+                        "message": "error: No working RPC available! Try again later...",
+                    },
+                })
+                .to_string(),
             )))
             .unwrap())
     };
@@ -16,12 +26,22 @@ macro_rules! no_rpc_available {
 
 #[macro_export]
 macro_rules! timed_out {
-    () => {
+    (
+        $id:expr
+    ) => {
         Ok(hyper::Response::builder()
             .status(408)
+            .header("Content-Type", "application/json")
             .body(Full::new(Bytes::from(
-                "{code:-32001, message:\"error: Request timed out! Try again later...\"}"
-                    .to_string(),
+                serde_json::json!({
+                    "jsonrpc": "2.0",
+                    "id": $id,
+                    "error": {
+                        "code": -32001,
+                        "message": "error: Request timed out! Try again later...",
+                    },
+                })
+                .to_string(),
             )))
             .unwrap())
     };
@@ -38,11 +58,22 @@ macro_rules! print_cache_error {
 
 #[macro_export]
 macro_rules! cache_error {
-    () => {
+    (
+        $id:expr
+    ) => {
         Ok(hyper::Response::builder()
             .status(500)
+            .header("Content-Type", "application/json")
             .body(Full::new(Bytes::from(
-                "{code:-32003, message:\"error: Cache error! Try again later...\"}".to_string(),
+                serde_json::json!({
+                    "jsonrpc": "2.0",
+                    "id": $id,
+                    "error": {
+                        "code": -32003,
+                        "message": "error: Cache error! Try again later...",
+                    },
+                })
+                .to_string(),
             )))
             .unwrap())
     };
@@ -60,3 +91,159 @@ macro_rules! rpc_response {
             .unwrap())
     };
 }
+
+#[macro_export]
+macro_rules! rate_limited {
+    () => {
+        Ok(hyper::Response::builder()
+            .status(429)
+            .body(Full::new(Bytes::from(
+                "{\"jsonrpc\":\"2.0\",\"id\":null,\"error\":{\"code\":-32005,\"message\":\"error: Rate limit exceeded! Try again later...\"}}"
+                    .to_string(),
+            )))
+            .unwrap())
+    };
+}
+
+#[macro_export]
+macro_rules! quota_exceeded {
+    () => {
+        Ok(hyper::Response::builder()
+            .status(429)
+            .body(Full::new(Bytes::from(
+                "{\"jsonrpc\":\"2.0\",\"id\":null,\"error\":{\"code\":-32005,\"message\":\"error: Daily request quota exceeded! Try again tomorrow...\"}}"
+                    .to_string(),
+            )))
+            .unwrap())
+    };
+}
+
+#[macro_export]
+macro_rules! upstream_overloaded {
+    (
+        $reason:expr
+    ) => {
+        Ok(hyper::Response::builder()
+            .status(503)
+            .header("Content-Type", "application/json")
+            .body(Full::new(Bytes::from(
+                serde_json::json!({
+                    "jsonrpc": "2.0",
+                    "id": null,
+                    "error": {
+                        "code": -32005,
+                        "message": format!("error: {}! Try again later...", $reason),
+                    },
+                })
+                .to_string(),
+            )))
+            .unwrap())
+    };
+}
+
+#[macro_export]
+macro_rules! request_too_large {
+    () => {
+        Ok(hyper::Response::builder()
+            .status(413)
+            .body(Full::new(Bytes::from(
+                "{\"jsonrpc\":\"2.0\",\"id\":null,\"error\":{\"code\":-32600,\"message\":\"error: Request body too large!\"}}"
+                    .to_string(),
+            )))
+            .unwrap())
+    };
+}
+
+#[macro_export]
+macro_rules! method_not_allowed {
+    (
+        $id:expr,
+        $reason:expr
+    ) => {
+        Ok(hyper::Response::builder()
+            .status(403)
+            .header("Content-Type", "application/json")
+            .body(Full::new(Bytes::from(
+                serde_json::json!({
+                    "jsonrpc": "2.0",
+                    "id": $id,
+                    "error": {
+                        "code": -32601,
+                        "message": format!("error: {}", $reason),
+                    },
+                })
+                .to_string(),
+            )))
+            .unwrap())
+    };
+}
+
+#[macro_export]
+macro_rules! pinned_rpc_unavailable {
+    (
+        $id:expr,
+        $name:expr
+    ) => {
+        Ok(hyper::Response::builder()
+            .status(503)
+            .header("Content-Type", "application/json")
+            .body(Full::new(Bytes::from(
+                serde_json::json!({
+                    "jsonrpc": "2.0",
+                    "id": $id,
+                    "error": {
+                        "code": -32002,
+                        "message": format!("error: Method is pinned to {}, which is currently unavailable!", $name),
+                    },
+                })
+                .to_string(),
+            )))
+            .unwrap())
+    };
+}
+
+#[macro_export]
+macro_rules! malformed_response {
+    (
+        $id:expr
+    ) => {
+        Ok(hyper::Response::builder()
+            .status(502)
+            .header("Content-Type", "application/json")
+            .body(Full::new(Bytes::from(
+                serde_json::json!({
+                    "jsonrpc": "2.0",
+                    "id": $id,
+                    "error": {
+                        "code": -32603,
+                        "message": "error: Every RPC we tried sent a malformed response! Try again later...",
+                    },
+                })
+                .to_string(),
+            )))
+            .unwrap())
+    };
+}
+
+#[macro_export]
+macro_rules! stale_response {
+    (
+        $id:expr
+    ) => {
+        Ok(hyper::Response::builder()
+            .status(503)
+            .header("Content-Type", "application/json")
+            .body(Full::new(Bytes::from(
+                serde_json::json!({
+                    "jsonrpc": "2.0",
+                    "id": $id,
+                    "error": {
+                        "code": -32002,
+                        "message": "error: Every RPC we tried is behind the head! Try again later...",
+                    },
+                })
+                .to_string(),
+            )))
+            .unwrap())
+    };
+}
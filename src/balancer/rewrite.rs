@@ -0,0 +1,424 @@
+// Operator defined rules that rewrite requests before we handle them.
+//
+// Every rule matches on the method, and optionally on param values and API keys.
+// Matching requests get their method renamed, params set, block tags swapped, or
+// block numbers clamped, in that order. Rules apply one after the other, so a rule
+// sees what the ones before it did. The rewritten request is what gets cached.
+use std::collections::HashMap;
+
+use regex::Regex;
+use serde_json::Value;
+
+#[derive(Debug, Clone)]
+pub struct RewriteRule {
+    pub name: String,
+    pub method: String,
+    // Param paths like `0.fromBlock`, and patterns their values have to match
+    pub params: Vec<(String, Regex)>,
+    // Only rewrite requests made with these API keys, every request if empty
+    pub keys: Vec<String>,
+    // New name of the method
+    pub rename: Option<String>,
+    // Param paths and the values we set them to
+    pub set: Vec<(String, Value)>,
+    // Block tags and what we replace them with, anywhere in the params
+    pub tags: HashMap<String, String>,
+    // Param paths and how many blocks below the head they can be at most
+    pub max_age: Vec<(String, u64)>,
+}
+
+fn path_segments(path: &str) -> impl Iterator<Item = &str> {
+    path.split('.').filter(|segment| !segment.is_empty())
+}
+
+fn get_path<'a>(value: &'a Value, path: &str) -> Option<&'a Value> {
+    path_segments(path).try_fold(value, |value, segment| {
+        match value {
+            Value::Array(values) => values.get(segment.parse::<usize>().ok()?),
+            Value::Object(map) => map.get(segment),
+            _ => None,
+        }
+    })
+}
+
+// Set `path` in `value`, creating the last segment if its parent exists
+fn set_path(value: &mut Value, path: &str, new: Value) -> bool {
+    let segments: Vec<&str> = path_segments(path).collect();
+    let (last, parents) = match segments.split_last() {
+        Some(split) => split,
+        None => return false,
+    };
+
+    let mut parent = value;
+    for segment in parents {
+        parent = match parent {
+            Value::Array(values) => {
+                match segment
+                    .parse::<usize>()
+                    .ok()
+                    .and_then(|i| values.get_mut(i))
+                {
+                    Some(value) => value,
+                    None => return false,
+                }
+            }
+            Value::Object(map) => {
+                match map.get_mut(*segment) {
+                    Some(value) => value,
+                    None => return false,
+                }
+            }
+            _ => return false,
+        };
+    }
+
+    match parent {
+        Value::Array(values) => {
+            let index = match last.parse::<usize>() {
+                Ok(index) => index,
+                Err(_) => return false,
+            };
+            if values.len() <= index {
+                values.resize(index + 1, Value::Null);
+            }
+            values[index] = new;
+        }
+        Value::Object(map) => {
+            map.insert(last.to_string(), new);
+        }
+        _ => return false,
+    }
+
+    true
+}
+
+fn replace_tags(value: &mut Value, tags: &HashMap<String, String>) {
+    match value {
+        Value::String(tag) => {
+            if let Some(new) = tags.get(tag.as_str()) {
+                *tag = new.clone();
+            }
+        }
+        Value::Array(values) => {
+            values
+                .iter_mut()
+                .for_each(|value| replace_tags(value, tags))
+        }
+        Value::Object(map) => map.values_mut().for_each(|value| replace_tags(value, tags)),
+        _ => {}
+    }
+}
+
+impl RewriteRule {
+    fn matches(&self, tx: &Value, api_key: Option<&str>) -> bool {
+        if tx["method"] != self.method.as_str() {
+            return false;
+        }
+        if !self.keys.is_empty() && !api_key.is_some_and(|key| self.keys.iter().any(|k| k == key)) {
+            return false;
+        }
+
+        self.params.iter().all(|(path, pattern)| {
+            match get_path(&tx["params"], path) {
+                Some(Value::String(value)) => pattern.is_match(value),
+                Some(value) => pattern.is_match(&value.to_string()),
+                None => false,
+            }
+        })
+    }
+
+    fn apply(&self, tx: &mut Value, head: u64) {
+        if let Some(rename) = &self.rename {
+            tx["method"] = rename.as_str().into();
+        }
+        for (path, value) in &self.set {
+            set_path(&mut tx["params"], path, value.clone());
+        }
+        if !self.tags.is_empty() {
+            replace_tags(&mut tx["params"], &self.tags);
+        }
+
+        // We can't tell how old blocks are without a head
+        if head == 0 {
+            return;
+        }
+        for (path, max_age) in &self.max_age {
+            let oldest = head.saturating_sub(*max_age);
+            // Tags other than `earliest` are recent enough
+            let block = match get_path(&tx["params"], path).and_then(Value::as_str) {
+                Some("earliest") => 0,
+                Some(block) => {
+                    match block
+                        .strip_prefix("0x")
+                        .and_then(|block| u64::from_str_radix(block, 16).ok())
+                    {
+                        Some(block) => block,
+                        None => continue,
+                    }
+                }
+                None => continue,
+            };
+            if block < oldest {
+                set_path(&mut tx["params"], path, format!("{:#x}", oldest).into());
+            }
+        }
+    }
+}
+
+// Subtable `key` of a rule, as path -> value pairs
+fn parse_paths<'a>(
+    table: &'a toml::map::Map<String, toml::Value>,
+    key: &str,
+) -> Vec<(String, &'a toml::Value)> {
+    match table.get(key) {
+        Some(paths) => {
+            paths
+                .as_table()
+                .unwrap_or_else(|| panic!("\x1b[31mErr:\x1b[0m Could not parse {} as table!", key))
+                .iter()
+                .map(|(path, value)| (path.to_string(), value))
+                .collect()
+        }
+        None => Vec::new(),
+    }
+}
+
+// Parse the `[rewrite]` table, where every subtable is a rule
+pub fn parse_rewrites(rewrite_table: &toml::map::Map<String, toml::Value>) -> Vec<RewriteRule> {
+    let mut rules = Vec::new();
+
+    for (name, rule_table) in rewrite_table {
+        let rule_table = rule_table
+            .as_table()
+            .expect("\x1b[31mErr:\x1b[0m Could not parse rewrite rule as table!");
+        let as_str = |value: &toml::Value, what: &str| {
+            value
+                .as_str()
+                .unwrap_or_else(|| panic!("\x1b[31mErr:\x1b[0m Could not parse {} as str!", what))
+                .to_string()
+        };
+
+        let method = as_str(
+            rule_table.get("method").unwrap_or_else(|| {
+                panic!(
+                    "\x1b[31mErr:\x1b[0m Missing method from rewrite rule {}!",
+                    name
+                )
+            }),
+            "method",
+        );
+        let params = parse_paths(rule_table, "params")
+            .into_iter()
+            .map(|(path, pattern)| {
+                let pattern = Regex::new(&as_str(pattern, "param pattern")).unwrap_or_else(|err| {
+                    panic!("\x1b[31mErr:\x1b[0m Invalid pattern for {}: {}", path, err)
+                });
+                (path, pattern)
+            })
+            .collect();
+        let keys = match rule_table.get("keys") {
+            Some(keys) => {
+                keys.as_array()
+                    .expect("\x1b[31mErr:\x1b[0m Could not parse rewrite keys as array!")
+                    .iter()
+                    .map(|key| as_str(key, "API key"))
+                    .collect()
+            }
+            None => Vec::new(),
+        };
+
+        let rule = RewriteRule {
+            name: name.to_string(),
+            method,
+            params,
+            keys,
+            rename: rule_table
+                .get("rename")
+                .map(|rename| as_str(rename, "rename")),
+            set: parse_paths(rule_table, "set")
+                .into_iter()
+                .map(|(path, value)| {
+                    let value = serde_json::to_value(value)
+                        .expect("\x1b[31mErr:\x1b[0m Could not convert rewrite value to JSON!");
+                    (path, value)
+                })
+                .collect(),
+            tags: parse_paths(rule_table, "tags")
+                .into_iter()
+                .map(|(tag, new)| (tag, as_str(new, "tag")))
+                .collect(),
+            max_age: parse_paths(rule_table, "max_age")
+                .into_iter()
+                .map(|(path, max_age)| {
+                    let max_age = max_age
+                        .as_integer()
+                        .expect("\x1b[31mErr:\x1b[0m Could not parse max_age as int!");
+                    (path, max_age as u64)
+                })
+                .collect(),
+        };
+        if rule.rename.is_none()
+            && rule.set.is_empty()
+            && rule.tags.is_empty()
+            && rule.max_age.is_empty()
+        {
+            panic!(
+                "\x1b[31mErr:\x1b[0m Rewrite rule {} doesn't rewrite anything!",
+                name
+            );
+        }
+        rules.push(rule);
+    }
+
+    rules
+}
+
+// Apply every rule matching `tx` to it, with `head` being the latest block we know of.
+//
+// Returns the names of the rules we applied.
+pub fn rewrite<'a>(
+    rules: &'a [RewriteRule],
+    tx: &mut Value,
+    api_key: Option<&str>,
+    head: u64,
+) -> Vec<&'a str> {
+    let mut applied = Vec::new();
+    for rule in rules {
+        if rule.matches(tx, api_key) {
+            rule.apply(tx, head);
+            applied.push(rule.name.as_str());
+        }
+    }
+
+    applied
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn rule(method: &str) -> RewriteRule {
+        RewriteRule {
+            name: "test".to_string(),
+            method: method.to_string(),
+            params: Vec::new(),
+            keys: Vec::new(),
+            rename: None,
+            set: Vec::new(),
+            tags: HashMap::new(),
+            max_age: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_paths() {
+        let mut params = json!([{"fromBlock": "0x1"}, "latest"]);
+        assert_eq!(get_path(&params, "0.fromBlock"), Some(&json!("0x1")));
+        assert_eq!(get_path(&params, "1"), Some(&json!("latest")));
+        assert_eq!(get_path(&params, "2"), None);
+
+        assert!(set_path(&mut params, "0.toBlock", json!("0x2")));
+        assert!(set_path(&mut params, "3", json!(true)));
+        assert!(!set_path(&mut params, "5.nope", json!(1)));
+        assert_eq!(
+            params,
+            json!([{"fromBlock": "0x1", "toBlock": "0x2"}, "latest", null, true])
+        );
+    }
+
+    #[test]
+    fn test_rename() {
+        let mut deprecated = rule("eth_getBlockTransactionCountByHash_v0");
+        deprecated.rename = Some("eth_getBlockTransactionCountByHash".to_string());
+
+        let mut tx = json!({"id": 1, "jsonrpc": "2.0", "method": "eth_getBlockTransactionCountByHash_v0", "params": ["0xabc"]});
+        assert_eq!(rewrite(&[deprecated.clone()], &mut tx, None, 0), ["test"]);
+        assert_eq!(tx["method"], "eth_getBlockTransactionCountByHash");
+
+        // Other methods are left alone
+        let mut tx = json!({"id": 1, "jsonrpc": "2.0", "method": "eth_chainId", "params": []});
+        assert!(rewrite(&[deprecated], &mut tx, None, 0).is_empty());
+    }
+
+    #[test]
+    fn test_tags_for_keys() {
+        let mut safe = rule("eth_call");
+        safe.keys = vec!["indexer".to_string()];
+        safe.tags = HashMap::from([("latest".to_string(), "safe".to_string())]);
+        let tx = json!({"id": 1, "jsonrpc": "2.0", "method": "eth_call", "params": [{"to": "0xaa"}, "latest"]});
+
+        let mut rewritten = tx.clone();
+        rewrite(&[safe.clone()], &mut rewritten, Some("indexer"), 0);
+        assert_eq!(rewritten["params"][1], "safe");
+
+        for api_key in [None, Some("other")] {
+            let mut rewritten = tx.clone();
+            rewrite(&[safe.clone()], &mut rewritten, api_key, 0);
+            assert_eq!(rewritten, tx);
+        }
+    }
+
+    #[test]
+    fn test_max_age() {
+        let mut clamp = rule("eth_getLogs");
+        clamp.max_age = vec![("0.fromBlock".to_string(), 100)];
+        let logs = |from: &str| json!({"id": 1, "jsonrpc": "2.0", "method": "eth_getLogs", "params": [{"fromBlock": from}]});
+
+        for (from, clamped) in [
+            ("0x0", "0x384"),
+            ("earliest", "0x384"),
+            ("0x10", "0x384"),
+            ("0x3e7", "0x3e7"),
+            ("latest", "latest"),
+        ] {
+            let mut tx = logs(from);
+            rewrite(&[clamp.clone()], &mut tx, None, 1000);
+            assert_eq!(tx["params"][0]["fromBlock"], clamped, "{}", from);
+        }
+
+        // Without a head we leave it
+        let mut tx = logs("0x0");
+        rewrite(&[clamp], &mut tx, None, 0);
+        assert_eq!(tx["params"][0]["fromBlock"], "0x0");
+    }
+
+    #[test]
+    fn test_parse_rewrites() {
+        let table: toml::Value = toml::from_str(
+            r#"
+            [clamp_logs]
+            method = "eth_getLogs"
+            max_age = { "0.fromBlock" = 10000 }
+
+            [safe_calls]
+            method = "eth_call"
+            keys = ["indexer"]
+            params = { "1" = "^latest$" }
+            set = { "1" = "safe" }
+            "#,
+        )
+        .unwrap();
+        let rules = parse_rewrites(table.as_table().unwrap());
+
+        assert_eq!(rules.len(), 2);
+        assert_eq!(rules[0].max_age, [("0.fromBlock".to_string(), 10000)]);
+        assert_eq!(rules[1].keys, ["indexer"]);
+        assert_eq!(rules[1].set, [("1".to_string(), json!("safe"))]);
+        assert!(rules[1].params[0].1.is_match("latest"));
+    }
+
+    #[test]
+    fn test_param_patterns() {
+        let mut set = rule("eth_getBalance");
+        set.params = vec![("1".to_string(), Regex::new("^pending$").unwrap())];
+        set.set = vec![("1".to_string(), json!("latest"))];
+
+        let mut tx = json!({"id": 1, "jsonrpc": "2.0", "method": "eth_getBalance", "params": ["0xaa", "pending"]});
+        rewrite(&[set.clone()], &mut tx, None, 0);
+        assert_eq!(tx["params"][1], "latest");
+
+        let mut tx = json!({"id": 1, "jsonrpc": "2.0", "method": "eth_getBalance", "params": ["0xaa", "0x10"]});
+        assert!(rewrite(&[set], &mut tx, None, 0).is_empty());
+    }
+}
@@ -0,0 +1,376 @@
+// Checks that upstream responses look like what the spec says they should.
+//
+// Some providers return blocks with missing fields, numbers with leading zeros or
+// addresses that aren't 20 bytes. Clients usually choke on those, and once cached we'd
+// keep serving them. With a sample rate set, that share of responses to the methods below
+// gets checked before it's cached or served. Responses that don't conform are retried on
+// another RPC, and counted against the RPC that sent them.
+//
+// Only fields the spec requires are checked. Extra fields, like the ones L2s add to
+// blocks and receipts, are fine.
+use crate::logging::filter::is_sampled;
+
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{
+            AtomicU64,
+            Ordering,
+        },
+        Mutex,
+    },
+};
+
+use serde_json::{
+    json,
+    Value,
+};
+
+#[derive(Debug, Clone, Copy)]
+enum Kind {
+    // Hex number without leading zeros
+    Quantity,
+    // Hex data of any even length
+    Data,
+    // Hex data of exactly this many bytes
+    Bytes(usize),
+    Bool,
+    Nullable(&'static Kind),
+    Array(&'static Kind),
+    // Either of two kinds, like transaction hashes or full transactions in blocks
+    Either(&'static Kind, &'static Kind),
+    // Object with at least these fields
+    Object(&'static [(&'static str, Kind)]),
+}
+
+const HASH: Kind = Kind::Bytes(32);
+const ADDRESS: Kind = Kind::Bytes(20);
+
+const LOG: Kind = Kind::Object(&[
+    ("address", ADDRESS),
+    ("topics", Kind::Array(&HASH)),
+    ("data", Kind::Data),
+    ("blockNumber", Kind::Nullable(&Kind::Quantity)),
+    ("blockHash", Kind::Nullable(&HASH)),
+    ("transactionHash", Kind::Nullable(&HASH)),
+    ("transactionIndex", Kind::Nullable(&Kind::Quantity)),
+    ("logIndex", Kind::Nullable(&Kind::Quantity)),
+    ("removed", Kind::Bool),
+]);
+
+const TRANSACTION: Kind = Kind::Object(&[
+    ("hash", HASH),
+    ("nonce", Kind::Quantity),
+    ("blockHash", Kind::Nullable(&HASH)),
+    ("blockNumber", Kind::Nullable(&Kind::Quantity)),
+    ("transactionIndex", Kind::Nullable(&Kind::Quantity)),
+    ("from", ADDRESS),
+    ("to", Kind::Nullable(&ADDRESS)),
+    ("value", Kind::Quantity),
+    ("gas", Kind::Quantity),
+    ("input", Kind::Data),
+]);
+
+const RECEIPT: Kind = Kind::Object(&[
+    ("transactionHash", HASH),
+    ("transactionIndex", Kind::Quantity),
+    ("blockHash", HASH),
+    ("blockNumber", Kind::Quantity),
+    ("from", ADDRESS),
+    ("to", Kind::Nullable(&ADDRESS)),
+    ("cumulativeGasUsed", Kind::Quantity),
+    ("gasUsed", Kind::Quantity),
+    ("contractAddress", Kind::Nullable(&ADDRESS)),
+    ("logs", Kind::Array(&LOG)),
+    ("logsBloom", Kind::Bytes(256)),
+]);
+
+// Pending blocks have no number or hash yet
+const BLOCK: Kind = Kind::Object(&[
+    ("number", Kind::Nullable(&Kind::Quantity)),
+    ("hash", Kind::Nullable(&HASH)),
+    ("parentHash", HASH),
+    ("sha3Uncles", HASH),
+    ("logsBloom", Kind::Nullable(&Kind::Bytes(256))),
+    ("transactionsRoot", HASH),
+    ("stateRoot", HASH),
+    ("receiptsRoot", HASH),
+    ("miner", ADDRESS),
+    ("extraData", Kind::Data),
+    ("gasLimit", Kind::Quantity),
+    ("gasUsed", Kind::Quantity),
+    ("timestamp", Kind::Quantity),
+    (
+        "transactions",
+        Kind::Array(&Kind::Either(&HASH, &TRANSACTION)),
+    ),
+    ("uncles", Kind::Array(&HASH)),
+]);
+
+// What the result of `method` should look like, None if we don't know
+fn result_kind(method: &str) -> Option<Kind> {
+    let kind = match method {
+        "eth_getBlockByNumber" | "eth_getBlockByHash" => Kind::Nullable(&BLOCK),
+        "eth_getTransactionByHash"
+        | "eth_getTransactionByBlockHashAndIndex"
+        | "eth_getTransactionByBlockNumberAndIndex" => Kind::Nullable(&TRANSACTION),
+        "eth_getTransactionReceipt" => Kind::Nullable(&RECEIPT),
+        "eth_getBlockReceipts" => Kind::Nullable(&Kind::Array(&RECEIPT)),
+        "eth_getLogs" => Kind::Array(&LOG),
+        "eth_blockNumber"
+        | "eth_chainId"
+        | "eth_gasPrice"
+        | "eth_getBalance"
+        | "eth_getTransactionCount" => Kind::Quantity,
+        _ => return None,
+    };
+    Some(kind)
+}
+
+fn hex_digits(value: &Value) -> Option<&str> {
+    value.as_str()?.strip_prefix("0x")
+}
+
+fn is_hex(digits: &str) -> bool {
+    digits.bytes().all(|byte| byte.is_ascii_hexdigit())
+}
+
+// Why `value` at `path` isn't of `kind`, None if it is
+fn violation(kind: Kind, value: &Value, path: &str) -> Option<String> {
+    match kind {
+        Kind::Quantity => {
+            match hex_digits(value) {
+                Some("0") => None,
+                Some(digits)
+                    if !digits.is_empty() && !digits.starts_with('0') && is_hex(digits) =>
+                {
+                    None
+                }
+                _ => Some(format!("{} is not a hex quantity: {}", path, value)),
+            }
+        }
+        Kind::Data => {
+            match hex_digits(value) {
+                Some(digits) if digits.len() % 2 == 0 && is_hex(digits) => None,
+                _ => Some(format!("{} is not hex data: {}", path, value)),
+            }
+        }
+        Kind::Bytes(len) => {
+            match hex_digits(value) {
+                Some(digits) if digits.len() == len * 2 && is_hex(digits) => None,
+                _ => Some(format!("{} is not {} bytes of hex: {}", path, len, value)),
+            }
+        }
+        Kind::Bool => {
+            match value.is_boolean() {
+                true => None,
+                false => Some(format!("{} is not a bool: {}", path, value)),
+            }
+        }
+        Kind::Nullable(kind) => {
+            match value.is_null() {
+                true => None,
+                false => violation(*kind, value, path),
+            }
+        }
+        Kind::Array(kind) => {
+            match value.as_array() {
+                Some(values) => {
+                    values
+                        .iter()
+                        .enumerate()
+                        .find_map(|(i, value)| violation(*kind, value, &format!("{}[{}]", path, i)))
+                }
+                None => Some(format!("{} is not an array", path)),
+            }
+        }
+        Kind::Either(first, second) => {
+            violation(*first, value, path).and_then(|_| violation(*second, value, path))
+        }
+        Kind::Object(fields) => {
+            let object = match value.as_object() {
+                Some(object) => object,
+                None => return Some(format!("{} is not an object", path)),
+            };
+            fields.iter().find_map(|(name, kind)| {
+                match object.get(*name) {
+                    Some(value) => violation(*kind, value, &format!("{}.{}", path, name)),
+                    None => Some(format!("{}.{} is missing", path, name)),
+                }
+            })
+        }
+    }
+}
+
+// Why the response `rx` to `method` doesn't conform, None if it does, is an error,
+// or is for a method we don't check
+pub fn check(method: &str, rx: &[u8]) -> Option<String> {
+    let kind = result_kind(method)?;
+    let rx: Value = match serde_json::from_slice(rx) {
+        Ok(rx) => rx,
+        Err(_) => return Some("response is not JSON".to_string()),
+    };
+    if rx.get("error").is_some() {
+        return None;
+    }
+
+    match rx.get("result") {
+        Some(result) => violation(kind, result, "result"),
+        None => Some("result is missing".to_string()),
+    }
+}
+
+#[derive(Debug, Default)]
+struct Violations {
+    count: u64,
+    // Latest reason, so operators can see what's wrong without digging through logs
+    last: String,
+}
+
+#[derive(Debug)]
+pub struct SchemaChecker {
+    sample_rate: f64,
+    checked: AtomicU64,
+    // RPC name -> responses of it that didn't conform
+    violations: Mutex<HashMap<String, Violations>>,
+}
+
+impl SchemaChecker {
+    pub fn new(sample_rate: f64) -> Self {
+        Self {
+            sample_rate,
+            checked: AtomicU64::new(0),
+            violations: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // If the response to `method` should be checked. Only counts methods we can check.
+    pub fn samples(&self, method: &str) -> bool {
+        result_kind(method).is_some()
+            && is_sampled(
+                self.checked.fetch_add(1, Ordering::Relaxed),
+                self.sample_rate,
+            )
+    }
+
+    // Remember that `rpc` sent a response that doesn't conform, and why
+    pub fn record(&self, rpc: &str, reason: &str) {
+        let mut violations = self.violations.lock().unwrap_or_else(|e| e.into_inner());
+        let violations = violations.entry(rpc.to_string()).or_default();
+        violations.count += 1;
+        violations.last = reason.to_string();
+    }
+
+    // Responses that didn't conform and the latest reason why, by RPC
+    pub fn report(&self) -> Value {
+        let violations = self.violations.lock().unwrap_or_else(|e| e.into_inner());
+        let mut report = json!({});
+        for (rpc, violations) in violations.iter() {
+            report[rpc] = json!({
+                "violations": violations.count,
+                "last": violations.last,
+            });
+        }
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response(result: Value) -> Vec<u8> {
+        serde_json::to_vec(&json!({"id": 1, "jsonrpc": "2.0", "result": result})).unwrap()
+    }
+
+    fn log() -> Value {
+        json!({
+            "address": format!("0x{}", "ab".repeat(20)),
+            "topics": [format!("0x{}", "01".repeat(32))],
+            "data": "0x",
+            "blockNumber": "0x10",
+            "blockHash": format!("0x{}", "02".repeat(32)),
+            "transactionHash": format!("0x{}", "03".repeat(32)),
+            "transactionIndex": "0x0",
+            "logIndex": "0x1",
+            "removed": false,
+        })
+    }
+
+    #[test]
+    fn test_check() {
+        assert_eq!(check("eth_blockNumber", &response(json!("0x10"))), None);
+        assert_eq!(check("eth_getLogs", &response(json!([log()]))), None);
+        // Nothing to check
+        assert_eq!(check("eth_call", &response(json!("0x0001"))), None);
+        assert_eq!(
+            check("eth_getTransactionReceipt", &response(Value::Null)),
+            None
+        );
+        assert_eq!(
+            check(
+                "eth_getLogs",
+                br#"{"id":1,"jsonrpc":"2.0","error":{"code":-32000,"message":"oops"}}"#
+            ),
+            None
+        );
+
+        assert_eq!(
+            check("eth_blockNumber", &response(json!("0x010"))),
+            Some("result is not a hex quantity: \"0x010\"".to_string())
+        );
+        let mut short = log();
+        short["address"] = json!("0xabcd");
+        assert_eq!(
+            check("eth_getLogs", &response(json!([log(), short]))),
+            Some("result[1].address is not 20 bytes of hex: \"0xabcd\"".to_string())
+        );
+        let mut missing = log();
+        missing.as_object_mut().unwrap().remove("removed");
+        assert_eq!(
+            check("eth_getLogs", &response(json!([missing]))),
+            Some("result[0].removed is missing".to_string())
+        );
+    }
+
+    #[test]
+    fn test_block_transactions() {
+        let mut block = json!({
+            "number": "0x1",
+            "hash": format!("0x{}", "01".repeat(32)),
+            "parentHash": format!("0x{}", "02".repeat(32)),
+            "sha3Uncles": format!("0x{}", "03".repeat(32)),
+            "logsBloom": format!("0x{}", "00".repeat(256)),
+            "transactionsRoot": format!("0x{}", "04".repeat(32)),
+            "stateRoot": format!("0x{}", "05".repeat(32)),
+            "receiptsRoot": format!("0x{}", "06".repeat(32)),
+            "miner": format!("0x{}", "07".repeat(20)),
+            "extraData": "0x",
+            "gasLimit": "0x1c9c380",
+            "gasUsed": "0x0",
+            "timestamp": "0x65",
+            "transactions": [format!("0x{}", "08".repeat(32))],
+            "uncles": [],
+        });
+        assert_eq!(
+            check("eth_getBlockByNumber", &response(block.clone())),
+            None
+        );
+
+        block["transactions"] = json!([{"hash": "0x1"}]);
+        assert!(check("eth_getBlockByNumber", &response(block)).is_some());
+    }
+
+    #[test]
+    fn test_schema_checker() {
+        let checker = SchemaChecker::new(0.5);
+        let sampled = (0..10).filter(|_| checker.samples("eth_getLogs")).count();
+        assert_eq!(sampled, 5);
+        assert!(!checker.samples("eth_call"));
+
+        checker.record("bad", "result is missing");
+        checker.record("bad", "result is not an array");
+        assert_eq!(checker.report()["bad"]["violations"], 2);
+        assert_eq!(checker.report()["bad"]["last"], "result is not an array");
+    }
+}
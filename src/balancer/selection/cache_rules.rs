@@ -1,10 +1,123 @@
+use crate::ratelimit::types::unix_millis;
+
+use std::{
+    collections::BTreeMap,
+    time::Duration,
+};
+
 use memchr::memmem;
+use serde_json::Value;
+use sled::Tree;
+
+// Tree where we keep when entries cached with a TTL expire
+pub const EXPIRY_TREE: &str = "cache_expiry";
+
+// How responses to a method get cached, set per method in `[cache_rules]`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CachePolicy {
+    // Cached if the request references a block, and dropped if that block reorgs
+    #[default]
+    Default,
+    Never,
+    // Cached whatever block it references, and never dropped
+    Forever,
+    // Cached whatever block it references, for this long
+    Ttl(Duration),
+    // Only cached once the block the request or its result references is finalized
+    Finalized,
+}
+
+impl CachePolicy {
+    // Parse `"never"`, `"forever"`, `"finalized"`, `"default"` or `{ ttl_ms = N }`
+    pub fn from_config(value: &toml::Value) -> Option<Self> {
+        if let Some(ttl) = value.get("ttl_ms") {
+            return ttl
+                .as_integer()
+                .filter(|ttl| *ttl > 0)
+                .map(|ttl| CachePolicy::Ttl(Duration::from_millis(ttl as u64)));
+        }
+
+        match value.as_str()? {
+            "default" => Some(CachePolicy::Default),
+            "never" => Some(CachePolicy::Never),
+            "forever" => Some(CachePolicy::Forever),
+            "finalized" => Some(CachePolicy::Finalized),
+            _ => None,
+        }
+    }
+}
+
+// Cache policies of methods, methods without one use `CachePolicy::Default`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CacheRules {
+    rules: BTreeMap<String, CachePolicy>,
+}
+
+impl CacheRules {
+    pub fn policy(&self, method: &str) -> CachePolicy {
+        self.rules.get(method).copied().unwrap_or_default()
+    }
+}
+
+// Parse the `[cache_rules]` table, `method = policy`
+pub fn parse_cache_rules(table: &toml::map::Map<String, toml::Value>) -> CacheRules {
+    let rules = table
+        .iter()
+        .map(|(method, policy)| {
+            let policy = CachePolicy::from_config(policy).unwrap_or_else(|| {
+                panic!(
+                    "\x1b[31mErr:\x1b[0m Cache rule for {} must be one of default/never/forever/finalized, or {{ ttl_ms = N }}!",
+                    method
+                )
+            });
+            (method.to_string(), policy)
+        })
+        .collect();
+
+    CacheRules { rules }
+}
+
+// If the entry under `key` expired. Entries we don't know the expiry of count as expired.
+pub fn is_expired(expiry: &Tree, key: &[u8]) -> Result<bool, sled::Error> {
+    let expires_at = match expiry.get(key)? {
+        Some(expires_at) => expires_at,
+        None => return Ok(true),
+    };
+
+    Ok(match <[u8; 8]>::try_from(expires_at.as_ref()) {
+        Ok(expires_at) => u64::from_be_bytes(expires_at) <= unix_millis(),
+        Err(_) => true,
+    })
+}
+
+// If the entry under `key` has an expiry and it passed. Entries without one never expire.
+pub fn has_expired(expiry: &Tree, key: &[u8]) -> Result<bool, sled::Error> {
+    Ok(expiry.contains_key(key)? && is_expired(expiry, key)?)
+}
+
+pub fn set_expiry(expiry: &Tree, key: &[u8], ttl: Duration) -> Result<(), sled::Error> {
+    let expires_at = unix_millis().saturating_add(ttl.as_millis() as u64);
+    expiry.insert(key, &expires_at.to_be_bytes())?;
+
+    Ok(())
+}
+
+// Block the result of a lookup is from, for requests that reference it by hash
+pub fn result_block_number(rx: &[u8]) -> Option<u64> {
+    let rx: Value = serde_json::from_slice(rx).ok()?;
+    let result = &rx["result"];
+    let number = result["blockNumber"]
+        .as_str()
+        .or_else(|| result["number"].as_str())?;
+
+    u64::from_str_radix(number.trim_start_matches("0x"), 16).ok()
+}
 
 // Return true if we are supposed to be caching the input.
 //
 // The default rust string contains does not use SIMD extensions
 // memchr::memmem is way faster because it uses them
-pub fn cache_method(rx: &str) -> bool {
+pub fn cache_method(rx: &[u8]) -> bool {
     // If no-cache feature is on, return false
     #[cfg(feature = "no-cache")]
     return false;
@@ -24,7 +137,7 @@ pub fn cache_method(rx: &str) -> bool {
     // We could potentially try to find `params` and then move from there but it would end up
     // being slower in most cases.
     for item in blacklist.iter() {
-        if memmem::find(rx.as_bytes(), item.as_bytes()).is_some() {
+        if memmem::find(rx, item.as_bytes()).is_some() {
             return false;
         }
     }
@@ -33,7 +146,7 @@ pub fn cache_method(rx: &str) -> bool {
 }
 
 // Same as cache_method but for results
-pub fn cache_result(rx: &str) -> bool {
+pub fn cache_result(rx: &[u8]) -> bool {
     // If no-cache feature is on, return false
     #[cfg(feature = "no-cache")]
     return false;
@@ -43,10 +156,86 @@ pub fn cache_result(rx: &str) -> bool {
     let blacklist = ["error", "-320", "-326", "-327"];
 
     for item in blacklist.iter() {
-        if memmem::find(rx.as_bytes(), item.as_bytes()).is_some() {
+        if memmem::find(rx, item.as_bytes()).is_some() {
             return false;
         }
     }
 
     true
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_cache_rules() {
+        let table: toml::map::Map<String, toml::Value> = toml::from_str(
+            r#"
+            eth_blockNumber = "never"
+            eth_chainId = "forever"
+            eth_call = { ttl_ms = 12000 }
+            eth_getTransactionByHash = "finalized"
+            "#,
+        )
+        .unwrap();
+        let rules = parse_cache_rules(&table);
+
+        assert_eq!(rules.policy("eth_blockNumber"), CachePolicy::Never);
+        assert_eq!(rules.policy("eth_chainId"), CachePolicy::Forever);
+        assert_eq!(
+            rules.policy("eth_call"),
+            CachePolicy::Ttl(Duration::from_secs(12))
+        );
+        assert_eq!(
+            rules.policy("eth_getTransactionByHash"),
+            CachePolicy::Finalized
+        );
+        assert_eq!(rules.policy("eth_getBalance"), CachePolicy::Default);
+    }
+
+    #[test]
+    fn test_cache_policy_from_config() {
+        assert_eq!(
+            CachePolicy::from_config(&toml::Value::from("sometimes")),
+            None
+        );
+        assert_eq!(CachePolicy::from_config(&toml::Value::from(12)), None);
+        let table: toml::Value = toml::from_str("ttl_ms = 0").unwrap();
+        assert_eq!(CachePolicy::from_config(&table), None);
+    }
+
+    #[test]
+    fn test_expiry() {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let expiry = db.open_tree(EXPIRY_TREE).unwrap();
+
+        assert!(is_expired(&expiry, b"key").unwrap());
+        set_expiry(&expiry, b"key", Duration::from_secs(60)).unwrap();
+        assert!(!is_expired(&expiry, b"key").unwrap());
+        set_expiry(&expiry, b"key", Duration::ZERO).unwrap();
+        assert!(is_expired(&expiry, b"key").unwrap());
+
+        // Only entries we gave an expiry can expire
+        assert!(has_expired(&expiry, b"key").unwrap());
+        assert!(!has_expired(&expiry, b"other").unwrap());
+    }
+
+    #[test]
+    fn test_result_block_number() {
+        assert_eq!(
+            result_block_number(br#"{"id":null,"result":{"blockNumber":"0x10","hash":"0x1"}}"#),
+            Some(16)
+        );
+        assert_eq!(
+            result_block_number(br#"{"id":null,"result":{"number":"0x11"}}"#),
+            Some(17)
+        );
+        // Pending transactions aren't in a block yet
+        assert_eq!(
+            result_block_number(br#"{"id":null,"result":{"blockNumber":null}}"#),
+            None
+        );
+        assert_eq!(result_block_number(br#"{"id":null,"result":null}"#), None);
+    }
+}
@@ -1,2 +1,3 @@
 pub mod cache_rules;
+pub mod routing;
 pub mod select;
@@ -0,0 +1,227 @@
+// Routing requests to RPCs by their tags, e.g. sending what needs archive state to archive
+// nodes and everything else to cheaper full nodes.
+//
+// Every subtable of `[routing]` is a rule. It matches requests for its methods, and
+// optionally only ones for blocks at least `min_age` blocks below the head, and sends
+// them to RPCs with any of its tags. Rules are checked in the order of their names, and
+// requests no rule matches go to RPCs with any of `default_tags`, if there are some.
+use crate::{
+    balancer::format::get_block_number_from_request,
+    NamedBlocknumbers,
+};
+
+use std::sync::{
+    Arc,
+    RwLock,
+};
+
+use serde_json::Value;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RoutingRule {
+    pub name: String,
+    // Method names, or namespaces like `trace_*`
+    pub methods: Vec<String>,
+    // Only requests for blocks at least this many blocks below the head
+    pub min_age: Option<u64>,
+    pub tags: Vec<String>,
+}
+
+impl RoutingRule {
+    fn matches_method(&self, method: &str) -> bool {
+        self.methods.iter().any(|pattern| {
+            match pattern.strip_suffix('*') {
+                Some(prefix) => method.starts_with(prefix),
+                None => pattern == method,
+            }
+        })
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RoutingRules {
+    rules: Vec<RoutingRule>,
+    default_tags: Vec<String>,
+}
+
+impl RoutingRules {
+    // Every tag some request can get routed to
+    pub fn tags(&self) -> Vec<&String> {
+        let mut tags: Vec<&String> = self
+            .rules
+            .iter()
+            .flat_map(|rule| &rule.tags)
+            .chain(&self.default_tags)
+            .collect();
+        tags.sort();
+        tags.dedup();
+        tags
+    }
+
+    // Tags of the RPCs `tx` can go to, None if it can go to any
+    pub fn tags_for(
+        &self,
+        tx: &Value,
+        head: u64,
+        named_numbers: &Arc<RwLock<NamedBlocknumbers>>,
+    ) -> Option<Vec<String>> {
+        let method = tx["method"].as_str().unwrap_or_default();
+
+        // Only worked out once, and only if a rule cares about it
+        let mut age = None;
+        for rule in &self.rules {
+            if !rule.matches_method(method) {
+                continue;
+            }
+            let min_age = match rule.min_age {
+                Some(min_age) => min_age,
+                None => return Some(rule.tags.clone()),
+            };
+
+            let age = *age.get_or_insert_with(|| {
+                // Can't tell how old anything is before we know the head
+                let number = get_block_number_from_request(tx.clone(), named_numbers)?;
+                (head > 0).then(|| head.saturating_sub(number))
+            });
+            if age.is_some_and(|age| age >= min_age) {
+                return Some(rule.tags.clone());
+            }
+        }
+
+        match self.default_tags.is_empty() {
+            true => None,
+            false => Some(self.default_tags.clone()),
+        }
+    }
+}
+
+fn parse_tags(value: &toml::Value, what: &str) -> Vec<String> {
+    value
+        .as_array()
+        .and_then(|tags| {
+            tags.iter()
+                .map(|tag| tag.as_str().map(str::to_string))
+                .collect()
+        })
+        .unwrap_or_else(|| {
+            panic!(
+                "\x1b[31mErr:\x1b[0m Could not parse {} as an array of strings!",
+                what
+            )
+        })
+}
+
+// Parse the `[routing]` table, where every subtable is a rule
+pub fn parse_routing(routing_table: &toml::map::Map<String, toml::Value>) -> RoutingRules {
+    let mut routing = RoutingRules::default();
+
+    for (name, value) in routing_table {
+        if name == "default_tags" {
+            routing.default_tags = parse_tags(value, "default_tags");
+            continue;
+        }
+
+        let rule_table = value
+            .as_table()
+            .expect("\x1b[31mErr:\x1b[0m Could not parse routing rule as table!");
+        let methods = parse_tags(
+            rule_table.get("methods").unwrap_or_else(|| {
+                panic!(
+                    "\x1b[31mErr:\x1b[0m Missing methods from routing rule {}!",
+                    name
+                )
+            }),
+            "routing methods",
+        );
+        let tags = parse_tags(
+            rule_table.get("tags").unwrap_or_else(|| {
+                panic!(
+                    "\x1b[31mErr:\x1b[0m Missing tags from routing rule {}!",
+                    name
+                )
+            }),
+            "routing tags",
+        );
+        let min_age = rule_table.get("min_age").map(|min_age| {
+            min_age
+                .as_integer()
+                .expect("\x1b[31mErr:\x1b[0m Could not parse min_age as int!") as u64
+        });
+
+        routing.rules.push(RoutingRule {
+            name: name.to_string(),
+            methods,
+            min_age,
+            tags,
+        });
+    }
+
+    routing
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn routing() -> RoutingRules {
+        let table = r#"
+            default_tags = ["full"]
+
+            [archive]
+            methods = ["trace_*", "debug_*"]
+            tags = ["archive"]
+
+            [historical]
+            methods = ["eth_call", "eth_getBalance"]
+            min_age = 128
+            tags = ["archive"]
+        "#
+        .parse::<toml::Value>()
+        .unwrap();
+        parse_routing(table.as_table().unwrap())
+    }
+
+    fn tags_for(tx: Value, head: u64) -> Option<Vec<String>> {
+        let named_numbers = Arc::new(RwLock::new(NamedBlocknumbers {
+            latest: head,
+            ..Default::default()
+        }));
+        routing().tags_for(&tx, head, &named_numbers)
+    }
+
+    #[test]
+    fn test_tags_for() {
+        let archive = Some(vec!["archive".to_string()]);
+        let full = Some(vec!["full".to_string()]);
+
+        let tx = json!({"method": "trace_block", "params": ["0x1"]});
+        assert_eq!(tags_for(tx, 1000), archive);
+        let tx = json!({"method": "debug_traceTransaction", "params": ["0xabc"]});
+        assert_eq!(tags_for(tx, 1000), archive);
+
+        // Old enough
+        let tx = json!({"method": "eth_getBalance", "params": ["0xaa", "0x64"]});
+        assert_eq!(tags_for(tx, 1000), archive);
+        let tx = json!({"method": "eth_call", "params": [{}, "earliest"]});
+        assert_eq!(tags_for(tx, 1000), archive);
+
+        // Recent enough for a full node
+        let tx = json!({"method": "eth_getBalance", "params": ["0xaa", "0x3e0"]});
+        assert_eq!(tags_for(tx, 1000), full);
+        let tx = json!({"method": "eth_call", "params": [{}, "latest"]});
+        assert_eq!(tags_for(tx, 1000), full);
+
+        // Without a head everything counts as recent
+        let tx = json!({"method": "eth_getBalance", "params": ["0xaa", "0x1"]});
+        assert_eq!(tags_for(tx, 0), full);
+
+        let tx = json!({"method": "eth_blockNumber", "params": []});
+        assert_eq!(tags_for(tx, 1000), full);
+        let named_numbers = Arc::new(RwLock::new(NamedBlocknumbers::default()));
+        assert_eq!(
+            RoutingRules::default().tags_for(&json!({"method": "trace_block"}), 1, &named_numbers),
+            None
+        );
+    }
+}
@@ -1,7 +1,226 @@
 use crate::Rpc;
 
+use std::{
+    collections::hash_map::DefaultHasher,
+    hash::{
+        Hash,
+        Hasher,
+    },
+};
+
+// Which RPCs a request is allowed to go to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Route {
+    // Any RPC, picked by the selection algo
+    Any,
+    // Only the RPC with this name
+    Pinned(String),
+    // Only RPCs in the pool of a namespace, picked by the selection algo
+    Pool(Vec<String>),
+    // Only RPCs with any of these tags, picked by the selection algo
+    Tagged(Vec<String>),
+    // Only RPCs that support `eth_call` state overrides
+    StateOverrides,
+    // Heavy methods prefer archive RPCs, falling back to any if none are available
+    Archive,
+    // Prefer RPCs with these names, falling back to any if none are available
+    Preferred(Vec<String>),
+}
+
+// How we pick between the RPCs a request is allowed to go to
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Strategy {
+    // Fastest RPC, picked by the selection algo
+    #[default]
+    Latency,
+    // Cheapest RPC faster than `max_latency` ms, 0 meaning no ceiling.
+    // RPCs that cost the same are picked by the selection algo.
+    Cheapest {
+        max_latency: f64,
+    },
+    // Same request always goes to the same RPC, so upstream caches get more hits.
+    // `key` is the hash of the request, and `attempt` how many times we retried it.
+    Affinity {
+        key: u64,
+        attempt: u32,
+    },
+    // Requests spread over RPCs proportionally to how fast they are
+    WeightedLatency,
+}
+
+impl Strategy {
+    pub fn from_config(strategy: &str, max_latency: f64) -> Option<Self> {
+        match strategy {
+            "latency" => Some(Strategy::Latency),
+            "cheapest" => Some(Strategy::Cheapest { max_latency }),
+            "affinity" => Some(Strategy::Affinity { key: 0, attempt: 0 }),
+            "weighted_latency" => Some(Strategy::WeightedLatency),
+            _ => None,
+        }
+    }
+
+    // Set the request we're picking an RPC for. Only affinity routing cares about it.
+    pub fn for_request(self, key: u64, attempt: u32) -> Self {
+        match self {
+            Strategy::Affinity { .. } => Strategy::Affinity { key, attempt },
+            strategy => strategy,
+        }
+    }
+}
+
+// Select the next RPC that satisfies `route`.
+//
+// RPCs paused by the schedule are skipped, unless a method is pinned to them.
+// RPCs in breach of their SLA only get the share of requests they admit, and degraded
+// ones only get requests no other RPC can take.
+pub fn pick_route(list: &mut [Rpc], route: &Route, strategy: Strategy) -> (Rpc, Option<usize>) {
+    match route {
+        Route::Any
+            if list
+                .iter()
+                .all(|rpc| !rpc.paused && !rpc.sla_breached() && !rpc.degraded) =>
+        {
+            pick_strategy(list, strategy)
+        }
+        Route::Any => pick_admitted(list, strategy, |rpc| !rpc.paused),
+        Route::Pinned(name) => pick_named(list, name),
+        Route::Pool(names) => {
+            pick_admitted(list, strategy, |rpc| {
+                !rpc.paused && names.contains(&rpc.name)
+            })
+        }
+        Route::Tagged(tags) => {
+            pick_admitted(list, strategy, |rpc| {
+                !rpc.paused && rpc.tags.iter().any(|tag| tags.contains(tag))
+            })
+        }
+        Route::StateOverrides => {
+            pick_admitted(list, strategy, |rpc| {
+                !rpc.paused && rpc.supports_state_overrides
+            })
+        }
+        Route::Archive => {
+            let (rpc, index) = pick_admitted(list, strategy, |rpc| !rpc.paused && rpc.archive);
+            if index.is_none() {
+                return pick_route(list, &Route::Any, strategy);
+            }
+            (rpc, index)
+        }
+        Route::Preferred(names) => {
+            let (rpc, index) = pick_admitted(list, strategy, |rpc| {
+                !rpc.paused && names.contains(&rpc.name)
+            });
+            if index.is_none() {
+                return pick_route(list, &Route::Any, strategy);
+            }
+            (rpc, index)
+        }
+    }
+}
+
+// Select the next RPC according to `strategy`
+pub fn pick_strategy(list: &mut [Rpc], strategy: Strategy) -> (Rpc, Option<usize>) {
+    match strategy {
+        Strategy::Latency => pick(list),
+        Strategy::Cheapest { max_latency } => pick_cheapest(list, max_latency),
+        Strategy::Affinity { key, attempt } => pick_affinity(list, key, attempt),
+        Strategy::WeightedLatency => pick_weighted(list),
+    }
+}
+
+// Give every RPC a weight inversely proportional to its latency, summing up to 1
+pub fn update_weights(list: &mut [Rpc]) {
+    let speed = |rpc: &Rpc| 1.0 / rpc.status.latency.max(1.0);
+    let total: f64 = list.iter().map(speed).sum();
+
+    for rpc in list.iter_mut() {
+        rpc.status.weight = speed(rpc) / total;
+    }
+}
+
+// Smooth weighted round robin. Every pick each RPC gains its weight in credit,
+// and the one with the most credit gets the request and pays for it.
+//
+// Spreads requests out evenly instead of sending bursts to the fastest RPC.
+// Until the first health check computes weights every RPC is weighed the same.
+fn pick_weighted(list: &mut [Rpc]) -> (Rpc, Option<usize>) {
+    if list.is_empty() {
+        return (Rpc::default(), None);
+    }
+
+    let total: f64 = list.iter().map(|rpc| rpc.status.weight).sum();
+    let weight = |rpc: &Rpc| if total > 0.0 { rpc.status.weight } else { 1.0 };
+    for rpc in list.iter_mut() {
+        rpc.status.credit += weight(rpc);
+    }
+
+    let index = (0..list.len())
+        .max_by(|&a, &b| list[a].status.credit.total_cmp(&list[b].status.credit))
+        .unwrap();
+    list[index].status.credit -= if total > 0.0 {
+        total
+    } else {
+        list.len() as f64
+    };
+
+    (list[index].clone(), Some(index))
+}
+
+// Rendezvous hashing. Every RPC gets a score for `key` and the highest one wins,
+// so adding or removing an RPC only moves the keys that belonged to it.
+//
+// Retries go to the next highest scoring RPC.
+fn pick_affinity(list: &[Rpc], key: u64, attempt: u32) -> (Rpc, Option<usize>) {
+    if list.is_empty() {
+        return (Rpc::default(), None);
+    }
+
+    let score = |rpc: &Rpc| {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        match rpc.name.is_empty() {
+            true => rpc.url.hash(&mut hasher),
+            false => rpc.name.hash(&mut hasher),
+        }
+        hasher.finish()
+    };
+
+    let mut ranked: Vec<(u64, usize)> = list
+        .iter()
+        .enumerate()
+        .map(|(index, rpc)| (score(rpc), index))
+        .collect();
+    ranked.sort_unstable_by(|a, b| b.cmp(a));
+
+    let index = ranked[attempt as usize % ranked.len()].1;
+    (list[index].clone(), Some(index))
+}
+
+// Pick among the cheapest RPCs under the latency ceiling.
+//
+// If every RPC is over the ceiling we ignore costs, since a slow
+// response is better than none.
+fn pick_cheapest(list: &mut [Rpc], max_latency: f64) -> (Rpc, Option<usize>) {
+    let fast_enough = |rpc: &Rpc| max_latency == 0.0 || rpc.status.latency <= max_latency;
+
+    let cheapest = list
+        .iter()
+        .filter(|rpc| fast_enough(rpc))
+        .map(|rpc| rpc.effective_cost())
+        .reduce(f64::min);
+
+    match cheapest {
+        Some(cheapest) => {
+            pick_filtered(list, Strategy::Latency, |rpc| {
+                rpc.effective_cost() == cheapest && fast_enough(rpc)
+            })
+        }
+        None => pick(list),
+    }
+}
+
 // Generic entry point fn to select the next rpc and return its position
-pub fn pick(list: &mut Vec<Rpc>) -> (Rpc, Option<usize>) {
+pub fn pick(list: &mut [Rpc]) -> (Rpc, Option<usize>) {
     // If len is 1, return the only element
     if list.len() == 1 {
         return (list[0].clone(), Some(0));
@@ -12,8 +231,58 @@ pub fn pick(list: &mut Vec<Rpc>) -> (Rpc, Option<usize>) {
     algo(list)
 }
 
+// Select an RPC by name, ignoring the selection algo.
+//
+// Used for pinned methods. Returns None if the RPC is not in the active list.
+pub fn pick_named(list: &[Rpc], name: &str) -> (Rpc, Option<usize>) {
+    match list.iter().position(|rpc| rpc.name == name) {
+        Some(index) => (list[index].clone(), Some(index)),
+        None => (Rpc::default(), None),
+    }
+}
+
+// Run `strategy` over RPCs matching `filter` that admit the request and aren't degraded.
+//
+// If none of them do, the SLA is ignored, since a slow response is better than none.
+// Degraded RPCs come last, a response from a node that's syncing still beats none.
+fn pick_admitted<F>(list: &mut [Rpc], strategy: Strategy, filter: F) -> (Rpc, Option<usize>)
+where
+    F: Fn(&Rpc) -> bool,
+{
+    let (rpc, index) = pick_filtered(list, strategy, |rpc| {
+        filter(rpc) && !rpc.degraded && rpc.sla_admits()
+    });
+    if index.is_some() {
+        return (rpc, index);
+    }
+    let (rpc, index) = pick_filtered(list, strategy, |rpc| filter(rpc) && !rpc.degraded);
+    match index {
+        Some(_) => (rpc, index),
+        None => pick_filtered(list, strategy, filter),
+    }
+}
+
+// Run `strategy` only over RPCs matching `filter`
+pub fn pick_filtered<F>(list: &mut [Rpc], strategy: Strategy, filter: F) -> (Rpc, Option<usize>)
+where
+    F: Fn(&Rpc) -> bool,
+{
+    let eligible: Vec<usize> = (0..list.len()).filter(|&i| filter(&list[i])).collect();
+    let mut sublist: Vec<Rpc> = eligible.iter().map(|&i| list[i].clone()).collect();
+
+    let (rpc, index) = pick_strategy(&mut sublist, strategy);
+
+    // Write back the counters the algo modified
+    for (sub_index, &index) in eligible.iter().enumerate() {
+        list[index].consecutive = sublist[sub_index].consecutive;
+        list[index].status.credit = sublist[sub_index].status.credit;
+    }
+
+    (rpc, index.map(|index| eligible[index]))
+}
+
 // Sorting algo
-pub fn argsort(data: &Vec<Rpc>) -> Vec<usize> {
+pub fn argsort(data: &[Rpc]) -> Vec<usize> {
     let mut indices = (0..data.len()).collect::<Vec<usize>>();
 
     // Use sort_by_cached_key with a closure that compares latency
@@ -33,7 +302,7 @@ pub fn argsort(data: &Vec<Rpc>) -> Vec<usize> {
     feature = "selection-weighed-round-robin",
     not(feature = "selection-random")
 ))]
-fn algo(list: &mut Vec<Rpc>) -> (Rpc, Option<usize>) {
+fn algo(list: &mut [Rpc]) -> (Rpc, Option<usize>) {
     // Sort by latency
     let indices = argsort(list);
 
@@ -118,4 +387,344 @@ mod tests {
         assert_eq!(rpc.status.latency, 6.0);
         assert_eq!(index, Some(1));
     }
+
+    #[test]
+    fn test_pick_named() {
+        let mut rpc1 = Rpc::default();
+        let mut rpc2 = Rpc::default();
+
+        rpc1.name = "llama".to_string();
+        rpc2.name = "my-reth".to_string();
+
+        let rpc_list = vec![rpc1, rpc2];
+
+        let (rpc, index) = pick_named(&rpc_list, "my-reth");
+        assert_eq!(rpc.name, "my-reth");
+        assert_eq!(index, Some(1));
+
+        let (_, index) = pick_named(&rpc_list, "missing");
+        assert_eq!(index, None);
+    }
+
+    #[test]
+    fn test_pick_filtered() {
+        let mut rpc1 = Rpc::default();
+        let mut rpc2 = Rpc::default();
+        let mut rpc3 = Rpc::default();
+
+        rpc1.status.latency = 1.0;
+        rpc1.supports_state_overrides = false;
+        rpc2.status.latency = 6.0;
+        rpc2.max_consecutive = 10;
+        rpc3.status.latency = 3.0;
+        rpc3.max_consecutive = 10;
+
+        let mut rpc_list = vec![rpc1, rpc2, rpc3];
+
+        // Fastest one doesn't support overrides, so we should get the 3rd one
+        let (rpc, index) = pick_route(&mut rpc_list, &Route::StateOverrides, Strategy::Latency);
+        assert_eq!(rpc.status.latency, 3.0);
+        assert_eq!(index, Some(2));
+        assert_eq!(rpc_list[2].consecutive, 1);
+
+        // Nothing matches
+        for rpc in rpc_list.iter_mut() {
+            rpc.supports_state_overrides = false;
+        }
+        let (_, index) = pick_route(&mut rpc_list, &Route::StateOverrides, Strategy::Latency);
+        assert_eq!(index, None);
+    }
+
+    #[test]
+    fn test_pick_archive() {
+        let mut rpc1 = Rpc::default();
+        let mut rpc2 = Rpc::default();
+
+        rpc1.status.latency = 1.0;
+        rpc1.max_consecutive = 10;
+        rpc2.status.latency = 6.0;
+        rpc2.max_consecutive = 10;
+        rpc2.archive = true;
+
+        let mut rpc_list = vec![rpc1, rpc2];
+
+        let (_, index) = pick_route(&mut rpc_list, &Route::Archive, Strategy::Latency);
+        assert_eq!(index, Some(1));
+
+        // Fall back to the regular algo without archive RPCs
+        rpc_list[1].archive = false;
+        let (_, index) = pick_route(&mut rpc_list, &Route::Archive, Strategy::Latency);
+        assert_eq!(index, Some(0));
+    }
+
+    #[test]
+    fn test_pick_affinity() {
+        let mut rpc_list: Vec<Rpc> = (0..4)
+            .map(|i| {
+                let mut rpc = Rpc::default();
+                rpc.name = format!("rpc{}", i);
+                rpc.status.latency = i as f64;
+                rpc
+            })
+            .collect();
+        let strategy = Strategy::from_config("affinity", 0.0).unwrap();
+
+        // Same key, same RPC, regardless of latency
+        let (_, first) = pick_route(&mut rpc_list, &Route::Any, strategy.for_request(42, 0));
+        rpc_list.reverse();
+        let (rpc, _) = pick_route(&mut rpc_list, &Route::Any, strategy.for_request(42, 0));
+        rpc_list.reverse();
+        assert_eq!(rpc.name, rpc_list[first.unwrap()].name);
+
+        // Retries go somewhere else
+        let (retry, _) = pick_route(&mut rpc_list, &Route::Any, strategy.for_request(42, 1));
+        assert_ne!(retry.name, rpc.name);
+
+        // Keys get spread over every RPC
+        let mut picked: Vec<usize> = (0..64)
+            .filter_map(|key| {
+                pick_route(&mut rpc_list, &Route::Any, strategy.for_request(key, 0)).1
+            })
+            .collect();
+        picked.sort();
+        picked.dedup();
+        assert_eq!(picked.len(), 4);
+
+        // Removing an RPC only moves the keys it had
+        let removed = rpc_list.remove(3);
+        for key in 0..64 {
+            let before = {
+                let mut full = rpc_list.clone();
+                full.push(removed.clone());
+                pick_route(&mut full, &Route::Any, strategy.for_request(key, 0)).0
+            };
+            if before.name != removed.name {
+                let (after, _) =
+                    pick_route(&mut rpc_list, &Route::Any, strategy.for_request(key, 0));
+                assert_eq!(after.name, before.name);
+            }
+        }
+    }
+
+    #[test]
+    fn test_pick_weighted() {
+        let mut rpc_list: Vec<Rpc> = [10.0, 20.0, 40.0]
+            .into_iter()
+            .map(|latency| {
+                let mut rpc = Rpc::default();
+                rpc.status.latency = latency;
+                rpc
+            })
+            .collect();
+        let strategy = Strategy::from_config("weighted_latency", 0.0).unwrap();
+
+        // Without weights every RPC gets the same share
+        let mut picked = [0; 3];
+        for _ in 0..30 {
+            picked[pick_route(&mut rpc_list, &Route::Any, strategy).1.unwrap()] += 1;
+        }
+        assert_eq!(picked, [10, 10, 10]);
+
+        // Twice as fast gets twice as many requests
+        update_weights(&mut rpc_list);
+        let mut picked = [0; 3];
+        for _ in 0..70 {
+            picked[pick_route(&mut rpc_list, &Route::Any, strategy).1.unwrap()] += 1;
+        }
+        assert_eq!(picked, [40, 20, 10]);
+
+        // Filtered routes keep their credit
+        rpc_list[0].archive = true;
+        rpc_list[1].archive = true;
+        let mut picked = [0; 3];
+        for _ in 0..30 {
+            picked[pick_route(&mut rpc_list, &Route::Archive, strategy)
+                .1
+                .unwrap()] += 1;
+        }
+        assert_eq!(picked, [20, 10, 0]);
+    }
+
+    #[test]
+    fn test_pick_cheapest() {
+        let mut self_hosted = Rpc::default();
+        let mut cheap = Rpc::default();
+        let mut expensive = Rpc::default();
+
+        self_hosted.status.latency = 900.0;
+        self_hosted.max_consecutive = 10;
+        cheap.status.latency = 200.0;
+        cheap.max_consecutive = 10;
+        cheap.cost = 1.0;
+        expensive.status.latency = 50.0;
+        expensive.max_consecutive = 10;
+        expensive.cost = 5.0;
+
+        let mut rpc_list = vec![expensive, cheap, self_hosted];
+
+        // Free RPCs win regardless of latency without a ceiling
+        let strategy = Strategy::Cheapest { max_latency: 0.0 };
+        let (_, index) = pick_route(&mut rpc_list, &Route::Any, strategy);
+        assert_eq!(index, Some(2));
+
+        // The self hosted node is too slow, so we go with the next cheapest
+        let strategy = Strategy::Cheapest { max_latency: 500.0 };
+        let (_, index) = pick_route(&mut rpc_list, &Route::Any, strategy);
+        assert_eq!(index, Some(1));
+
+        // Everything is too slow, fall back to picking by latency
+        let strategy = Strategy::Cheapest { max_latency: 10.0 };
+        let (_, index) = pick_route(&mut rpc_list, &Route::Any, strategy);
+        assert_eq!(index, Some(0));
+
+        // Routes still apply
+        rpc_list[0].archive = true;
+        let strategy = Strategy::Cheapest { max_latency: 0.0 };
+        let (_, index) = pick_route(&mut rpc_list, &Route::Archive, strategy);
+        assert_eq!(index, Some(0));
+    }
+
+    #[test]
+    fn test_pick_paused() {
+        let mut rpc1 = Rpc::default();
+        let mut rpc2 = Rpc::default();
+
+        rpc1.name = "paused".to_string();
+        rpc1.status.latency = 1.0;
+        rpc1.max_consecutive = 10;
+        rpc1.paused = true;
+        rpc2.status.latency = 6.0;
+        rpc2.max_consecutive = 10;
+
+        let mut rpc_list = vec![rpc1, rpc2];
+
+        let (_, index) = pick_route(&mut rpc_list, &Route::Any, Strategy::Latency);
+        assert_eq!(index, Some(1));
+
+        // Pinned methods ignore the schedule
+        let route = Route::Pinned("paused".to_string());
+        let (_, index) = pick_route(&mut rpc_list, &route, Strategy::Latency);
+        assert_eq!(index, Some(0));
+    }
+
+    #[test]
+    fn test_pick_tagged() {
+        let mut rpc1 = Rpc::default();
+        let mut rpc2 = Rpc::default();
+        let mut rpc3 = Rpc::default();
+
+        rpc1.status.latency = 1.0;
+        rpc1.tags = vec!["full".to_string()];
+        rpc2.status.latency = 6.0;
+        rpc2.tags = vec!["archive".to_string(), "erigon".to_string()];
+        rpc3.status.latency = 4.0;
+        rpc3.tags = vec!["archive".to_string()];
+        for rpc in [&mut rpc1, &mut rpc2, &mut rpc3] {
+            rpc.max_consecutive = 10;
+        }
+
+        let mut rpc_list = vec![rpc1, rpc2, rpc3];
+        let route = Route::Tagged(vec!["archive".to_string()]);
+
+        let (_, index) = pick_route(&mut rpc_list, &route, Strategy::Latency);
+        assert_eq!(index, Some(2));
+
+        // Never falls back to untagged RPCs
+        rpc_list[1].paused = true;
+        rpc_list[2].paused = true;
+        let (_, index) = pick_route(&mut rpc_list, &route, Strategy::Latency);
+        assert_eq!(index, None);
+    }
+
+    #[test]
+    fn test_pick_pool() {
+        let mut rpc1 = Rpc::default();
+        let mut rpc2 = Rpc::default();
+        let mut rpc3 = Rpc::default();
+
+        rpc1.name = "geth".to_string();
+        rpc1.status.latency = 1.0;
+        rpc2.name = "erigon-1".to_string();
+        rpc2.status.latency = 6.0;
+        rpc3.name = "erigon-2".to_string();
+        rpc3.status.latency = 4.0;
+        for rpc in [&mut rpc1, &mut rpc2, &mut rpc3] {
+            rpc.max_consecutive = 10;
+        }
+
+        let mut rpc_list = vec![rpc1, rpc2, rpc3];
+        let route = Route::Pool(vec!["erigon-1".to_string(), "erigon-2".to_string()]);
+
+        // Fastest of the pool, not of everyone
+        let (_, index) = pick_route(&mut rpc_list, &route, Strategy::Latency);
+        assert_eq!(index, Some(2));
+
+        // Never falls back to RPCs outside of it
+        rpc_list[1].paused = true;
+        rpc_list[2].paused = true;
+        let (_, index) = pick_route(&mut rpc_list, &route, Strategy::Latency);
+        assert_eq!(index, None);
+
+        // Preferred RPCs fall back to everyone else
+        let route = Route::Preferred(vec!["erigon-1".to_string(), "erigon-2".to_string()]);
+        let (_, index) = pick_route(&mut rpc_list, &route, Strategy::Latency);
+        assert_eq!(index, Some(0));
+        rpc_list[1].paused = false;
+        let (_, index) = pick_route(&mut rpc_list, &route, Strategy::Latency);
+        assert_eq!(index, Some(1));
+    }
+
+    #[test]
+    fn test_pick_sla_breached() {
+        use crate::rpc::sla::Sla;
+        use std::time::Duration;
+
+        let mut fast = Rpc::default();
+        let mut slow = Rpc::default();
+
+        fast.status.latency = 1.0;
+        fast.max_consecutive = 1000;
+        fast.sla = Some(Sla {
+            percentile: 95.0,
+            max_latency: Duration::from_millis(250),
+        });
+        slow.status.latency = 6.0;
+        slow.max_consecutive = 1000;
+
+        let mut rpc_list = vec![fast, slow];
+        rpc_list[0].sla_stats().set_share(0.25);
+
+        let picked_fast = (0..100)
+            .filter(|_| pick_route(&mut rpc_list, &Route::Any, Strategy::Latency).1 == Some(0))
+            .count();
+        assert_eq!(picked_fast, 25);
+
+        // Still used if it's the only RPC left
+        rpc_list[0].sla_stats().set_share(0.0);
+        rpc_list[1].paused = true;
+        let (_, index) = pick_route(&mut rpc_list, &Route::Any, Strategy::Latency);
+        assert_eq!(index, Some(0));
+    }
+
+    #[test]
+    fn test_pick_degraded() {
+        let mut fast = Rpc::default();
+        fast.status.latency = 1.0;
+        fast.max_consecutive = 1000;
+        fast.degraded = true;
+        let mut slow = Rpc::default();
+        slow.status.latency = 6.0;
+        slow.max_consecutive = 1000;
+        let mut rpc_list = vec![fast, slow];
+
+        for _ in 0..10 {
+            let (_, index) = pick_route(&mut rpc_list, &Route::Any, Strategy::Latency);
+            assert_eq!(index, Some(1));
+        }
+
+        // Only used if nothing else can be
+        rpc_list[1].paused = true;
+        let (_, index) = pick_route(&mut rpc_list, &Route::Any, Strategy::Latency);
+        assert_eq!(index, Some(0));
+    }
 }
@@ -0,0 +1,299 @@
+// Limits how many requests each upstream has in flight at once.
+//
+// Requests over the limit wait in one of three lanes, and whenever a request finishes
+// the slot goes to the oldest request in the most important lane that has any.
+// This way a bursty backfill can't starve interactive clients out of an upstream.
+//
+// Lanes are bounded. Once a lane is full we tell the client to back off instead of
+// queueing more, so load is pushed back to whoever is generating it.
+use std::{
+    collections::{
+        HashMap,
+        VecDeque,
+    },
+    fmt,
+    sync::Mutex,
+};
+
+use hyper::HeaderMap;
+use tokio::sync::oneshot;
+
+const PRIORITY_HEADER: &str = "x-blutgang-priority";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Priority {
+    // Regular requests
+    #[default]
+    Interactive,
+    // Requests made to keep subscriptions up to date
+    Subscription,
+    // Bulk fetches like `blutgang_getBlocks`, served last
+    Backfill,
+}
+
+impl Priority {
+    const LANES: usize = 3;
+
+    pub fn from_config(priority: &str) -> Option<Self> {
+        match priority {
+            "interactive" => Some(Priority::Interactive),
+            "subscription" => Some(Priority::Subscription),
+            "backfill" => Some(Priority::Backfill),
+            _ => None,
+        }
+    }
+
+    fn lane(self) -> usize {
+        self as usize
+    }
+}
+
+// Clients can lower their own priority, e.g. indexers backfilling history
+pub fn get_priority(headers: &HeaderMap) -> Priority {
+    headers
+        .get(PRIORITY_HEADER)
+        .and_then(|priority| priority.to_str().ok())
+        .and_then(Priority::from_config)
+        .unwrap_or_default()
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct QueueFull {
+    pub upstream: String,
+    pub priority: Priority,
+}
+
+impl fmt::Display for QueueFull {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Too many {:?} requests queued for {}",
+            self.priority, self.upstream
+        )
+    }
+}
+
+#[derive(Debug, Default)]
+struct Lanes {
+    in_flight: usize,
+    waiting: [VecDeque<oneshot::Sender<()>>; Priority::LANES],
+}
+
+#[derive(Debug)]
+pub struct SendQueue {
+    max_in_flight: usize,
+    max_queued: usize,
+    upstreams: Mutex<HashMap<String, Lanes>>,
+}
+
+// Slot for one in-flight request, given to the next waiting request once dropped
+#[derive(Debug)]
+pub struct SendPermit<'a> {
+    queue: &'a SendQueue,
+    upstream: String,
+}
+
+impl Drop for SendPermit<'_> {
+    fn drop(&mut self) {
+        self.queue.release(&self.upstream);
+    }
+}
+
+// A request waiting for a slot. If it gives up after it got one, the slot is passed on.
+struct Waiter<'a> {
+    queue: &'a SendQueue,
+    upstream: String,
+    rx: oneshot::Receiver<()>,
+    granted: bool,
+}
+
+impl Drop for Waiter<'_> {
+    fn drop(&mut self) {
+        if self.granted {
+            return;
+        }
+
+        self.rx.close();
+        if self.rx.try_recv().is_ok() {
+            self.queue.release(&self.upstream);
+        }
+    }
+}
+
+impl SendQueue {
+    pub fn new(max_in_flight: usize, max_queued: usize) -> Self {
+        Self {
+            max_in_flight,
+            max_queued,
+            upstreams: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // Wait for a slot to send a request to `upstream`.
+    //
+    // Returns an error right away if too many requests with the same priority are waiting.
+    pub async fn acquire(
+        &self,
+        upstream: &str,
+        priority: Priority,
+    ) -> Result<SendPermit<'_>, QueueFull> {
+        let rx = {
+            let mut upstreams = self.upstreams.lock().unwrap_or_else(|e| e.into_inner());
+            let lanes = upstreams.entry(upstream.to_string()).or_default();
+
+            if lanes.in_flight < self.max_in_flight {
+                lanes.in_flight += 1;
+                return Ok(self.permit(upstream));
+            }
+
+            let lane = &mut lanes.waiting[priority.lane()];
+            lane.retain(|waiting| !waiting.is_closed());
+            if lane.len() >= self.max_queued {
+                return Err(QueueFull {
+                    upstream: upstream.to_string(),
+                    priority,
+                });
+            }
+
+            let (tx, rx) = oneshot::channel();
+            lane.push_back(tx);
+            rx
+        };
+
+        let mut waiter = Waiter {
+            queue: self,
+            upstream: upstream.to_string(),
+            rx,
+            granted: false,
+        };
+        // We're the only ones who can drop the sender, and only after handing us the slot
+        let _ = (&mut waiter.rx).await;
+        waiter.granted = true;
+
+        Ok(self.permit(upstream))
+    }
+
+    fn permit(&self, upstream: &str) -> SendPermit<'_> {
+        SendPermit {
+            queue: self,
+            upstream: upstream.to_string(),
+        }
+    }
+
+    // Hand a finished request's slot to the next one in line, or free it
+    fn release(&self, upstream: &str) {
+        let mut upstreams = self.upstreams.lock().unwrap_or_else(|e| e.into_inner());
+        let lanes = match upstreams.get_mut(upstream) {
+            Some(lanes) => lanes,
+            None => return,
+        };
+
+        for lane in lanes.waiting.iter_mut() {
+            while let Some(waiting) = lane.pop_front() {
+                if waiting.send(()).is_ok() {
+                    return;
+                }
+            }
+        }
+
+        lanes.in_flight = lanes.in_flight.saturating_sub(1);
+    }
+
+    // Requests in flight and waiting in each lane, by upstream
+    #[cfg(test)]
+    fn queued(&self, upstream: &str) -> (usize, [usize; Priority::LANES]) {
+        let upstreams = self.upstreams.lock().unwrap();
+        let lanes = &upstreams[upstream];
+        (
+            lanes.in_flight,
+            [0, 1, 2].map(|lane| {
+                lanes.waiting[lane]
+                    .iter()
+                    .filter(|waiting| !waiting.is_closed())
+                    .count()
+            }),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        sync::Arc,
+        time::Duration,
+    };
+    use tokio::time::sleep;
+
+    #[tokio::test]
+    async fn test_priority_lanes() {
+        let queue = Arc::new(SendQueue::new(1, 8));
+        let permit = queue.acquire("rpc", Priority::Interactive).await.unwrap();
+
+        let (order_tx, mut order_rx) = tokio::sync::mpsc::unbounded_channel();
+        for priority in [
+            Priority::Backfill,
+            Priority::Subscription,
+            Priority::Interactive,
+        ] {
+            let queue = Arc::clone(&queue);
+            let order_tx = order_tx.clone();
+            tokio::spawn(async move {
+                let _permit = queue.acquire("rpc", priority).await.unwrap();
+                order_tx.send(priority).unwrap();
+            });
+            sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(queue.queued("rpc"), (1, [1, 1, 1]));
+
+        // Interactive requests go first even though they came in last
+        drop(permit);
+        assert_eq!(order_rx.recv().await, Some(Priority::Interactive));
+        assert_eq!(order_rx.recv().await, Some(Priority::Subscription));
+        assert_eq!(order_rx.recv().await, Some(Priority::Backfill));
+
+        sleep(Duration::from_millis(10)).await;
+        assert_eq!(queue.queued("rpc"), (0, [0, 0, 0]));
+    }
+
+    #[tokio::test]
+    async fn test_backpressure() {
+        let queue = SendQueue::new(1, 1);
+        let _permit = queue.acquire("rpc", Priority::Interactive).await.unwrap();
+
+        // One backfill request can wait, the next one is told to back off
+        let waiting = tokio::time::timeout(
+            Duration::from_millis(10),
+            queue.acquire("rpc", Priority::Backfill),
+        );
+        let full = async {
+            sleep(Duration::from_millis(5)).await;
+            queue.acquire("rpc", Priority::Backfill).await
+        };
+        let (waiting, full) = tokio::join!(waiting, full);
+        assert!(waiting.is_err());
+        assert_eq!(full.unwrap_err().priority, Priority::Backfill);
+
+        // Other lanes and upstreams are unaffected
+        let other = queue.acquire("other", Priority::Backfill).await;
+        assert!(other.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_waiter() {
+        let queue = SendQueue::new(1, 8);
+        let permit = queue.acquire("rpc", Priority::Interactive).await.unwrap();
+
+        // Gives up before getting a slot
+        let cancelled = tokio::time::timeout(
+            Duration::from_millis(5),
+            queue.acquire("rpc", Priority::Interactive),
+        )
+        .await;
+        assert!(cancelled.is_err());
+
+        drop(permit);
+        assert_eq!(queue.queued("rpc"), (0, [0, 0, 0]));
+        assert!(queue.acquire("rpc", Priority::Interactive).await.is_ok());
+    }
+}
@@ -0,0 +1,163 @@
+// Catch RPCs answering `latest` queries with blocks we're already past.
+//
+// An RPC that falls behind keeps answering until the next health check moves it to
+// the poverty list, and everything it returns for `latest` until then is stale.
+// Responses that tell us what block they're from are checked against the head, and
+// retried on another RPC if they're too far behind it.
+use crate::{
+    balancer::block_range::parse_block,
+    NamedBlocknumbers,
+    Rpc,
+};
+
+use std::{
+    sync::RwLock,
+    time::Duration,
+};
+
+use serde_json::Value;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Staleness {
+    // Head from the health checks
+    pub head: u64,
+    // Blocks responses can be behind the head
+    pub max_delta: u64,
+    // How long to wait before retrying a stale response
+    pub wait: Duration,
+}
+
+// Returns true if `tx` asks for the latest block in a way the response tells us
+// which block it's from
+pub fn is_latest_query(tx: &Value) -> bool {
+    match tx["method"].as_str() {
+        Some("eth_blockNumber") => true,
+        Some("eth_getBlockByNumber") => tx["params"][0] == "latest",
+        _ => false,
+    }
+}
+
+// Block the response `rx` to a request for `method` is from
+fn referenced_block(method: &str, rx: &Value) -> Option<u64> {
+    let number = match method {
+        "eth_blockNumber" => &rx["result"],
+        "eth_getBlockByNumber" => &rx["result"]["number"],
+        _ => return None,
+    };
+    parse_block(number, &NamedBlocknumbers::default()).ok()
+}
+
+// Block the raw response `rx` to a `latest` query for `method` is from
+pub fn served_block(method: &str, rx: &[u8]) -> Option<u64> {
+    referenced_block(method, &serde_json::from_slice(rx).ok()?)
+}
+
+impl Staleness {
+    // How many blocks the response `rx` to `tx` is behind the head, if that's more
+    // than we allow. Errors and responses we can't place are never stale.
+    pub fn behind(&self, tx: &Value, rx: &[u8]) -> Option<u64> {
+        if self.head == 0 {
+            return None;
+        }
+
+        let block = served_block(tx["method"].as_str()?, rx)?;
+        let behind = self.head.saturating_sub(block);
+        (behind > self.max_delta).then_some(behind)
+    }
+}
+
+// Count a stale response against the RPC named `name` like a timeout,
+// so it's picked less until it catches up
+pub fn penalize(rpc_list: &RwLock<Vec<Rpc>>, name: &str, penalty: f64) {
+    if let Some(rpc) = rpc_list
+        .write()
+        .unwrap()
+        .iter_mut()
+        .find(|rpc| rpc.name == name)
+    {
+        rpc.update_latency(penalty);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_is_latest_query() {
+        assert!(is_latest_query(
+            &json!({"method": "eth_blockNumber", "params": []})
+        ));
+        assert!(is_latest_query(
+            &json!({"method": "eth_getBlockByNumber", "params": ["latest", false]})
+        ));
+        assert!(!is_latest_query(
+            &json!({"method": "eth_getBlockByNumber", "params": ["0x10", false]})
+        ));
+        assert!(!is_latest_query(
+            &json!({"method": "eth_getBalance", "params": ["0xabc", "latest"]})
+        ));
+    }
+
+    #[test]
+    fn test_behind() {
+        let staleness = Staleness {
+            head: 100,
+            max_delta: 2,
+            wait: Duration::ZERO,
+        };
+        let block_number = json!({"method": "eth_blockNumber", "params": []});
+        let block = json!({"method": "eth_getBlockByNumber", "params": ["latest", false]});
+
+        assert_eq!(
+            staleness.behind(
+                &block_number,
+                br#"{"id":1,"jsonrpc":"2.0","result":"0x62"}"#
+            ),
+            None
+        );
+        assert_eq!(
+            staleness.behind(
+                &block_number,
+                br#"{"id":1,"jsonrpc":"2.0","result":"0x5a"}"#
+            ),
+            Some(10)
+        );
+        assert_eq!(
+            staleness.behind(
+                &block,
+                br#"{"id":1,"jsonrpc":"2.0","result":{"number":"0x50"}}"#
+            ),
+            Some(20)
+        );
+
+        // Errors aren't stale data
+        assert_eq!(
+            staleness.behind(
+                &block,
+                br#"{"id":1,"jsonrpc":"2.0","error":{"code":-32000,"message":"oops"}}"#
+            ),
+            None
+        );
+
+        // Ahead of us is fine, our head is just a bit behind
+        assert_eq!(
+            staleness.behind(
+                &block_number,
+                br#"{"id":1,"jsonrpc":"2.0","result":"0x70"}"#
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn test_penalize() {
+        let mut rpc = Rpc::new("http://localhost:8545".to_string(), 5, 5.0);
+        rpc.name = "stale".to_string();
+        let rpc_list = RwLock::new(vec![rpc]);
+
+        penalize(&rpc_list, "stale", 1000.0);
+        assert!(rpc_list.read().unwrap()[0].status.latency > 0.0);
+    }
+}
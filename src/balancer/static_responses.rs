@@ -0,0 +1,165 @@
+// Answers for methods whose result never changes, like `eth_chainId`.
+//
+// Wallets and libraries call these all the time, often before every request, and
+// there's no point in sending them upstream. We ask every RPC once on startup and answer
+// from memory after that. RPCs that disagree on the chain id would serve different
+// chains behind one endpoint, so we refuse to start instead. `web3_clientVersion` gets
+// our own version, like it does from the cache, since the RPCs run different clients.
+use crate::{
+    config::cache_setup::CLIENT_VERSION,
+    Rpc,
+};
+
+use std::{
+    collections::HashMap,
+    time::Duration,
+};
+
+use serde_json::{
+    json,
+    Value,
+};
+use tokio::time::timeout;
+
+// Every RPC has to agree on these
+const CHAIN_METHODS: [&str; 2] = ["eth_chainId", "net_version"];
+// We only answer these if the RPCs agree on them, they're not worth refusing to start over
+const AGREED_METHODS: [&str; 1] = ["eth_protocolVersion"];
+
+#[derive(Debug, Clone, Default)]
+pub struct StaticResponses {
+    // Method -> result
+    results: HashMap<&'static str, Value>,
+}
+
+impl StaticResponses {
+    // Response to `method` for the request with `id`, None if we don't answer it
+    pub fn response(&self, id: &Value, method: &str) -> Option<Value> {
+        let result = match method {
+            "web3_clientVersion" => Value::String(CLIENT_VERSION.to_string()),
+            method => self.results.get(method)?.clone(),
+        };
+
+        Some(json!({
+            "id": id,
+            "jsonrpc": "2.0",
+            "result": result,
+        }))
+    }
+}
+
+async fn call(rpc: &Rpc, method: &str, ttl: Duration) -> Option<Value> {
+    let tx = json!({
+        "id": 1,
+        "jsonrpc": "2.0",
+        "method": method,
+        "params": [],
+    });
+    let rx = timeout(ttl, rpc.send_request(tx)).await.ok()?.ok()?;
+    let mut rx: Value = serde_json::from_slice(&rx).ok()?;
+
+    match rx["result"].is_null() {
+        true => None,
+        false => Some(rx["result"].take()),
+    }
+}
+
+// The result every RPC in `answers` gave, None if they don't all agree or none answered
+fn agreed(answers: &[(String, Value)]) -> Option<Value> {
+    let (_, first) = answers.first()?;
+    answers
+        .iter()
+        .all(|(_, answer)| answer == first)
+        .then(|| first.clone())
+}
+
+// Describe which RPC said what, for when they don't agree
+fn disagreement(method: &str, answers: &[(String, Value)]) -> String {
+    let answers: Vec<String> = answers
+        .iter()
+        .map(|(rpc, answer)| format!("{} says {}", rpc, answer))
+        .collect();
+    format!("RPCs disagree on {}: {}", method, answers.join(", "))
+}
+
+// Ask every RPC in `rpc_list` for the results we answer with. RPCs that don't answer are
+// skipped, but we error if the ones that do are on different chains.
+pub async fn verify(rpc_list: &[Rpc], ttl: Duration) -> Result<StaticResponses, String> {
+    let mut responses = StaticResponses::default();
+
+    for method in CHAIN_METHODS.into_iter().chain(AGREED_METHODS) {
+        let mut answers = Vec::with_capacity(rpc_list.len());
+        for rpc in rpc_list {
+            match call(rpc, method, ttl).await {
+                Some(answer) => answers.push((rpc.name.clone(), answer)),
+                None => {
+                    println!(
+                        "\x1b[93mWrn:\x1b[0m {} did not answer {}, not checking it",
+                        rpc.name, method
+                    );
+                }
+            }
+        }
+
+        match agreed(&answers) {
+            Some(result) => {
+                responses.results.insert(method, result);
+            }
+            None if answers.is_empty() => {}
+            None if CHAIN_METHODS.contains(&method) => {
+                return Err(disagreement(method, &answers));
+            }
+            None => {
+                println!(
+                    "\x1b[93mWrn:\x1b[0m {}, forwarding it instead",
+                    disagreement(method, &answers)
+                );
+            }
+        }
+    }
+
+    Ok(responses)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_agreed() {
+        let answers = vec![
+            ("llama".to_string(), json!("0x1")),
+            ("infura".to_string(), json!("0x1")),
+        ];
+        assert_eq!(agreed(&answers), Some(json!("0x1")));
+        assert_eq!(agreed(&[]), None);
+
+        let answers = vec![
+            ("llama".to_string(), json!("0x1")),
+            ("op".to_string(), json!("0xa")),
+        ];
+        assert_eq!(agreed(&answers), None);
+        assert_eq!(
+            disagreement("eth_chainId", &answers),
+            "RPCs disagree on eth_chainId: llama says \"0x1\", op says \"0xa\""
+        );
+    }
+
+    #[test]
+    fn test_response() {
+        let mut responses = StaticResponses::default();
+        responses.results.insert("eth_chainId", json!("0x1"));
+
+        assert_eq!(
+            responses.response(&json!(7), "eth_chainId"),
+            Some(json!({"id": 7, "jsonrpc": "2.0", "result": "0x1"}))
+        );
+        assert_eq!(
+            responses.response(&json!(1), "web3_clientVersion").unwrap()["result"],
+            CLIENT_VERSION
+        );
+        // Nobody answered it on startup
+        assert_eq!(responses.response(&json!(1), "net_version"), None);
+        assert_eq!(responses.response(&json!(1), "eth_call"), None);
+    }
+}
@@ -0,0 +1,269 @@
+// Strict JSON-RPC 2.0 compliance.
+//
+// RPCs don't all follow the spec to the letter. Some leave out `"jsonrpc"`, send both
+// `result` and `error`, or errors that aren't objects. Mixing them behind one endpoint
+// breaks clients that validate responses, so in strict mode we check requests fully and
+// rewrite every response into a spec compliant one.
+//
+// Legacy requests without `"jsonrpc"` are rejected, unless `legacy_jsonrpc` is set.
+// Then they're upgraded before being sent upstream, and answered JSON-RPC 1.0 style.
+use crate::balancer::format::is_parse_error;
+
+use hyper::body::Bytes;
+use serde_json::{
+    json,
+    Map,
+    Value,
+};
+
+pub const PARSE_ERROR: i64 = -32700;
+pub const INVALID_REQUEST: i64 = -32600;
+const INTERNAL_ERROR: i64 = -32603;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidRequest {
+    pub code: i64,
+    pub message: &'static str,
+}
+
+impl InvalidRequest {
+    const fn new(code: i64, message: &'static str) -> Self {
+        Self { code, message }
+    }
+
+    // Error response, with the id of the request if it's a valid one
+    pub fn to_response(&self, tx: &Value) -> Value {
+        let id = match &tx["id"] {
+            id @ (Value::String(_) | Value::Number(_)) => id.clone(),
+            _ => Value::Null,
+        };
+
+        json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "error": {
+                "code": self.code,
+                "message": self.message,
+            },
+        })
+    }
+}
+
+// Returns true if `tx` is a legacy request, without a `"jsonrpc"` member
+pub fn is_legacy(tx: &Value) -> bool {
+    tx.get("jsonrpc").is_none()
+}
+
+pub fn validate_request(tx: &Value, legacy_jsonrpc: bool) -> Result<(), InvalidRequest> {
+    if is_parse_error(tx) {
+        return Err(InvalidRequest::new(PARSE_ERROR, "Parse error"));
+    }
+
+    let tx = match tx.as_object() {
+        Some(tx) => tx,
+        None => {
+            return Err(InvalidRequest::new(
+                INVALID_REQUEST,
+                "Request must be an object",
+            ))
+        }
+    };
+
+    match tx.get("jsonrpc") {
+        Some(Value::String(version)) if version == "2.0" => {}
+        None if legacy_jsonrpc => {}
+        None => {
+            return Err(InvalidRequest::new(
+                INVALID_REQUEST,
+                "Missing jsonrpc, it must be \"2.0\"",
+            ))
+        }
+        Some(_) => {
+            return Err(InvalidRequest::new(
+                INVALID_REQUEST,
+                "jsonrpc must be \"2.0\"",
+            ))
+        }
+    }
+
+    if !tx.get("method").is_some_and(Value::is_string) {
+        return Err(InvalidRequest::new(
+            INVALID_REQUEST,
+            "method must be a string",
+        ));
+    }
+
+    match tx.get("id") {
+        None | Some(Value::Null | Value::String(_) | Value::Number(_)) => {}
+        Some(_) => {
+            return Err(InvalidRequest::new(
+                INVALID_REQUEST,
+                "id must be a string, number or null",
+            ))
+        }
+    }
+
+    match tx.get("params") {
+        None | Some(Value::Array(_) | Value::Object(_)) => Ok(()),
+        Some(_) => {
+            Err(InvalidRequest::new(
+                INVALID_REQUEST,
+                "params must be an array or an object",
+            ))
+        }
+    }
+}
+
+// Errors must be objects with an integer code and a string message
+fn normalize_error(error: Value) -> Value {
+    let mut error = match error {
+        Value::Object(error) => error,
+        Value::String(message) => {
+            return json!({
+                "code": INTERNAL_ERROR,
+                "message": message,
+            })
+        }
+        error => {
+            return json!({
+                "code": INTERNAL_ERROR,
+                "message": error.to_string(),
+            })
+        }
+    };
+
+    if !error.get("code").is_some_and(Value::is_i64) {
+        error.insert("code".to_string(), INTERNAL_ERROR.into());
+    }
+    if !error.get("message").is_some_and(Value::is_string) {
+        error.insert("message".to_string(), "Unknown error".into());
+    }
+
+    Value::Object(error)
+}
+
+// Rewrite `rx` into a spec compliant response to the request with `id`
+pub fn strict_response(rx: &[u8], id: &Value, legacy: bool) -> Bytes {
+    let mut rx: Map<String, Value> = match serde_json::from_slice(rx) {
+        Ok(Value::Object(rx)) => rx,
+        _ => {
+            let mut rx = Map::new();
+            rx.insert("error".to_string(), "Invalid response from upstream".into());
+            rx
+        }
+    };
+
+    let result = rx.remove("result").unwrap_or_default();
+    let error = match rx.remove("error").unwrap_or_default() {
+        Value::Null => None,
+        error => Some(normalize_error(error)),
+    };
+
+    let mut response = Map::new();
+    response.insert("id".to_string(), id.clone());
+    match (legacy, error) {
+        // JSON-RPC 1.0 always has both
+        (true, error) => {
+            response.insert("result".to_string(), result);
+            response.insert("error".to_string(), error.unwrap_or_default());
+        }
+        (false, Some(error)) => {
+            response.insert("jsonrpc".to_string(), "2.0".into());
+            response.insert("error".to_string(), error);
+        }
+        (false, None) => {
+            response.insert("jsonrpc".to_string(), "2.0".into());
+            response.insert("result".to_string(), result);
+        }
+    }
+
+    Bytes::from(Value::Object(response).to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(rx: Bytes) -> Value {
+        serde_json::from_slice(&rx).unwrap()
+    }
+
+    #[test]
+    fn test_validate_request() {
+        let valid = json!({"jsonrpc": "2.0", "id": 1, "method": "eth_chainId", "params": []});
+        assert_eq!(validate_request(&valid, false), Ok(()));
+        let valid = json!({"jsonrpc": "2.0", "id": "a", "method": "eth_chainId"});
+        assert_eq!(validate_request(&valid, false), Ok(()));
+
+        let legacy = json!({"id": 1, "method": "getblockcount", "params": []});
+        assert_eq!(
+            validate_request(&legacy, false).unwrap_err().code,
+            INVALID_REQUEST
+        );
+        assert_eq!(validate_request(&legacy, true), Ok(()));
+
+        for invalid in [
+            json!({"jsonrpc": "1.0", "id": 1, "method": "eth_chainId"}),
+            json!({"jsonrpc": "2.0", "id": 1, "method": 1}),
+            json!({"jsonrpc": "2.0", "id": [1], "method": "eth_chainId"}),
+            json!({"jsonrpc": "2.0", "id": 1, "method": "eth_chainId", "params": "0x1"}),
+            json!([{"jsonrpc": "2.0", "id": 1, "method": "eth_chainId"}]),
+        ] {
+            assert_eq!(
+                validate_request(&invalid, true).unwrap_err().code,
+                INVALID_REQUEST
+            );
+        }
+
+        let error = validate_request(&json!({"jsonrpc": "2.0", "id": {}, "method": 1}), true)
+            .unwrap_err()
+            .to_response(&json!({"id": {}}));
+        assert_eq!(error["id"], Value::Null);
+        let error = InvalidRequest::new(INVALID_REQUEST, "nope").to_response(&json!({"id": 7}));
+        assert_eq!(error["id"], 7);
+    }
+
+    #[test]
+    fn test_strict_response() {
+        let id = json!(7);
+
+        // Missing jsonrpc, wrong id and a null error next to the result
+        let rx = parse(strict_response(
+            br#"{"id":"1","result":"0x1","error":null}"#,
+            &id,
+            false,
+        ));
+        assert_eq!(rx, json!({"jsonrpc": "2.0", "id": 7, "result": "0x1"}));
+
+        let rx = parse(strict_response(
+            br#"{"jsonrpc":"2.0","id":7,"result":null,"error":"execution reverted"}"#,
+            &id,
+            false,
+        ));
+        assert_eq!(
+            rx,
+            json!({"jsonrpc": "2.0", "id": 7, "error": {"code": -32603, "message": "execution reverted"}})
+        );
+
+        let rx = parse(strict_response(
+            br#"{"jsonrpc":"2.0","id":7,"error":{"code":3,"message":"reverted","data":"0x"}}"#,
+            &id,
+            false,
+        ));
+        assert_eq!(
+            rx["error"],
+            json!({"code": 3, "message": "reverted", "data": "0x"})
+        );
+
+        let rx = parse(strict_response(b"not json", &id, false));
+        assert_eq!(rx["error"]["code"], -32603);
+
+        // Legacy requests get JSON-RPC 1.0 responses
+        let rx = parse(strict_response(
+            br#"{"jsonrpc":"2.0","id":7,"result":100}"#,
+            &id,
+            true,
+        ));
+        assert_eq!(rx, json!({"id": 7, "result": 100, "error": null}));
+    }
+}
@@ -0,0 +1,226 @@
+// Durable queue for `eth_sendRawTransaction` while every RPC is down.
+//
+// Clients opt in per request with the `x-blutgang-queue` header. Their transaction gets
+// stored in its own sled tree, so it survives restarts and cache clears, and they get
+// its hash back right away along with an `x-blutgang-queued` header. Once an RPC is
+// back, queued transactions are broadcast to every healthy RPC in the order they came in.
+use crate::{
+    rpc::keccak::keccak256,
+    Rpc,
+};
+
+use std::{
+    sync::{
+        Arc,
+        RwLock,
+    },
+    time::Duration,
+};
+
+use hyper::HeaderMap;
+use serde_json::{
+    json,
+    Value,
+};
+use tokio::time::{
+    sleep,
+    timeout,
+};
+
+pub const QUEUE_HEADER: &str = "x-blutgang-queue";
+pub const QUEUED_HEADER: &str = "x-blutgang-queued";
+
+const TREE: &str = "tx_queue";
+
+// How often we check if queued transactions can go out
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+// Returns true if the client is fine with their transaction being queued
+pub fn wants_queue(headers: &HeaderMap) -> bool {
+    headers
+        .get(QUEUE_HEADER)
+        .and_then(|consent| consent.to_str().ok())
+        .is_some_and(|consent| consent == "1" || consent.eq_ignore_ascii_case("true"))
+}
+
+// Raw transaction of an `eth_sendRawTransaction` request, and its hash
+pub fn raw_transaction(tx: &Value) -> Option<(String, String)> {
+    if tx["method"] != "eth_sendRawTransaction" {
+        return None;
+    }
+
+    let raw = tx["params"][0].as_str()?;
+    let hex = raw.strip_prefix("0x")?;
+    if hex.is_empty() || hex.len() % 2 != 0 {
+        return None;
+    }
+    let bytes = (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16))
+        .collect::<Result<Vec<u8>, _>>()
+        .ok()?;
+
+    let hash: String = keccak256(&bytes)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    Some((raw.to_lowercase(), format!("0x{}", hash)))
+}
+
+#[derive(Debug, Clone)]
+pub struct TxQueue {
+    db: Arc<sled::Db>,
+    tree: sled::Tree,
+}
+
+impl TxQueue {
+    pub fn new(db: Arc<sled::Db>) -> Result<Self, sled::Error> {
+        let tree = db.open_tree(TREE)?;
+        Ok(Self { db, tree })
+    }
+
+    // Keys are increasing ids, so iterating the tree goes in the order we queued
+    pub fn push(&self, raw: &str) -> Result<(), sled::Error> {
+        let id = self.db.generate_id()?;
+        self.tree.insert(id.to_be_bytes(), raw.as_bytes())?;
+        self.tree.flush()?;
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.tree.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tree.is_empty()
+    }
+
+    fn queued(&self) -> Vec<(sled::IVec, String)> {
+        self.tree
+            .iter()
+            .filter_map(Result::ok)
+            .map(|(key, raw)| (key, String::from_utf8_lossy(&raw).to_string()))
+            .collect()
+    }
+}
+
+// What clients get back for a transaction we queued
+pub fn queued_response(id: &Value, hash: &str) -> Value {
+    json!({
+        "id": id,
+        "jsonrpc": "2.0",
+        "result": hash,
+    })
+}
+
+// Send `raw` to every one of `rpcs`. Returns true once any of them answered,
+// errors included, since sending it again wouldn't change what they say.
+async fn broadcast(raw: &str, rpcs: &[Rpc], ttl: Duration) -> bool {
+    let tx = json!({
+        "id": 1,
+        "jsonrpc": "2.0",
+        "method": "eth_sendRawTransaction",
+        "params": [raw],
+    });
+
+    let mut answered = false;
+    for rpc in rpcs {
+        let rx = match timeout(ttl, rpc.send_request(tx.clone())).await {
+            Ok(Ok(rx)) => rx,
+            _ => continue,
+        };
+        let rx: Value = match serde_json::from_slice(&rx) {
+            Ok(rx) => rx,
+            Err(_) => continue,
+        };
+
+        if let Some(error) = rx.get("error") {
+            println!(
+                "\x1b[93mWrn:\x1b[0m {} rejected a queued transaction: {}",
+                rpc.name, error["message"]
+            );
+        }
+        answered = true;
+    }
+    answered
+}
+
+// Broadcast queued transactions once there's an RPC to send them to
+pub async fn flush_tx_queue(queue: Arc<TxQueue>, rpc_list: Arc<RwLock<Vec<Rpc>>>, ttl: Duration) {
+    loop {
+        sleep(FLUSH_INTERVAL).await;
+        if queue.is_empty() {
+            continue;
+        }
+
+        let rpcs: Vec<Rpc> = rpc_list
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|rpc| !rpc.paused)
+            .cloned()
+            .collect();
+        if rpcs.is_empty() {
+            continue;
+        }
+
+        println!(
+            "\x1b[35mInfo:\x1b[0m Broadcasting {} queued transactions.",
+            queue.len()
+        );
+        for (key, raw) in queue.queued() {
+            // Later transactions usually depend on earlier ones, so keep the order
+            if !broadcast(&raw, &rpcs, ttl).await {
+                break;
+            }
+            let _ = queue.tree.remove(key);
+        }
+        let _ = queue.tree.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::header::HeaderValue;
+
+    #[test]
+    fn test_wants_queue() {
+        let mut headers = HeaderMap::new();
+        assert!(!wants_queue(&headers));
+        headers.insert(QUEUE_HEADER, HeaderValue::from_static("true"));
+        assert!(wants_queue(&headers));
+        headers.insert(QUEUE_HEADER, HeaderValue::from_static("0"));
+        assert!(!wants_queue(&headers));
+    }
+
+    #[test]
+    fn test_raw_transaction() {
+        let tx = json!({"id": 1, "jsonrpc": "2.0", "method": "eth_sendRawTransaction", "params": ["0xABC0"]});
+        let (raw, hash) = raw_transaction(&tx).unwrap();
+        assert_eq!(raw, "0xabc0");
+        assert_eq!(hash.len(), 66);
+
+        for params in [json!(["0xabc"]), json!(["abc0"]), json!(["0x"]), json!([])] {
+            let tx = json!({"method": "eth_sendRawTransaction", "params": params});
+            assert_eq!(raw_transaction(&tx), None);
+        }
+        let tx = json!({"method": "eth_call", "params": ["0xabc0"]});
+        assert_eq!(raw_transaction(&tx), None);
+    }
+
+    #[test]
+    fn test_queue_order() {
+        let db = Arc::new(sled::Config::new().temporary(true).open().unwrap());
+        let queue = TxQueue::new(Arc::clone(&db)).unwrap();
+
+        queue.push("0x01").unwrap();
+        queue.push("0x02").unwrap();
+        let queued: Vec<String> = queue.queued().into_iter().map(|(_, raw)| raw).collect();
+        assert_eq!(queued, ["0x01", "0x02"]);
+
+        // Not touched by clearing the cache
+        db.clear().unwrap();
+        assert_eq!(queue.len(), 2);
+    }
+}
@@ -0,0 +1,273 @@
+// Keeps track of transactions we sent with `eth_sendRawTransaction`, and of
+// `eth_getTransactionByHash` lookups that came back empty.
+//
+// A null from `eth_getTransactionByHash` can either mean the transaction doesn't exist,
+// or that the RPC we asked hasn't seen it yet. Nulls for transactions we know are
+// pending never get cached, so wallets polling for their own transactions always go
+// upstream. Everything else gets a short lived negative cache entry.
+//
+// We also remember which RPCs have our transactions, the one that accepted it and the
+// ones we've seen return it since, so lookups for them go there instead of to RPCs it
+// might not have gossiped to yet.
+use memchr::memmem;
+use serde_json::Value;
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{
+        Duration,
+        Instant,
+    },
+};
+
+// How long a sent transaction counts as pending if we never see it get included
+const PENDING_TTL: Duration = Duration::from_secs(30 * 60);
+
+// Prune expired entries once a map grows past this
+const PRUNE_THRESHOLD: usize = 16384;
+
+#[derive(Debug)]
+pub struct TxTracker {
+    // Transaction hash -> when we sent it
+    pending: Mutex<HashMap<String, Instant>>,
+    // Transaction hash -> when the negative cache entry expires
+    missing: Mutex<HashMap<String, Instant>>,
+    // Transaction hash -> when we sent it, and the RPCs we know have it
+    known: Mutex<HashMap<String, (Instant, Vec<String>)>>,
+    negative_ttl: Duration,
+}
+
+fn prune(map: &mut HashMap<String, Instant>, keep: impl Fn(&Instant) -> bool) {
+    if map.len() > PRUNE_THRESHOLD {
+        map.retain(|_, time| keep(time));
+    }
+}
+
+// Transaction hash of an `eth_getTransactionByHash` request
+pub fn lookup_hash(tx: &Value) -> Option<String> {
+    if tx["method"] != "eth_getTransactionByHash" {
+        return None;
+    }
+
+    tx["params"][0].as_str().map(|hash| hash.to_lowercase())
+}
+
+// Transaction hash of a request for a transaction or its receipt
+pub fn read_hash(tx: &Value) -> Option<String> {
+    if tx["method"] != "eth_getTransactionByHash" && tx["method"] != "eth_getTransactionReceipt" {
+        return None;
+    }
+
+    tx["params"][0].as_str().map(|hash| hash.to_lowercase())
+}
+
+impl TxTracker {
+    pub fn new(negative_ttl: Duration) -> Self {
+        Self {
+            pending: Mutex::new(HashMap::new()),
+            missing: Mutex::new(HashMap::new()),
+            known: Mutex::new(HashMap::new()),
+            negative_ttl,
+        }
+    }
+
+    // Record the hash `eth_sendRawTransaction` returned as pending, and `rpc` as
+    // having it if we know which RPC accepted it
+    pub fn record_sent(&self, rx: &[u8], rpc: Option<&str>) {
+        let rx: Value = match serde_json::from_slice(rx) {
+            Ok(rx) => rx,
+            Err(_) => return,
+        };
+        let hash = match rx["result"].as_str() {
+            Some(hash) => hash.to_lowercase(),
+            None => return,
+        };
+
+        self.missing.lock().unwrap().remove(&hash);
+
+        let mut known = self.known.lock().unwrap();
+        if known.len() > PRUNE_THRESHOLD {
+            known.retain(|_, (sent, _)| sent.elapsed() < PENDING_TTL);
+        }
+        let rpcs = rpc.map(|rpc| vec![rpc.to_string()]).unwrap_or_default();
+        known.insert(hash.clone(), (Instant::now(), rpcs));
+        drop(known);
+
+        let mut pending = self.pending.lock().unwrap();
+        prune(&mut pending, |sent| sent.elapsed() < PENDING_TTL);
+        pending.insert(hash, Instant::now());
+    }
+
+    // Remember that `rpc` has our transaction `hash` if it returned it or its receipt in `rx`
+    pub fn record_seen(&self, hash: &str, rpc: &str, rx: &[u8]) {
+        if memmem::find(rx, b"\"error\"").is_some()
+            || memmem::find(rx, b"\"result\":null").is_some()
+        {
+            return;
+        }
+
+        if let Some((_, rpcs)) = self.known.lock().unwrap().get_mut(hash) {
+            if !rpcs.iter().any(|known| known == rpc) {
+                rpcs.push(rpc.to_string());
+            }
+        }
+    }
+
+    // RPCs we know have our transaction `hash`, None if it's not ours or we don't know any
+    pub fn known_by(&self, hash: &str) -> Option<Vec<String>> {
+        self.known
+            .lock()
+            .unwrap()
+            .get(hash)
+            .filter(|(sent, rpcs)| sent.elapsed() < PENDING_TTL && !rpcs.is_empty())
+            .map(|(_, rpcs)| rpcs.clone())
+    }
+
+    pub fn is_pending(&self, hash: &str) -> bool {
+        self.pending
+            .lock()
+            .unwrap()
+            .get(hash)
+            .is_some_and(|sent| sent.elapsed() < PENDING_TTL)
+    }
+
+    // Returns true if we recently looked up `hash` and it didn't exist
+    pub fn is_missing(&self, hash: &str) -> bool {
+        self.missing
+            .lock()
+            .unwrap()
+            .get(hash)
+            .is_some_and(|expires| *expires > Instant::now())
+    }
+
+    // Update our state with the response to an `eth_getTransactionByHash` lookup
+    pub fn record_lookup(&self, hash: String, rx: &[u8]) {
+        // Errors tell us nothing about the transaction
+        if memmem::find(rx, b"\"error\"").is_some() {
+            return;
+        }
+
+        if memmem::find(rx, b"\"result\":null").is_none() {
+            // Once it's included it's not pending anymore
+            if memmem::find(rx, b"\"blockNumber\":null").is_none() {
+                self.pending.lock().unwrap().remove(&hash);
+            }
+            self.missing.lock().unwrap().remove(&hash);
+            return;
+        }
+
+        if self.is_pending(&hash) {
+            return;
+        }
+
+        let now = Instant::now();
+        let mut missing = self.missing.lock().unwrap();
+        prune(&mut missing, |expires| *expires > now);
+        missing.insert(hash, now + self.negative_ttl);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HASH: &str = "0xabc";
+
+    #[test]
+    fn test_negative_cache() {
+        let tracker = TxTracker::new(Duration::from_secs(60));
+        assert!(!tracker.is_missing(HASH));
+
+        tracker.record_lookup(
+            HASH.to_string(),
+            br#"{"id":null,"jsonrpc":"2.0","result":null}"#,
+        );
+        assert!(tracker.is_missing(HASH));
+
+        // Found it after all
+        tracker.record_lookup(
+            HASH.to_string(),
+            br#"{"id":null,"jsonrpc":"2.0","result":{"blockNumber":"0x1"}}"#,
+        );
+        assert!(!tracker.is_missing(HASH));
+
+        // Errors don't count as missing
+        tracker.record_lookup(
+            "0xdef".to_string(),
+            br#"{"id":null,"jsonrpc":"2.0","error":{"code":-32000,"message":"oops"}}"#,
+        );
+        assert!(!tracker.is_missing("0xdef"));
+    }
+
+    #[test]
+    fn test_pending_not_cached() {
+        let tracker = TxTracker::new(Duration::from_secs(60));
+        tracker.record_sent(br#"{"id":1,"jsonrpc":"2.0","result":"0xABC"}"#, None);
+        assert!(tracker.is_pending(HASH));
+
+        // RPC hasn't seen our transaction yet
+        tracker.record_lookup(
+            HASH.to_string(),
+            br#"{"id":null,"jsonrpc":"2.0","result":null}"#,
+        );
+        assert!(!tracker.is_missing(HASH));
+
+        // Still in the mempool
+        tracker.record_lookup(
+            HASH.to_string(),
+            br#"{"id":null,"jsonrpc":"2.0","result":{"blockNumber":null}}"#,
+        );
+        assert!(tracker.is_pending(HASH));
+
+        // Included
+        tracker.record_lookup(
+            HASH.to_string(),
+            br#"{"id":null,"jsonrpc":"2.0","result":{"blockNumber":"0x1"}}"#,
+        );
+        assert!(!tracker.is_pending(HASH));
+    }
+
+    #[test]
+    fn test_known_by() {
+        let tracker = TxTracker::new(Duration::from_secs(60));
+        tracker.record_sent(
+            br#"{"id":1,"jsonrpc":"2.0","result":"0xABC"}"#,
+            Some("llama"),
+        );
+        assert_eq!(tracker.known_by(HASH), Some(vec!["llama".to_string()]));
+
+        // RPCs that haven't seen it yet don't count
+        tracker.record_seen(HASH, "infura", br#"{"id":1,"jsonrpc":"2.0","result":null}"#);
+        assert_eq!(tracker.known_by(HASH), Some(vec!["llama".to_string()]));
+        tracker.record_seen(
+            HASH,
+            "infura",
+            br#"{"id":1,"jsonrpc":"2.0","result":{"blockNumber":null}}"#,
+        );
+        assert_eq!(
+            tracker.known_by(HASH),
+            Some(vec!["llama".to_string(), "infura".to_string()])
+        );
+
+        // Only our own transactions
+        tracker.record_seen(
+            "0xdef",
+            "infura",
+            br#"{"id":1,"jsonrpc":"2.0","result":{}}"#,
+        );
+        assert_eq!(tracker.known_by("0xdef"), None);
+    }
+
+    #[test]
+    fn test_lookup_hash() {
+        let tx = serde_json::json!({"method": "eth_getTransactionByHash", "params": ["0xABC"]});
+        assert_eq!(lookup_hash(&tx), Some(HASH.to_string()));
+
+        let tx = serde_json::json!({"method": "eth_getBlockByHash", "params": ["0xABC", false]});
+        assert_eq!(lookup_hash(&tx), None);
+
+        let tx = serde_json::json!({"method": "eth_getTransactionReceipt", "params": ["0xABC"]});
+        assert_eq!(lookup_hash(&tx), None);
+        assert_eq!(read_hash(&tx), Some(HASH.to_string()));
+    }
+}
@@ -0,0 +1,261 @@
+use crate::{
+    balancer::ids::IdRemap,
+    rpc::error::RpcError,
+    Rpc,
+};
+
+use std::{
+    collections::HashMap,
+    time::Duration,
+};
+
+use hyper::body::Bytes;
+use serde_json::{
+    json,
+    Value,
+};
+use tokio::{
+    sync::{
+        mpsc,
+        oneshot,
+    },
+    time::{
+        timeout_at,
+        Instant,
+    },
+};
+
+// Error codes RPCs return for entries they couldn't serve, rather than for bad requests
+const FAILED_ENTRY_CODES: [i64; 2] = [-32603, -32005];
+
+// What to do when some entries of a batch fail upstream
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BatchFailure {
+    // Retry the failed entries on their own, on other RPCs if possible
+    #[default]
+    Retry,
+    // Return the failed entries as errors, and the rest as they are
+    Error,
+    // If any entry fails, every entry in the batch gets an error
+    Fail,
+}
+
+impl BatchFailure {
+    pub fn from_config(policy: &str) -> Option<Self> {
+        match policy {
+            "retry" => Some(BatchFailure::Retry),
+            "error" => Some(BatchFailure::Error),
+            "fail" => Some(BatchFailure::Fail),
+            _ => None,
+        }
+    }
+}
+
+// Missing responses get restored as -32603 errors, so they count as failed too
+fn is_failed_entry(response: &Value) -> bool {
+    response["error"]["code"]
+        .as_i64()
+        .is_some_and(|code| FAILED_ENTRY_CODES.contains(&code))
+}
+
+fn entry_error(id: &Value, message: &str) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "error": {
+            "code": -32603,
+            "message": message,
+        },
+    })
+}
+
+// Decide what every entry of a batch gets back, according to `policy`.
+//
+// `Err` means the entry gets retried on its own.
+fn apply_failure_policy(
+    responses: Vec<Value>,
+    policy: BatchFailure,
+) -> Vec<Result<Value, RpcError>> {
+    let any_failed = responses.iter().any(is_failed_entry);
+
+    responses
+        .into_iter()
+        .map(|response| {
+            let failed = is_failed_entry(&response);
+            match policy {
+                BatchFailure::Retry if failed => {
+                    Err(RpcError::InvalidResponse(format!(
+                        "error: Batch entry failed: {}",
+                        response["error"]
+                    )))
+                }
+                BatchFailure::Fail if any_failed && !failed => {
+                    Ok(entry_error(
+                        &response["id"],
+                        "error: Another request in the batch failed",
+                    ))
+                }
+                _ => Ok(response),
+            }
+        })
+        .collect()
+}
+
+// A request waiting to be coalesced into an upstream batch
+#[derive(Debug)]
+struct BatchedRequest {
+    rpc: Rpc,
+    tx: Value,
+    reply: oneshot::Sender<Result<Bytes, RpcError>>,
+}
+
+// Coalesces requests going to the same RPC at around the same time into one JSON-RPC batch.
+//
+// Every request gets a fresh internal id, so clients reusing ids can share a batch.
+#[derive(Debug, Clone)]
+pub struct UpstreamBatcher {
+    batch_tx: mpsc::Sender<BatchedRequest>,
+}
+
+impl UpstreamBatcher {
+    // Spawn the batching task. Batches are sent after `window` or once they have `max_size` requests.
+    pub fn new(max_size: usize, window: Duration, policy: BatchFailure) -> Self {
+        let (batch_tx, batch_rx) = mpsc::channel(max_size * 16);
+        tokio::task::spawn(batcher(batch_rx, max_size, window, policy));
+
+        Self { batch_tx }
+    }
+
+    // Send `tx` to `rpc` as part of a batch and return its response
+    pub async fn send(&self, rpc: &Rpc, tx: Value) -> Result<Bytes, RpcError> {
+        let (reply, reply_rx) = oneshot::channel();
+        let request = BatchedRequest {
+            rpc: rpc.clone(),
+            tx,
+            reply,
+        };
+
+        let closed = || RpcError::InvalidResponse("error: Upstream batcher closed".to_string());
+        self.batch_tx.send(request).await.map_err(|_| closed())?;
+        reply_rx.await.map_err(|_| closed())?
+    }
+}
+
+async fn batcher(
+    mut batch_rx: mpsc::Receiver<BatchedRequest>,
+    max_size: usize,
+    window: Duration,
+    policy: BatchFailure,
+) {
+    while let Some(first) = batch_rx.recv().await {
+        let mut requests = vec![first];
+
+        let deadline = Instant::now() + window;
+        while requests.len() < max_size {
+            match timeout_at(deadline, batch_rx.recv()).await {
+                Ok(Some(request)) => requests.push(request),
+                _ => break,
+            }
+        }
+
+        // Only requests to the same RPC can share a batch
+        let mut groups: HashMap<String, Vec<BatchedRequest>> = HashMap::new();
+        for request in requests {
+            groups
+                .entry(request.rpc.url.clone())
+                .or_default()
+                .push(request);
+        }
+
+        for (_, group) in groups {
+            tokio::task::spawn(send_batch(group, policy));
+        }
+    }
+}
+
+async fn send_batch(group: Vec<BatchedRequest>, policy: BatchFailure) {
+    // Nothing to gain from batching a single request
+    if group.len() == 1 {
+        let request = group.into_iter().next().unwrap();
+        let rx = request.rpc.send_request(request.tx).await;
+        let _ = request.reply.send(rx);
+        return;
+    }
+
+    let rpc = group[0].rpc.clone();
+    let (mut batch, replies): (Vec<Value>, Vec<_>) = group
+        .into_iter()
+        .map(|request| (request.tx, request.reply))
+        .unzip();
+    let remap = IdRemap::remap(&mut batch);
+
+    let responses = match rpc.send_request(Value::Array(batch)).await {
+        Ok(rx) => {
+            serde_json::from_slice::<Vec<Value>>(&rx).map_err(|err| {
+                RpcError::InvalidResponse(format!("error: Invalid batch response: {}", err))
+            })
+        }
+        Err(err) => Err(err),
+    };
+
+    // Every request we batch has an id, so restored responses line up with `replies`
+    let responses = match responses {
+        Ok(responses) => apply_failure_policy(remap.restore(responses), policy),
+        // Nothing came back, so every entry failed
+        Err(err) if policy == BatchFailure::Retry => {
+            replies
+                .iter()
+                .map(|_| Err(RpcError::InvalidResponse(err.to_string())))
+                .collect()
+        }
+        Err(err) => {
+            remap
+                .restore(Vec::new())
+                .iter()
+                .map(|response| Ok(entry_error(&response["id"], &err.to_string())))
+                .collect()
+        }
+    };
+
+    for (reply, response) in replies.into_iter().zip(responses) {
+        let rx = response.map(|response| Bytes::from(serde_json::to_vec(&response).unwrap()));
+        let _ = reply.send(rx);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn responses() -> Vec<Value> {
+        vec![
+            json!({"jsonrpc": "2.0", "id": 1, "result": "0x1"}),
+            json!({"jsonrpc": "2.0", "id": 2, "error": {"code": -32005, "message": "limit exceeded"}}),
+            // Reverts are valid responses, not failures
+            json!({"jsonrpc": "2.0", "id": 3, "error": {"code": 3, "message": "execution reverted"}}),
+        ]
+    }
+
+    #[test]
+    fn test_failure_policy() {
+        let retry = apply_failure_policy(responses(), BatchFailure::Retry);
+        assert_eq!(retry[0].as_ref().unwrap()["result"], "0x1");
+        assert!(retry[1].is_err());
+        assert_eq!(retry[2].as_ref().unwrap()["error"]["code"], 3);
+
+        let error = apply_failure_policy(responses(), BatchFailure::Error);
+        assert_eq!(error[0].as_ref().unwrap()["result"], "0x1");
+        assert_eq!(error[1].as_ref().unwrap()["error"]["code"], -32005);
+
+        let fail = apply_failure_policy(responses(), BatchFailure::Fail);
+        for (response, id) in fail.iter().zip([1, 2, 3]) {
+            let response = response.as_ref().unwrap();
+            assert_eq!(response["id"], id);
+            assert!(response["error"].is_object());
+        }
+
+        // Nothing failed, nothing changes
+        let fail = apply_failure_policy(vec![responses().remove(0)], BatchFailure::Fail);
+        assert_eq!(fail[0].as_ref().unwrap()["result"], "0x1");
+    }
+}
@@ -0,0 +1,91 @@
+use serde_json::Value;
+
+// Return the height a Bitcoin Core request is cached under or None if we shouldn't cache it.
+//
+// Blocks and transactions are keyed by their hash, so they never change and use height 0.
+// Verbose outputs contain `confirmations`, which changes every block, so only raw
+// outputs are cached. `getblockhash` depends on the height and gets invalidated on reorgs.
+pub fn get_height_from_request(tx: &Value) -> Option<u64> {
+    let params = tx["params"].as_array()?;
+
+    // `true`, `1` and `2` all mean verbose depending on the method
+    let verbose = |index: usize| {
+        match params.get(index) {
+            None | Some(Value::Null) => None,
+            Some(Value::Bool(verbose)) => Some(*verbose),
+            Some(verbosity) => Some(verbosity.as_u64() != Some(0)),
+        }
+    };
+
+    match tx["method"].as_str()? {
+        // Verbosity defaults to 1
+        "getblock" if verbose(1) == Some(false) => Some(0),
+        // Verbose defaults to true
+        "getblockheader" if verbose(1) == Some(false) => Some(0),
+        // Verbose defaults to false
+        "getrawtransaction" if verbose(1) != Some(true) => Some(0),
+        "getblockfilter" | "getblockstats" if params.first()?.is_string() => Some(0),
+        "getblockhash" => params.first()?.as_u64(),
+        _ => None,
+    }
+}
+
+// Returns true if the result of a Bitcoin Core request can be cached.
+//
+// Bitcoin Core always includes `error`, and sets it to null on success.
+pub fn cache_bitcoin_result(rx: &[u8]) -> bool {
+    match serde_json::from_slice::<Value>(rx) {
+        Ok(rx) => rx["error"].is_null() && !rx["result"].is_null(),
+        Err(_) => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const HASH: &str = "00000000839a8e6886ab5951d76f411475428afc90947ee320161bbf18eb6048";
+
+    #[test]
+    fn test_get_height_from_request() {
+        let tx = json!({"id": 1, "jsonrpc": "1.0", "method": "getblock", "params": [HASH]});
+        assert_eq!(get_height_from_request(&tx), None);
+
+        let tx = json!({"id": 1, "jsonrpc": "1.0", "method": "getblock", "params": [HASH, 0]});
+        assert_eq!(get_height_from_request(&tx), Some(0));
+
+        let tx = json!({"id": 1, "jsonrpc": "1.0", "method": "getblock", "params": [HASH, 2]});
+        assert_eq!(get_height_from_request(&tx), None);
+
+        let tx =
+            json!({"id": 1, "jsonrpc": "1.0", "method": "getblockheader", "params": [HASH, false]});
+        assert_eq!(get_height_from_request(&tx), Some(0));
+
+        let tx =
+            json!({"id": 1, "jsonrpc": "1.0", "method": "getrawtransaction", "params": [HASH]});
+        assert_eq!(get_height_from_request(&tx), Some(0));
+
+        let tx = json!({"id": 1, "jsonrpc": "1.0", "method": "getrawtransaction", "params": [HASH, true]});
+        assert_eq!(get_height_from_request(&tx), None);
+
+        let tx = json!({"id": 1, "jsonrpc": "1.0", "method": "getblockstats", "params": [1000]});
+        assert_eq!(get_height_from_request(&tx), None);
+
+        let tx = json!({"id": 1, "jsonrpc": "1.0", "method": "getblockhash", "params": [1000]});
+        assert_eq!(get_height_from_request(&tx), Some(1000));
+
+        let tx = json!({"id": 1, "jsonrpc": "1.0", "method": "getblockcount", "params": []});
+        assert_eq!(get_height_from_request(&tx), None);
+    }
+
+    #[test]
+    fn test_cache_bitcoin_result() {
+        assert!(cache_bitcoin_result(
+            br#"{"result":"0100","error":null,"id":1}"#
+        ));
+        assert!(!cache_bitcoin_result(
+            br#"{"result":null,"error":{"code":-5,"message":"Block not found"},"id":1}"#
+        ));
+    }
+}
@@ -1,27 +1,38 @@
 use sled::Db;
 use std::sync::Arc;
 
+// `blutgang_is_lb` and `web3_clientVersion`, cached as blake3 hashes
+const VERSION_KEYS: [[u8; 32]; 2] = [
+    [
+        176, 76, 1, 109, 13, 127, 134, 25, 55, 111, 28, 182, 82, 155, 135, 143, 204, 161, 53, 4,
+        158, 140, 22, 219, 138, 5, 57, 150, 8, 154, 17, 252,
+    ],
+    [
+        36, 20, 170, 125, 105, 107, 149, 148, 52, 126, 215, 218, 112, 55, 222, 60, 186, 44, 67,
+        121, 225, 160, 31, 209, 9, 99, 81, 233, 137, 37, 62, 79,
+    ],
+];
+
+// Returns true if `key` is one of the entries we insert on startup, which are never evicted
+pub fn is_setup_key(key: &[u8]) -> bool {
+    key == b"xxhash" || key == b"blake3" || VERSION_KEYS.iter().any(|version| key == version)
+}
+
+// What we answer `web3_clientVersion` with, so clients know they're talking to us
+pub const CLIENT_VERSION: &str = "blutgang 0.2.1 Myrddin nc; `I won't run away!`";
+
 pub fn setup_data(cache: Arc<Db>) {
-    let version_str = "{\"jsonrpc\":\"2.0\",\"id\":null,\"result\":\"blutgang 0.2.1 Myrddin nc; `I won't run away!`\"}";
+    let version_str = format!(
+        "{{\"jsonrpc\":\"2.0\",\"id\":null,\"result\":\"{}\"}}",
+        CLIENT_VERSION
+    );
 
     // Insert kv pair `blutgang_is_lb` `true` to know what we're interacting with
     // `blutgang_is_lb` is cached as a blake3 cache
-    let _ = cache.insert(
-        [
-            176, 76, 1, 109, 13, 127, 134, 25, 55, 111, 28, 182, 82, 155, 135, 143, 204, 161, 53,
-            4, 158, 140, 22, 219, 138, 5, 57, 150, 8, 154, 17, 252,
-        ],
-        version_str,
-    );
+    let _ = cache.insert(VERSION_KEYS[0], version_str.as_bytes());
     // Insert kv pair `web3_clientVersion` `true` to know what we're interacting with
     // `web3_clientVersion` is cached as a blake3 cache
-    let _ = cache.insert(
-        [
-            36, 20, 170, 125, 105, 107, 149, 148, 52, 126, 215, 218, 112, 55, 222, 60, 186, 44, 67,
-            121, 225, 160, 31, 209, 9, 99, 81, 233, 137, 37, 62, 79,
-        ],
-        version_str,
-    );
+    let _ = cache.insert(VERSION_KEYS[1], version_str.as_bytes());
 
     // Insert which hashing algo we're using based on the selected features.
     // If `xxhash` is enabled we're using xxhash3, otherwise blake3.
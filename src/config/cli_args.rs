@@ -114,4 +114,23 @@ pub fn create_match() -> clap::Command {
             .num_args(1..)
             .requires("jwt")
             .help("JWT token"))
+        .arg(Arg::new("hardened")
+            .long("hardened")
+            .num_args(0..)
+            .help("Enable protections for publicly exposed endpoints (method denylist, body and rate limits, keyed eth_sendRawTransaction)"))
+        .arg(Arg::new("self_test")
+            .long("self-test")
+            .num_args(0..)
+            .help("Send test requests through blutgang to the configured RPCs, print a report and exit"))
+        .subcommand(Command::new("status")
+            .about("Print the upstreams and cache of a running blutgang from its admin namespace")
+            .arg(Arg::new("url")
+                .long("url")
+                .num_args(1)
+                .default_value("http://127.0.0.1:5715")
+                .help("Url of the admin namespace"))
+            .arg(Arg::new("key")
+                .long("key")
+                .num_args(1)
+                .help("Admin key, if admin keys are set")))
 }
@@ -0,0 +1,472 @@
+// What changes between the config we're running with and a new one, for `blutgang_reloadConfig`.
+//
+// Only settings that are read on every request can change while we're running, along
+// with the RPC list. Everything else is read once on startup, so we only report it
+// so whoever is reloading knows a restart is needed for it to take effect.
+use crate::{
+    config::types::Settings,
+    Rpc,
+};
+
+use serde_json::{
+    json,
+    Value,
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SettingChange {
+    pub name: &'static str,
+    pub old: Value,
+    pub new: Value,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ConfigDiff {
+    pub rpcs_added: Vec<String>,
+    pub rpcs_removed: Vec<String>,
+    pub rpcs_changed: Vec<String>,
+    // Settings that get applied right away
+    pub changed: Vec<SettingChange>,
+    // Settings that only take effect after a restart
+    pub restart_required: Vec<&'static str>,
+}
+
+impl ConfigDiff {
+    pub fn is_empty(&self) -> bool {
+        self.rpcs_added.is_empty()
+            && self.rpcs_removed.is_empty()
+            && self.rpcs_changed.is_empty()
+            && self.changed.is_empty()
+            && self.restart_required.is_empty()
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "rpcs": {
+                "added": self.rpcs_added,
+                "removed": self.rpcs_removed,
+                "changed": self.rpcs_changed,
+            },
+            "settings": self
+                .changed
+                .iter()
+                .map(|change| json!({"name": change.name, "old": change.old, "new": change.new}))
+                .collect::<Vec<_>>(),
+            "restart_required": self.restart_required,
+        })
+    }
+}
+
+// What we compare RPCs with the same name by
+fn rpc_settings(rpc: &Rpc) -> Value {
+    json!({
+        "url": rpc.url,
+        "ws_url": rpc.ws_url,
+        "max_consecutive": rpc.max_consecutive,
+        "archive": rpc.archive,
+        "tags": rpc.tags,
+        "supports_state_overrides": rpc.supports_state_overrides,
+        "cost": rpc.cost,
+        "prewarm": rpc.prewarm,
+        "max_per_second": rpc.max_per_second,
+        "probes": format!("{:?}", rpc.probes),
+        "ma_window": format!("{:?}", rpc.status.window),
+        "ip_preference": format!("{:?}", rpc.ip_preference),
+        "signer": rpc.signer.as_ref().map(|signer| format!("{:?}", signer)),
+        "sla": rpc.sla.map(|sla| sla.to_string()),
+    })
+}
+
+// Settings we can change while running
+fn live_settings(settings: &Settings) -> Vec<(&'static str, Value)> {
+    vec![
+        ("ttl", json!(settings.ttl)),
+        ("max_retries", json!(settings.max_retries)),
+        ("health_check_ttl", json!(settings.health_check_ttl)),
+        ("pin", json!(settings.pin)),
+        ("namespace_pools", json!(settings.namespace_pools)),
+        ("wallet_upstream", json!(settings.wallet_upstream)),
+        (
+            "state_overrides",
+            json!(format!("{:?}", settings.state_override_policy)),
+        ),
+        ("strategy", json!(format!("{:?}", settings.strategy))),
+        ("export_upstreams", json!(settings.export_upstreams)),
+        ("strict_jsonrpc", json!(settings.strict_jsonrpc)),
+        ("legacy_jsonrpc", json!(settings.legacy_jsonrpc)),
+        ("logs_cache_chunk", json!(settings.logs_cache_chunk)),
+        ("stale_latest_delta", json!(settings.stale_latest_delta)),
+        ("max_batch_size", json!(settings.max_batch_size)),
+        (
+            "upstream_override_keys",
+            json!(format!("{} keys", settings.upstream_override_keys.len())),
+        ),
+        ("expose_provenance", json!(settings.expose_provenance)),
+        ("unfinalized_ttl", json!(settings.unfinalized_ttl)),
+        ("hedge_delay", json!(format!("{:?}", settings.hedge_delay))),
+        ("rewrite", json!(format!("{:?}", settings.rewrite))),
+        ("cache_rules", json!(format!("{:?}", settings.cache_rules))),
+        ("routing", json!(format!("{:?}", settings.routing))),
+        ("quorum", json!(format!("{:?}", settings.quorum))),
+        ("clock", json!(format!("{:?}", settings.clock))),
+        ("probes", json!(format!("{:?}", settings.probes))),
+        (
+            "broadcast_transactions",
+            json!(settings.broadcast_transactions),
+        ),
+        ("log_level", json!(settings.log_filter.to_string())),
+        ("log_sample_rate", json!(settings.log_filter.sample_rate)),
+        ("log_format", json!(settings.log_filter.format.to_string())),
+        (
+            "hardened.denied_namespaces",
+            json!(settings.hardened.denied_namespaces),
+        ),
+        (
+            "hardened.max_body_size",
+            json!(settings.hardened.max_body_size),
+        ),
+        // Don't leak the keys themselves
+        (
+            "hardened.allowed_keys",
+            json!(format!("{} keys", settings.hardened.allowed_keys.len())),
+        ),
+    ]
+}
+
+// Settings that are only read on startup
+fn restart_settings(settings: &Settings) -> Vec<(&'static str, String)> {
+    let sled = &settings.sled_config;
+    let admin = &settings.admin;
+    let hardened = &settings.hardened;
+
+    vec![
+        ("address", format!("{:?}", settings.address)),
+        ("health_check", format!("{:?}", settings.health_check)),
+        ("chain", format!("{:?}", settings.chain)),
+        (
+            "sled",
+            format!(
+                "{:?}",
+                (
+                    &sled.path,
+                    sled.cache_capacity,
+                    sled.flush_every_ms,
+                    sled.use_compression,
+                    sled.compression_factor,
+                    sled.mode,
+                )
+            ),
+        ),
+        (
+            "admin",
+            format!(
+                "{:?} {:?}",
+                admin,
+                admin
+                    .keys
+                    .iter()
+                    .map(|key| (&key.name, &key.key, key.role))
+                    .collect::<Vec<_>>()
+            ),
+        ),
+        (
+            "hardened rate limits",
+            format!(
+                "{:?}",
+                (
+                    hardened.enabled,
+                    hardened.requests_per_second,
+                    hardened.burst,
+                    hardened.daily_quota,
+                )
+            ),
+        ),
+        ("slo", format!("{:?}", settings.slo)),
+        ("anomaly", format!("{:?}", settings.anomaly)),
+        ("sla", format!("{:?}", settings.sla)),
+        ("watchdog", format!("{:?}", settings.watchdog)),
+        ("divergence", format!("{:?}", settings.divergence)),
+        ("reorg", format!("{:?}", settings.reorg)),
+        ("websocket", format!("{:?}", settings.websocket)),
+        ("backfill", format!("{:?}", settings.backfill)),
+        ("prometheus", format!("{:?}", settings.prometheus)),
+        ("tls", format!("{:?}", settings.tls)),
+        ("event_store", format!("{:?}", settings.event_store)),
+        ("standby", format!("{:?}", settings.standby)),
+        ("schedule", format!("{:?}", settings.schedule)),
+        ("wallet", format!("{:?}", settings.wallet)),
+        ("tx_manager", format!("{:?}", settings.tx_manager)),
+        (
+            "heavy_concurrency",
+            format!("{:?}", settings.heavy_concurrency),
+        ),
+        (
+            "upstream_batch",
+            format!(
+                "{:?}",
+                (
+                    settings.upstream_batch_size,
+                    settings.upstream_batch_window,
+                    settings.upstream_batch_failure,
+                )
+            ),
+        ),
+        (
+            "upstream_max_in_flight",
+            format!(
+                "{:?}",
+                (
+                    settings.upstream_max_in_flight,
+                    settings.upstream_max_queued
+                )
+            ),
+        ),
+        (
+            "negative_cache_ttl",
+            format!("{:?}", settings.negative_cache_ttl),
+        ),
+        (
+            "read_your_writes",
+            format!("{:?}", settings.read_your_writes),
+        ),
+        (
+            "static_responses",
+            format!("{:?}", settings.static_responses),
+        ),
+        ("coalesce_window", format!("{:?}", settings.coalesce_window)),
+        ("single_flight", format!("{:?}", settings.single_flight)),
+        ("head_gating", format!("{:?}", settings.head_gating)),
+        (
+            "schema_sample_rate",
+            format!("{:?}", settings.schema_sample_rate),
+        ),
+        ("tx_queue", format!("{:?}", settings.tx_queue)),
+        (
+            "cache_compression",
+            format!(
+                "{:?}",
+                (
+                    settings.cache_compression,
+                    settings.cache_dictionary_interval,
+                    settings.cache_dedup,
+                )
+            ),
+        ),
+        (
+            "cache_max_entries",
+            format!(
+                "{:?}",
+                (
+                    settings.cache_max_entries,
+                    settings.cache_admission,
+                    settings.cache_admission_window,
+                )
+            ),
+        ),
+        ("cache_max_size", format!("{:?}", settings.cache_max_size)),
+        (
+            "prewarm_interval",
+            format!("{:?}", settings.prewarm_interval),
+        ),
+        (
+            "handshake_interval",
+            format!("{:?}", settings.handshake_interval),
+        ),
+        (
+            "fee_oracle",
+            format!(
+                "{:?}",
+                (settings.fee_oracle_interval, settings.fee_oracle_blocks)
+            ),
+        ),
+        ("track_inclusion", format!("{:?}", settings.track_inclusion)),
+        ("metrics", format!("{:?}", settings.metrics)),
+        (
+            "top_talkers",
+            format!(
+                "{:?}",
+                (
+                    settings.top_talkers,
+                    settings.top_talkers_max_clients,
+                    settings.top_talkers_export
+                )
+            ),
+        ),
+        (
+            "consumer_cache_stats",
+            format!("{:?}", settings.consumer_cache_stats),
+        ),
+        (
+            "drift_interval",
+            format!(
+                "{:?}",
+                (settings.drift_interval, &settings.drift_alert_webhook)
+            ),
+        ),
+        ("reload_interval", format!("{:?}", settings.reload_interval)),
+        ("chains", format!("{:?}", settings.chains)),
+    ]
+}
+
+// Compare what we're running with to `new`.
+//
+// `running` are the RPCs we're currently using, including the ones in the poverty list.
+pub fn diff_config(old: &Settings, new: &Settings, running: &[&Rpc]) -> ConfigDiff {
+    let mut diff = ConfigDiff::default();
+
+    for rpc in &new.rpc_list {
+        match running.iter().find(|running| running.name == rpc.name) {
+            Some(running) if rpc_settings(running) != rpc_settings(rpc) => {
+                diff.rpcs_changed.push(rpc.name.clone())
+            }
+            Some(_) => {}
+            None => diff.rpcs_added.push(rpc.name.clone()),
+        }
+    }
+    for rpc in running {
+        if !new.rpc_list.iter().any(|new_rpc| new_rpc.name == rpc.name) {
+            diff.rpcs_removed.push(rpc.name.clone());
+        }
+    }
+
+    for ((name, old), (_, new)) in live_settings(old).into_iter().zip(live_settings(new)) {
+        if old != new {
+            diff.changed.push(SettingChange { name, old, new });
+        }
+    }
+
+    for ((name, old), (_, new)) in restart_settings(old).into_iter().zip(restart_settings(new)) {
+        if old != new {
+            diff.restart_required.push(name);
+        }
+    }
+
+    diff
+}
+
+// Apply the settings we can change while running
+pub fn apply_settings(config: &mut Settings, new: &Settings) {
+    config.ttl = new.ttl;
+    config.max_retries = new.max_retries;
+    config.health_check_ttl = new.health_check_ttl;
+    config.pin = new.pin.clone();
+    config.namespace_pools = new.namespace_pools.clone();
+    config.wallet_upstream = new.wallet_upstream.clone();
+    config.state_override_policy = new.state_override_policy;
+    config.strategy = new.strategy;
+    config.export_upstreams = new.export_upstreams;
+    config.strict_jsonrpc = new.strict_jsonrpc;
+    config.legacy_jsonrpc = new.legacy_jsonrpc;
+    config.logs_cache_chunk = new.logs_cache_chunk;
+    config.stale_latest_delta = new.stale_latest_delta;
+    config.max_batch_size = new.max_batch_size;
+    config.upstream_override_keys = new.upstream_override_keys.clone();
+    config.expose_provenance = new.expose_provenance;
+    config.unfinalized_ttl = new.unfinalized_ttl;
+    config.hedge_delay = new.hedge_delay;
+    config.rewrite = new.rewrite.clone();
+    config.cache_rules = new.cache_rules.clone();
+    config.routing = new.routing.clone();
+    config.quorum = new.quorum.clone();
+    config.clock = new.clock.clone();
+    config.probes = new.probes.clone();
+    config.broadcast_transactions = new.broadcast_transactions;
+    config.log_filter = new.log_filter.clone();
+    config.hardened.denied_namespaces = new.hardened.denied_namespaces.clone();
+    config.hardened.max_body_size = new.hardened.max_body_size;
+    config.hardened.allowed_keys = new.hardened.allowed_keys.clone();
+    config.rpc_list = new.rpc_list.clone();
+}
+
+// Swap in the added and changed RPCs, and drop the removed ones.
+//
+// RPCs that didn't change are left alone, so they keep their latency and health history.
+pub fn apply_rpcs(
+    rpc_list: &mut Vec<Rpc>,
+    poverty_list: &mut Vec<Rpc>,
+    new: &[Rpc],
+    diff: &ConfigDiff,
+) {
+    let replaced =
+        |rpc: &Rpc| diff.rpcs_removed.contains(&rpc.name) || diff.rpcs_changed.contains(&rpc.name);
+    rpc_list.retain(|rpc| !replaced(rpc));
+    poverty_list.retain(|rpc| !replaced(rpc));
+
+    rpc_list.extend(
+        new.iter()
+            .filter(|rpc| {
+                diff.rpcs_added.contains(&rpc.name) || diff.rpcs_changed.contains(&rpc.name)
+            })
+            .cloned(),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rpc(name: &str, url: &str) -> Rpc {
+        let mut rpc = Rpc::new(url.to_string(), 0, 0.0);
+        rpc.name = name.to_string();
+        rpc
+    }
+
+    fn settings(rpc_list: Vec<Rpc>, ttl: u128) -> Settings {
+        Settings {
+            rpc_list,
+            ttl,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_diff_config() {
+        let old = settings(vec![rpc("a", "http://a"), rpc("b", "http://b")], 1000);
+        let mut new = settings(vec![rpc("b", "http://b2"), rpc("c", "http://c")], 2000);
+        new.heavy_concurrency = 4;
+
+        let running: Vec<&Rpc> = old.rpc_list.iter().collect();
+        let diff = diff_config(&old, &new, &running);
+
+        assert_eq!(diff.rpcs_added, vec!["c"]);
+        assert_eq!(diff.rpcs_removed, vec!["a"]);
+        assert_eq!(diff.rpcs_changed, vec!["b"]);
+        assert_eq!(
+            diff.changed,
+            vec![SettingChange {
+                name: "ttl",
+                old: json!(1000),
+                new: json!(2000),
+            }]
+        );
+        assert_eq!(diff.restart_required, vec!["heavy_concurrency"]);
+
+        // Nothing changes when reloading the same config
+        assert!(diff_config(&old, &old.clone(), &running).is_empty());
+    }
+
+    #[test]
+    fn test_apply_rpcs() {
+        let mut rpc_list = vec![rpc("a", "http://a")];
+        let mut poverty_list = vec![rpc("b", "http://b")];
+        let mut kept = rpc("a", "http://a");
+        kept.status.latency = 5.0;
+        rpc_list[0] = kept;
+
+        let old = settings(vec![rpc("a", "http://a"), rpc("b", "http://b")], 1000);
+        let new = settings(vec![rpc("a", "http://a"), rpc("c", "http://c")], 1000);
+        let running: Vec<&Rpc> = rpc_list.iter().chain(poverty_list.iter()).collect();
+        let diff = diff_config(&old, &new, &running);
+
+        apply_rpcs(&mut rpc_list, &mut poverty_list, &new.rpc_list, &diff);
+        assert_eq!(
+            rpc_list
+                .iter()
+                .map(|rpc| rpc.name.as_str())
+                .collect::<Vec<_>>(),
+            vec!["a", "c"]
+        );
+        assert_eq!(rpc_list[0].status.latency, 5.0);
+        assert!(poverty_list.is_empty());
+    }
+}
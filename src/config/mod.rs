@@ -1,4 +1,7 @@
 pub mod cache_setup;
 pub mod cli_args;
+pub mod diff;
+pub mod reload;
+pub mod schedule;
 pub mod setup;
 pub mod types;
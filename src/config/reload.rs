@@ -0,0 +1,271 @@
+// Reloading the config file while we're running, from `blutgang_reloadConfig`, on SIGHUP
+// or when the file changes.
+//
+// The new config is parsed and diffed before we take any locks. The settings and both
+// RPC lists are then swapped while holding all of their locks, so nothing sees the new
+// settings with the old RPCs or the other way around. Requests that already read what
+// they need, like their ttl and retries, finish with it.
+use crate::{
+    config::{
+        diff::{
+            apply_rpcs,
+            apply_settings,
+            diff_config,
+            ConfigDiff,
+        },
+        types::Settings,
+    },
+    logging::filter::set_log_filter,
+    Rpc,
+};
+
+use std::{
+    fs,
+    path::Path,
+    sync::{
+        Arc,
+        RwLock,
+    },
+    time::{
+        Duration,
+        SystemTime,
+    },
+};
+
+use tokio::time::sleep;
+
+#[cfg(unix)]
+use tokio::signal::unix::{
+    signal,
+    Signal,
+    SignalKind,
+};
+
+// Read the config file we started with again and apply what changed, unless it's a `dry_run`
+pub async fn reload_config(
+    rpc_list: &Arc<RwLock<Vec<Rpc>>>,
+    poverty_list: &Arc<RwLock<Vec<Rpc>>>,
+    config: &Arc<RwLock<Settings>>,
+    dry_run: bool,
+) -> Result<ConfigDiff, String> {
+    let path = config
+        .read()
+        .unwrap()
+        .config_path
+        .clone()
+        .ok_or_else(|| "blutgang was not started with a config file".to_string())?;
+    let new = Settings::reload(&path).await?;
+
+    let diff = {
+        let config_guard = config.read().unwrap();
+        let rpc_list_guard = rpc_list.read().unwrap();
+        let poverty_list_guard = poverty_list.read().unwrap();
+        let running: Vec<&Rpc> = rpc_list_guard
+            .iter()
+            .chain(poverty_list_guard.iter())
+            .collect();
+        diff_config(&config_guard, &new, &running)
+    };
+
+    if !dry_run && !diff.is_empty() {
+        // Same order as when we diff, the config first
+        let mut config_guard = config.write().unwrap();
+        let mut rpc_list_guard = rpc_list.write().unwrap();
+        let mut poverty_list_guard = poverty_list.write().unwrap();
+        apply_rpcs(
+            &mut rpc_list_guard,
+            &mut poverty_list_guard,
+            &new.rpc_list,
+            &diff,
+        );
+        apply_settings(&mut config_guard, &new);
+        set_log_filter(new.log_filter.clone());
+    }
+
+    Ok(diff)
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|meta| meta.modified()).ok()
+}
+
+// Print what a reload changed, and what won't change until we restart
+fn log_reload(diff: &ConfigDiff) {
+    println!(
+        "\x1b[35mInfo:\x1b[0m Reloaded config: {} settings changed, {} RPCs added, {} removed and {} changed",
+        diff.changed.len(),
+        diff.rpcs_added.len(),
+        diff.rpcs_removed.len(),
+        diff.rpcs_changed.len(),
+    );
+    if !diff.restart_required.is_empty() {
+        println!(
+            "\x1b[93mWrn:\x1b[0m Restart to apply: {}",
+            diff.restart_required.join(", ")
+        );
+    }
+}
+
+#[cfg(unix)]
+pub type Hangups = Option<Signal>;
+#[cfg(not(unix))]
+pub type Hangups = Option<()>;
+
+// Start listening for SIGHUPs, which reload the config and TLS certificate
+#[cfg(unix)]
+pub fn listen_for_hangups() -> Hangups {
+    signal(SignalKind::hangup()).ok()
+}
+
+#[cfg(not(unix))]
+pub fn listen_for_hangups() -> Hangups {
+    None
+}
+
+// Resolves every time we get a SIGHUP, never if we can't get them
+#[cfg(unix)]
+pub async fn hangup(hangups: &mut Hangups) {
+    if let Some(hangups) = hangups {
+        if hangups.recv().await.is_some() {
+            return;
+        }
+    }
+    std::future::pending().await
+}
+
+#[cfg(not(unix))]
+pub async fn hangup(_hangups: &mut Hangups) {
+    std::future::pending().await
+}
+
+// Reload the config on SIGHUP, and every time the file changes checking every `interval`.
+// An `interval` of 0 only reloads on SIGHUP.
+//
+// Configs that don't parse are ignored until the file changes again.
+pub async fn watch_config(
+    rpc_list: Arc<RwLock<Vec<Rpc>>>,
+    poverty_list: Arc<RwLock<Vec<Rpc>>>,
+    config: Arc<RwLock<Settings>>,
+    interval: Duration,
+) {
+    let path = match config.read().unwrap().config_path.clone() {
+        Some(path) => path,
+        None => return,
+    };
+    let mut last_modified = modified(&path);
+    let polling = !interval.is_zero();
+    let mut hangups = listen_for_hangups();
+
+    loop {
+        tokio::select! {
+            _ = sleep(interval), if polling => {
+                let modified = modified(&path);
+                if modified == last_modified {
+                    continue;
+                }
+                last_modified = modified;
+            }
+            _ = hangup(&mut hangups) => {
+                last_modified = modified(&path);
+            }
+        }
+
+        match reload_config(&rpc_list, &poverty_list, &config, false).await {
+            Ok(diff) if diff.is_empty() => {}
+            Ok(diff) => log_reload(&diff),
+            Err(err) => {
+                println!(
+                    "\x1b[31mErr:\x1b[0m Could not reload config, keeping the current one: {}",
+                    err
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_watch_config() {
+        let path =
+            std::env::temp_dir().join(format!("blutgang-watch-config-{}.toml", std::process::id()));
+        let example = include_str!("../../example_config.toml")
+            .replace("sort_on_startup = true", "sort_on_startup = false");
+        fs::write(&path, &example).unwrap();
+
+        let mut settings = Settings::reload(&path).await.unwrap();
+        settings.config_path = Some(path.clone());
+        let rpc_list = Arc::new(RwLock::new(settings.rpc_list.clone()));
+        let poverty_list = Arc::new(RwLock::new(Vec::new()));
+        let config = Arc::new(RwLock::new(settings));
+
+        tokio::task::spawn(watch_config(
+            Arc::clone(&rpc_list),
+            Arc::clone(&poverty_list),
+            Arc::clone(&config),
+            Duration::from_millis(10),
+        ));
+        sleep(Duration::from_millis(50)).await;
+
+        // Broken configs are ignored
+        fs::write(&path, "[blutgang\n").unwrap();
+        sleep(Duration::from_millis(100)).await;
+        assert_eq!(config.read().unwrap().ttl, 300);
+
+        fs::write(&path, example.replace("\nttl = 300", "\nttl = 450")).unwrap();
+        let mut ttl = 0;
+        for _ in 0..100 {
+            ttl = config.read().unwrap().ttl;
+            if ttl == 450 {
+                break;
+            }
+            sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(ttl, 450);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_reload_on_hangup() {
+        let path =
+            std::env::temp_dir().join(format!("blutgang-hangup-{}.toml", std::process::id()));
+        // Reloads don't sort RPCs again, so this doesn't go measure their latency
+        let example = include_str!("../../example_config.toml");
+        fs::write(&path, example).unwrap();
+
+        let mut settings = Settings::reload(&path).await.unwrap();
+        settings.config_path = Some(path.clone());
+        let rpc_list = Arc::new(RwLock::new(settings.rpc_list.clone()));
+        let poverty_list = Arc::new(RwLock::new(Vec::new()));
+        let config = Arc::new(RwLock::new(settings));
+
+        // Not polling, only SIGHUP reloads it
+        tokio::task::spawn(watch_config(
+            Arc::clone(&rpc_list),
+            Arc::clone(&poverty_list),
+            Arc::clone(&config),
+            Duration::ZERO,
+        ));
+        sleep(Duration::from_millis(50)).await;
+
+        fs::write(&path, example.replace("\nttl = 300", "\nttl = 450")).unwrap();
+        sleep(Duration::from_millis(50)).await;
+        assert_eq!(config.read().unwrap().ttl, 300);
+
+        std::process::Command::new("kill")
+            .args(["-HUP", &std::process::id().to_string()])
+            .status()
+            .unwrap();
+        let mut ttl = 0;
+        for _ in 0..100 {
+            ttl = config.read().unwrap().ttl;
+            if ttl == 450 {
+                break;
+            }
+            sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(ttl, 450);
+    }
+}
@@ -0,0 +1,238 @@
+use crate::Rpc;
+
+use std::{
+    collections::HashMap,
+    sync::{
+        Arc,
+        RwLock,
+    },
+    time::Duration,
+};
+
+use chrono::{
+    NaiveTime,
+    Timelike,
+    Utc,
+};
+use tokio::time::sleep;
+use toml::Value;
+
+// A time window (UTC) that changes which RPCs we route to and what they cost
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScheduleRule {
+    pub name: String,
+    // Minutes since midnight. `end` is exclusive, and the window wraps
+    // around midnight if `start` is after `end`.
+    pub start: u32,
+    pub end: u32,
+    // RPCs that are only used while this window is active
+    pub only: Vec<String>,
+    // RPCs that aren't used while this window is active
+    pub disable: Vec<String>,
+    // Cost overrides while this window is active
+    pub cost: HashMap<String, f64>,
+}
+
+impl ScheduleRule {
+    pub fn is_active(&self, minute: u32) -> bool {
+        if self.start <= self.end {
+            (self.start..self.end).contains(&minute)
+        } else {
+            minute >= self.start || minute < self.end
+        }
+    }
+
+    // Every RPC name this rule mentions
+    pub fn rpc_names(&self) -> impl Iterator<Item = &String> {
+        self.only
+            .iter()
+            .chain(self.disable.iter())
+            .chain(self.cost.keys())
+    }
+}
+
+// Parse `HH:MM` into minutes since midnight
+fn parse_time(time: &str) -> Option<u32> {
+    let time = NaiveTime::parse_from_str(time, "%H:%M").ok()?;
+    Some(time.hour() * 60 + time.minute())
+}
+
+fn parse_names(table: &toml::map::Map<String, Value>, key: &str) -> Vec<String> {
+    match table.get(key) {
+        Some(names) => {
+            names
+                .as_array()
+                .unwrap_or_else(|| panic!("\x1b[31mErr:\x1b[0m Could not parse {} as array!", key))
+                .iter()
+                .map(|name| {
+                    name.as_str()
+                        .expect("\x1b[31mErr:\x1b[0m Could not parse RPC name as str!")
+                        .to_string()
+                })
+                .collect()
+        }
+        None => Vec::new(),
+    }
+}
+
+// Parse the `[schedule]` table, where every subtable is a rule
+pub fn parse_schedule(schedule_table: &toml::map::Map<String, Value>) -> Vec<ScheduleRule> {
+    let mut rules = Vec::new();
+
+    for (name, rule_table) in schedule_table {
+        let rule_table = rule_table
+            .as_table()
+            .expect("\x1b[31mErr:\x1b[0m Could not parse schedule rule as table!");
+
+        let get_time = |key: &str| {
+            let time = rule_table
+                .get(key)
+                .unwrap_or_else(|| panic!("\x1b[31mErr:\x1b[0m Missing {} from {}!", key, name))
+                .as_str()
+                .unwrap_or_else(|| panic!("\x1b[31mErr:\x1b[0m Could not parse {} as str!", key));
+            parse_time(time).unwrap_or_else(|| {
+                panic!("\x1b[31mErr:\x1b[0m {} must be formatted as HH:MM!", key)
+            })
+        };
+
+        let cost = match rule_table.get("cost") {
+            Some(cost) => {
+                cost.as_table()
+                    .expect("\x1b[31mErr:\x1b[0m Could not parse schedule cost as table!")
+                    .iter()
+                    .map(|(rpc, cost)| {
+                        let cost = cost
+                            .as_float()
+                            .or_else(|| cost.as_integer().map(|cost| cost as f64))
+                            .expect("\x1b[31mErr:\x1b[0m Could not parse cost as a number!");
+                        (rpc.to_string(), cost)
+                    })
+                    .collect()
+            }
+            None => HashMap::new(),
+        };
+
+        rules.push(ScheduleRule {
+            name: name.to_string(),
+            start: get_time("start"),
+            end: get_time("end"),
+            only: parse_names(rule_table, "only"),
+            disable: parse_names(rule_table, "disable"),
+            cost,
+        });
+    }
+
+    rules
+}
+
+// Pause or unpause RPCs and set their cost overrides according to the rules active at `minute`
+pub fn apply_schedule(rules: &[ScheduleRule], rpc_list: &mut [Rpc], minute: u32) {
+    let active: Vec<&ScheduleRule> = rules.iter().filter(|rule| rule.is_active(minute)).collect();
+
+    for rpc in rpc_list.iter_mut() {
+        let restricted = rules.iter().any(|rule| rule.only.contains(&rpc.name));
+        let allowed = active.iter().any(|rule| rule.only.contains(&rpc.name));
+        let disabled = active.iter().any(|rule| rule.disable.contains(&rpc.name));
+
+        rpc.paused = (restricted && !allowed) || disabled;
+        rpc.cost_override = active
+            .iter()
+            .find_map(|rule| rule.cost.get(&rpc.name).copied());
+    }
+}
+
+fn current_minute() -> u32 {
+    let now = Utc::now().time();
+    now.hour() * 60 + now.minute()
+}
+
+// Re-evaluate the schedule at the start of every minute.
+//
+// Both lists are updated so RPCs coming back from poverty have the right state.
+pub async fn run_schedule(
+    rules: Vec<ScheduleRule>,
+    rpc_list: Arc<RwLock<Vec<Rpc>>>,
+    poverty_list: Arc<RwLock<Vec<Rpc>>>,
+) {
+    let mut last_active: Vec<&str> = Vec::new();
+
+    loop {
+        let minute = current_minute();
+
+        let active: Vec<&str> = rules
+            .iter()
+            .filter(|rule| rule.is_active(minute))
+            .map(|rule| rule.name.as_str())
+            .collect();
+        if active != last_active {
+            println!("\x1b[35mInfo:\x1b[0m Active schedule rules: {:?}", active);
+            last_active = active;
+        }
+
+        apply_schedule(&rules, &mut rpc_list.write().unwrap(), minute);
+        apply_schedule(&rules, &mut poverty_list.write().unwrap(), minute);
+
+        let seconds = Utc::now().second() as u64;
+        sleep(Duration::from_secs(60 - seconds.min(59))).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(start: &str, end: &str) -> ScheduleRule {
+        ScheduleRule {
+            name: "night".to_string(),
+            start: parse_time(start).unwrap(),
+            end: parse_time(end).unwrap(),
+            only: Vec::new(),
+            disable: Vec::new(),
+            cost: HashMap::new(),
+        }
+    }
+
+    #[test]
+    fn test_is_active() {
+        let day = rule("09:00", "17:00");
+        assert!(day.is_active(9 * 60));
+        assert!(!day.is_active(17 * 60));
+        assert!(!day.is_active(3 * 60));
+
+        // Wraps around midnight
+        let night = rule("22:00", "06:00");
+        assert!(night.is_active(23 * 60));
+        assert!(night.is_active(2 * 60));
+        assert!(!night.is_active(12 * 60));
+
+        assert_eq!(parse_time("25:00"), None);
+    }
+
+    #[test]
+    fn test_apply_schedule() {
+        let mut night = rule("22:00", "06:00");
+        night.only = vec!["paid".to_string()];
+        night.disable = vec!["self_hosted".to_string()];
+        night.cost.insert("paid".to_string(), 0.0);
+
+        let mut rpc_list = vec![Rpc::default(), Rpc::default(), Rpc::default()];
+        rpc_list[0].name = "paid".to_string();
+        rpc_list[0].cost = 5.0;
+        rpc_list[1].name = "self_hosted".to_string();
+        rpc_list[2].name = "other".to_string();
+
+        // Daytime, paid is off
+        apply_schedule(&[night.clone()], &mut rpc_list, 12 * 60);
+        assert!(rpc_list[0].paused);
+        assert!(!rpc_list[1].paused);
+        assert!(!rpc_list[2].paused);
+        assert_eq!(rpc_list[0].effective_cost(), 5.0);
+
+        // Nighttime, rates reset and we move to the paid RPC
+        apply_schedule(&[night], &mut rpc_list, 23 * 60);
+        assert!(!rpc_list[0].paused);
+        assert!(rpc_list[1].paused);
+        assert!(!rpc_list[2].paused);
+        assert_eq!(rpc_list[0].effective_cost(), 0.0);
+    }
+}
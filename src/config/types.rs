@@ -1,22 +1,93 @@
 use crate::{
-    config::setup::sort_by_latency,
+    admin::auth::{
+        AdminKey,
+        AdminRole,
+    },
+    balancer::{
+        admission::Admission,
+        classify::Chain,
+        hedge::HedgeDelay,
+        overrides::StateOverridePolicy,
+        panic_guard::panic_reason,
+        rewrite::{
+            parse_rewrites,
+            RewriteRule,
+        },
+        selection::{
+            cache_rules::{
+                parse_cache_rules,
+                CacheRules,
+            },
+            routing::{
+                parse_routing,
+                RoutingRules,
+            },
+            select::{
+                Route,
+                Strategy,
+            },
+        },
+        upstream_batch::BatchFailure,
+    },
+    config::{
+        schedule::{
+            parse_schedule,
+            ScheduleRule,
+        },
+        setup::sort_by_latency,
+    },
+    health::{
+        clock::{
+            default_max_drift,
+            ClockSource,
+        },
+        watchdog::WatchdogAction,
+    },
+    logging::{
+        filter::LogFilter,
+        format::LogFormat,
+    },
+    rpc::{
+        dial::IpPreference,
+        probe::{
+            Probe,
+            ProbeConfig,
+        },
+        signer::{
+            parse_jwt_secret,
+            signer_from_config,
+            JwtSigner,
+            RequestSigner,
+            SignerConfig,
+        },
+        sla::Sla,
+        types::{
+            Protocol,
+            RpcAuth,
+        },
+        window::WindowConfig,
+    },
     Rpc,
 };
-use clap::{
-    ArgMatches,
-    Command,
-};
+use clap::ArgMatches;
 use jsonwebtoken::DecodingKey;
 
 use sled::Config;
 
 use std::{
+    collections::HashMap,
     fmt,
     fmt::Debug,
     fs::{
         self,
     },
     net::SocketAddr,
+    path::{
+        Path,
+        PathBuf,
+    },
+    sync::Arc,
+    time::Duration,
 };
 
 use toml::Value;
@@ -28,6 +99,10 @@ pub struct AdminSettings {
     pub readonly: bool,
     pub jwt: bool,
     pub key: DecodingKey,
+    // Named admin keys and their roles. Auth is disabled if empty.
+    pub keys: Vec<AdminKey>,
+    // File every admin request gets appended to
+    pub audit_log: Option<PathBuf>,
 }
 
 impl Default for AdminSettings {
@@ -38,6 +113,8 @@ impl Default for AdminSettings {
             readonly: false,
             jwt: false,
             key: DecodingKey::from_secret(b""),
+            keys: Vec::new(),
+            audit_log: None,
         }
     }
 }
@@ -49,10 +126,470 @@ impl Debug for AdminSettings {
         write!(f, ", address: {:?}", self.address)?;
         write!(f, ", readonly: {:?}", self.readonly)?;
         write!(f, ", jwt: HIDDEN",)?;
+        write!(f, ", keys: {} HIDDEN", self.keys.len())?;
+        write!(f, ", audit_log: {:?}", self.audit_log)?;
         write!(f, " }}")
     }
 }
 
+// Tables that configure blutgang itself. Every other table is parsed as an RPC.
+const RESERVED_TABLES: &[&str] = &[
+    "blutgang",
+    "sled",
+    "admin",
+    "hardened",
+    "slo",
+    "schedule",
+    "anomaly",
+    "wallet",
+    "sla",
+    "watchdog",
+    "divergence",
+    "websocket",
+    "rewrite",
+    "backfill",
+    "prometheus",
+    "cache_rules",
+    "routing",
+    "quorum",
+    "reorg",
+    "tx_manager",
+    "tls",
+    "event_store",
+    "standby",
+    "clock",
+    "probes",
+    "chains",
+];
+
+// Bundle of protections for publicly exposed endpoints, enabled with `--hardened`
+#[derive(Debug, Clone)]
+pub struct HardenedSettings {
+    pub enabled: bool,
+    // Methods starting with any of these are rejected
+    pub denied_namespaces: Vec<String>,
+    // Maximum request body size in bytes
+    pub max_body_size: usize,
+    // Per-client token bucket
+    pub requests_per_second: f64,
+    pub burst: f64,
+    // Max requests per client per day, 0 for unlimited
+    pub daily_quota: u64,
+    // Keys (`x-api-key` header) allowed to use `eth_sendRawTransaction`
+    pub allowed_keys: Vec<String>,
+}
+
+impl Default for HardenedSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            denied_namespaces: vec![
+                "admin_".to_string(),
+                "debug_".to_string(),
+                "personal_".to_string(),
+                "miner_".to_string(),
+            ],
+            max_body_size: 5_242_880,
+            requests_per_second: 50.0,
+            burst: 100.0,
+            daily_quota: 0,
+            allowed_keys: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SloSettings {
+    pub enabled: bool,
+    // Target success rate, e.g. 0.999 for 99.9%
+    pub target: f64,
+    // Window the target applies to
+    pub window_days: u64,
+    // Alert once the error budget is burning this many times faster than sustainable
+    pub burn_rate_threshold: f64,
+    // Optional url we POST alerts to
+    pub alert_webhook: Option<String>,
+}
+
+impl Default for SloSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            target: 0.999,
+            window_days: 30,
+            burn_rate_threshold: 14.4,
+            alert_webhook: None,
+        }
+    }
+}
+
+// Enforcement of the response time SLAs set on RPCs
+#[derive(Debug, Clone)]
+pub struct SlaSettings {
+    // Window we take the SLA percentile over
+    pub window: Duration,
+    // Windows with fewer requests than this can't breach the SLA
+    pub min_samples: usize,
+    // Share of their usual requests RPCs in breach still get, from 0 to 1
+    pub share: f64,
+    // Optional url we POST alerts to
+    pub alert_webhook: Option<String>,
+}
+
+impl Default for SlaSettings {
+    fn default() -> Self {
+        Self {
+            window: Duration::from_secs(300),
+            min_samples: 100,
+            share: 0.1,
+            alert_webhook: None,
+        }
+    }
+}
+
+// What the watchdog checks and does when our subsystems get stuck
+#[derive(Debug, Clone)]
+pub struct WatchdogSettings {
+    pub enabled: bool,
+    // How often we check our subsystems
+    pub interval: Duration,
+    // The head tracker is stuck if the head doesn't advance for this long
+    pub head_timeout: Duration,
+    // What we do when a subsystem gets stuck, and undo when it recovers
+    pub actions: Vec<WatchdogAction>,
+    // Optional url we POST alerts to
+    pub alert_webhook: Option<String>,
+}
+
+impl Default for WatchdogSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            interval: Duration::from_secs(10),
+            head_timeout: Duration::from_secs(120),
+            actions: vec![WatchdogAction::Log],
+            alert_webhook: None,
+        }
+    }
+}
+
+// Checks that backup RPCs would hold up if we had to fail over to them
+#[derive(Debug, Clone)]
+pub struct StandbySettings {
+    pub enabled: bool,
+    // RPCs with this tag are standbys
+    pub tag: String,
+    // How often we check them
+    pub interval: Duration,
+    // Rounds of checks readiness is computed over
+    pub window: usize,
+}
+
+impl Default for StandbySettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            tag: "backup".to_string(),
+            interval: Duration::from_secs(60),
+            window: 10,
+        }
+    }
+}
+
+// Probes of RPC health beyond their head, like if they're syncing
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ProbeSettings {
+    pub enabled: bool,
+    // How RPCs get probed unless their table says otherwise
+    pub defaults: ProbeConfig,
+}
+
+// Another chain served from this process, with a config file of its own
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChainRoute {
+    pub name: String,
+    // Requests under this path, or naming the chain in `x-blutgang-chain`, go to it
+    pub path: String,
+    pub config: PathBuf,
+}
+
+// Checks that the head of every RPC is from around the time it should be
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClockSettings {
+    pub enabled: bool,
+    // What head timestamps are compared against
+    pub source: ClockSource,
+    // How far off a head timestamp can be before its RPC leaves the pool
+    pub max_drift: Duration,
+}
+
+impl Default for ClockSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            source: ClockSource::System,
+            max_drift: default_max_drift(None),
+        }
+    }
+}
+
+// Comparison of block hashes across RPCs, to catch ones serving a different chain
+#[derive(Debug, Clone)]
+pub struct DivergenceSettings {
+    pub enabled: bool,
+    // How often we compare hashes
+    pub interval: Duration,
+    // Blocks below the head we compare at, deeper than reorgs normally go
+    pub depth: u64,
+    // Keep RPCs disagreeing with the majority out of the pool until they agree again
+    pub quarantine: bool,
+    // Optional url we POST alerts to
+    pub alert_webhook: Option<String>,
+}
+
+impl Default for DivergenceSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            interval: Duration::from_secs(60),
+            depth: 64,
+            quarantine: false,
+            alert_webhook: None,
+        }
+    }
+}
+
+// Reorg detection from the hashes RPCs report, dropping cached responses of blocks that changed
+#[derive(Debug, Clone)]
+pub struct ReorgSettings {
+    pub enabled: bool,
+    // Blocks of history we keep per RPC, and so the deepest reorg we can catch
+    pub depth: u64,
+    // Seconds reorged entries are kept as tombstones, deleted right away if 0
+    pub tombstone_retention: u64,
+}
+
+impl Default for ReorgSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            depth: 64,
+            tombstone_retention: 0,
+        }
+    }
+}
+
+// Reads of state that get sent to several RPCs at once, answered with what most agree on
+#[derive(Debug, Clone, PartialEq)]
+pub struct QuorumSettings {
+    pub enabled: bool,
+    // Methods that go through quorum
+    pub methods: Vec<String>,
+    // RPCs every one of them is sent to
+    pub size: usize,
+}
+
+impl Default for QuorumSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            methods: vec![
+                "eth_call".to_string(),
+                "eth_getBalance".to_string(),
+                "eth_getStorageAt".to_string(),
+            ],
+            size: 3,
+        }
+    }
+}
+
+impl QuorumSettings {
+    // How many RPCs `method` goes to, None if it doesn't go through quorum
+    pub fn size_for(&self, method: &str) -> Option<usize> {
+        (self.enabled && self.methods.iter().any(|quorum| quorum == method)).then_some(self.size)
+    }
+}
+
+// WebSocket listener for `eth_subscribe`, fanning out upstream subscriptions
+#[derive(Debug, Clone)]
+pub struct WebsocketSettings {
+    pub enabled: bool,
+    pub address: SocketAddr,
+    // Subscriptions a single connection can have open
+    pub max_subscriptions: usize,
+}
+
+impl Default for WebsocketSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            address: "127.0.0.1:3002".parse::<SocketAddr>().unwrap(),
+            max_subscriptions: 64,
+        }
+    }
+}
+
+// Prometheus endpoint with per-RPC stats, on its own port
+#[derive(Debug, Clone)]
+pub struct PrometheusSettings {
+    pub enabled: bool,
+    pub address: SocketAddr,
+}
+
+impl Default for PrometheusSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            address: "127.0.0.1:3003".parse::<SocketAddr>().unwrap(),
+        }
+    }
+}
+
+// TLS for the client facing HTTP and WebSocket listeners
+#[derive(Debug, Clone)]
+pub struct TlsSettings {
+    pub enabled: bool,
+    // PEM certificate chain, leaf first
+    pub cert: PathBuf,
+    // PEM private key
+    pub key: PathBuf,
+    // How often we check if the certificate changed on disk, zero only checks on SIGHUP
+    pub reload_interval: Duration,
+}
+
+impl Default for TlsSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            cert: PathBuf::new(),
+            key: PathBuf::new(),
+            reload_interval: Duration::from_secs(60),
+        }
+    }
+}
+
+// Request summaries and health events kept in sled, queryable from the admin namespace
+#[derive(Debug, Clone)]
+pub struct EventStoreSettings {
+    pub enabled: bool,
+    // Events older than this get dropped
+    pub retention: Duration,
+}
+
+impl Default for EventStoreSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            retention: Duration::from_secs(7 * 24 * 3600),
+        }
+    }
+}
+
+// Paces backfill requests to stay under the rate limits of providers
+#[derive(Debug, Clone)]
+pub struct BackfillSettings {
+    pub enabled: bool,
+    // Requests per second we assume RPCs without `max_per_second` allow
+    pub rate: f64,
+    // Backfill never goes slower than this, whatever the other traffic is
+    pub min_rate: f64,
+}
+
+impl Default for BackfillSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            rate: 25.0,
+            min_rate: 1.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AnomalySettings {
+    pub enabled: bool,
+    // Length of the windows we compare against each other
+    pub window: Duration,
+    // Windows with fewer requests than this are never anomalous
+    pub min_requests: u64,
+    // Flag clients sending this many times more requests than usual
+    pub volume_factor: f64,
+    // Flag clients whose method mix moved this far from usual, 0 being the same mix
+    // and 1 being completely different methods
+    pub mix_threshold: f64,
+    // Cap flagged clients to their usual volume for `throttle_duration`
+    pub throttle: bool,
+    pub throttle_duration: Duration,
+    // Optional url we POST alerts to
+    pub alert_webhook: Option<String>,
+}
+
+impl Default for AnomalySettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            window: Duration::from_secs(60),
+            min_requests: 100,
+            volume_factor: 10.0,
+            mix_threshold: 0.5,
+            throttle: false,
+            throttle_duration: Duration::from_secs(300),
+            alert_webhook: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct WalletSettings {
+    pub enabled: bool,
+    // Url of the signer holding the keys, e.g. Web3Signer or Clef
+    pub signer: String,
+    // Accounts we send transactions from. Asked from the signer if empty.
+    pub accounts: Vec<String>,
+    // Estimated gas gets multiplied by this, so small state changes don't make transactions run out
+    pub gas_multiplier: f64,
+    // API keys of the clients that can use the wallet
+    pub keys: Vec<String>,
+}
+
+impl Default for WalletSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            signer: "http://127.0.0.1:9000".to_string(),
+            accounts: Vec::new(),
+            gas_multiplier: 1.2,
+            keys: Vec::new(),
+        }
+    }
+}
+
+// Resubmitting transactions with higher fees until they're included
+#[derive(Debug, Clone, PartialEq)]
+pub struct TxManagerSettings {
+    pub enabled: bool,
+    // How long a transaction can go without being included before we bump it
+    pub interval: Duration,
+    // How much every bump raises the fees by
+    pub bump_percent: u64,
+    // Bumps before we give up on a transaction
+    pub max_bumps: u32,
+    // Fees never get bumped past this, in wei. Unbounded if None.
+    pub max_fee_per_gas: Option<u64>,
+}
+
+impl Default for TxManagerSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            interval: Duration::from_secs(30),
+            bump_percent: 15,
+            max_bumps: 5,
+            max_fee_per_gas: None,
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Settings {
     pub rpc_list: Vec<Rpc>,
@@ -64,6 +601,147 @@ pub struct Settings {
     pub health_check_ttl: u64,
     pub sled_config: Config,
     pub admin: AdminSettings,
+    pub hardened: HardenedSettings,
+    pub slo: SloSettings,
+    pub anomaly: AnomalySettings,
+    pub sla: SlaSettings,
+    pub watchdog: WatchdogSettings,
+    pub divergence: DivergenceSettings,
+    pub quorum: QuorumSettings,
+    pub reorg: ReorgSettings,
+    pub websocket: WebsocketSettings,
+    pub backfill: BackfillSettings,
+    pub prometheus: PrometheusSettings,
+    pub tls: TlsSettings,
+    pub event_store: EventStoreSettings,
+    pub standby: StandbySettings,
+    pub probes: ProbeSettings,
+    pub clock: ClockSettings,
+    pub wallet: WalletSettings,
+    pub tx_manager: TxManagerSettings,
+    // Methods hard-pinned to a named RPC, `method -> rpc name`
+    pub pin: HashMap<String, String>,
+    // Namespaces whose methods only go to some RPCs, `namespace -> rpc names`
+    pub namespace_pools: HashMap<String, Vec<String>>,
+    // RPC wallet methods like `eth_sendTransaction` are forwarded to. Rejected if None.
+    pub wallet_upstream: Option<String>,
+    pub state_override_policy: StateOverridePolicy,
+    // Max amount of heavy methods (`eth_simulateV1`, `eth_callMany`, `debug_traceCall`) in flight
+    pub heavy_concurrency: usize,
+    // Only allow L2 methods that exist on this chain. Allows all if None.
+    pub chain: Option<Chain>,
+    // Serve healthy upstreams and their weights at `/upstreams`
+    pub export_upstreams: bool,
+    // Validate requests and rewrite responses to follow JSON-RPC 2.0 to the letter
+    pub strict_jsonrpc: bool,
+    // Accept requests without `"jsonrpc"` in strict mode, answered JSON-RPC 1.0 style
+    pub legacy_jsonrpc: bool,
+    // Blocks per cached chunk of `eth_getLogs` results, 0 to not cache them
+    pub logs_cache_chunk: u64,
+    // Blocks `latest` responses can be behind the head before we retry them elsewhere, 0 disables it
+    pub stale_latest_delta: u64,
+    // Never answer a client's `latest` query with an older block than we already gave it
+    pub head_gating: bool,
+    // Share of responses checked against the schema of their method, 0 disables it
+    pub schema_sample_rate: f64,
+    // Max requests in a batch from a client, 0 for no limit
+    pub max_batch_size: usize,
+    // API keys allowed to pick the RPC their requests go to with `x-blutgang-upstream`
+    pub upstream_override_keys: Vec<String>,
+    // Let clients ask where their responses came from with `x-blutgang-provenance`
+    pub expose_provenance: bool,
+    // How long responses for blocks that aren't finalized yet stay cached for, in ms.
+    // 0 keeps them until they're finalized or reorged out.
+    pub unfinalized_ttl: u64,
+    // Max requests coalesced into one upstream batch, 0 disables batching
+    pub upstream_batch_size: usize,
+    // Max requests in flight to a single RPC, 0 disables the limit
+    pub upstream_max_in_flight: usize,
+    // Max requests of each priority waiting for a single RPC
+    pub upstream_max_queued: usize,
+    // How long we wait for more requests before sending a batch, in ms
+    pub upstream_batch_window: u64,
+    // What happens to a batch when some of its entries fail upstream
+    pub upstream_batch_failure: BatchFailure,
+    // How we pick between eligible RPCs
+    pub strategy: Strategy,
+    // Time windows that pause RPCs or change their cost
+    pub schedule: Vec<ScheduleRule>,
+    // Rules rewriting requests before we handle them, applied in order
+    pub rewrite: Vec<RewriteRule>,
+    // How each method gets cached, from the `[cache_rules]` table
+    pub cache_rules: CacheRules,
+    // Which RPCs requests go to by their tags, from the `[routing]` table
+    pub routing: RoutingRules,
+    // How long we remember `eth_getTransactionByHash` lookups that returned null, in ms.
+    // 0 disables the negative cache.
+    pub negative_cache_ttl: u64,
+    // Send lookups for transactions we sent to RPCs we know have them
+    pub read_your_writes: bool,
+    // Answer methods like `eth_chainId` ourselves, after checking the RPCs agree on them
+    pub static_responses: bool,
+    // How long identical requests for the head share one upstream call, in ms. 0 disables it.
+    pub coalesce_window: u64,
+    // Identical requests in flight at the same time share one upstream call
+    pub single_flight: bool,
+    // Send slow requests to a second RPC after this long, None disables hedging
+    pub hedge_delay: Option<HedgeDelay>,
+    // Queue `eth_sendRawTransaction` while every RPC is down, for clients that ask for it
+    pub tx_queue: bool,
+    // Send `eth_sendRawTransaction` to every healthy RPC at once instead of just one
+    pub broadcast_transactions: bool,
+    // Log levels and request log sampling we start with. Can be changed at runtime.
+    pub log_filter: LogFilter,
+    // Run the self-test and exit instead of serving requests
+    pub self_test: bool,
+    // zstd level cached responses are compressed with, 0 disables compression
+    pub cache_compression: i32,
+    // How often to retrain the compression dictionary, in seconds. 0 disables dictionaries.
+    pub cache_dictionary_interval: u64,
+    // Store identical responses only once
+    pub cache_dedup: bool,
+    // Entries the cache holds at most, 0 for unbounded
+    pub cache_max_entries: usize,
+    // MB of cached responses the cache holds at most, 0 for unbounded
+    pub cache_max_size: u64,
+    // Which entries get to replace others once the cache is full
+    pub cache_admission: Admission,
+    // Share of `cache_max_entries` new entries go into before being considered for admission
+    pub cache_admission_window: f64,
+    // Connections we keep open to each RPC unless set per RPC, 0 disables prewarming
+    pub prewarm_connections: usize,
+    // How often we prewarm connections, in ms
+    pub prewarm_interval: u64,
+    // How often we time connecting to each RPC, in ms. 0 disables it.
+    pub handshake_interval: u64,
+    // Address family we connect to RPCs over first unless set per RPC
+    pub ip_preference: IpPreference,
+    // How often we update the fee oracle, in ms. 0 disables it.
+    pub fee_oracle_interval: u64,
+    // Blocks the fee oracle bases its suggestions on
+    pub fee_oracle_blocks: usize,
+    // Measure how long transactions broadcast through each RPC take to get included
+    pub track_inclusion: bool,
+    // Serve latency histograms at /metrics
+    pub metrics: bool,
+    // Track requests, bytes and errors of every client
+    pub top_talkers: bool,
+    // Clients we keep stats for
+    pub top_talkers_max_clients: usize,
+    // Busiest clients we export metric series for
+    pub top_talkers_export: usize,
+    // Count cache hits and misses of every client for `blutgang_cacheStats`
+    pub consumer_cache_stats: bool,
+    // How often we check what client and modules RPCs run, in seconds. 0 disables it.
+    pub drift_interval: u64,
+    // Optional url we POST changes to
+    pub drift_alert_webhook: Option<String>,
+    // File we read our settings from, used when reloading them
+    pub config_path: Option<PathBuf>,
+    // How often we check if the config file changed and reload it, in seconds. 0 disables it.
+    pub reload_interval: u64,
+    // Other chains we serve, from the `[chains]` table
+    pub chains: Vec<ChainRoute>,
 }
 
 impl Default for Settings {
@@ -78,31 +756,166 @@ impl Default for Settings {
             health_check_ttl: 1000,
             sled_config: sled::Config::default(),
             admin: AdminSettings::default(),
+            hardened: HardenedSettings::default(),
+            slo: SloSettings::default(),
+            anomaly: AnomalySettings::default(),
+            sla: SlaSettings::default(),
+            watchdog: WatchdogSettings::default(),
+            divergence: DivergenceSettings::default(),
+            quorum: QuorumSettings::default(),
+            reorg: ReorgSettings::default(),
+            websocket: WebsocketSettings::default(),
+            backfill: BackfillSettings::default(),
+            prometheus: PrometheusSettings::default(),
+            tls: TlsSettings::default(),
+            event_store: EventStoreSettings::default(),
+            standby: StandbySettings::default(),
+            probes: ProbeSettings::default(),
+            clock: ClockSettings::default(),
+            wallet: WalletSettings::default(),
+            tx_manager: TxManagerSettings::default(),
+            pin: HashMap::new(),
+            namespace_pools: HashMap::new(),
+            wallet_upstream: None,
+            state_override_policy: StateOverridePolicy::default(),
+            heavy_concurrency: 16,
+            chain: None,
+            export_upstreams: false,
+            strict_jsonrpc: false,
+            legacy_jsonrpc: false,
+            logs_cache_chunk: 0,
+            stale_latest_delta: 0,
+            head_gating: false,
+            schema_sample_rate: 0.0,
+            max_batch_size: 1000,
+            upstream_override_keys: Vec::new(),
+            expose_provenance: false,
+            unfinalized_ttl: 60000,
+            upstream_batch_size: 0,
+            upstream_max_in_flight: 0,
+            upstream_max_queued: 1024,
+            upstream_batch_window: 2,
+            upstream_batch_failure: BatchFailure::default(),
+            strategy: Strategy::default(),
+            schedule: Vec::new(),
+            rewrite: Vec::new(),
+            cache_rules: CacheRules::default(),
+            routing: RoutingRules::default(),
+            negative_cache_ttl: 0,
+            read_your_writes: false,
+            static_responses: false,
+            coalesce_window: 0,
+            single_flight: false,
+            hedge_delay: None,
+            tx_queue: false,
+            broadcast_transactions: false,
+            log_filter: LogFilter::default(),
+            self_test: false,
+            cache_compression: 0,
+            cache_dictionary_interval: 0,
+            cache_dedup: false,
+            cache_max_entries: 0,
+            cache_max_size: 0,
+            cache_admission: Admission::TinyLfu,
+            cache_admission_window: 0.01,
+            prewarm_connections: 0,
+            prewarm_interval: 30000,
+            handshake_interval: 0,
+            ip_preference: IpPreference::default(),
+            fee_oracle_interval: 0,
+            fee_oracle_blocks: 20,
+            track_inclusion: false,
+            metrics: false,
+            top_talkers: false,
+            top_talkers_max_clients: 10000,
+            top_talkers_export: 10,
+            consumer_cache_stats: false,
+            drift_interval: 0,
+            drift_alert_webhook: None,
+            config_path: None,
+            reload_interval: 0,
+            chains: Vec::new(),
         }
     }
 }
 
 impl Settings {
-    pub async fn new(matches: Command) -> Settings {
-        let matches = matches.get_matches();
+    pub async fn new(matches: ArgMatches) -> Settings {
+        let hardened = matches.get_occurrences::<String>("hardened").is_some();
+        let self_test = matches.get_occurrences::<String>("self_test").is_some();
 
         // Try to open the file at the path specified in the args
-        let path = matches.get_one::<String>("config").unwrap();
-        let file: Option<String> = match fs::read_to_string(path) {
+        let path = matches.get_one::<String>("config").unwrap().clone();
+        let file: Option<String> = match fs::read_to_string(&path) {
             Ok(file) => Some(file),
             Err(_) => panic!("\x1b[31mErr:\x1b[0m Error opening config file at {}", path),
         };
 
-        if let Some(file) = file {
+        let mut settings = if let Some(file) = file {
             println!("\x1b[35mInfo:\x1b[0m Using config file at {}", path);
-            return Settings::create_from_file(file).await;
+            Settings::create_from_file(file, true).await
+        } else {
+            println!("\x1b[35mInfo:\x1b[0m Using command line arguments for settings...");
+            Settings::create_from_matches(matches)
+        };
+
+        // `--hardened` takes precedence over whatever the config says
+        if hardened {
+            settings.hardened.enabled = true;
         }
+        if settings.hardened.enabled {
+            println!("\x1b[35mInfo:\x1b[0m Hardened mode enabled");
+        }
+        settings.self_test = self_test;
+        settings.config_path = Some(PathBuf::from(path));
+
+        settings
+    }
+
+    // Parse the config file at `path` again, e.g. to reload it.
+    //
+    // Invalid configs make parsing panic, so we parse in a separate task
+    // and return what went wrong instead. Unlike at startup, RPCs aren't
+    // sorted by latency again.
+    pub async fn reload(path: &Path) -> Result<Settings, String> {
+        let file = fs::read_to_string(path)
+            .map_err(|err| format!("Could not read {}: {}", path.display(), err))?;
+
+        match tokio::task::spawn(Settings::create_from_file(file, false)).await {
+            Ok(settings) => Ok(settings),
+            Err(err) => {
+                let reason = match err.try_into_panic() {
+                    Ok(panic) => {
+                        panic_reason(&*panic)
+                            .unwrap_or("Invalid config")
+                            .to_string()
+                    }
+                    Err(_) => "Config parsing was cancelled".to_string(),
+                };
+                Err(reason.replace("\x1b[31mErr:\x1b[0m ", ""))
+            }
+        }
+    }
+
+    // JSON-RPC dialect our RPCs speak, derived from `chain`
+    pub fn protocol(&self) -> Protocol {
+        self.chain.map(|chain| chain.protocol()).unwrap_or_default()
+    }
 
-        println!("\x1b[35mInfo:\x1b[0m Using command line arguments for settings...");
-        Settings::create_from_matches(matches)
+    // RPCs `method` is limited to, the one it's pinned to or the pool of its namespace
+    pub fn route_for(&self, method: &str) -> Option<Route> {
+        if let Some(name) = self.pin.get(method) {
+            return Some(Route::Pinned(name.clone()));
+        }
+        let namespace = method.split('_').next()?;
+        self.namespace_pools
+            .get(namespace)
+            .cloned()
+            .map(Route::Pool)
     }
 
-    async fn create_from_file(conf_file: String) -> Settings {
+    // `startup` is false when reloading, which skips what only makes sense when we start
+    async fn create_from_file(conf_file: String, startup: bool) -> Settings {
         let parsed_toml = conf_file.parse::<Value>().expect("Error parsing TOML");
 
         let table_names: Vec<&String> = parsed_toml.as_table().unwrap().keys().collect::<Vec<_>>();
@@ -139,22 +952,594 @@ impl Settings {
         } else {
             format!("{}:{}", address, port)
         };
-        let address = address
-            .parse::<SocketAddr>()
-            .expect("\x1b[31mErr:\x1b[0m Could not address to SocketAddr!");
+        let address = address
+            .parse::<SocketAddr>()
+            .expect("\x1b[31mErr:\x1b[0m Could not address to SocketAddr!");
+
+        let ma_length = blutgang_table
+            .get("ma_length")
+            .expect("\x1b[31mErr:\x1b[0m Missing ma_length!")
+            .as_integer()
+            .expect("\x1b[31mErr:\x1b[0m Could not parse ma_length as int!")
+            as f64;
+        let ma_window = parse_ma_window(blutgang_table, ma_length);
+
+        let health_check = blutgang_table
+            .get("health_check")
+            .expect("\x1b[31mErr:\x1b[0m Missing health_check toggle!")
+            .as_bool()
+            .expect("\x1b[31mErr:\x1b[0m Could not parse health_check as bool!");
+
+        // Optional `pin = { "method" = "rpc_name" }`
+        let pin: HashMap<String, String> = match blutgang_table.get("pin") {
+            Some(pin) => {
+                pin.as_table()
+                    .expect("\x1b[31mErr:\x1b[0m Could not parse pin as table!")
+                    .iter()
+                    .map(|(method, name)| {
+                        let name = name
+                            .as_str()
+                            .expect("\x1b[31mErr:\x1b[0m Could not parse pinned RPC name as str!");
+                        (method.to_string(), name.to_string())
+                    })
+                    .collect()
+            }
+            None => HashMap::new(),
+        };
+
+        // Optional `namespace_pools = { "trace" = ["rpc_name", ...] }`.
+        // Namespaces can be written with or without their trailing `_`.
+        let namespace_pools: HashMap<String, Vec<String>> =
+            match blutgang_table.get("namespace_pools") {
+                Some(pools) => {
+                    pools
+                        .as_table()
+                        .expect("\x1b[31mErr:\x1b[0m Could not parse namespace_pools as table!")
+                        .iter()
+                        .map(|(namespace, names)| {
+                            let names = parse_string_array(names).expect(
+                            "\x1b[31mErr:\x1b[0m Could not parse namespace pool as array of str!",
+                        );
+                            (namespace.trim_end_matches('_').to_string(), names)
+                        })
+                        .collect()
+                }
+                None => HashMap::new(),
+            };
+
+        let wallet_upstream = blutgang_table.get("wallet_upstream").map(|name| {
+            name.as_str()
+                .expect("\x1b[31mErr:\x1b[0m Could not parse wallet_upstream as str!")
+                .to_string()
+        });
+
+        // Optional, what to do with `eth_call` state overrides
+        let state_override_policy = match blutgang_table.get("state_overrides") {
+            Some(policy) => {
+                let policy = policy
+                    .as_str()
+                    .expect("\x1b[31mErr:\x1b[0m Could not parse state_overrides as str!");
+                StateOverridePolicy::from_config(policy).expect(
+                    "\x1b[31mErr:\x1b[0m state_overrides must be one of passthrough/strip/reject/route!",
+                )
+            }
+            None => StateOverridePolicy::default(),
+        };
+
+        let heavy_concurrency = match blutgang_table.get("heavy_concurrency") {
+            Some(heavy_concurrency) => {
+                heavy_concurrency
+                    .as_integer()
+                    .expect("\x1b[31mErr:\x1b[0m Could not parse heavy_concurrency as int!")
+                    as usize
+            }
+            None => Settings::default().heavy_concurrency,
+        };
+
+        let chain = blutgang_table.get("chain").map(|chain| {
+            let chain = chain
+                .as_str()
+                .expect("\x1b[31mErr:\x1b[0m Could not parse chain as str!");
+            Chain::from_config(chain).expect(
+                "\x1b[31mErr:\x1b[0m chain must be one of ethereum/optimism/arbitrum/zksync/starknet/solana/bitcoin!",
+            )
+        });
+
+        let export_upstreams = match blutgang_table.get("export_upstreams") {
+            Some(export_upstreams) => {
+                export_upstreams
+                    .as_bool()
+                    .expect("\x1b[31mErr:\x1b[0m Could not parse export_upstreams as bool!")
+            }
+            None => false,
+        };
+
+        let strict_jsonrpc = match blutgang_table.get("strict_jsonrpc") {
+            Some(strict_jsonrpc) => {
+                strict_jsonrpc
+                    .as_bool()
+                    .expect("\x1b[31mErr:\x1b[0m Could not parse strict_jsonrpc as bool!")
+            }
+            None => Settings::default().strict_jsonrpc,
+        };
+
+        let legacy_jsonrpc = match blutgang_table.get("legacy_jsonrpc") {
+            Some(legacy_jsonrpc) => {
+                legacy_jsonrpc
+                    .as_bool()
+                    .expect("\x1b[31mErr:\x1b[0m Could not parse legacy_jsonrpc as bool!")
+            }
+            None => Settings::default().legacy_jsonrpc,
+        };
+
+        let logs_cache_chunk = match blutgang_table.get("logs_cache_chunk") {
+            Some(logs_cache_chunk) => {
+                logs_cache_chunk
+                    .as_integer()
+                    .expect("\x1b[31mErr:\x1b[0m Could not parse logs_cache_chunk as int!")
+                    as u64
+            }
+            None => Settings::default().logs_cache_chunk,
+        };
+
+        let stale_latest_delta = match blutgang_table.get("stale_latest_delta") {
+            Some(stale_latest_delta) => {
+                stale_latest_delta
+                    .as_integer()
+                    .expect("\x1b[31mErr:\x1b[0m Could not parse stale_latest_delta as int!")
+                    as u64
+            }
+            None => Settings::default().stale_latest_delta,
+        };
+
+        let head_gating = match blutgang_table.get("head_gating") {
+            Some(head_gating) => {
+                head_gating
+                    .as_bool()
+                    .expect("\x1b[31mErr:\x1b[0m Could not parse head_gating as bool!")
+            }
+            None => Settings::default().head_gating,
+        };
+
+        let schema_sample_rate = match blutgang_table.get("schema_sample_rate") {
+            Some(schema_sample_rate) => {
+                schema_sample_rate
+                    .as_float()
+                    .or_else(|| schema_sample_rate.as_integer().map(|rate| rate as f64))
+                    .expect("\x1b[31mErr:\x1b[0m Could not parse schema_sample_rate as float!")
+            }
+            None => Settings::default().schema_sample_rate,
+        };
+        if !(0.0..=1.0).contains(&schema_sample_rate) {
+            panic!("\x1b[31mErr:\x1b[0m schema_sample_rate must be between 0 and 1!");
+        }
+
+        let max_batch_size = match blutgang_table.get("max_batch_size") {
+            Some(max_batch_size) => {
+                max_batch_size
+                    .as_integer()
+                    .expect("\x1b[31mErr:\x1b[0m Could not parse max_batch_size as int!")
+                    as usize
+            }
+            None => Settings::default().max_batch_size,
+        };
+
+        let upstream_override_keys =
+            match blutgang_table.get("upstream_override_keys") {
+                Some(upstream_override_keys) => parse_string_array(upstream_override_keys).expect(
+                    "\x1b[31mErr:\x1b[0m Could not parse upstream_override_keys as array of str!",
+                ),
+                None => Vec::new(),
+            };
+
+        let expose_provenance = match blutgang_table.get("expose_provenance") {
+            Some(expose_provenance) => {
+                expose_provenance
+                    .as_bool()
+                    .expect("\x1b[31mErr:\x1b[0m Could not parse expose_provenance as bool!")
+            }
+            None => false,
+        };
+
+        let unfinalized_ttl = match blutgang_table.get("unfinalized_ttl") {
+            Some(unfinalized_ttl) => {
+                unfinalized_ttl
+                    .as_integer()
+                    .expect("\x1b[31mErr:\x1b[0m Could not parse unfinalized_ttl as int!")
+                    as u64
+            }
+            None => Settings::default().unfinalized_ttl,
+        };
+
+        let upstream_batch_size = match blutgang_table.get("upstream_batch_size") {
+            Some(upstream_batch_size) => {
+                upstream_batch_size
+                    .as_integer()
+                    .expect("\x1b[31mErr:\x1b[0m Could not parse upstream_batch_size as int!")
+                    as usize
+            }
+            None => Settings::default().upstream_batch_size,
+        };
+        let upstream_max_in_flight = match blutgang_table.get("upstream_max_in_flight") {
+            Some(upstream_max_in_flight) => {
+                upstream_max_in_flight
+                    .as_integer()
+                    .expect("\x1b[31mErr:\x1b[0m Could not parse upstream_max_in_flight as int!")
+                    as usize
+            }
+            None => Settings::default().upstream_max_in_flight,
+        };
+        let upstream_max_queued = match blutgang_table.get("upstream_max_queued") {
+            Some(upstream_max_queued) => {
+                upstream_max_queued
+                    .as_integer()
+                    .expect("\x1b[31mErr:\x1b[0m Could not parse upstream_max_queued as int!")
+                    as usize
+            }
+            None => Settings::default().upstream_max_queued,
+        };
+        let upstream_batch_window = match blutgang_table.get("upstream_batch_window") {
+            Some(upstream_batch_window) => {
+                upstream_batch_window
+                    .as_integer()
+                    .expect("\x1b[31mErr:\x1b[0m Could not parse upstream_batch_window as int!")
+                    as u64
+            }
+            None => Settings::default().upstream_batch_window,
+        };
+        let upstream_batch_failure = match blutgang_table.get("upstream_batch_failure") {
+            Some(upstream_batch_failure) => {
+                let upstream_batch_failure = upstream_batch_failure
+                    .as_str()
+                    .expect("\x1b[31mErr:\x1b[0m Could not parse upstream_batch_failure as str!");
+                BatchFailure::from_config(upstream_batch_failure).expect(
+                    "\x1b[31mErr:\x1b[0m upstream_batch_failure must be one of retry/error/fail!",
+                )
+            }
+            None => Settings::default().upstream_batch_failure,
+        };
+
+        // Optional, `latency`, `affinity` or `cheapest` with an optional `max_latency` ceiling
+        let max_latency = match blutgang_table.get("max_latency") {
+            Some(max_latency) => {
+                max_latency
+                    .as_integer()
+                    .expect("\x1b[31mErr:\x1b[0m Could not parse max_latency as int!")
+                    as f64
+            }
+            None => 0.0,
+        };
+        let strategy = match blutgang_table.get("strategy") {
+            Some(strategy) => {
+                let strategy = strategy
+                    .as_str()
+                    .expect("\x1b[31mErr:\x1b[0m Could not parse strategy as str!");
+                Strategy::from_config(strategy, max_latency).expect(
+                    "\x1b[31mErr:\x1b[0m strategy must be one of latency/cheapest/affinity/weighted_latency!",
+                )
+            }
+            None => Settings::default().strategy,
+        };
+
+        let negative_cache_ttl = match blutgang_table.get("negative_cache_ttl") {
+            Some(negative_cache_ttl) => {
+                negative_cache_ttl
+                    .as_integer()
+                    .expect("\x1b[31mErr:\x1b[0m Could not parse negative_cache_ttl as int!")
+                    as u64
+            }
+            None => Settings::default().negative_cache_ttl,
+        };
+
+        let read_your_writes = match blutgang_table.get("read_your_writes") {
+            Some(read_your_writes) => {
+                read_your_writes
+                    .as_bool()
+                    .expect("\x1b[31mErr:\x1b[0m Could not parse read_your_writes as bool!")
+            }
+            None => Settings::default().read_your_writes,
+        };
+
+        let static_responses = match blutgang_table.get("static_responses") {
+            Some(static_responses) => {
+                static_responses
+                    .as_bool()
+                    .expect("\x1b[31mErr:\x1b[0m Could not parse static_responses as bool!")
+            }
+            None => Settings::default().static_responses,
+        };
+
+        let coalesce_window = match blutgang_table.get("coalesce_window") {
+            Some(coalesce_window) => {
+                coalesce_window
+                    .as_integer()
+                    .expect("\x1b[31mErr:\x1b[0m Could not parse coalesce_window as int!")
+                    as u64
+            }
+            None => Settings::default().coalesce_window,
+        };
+
+        let single_flight = match blutgang_table.get("single_flight") {
+            Some(single_flight) => {
+                single_flight
+                    .as_bool()
+                    .expect("\x1b[31mErr:\x1b[0m Could not parse single_flight as bool!")
+            }
+            None => Settings::default().single_flight,
+        };
+
+        let hedge_delay = blutgang_table.get("hedge_delay").map(|hedge_delay| {
+            HedgeDelay::from_config(hedge_delay).expect(
+                "\x1b[31mErr:\x1b[0m Could not parse hedge_delay, expected ms or a quantile like \"p95\"!",
+            )
+        });
+
+        let tx_queue = match blutgang_table.get("tx_queue") {
+            Some(tx_queue) => {
+                tx_queue
+                    .as_bool()
+                    .expect("\x1b[31mErr:\x1b[0m Could not parse tx_queue as bool!")
+            }
+            None => Settings::default().tx_queue,
+        };
+
+        let broadcast_transactions = match blutgang_table.get("broadcast_transactions") {
+            Some(broadcast_transactions) => {
+                broadcast_transactions
+                    .as_bool()
+                    .expect("\x1b[31mErr:\x1b[0m Could not parse broadcast_transactions as bool!")
+            }
+            None => Settings::default().broadcast_transactions,
+        };
+
+        let cache_compression = match blutgang_table.get("cache_compression") {
+            Some(cache_compression) => {
+                cache_compression
+                    .as_integer()
+                    .expect("\x1b[31mErr:\x1b[0m Could not parse cache_compression as int!")
+                    as i32
+            }
+            None => Settings::default().cache_compression,
+        };
+        if !(0..=22).contains(&cache_compression) {
+            panic!("\x1b[31mErr:\x1b[0m cache_compression must be between 0 and 22!");
+        }
+
+        let cache_dictionary_interval = match blutgang_table.get("cache_dictionary_interval") {
+            Some(cache_dictionary_interval) => {
+                cache_dictionary_interval
+                    .as_integer()
+                    .expect("\x1b[31mErr:\x1b[0m Could not parse cache_dictionary_interval as int!")
+                    as u64
+            }
+            None => Settings::default().cache_dictionary_interval,
+        };
+        if cache_dictionary_interval > 0 && cache_compression == 0 {
+            println!(
+                "\x1b[93mWrn:\x1b[0m cache_dictionary_interval has no effect without cache_compression!"
+            );
+        }
+
+        let cache_dedup = match blutgang_table.get("cache_dedup") {
+            Some(cache_dedup) => {
+                cache_dedup
+                    .as_bool()
+                    .expect("\x1b[31mErr:\x1b[0m Could not parse cache_dedup as bool!")
+            }
+            None => Settings::default().cache_dedup,
+        };
+
+        let cache_max_entries = match blutgang_table.get("cache_max_entries") {
+            Some(cache_max_entries) => {
+                cache_max_entries
+                    .as_integer()
+                    .expect("\x1b[31mErr:\x1b[0m Could not parse cache_max_entries as int!")
+                    as usize
+            }
+            None => Settings::default().cache_max_entries,
+        };
+
+        let cache_max_size = match blutgang_table.get("cache_max_size") {
+            Some(cache_max_size) => {
+                cache_max_size
+                    .as_integer()
+                    .expect("\x1b[31mErr:\x1b[0m Could not parse cache_max_size as int!")
+                    as u64
+            }
+            None => Settings::default().cache_max_size,
+        };
+
+        let cache_admission = match blutgang_table.get("cache_admission") {
+            Some(cache_admission) => {
+                let cache_admission = cache_admission
+                    .as_str()
+                    .expect("\x1b[31mErr:\x1b[0m Could not parse cache_admission as str!");
+                Admission::from_config(cache_admission)
+                    .expect("\x1b[31mErr:\x1b[0m cache_admission must be one of tinylfu/lru!")
+            }
+            None => Settings::default().cache_admission,
+        };
+
+        let cache_admission_window = match blutgang_table.get("cache_admission_window") {
+            Some(cache_admission_window) => {
+                cache_admission_window
+                    .as_float()
+                    .expect("\x1b[31mErr:\x1b[0m Could not parse cache_admission_window as float!")
+            }
+            None => Settings::default().cache_admission_window,
+        };
+        if !(0.0..1.0).contains(&cache_admission_window) {
+            panic!("\x1b[31mErr:\x1b[0m cache_admission_window must be between 0 and 1!");
+        }
+
+        let prewarm_connections = match blutgang_table.get("prewarm_connections") {
+            Some(prewarm_connections) => {
+                prewarm_connections
+                    .as_integer()
+                    .expect("\x1b[31mErr:\x1b[0m Could not parse prewarm_connections as int!")
+                    as usize
+            }
+            None => Settings::default().prewarm_connections,
+        };
+
+        let prewarm_interval = match blutgang_table.get("prewarm_interval") {
+            Some(prewarm_interval) => {
+                prewarm_interval
+                    .as_integer()
+                    .expect("\x1b[31mErr:\x1b[0m Could not parse prewarm_interval as int!")
+                    as u64
+            }
+            None => Settings::default().prewarm_interval,
+        };
+
+        let handshake_interval = match blutgang_table.get("handshake_interval") {
+            Some(handshake_interval) => {
+                handshake_interval
+                    .as_integer()
+                    .expect("\x1b[31mErr:\x1b[0m Could not parse handshake_interval as int!")
+                    as u64
+            }
+            None => Settings::default().handshake_interval,
+        };
+
+        let fee_oracle_interval = match blutgang_table.get("fee_oracle_interval") {
+            Some(fee_oracle_interval) => {
+                fee_oracle_interval
+                    .as_integer()
+                    .expect("\x1b[31mErr:\x1b[0m Could not parse fee_oracle_interval as int!")
+                    as u64
+            }
+            None => Settings::default().fee_oracle_interval,
+        };
+
+        let fee_oracle_blocks = match blutgang_table.get("fee_oracle_blocks") {
+            Some(fee_oracle_blocks) => {
+                fee_oracle_blocks
+                    .as_integer()
+                    .expect("\x1b[31mErr:\x1b[0m Could not parse fee_oracle_blocks as int!")
+                    as usize
+            }
+            None => Settings::default().fee_oracle_blocks,
+        };
+
+        let track_inclusion = match blutgang_table.get("track_inclusion") {
+            Some(track_inclusion) => {
+                track_inclusion
+                    .as_bool()
+                    .expect("\x1b[31mErr:\x1b[0m Could not parse track_inclusion as bool!")
+            }
+            None => Settings::default().track_inclusion,
+        };
+
+        let metrics = match blutgang_table.get("metrics") {
+            Some(metrics) => {
+                metrics
+                    .as_bool()
+                    .expect("\x1b[31mErr:\x1b[0m Could not parse metrics as bool!")
+            }
+            None => Settings::default().metrics,
+        };
+
+        let top_talkers = match blutgang_table.get("top_talkers") {
+            Some(top_talkers) => {
+                top_talkers
+                    .as_bool()
+                    .expect("\x1b[31mErr:\x1b[0m Could not parse top_talkers as bool!")
+            }
+            None => Settings::default().top_talkers,
+        };
+
+        let consumer_cache_stats = match blutgang_table.get("consumer_cache_stats") {
+            Some(consumer_cache_stats) => {
+                consumer_cache_stats
+                    .as_bool()
+                    .expect("\x1b[31mErr:\x1b[0m Could not parse consumer_cache_stats as bool!")
+            }
+            None => Settings::default().consumer_cache_stats,
+        };
+
+        let top_talkers_max_clients = match blutgang_table.get("top_talkers_max_clients") {
+            Some(top_talkers_max_clients) => {
+                top_talkers_max_clients
+                    .as_integer()
+                    .expect("\x1b[31mErr:\x1b[0m Could not parse top_talkers_max_clients as int!")
+                    as usize
+            }
+            None => Settings::default().top_talkers_max_clients,
+        };
+
+        let top_talkers_export = match blutgang_table.get("top_talkers_export") {
+            Some(top_talkers_export) => {
+                top_talkers_export
+                    .as_integer()
+                    .expect("\x1b[31mErr:\x1b[0m Could not parse top_talkers_export as int!")
+                    as usize
+            }
+            None => Settings::default().top_talkers_export,
+        };
+
+        let drift_interval = match blutgang_table.get("drift_interval") {
+            Some(drift_interval) => {
+                drift_interval
+                    .as_integer()
+                    .expect("\x1b[31mErr:\x1b[0m Could not parse drift_interval as int!")
+                    as u64
+            }
+            None => Settings::default().drift_interval,
+        };
+
+        let reload_interval = match blutgang_table.get("reload_interval") {
+            Some(reload_interval) => {
+                reload_interval
+                    .as_integer()
+                    .expect("\x1b[31mErr:\x1b[0m Could not parse reload_interval as int!")
+                    as u64
+            }
+            None => Settings::default().reload_interval,
+        };
+
+        let drift_alert_webhook = match blutgang_table.get("drift_alert_webhook") {
+            Some(drift_alert_webhook) => {
+                let drift_alert_webhook = drift_alert_webhook
+                    .as_str()
+                    .expect("\x1b[31mErr:\x1b[0m Could not parse drift_alert_webhook as str!");
+                (!drift_alert_webhook.is_empty()).then(|| drift_alert_webhook.to_string())
+            }
+            None => Settings::default().drift_alert_webhook,
+        };
+
+        let ip_preference = match blutgang_table.get("ip_preference") {
+            Some(ip_preference) => parse_ip_preference(ip_preference),
+            None => Settings::default().ip_preference,
+        };
 
-        let ma_length = blutgang_table
-            .get("ma_length")
-            .expect("\x1b[31mErr:\x1b[0m Missing ma_length!")
-            .as_integer()
-            .expect("\x1b[31mErr:\x1b[0m Could not parse ma_length as int!")
-            as f64;
+        let mut log_filter = match blutgang_table.get("log_level") {
+            Some(log_level) => {
+                let log_level = log_level
+                    .as_str()
+                    .expect("\x1b[31mErr:\x1b[0m Could not parse log_level as str!");
+                LogFilter::parse(log_level)
+                    .expect("\x1b[31mErr:\x1b[0m log_level must look like `info,health=debug`!")
+            }
+            None => Settings::default().log_filter,
+        };
+        if let Some(log_sample_rate) = blutgang_table.get("log_sample_rate") {
+            let log_sample_rate = log_sample_rate
+                .as_float()
+                .or_else(|| log_sample_rate.as_integer().map(|rate| rate as f64))
+                .expect("\x1b[31mErr:\x1b[0m Could not parse log_sample_rate as float!");
+            if !(0.0..=1.0).contains(&log_sample_rate) {
+                panic!("\x1b[31mErr:\x1b[0m log_sample_rate must be between 0 and 1!");
+            }
+            log_filter.sample_rate = log_sample_rate;
+        }
+        if let Some(log_format) = blutgang_table.get("log_format") {
+            log_filter.format = log_format
+                .as_str()
+                .and_then(LogFormat::from_config)
+                .expect("\x1b[31mErr:\x1b[0m log_format must be either `text` or `json`!");
+        }
 
-        let health_check = blutgang_table
-            .get("health_check")
-            .expect("\x1b[31mErr:\x1b[0m Missing health_check toggle!")
-            .as_bool()
-            .expect("\x1b[31mErr:\x1b[0m Could not parse health_check as bool!");
         let ttl = blutgang_table
             .get("ttl")
             .expect("\x1b[31mErr:\x1b[0m Missing ttl!")
@@ -232,12 +1617,27 @@ impl Settings {
             .print_profile_on_drop(print_profile)
             .use_compression(compression);
 
+        // Has to come before the RPCs, since it's what they get probed with by default
+        let mut probes = ProbeSettings::default();
+        if let Some(probes_table) = parsed_toml.get("probes") {
+            let probes_table = probes_table
+                .as_table()
+                .expect("\x1b[31mErr:\x1b[0m Could not parse probes table!");
+
+            if let Some(enabled) = probes_table.get("enabled") {
+                probes.enabled = enabled
+                    .as_bool()
+                    .expect("\x1b[31mErr:\x1b[0m Could not parse probes enabled as bool!");
+            }
+            probes.defaults = parse_probe_config(probes_table, "", ProbeConfig::default());
+        }
+
         // Parse all the other tables as RPCs and put them in a Vec<Rpc>
         //
         // Sort RPCs by latency if enabled
         let mut rpc_list: Vec<Rpc> = Vec::new();
         for table_name in table_names {
-            if table_name != "blutgang" && table_name != "sled" && table_name != "admin" {
+            if !RESERVED_TABLES.contains(&table_name.as_str()) {
                 let rpc_table = parsed_toml.get(table_name).unwrap().as_table().unwrap();
 
                 let max_consecutive = rpc_table
@@ -253,11 +1653,190 @@ impl Settings {
                     .expect("\x1b[31mErr:\x1b[0m Could not parse URL from a RPC as str!")
                     .to_string();
 
-                let rpc = Rpc::new(url, max_consecutive, ma_length);
+                let mut rpc = Rpc::new(url, max_consecutive, ma_length);
+                rpc.name = table_name.to_string();
+                rpc.status.window = ma_window;
+                rpc.protocol = chain.map(|chain| chain.protocol()).unwrap_or_default();
+                if let Some(ws_url) = rpc_table.get("ws_url") {
+                    let ws_url = ws_url
+                        .as_str()
+                        .expect("\x1b[31mErr:\x1b[0m Could not parse ws_url as str!");
+                    if !ws_url.is_empty() {
+                        rpc.ws_url = Some(ws_url.to_string());
+                    }
+                }
+                if let Some(state_overrides) = rpc_table.get("state_overrides") {
+                    rpc.supports_state_overrides = state_overrides
+                        .as_bool()
+                        .expect("\x1b[31mErr:\x1b[0m Could not parse state_overrides as bool!");
+                }
+                if let Some(archive) = rpc_table.get("archive") {
+                    rpc.archive = archive
+                        .as_bool()
+                        .expect("\x1b[31mErr:\x1b[0m Could not parse archive as bool!");
+                }
+                if let Some(tags) = rpc_table.get("tags") {
+                    rpc.tags = parse_string_array(tags)
+                        .expect("\x1b[31mErr:\x1b[0m Could not parse tags as an array of strings!");
+                }
+                if let Some(cost) = rpc_table.get("cost") {
+                    rpc.cost = cost
+                        .as_float()
+                        .or_else(|| cost.as_integer().map(|cost| cost as f64))
+                        .expect("\x1b[31mErr:\x1b[0m Could not parse cost as a number!");
+                }
+                if let Some(sla_latency) = rpc_table.get("sla_latency") {
+                    let percentile = match rpc_table.get("sla_percentile") {
+                        Some(percentile) => percentile
+                            .as_float()
+                            .or_else(|| percentile.as_integer().map(|p| p as f64))
+                            .expect(
+                                "\x1b[31mErr:\x1b[0m Could not parse sla_percentile as a number!",
+                            ),
+                        None => 95.0,
+                    };
+                    if percentile <= 0.0 || percentile > 100.0 {
+                        panic!("\x1b[31mErr:\x1b[0m sla_percentile must be between 0 and 100!");
+                    }
+                    rpc.sla = Some(Sla {
+                        percentile,
+                        max_latency: Duration::from_millis(
+                            sla_latency
+                                .as_integer()
+                                .expect("\x1b[31mErr:\x1b[0m Could not parse sla_latency as int!")
+                                as u64,
+                        ),
+                    });
+                }
+                rpc.prewarm = match rpc_table.get("prewarm_connections") {
+                    Some(prewarm) => {
+                        prewarm.as_integer().expect(
+                            "\x1b[31mErr:\x1b[0m Could not parse prewarm_connections as int!",
+                        ) as usize
+                    }
+                    None => prewarm_connections,
+                };
+                let rpc_ip_preference = match rpc_table.get("ip_preference") {
+                    Some(ip_preference) => parse_ip_preference(ip_preference),
+                    None => ip_preference,
+                };
+                if rpc_ip_preference != IpPreference::default() {
+                    rpc.set_ip_preference(rpc_ip_preference);
+                }
+                if let Some(max_per_second) = rpc_table.get("max_per_second") {
+                    rpc.max_per_second = parse_rate(max_per_second, "max_per_second");
+                }
+                rpc.auth = parse_rpc_auth(rpc_table);
+                rpc.signer = match (rpc_table.get("signer"), rpc_table.get("jwt_secret")) {
+                    (Some(signer), None) => Some(parse_rpc_signer(signer)),
+                    (None, Some(jwt_secret)) => Some(parse_rpc_jwt_secret(jwt_secret)),
+                    (None, None) => None,
+                    (Some(_), Some(_)) => {
+                        panic!("\x1b[31mErr:\x1b[0m An RPC can't have a signer and a jwt_secret!")
+                    }
+                };
+                if probes.enabled {
+                    let config = parse_probe_config(rpc_table, "probe_", probes.defaults.clone());
+                    rpc.probes = (!config.probes.is_empty()).then_some(config);
+                }
                 rpc_list.push(rpc);
             }
         }
 
+        // Optional `[schedule]` table
+        let schedule = match parsed_toml.get("schedule") {
+            Some(schedule_table) => {
+                parse_schedule(
+                    schedule_table
+                        .as_table()
+                        .expect("\x1b[31mErr:\x1b[0m Could not parse schedule table!"),
+                )
+            }
+            None => Vec::new(),
+        };
+        for rule in &schedule {
+            for name in rule.rpc_names() {
+                if !rpc_list.iter().any(|rpc| &rpc.name == name) {
+                    panic!(
+                        "\x1b[31mErr:\x1b[0m Schedule rule {} uses {}, but no RPC with that name exists!",
+                        rule.name, name
+                    );
+                }
+            }
+        }
+
+        // Optional `[rewrite]` table
+        let rewrite = match parsed_toml.get("rewrite") {
+            Some(rewrite_table) => {
+                parse_rewrites(
+                    rewrite_table
+                        .as_table()
+                        .expect("\x1b[31mErr:\x1b[0m Could not parse rewrite table!"),
+                )
+            }
+            None => Vec::new(),
+        };
+
+        // Optional `[cache_rules]` table
+        let cache_rules = match parsed_toml.get("cache_rules") {
+            Some(cache_rules_table) => {
+                parse_cache_rules(
+                    cache_rules_table
+                        .as_table()
+                        .expect("\x1b[31mErr:\x1b[0m Could not parse cache_rules table!"),
+                )
+            }
+            None => CacheRules::default(),
+        };
+
+        // Optional `[routing]` table
+        let routing = match parsed_toml.get("routing") {
+            Some(routing_table) => {
+                parse_routing(
+                    routing_table
+                        .as_table()
+                        .expect("\x1b[31mErr:\x1b[0m Could not parse routing table!"),
+                )
+            }
+            None => RoutingRules::default(),
+        };
+        for tag in routing.tags() {
+            if !rpc_list.iter().any(|rpc| rpc.tags.contains(tag)) {
+                println!(
+                    "\x1b[93mWrn:\x1b[0m No RPC is tagged {}, requests routed to it will fail!",
+                    tag
+                );
+            }
+        }
+
+        // Make sure every pinned RPC exists so we don't find out at request time
+        for (method, name) in &pin {
+            if !rpc_list.iter().any(|rpc| &rpc.name == name) {
+                panic!(
+                    "\x1b[31mErr:\x1b[0m {} is pinned to {}, but no RPC with that name exists!",
+                    method, name
+                );
+            }
+        }
+
+        for (namespace, names) in &namespace_pools {
+            if names.is_empty() {
+                panic!(
+                    "\x1b[31mErr:\x1b[0m The pool of the {} namespace has no RPCs!",
+                    namespace
+                );
+            }
+            if let Some(name) = names
+                .iter()
+                .find(|name| !rpc_list.iter().any(|rpc| &rpc.name == *name))
+            {
+                panic!(
+                    "\x1b[31mErr:\x1b[0m {} is in the pool of the {} namespace, but no RPC with that name exists!",
+                    name, namespace
+                );
+            }
+        }
+
         // Admin namespace things
         let admin_table = parsed_toml
             .get("admin")
@@ -298,12 +1877,57 @@ impl Settings {
                 String::new()
             };
 
+            // Optional `[admin.keys.<name>]` tables with a `key` and a `role`
+            let mut keys = Vec::new();
+            if let Some(keys_table) = admin_table.get("keys") {
+                let keys_table = keys_table
+                    .as_table()
+                    .expect("\x1b[31mErr:\x1b[0m Could not parse admin keys as table!");
+                for (name, key_table) in keys_table {
+                    let key = key_table
+                        .get("key")
+                        .and_then(|key| key.as_str())
+                        .unwrap_or_else(|| {
+                            panic!("\x1b[31mErr:\x1b[0m Missing key for admin key {}!", name)
+                        });
+                    let role = key_table
+                        .get("role")
+                        .and_then(|role| role.as_str())
+                        .and_then(AdminRole::from_config)
+                        .unwrap_or_else(|| {
+                            panic!(
+                                "\x1b[31mErr:\x1b[0m Role of admin key {} must be read/write!",
+                                name
+                            )
+                        });
+
+                    keys.push(AdminKey {
+                        name: name.to_string(),
+                        key: key.to_string(),
+                        role,
+                    });
+                }
+            }
+
+            // Empty path means no audit log file
+            let audit_log = admin_table
+                .get("audit_log")
+                .map(|audit_log| {
+                    audit_log
+                        .as_str()
+                        .expect("\x1b[31mErr:\x1b[0m Could not parse audit_log as str!")
+                })
+                .filter(|audit_log| !audit_log.is_empty())
+                .map(PathBuf::from);
+
             AdminSettings {
                 enabled,
                 address: address.parse::<SocketAddr>().unwrap(),
                 readonly,
                 jwt,
                 key: DecodingKey::from_secret(key.as_bytes()),
+                keys,
+                audit_log,
             }
         } else {
             AdminSettings {
@@ -312,10 +1936,661 @@ impl Settings {
                 readonly: false,
                 jwt: false,
                 key: DecodingKey::from_secret(b""),
+                keys: Vec::new(),
+                audit_log: None,
+            }
+        };
+
+        // Hardened mode, everything is optional and falls back to the defaults
+        let mut hardened = HardenedSettings::default();
+        if let Some(hardened_table) = parsed_toml.get("hardened") {
+            let hardened_table = hardened_table
+                .as_table()
+                .expect("\x1b[31mErr:\x1b[0m Could not parse hardened table!");
+
+            if let Some(enabled) = hardened_table.get("enabled") {
+                hardened.enabled = enabled
+                    .as_bool()
+                    .expect("\x1b[31mErr:\x1b[0m Could not parse hardened enabled as bool!");
+            }
+            if let Some(denied_namespaces) = hardened_table.get("denied_namespaces") {
+                hardened.denied_namespaces = parse_string_array(denied_namespaces).expect(
+                    "\x1b[31mErr:\x1b[0m Could not parse denied_namespaces as array of str!",
+                );
+            }
+            if let Some(max_body_size) = hardened_table.get("max_body_size") {
+                hardened.max_body_size = max_body_size
+                    .as_integer()
+                    .expect("\x1b[31mErr:\x1b[0m Could not parse max_body_size as int!")
+                    as usize;
+            }
+            if let Some(requests_per_second) = hardened_table.get("requests_per_second") {
+                hardened.requests_per_second = requests_per_second
+                    .as_integer()
+                    .expect("\x1b[31mErr:\x1b[0m Could not parse requests_per_second as int!")
+                    as f64;
+            }
+            if let Some(burst) = hardened_table.get("burst") {
+                hardened.burst = burst
+                    .as_integer()
+                    .expect("\x1b[31mErr:\x1b[0m Could not parse burst as int!")
+                    as f64;
+            }
+            if let Some(daily_quota) = hardened_table.get("daily_quota") {
+                hardened.daily_quota = daily_quota
+                    .as_integer()
+                    .expect("\x1b[31mErr:\x1b[0m Could not parse daily_quota as int!")
+                    as u64;
+            }
+            if let Some(allowed_keys) = hardened_table.get("allowed_keys") {
+                hardened.allowed_keys = parse_string_array(allowed_keys)
+                    .expect("\x1b[31mErr:\x1b[0m Could not parse allowed_keys as array of str!");
+            }
+        }
+
+        let mut slo = SloSettings::default();
+        if let Some(slo_table) = parsed_toml.get("slo") {
+            let slo_table = slo_table
+                .as_table()
+                .expect("\x1b[31mErr:\x1b[0m Could not parse slo table!");
+
+            if let Some(enabled) = slo_table.get("enabled") {
+                slo.enabled = enabled
+                    .as_bool()
+                    .expect("\x1b[31mErr:\x1b[0m Could not parse slo enabled as bool!");
+            }
+            if let Some(target) = slo_table.get("target") {
+                slo.target = target
+                    .as_float()
+                    .expect("\x1b[31mErr:\x1b[0m Could not parse slo target as float!");
+                if !(0.0..1.0).contains(&slo.target) {
+                    panic!("\x1b[31mErr:\x1b[0m slo target must be between 0 and 1!");
+                }
+            }
+            if let Some(window_days) = slo_table.get("window_days") {
+                slo.window_days = window_days
+                    .as_integer()
+                    .expect("\x1b[31mErr:\x1b[0m Could not parse window_days as int!")
+                    as u64;
+            }
+            if let Some(burn_rate_threshold) = slo_table.get("burn_rate_threshold") {
+                slo.burn_rate_threshold = burn_rate_threshold
+                    .as_float()
+                    .expect("\x1b[31mErr:\x1b[0m Could not parse burn_rate_threshold as float!");
+            }
+            if let Some(alert_webhook) = slo_table.get("alert_webhook") {
+                let alert_webhook = alert_webhook
+                    .as_str()
+                    .expect("\x1b[31mErr:\x1b[0m Could not parse alert_webhook as str!");
+                if !alert_webhook.is_empty() {
+                    slo.alert_webhook = Some(alert_webhook.to_string());
+                }
+            }
+        }
+
+        let mut anomaly = AnomalySettings::default();
+        if let Some(anomaly_table) = parsed_toml.get("anomaly") {
+            let anomaly_table = anomaly_table
+                .as_table()
+                .expect("\x1b[31mErr:\x1b[0m Could not parse anomaly table!");
+
+            if let Some(enabled) = anomaly_table.get("enabled") {
+                anomaly.enabled = enabled
+                    .as_bool()
+                    .expect("\x1b[31mErr:\x1b[0m Could not parse anomaly enabled as bool!");
+            }
+            if let Some(window) = anomaly_table.get("window") {
+                anomaly.window = Duration::from_secs(
+                    window
+                        .as_integer()
+                        .expect("\x1b[31mErr:\x1b[0m Could not parse anomaly window as int!")
+                        as u64,
+                );
+            }
+            if let Some(min_requests) = anomaly_table.get("min_requests") {
+                anomaly.min_requests = min_requests
+                    .as_integer()
+                    .expect("\x1b[31mErr:\x1b[0m Could not parse min_requests as int!")
+                    as u64;
+            }
+            if let Some(volume_factor) = anomaly_table.get("volume_factor") {
+                anomaly.volume_factor = volume_factor
+                    .as_float()
+                    .expect("\x1b[31mErr:\x1b[0m Could not parse volume_factor as float!");
+            }
+            if let Some(mix_threshold) = anomaly_table.get("mix_threshold") {
+                anomaly.mix_threshold = mix_threshold
+                    .as_float()
+                    .expect("\x1b[31mErr:\x1b[0m Could not parse mix_threshold as float!");
+                if !(0.0..=1.0).contains(&anomaly.mix_threshold) {
+                    panic!("\x1b[31mErr:\x1b[0m mix_threshold must be between 0 and 1!");
+                }
+            }
+            if let Some(throttle) = anomaly_table.get("throttle") {
+                anomaly.throttle = throttle
+                    .as_bool()
+                    .expect("\x1b[31mErr:\x1b[0m Could not parse throttle as bool!");
+            }
+            if let Some(throttle_duration) = anomaly_table.get("throttle_duration") {
+                anomaly.throttle_duration = Duration::from_secs(
+                    throttle_duration
+                        .as_integer()
+                        .expect("\x1b[31mErr:\x1b[0m Could not parse throttle_duration as int!")
+                        as u64,
+                );
+            }
+            if let Some(alert_webhook) = anomaly_table.get("alert_webhook") {
+                let alert_webhook = alert_webhook
+                    .as_str()
+                    .expect("\x1b[31mErr:\x1b[0m Could not parse alert_webhook as str!");
+                if !alert_webhook.is_empty() {
+                    anomaly.alert_webhook = Some(alert_webhook.to_string());
+                }
+            }
+        }
+
+        let mut sla = SlaSettings::default();
+        if let Some(sla_table) = parsed_toml.get("sla") {
+            let sla_table = sla_table
+                .as_table()
+                .expect("\x1b[31mErr:\x1b[0m Could not parse sla table!");
+
+            if let Some(window) = sla_table.get("window") {
+                sla.window = Duration::from_secs(
+                    window
+                        .as_integer()
+                        .expect("\x1b[31mErr:\x1b[0m Could not parse sla window as int!")
+                        as u64,
+                );
+            }
+            if let Some(min_samples) = sla_table.get("min_samples") {
+                sla.min_samples = min_samples
+                    .as_integer()
+                    .expect("\x1b[31mErr:\x1b[0m Could not parse min_samples as int!")
+                    as usize;
+            }
+            if let Some(share) = sla_table.get("share") {
+                sla.share = share
+                    .as_float()
+                    .expect("\x1b[31mErr:\x1b[0m Could not parse sla share as float!");
+                if !(0.0..=1.0).contains(&sla.share) {
+                    panic!("\x1b[31mErr:\x1b[0m sla share must be between 0 and 1!");
+                }
+            }
+            if let Some(alert_webhook) = sla_table.get("alert_webhook") {
+                let alert_webhook = alert_webhook
+                    .as_str()
+                    .expect("\x1b[31mErr:\x1b[0m Could not parse alert_webhook as str!");
+                if !alert_webhook.is_empty() {
+                    sla.alert_webhook = Some(alert_webhook.to_string());
+                }
+            }
+        }
+
+        let mut watchdog = WatchdogSettings::default();
+        if let Some(watchdog_table) = parsed_toml.get("watchdog") {
+            let watchdog_table = watchdog_table
+                .as_table()
+                .expect("\x1b[31mErr:\x1b[0m Could not parse watchdog table!");
+
+            if let Some(enabled) = watchdog_table.get("enabled") {
+                watchdog.enabled = enabled
+                    .as_bool()
+                    .expect("\x1b[31mErr:\x1b[0m Could not parse watchdog enabled as bool!");
+            }
+            if let Some(interval) = watchdog_table.get("interval") {
+                watchdog.interval = Duration::from_secs(
+                    interval
+                        .as_integer()
+                        .expect("\x1b[31mErr:\x1b[0m Could not parse watchdog interval as int!")
+                        as u64,
+                );
+            }
+            if let Some(head_timeout) = watchdog_table.get("head_timeout") {
+                watchdog.head_timeout = Duration::from_secs(
+                    head_timeout
+                        .as_integer()
+                        .expect("\x1b[31mErr:\x1b[0m Could not parse head_timeout as int!")
+                        as u64,
+                );
+            }
+            if let Some(actions) = watchdog_table.get("actions") {
+                watchdog.actions = actions
+                    .as_array()
+                    .expect("\x1b[31mErr:\x1b[0m Could not parse watchdog actions as array!")
+                    .iter()
+                    .map(|action| {
+                        action.as_str().and_then(WatchdogAction::from_config).expect(
+                            "\x1b[31mErr:\x1b[0m watchdog actions must be log/alert/unready/restart!",
+                        )
+                    })
+                    .collect();
+            }
+            if let Some(alert_webhook) = watchdog_table.get("alert_webhook") {
+                let alert_webhook = alert_webhook
+                    .as_str()
+                    .expect("\x1b[31mErr:\x1b[0m Could not parse alert_webhook as str!");
+                if !alert_webhook.is_empty() {
+                    watchdog.alert_webhook = Some(alert_webhook.to_string());
+                }
+            }
+            if watchdog.actions.contains(&WatchdogAction::Alert) && watchdog.alert_webhook.is_none()
+            {
+                panic!("\x1b[31mErr:\x1b[0m The alert watchdog action needs an alert_webhook!");
+            }
+        }
+
+        let mut divergence = DivergenceSettings::default();
+        if let Some(divergence_table) = parsed_toml.get("divergence") {
+            let divergence_table = divergence_table
+                .as_table()
+                .expect("\x1b[31mErr:\x1b[0m Could not parse divergence table!");
+
+            if let Some(enabled) = divergence_table.get("enabled") {
+                divergence.enabled = enabled
+                    .as_bool()
+                    .expect("\x1b[31mErr:\x1b[0m Could not parse divergence enabled as bool!");
+            }
+            if let Some(interval) = divergence_table.get("interval") {
+                divergence.interval = Duration::from_secs(
+                    interval
+                        .as_integer()
+                        .expect("\x1b[31mErr:\x1b[0m Could not parse divergence interval as int!")
+                        as u64,
+                );
+            }
+            if let Some(depth) = divergence_table.get("depth") {
+                divergence.depth = depth
+                    .as_integer()
+                    .expect("\x1b[31mErr:\x1b[0m Could not parse divergence depth as int!")
+                    as u64;
+            }
+            if let Some(quarantine) = divergence_table.get("quarantine") {
+                divergence.quarantine = quarantine
+                    .as_bool()
+                    .expect("\x1b[31mErr:\x1b[0m Could not parse quarantine as bool!");
+            }
+            if let Some(alert_webhook) = divergence_table.get("alert_webhook") {
+                let alert_webhook = alert_webhook
+                    .as_str()
+                    .expect("\x1b[31mErr:\x1b[0m Could not parse alert_webhook as str!");
+                if !alert_webhook.is_empty() {
+                    divergence.alert_webhook = Some(alert_webhook.to_string());
+                }
+            }
+        }
+
+        let mut reorg = ReorgSettings::default();
+        if let Some(reorg_table) = parsed_toml.get("reorg") {
+            let reorg_table = reorg_table
+                .as_table()
+                .expect("\x1b[31mErr:\x1b[0m Could not parse reorg table!");
+
+            if let Some(enabled) = reorg_table.get("enabled") {
+                reorg.enabled = enabled
+                    .as_bool()
+                    .expect("\x1b[31mErr:\x1b[0m Could not parse reorg enabled as bool!");
+            }
+            if let Some(depth) = reorg_table.get("depth") {
+                reorg.depth = depth
+                    .as_integer()
+                    .expect("\x1b[31mErr:\x1b[0m Could not parse reorg depth as int!")
+                    as u64;
+            }
+            if let Some(tombstone_retention) = reorg_table.get("tombstone_retention") {
+                reorg.tombstone_retention = tombstone_retention
+                    .as_integer()
+                    .expect("\x1b[31mErr:\x1b[0m Could not parse reorg tombstone_retention as int!")
+                    as u64;
+            }
+        }
+
+        let mut quorum = QuorumSettings::default();
+        if let Some(quorum_table) = parsed_toml.get("quorum") {
+            let quorum_table = quorum_table
+                .as_table()
+                .expect("\x1b[31mErr:\x1b[0m Could not parse quorum table!");
+
+            if let Some(enabled) = quorum_table.get("enabled") {
+                quorum.enabled = enabled
+                    .as_bool()
+                    .expect("\x1b[31mErr:\x1b[0m Could not parse quorum enabled as bool!");
+            }
+            if let Some(methods) = quorum_table.get("methods") {
+                quorum.methods = parse_string_array(methods)
+                    .expect("\x1b[31mErr:\x1b[0m Could not parse quorum methods as array of str!");
+            }
+            if let Some(size) = quorum_table.get("size") {
+                quorum.size =
+                    size.as_integer().filter(|size| *size > 0).expect(
+                        "\x1b[31mErr:\x1b[0m Could not parse quorum size as a positive int!",
+                    ) as usize;
+            }
+        }
+
+        let mut websocket = WebsocketSettings::default();
+        if let Some(websocket_table) = parsed_toml.get("websocket") {
+            let websocket_table = websocket_table
+                .as_table()
+                .expect("\x1b[31mErr:\x1b[0m Could not parse websocket table!");
+
+            if let Some(enabled) = websocket_table.get("enabled") {
+                websocket.enabled = enabled
+                    .as_bool()
+                    .expect("\x1b[31mErr:\x1b[0m Could not parse websocket enabled as bool!");
+            }
+            if let Some(address) = websocket_table.get("address") {
+                websocket.address = address
+                    .as_str()
+                    .expect("\x1b[31mErr:\x1b[0m Could not parse websocket address as str!")
+                    .replace("localhost", "127.0.0.1")
+                    .parse::<SocketAddr>()
+                    .expect("\x1b[31mErr:\x1b[0m Invalid websocket address!");
+            }
+            if let Some(max_subscriptions) = websocket_table.get("max_subscriptions") {
+                websocket.max_subscriptions = max_subscriptions
+                    .as_integer()
+                    .expect("\x1b[31mErr:\x1b[0m Could not parse max_subscriptions as int!")
+                    as usize;
+            }
+        }
+
+        let mut backfill = BackfillSettings::default();
+        if let Some(backfill_table) = parsed_toml.get("backfill") {
+            let backfill_table = backfill_table
+                .as_table()
+                .expect("\x1b[31mErr:\x1b[0m Could not parse backfill table!");
+
+            if let Some(enabled) = backfill_table.get("enabled") {
+                backfill.enabled = enabled
+                    .as_bool()
+                    .expect("\x1b[31mErr:\x1b[0m Could not parse backfill enabled as bool!");
+            }
+            if let Some(rate) = backfill_table.get("rate") {
+                backfill.rate = parse_rate(rate, "rate");
+            }
+            if let Some(min_rate) = backfill_table.get("min_rate") {
+                backfill.min_rate = parse_rate(min_rate, "min_rate");
+            }
+            if backfill.min_rate <= 0.0 {
+                panic!("\x1b[31mErr:\x1b[0m Backfill min_rate must be above 0!");
+            }
+        }
+
+        let mut prometheus = PrometheusSettings::default();
+        if let Some(prometheus_table) = parsed_toml.get("prometheus") {
+            let prometheus_table = prometheus_table
+                .as_table()
+                .expect("\x1b[31mErr:\x1b[0m Could not parse prometheus table!");
+
+            if let Some(enabled) = prometheus_table.get("enabled") {
+                prometheus.enabled = enabled
+                    .as_bool()
+                    .expect("\x1b[31mErr:\x1b[0m Could not parse prometheus enabled as bool!");
+            }
+            if let Some(address) = prometheus_table.get("address") {
+                prometheus.address = address
+                    .as_str()
+                    .expect("\x1b[31mErr:\x1b[0m Could not parse prometheus address as str!")
+                    .replace("localhost", "127.0.0.1")
+                    .parse::<SocketAddr>()
+                    .expect("\x1b[31mErr:\x1b[0m Invalid prometheus address!");
+            }
+        }
+
+        let mut tls = TlsSettings::default();
+        if let Some(tls_table) = parsed_toml.get("tls") {
+            let tls_table = tls_table
+                .as_table()
+                .expect("\x1b[31mErr:\x1b[0m Could not parse tls table!");
+
+            if let Some(enabled) = tls_table.get("enabled") {
+                tls.enabled = enabled
+                    .as_bool()
+                    .expect("\x1b[31mErr:\x1b[0m Could not parse tls enabled as bool!");
+            }
+            if let Some(cert) = tls_table.get("cert") {
+                tls.cert = PathBuf::from(
+                    cert.as_str()
+                        .expect("\x1b[31mErr:\x1b[0m Could not parse tls cert as str!"),
+                );
+            }
+            if let Some(key) = tls_table.get("key") {
+                tls.key = PathBuf::from(
+                    key.as_str()
+                        .expect("\x1b[31mErr:\x1b[0m Could not parse tls key as str!"),
+                );
+            }
+            if let Some(reload_interval) = tls_table.get("reload_interval") {
+                tls.reload_interval = Duration::from_secs(
+                    reload_interval
+                        .as_integer()
+                        .expect("\x1b[31mErr:\x1b[0m Could not parse tls reload_interval as int!")
+                        as u64,
+                );
             }
+            if tls.enabled && (tls.cert.as_os_str().is_empty() || tls.key.as_os_str().is_empty()) {
+                panic!("\x1b[31mErr:\x1b[0m TLS needs both a cert and a key!");
+            }
+        }
+
+        let mut event_store = EventStoreSettings::default();
+        if let Some(event_store_table) = parsed_toml.get("event_store") {
+            let event_store_table = event_store_table
+                .as_table()
+                .expect("\x1b[31mErr:\x1b[0m Could not parse event_store table!");
+
+            if let Some(enabled) = event_store_table.get("enabled") {
+                event_store.enabled = enabled
+                    .as_bool()
+                    .expect("\x1b[31mErr:\x1b[0m Could not parse event_store enabled as bool!");
+            }
+            if let Some(retention) = event_store_table.get("retention") {
+                event_store.retention = Duration::from_secs(
+                    retention
+                        .as_integer()
+                        .expect("\x1b[31mErr:\x1b[0m Could not parse event_store retention as int!")
+                        as u64
+                        * 3600,
+                );
+            }
+        }
+
+        let mut standby = StandbySettings::default();
+        if let Some(standby_table) = parsed_toml.get("standby") {
+            let standby_table = standby_table
+                .as_table()
+                .expect("\x1b[31mErr:\x1b[0m Could not parse standby table!");
+
+            if let Some(enabled) = standby_table.get("enabled") {
+                standby.enabled = enabled
+                    .as_bool()
+                    .expect("\x1b[31mErr:\x1b[0m Could not parse standby enabled as bool!");
+            }
+            if let Some(tag) = standby_table.get("tag") {
+                standby.tag = tag
+                    .as_str()
+                    .expect("\x1b[31mErr:\x1b[0m Could not parse standby tag as str!")
+                    .to_string();
+            }
+            if let Some(interval) = standby_table.get("interval") {
+                standby.interval = Duration::from_secs(
+                    interval
+                        .as_integer()
+                        .expect("\x1b[31mErr:\x1b[0m Could not parse standby interval as int!")
+                        as u64,
+                );
+            }
+            if let Some(window) = standby_table.get("window") {
+                standby.window = window
+                    .as_integer()
+                    .expect("\x1b[31mErr:\x1b[0m Could not parse standby window as int!")
+                    as usize;
+            }
+        }
+
+        let mut clock = ClockSettings {
+            max_drift: default_max_drift(chain),
+            ..Default::default()
         };
+        if let Some(clock_table) = parsed_toml.get("clock") {
+            let clock_table = clock_table
+                .as_table()
+                .expect("\x1b[31mErr:\x1b[0m Could not parse clock table!");
+
+            if let Some(enabled) = clock_table.get("enabled") {
+                clock.enabled = enabled
+                    .as_bool()
+                    .expect("\x1b[31mErr:\x1b[0m Could not parse clock enabled as bool!");
+            }
+            if let Some(source) = clock_table.get("source") {
+                clock.source = source
+                    .as_str()
+                    .and_then(ClockSource::from_config)
+                    .expect("\x1b[31mErr:\x1b[0m clock source must be either `system` or `pool`!");
+            }
+            if let Some(max_drift) = clock_table.get("max_drift") {
+                clock.max_drift = Duration::from_secs(
+                    max_drift
+                        .as_integer()
+                        .expect("\x1b[31mErr:\x1b[0m Could not parse clock max_drift as int!")
+                        as u64,
+                );
+            }
+        }
+
+        let mut chains: Vec<ChainRoute> = Vec::new();
+        if let Some(chains_table) = parsed_toml.get("chains") {
+            let chains_table = chains_table
+                .as_table()
+                .expect("\x1b[31mErr:\x1b[0m Could not parse chains table!");
+
+            for (name, chain_table) in chains_table {
+                let config = chain_table
+                    .get("config")
+                    .unwrap_or_else(|| {
+                        panic!("\x1b[31mErr:\x1b[0m Missing config of chain {}!", name)
+                    })
+                    .as_str()
+                    .expect("\x1b[31mErr:\x1b[0m Could not parse chain config as str!");
+                let path = match chain_table.get("path") {
+                    Some(path) => {
+                        path.as_str()
+                            .expect("\x1b[31mErr:\x1b[0m Could not parse chain path as str!")
+                            .trim_end_matches('/')
+                            .to_string()
+                    }
+                    None => format!("/{}", name),
+                };
+                if !path.starts_with('/') || path.len() < 2 {
+                    panic!(
+                        "\x1b[31mErr:\x1b[0m Chain {} needs a path like /{}!",
+                        name, name
+                    );
+                }
+                if chains.iter().any(|chain| chain.path == path) {
+                    panic!(
+                        "\x1b[31mErr:\x1b[0m More than one chain is served at {}!",
+                        path
+                    );
+                }
+
+                chains.push(ChainRoute {
+                    name: name.clone(),
+                    path,
+                    config: PathBuf::from(config),
+                });
+            }
+        }
+
+        let mut wallet = WalletSettings::default();
+        if let Some(wallet_table) = parsed_toml.get("wallet") {
+            let wallet_table = wallet_table
+                .as_table()
+                .expect("\x1b[31mErr:\x1b[0m Could not parse wallet table!");
+
+            if let Some(enabled) = wallet_table.get("enabled") {
+                wallet.enabled = enabled
+                    .as_bool()
+                    .expect("\x1b[31mErr:\x1b[0m Could not parse wallet enabled as bool!");
+            }
+            if let Some(signer) = wallet_table.get("signer") {
+                wallet.signer = signer
+                    .as_str()
+                    .expect("\x1b[31mErr:\x1b[0m Could not parse wallet signer as str!")
+                    .to_string();
+            }
+            if let Some(accounts) = wallet_table.get("accounts") {
+                wallet.accounts = parse_string_array(accounts)
+                    .expect("\x1b[31mErr:\x1b[0m Could not parse wallet accounts as an array of strings!")
+                    .iter()
+                    .map(|account| account.to_lowercase())
+                    .collect();
+            }
+            if let Some(gas_multiplier) = wallet_table.get("gas_multiplier") {
+                wallet.gas_multiplier = gas_multiplier
+                    .as_float()
+                    .expect("\x1b[31mErr:\x1b[0m Could not parse gas_multiplier as float!");
+                if wallet.gas_multiplier < 1.0 {
+                    panic!("\x1b[31mErr:\x1b[0m gas_multiplier can't be lower than 1!");
+                }
+            }
+            if let Some(keys) = wallet_table.get("keys") {
+                wallet.keys = parse_string_array(keys).expect(
+                    "\x1b[31mErr:\x1b[0m Could not parse wallet keys as an array of strings!",
+                );
+            }
+            // Anyone could send transactions from our accounts otherwise
+            if wallet.enabled && wallet.keys.is_empty() {
+                panic!("\x1b[31mErr:\x1b[0m The wallet needs at least one API key in `keys`!");
+            }
+        }
+
+        let mut tx_manager = TxManagerSettings::default();
+        if let Some(tx_manager_table) = parsed_toml.get("tx_manager") {
+            let tx_manager_table = tx_manager_table
+                .as_table()
+                .expect("\x1b[31mErr:\x1b[0m Could not parse tx_manager table!");
+
+            if let Some(enabled) = tx_manager_table.get("enabled") {
+                tx_manager.enabled = enabled
+                    .as_bool()
+                    .expect("\x1b[31mErr:\x1b[0m Could not parse tx_manager enabled as bool!");
+            }
+            if let Some(interval) = tx_manager_table.get("interval") {
+                tx_manager.interval = Duration::from_secs(
+                    interval
+                        .as_integer()
+                        .expect("\x1b[31mErr:\x1b[0m Could not parse tx_manager interval as int!")
+                        as u64,
+                );
+            }
+            if let Some(bump_percent) = tx_manager_table.get("bump_percent") {
+                tx_manager.bump_percent = bump_percent
+                    .as_integer()
+                    .expect("\x1b[31mErr:\x1b[0m Could not parse bump_percent as int!")
+                    as u64;
+                // Nodes reject replacements that don't raise the fees by at least 10%
+                if tx_manager.bump_percent < 10 {
+                    panic!("\x1b[31mErr:\x1b[0m bump_percent can't be lower than 10!");
+                }
+            }
+            if let Some(max_bumps) = tx_manager_table.get("max_bumps") {
+                tx_manager.max_bumps = max_bumps
+                    .as_integer()
+                    .expect("\x1b[31mErr:\x1b[0m Could not parse max_bumps as int!")
+                    as u32;
+            }
+            if let Some(max_fee_per_gas) = tx_manager_table.get("max_fee_per_gas") {
+                let max_fee_per_gas = max_fee_per_gas
+                    .as_integer()
+                    .expect("\x1b[31mErr:\x1b[0m Could not parse max_fee_per_gas as int!")
+                    as u64;
+                tx_manager.max_fee_per_gas = (max_fee_per_gas > 0).then_some(max_fee_per_gas);
+            }
+        }
 
-        if sort_on_startup {
+        if startup && sort_on_startup {
             println!("Sorting RPCs by latency...");
             rpc_list = sort_by_latency(rpc_list, ma_length).await;
         }
@@ -330,6 +2605,85 @@ impl Settings {
             health_check_ttl,
             sled_config,
             admin,
+            hardened,
+            slo,
+            anomaly,
+            sla,
+            watchdog,
+            divergence,
+            quorum,
+            reorg,
+            websocket,
+            backfill,
+            prometheus,
+            tls,
+            event_store,
+            standby,
+            probes,
+            clock,
+            wallet,
+            tx_manager,
+            pin,
+            namespace_pools,
+            wallet_upstream,
+            state_override_policy,
+            heavy_concurrency,
+            chain,
+            export_upstreams,
+            strict_jsonrpc,
+            legacy_jsonrpc,
+            logs_cache_chunk,
+            stale_latest_delta,
+            head_gating,
+            schema_sample_rate,
+            max_batch_size,
+            upstream_override_keys,
+            expose_provenance,
+            unfinalized_ttl,
+            upstream_batch_size,
+            upstream_max_in_flight,
+            upstream_max_queued,
+            upstream_batch_window,
+            upstream_batch_failure,
+            strategy,
+            schedule,
+            rewrite,
+            cache_rules,
+            routing,
+            negative_cache_ttl,
+            read_your_writes,
+            static_responses,
+            coalesce_window,
+            single_flight,
+            hedge_delay,
+            tx_queue,
+            broadcast_transactions,
+            log_filter,
+            self_test: false,
+            cache_compression,
+            cache_dictionary_interval,
+            cache_dedup,
+            cache_max_entries,
+            cache_max_size,
+            cache_admission,
+            cache_admission_window,
+            prewarm_connections,
+            prewarm_interval,
+            handshake_interval,
+            ip_preference,
+            fee_oracle_interval,
+            fee_oracle_blocks,
+            track_inclusion,
+            metrics,
+            top_talkers,
+            top_talkers_max_clients,
+            top_talkers_export,
+            consumer_cache_stats,
+            drift_interval,
+            drift_alert_webhook,
+            config_path: None,
+            reload_interval,
+            chains,
         }
     }
 
@@ -437,6 +2791,8 @@ impl Settings {
                 readonly,
                 jwt,
                 key: DecodingKey::from_secret(key.as_bytes()),
+                keys: Vec::new(),
+                audit_log: None,
             }
         } else {
             AdminSettings {
@@ -445,6 +2801,8 @@ impl Settings {
                 readonly: false,
                 jwt: false,
                 key: DecodingKey::from_secret(b""),
+                keys: Vec::new(),
+                audit_log: None,
             }
         };
 
@@ -458,6 +2816,308 @@ impl Settings {
             health_check_ttl,
             sled_config,
             admin,
+            hardened: HardenedSettings::default(),
+            slo: SloSettings::default(),
+            anomaly: AnomalySettings::default(),
+            sla: SlaSettings::default(),
+            watchdog: WatchdogSettings::default(),
+            divergence: DivergenceSettings::default(),
+            quorum: QuorumSettings::default(),
+            reorg: ReorgSettings::default(),
+            websocket: WebsocketSettings::default(),
+            backfill: BackfillSettings::default(),
+            prometheus: PrometheusSettings::default(),
+            tls: TlsSettings::default(),
+            event_store: EventStoreSettings::default(),
+            standby: StandbySettings::default(),
+            probes: ProbeSettings::default(),
+            clock: ClockSettings::default(),
+            wallet: WalletSettings::default(),
+            tx_manager: TxManagerSettings::default(),
+            pin: HashMap::new(),
+            namespace_pools: HashMap::new(),
+            wallet_upstream: None,
+            state_override_policy: StateOverridePolicy::default(),
+            heavy_concurrency: Settings::default().heavy_concurrency,
+            chain: None,
+            export_upstreams: false,
+            strict_jsonrpc: false,
+            legacy_jsonrpc: false,
+            logs_cache_chunk: 0,
+            stale_latest_delta: 0,
+            head_gating: false,
+            schema_sample_rate: 0.0,
+            max_batch_size: 1000,
+            upstream_override_keys: Vec::new(),
+            expose_provenance: false,
+            unfinalized_ttl: 60000,
+            upstream_batch_size: 0,
+            upstream_max_in_flight: 0,
+            upstream_max_queued: 1024,
+            upstream_batch_window: 2,
+            upstream_batch_failure: BatchFailure::default(),
+            strategy: Strategy::default(),
+            schedule: Vec::new(),
+            rewrite: Vec::new(),
+            cache_rules: CacheRules::default(),
+            routing: RoutingRules::default(),
+            negative_cache_ttl: 0,
+            read_your_writes: false,
+            static_responses: false,
+            coalesce_window: 0,
+            single_flight: false,
+            hedge_delay: None,
+            tx_queue: false,
+            broadcast_transactions: false,
+            log_filter: LogFilter::default(),
+            self_test: false,
+            cache_compression: 0,
+            cache_dictionary_interval: 0,
+            cache_dedup: false,
+            cache_max_entries: 0,
+            cache_max_size: 0,
+            cache_admission: Admission::TinyLfu,
+            cache_admission_window: 0.01,
+            prewarm_connections: 0,
+            prewarm_interval: 30000,
+            handshake_interval: 0,
+            ip_preference: IpPreference::default(),
+            fee_oracle_interval: 0,
+            fee_oracle_blocks: 20,
+            track_inclusion: false,
+            metrics: false,
+            top_talkers: false,
+            top_talkers_max_clients: 10000,
+            top_talkers_export: 10,
+            consumer_cache_stats: false,
+            drift_interval: 0,
+            drift_alert_webhook: None,
+            config_path: None,
+            reload_interval: 0,
+            chains: Vec::new(),
+        }
+    }
+}
+
+// Requests per second, as an int or a float
+fn parse_rate(rate: &Value, name: &str) -> f64 {
+    rate.as_float()
+        .or_else(|| rate.as_integer().map(|rate| rate as f64))
+        .unwrap_or_else(|| panic!("\x1b[31mErr:\x1b[0m Could not parse {} as a number!", name))
+}
+
+// Parse the `interval`, `checks`, `max_lag` and `min_peers` keys of `table`, starting
+// with `prefix`. Keys that aren't there are taken from `defaults`.
+fn parse_probe_config(
+    table: &toml::map::Map<String, Value>,
+    prefix: &str,
+    defaults: ProbeConfig,
+) -> ProbeConfig {
+    let get_int = |key: &str| {
+        let key = format!("{}{}", prefix, key);
+        table.get(&key).map(|value| {
+            value
+                .as_integer()
+                .unwrap_or_else(|| panic!("\x1b[31mErr:\x1b[0m Could not parse {} as int!", key))
+                as u64
+        })
+    };
+
+    let mut config = defaults;
+    if let Some(interval) = get_int("interval") {
+        config.interval = Duration::from_secs(interval);
+    }
+    if let Some(max_lag) = get_int("max_lag") {
+        config.max_lag = max_lag;
+    }
+    if let Some(min_peers) = get_int("min_peers") {
+        config.min_peers = min_peers;
+    }
+    let key = format!("{}checks", prefix);
+    if let Some(checks) = table.get(&key) {
+        config.probes = parse_string_array(checks)
+            .and_then(|checks| {
+                checks
+                    .iter()
+                    .map(|check| Probe::from_config(check))
+                    .collect()
+            })
+            .unwrap_or_else(|| {
+                panic!(
+                    "\x1b[31mErr:\x1b[0m {} must be a list of block_number/syncing/peer_count!",
+                    key
+                )
+            });
+    }
+
+    config
+}
+
+// Parse the `ma_min_length`, `ma_max_length` and `ma_volatility` keys. The window only
+// adapts if one of the lengths is set, and starts out at `ma_length`.
+fn parse_ma_window(
+    blutgang_table: &toml::map::Map<String, Value>,
+    ma_length: f64,
+) -> Option<WindowConfig> {
+    let get_length = |key: &str| {
+        blutgang_table.get(key).map(|value| {
+            value
+                .as_integer()
+                .filter(|length| *length > 0)
+                .unwrap_or_else(|| panic!("\x1b[31mErr:\x1b[0m {} must be an int above 0!", key))
+                as usize
+        })
+    };
+
+    // Whichever bound isn't set leaves room for `ma_length`
+    let defaults = WindowConfig::default();
+    let ma_length = ma_length as usize;
+    let (min_length, max_length) = match (get_length("ma_min_length"), get_length("ma_max_length"))
+    {
+        (None, None) => return None,
+        (min_length, max_length) => {
+            (
+                min_length.unwrap_or(defaults.min_length.min(ma_length)),
+                max_length.unwrap_or(defaults.max_length.max(ma_length)),
+            )
+        }
+    };
+    if min_length > max_length {
+        panic!("\x1b[31mErr:\x1b[0m ma_min_length can't be above ma_max_length!");
+    }
+
+    let volatility = match blutgang_table.get("ma_volatility") {
+        Some(volatility) => {
+            volatility
+                .as_float()
+                .or_else(|| volatility.as_integer().map(|volatility| volatility as f64))
+                .expect("\x1b[31mErr:\x1b[0m Could not parse ma_volatility as a number!")
+        }
+        None => defaults.volatility,
+    };
+
+    Some(WindowConfig {
+        min_length,
+        max_length,
+        volatility,
+    })
+}
+
+fn parse_ip_preference(ip_preference: &Value) -> IpPreference {
+    let ip_preference = ip_preference
+        .as_str()
+        .expect("\x1b[31mErr:\x1b[0m Could not parse ip_preference as str!");
+    IpPreference::from_config(ip_preference)
+        .expect("\x1b[31mErr:\x1b[0m ip_preference must be one of v4/v6/auto!")
+}
+
+// Parse the optional `username`/`password` or `cookie` keys of an RPC
+fn parse_rpc_auth(rpc_table: &toml::map::Map<String, Value>) -> Option<RpcAuth> {
+    let get_str = |key: &str| {
+        rpc_table.get(key).map(|value| {
+            value
+                .as_str()
+                .unwrap_or_else(|| panic!("\x1b[31mErr:\x1b[0m Could not parse {} as str!", key))
+                .to_string()
+        })
+    };
+
+    match (get_str("username"), get_str("password"), get_str("cookie")) {
+        (None, None, None) => None,
+        (Some(username), Some(password), None) => Some(RpcAuth::Basic { username, password }),
+        (None, None, Some(cookie)) => Some(RpcAuth::Cookie(cookie.into())),
+        _ => {
+            panic!(
+                "\x1b[31mErr:\x1b[0m An RPC needs either both username and password, or a cookie!"
+            )
         }
     }
 }
+
+// Parse the optional `signer` table of an RPC
+fn parse_rpc_signer(signer_table: &Value) -> Arc<dyn RequestSigner> {
+    let signer_table = signer_table
+        .as_table()
+        .expect("\x1b[31mErr:\x1b[0m Could not parse signer as table!");
+    let get_str = |key: &str| {
+        signer_table.get(key).map(|value| {
+            value
+                .as_str()
+                .unwrap_or_else(|| {
+                    panic!("\x1b[31mErr:\x1b[0m Could not parse signer {} as str!", key)
+                })
+                .to_string()
+        })
+    };
+
+    // Secrets can be kept out of the config file
+    let secret = match (get_str("secret"), get_str("secret_env")) {
+        (Some(secret), None) => secret,
+        (None, Some(secret_env)) => {
+            std::env::var(&secret_env).unwrap_or_else(|_| {
+                panic!(
+                    "\x1b[31mErr:\x1b[0m Signer secret_env {} is not set!",
+                    secret_env
+                )
+            })
+        }
+        _ => panic!("\x1b[31mErr:\x1b[0m A signer needs either a secret or a secret_env!"),
+    };
+
+    let headers = match signer_table.get("headers") {
+        Some(headers) => {
+            headers
+                .as_table()
+                .expect("\x1b[31mErr:\x1b[0m Could not parse signer headers as table!")
+                .iter()
+                .map(|(name, value)| {
+                    let value = value
+                        .as_str()
+                        .expect("\x1b[31mErr:\x1b[0m Could not parse signer header value as str!");
+                    (name.clone(), value.to_string())
+                })
+                .collect()
+        }
+        None => Vec::new(),
+    };
+
+    let config = SignerConfig {
+        kind: get_str("kind").unwrap_or_else(|| "hmac-sha256".to_string()),
+        secret,
+        header: get_str("header").expect("\x1b[31mErr:\x1b[0m Missing header from a signer!"),
+        encoding: get_str("encoding"),
+        payload: get_str("payload"),
+        timestamp_header: get_str("timestamp_header"),
+        timestamp_unit: get_str("timestamp_unit"),
+        headers,
+    };
+
+    signer_from_config(config)
+        .unwrap_or_else(|err| panic!("\x1b[31mErr:\x1b[0m Invalid signer: {}", err))
+}
+
+// Parse the optional `jwt_secret` of an RPC, a path to the file holding the secret
+fn parse_rpc_jwt_secret(jwt_secret: &Value) -> Arc<dyn RequestSigner> {
+    let path = jwt_secret
+        .as_str()
+        .expect("\x1b[31mErr:\x1b[0m Could not parse jwt_secret as str!");
+    let secret = std::fs::read_to_string(path).unwrap_or_else(|err| {
+        panic!(
+            "\x1b[31mErr:\x1b[0m Could not read jwt_secret {}: {}",
+            path, err
+        )
+    });
+    let secret = parse_jwt_secret(&secret)
+        .unwrap_or_else(|err| panic!("\x1b[31mErr:\x1b[0m Invalid jwt_secret {}: {}", path, err));
+
+    Arc::new(JwtSigner::new(&secret))
+}
+
+// Parse a TOML array of strings, None if it isn't one
+fn parse_string_array(value: &Value) -> Option<Vec<String>> {
+    value
+        .as_array()?
+        .iter()
+        .map(|item| item.as_str().map(|item| item.to_string()))
+        .collect()
+}
@@ -0,0 +1,230 @@
+use crate::{
+    gas::{
+        poll::fee_history_request,
+        types::FeeOracle,
+    },
+    rpc::types::first_result,
+    Rpc,
+};
+
+use std::{
+    sync::{
+        Arc,
+        RwLock,
+    },
+    time::Duration,
+};
+
+use hyper::body::Bytes;
+use serde_json::{
+    json,
+    Value,
+};
+
+pub const METHOD: &str = "blutgang_suggestFees";
+
+pub const PRIORITY_FEE_METHOD: &str = "eth_maxPriorityFeePerGas";
+
+// Blocks we look at to work out a priority fee when the oracle is disabled
+const FALLBACK_BLOCKS: usize = 20;
+
+// Field `eth_feeHistory` responses are enriched with
+pub const FEE_HISTORY_FIELD: &str = "blutgangSuggestedFees";
+
+// Respond to `blutgang_suggestFees`.
+//
+// Returns None if `tx` is some other method.
+pub fn execute_fee_method(tx: &Value, oracle: Option<&FeeOracle>) -> Option<Value> {
+    if tx["method"] != METHOD {
+        return None;
+    }
+
+    let suggestion = match oracle {
+        Some(oracle) => oracle.suggest().ok_or("No fee data yet, try again later"),
+        None => Err("The fee oracle is disabled, set `fee_oracle_interval` to enable it"),
+    };
+
+    Some(match suggestion {
+        Ok(suggestion) => {
+            json!({
+                "id": tx["id"],
+                "jsonrpc": "2.0",
+                "result": suggestion.to_json(),
+            })
+        }
+        Err(message) => {
+            json!({
+                "id": tx["id"],
+                "jsonrpc": "2.0",
+                "error": {
+                    "code": -32000,
+                    "message": message,
+                },
+            })
+        }
+    })
+}
+
+// Add our suggestion to a successful `eth_feeHistory` response
+pub fn enrich_fee_history(rx: Bytes, oracle: &FeeOracle) -> Bytes {
+    let suggestion = match oracle.suggest() {
+        Some(suggestion) => suggestion,
+        None => return rx,
+    };
+
+    let mut parsed: Value = match serde_json::from_slice(&rx) {
+        Ok(parsed) => parsed,
+        Err(_) => return rx,
+    };
+    match parsed["result"].as_object_mut() {
+        Some(result) => {
+            result.insert(FEE_HISTORY_FIELD.to_string(), suggestion.to_json());
+            Bytes::from(parsed.to_string())
+        }
+        None => rx,
+    }
+}
+
+// If `rx` is an error saying the RPC doesn't implement the method.
+//
+// Clients don't agree on a code for this, so we go by the message too.
+pub fn is_method_not_found(rx: &[u8]) -> bool {
+    let rx: Value = match serde_json::from_slice(rx) {
+        Ok(rx) => rx,
+        Err(_) => return false,
+    };
+    if rx["error"]["code"] == -32601 {
+        return true;
+    }
+
+    let message = rx["error"]["message"]
+        .as_str()
+        .unwrap_or_default()
+        .to_lowercase();
+    [
+        "not found",
+        "not supported",
+        "unsupported",
+        "does not exist",
+        "not available",
+    ]
+    .iter()
+    .any(|pattern| message.contains(pattern))
+}
+
+// Priority fee we suggest for the next block, for RPCs that don't implement
+// `eth_maxPriorityFeePerGas` themselves.
+//
+// Uses the oracle if it has data, and the fee history of the last few blocks otherwise.
+pub async fn fallback_priority_fee(
+    oracle: Option<&FeeOracle>,
+    rpc_list: &Arc<RwLock<Vec<Rpc>>>,
+    ttl: Duration,
+) -> Option<u64> {
+    if let Some(suggestion) = oracle.and_then(FeeOracle::suggest) {
+        return Some(suggestion.tips[1]);
+    }
+
+    let history = first_result(rpc_list, &fee_history_request(FALLBACK_BLOCKS), ttl).await?;
+    let recent = FeeOracle::new(FALLBACK_BLOCKS);
+    recent.record(&history).ok()?;
+    recent.suggest().map(|suggestion| suggestion.tips[1])
+}
+
+pub fn priority_fee_response(id: &Value, tip: u64) -> Bytes {
+    Bytes::from(
+        json!({
+            "id": id,
+            "jsonrpc": "2.0",
+            "result": format!("{:#x}", tip),
+        })
+        .to_string(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn oracle() -> FeeOracle {
+        let oracle = FeeOracle::new(10);
+        oracle
+            .record(&json!({
+                "oldestBlock": "0x1",
+                "baseFeePerGas": ["0x64", "0x64"],
+                "gasUsedRatio": [0.5],
+                "reward": [["0x1", "0x2", "0x3"]],
+            }))
+            .unwrap();
+        oracle
+    }
+
+    #[test]
+    fn test_execute_fee_method() {
+        let tx = json!({"id": 7, "jsonrpc": "2.0", "method": METHOD, "params": []});
+
+        let rx = execute_fee_method(&tx, Some(&oracle())).unwrap();
+        assert_eq!(rx["id"], 7);
+        assert_eq!(rx["result"]["fast"]["maxFeePerGas"], "0xcb");
+
+        let rx = execute_fee_method(&tx, None).unwrap();
+        assert_eq!(rx["error"]["code"], -32000);
+        let rx = execute_fee_method(&tx, Some(&FeeOracle::new(10))).unwrap();
+        assert!(rx["error"].is_object());
+
+        let tx = json!({"id": 7, "jsonrpc": "2.0", "method": "eth_gasPrice", "params": []});
+        assert_eq!(execute_fee_method(&tx, None), None);
+    }
+
+    #[test]
+    fn test_is_method_not_found() {
+        assert!(is_method_not_found(
+            br#"{"id":1,"jsonrpc":"2.0","error":{"code":-32601,"message":"Method not found"}}"#
+        ));
+        assert!(is_method_not_found(
+            br#"{"id":1,"jsonrpc":"2.0","error":{"code":-32000,"message":"method eth_maxPriorityFeePerGas not supported"}}"#
+        ));
+        assert!(!is_method_not_found(
+            br#"{"id":1,"jsonrpc":"2.0","error":{"code":-32000,"message":"header not ready"}}"#
+        ));
+        assert!(!is_method_not_found(
+            br#"{"id":1,"jsonrpc":"2.0","result":"0x1"}"#
+        ));
+        assert!(!is_method_not_found(b"Bad Gateway"));
+    }
+
+    #[tokio::test]
+    async fn test_fallback_priority_fee() {
+        let rpc_list = Arc::new(RwLock::new(Vec::new()));
+        let ttl = Duration::from_millis(100);
+
+        assert_eq!(
+            fallback_priority_fee(Some(&oracle()), &rpc_list, ttl).await,
+            Some(2)
+        );
+        // No oracle data and no RPC to ask
+        assert_eq!(
+            fallback_priority_fee(Some(&FeeOracle::new(10)), &rpc_list, ttl).await,
+            None
+        );
+
+        let rx: Value = serde_json::from_slice(&priority_fee_response(&json!(7), 2)).unwrap();
+        assert_eq!(rx["id"], 7);
+        assert_eq!(rx["result"], "0x2");
+    }
+
+    #[test]
+    fn test_enrich_fee_history() {
+        let rx = Bytes::from(r#"{"id":1,"jsonrpc":"2.0","result":{"oldestBlock":"0x1"}}"#);
+        let enriched: Value = serde_json::from_slice(&enrich_fee_history(rx, &oracle())).unwrap();
+        assert_eq!(enriched["result"]["oldestBlock"], "0x1");
+        assert_eq!(
+            enriched["result"][FEE_HISTORY_FIELD]["nextBaseFeePerGas"],
+            "0x64"
+        );
+
+        // Errors are left alone
+        let rx = Bytes::from(r#"{"id":1,"jsonrpc":"2.0","error":{"code":-1,"message":"no"}}"#);
+        assert_eq!(enrich_fee_history(rx.clone(), &oracle()), rx);
+    }
+}
@@ -0,0 +1,3 @@
+pub mod methods;
+pub mod poll;
+pub mod types;
@@ -0,0 +1,91 @@
+// Keep the fee oracle up to date.
+//
+// Every `interval` we ask for the fee history of the blocks the oracle keeps, replacing
+// what we had so reorged blocks don't stick around. RPCs are tried fastest first, so one
+// RPC going down doesn't stop the oracle.
+use crate::{
+    gas::types::{
+        FeeOracle,
+        PERCENTILES,
+    },
+    Rpc,
+};
+
+use std::{
+    sync::{
+        Arc,
+        RwLock,
+    },
+    time::Duration,
+};
+
+use serde_json::{
+    json,
+    Value,
+};
+use tokio::time::{
+    sleep,
+    timeout,
+};
+
+// `eth_feeHistory` of the last `blocks` blocks at `PERCENTILES`
+pub fn fee_history_request(blocks: usize) -> Value {
+    json!({
+        "id": 1,
+        "jsonrpc": "2.0",
+        "method": "eth_feeHistory",
+        "params": [format!("{:#x}", blocks), "latest", PERCENTILES],
+    })
+}
+
+async fn fee_history(rpc: &Rpc, blocks: usize, request_timeout: Duration) -> Option<Value> {
+    let tx = fee_history_request(blocks);
+
+    let rx = timeout(request_timeout, rpc.send_request(tx))
+        .await
+        .ok()?
+        .ok()?;
+    let mut rx: Value = serde_json::from_slice(&rx).ok()?;
+
+    rx["result"].is_object().then(|| rx["result"].take())
+}
+
+pub async fn poll_fees(
+    rpc_list: Arc<RwLock<Vec<Rpc>>>,
+    oracle: Arc<FeeOracle>,
+    interval: Duration,
+) {
+    loop {
+        let rpcs: Vec<Rpc> = rpc_list
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|rpc| !rpc.paused)
+            .cloned()
+            .collect();
+
+        let mut updated = false;
+        for rpc in rpcs {
+            if let Some(history) = fee_history(&rpc, oracle.capacity(), interval).await {
+                match oracle.record(&history) {
+                    Ok(()) => {
+                        updated = true;
+                        break;
+                    }
+                    Err(err) => {
+                        println!(
+                            "\x1b[93mWrn:\x1b[0m Invalid fee history from {}: {}",
+                            rpc.name, err
+                        );
+                    }
+                }
+            }
+        }
+
+        if !updated {
+            println!("\x1b[93mWrn:\x1b[0m Could not update the fee oracle!");
+        }
+
+        sleep(interval).await;
+    }
+}
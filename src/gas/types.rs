@@ -0,0 +1,282 @@
+// Rolling fee oracle.
+//
+// We keep the base fee and priority fee percentiles of the last few blocks, as reported by
+// `eth_feeHistory`, and derive fee suggestions from them. Every client gets the same
+// advice no matter which RPC would have served them.
+use crate::rpc::types::hex_to_decimal;
+
+use std::{
+    collections::BTreeMap,
+    sync::Mutex,
+};
+
+use serde_json::{
+    json,
+    Value,
+};
+
+// Percentiles of priority fees we ask for, one per tier of `FeeSuggestion`
+pub const PERCENTILES: [f64; 3] = [10.0, 50.0, 90.0];
+
+// Next base fee compared to the average of the window that counts as a trend
+const TREND_THRESHOLD: f64 = 0.1;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct BlockFees {
+    base_fee: u64,
+    gas_used_ratio: f64,
+    // Priority fees at `PERCENTILES`
+    rewards: [u64; 3],
+}
+
+#[derive(Debug, Default)]
+struct OracleState {
+    blocks: BTreeMap<u64, BlockFees>,
+    // Base fee of the block after the latest one
+    next_base_fee: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Trend {
+    Rising,
+    Stable,
+    Falling,
+}
+
+impl Trend {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Trend::Rising => "rising",
+            Trend::Stable => "stable",
+            Trend::Falling => "falling",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FeeSuggestion {
+    pub latest_block: u64,
+    pub blocks: usize,
+    pub base_fee: u64,
+    pub next_base_fee: u64,
+    pub trend: Trend,
+    // Priority fees, from cheap and slow to expensive and fast
+    pub tips: [u64; 3],
+}
+
+impl FeeSuggestion {
+    // Max fee that stays valid even if the base fee keeps rising for a few blocks
+    pub fn max_fee(&self, tip: u64) -> u64 {
+        self.next_base_fee.saturating_mul(2).saturating_add(tip)
+    }
+
+    pub fn to_json(&self) -> Value {
+        let tier = |tip: u64| {
+            json!({
+                "maxPriorityFeePerGas": format!("{:#x}", tip),
+                "maxFeePerGas": format!("{:#x}", self.max_fee(tip)),
+            })
+        };
+
+        json!({
+            "latestBlock": format!("{:#x}", self.latest_block),
+            "blocks": self.blocks,
+            "baseFeePerGas": format!("{:#x}", self.base_fee),
+            "nextBaseFeePerGas": format!("{:#x}", self.next_base_fee),
+            "baseFeeTrend": self.trend.as_str(),
+            "slow": tier(self.tips[0]),
+            "standard": tier(self.tips[1]),
+            "fast": tier(self.tips[2]),
+        })
+    }
+}
+
+fn quantity(value: &Value) -> Option<u64> {
+    value.as_str().and_then(|value| hex_to_decimal(value).ok())
+}
+
+fn median(mut values: Vec<u64>) -> u64 {
+    if values.is_empty() {
+        return 0;
+    }
+    values.sort_unstable();
+    values[values.len() / 2]
+}
+
+#[derive(Debug)]
+pub struct FeeOracle {
+    state: Mutex<OracleState>,
+    // Blocks we keep
+    capacity: usize,
+}
+
+impl FeeOracle {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            state: Mutex::new(OracleState::default()),
+            capacity: capacity.max(1),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    // Add the result of an `eth_feeHistory` call made with `PERCENTILES`, up to the latest block.
+    //
+    // Blocks we already have get replaced, and ones past the latest block were reorged out.
+    pub fn record(&self, history: &Value) -> Result<(), String> {
+        let oldest = quantity(&history["oldestBlock"]).ok_or("missing oldestBlock")?;
+        let base_fees = history["baseFeePerGas"]
+            .as_array()
+            .ok_or("missing baseFeePerGas")?;
+        let ratios = history["gasUsedRatio"]
+            .as_array()
+            .ok_or("missing gasUsedRatio")?;
+        let rewards = history["reward"].as_array().ok_or("missing reward")?;
+
+        if ratios.is_empty() || base_fees.len() != ratios.len() + 1 || rewards.len() != ratios.len()
+        {
+            return Err("inconsistent fee history".to_string());
+        }
+
+        let mut blocks = Vec::with_capacity(ratios.len());
+        for (index, ratio) in ratios.iter().enumerate() {
+            let mut block_rewards = [0; 3];
+            for (percentile, reward) in block_rewards.iter_mut().enumerate() {
+                *reward = quantity(&rewards[index][percentile]).ok_or("invalid reward")?;
+            }
+            blocks.push(BlockFees {
+                base_fee: quantity(&base_fees[index]).ok_or("invalid baseFeePerGas")?,
+                gas_used_ratio: ratio.as_f64().ok_or("invalid gasUsedRatio")?,
+                rewards: block_rewards,
+            });
+        }
+        let next_base_fee = quantity(&base_fees[ratios.len()]).ok_or("invalid baseFeePerGas")?;
+        let latest = oldest + blocks.len() as u64 - 1;
+
+        let mut state = self.state.lock().unwrap();
+        state.blocks.split_off(&(latest + 1));
+        for (number, block) in (oldest..).zip(blocks) {
+            state.blocks.insert(number, block);
+        }
+        while state.blocks.len() > self.capacity {
+            state.blocks.pop_first();
+        }
+        state.next_base_fee = next_base_fee;
+
+        Ok(())
+    }
+
+    pub fn suggest(&self) -> Option<FeeSuggestion> {
+        let state = self.state.lock().unwrap();
+        let (latest_block, latest) = state.blocks.last_key_value()?;
+
+        let average = state
+            .blocks
+            .values()
+            .map(|block| block.base_fee as f64)
+            .sum::<f64>()
+            / state.blocks.len() as f64;
+        let trend = match state.next_base_fee as f64 {
+            next if next > average * (1.0 + TREND_THRESHOLD) => Trend::Rising,
+            next if next < average * (1.0 - TREND_THRESHOLD) => Trend::Falling,
+            _ => Trend::Stable,
+        };
+
+        // Empty blocks report no priority fees, which would drag every tier to 0
+        let mut tips = [0; 3];
+        for (percentile, tip) in tips.iter_mut().enumerate() {
+            *tip = median(
+                state
+                    .blocks
+                    .values()
+                    .filter(|block| block.gas_used_ratio > 0.0)
+                    .map(|block| block.rewards[percentile])
+                    .collect(),
+            );
+        }
+
+        Some(FeeSuggestion {
+            latest_block: *latest_block,
+            blocks: state.blocks.len(),
+            base_fee: latest.base_fee,
+            next_base_fee: state.next_base_fee,
+            trend,
+            tips,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn history(oldest: u64, base_fees: &[u64], rewards: &[[u64; 3]]) -> Value {
+        json!({
+            "oldestBlock": format!("{:#x}", oldest),
+            "baseFeePerGas": base_fees.iter().map(|fee| format!("{:#x}", fee)).collect::<Vec<_>>(),
+            "gasUsedRatio": rewards.iter().map(|reward| if reward[2] == 0 { 0.0 } else { 0.5 }).collect::<Vec<_>>(),
+            "reward": rewards
+                .iter()
+                .map(|reward| reward.iter().map(|fee| format!("{:#x}", fee)).collect::<Vec<_>>())
+                .collect::<Vec<_>>(),
+        })
+    }
+
+    #[test]
+    fn test_suggest() {
+        let oracle = FeeOracle::new(3);
+        assert_eq!(oracle.suggest(), None);
+
+        oracle
+            .record(&history(
+                10,
+                &[100, 100, 100, 150],
+                &[[1, 2, 3], [1, 4, 5], [0, 0, 0]],
+            ))
+            .unwrap();
+        let suggestion = oracle.suggest().unwrap();
+        assert_eq!(suggestion.latest_block, 12);
+        assert_eq!(suggestion.base_fee, 100);
+        assert_eq!(suggestion.next_base_fee, 150);
+        assert_eq!(suggestion.trend, Trend::Rising);
+        // The empty block is ignored
+        assert_eq!(suggestion.tips, [1, 4, 5]);
+        assert_eq!(suggestion.max_fee(4), 304);
+
+        let json = suggestion.to_json();
+        assert_eq!(json["standard"]["maxPriorityFeePerGas"], "0x4");
+        assert_eq!(json["baseFeeTrend"], "rising");
+        assert_eq!(
+            oracle.record(&json!({"oldestBlock": "0x1"})),
+            Err("missing baseFeePerGas".to_string())
+        );
+    }
+
+    #[test]
+    fn test_rolling_window() {
+        let oracle = FeeOracle::new(3);
+        oracle
+            .record(&history(10, &[100, 100, 100, 100], &[[1, 1, 1]; 3]))
+            .unwrap();
+        oracle
+            .record(&history(12, &[50, 50, 50], &[[2, 2, 2]; 2]))
+            .unwrap();
+
+        // Only the last 3 blocks are kept
+        let suggestion = oracle.suggest().unwrap();
+        assert_eq!(suggestion.blocks, 3);
+        assert_eq!(suggestion.latest_block, 13);
+        assert_eq!(suggestion.tips, [2, 2, 2]);
+        assert_eq!(suggestion.trend, Trend::Falling);
+
+        // Reorged back to 12, 13 is gone
+        oracle
+            .record(&history(12, &[50, 50], &[[3, 3, 3]]))
+            .unwrap();
+        let suggestion = oracle.suggest().unwrap();
+        assert_eq!(suggestion.latest_block, 12);
+        assert_eq!(suggestion.blocks, 2);
+    }
+}
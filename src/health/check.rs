@@ -1,11 +1,23 @@
 use crate::{
+    balancer::selection::select::update_weights,
+    config::types::ClockSettings,
     health::{
+        clock::{
+            drift,
+            head_timestamp,
+            reference_time,
+        },
         error::HealthError,
         safe_block::{
             get_safe_block,
             NamedBlocknumbers,
         },
     },
+    logging::filter::{
+        log_enabled,
+        LogLevel,
+    },
+    metrics::store::EventStore,
     Rpc,
     Settings,
 };
@@ -36,19 +48,35 @@ pub async fn health_check(
     rpc_list: Arc<RwLock<Vec<Rpc>>>,
     poverty_list: Arc<RwLock<Vec<Rpc>>>,
     blocknum_tx: &tokio::sync::watch::Sender<u64>,
-    finalized_tx: tokio::sync::watch::Sender<u64>,
+    finalized_tx: &tokio::sync::watch::Sender<u64>,
     named_numbers_rwlock: &Arc<RwLock<NamedBlocknumbers>>,
     config: &Arc<RwLock<Settings>>,
+    event_store: Option<Arc<EventStore>>,
 ) -> Result<(), HealthError> {
     loop {
         let health_check_ttl = config.read().unwrap().health_check_ttl;
         let ttl = config.read().unwrap().ttl;
+        let head_tolerance = config.read().unwrap().protocol().head_tolerance();
+        let clock = Some(config.read().unwrap().clock.clone()).filter(|clock| clock.enabled);
 
         sleep(Duration::from_millis(health_check_ttl)).await;
-        check(&rpc_list, &poverty_list, blocknum_tx, &ttl).await?;
+        let failing = poverty_names(&poverty_list);
+        check(
+            &rpc_list,
+            &poverty_list,
+            blocknum_tx,
+            &ttl,
+            head_tolerance,
+            clock.as_ref(),
+        )
+        .await?;
+        if let Some(event_store) = &event_store {
+            record_transitions(event_store, &failing, &rpc_list, &poverty_list);
+        }
+        update_weights(&mut rpc_list.write().unwrap());
         get_safe_block(
             &rpc_list,
-            &finalized_tx,
+            finalized_tx,
             named_numbers_rwlock,
             health_check_ttl,
         )
@@ -56,21 +84,57 @@ pub async fn health_check(
     }
 }
 
+fn poverty_names(poverty_list: &RwLock<Vec<Rpc>>) -> Vec<String> {
+    poverty_list
+        .read()
+        .unwrap()
+        .iter()
+        .map(|rpc| rpc.name.clone())
+        .collect()
+}
+
+// Record RPCs that went into or out of the poverty list since it held `failing`
+fn record_transitions(
+    event_store: &EventStore,
+    failing: &[String],
+    rpc_list: &RwLock<Vec<Rpc>>,
+    poverty_list: &RwLock<Vec<Rpc>>,
+) {
+    let now_failing = poverty_names(poverty_list);
+    for name in now_failing.iter().filter(|name| !failing.contains(name)) {
+        event_store.record_health(name, false);
+    }
+
+    // Only the ones that made it back, not ones removed from the admin namespace
+    let rpc_list_guard = rpc_list.read().unwrap();
+    for name in failing.iter().filter(|name| !now_failing.contains(name)) {
+        if rpc_list_guard.iter().any(|rpc| &rpc.name == name) {
+            event_store.record_health(name, true);
+        }
+    }
+}
+
 // Track the head of each RPC and process them accordingly
 async fn check(
     rpc_list: &Arc<RwLock<Vec<Rpc>>>,
     poverty_list: &Arc<RwLock<Vec<Rpc>>>,
     blocknum_tx: &tokio::sync::watch::Sender<u64>,
     ttl: &u128,
+    head_tolerance: u64,
+    clock: Option<&ClockSettings>,
 ) -> Result<(), HealthError> {
     print!("\x1b[35mInfo:\x1b[0m Checking RPC health... ");
     // Head blocks reported by each RPC, we also use it to mark delinquents
     //
     // If a head is marked at `0` that means that the rpc is delinquent
-    let heads = head_check(rpc_list, *ttl).await?;
+    let mut heads = head_check(rpc_list, *ttl).await?;
+    let reference = match clock {
+        Some(clock) => Some(check_drift(rpc_list, &mut heads, clock, None, *ttl).await),
+        None => None,
+    };
 
     // Remove RPCs that are falling behind
-    let agreed_head = make_poverty(rpc_list, poverty_list, heads)?;
+    let agreed_head = make_poverty(rpc_list, poverty_list, heads, head_tolerance)?;
     // Send new blocknumber if modified
     let send_if_changed = |number: &mut u64| {
         if number != &agreed_head {
@@ -86,11 +150,23 @@ async fn check(
     // Its ok if we call them twice because some might have been accidentally put here
 
     // Do a head check over the current poverty list to see if any nodes are back to normal
-    let poverty_heads = head_check(poverty_list, *ttl).await?;
+    let mut poverty_heads = head_check(poverty_list, *ttl).await?;
+    // Drifting RPCs stay out until they're following the same clock as the pool
+    if let Some(clock) = clock {
+        check_drift(poverty_list, &mut poverty_heads, clock, reference, *ttl).await;
+    }
 
-    escape_poverty(rpc_list, poverty_list, poverty_heads, agreed_head)?;
+    escape_poverty(
+        rpc_list,
+        poverty_list,
+        poverty_heads,
+        agreed_head,
+        head_tolerance,
+    )?;
 
-    println!("OK!");
+    if log_enabled("health", LogLevel::Debug) {
+        println!("OK!");
+    }
 
     Ok(())
 }
@@ -128,6 +204,7 @@ async fn head_check(
                 Ok(response) => response.unwrap_or(0), // Handle timeout as 0
                 Err(_) => 0,                           // Handle timeout as 0
             };
+            rpc_clone.pool_stats().record_head(head);
 
             let head_result = HeadResult {
                 rpc_list_index: i,
@@ -158,11 +235,74 @@ async fn head_check(
     Ok(heads)
 }
 
+// Mark RPCs whose head timestamp is more than `clock.max_drift` off as delinquent.
+//
+// Their heads are compared against `reference` if set, and the clock we're configured
+// with otherwise. Returns what we compared against.
+async fn check_drift(
+    rpc_list: &Arc<RwLock<Vec<Rpc>>>,
+    heads: &mut [HeadResult],
+    clock: &ClockSettings,
+    reference: Option<u64>,
+    ttl: u128,
+) -> u64 {
+    let ttl = Duration::from_millis(ttl.try_into().unwrap());
+
+    let mut lookups = Vec::with_capacity(heads.len());
+    for head in heads.iter() {
+        let rpc = rpc_list.read().unwrap().get(head.rpc_list_index).cloned();
+        let reported_head = head.reported_head;
+        lookups.push(tokio::spawn(async move {
+            match rpc {
+                // Delinquent already
+                Some(rpc) if reported_head > 0 => head_timestamp(&rpc, reported_head, ttl).await,
+                _ => None,
+            }
+        }));
+    }
+    let mut timestamps = Vec::with_capacity(lookups.len());
+    for lookup in lookups {
+        timestamps.push(lookup.await.unwrap_or_default());
+    }
+
+    // Ones in the poverty list were warned about when they left the pool
+    let warn = reference.is_none() && log_enabled("health", LogLevel::Warn);
+    let known: Vec<u64> = timestamps.iter().flatten().copied().collect();
+    let reference = reference.unwrap_or_else(|| reference_time(clock.source, &known));
+
+    // RPCs that didn't tell us their timestamp aren't punished for it
+    for (head, timestamp) in heads.iter_mut().zip(timestamps) {
+        let drift = match timestamp {
+            Some(timestamp) => drift(timestamp, reference),
+            None => continue,
+        };
+        if drift <= clock.max_drift {
+            continue;
+        }
+
+        if warn {
+            if let Some(rpc) = rpc_list.read().unwrap().get(head.rpc_list_index) {
+                println!(
+                    "\x1b[93mWrn:\x1b[0m Head of {} is {}s off the clock!",
+                    rpc.name,
+                    drift.as_secs()
+                );
+            }
+        }
+        head.reported_head = 0;
+    }
+
+    reference
+}
+
 // Add unresponsive/erroring RPCs to the poverty list
+//
+// RPCs more than `head_tolerance` blocks behind the highest head are considered delinquent.
 fn make_poverty(
     rpc_list: &Arc<RwLock<Vec<Rpc>>>,
     poverty_list: &Arc<RwLock<Vec<Rpc>>>,
     heads: Vec<HeadResult>,
+    head_tolerance: u64,
 ) -> Result<u64, HealthError> {
     // Get the highest head reported by the RPCs
     let mut highest_head = 0;
@@ -177,7 +317,7 @@ fn make_poverty(
     let mut poverty_list_guard = poverty_list.write().unwrap();
 
     for head in heads {
-        if head.reported_head < highest_head {
+        if head.reported_head + head_tolerance < highest_head {
             // Mark the RPC as erroring
             rpc_list_guard[head.rpc_list_index].status.is_erroring = true;
             println!(
@@ -202,13 +342,18 @@ fn escape_poverty(
     poverty_list: &Arc<RwLock<Vec<Rpc>>>,
     poverty_heads: Vec<HeadResult>,
     agreed_head: u64,
+    head_tolerance: u64,
 ) -> Result<(), HealthError> {
     // Check if any nodes made it 🗣️🔥🔥🔥
     let mut poverty_list_guard = poverty_list.write().unwrap();
     let mut rpc_list_guard = rpc_list.write().unwrap();
 
     for head_result in poverty_heads {
-        if head_result.reported_head >= agreed_head {
+        // Quarantined RPCs are released by `detect_divergence` once they're on our chain again
+        if poverty_list_guard[head_result.rpc_list_index].quarantined {
+            continue;
+        }
+        if head_result.reported_head + head_tolerance >= agreed_head {
             let mut rpc = poverty_list_guard[head_result.rpc_list_index].clone();
             rpc.status.is_erroring = false;
             println!(
@@ -271,7 +416,7 @@ mod tests {
         let heads = dummy_head_check();
 
         // Call the make_poverty function
-        let result = make_poverty(&rpc_list, &poverty_list, heads);
+        let result = make_poverty(&rpc_list, &poverty_list, heads, 0);
         assert!(result.is_ok());
 
         // Check the state of RPCs after the test
@@ -285,6 +430,37 @@ mod tests {
         assert_eq!(poverty_list_guard.len(), 2);
     }
 
+    #[test]
+    fn test_poverty_tolerance() {
+        let rpc_list = Arc::new(RwLock::new(vec![
+            Rpc::default(),
+            Rpc::default(),
+            Rpc::default(),
+        ]));
+        let poverty_list = Arc::new(RwLock::new(vec![]));
+
+        // Only the RPC reporting `0` is more than 1 block behind
+        let heads = vec![
+            HeadResult {
+                rpc_list_index: 0,
+                reported_head: 100,
+            },
+            HeadResult {
+                rpc_list_index: 1,
+                reported_head: 99,
+            },
+            HeadResult {
+                rpc_list_index: 2,
+                reported_head: 0,
+            },
+        ];
+
+        let result = make_poverty(&rpc_list, &poverty_list, heads, 1);
+        assert_eq!(result.unwrap(), 100);
+        assert_eq!(rpc_list.read().unwrap().len(), 2);
+        assert_eq!(poverty_list.read().unwrap().len(), 1);
+    }
+
     #[test]
     fn test_escape() {
         // Create a mock RPC list and poverty list
@@ -311,7 +487,7 @@ mod tests {
         ];
 
         // Call the escape_poverty function
-        let result = escape_poverty(&rpc_list, &poverty_list, heads, 18193012);
+        let result = escape_poverty(&rpc_list, &poverty_list, heads, 18193012, 0);
         assert!(result.is_ok());
 
         // Check the state of RPCs after the test
@@ -0,0 +1,151 @@
+// Catch RPCs whose head is from the wrong time.
+//
+// A stalled node or one following a fork can report block numbers that look plausible,
+// but the timestamp of its head gives it away. We compare it against a clock, either
+// ours or the median head timestamp of the pool for hosts whose clock we can't trust,
+// and RPCs that are off by more than `max_drift` are taken out of the pool until they
+// catch up. How much drift is normal depends on the block time of the chain.
+use crate::{
+    balancer::classify::Chain,
+    rpc::types::Protocol,
+    Rpc,
+};
+
+use std::time::{
+    Duration,
+    SystemTime,
+    UNIX_EPOCH,
+};
+
+use serde_json::{
+    json,
+    Value,
+};
+use tokio::time::timeout;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ClockSource {
+    // Our own clock
+    #[default]
+    System,
+    // Median head timestamp of the active pool
+    Pool,
+}
+
+impl ClockSource {
+    pub fn from_config(source: &str) -> Option<Self> {
+        match source {
+            "system" => Some(ClockSource::System),
+            "pool" => Some(ClockSource::Pool),
+            _ => None,
+        }
+    }
+}
+
+// Drift we allow by default, a few blocks' worth so missed slots don't count
+pub fn default_max_drift(chain: Option<Chain>) -> Duration {
+    let secs = match chain {
+        None | Some(Chain::Ethereum) => 60,
+        Some(Chain::Optimism | Chain::Arbitrum | Chain::ZkSync) => 10,
+        Some(Chain::Starknet) => 120,
+        Some(Chain::Solana) => 30,
+        // Miners can set timestamps up to 2 hours ahead
+        Some(Chain::Bitcoin) => 7200,
+    };
+    Duration::from_secs(secs)
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+fn as_u64(value: &Value) -> Option<u64> {
+    match value {
+        Value::String(hex) => u64::from_str_radix(hex.trim_start_matches("0x"), 16).ok(),
+        value => value.as_u64(),
+    }
+}
+
+async fn call(rpc: &Rpc, method: &str, params: Value, ttl: Duration) -> Option<Value> {
+    let tx = json!({
+        "id": 1,
+        "jsonrpc": "2.0",
+        "method": method,
+        "params": params,
+    });
+    let rx = timeout(ttl, rpc.send_request(tx)).await.ok()?.ok()?;
+    let mut rx: Value = serde_json::from_slice(&rx).ok()?;
+
+    Some(rx["result"].take())
+}
+
+// Timestamp of block `head` according to `rpc`, in seconds
+pub async fn head_timestamp(rpc: &Rpc, head: u64, ttl: Duration) -> Option<u64> {
+    match rpc.protocol {
+        Protocol::Evm => {
+            let block = call(
+                rpc,
+                "eth_getBlockByNumber",
+                json!([format!("{:#x}", head), false]),
+                ttl,
+            )
+            .await?;
+            as_u64(&block["timestamp"])
+        }
+        Protocol::Solana => {
+            call(rpc, "getBlockTime", json!([head]), ttl)
+                .await?
+                .as_u64()
+        }
+        Protocol::Bitcoin => {
+            let hash = call(rpc, "getblockhash", json!([head]), ttl).await?;
+            let header = call(rpc, "getblockheader", json!([hash]), ttl).await?;
+            header["time"].as_u64()
+        }
+    }
+}
+
+// Time head timestamps get compared against. The pool falls back to our clock if none
+// of its RPCs told us theirs.
+pub fn reference_time(source: ClockSource, timestamps: &[u64]) -> u64 {
+    match source {
+        ClockSource::Pool if !timestamps.is_empty() => {
+            let mut timestamps = timestamps.to_vec();
+            timestamps.sort_unstable();
+            timestamps[timestamps.len() / 2]
+        }
+        _ => now_secs(),
+    }
+}
+
+// How far `timestamp` is from `reference`, in either direction
+pub fn drift(timestamp: u64, reference: u64) -> Duration {
+    Duration::from_secs(timestamp.abs_diff(reference))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reference_time() {
+        assert_eq!(reference_time(ClockSource::Pool, &[30, 10, 20]), 20);
+        assert_eq!(reference_time(ClockSource::Pool, &[10, 1000, 11, 12]), 12);
+
+        let now = now_secs();
+        assert!(reference_time(ClockSource::System, &[10]) >= now);
+        assert!(reference_time(ClockSource::Pool, &[]) >= now);
+    }
+
+    #[test]
+    fn test_drift() {
+        assert_eq!(drift(100, 160), Duration::from_secs(60));
+        assert_eq!(drift(160, 100), Duration::from_secs(60));
+        assert_eq!(as_u64(&json!("0x64")), Some(100));
+        assert_eq!(as_u64(&json!(100)), Some(100));
+        assert_eq!(as_u64(&Value::Null), None);
+    }
+}
@@ -0,0 +1,269 @@
+// Detect RPCs that are on a different chain than the rest of the pool.
+//
+// Heads are compared by number only, so an RPC serving a fork (or a malicious chain)
+// at the right height looks perfectly healthy. Here we compare block hashes at a
+// height deep enough that normal reorgs are settled, and every RPC should agree.
+// RPCs that don't can be quarantined in the poverty list, where they stay until
+// they agree with the pool again.
+use crate::{
+    config::types::DivergenceSettings,
+    Rpc,
+};
+
+use std::{
+    collections::BTreeMap,
+    sync::{
+        Arc,
+        RwLock,
+    },
+    time::Duration,
+};
+
+use reqwest::Client;
+use serde_json::{
+    json,
+    Value,
+};
+use tokio::{
+    sync::watch,
+    time::{
+        sleep,
+        timeout,
+    },
+};
+
+// RPCs grouped by the hash they reported at `height`
+#[derive(Debug, Clone, PartialEq)]
+struct Divergence {
+    height: u64,
+    // Hash -> RPC names
+    groups: BTreeMap<String, Vec<String>>,
+    // None if no hash has more RPCs behind it than every other one
+    majority: Option<String>,
+}
+
+impl Divergence {
+    // RPCs that don't agree with the majority, or everyone without one
+    fn minority(&self) -> Vec<String> {
+        self.groups
+            .iter()
+            .filter(|(hash, _)| Some(*hash) != self.majority.as_ref())
+            .flat_map(|(_, names)| names.iter().cloned())
+            .collect()
+    }
+}
+
+// Group `hashes` of RPCs at `height`. Returns None if they all agree.
+fn compare(height: u64, hashes: Vec<(String, String)>) -> Option<Divergence> {
+    let mut groups: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for (name, hash) in hashes {
+        groups.entry(hash).or_default().push(name);
+    }
+    if groups.len() < 2 {
+        return None;
+    }
+
+    let most = groups.values().map(Vec::len).max().unwrap_or_default();
+    let mut largest = groups.iter().filter(|(_, names)| names.len() == most);
+    let majority = match (largest.next(), largest.next()) {
+        (Some((hash, _)), None) => Some(hash.clone()),
+        _ => None,
+    };
+
+    Some(Divergence {
+        height,
+        groups,
+        majority,
+    })
+}
+
+// Hash of block `height` according to `rpc`
+async fn block_hash(rpc: &Rpc, height: u64, ttl: Duration) -> Option<String> {
+    let tx = json!({
+        "id": 1,
+        "jsonrpc": "2.0",
+        "method": "eth_getBlockByNumber",
+        "params": [format!("{:#x}", height), false],
+    });
+    let rx = timeout(ttl, rpc.send_request(tx)).await.ok()?.ok()?;
+    let rx: Value = serde_json::from_slice(&rx).ok()?;
+
+    rx["result"]["hash"].as_str().map(str::to_lowercase)
+}
+
+// Move the RPCs named in `names` to the poverty list and keep them there
+fn quarantine(rpc_list: &RwLock<Vec<Rpc>>, poverty_list: &RwLock<Vec<Rpc>>, names: &[String]) {
+    let mut rpc_list = rpc_list.write().unwrap();
+    let mut poverty_list = poverty_list.write().unwrap();
+
+    for rpc in rpc_list.iter_mut().filter(|rpc| names.contains(&rpc.name)) {
+        println!(
+            "\x1b[93mWrn:\x1b[0m Quarantining {}, it's on a different chain than the pool!",
+            rpc.name
+        );
+        rpc.status.is_erroring = true;
+        rpc.quarantined = true;
+        poverty_list.push(rpc.clone());
+    }
+    rpc_list.retain(|rpc| !rpc.quarantined);
+}
+
+// Let quarantined RPCs that aren't in `names` back out, the next health check
+// moves them back to the active list
+fn release(poverty_list: &RwLock<Vec<Rpc>>, names: &[String]) {
+    for rpc in poverty_list.write().unwrap().iter_mut() {
+        if rpc.quarantined && !names.contains(&rpc.name) {
+            println!(
+                "\x1b[35mInfo:\x1b[0m {} agrees with the pool again, releasing it from quarantine.",
+                rpc.name
+            );
+            rpc.quarantined = false;
+        }
+    }
+}
+
+async fn send_alert(client: &Client, webhook: &str, divergence: &Divergence, quarantined: bool) {
+    let alert = json!({
+        "source": "blutgang",
+        "alert": "chain_divergence",
+        "height": divergence.height,
+        "hashes": divergence.groups,
+        "majority": divergence.majority,
+        "minority": divergence.minority(),
+        "quarantined": quarantined,
+    });
+
+    if let Err(err) = client.post(webhook).json(&alert).send().await {
+        println!(
+            "\x1b[31mErr:\x1b[0m Could not send divergence alert: {}",
+            err
+        );
+    }
+}
+
+// Compare the hashes of the block `settings.depth` below the head every `settings.interval`.
+//
+// Divergences are always printed, and sent to `settings.alert_webhook` if set. We only
+// alert again once the RPCs that disagree change. With `settings.quarantine`, RPCs
+// disagreeing with a clear majority are kept out of the pool until they agree again.
+pub async fn detect_divergence(
+    rpc_list: Arc<RwLock<Vec<Rpc>>>,
+    poverty_list: Arc<RwLock<Vec<Rpc>>>,
+    blocknum_rx: watch::Receiver<u64>,
+    settings: DivergenceSettings,
+    ttl: Duration,
+) {
+    let client = Client::new();
+    let mut last_minority: Vec<String> = Vec::new();
+
+    loop {
+        sleep(settings.interval).await;
+
+        let head = *blocknum_rx.borrow();
+        if head <= settings.depth {
+            continue;
+        }
+        let height = head - settings.depth;
+
+        // Quarantined RPCs are checked too, so we notice when they're back on our chain
+        let rpcs: Vec<Rpc> = rpc_list
+            .read()
+            .unwrap()
+            .iter()
+            .chain(
+                poverty_list
+                    .read()
+                    .unwrap()
+                    .iter()
+                    .filter(|rpc| rpc.quarantined),
+            )
+            .cloned()
+            .collect();
+
+        let mut hashes = Vec::new();
+        for rpc in rpcs {
+            if let Some(hash) = block_hash(&rpc, height, ttl).await {
+                hashes.push((rpc.name.clone(), hash));
+            }
+        }
+
+        let divergence = match compare(height, hashes) {
+            Some(divergence) => divergence,
+            None => {
+                release(&poverty_list, &[]);
+                last_minority.clear();
+                continue;
+            }
+        };
+
+        let minority = divergence.minority();
+        let quarantined = settings.quarantine && divergence.majority.is_some();
+        if quarantined {
+            quarantine(&rpc_list, &poverty_list, &minority);
+            release(&poverty_list, &minority);
+        }
+
+        if minority != last_minority {
+            println!(
+                "\x1b[93mWrn:\x1b[0m RPCs disagree about block {}: {:?}",
+                height, divergence.groups
+            );
+            if let Some(webhook) = &settings.alert_webhook {
+                send_alert(&client, webhook, &divergence, quarantined).await;
+            }
+            last_minority = minority;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hashes(hashes: &[(&str, &str)]) -> Vec<(String, String)> {
+        hashes
+            .iter()
+            .map(|(name, hash)| (name.to_string(), hash.to_string()))
+            .collect()
+    }
+
+    fn rpc(name: &str) -> Rpc {
+        let mut rpc = Rpc::default();
+        rpc.name = name.to_string();
+        rpc
+    }
+
+    #[test]
+    fn test_compare() {
+        assert_eq!(
+            compare(100, hashes(&[("a", "0x1"), ("b", "0x1"), ("c", "0x1")])),
+            None
+        );
+
+        let divergence = compare(100, hashes(&[("a", "0x1"), ("b", "0x2"), ("c", "0x1")])).unwrap();
+        assert_eq!(divergence.majority.as_deref(), Some("0x1"));
+        assert_eq!(divergence.minority(), ["b"]);
+
+        // No majority to go by
+        let divergence = compare(100, hashes(&[("a", "0x1"), ("b", "0x2")])).unwrap();
+        assert_eq!(divergence.majority, None);
+        assert_eq!(divergence.minority(), ["a", "b"]);
+    }
+
+    #[test]
+    fn test_quarantine() {
+        let rpc_list = RwLock::new(vec![rpc("a"), rpc("b"), rpc("c")]);
+        let poverty_list = RwLock::new(Vec::new());
+
+        quarantine(&rpc_list, &poverty_list, &["b".to_string()]);
+        assert_eq!(rpc_list.read().unwrap().len(), 2);
+        assert!(poverty_list.read().unwrap()[0].quarantined);
+
+        // Still disagreeing
+        release(&poverty_list, &["b".to_string()]);
+        assert!(poverty_list.read().unwrap()[0].quarantined);
+
+        release(&poverty_list, &[]);
+        assert!(!poverty_list.read().unwrap()[0].quarantined);
+    }
+}
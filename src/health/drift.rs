@@ -0,0 +1,170 @@
+// Periodically check what client and modules every RPC runs, and alert when that changes
+use crate::{
+    rpc::{
+        fingerprint::{
+            Fingerprint,
+            FingerprintChange,
+        },
+        types::Protocol,
+    },
+    Rpc,
+};
+
+use std::{
+    collections::BTreeMap,
+    sync::{
+        Arc,
+        RwLock,
+    },
+    time::Duration,
+};
+
+use reqwest::Client;
+use serde_json::{
+    json,
+    Value,
+};
+use tokio::time::{
+    sleep,
+    timeout,
+};
+
+// `result` of calling `method` on `rpc`. Null if the RPC answered with an error,
+// None if it didn't answer at all, since then we can't tell what it runs.
+async fn call(rpc: &Rpc, method: &str, ttl: Duration) -> Option<Value> {
+    let tx = json!({
+        "id": 1,
+        "jsonrpc": "2.0",
+        "method": method,
+        "params": [],
+    });
+    let rx = timeout(ttl, rpc.send_request(tx)).await.ok()?.ok()?;
+    let mut rx: Value = serde_json::from_slice(&rx).ok()?;
+
+    Some(rx["result"].take())
+}
+
+// Parse the result of `rpc_modules`, `{"eth": "1.0", ...}`
+fn parse_modules(result: &Value) -> BTreeMap<String, String> {
+    let modules = match result.as_object() {
+        Some(modules) => modules,
+        None => return BTreeMap::new(),
+    };
+
+    modules
+        .iter()
+        .map(|(module, version)| {
+            let version = match version {
+                Value::String(version) => version.clone(),
+                version => version.to_string(),
+            };
+            (module.clone(), version)
+        })
+        .collect()
+}
+
+// What `rpc` runs now, or None if it didn't answer
+async fn probe(rpc: &Rpc, ttl: Duration) -> Option<Fingerprint> {
+    let fingerprint = match rpc.protocol {
+        Protocol::Evm => {
+            let version = call(rpc, "web3_clientVersion", ttl).await?;
+            let modules = call(rpc, "rpc_modules", ttl).await?;
+            Fingerprint {
+                client_version: version.as_str().map(str::to_string),
+                modules: parse_modules(&modules),
+            }
+        }
+        Protocol::Solana => {
+            let version = call(rpc, "getVersion", ttl).await?;
+            Fingerprint {
+                client_version: version["solana-core"].as_str().map(str::to_string),
+                modules: BTreeMap::new(),
+            }
+        }
+        Protocol::Bitcoin => {
+            let info = call(rpc, "getnetworkinfo", ttl).await?;
+            Fingerprint {
+                client_version: info["subversion"].as_str().map(str::to_string),
+                modules: BTreeMap::new(),
+            }
+        }
+    };
+
+    Some(fingerprint)
+}
+
+async fn send_alert(client: &Client, webhook: &str, rpc: &Rpc, change: &FingerprintChange) {
+    let alert = json!({
+        "source": "blutgang",
+        "alert": "upstream_changed",
+        "rpc": rpc.name,
+        "changes": change.from.describe_changes(&change.to),
+        "from": change.from.to_json(),
+        "to": change.to.to_json(),
+    });
+
+    if let Err(err) = client.post(webhook).json(&alert).send().await {
+        println!("\x1b[31mErr:\x1b[0m Could not send drift alert: {}", err);
+    }
+}
+
+// Fingerprint every RPC each `interval`, and alert if one runs something
+// different than last time.
+//
+// Changes are always printed, and sent to `webhook` if set. The history is
+// kept on the RPCs, for the `blutgang_versions` admin method.
+pub async fn track_drift(
+    rpc_list: Arc<RwLock<Vec<Rpc>>>,
+    poverty_list: Arc<RwLock<Vec<Rpc>>>,
+    interval: Duration,
+    ttl: Duration,
+    webhook: Option<String>,
+) {
+    let client = Client::new();
+
+    loop {
+        let rpcs: Vec<Rpc> = rpc_list
+            .read()
+            .unwrap()
+            .iter()
+            .chain(poverty_list.read().unwrap().iter())
+            .cloned()
+            .collect();
+
+        for rpc in rpcs {
+            let fingerprint = match probe(&rpc, ttl).await {
+                Some(fingerprint) => fingerprint,
+                None => continue,
+            };
+            let change = match rpc.fingerprints().record(fingerprint) {
+                Some(change) => change,
+                None => continue,
+            };
+
+            println!(
+                "\x1b[93mWrn:\x1b[0m {} changed: {}",
+                rpc.name,
+                change.from.describe_changes(&change.to).join(", ")
+            );
+            if let Some(webhook) = &webhook {
+                send_alert(&client, webhook, &rpc, &change).await;
+            }
+        }
+
+        sleep(interval).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_modules() {
+        let modules = parse_modules(&json!({"eth": "1.0", "net": 1.0}));
+        assert_eq!(modules["eth"], "1.0");
+        assert_eq!(modules["net"], "1.0");
+
+        assert!(parse_modules(&Value::Null).is_empty());
+    }
+}
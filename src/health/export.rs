@@ -0,0 +1,82 @@
+use crate::Rpc;
+
+use serde_json::{
+    json,
+    Value,
+};
+
+// Highest weight we hand out, same as the fastest RPC
+const MAX_WEIGHT: f64 = 100.0;
+
+// Weight RPCs relative to the fastest one, similar to SRV records.
+//
+// RPCs we have no latency data for yet get the max weight.
+fn weight(latency: f64, fastest: f64) -> u64 {
+    if latency <= 0.0 || fastest <= 0.0 {
+        return MAX_WEIGHT as u64;
+    }
+
+    ((fastest / latency) * MAX_WEIGHT)
+        .round()
+        .clamp(1.0, MAX_WEIGHT) as u64
+}
+
+// List healthy upstreams along with their weights, so external
+// systems that connect to nodes directly can use them.
+//
+// Only RPCs in the active list are included, RPCs in poverty or paused by
+// the schedule are not.
+pub fn healthy_upstreams(rpc_list: &[Rpc]) -> Value {
+    let fastest = rpc_list
+        .iter()
+        .map(|rpc| rpc.status.latency)
+        .filter(|latency| *latency > 0.0)
+        .fold(f64::INFINITY, f64::min);
+
+    let upstreams: Vec<Value> = rpc_list
+        .iter()
+        .filter(|rpc| !rpc.status.is_erroring && !rpc.paused)
+        .map(|rpc| {
+            json!({
+                "name": rpc.name,
+                "target": rpc.url,
+                "priority": 0,
+                "weight": weight(rpc.status.latency, fastest),
+                "latency": rpc.status.latency,
+            })
+        })
+        .collect();
+
+    json!({ "upstreams": upstreams })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_weight() {
+        assert_eq!(weight(0.0, 10.0), 100);
+        assert_eq!(weight(10.0, 10.0), 100);
+        assert_eq!(weight(20.0, 10.0), 50);
+        assert_eq!(weight(100_000.0, 10.0), 1);
+    }
+
+    #[test]
+    fn test_healthy_upstreams() {
+        let mut fast = Rpc::new("http://fast".to_string(), 5, 5.0);
+        fast.update_latency(10.0);
+        let mut slow = Rpc::new("http://slow".to_string(), 5, 5.0);
+        slow.update_latency(40.0);
+        let mut erroring = Rpc::new("http://erroring".to_string(), 5, 5.0);
+        erroring.status.is_erroring = true;
+
+        let rx = healthy_upstreams(&[fast, slow, erroring]);
+        let upstreams = rx["upstreams"].as_array().unwrap();
+
+        assert_eq!(upstreams.len(), 2);
+        assert_eq!(upstreams[0]["target"], "http://fast");
+        assert_eq!(upstreams[0]["weight"], 100);
+        assert_eq!(upstreams[1]["weight"], 25);
+    }
+}
@@ -0,0 +1,95 @@
+// Check RPCs over IPv4 and IPv6 separately, so we know when one of them is degraded.
+//
+// Only RPCs whose hostname resolves to both families are checked, for everything
+// else there is nothing to choose between. The results decide which family
+// RPCs with `ip_preference = "auto"` connect over first.
+use crate::{
+    rpc::dial::Family,
+    Rpc,
+    Settings,
+};
+
+use std::{
+    sync::{
+        Arc,
+        RwLock,
+    },
+    time::{
+        Duration,
+        Instant,
+    },
+};
+
+use tokio::{
+    net::lookup_host,
+    task::JoinSet,
+    time::{
+        sleep,
+        timeout,
+    },
+};
+use url::Url;
+
+// Families `url` resolves to, if it's a hostname and not an IP
+async fn resolved_families(url: &str) -> Vec<Family> {
+    let url = match Url::parse(url) {
+        Ok(url) => url,
+        Err(_) => return Vec::new(),
+    };
+    let host = match url.host() {
+        Some(url::Host::Domain(host)) => host.to_string(),
+        _ => return Vec::new(),
+    };
+    let port = url.port_or_known_default().unwrap_or(0);
+
+    let mut families = Vec::new();
+    if let Ok(addrs) = lookup_host((host, port)).await {
+        for addr in addrs {
+            let family = Family::of(&addr);
+            if !families.contains(&family) {
+                families.push(family);
+            }
+        }
+    }
+
+    families
+}
+
+async fn check_rpc(rpc: Rpc, request_timeout: Duration) {
+    let families = resolved_families(&rpc.url).await;
+    if families.len() < 2 {
+        return;
+    }
+
+    for family in families {
+        let start = Instant::now();
+        match timeout(request_timeout, rpc.check_family(family)).await {
+            Ok(Ok(_)) => {
+                rpc.family_health()
+                    .record_success(family, start.elapsed().as_secs_f64() * 1000.0)
+            }
+            _ => rpc.family_health().record_failure(family),
+        }
+    }
+}
+
+pub async fn check_families(
+    rpc_list: Arc<RwLock<Vec<Rpc>>>,
+    poverty_list: Arc<RwLock<Vec<Rpc>>>,
+    config: Arc<RwLock<Settings>>,
+) {
+    loop {
+        let health_check_ttl = Duration::from_millis(config.read().unwrap().health_check_ttl);
+        sleep(health_check_ttl).await;
+
+        // RPCs in poverty too, they might be there because of one of the families
+        let mut rpcs: Vec<Rpc> = rpc_list.read().unwrap().clone();
+        rpcs.extend(poverty_list.read().unwrap().iter().cloned());
+
+        let mut checks = JoinSet::new();
+        for rpc in rpcs {
+            checks.spawn(check_rpc(rpc, health_check_ttl));
+        }
+        while checks.join_next().await.is_some() {}
+    }
+}
@@ -0,0 +1,145 @@
+// Time the TCP connect and TLS handshake to every RPC every `interval`.
+//
+// reqwest connects on its own, so we open a connection of our own to each RPC and
+// time it the same way reqwest would connect: resolve, connect and do the handshake
+// with native-tls. The connection is closed right after, no request goes over it.
+use crate::{
+    rpc::timing::Phase,
+    Rpc,
+};
+
+use std::{
+    net::SocketAddr,
+    sync::{
+        Arc,
+        RwLock,
+    },
+    time::{
+        Duration,
+        Instant,
+    },
+};
+
+use tokio::{
+    net::TcpStream,
+    task::JoinSet,
+    time::{
+        sleep,
+        timeout,
+    },
+};
+use tokio_native_tls::{
+    native_tls,
+    TlsConnector,
+};
+use url::Url;
+
+// Host, port and if we'd talk TLS to `url`
+fn endpoint(url: &str) -> Option<(String, u16, bool)> {
+    let url = Url::parse(url).ok()?;
+    let tls = match url.scheme() {
+        "https" | "wss" => true,
+        "http" | "ws" => false,
+        _ => return None,
+    };
+
+    Some((
+        url.host_str()?.to_string(),
+        url.port_or_known_default()?,
+        tls,
+    ))
+}
+
+// Time connecting to `rpc`, recording how long each phase took
+async fn measure(rpc: &Rpc) -> Result<(), String> {
+    let (host, port, tls) = endpoint(&rpc.url).ok_or("not an http(s) url")?;
+
+    let resolving = Instant::now();
+    let addrs: Vec<SocketAddr> = tokio::net::lookup_host((host.as_str(), port))
+        .await
+        .map_err(|err| err.to_string())?
+        .collect();
+    rpc.timings().record(Phase::Dns, resolving.elapsed());
+
+    let connecting = Instant::now();
+    let stream = TcpStream::connect(&addrs[..])
+        .await
+        .map_err(|err| err.to_string())?;
+    rpc.timings().record(Phase::Connect, connecting.elapsed());
+
+    if tls {
+        let connector = native_tls::TlsConnector::new().map_err(|err| err.to_string())?;
+        let handshaking = Instant::now();
+        TlsConnector::from(connector)
+            .connect(&host, stream)
+            .await
+            .map_err(|err| err.to_string())?;
+        rpc.timings().record(Phase::Tls, handshaking.elapsed());
+    }
+
+    Ok(())
+}
+
+pub async fn measure_handshakes(rpc_list: Arc<RwLock<Vec<Rpc>>>, interval: Duration) {
+    loop {
+        let rpcs: Vec<Rpc> = rpc_list
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|rpc| !rpc.paused)
+            .cloned()
+            .collect();
+
+        let mut handshakes = JoinSet::new();
+        for rpc in rpcs {
+            handshakes.spawn(async move {
+                match timeout(interval, measure(&rpc)).await {
+                    Ok(Ok(())) => {}
+                    Ok(Err(err)) => {
+                        println!(
+                            "\x1b[93mWrn:\x1b[0m Could not time connecting to {}: {}",
+                            rpc.name, err
+                        );
+                    }
+                    Err(_) => {
+                        println!("\x1b[93mWrn:\x1b[0m Connecting to {} timed out", rpc.name);
+                    }
+                }
+            });
+        }
+        while handshakes.join_next().await.is_some() {}
+
+        sleep(interval).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_endpoint() {
+        assert_eq!(
+            endpoint("https://eth.llamarpc.com"),
+            Some(("eth.llamarpc.com".to_string(), 443, true))
+        );
+        assert_eq!(
+            endpoint("http://127.0.0.1:8545/rpc"),
+            Some(("127.0.0.1".to_string(), 8545, false))
+        );
+        assert_eq!(endpoint("ipc:///tmp/geth.ipc"), None);
+    }
+
+    #[tokio::test]
+    async fn test_measure() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let rpc = Rpc::new(format!("http://{}", address), 1, 1.0);
+
+        measure(&rpc).await.unwrap();
+        assert_eq!(rpc.timings().get(Phase::Connect).count, 1);
+        assert_eq!(rpc.timings().get(Phase::Dns).count, 1);
+        // Plain http, no handshake
+        assert_eq!(rpc.timings().get(Phase::Tls).count, 0);
+    }
+}
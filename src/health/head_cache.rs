@@ -1,24 +1,36 @@
+use crate::health::tombstone::{
+    bury,
+    purge,
+};
+
 use std::{
     collections::BTreeMap,
     sync::{
         Arc,
         RwLock,
     },
+    time::Duration,
 };
 
-use sled::Batch;
 use tokio_stream::{
     wrappers::WatchStream,
     StreamExt,
 };
 
+// Block number -> cache keys of the responses we cached for it, until it's finalized
+pub type HeadCache = RwLock<BTreeMap<u64, Vec<Vec<u8>>>>;
+
 // Check if we need to do a reorg or if a new block has finalized.
+//
+// Reorged entries are kept as tombstones for `tombstone_retention` if it isn't zero.
 pub async fn manage_cache(
-    head_cache: &Arc<RwLock<BTreeMap<u64, Vec<String>>>>,
+    head_cache: &Arc<HeadCache>,
     blocknum_rx: tokio::sync::watch::Receiver<u64>,
     finalized_rx: Arc<tokio::sync::watch::Receiver<u64>>,
     cache: &Arc<sled::Db>,
+    tombstone_retention: Duration,
 ) -> Result<(), sled::Error> {
+    let keep = !tombstone_retention.is_zero();
     let mut block_number = 0;
     let mut last_finalized = 0;
 
@@ -33,7 +45,7 @@ pub async fn manage_cache(
         // remove everything from the last block to the `new_block`
         if new_block <= block_number {
             println!("\x1b[93mWrn:\x1b[0m Reorg detected!\nRemoving stale entries from the cache.");
-            handle_reorg(head_cache, block_number, new_block, cache)?;
+            handle_reorg(head_cache, new_block, block_number, cache, keep)?;
         }
 
         // Check if finalized_stream has changed
@@ -44,6 +56,9 @@ pub async fn manage_cache(
             );
             // Remove stale entries from the head_cache
             remove_stale(head_cache, last_finalized)?;
+            if keep {
+                purge(cache, tombstone_retention)?;
+            }
         }
 
         block_number = new_block;
@@ -53,30 +68,27 @@ pub async fn manage_cache(
 
 // We use the head_cache to store keys of querries we made near the tip
 // If a reorg happens, we need to remove all querries in the reorg range
-// from the sled database.
-fn handle_reorg(
-    head_cache: &Arc<RwLock<BTreeMap<u64, Vec<String>>>>,
+// from the sled database, keeping them as tombstones if `keep` is set.
+pub fn handle_reorg(
+    head_cache: &Arc<HeadCache>,
     block_number: u64,
     new_block: u64,
     cache: &Arc<sled::Db>,
+    keep: bool,
 ) -> Result<(), sled::Error> {
-    // sled batch
-    let mut batch = Batch::default();
-
     // Go over the head cache and get all the keys from block_number to new_block
+    let mut keys = Vec::new();
     let mut head_cache_guard = head_cache.write().unwrap();
     for i in block_number..new_block + 1 {
-        if let Some(keys) = head_cache_guard.get(&i) {
-            for key in keys {
-                batch.remove(key.as_bytes());
-            }
-            // Remove the entry from the head_cache
-            head_cache_guard.remove(&i);
+        // Remove the entry from the head_cache
+        if let Some(block_keys) = head_cache_guard.remove(&i) {
+            keys.extend(block_keys);
         }
     }
+    drop(head_cache_guard);
 
-    // Apply the batch to the cache
-    cache.apply_batch(batch)?;
+    let depth = new_block.saturating_sub(block_number) + 1;
+    bury(cache, &keys, depth, keep)?;
 
     Ok(())
 }
@@ -85,10 +97,7 @@ fn handle_reorg(
 //
 // Once a new block finalizes, we can be sure that certain TXs wont
 // reorg, so theyre safe to be permanantly in the cache.
-fn remove_stale(
-    head_cache: &Arc<RwLock<BTreeMap<u64, Vec<String>>>>,
-    block_number: u64,
-) -> Result<(), sled::Error> {
+fn remove_stale(head_cache: &Arc<HeadCache>, block_number: u64) -> Result<(), sled::Error> {
     // Get the lowest block_number from the BTreeMap
     let mut head_cache_guard = head_cache.write().unwrap();
 
@@ -108,6 +117,7 @@ fn remove_stale(
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::health::tombstone::list;
     use sled::Config;
 
     // #[tokio::test]
@@ -148,13 +158,13 @@ mod tests {
         // Add some data to the head_cache
         {
             let mut head_cache_guard = head_cache.write().unwrap();
-            head_cache_guard.insert(1, vec!["key1".to_string()]);
-            head_cache_guard.insert(2, vec!["key2".to_string()]);
-            head_cache_guard.insert(3, vec!["key3".to_string()]);
+            head_cache_guard.insert(1, vec![b"key1".to_vec()]);
+            head_cache_guard.insert(2, vec![b"key2".to_vec()]);
+            head_cache_guard.insert(3, vec![b"key3".to_vec()]);
         }
 
         // Call handle_reorg
-        let result = handle_reorg(&head_cache, 2, 3, &cache);
+        let result = handle_reorg(&head_cache, 2, 3, &cache, false);
 
         // Verify the result and check if the data is removed from the cache
         assert!(result.is_ok());
@@ -172,6 +182,28 @@ mod tests {
         assert!(key3.is_none());
     }
 
+    #[test]
+    fn test_handle_reorg_tombstones() {
+        let head_cache = Arc::new(RwLock::new(BTreeMap::new()));
+        let cache = Arc::new(Config::new().temporary(true).open().unwrap());
+        let _ = cache.insert("key1", "value1");
+        head_cache
+            .write()
+            .unwrap()
+            .insert(1, vec![b"key1".to_vec()]);
+
+        handle_reorg(&head_cache, 1, 2, &cache, true).unwrap();
+
+        // Gone from the cache, but still around
+        assert!(cache.get("key1").unwrap().is_none());
+        let tombstones = list(&cache, 10).unwrap();
+        assert_eq!(tombstones.len(), 1);
+        let (key, tombstone) = &tombstones[0];
+        assert_eq!(&key[..], b"key1");
+        assert_eq!(tombstone.depth, 2);
+        assert_eq!(&tombstone.data[..], b"value1");
+    }
+
     #[test]
     fn test_remove_stale() {
         // Create test data and resources
@@ -180,8 +212,8 @@ mod tests {
         // Add some data to the head_cache
         {
             let mut head_cache_guard = head_cache.write().unwrap();
-            head_cache_guard.insert(1, vec!["key1".to_string()]);
-            head_cache_guard.insert(2, vec!["key2".to_string()]);
+            head_cache_guard.insert(1, vec![b"key1".to_vec()]);
+            head_cache_guard.insert(2, vec![b"key2".to_vec()]);
         }
 
         // Call remove_stale
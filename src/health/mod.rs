@@ -1,4 +1,18 @@
 pub mod check;
+pub mod clock;
+pub mod divergence;
+pub mod drift;
 pub mod error;
+pub mod export;
+pub mod families;
+pub mod handshake;
 pub mod head_cache;
+pub mod prewarm;
+pub mod probe;
+pub mod reorg;
 pub mod safe_block;
+pub mod self_test;
+pub mod sla;
+pub mod standby;
+pub mod tombstone;
+pub mod watchdog;
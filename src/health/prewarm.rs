@@ -0,0 +1,74 @@
+// Keep connections to RPCs open so requests after idle periods don't have to connect first.
+//
+// Every `interval` we send `rpc.prewarm` cheap requests to each active RPC at the same
+// time. Each of them needs its own connection, so the pool ends up with that many open
+// ones, and because they're used regularly they don't get closed for being idle.
+use crate::Rpc;
+
+use std::{
+    sync::{
+        Arc,
+        RwLock,
+    },
+    time::Duration,
+};
+
+use tokio::{
+    task::JoinSet,
+    time::{
+        sleep,
+        timeout,
+    },
+};
+
+// Open `rpc.prewarm` connections to `rpc`, or use the ones that are already open
+async fn prewarm(rpc: Rpc, request_timeout: Duration) {
+    let mut requests = JoinSet::new();
+    for _ in 0..rpc.prewarm {
+        let rpc = rpc.clone();
+        requests.spawn(async move {
+            matches!(
+                timeout(request_timeout, rpc.block_number()).await,
+                Ok(Ok(_))
+            )
+        });
+    }
+
+    let mut warm = 0;
+    let mut failed = 0;
+    while let Some(result) = requests.join_next().await {
+        match result {
+            Ok(true) => warm += 1,
+            _ => failed += 1,
+        }
+    }
+
+    if failed > 0 {
+        println!(
+            "\x1b[93mWrn:\x1b[0m Could only prewarm {}/{} connections to {}",
+            warm, rpc.prewarm, rpc.name
+        );
+    }
+    rpc.pool_stats().record_prewarm(warm, failed);
+}
+
+pub async fn prewarm_connections(rpc_list: Arc<RwLock<Vec<Rpc>>>, interval: Duration) {
+    loop {
+        // RPCs in poverty or paused by the schedule aren't getting requests anyway
+        let rpcs: Vec<Rpc> = rpc_list
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|rpc| rpc.prewarm > 0 && !rpc.paused)
+            .cloned()
+            .collect();
+
+        let mut rounds = JoinSet::new();
+        for rpc in rpcs {
+            rounds.spawn(prewarm(rpc, interval));
+        }
+        while rounds.join_next().await.is_some() {}
+
+        sleep(interval).await;
+    }
+}
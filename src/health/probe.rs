@@ -0,0 +1,164 @@
+// Run the probes set up on each RPC every `interval`, and mark the RPCs failing them as
+// degraded so the balancer avoids them. They're marked healthy again once they pass.
+use crate::{
+    rpc::probe::{
+        run_probe,
+        ProbeConfig,
+        ProbeResult,
+    },
+    Rpc,
+};
+
+use std::{
+    collections::HashMap,
+    sync::{
+        Arc,
+        RwLock,
+    },
+    time::{
+        Duration,
+        Instant,
+    },
+};
+
+use tokio::{
+    sync::watch,
+    time::sleep,
+};
+
+// How often we look for RPCs that are due, so intervals are only as precise as this
+const TICK: Duration = Duration::from_secs(1);
+
+// Results of probing `rpc`. Probes that fail are left out, unresponsive RPCs are for
+// the head checks to deal with.
+async fn probe(rpc: &Rpc, config: &ProbeConfig, ttl: Duration) -> Vec<ProbeResult> {
+    let mut results = Vec::with_capacity(config.probes.len());
+    for probe in &config.probes {
+        if let Ok(result) = run_probe(rpc, *probe, ttl).await {
+            results.push(result);
+        }
+    }
+    results
+}
+
+// Highest head any probe or the pool saw
+fn best_head(pool_head: u64, results: &[(String, ProbeConfig, Vec<ProbeResult>)]) -> u64 {
+    results
+        .iter()
+        .flat_map(|(_, _, results)| results)
+        .filter_map(|result| {
+            match result {
+                ProbeResult::Head(head) => Some(*head),
+                _ => None,
+            }
+        })
+        .fold(pool_head, u64::max)
+}
+
+// Mark `name` as degraded if there are `reasons` to, and healthy otherwise
+fn set_degraded(rpc_list: &RwLock<Vec<Rpc>>, name: &str, reasons: &[String]) {
+    let mut rpc_list_guard = rpc_list.write().unwrap();
+    let rpc = match rpc_list_guard.iter_mut().find(|rpc| rpc.name == name) {
+        Some(rpc) => rpc,
+        None => return,
+    };
+
+    match (rpc.degraded, reasons.is_empty()) {
+        (false, false) => {
+            println!(
+                "\x1b[93mWrn:\x1b[0m {} is degraded: {}",
+                name,
+                reasons.join(", ")
+            );
+        }
+        (true, true) => println!("\x1b[35mInfo:\x1b[0m {} passes its probes again", name),
+        _ => {}
+    }
+    rpc.degraded = !reasons.is_empty();
+}
+
+pub async fn run_probes(
+    rpc_list: Arc<RwLock<Vec<Rpc>>>,
+    blocknum_rx: watch::Receiver<u64>,
+    ttl: Duration,
+) {
+    let mut last_probed: HashMap<String, Instant> = HashMap::new();
+
+    loop {
+        sleep(TICK).await;
+
+        let due: Vec<(Rpc, ProbeConfig)> = rpc_list
+            .read()
+            .unwrap()
+            .iter()
+            .filter_map(|rpc| {
+                let config = rpc.probes.clone()?;
+                let due = last_probed
+                    .get(&rpc.name)
+                    .map_or(true, |at| at.elapsed() >= config.interval);
+                due.then(|| (rpc.clone(), config))
+            })
+            .collect();
+        if due.is_empty() {
+            continue;
+        }
+
+        let mut probes = Vec::with_capacity(due.len());
+        for (rpc, config) in due {
+            last_probed.insert(rpc.name.clone(), Instant::now());
+            probes.push(tokio::spawn(async move {
+                let results = probe(&rpc, &config, ttl).await;
+                (rpc.name, config, results)
+            }));
+        }
+        let mut results = Vec::with_capacity(probes.len());
+        for probe in probes {
+            if let Ok(result) = probe.await {
+                results.push(result);
+            }
+        }
+
+        let best_head = best_head(*blocknum_rx.borrow(), &results);
+        for (name, config, results) in &results {
+            set_degraded(&rpc_list, name, &config.degraded_by(results, best_head));
+        }
+
+        // Forget RPCs that were removed
+        let rpc_list_guard = rpc_list.read().unwrap();
+        last_probed.retain(|name, _| rpc_list_guard.iter().any(|rpc| &rpc.name == name));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_set_degraded() {
+        let mut rpc = Rpc::default();
+        rpc.name = "llama".to_string();
+        let rpc_list = RwLock::new(vec![rpc]);
+
+        set_degraded(&rpc_list, "llama", &["syncing".to_string()]);
+        assert!(rpc_list.read().unwrap()[0].degraded);
+        set_degraded(&rpc_list, "llama", &[]);
+        assert!(!rpc_list.read().unwrap()[0].degraded);
+        // Unknown RPCs are ignored
+        set_degraded(&rpc_list, "infura", &["syncing".to_string()]);
+    }
+
+    #[test]
+    fn test_best_head() {
+        let config = ProbeConfig::default();
+        let results = vec![
+            (
+                "a".to_string(),
+                config.clone(),
+                vec![ProbeResult::Head(110)],
+            ),
+            ("b".to_string(), config, vec![ProbeResult::Syncing(false)]),
+        ];
+        assert_eq!(best_head(100, &results), 110);
+        assert_eq!(best_head(120, &results), 120);
+    }
+}
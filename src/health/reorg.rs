@@ -0,0 +1,272 @@
+// Catch reorgs by the hashes RPCs report, and drop what we cached from the blocks that changed.
+//
+// `manage_cache` only notices reorgs that make the head go back. Most reorgs replace
+// blocks without the head ever going back, so here we remember the last hashes every
+// RPC gave us and check that each new head still builds on them. When one doesn't,
+// we walk back to the last block that still matches and purge everything above it.
+use crate::{
+    config::types::ReorgSettings,
+    health::head_cache::{
+        handle_reorg,
+        HeadCache,
+    },
+    Rpc,
+};
+
+use std::{
+    collections::{
+        BTreeMap,
+        HashMap,
+    },
+    future::Future,
+    sync::{
+        Arc,
+        RwLock,
+    },
+    time::Duration,
+};
+
+use serde_json::{
+    json,
+    Value,
+};
+use tokio::{
+    sync::watch,
+    time::timeout,
+};
+use tokio_stream::{
+    wrappers::WatchStream,
+    StreamExt,
+};
+
+#[derive(Debug, Clone, PartialEq)]
+struct Header {
+    number: u64,
+    hash: String,
+    parent_hash: String,
+}
+
+// Hashes of the last blocks one RPC gave us, by number
+#[derive(Debug, Default)]
+struct Chain {
+    hashes: BTreeMap<u64, String>,
+}
+
+impl Chain {
+    // First block we knew that `header` replaces, None if it builds on what we know
+    fn diverges_at(&self, header: &Header) -> Option<u64> {
+        if let Some(hash) = self.hashes.get(&header.number) {
+            if *hash != header.hash {
+                return Some(header.number);
+            }
+        }
+        match self.hashes.get(&header.number.checked_sub(1)?) {
+            Some(parent) if *parent != header.parent_hash => Some(header.number - 1),
+            _ => None,
+        }
+    }
+
+    // Remember `header`, forgetting blocks more than `depth` below the newest one
+    fn record(&mut self, header: Header, depth: u64) {
+        self.hashes.insert(header.number, header.hash);
+
+        let newest = *self.hashes.keys().next_back().unwrap();
+        let oldest = newest.saturating_sub(depth);
+        self.hashes = self.hashes.split_off(&oldest);
+    }
+
+    fn newest(&self) -> Option<u64> {
+        self.hashes.keys().next_back().copied()
+    }
+}
+
+// Walk back from `from` until the hash `fetch` gives us is the one we know.
+//
+// Returns the highest block that didn't change, or None if nothing we remember still
+// matches, along with the headers we fetched on the way.
+async fn find_fork<F, Fut>(chain: &Chain, from: u64, fetch: F) -> (Option<u64>, Vec<Header>)
+where
+    F: Fn(u64) -> Fut,
+    Fut: Future<Output = Option<Header>>,
+{
+    let mut fetched = Vec::new();
+    let oldest = match chain.hashes.keys().next() {
+        Some(oldest) => *oldest,
+        None => return (None, fetched),
+    };
+
+    for number in (oldest..=from).rev() {
+        let known = match chain.hashes.get(&number) {
+            Some(known) => known,
+            None => continue,
+        };
+        let header = match fetch(number).await {
+            Some(header) => header,
+            // Can't tell, so assume everything above it changed
+            None => return (Some(number), fetched),
+        };
+        if header.hash == *known {
+            return (Some(number), fetched);
+        }
+        fetched.push(header);
+    }
+
+    (None, fetched)
+}
+
+// Header of block `number` according to `rpc`
+async fn header(rpc: &Rpc, number: u64, ttl: Duration) -> Option<Header> {
+    let tx = json!({
+        "id": 1,
+        "jsonrpc": "2.0",
+        "method": "eth_getBlockByNumber",
+        "params": [format!("{:#x}", number), false],
+    });
+    let rx = timeout(ttl, rpc.send_request(tx)).await.ok()?.ok()?;
+    let rx: Value = serde_json::from_slice(&rx).ok()?;
+
+    let block = &rx["result"];
+    Some(Header {
+        number: u64::from_str_radix(block["number"].as_str()?.trim_start_matches("0x"), 16).ok()?,
+        hash: block["hash"].as_str()?.to_lowercase(),
+        parent_hash: block["parentHash"].as_str()?.to_lowercase(),
+    })
+}
+
+// Check the new head of every RPC against the last `settings.depth` hashes it gave us.
+//
+// Cached responses for the blocks that changed get removed through `head_cache`, like
+// the ones `manage_cache` removes when the head goes back.
+pub async fn detect_reorgs(
+    rpc_list: Arc<RwLock<Vec<Rpc>>>,
+    blocknum_rx: watch::Receiver<u64>,
+    head_cache: Arc<HeadCache>,
+    cache: Arc<sled::Db>,
+    settings: ReorgSettings,
+    ttl: Duration,
+) {
+    let mut chains: HashMap<String, Chain> = HashMap::new();
+    let mut blocknum_stream = WatchStream::new(blocknum_rx.clone());
+
+    while blocknum_stream.next().await.is_some() {
+        let head = *blocknum_rx.borrow();
+        if head == 0 {
+            continue;
+        }
+
+        let rpcs: Vec<Rpc> = rpc_list.read().unwrap().clone();
+        chains.retain(|name, _| rpcs.iter().any(|rpc| &rpc.name == name));
+
+        for rpc in rpcs {
+            // RPCs that don't have the head yet get checked on the next one
+            let header = match header(&rpc, head, ttl).await {
+                Some(header) => header,
+                None => continue,
+            };
+            let chain = chains.entry(rpc.name.clone()).or_default();
+
+            if let Some(changed) = chain.diverges_at(&header) {
+                let (fork, fetched) =
+                    find_fork(chain, changed, |number| self::header(&rpc, number, ttl)).await;
+                let from = match fork {
+                    Some(fork) => fork + 1,
+                    None => *chain.hashes.keys().next().unwrap(),
+                };
+                let to = chain.newest().unwrap_or(head).max(head);
+
+                println!(
+                    "\x1b[93mWrn:\x1b[0m Reorg on {}: blocks {} to {} changed, removing them from the cache.",
+                    rpc.name, from, to
+                );
+                let keep = settings.tombstone_retention > 0;
+                if let Err(err) = handle_reorg(&head_cache, from, to, &cache, keep) {
+                    println!(
+                        "\x1b[31mErr:\x1b[0m Could not remove reorged entries: {}",
+                        err
+                    );
+                }
+
+                // What we knew above the fork is gone, replace it with what we just fetched
+                chain.hashes.retain(|number, _| *number < from);
+                for header in fetched {
+                    chain.record(header, settings.depth);
+                }
+            }
+
+            chain.record(header, settings.depth);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(number: u64, hash: &str, parent_hash: &str) -> Header {
+        Header {
+            number,
+            hash: hash.to_string(),
+            parent_hash: parent_hash.to_string(),
+        }
+    }
+
+    fn chain(hashes: &[(u64, &str)]) -> Chain {
+        Chain {
+            hashes: hashes
+                .iter()
+                .map(|(number, hash)| (*number, hash.to_string()))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_diverges_at() {
+        let known = chain(&[(10, "0xa"), (11, "0xb")]);
+
+        assert_eq!(known.diverges_at(&header(12, "0xc", "0xb")), None);
+        // New parent
+        assert_eq!(known.diverges_at(&header(12, "0xc", "0xf")), Some(11));
+        // Same height, new hash
+        assert_eq!(known.diverges_at(&header(11, "0xf", "0xa")), Some(11));
+        // Nothing to compare against
+        assert_eq!(known.diverges_at(&header(20, "0xc", "0xf")), None);
+        assert_eq!(Chain::default().diverges_at(&header(0, "0xa", "0x0")), None);
+    }
+
+    #[test]
+    fn test_record() {
+        let mut known = Chain::default();
+        for number in 0..10 {
+            known.record(header(number, &format!("0x{}", number), ""), 3);
+        }
+        assert_eq!(
+            known.hashes.keys().copied().collect::<Vec<_>>(),
+            [6, 7, 8, 9]
+        );
+        assert_eq!(known.newest(), Some(9));
+    }
+
+    #[tokio::test]
+    async fn test_find_fork() {
+        let known = chain(&[(10, "0xa"), (11, "0xb"), (12, "0xc"), (13, "0xd")]);
+        // The RPC now has a new 12 and 13
+        let new = chain(&[(10, "0xa"), (11, "0xb"), (12, "0xc2"), (13, "0xd2")]);
+        let fetch = |number: u64| {
+            let hash = new.hashes.get(&number).cloned();
+            async move { hash.map(|hash| header(number, &hash, "")) }
+        };
+
+        let (fork, fetched) = find_fork(&known, 13, fetch).await;
+        assert_eq!(fork, Some(11));
+        assert_eq!(
+            fetched,
+            vec![header(13, "0xd2", ""), header(12, "0xc2", "")]
+        );
+
+        // Nothing matches anymore
+        let fetch = |number: u64| async move { Some(header(number, "0xf", "")) };
+        let (fork, fetched) = find_fork(&known, 13, fetch).await;
+        assert_eq!(fork, None);
+        assert_eq!(fetched.len(), 4);
+    }
+}
@@ -50,7 +50,6 @@ pub async fn get_safe_block(
         let rpc_clone = rpc_list.read().unwrap()[i].clone();
         let tx = tx.clone(); // Clone the sender for this RPC
 
-
         // Spawn a future for each RPC
         let rpc_future = async move {
             let a = rpc_clone.get_finalized_block();
@@ -58,7 +57,7 @@ pub async fn get_safe_block(
 
             let reported_finalized = match result {
                 Ok(response) => response.unwrap_or_default(), // Handle timeout as 0
-                Err(_) => 0,                       // Handle timeout as 0
+                Err(_) => 0,                                  // Handle timeout as 0
             };
 
             // Send the result to the main thread through the channel
@@ -98,7 +97,12 @@ pub async fn get_safe_block(
     // println!("Safe block: {}", safe);
 
     // Return as NamedBlocknumbers
+    //
+    // We only ask RPCs for the finalized block. `safe` is never behind it, so requests
+    // tagged `safe` get the finalized block, which is older than what the RPCs would
+    // use but is safe too.
     let mut nn_rwlock = named_numbers_rwlock.write().unwrap();
+    nn_rwlock.finalized = safe;
     nn_rwlock.safe = safe;
 
     Ok(safe)
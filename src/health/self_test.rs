@@ -0,0 +1,348 @@
+// `--self-test` sends a battery of real requests through the full request pipeline
+// and prints a pass/fail report. Meant to be used as a deployment gate, so we exit
+// with a non-zero code if anything fails.
+use crate::{
+    balancer::block_cache::request_hash,
+    rpc::types::{
+        hex_to_decimal,
+        Protocol,
+    },
+    Rpc,
+};
+
+use std::{
+    net::SocketAddr,
+    time::{
+        Duration,
+        Instant,
+    },
+};
+
+use reqwest::Client;
+use serde_json::{
+    json,
+    Value,
+};
+use sled::Db;
+
+#[cfg(feature = "xxhash")]
+use zerocopy::AsBytes; // Impls AsBytes trait for u64
+
+// How far behind the head the block we test caching with is, so it's not reorged out
+const CACHE_TEST_DEPTH: u64 = 64;
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CheckStatus {
+    Pass,
+    Fail,
+    Skip,
+}
+
+#[derive(Debug, Clone)]
+pub struct CheckResult {
+    pub name: String,
+    pub status: CheckStatus,
+    pub detail: String,
+    pub time: Duration,
+}
+
+impl CheckResult {
+    fn new(name: &str, outcome: Result<String, String>, time: Duration) -> Self {
+        let (status, detail) = match outcome {
+            Ok(detail) => (CheckStatus::Pass, detail),
+            Err(detail) => (CheckStatus::Fail, detail),
+        };
+
+        Self {
+            name: name.to_string(),
+            status,
+            detail,
+            time,
+        }
+    }
+
+    fn skip(name: &str, reason: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            status: CheckStatus::Skip,
+            detail: reason.to_string(),
+            time: Duration::ZERO,
+        }
+    }
+}
+
+// Get the result out of a response, or describe what's wrong with it
+fn check_response(rx: &Value) -> Result<&Value, String> {
+    if !rx["error"].is_null() {
+        return Err(format!("error response: {}", rx["error"]));
+    }
+
+    match rx.get("result") {
+        Some(result) => Ok(result),
+        None => Err(format!("response has no result: {}", rx)),
+    }
+}
+
+struct SelfTest {
+    client: Client,
+    url: String,
+    id: u64,
+}
+
+impl SelfTest {
+    async fn call(&mut self, method: &str, params: Value) -> Result<Value, String> {
+        self.id += 1;
+        let tx = json!({
+            "id": self.id,
+            "jsonrpc": "2.0",
+            "method": method,
+            "params": params,
+        });
+
+        let rx: Value = self
+            .client
+            .post(&self.url)
+            .json(&tx)
+            .send()
+            .await
+            .map_err(|err| err.to_string())?
+            .json()
+            .await
+            .map_err(|err| err.to_string())?;
+
+        if rx["id"] != self.id {
+            return Err(format!("response has the wrong id: {}", rx["id"]));
+        }
+
+        check_response(&rx).cloned()
+    }
+}
+
+// Ask every upstream for its head directly
+async fn check_upstreams(rpc_list: &[Rpc], results: &mut Vec<CheckResult>) {
+    for rpc in rpc_list {
+        let time = Instant::now();
+        let outcome = rpc
+            .block_number()
+            .await
+            .map(|head| format!("head {}", head))
+            .map_err(|err| err.to_string());
+        results.push(CheckResult::new(
+            &format!("upstream {}", rpc.name),
+            outcome,
+            time.elapsed(),
+        ));
+    }
+}
+
+async fn check_pipeline(test: &mut SelfTest, cache: &Db, results: &mut Vec<CheckResult>) {
+    // Block fetch
+    let time = Instant::now();
+    let head = match test
+        .call("eth_blockNumber", json!([]))
+        .await
+        .and_then(|head| {
+            let head = head.as_str().ok_or("head is not a string")?;
+            hex_to_decimal(head).map_err(|err| err.to_string())
+        }) {
+        Ok(head) => head,
+        Err(err) => {
+            results.push(CheckResult::new(
+                "eth_blockNumber",
+                Err(err),
+                time.elapsed(),
+            ));
+            return;
+        }
+    };
+    results.push(CheckResult::new(
+        "eth_blockNumber",
+        Ok(format!("head {}", head)),
+        time.elapsed(),
+    ));
+
+    let number = format!("{:#x}", head.saturating_sub(CACHE_TEST_DEPTH));
+
+    let time = Instant::now();
+    let outcome = test
+        .call("eth_getBlockByNumber", json!([number, false]))
+        .await
+        .and_then(|block| {
+            match block["number"] == number {
+                true => Ok(format!("block {}", number)),
+                false => Err(format!("got the wrong block: {}", block["number"])),
+            }
+        });
+    results.push(CheckResult::new(
+        "eth_getBlockByNumber",
+        outcome,
+        time.elapsed(),
+    ));
+
+    // Logs query
+    let time = Instant::now();
+    let outcome = test
+        .call(
+            "eth_getLogs",
+            json!([{"fromBlock": number, "toBlock": number}]),
+        )
+        .await
+        .and_then(|logs| {
+            match logs.as_array() {
+                Some(logs) => Ok(format!("{} logs", logs.len())),
+                None => Err(format!("logs are not an array: {}", logs)),
+            }
+        });
+    results.push(CheckResult::new("eth_getLogs", outcome, time.elapsed()));
+
+    // Call
+    let time = Instant::now();
+    let outcome = test
+        .call(
+            "eth_call",
+            json!([{"to": "0x0000000000000000000000000000000000000000", "data": "0x"}, number]),
+        )
+        .await
+        .and_then(|rx| {
+            match rx.as_str() {
+                Some(rx) => Ok(format!("returned {}", rx)),
+                None => Err(format!("call result is not a string: {}", rx)),
+            }
+        });
+    results.push(CheckResult::new("eth_call", outcome, time.elapsed()));
+
+    // Cache roundtrip. The first request has to be cached, and the second one served from it.
+    let time = Instant::now();
+    let params = json!([number, true]);
+    let key = request_hash(&json!({
+        "id": null,
+        "jsonrpc": "2.0",
+        "method": "eth_getBlockByNumber",
+        "params": params,
+    }));
+    let outcome = async {
+        if cfg!(feature = "no-cache") {
+            return Err("blutgang was built with caching disabled".to_string());
+        }
+
+        let first = test.call("eth_getBlockByNumber", params.clone()).await?;
+        if !cache.contains_key(key.as_bytes()).unwrap_or(false) {
+            return Err("response was not cached".to_string());
+        }
+
+        let second = test.call("eth_getBlockByNumber", params.clone()).await?;
+        match first == second {
+            true => Ok("cached response matches".to_string()),
+            false => Err("cached response does not match".to_string()),
+        }
+    }
+    .await;
+    results.push(CheckResult::new("cache roundtrip", outcome, time.elapsed()));
+}
+
+// Subscriptions are answered by blutgang itself
+async fn check_subscriptions(test: &mut SelfTest, results: &mut Vec<CheckResult>) {
+    let time = Instant::now();
+    let outcome = async {
+        let sub_id = test
+            .call("blutgang_subscribe", json!(["finalizedHeads"]))
+            .await?;
+        test.call("blutgang_getSubscriptionChanges", json!([sub_id]))
+            .await?;
+        match test.call("blutgang_unsubscribe", json!([sub_id])).await? {
+            Value::Bool(true) => Ok(format!("subscription {}", sub_id)),
+            rx => Err(format!("could not unsubscribe: {}", rx)),
+        }
+    }
+    .await;
+    results.push(CheckResult::new("subscribe", outcome, time.elapsed()));
+}
+
+pub fn print_report(results: &[CheckResult]) {
+    println!("\n\x1b[35mInfo:\x1b[0m Self-test report:");
+    for result in results {
+        let status = match result.status {
+            CheckStatus::Pass => "\x1b[32mPASS\x1b[0m",
+            CheckStatus::Fail => "\x1b[31mFAIL\x1b[0m",
+            CheckStatus::Skip => "\x1b[93mSKIP\x1b[0m",
+        };
+        println!(
+            "  {} {} ({:?}): {}",
+            status, result.name, result.time, result.detail
+        );
+    }
+
+    let failed = results
+        .iter()
+        .filter(|result| result.status == CheckStatus::Fail)
+        .count();
+    println!("{}/{} checks failed", failed, results.len());
+}
+
+// Run every check against blutgang listening at `address`. Returns true if all of them passed.
+//
+// With `tls`, we connect over HTTPS and accept whatever certificate we're serving, since
+// it's for our public hostname and not the address we connect to.
+pub async fn self_test(
+    address: SocketAddr,
+    rpc_list: Vec<Rpc>,
+    protocol: Protocol,
+    cache: &Db,
+    tls: bool,
+) -> bool {
+    let mut test = SelfTest {
+        client: Client::builder()
+            .timeout(REQUEST_TIMEOUT)
+            .danger_accept_invalid_certs(tls)
+            .build()
+            .unwrap(),
+        url: format!("{}://{}", if tls { "https" } else { "http" }, address),
+        id: 0,
+    };
+    let mut results = Vec::new();
+
+    check_upstreams(&rpc_list, &mut results).await;
+
+    if protocol == Protocol::Evm {
+        check_pipeline(&mut test, cache, &mut results).await;
+    } else {
+        for name in [
+            "eth_blockNumber",
+            "eth_getBlockByNumber",
+            "eth_getLogs",
+            "eth_call",
+            "cache roundtrip",
+        ] {
+            results.push(CheckResult::skip(name, "only available for EVM chains"));
+        }
+    }
+
+    check_subscriptions(&mut test, &mut results).await;
+
+    print_report(&results);
+    results
+        .iter()
+        .all(|result| result.status != CheckStatus::Fail)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_response() {
+        let rx = json!({"id": 1, "jsonrpc": "2.0", "result": "0x10"});
+        assert_eq!(check_response(&rx), Ok(&json!("0x10")));
+
+        // Null is a valid result
+        let rx = json!({"id": 1, "jsonrpc": "2.0", "result": null});
+        assert_eq!(check_response(&rx), Ok(&Value::Null));
+
+        let rx = json!({"id": 1, "jsonrpc": "2.0", "error": {"code": -32000, "message": "oops"}});
+        assert!(check_response(&rx).is_err());
+
+        let rx = json!({"id": 1, "jsonrpc": "2.0"});
+        assert!(check_response(&rx).is_err());
+    }
+}
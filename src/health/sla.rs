@@ -0,0 +1,166 @@
+use crate::{
+    config::types::SlaSettings,
+    rpc::sla::Sla,
+    Rpc,
+};
+
+use std::{
+    sync::{
+        Arc,
+        RwLock,
+    },
+    time::Duration,
+};
+
+use reqwest::Client;
+use serde_json::json;
+use tokio::time::sleep;
+
+// How often we check if RPCs meet their SLAs
+const CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Transition {
+    Breached(Duration),
+    // With the latency at recovery, or None if it didn't get enough requests to tell
+    Recovered(Option<Duration>),
+}
+
+// Returns how the SLA state of an RPC changes with `latency`, the
+// latency at the SLA percentile over the window.
+//
+// Breached RPCs without enough requests in the window get their share back,
+// or a share of 0 would keep them out forever.
+fn transition(sla: &Sla, breached: bool, latency: Option<Duration>) -> Option<Transition> {
+    match (breached, latency) {
+        (false, Some(latency)) if latency > sla.max_latency => Some(Transition::Breached(latency)),
+        (true, Some(latency)) if latency <= sla.max_latency => {
+            Some(Transition::Recovered(Some(latency)))
+        }
+        (true, None) => Some(Transition::Recovered(None)),
+        _ => None,
+    }
+}
+
+async fn send_alert(
+    client: &Client,
+    webhook: &str,
+    rpc: &Rpc,
+    sla: &Sla,
+    transition: Transition,
+    share: f64,
+) {
+    let (alert, latency, share) = match transition {
+        Transition::Breached(latency) => ("sla_breached", Some(latency), share),
+        Transition::Recovered(latency) => ("sla_recovered", latency, 1.0),
+    };
+    let alert = json!({
+        "source": "blutgang",
+        "alert": alert,
+        "rpc": rpc.name,
+        "sla": sla.to_string(),
+        "latency_ms": latency.map(|latency| latency.as_millis() as u64),
+        "share": share,
+    });
+
+    if let Err(err) = client.post(webhook).json(&alert).send().await {
+        println!("\x1b[31mErr:\x1b[0m Could not send SLA alert: {}", err);
+    }
+}
+
+// Check every RPC with an SLA, and cut the traffic of the ones in breach
+// to `settings.share` until they recover.
+//
+// Changes are always printed, and sent to `settings.alert_webhook` if set.
+pub async fn enforce_slas(rpc_list: Arc<RwLock<Vec<Rpc>>>, settings: SlaSettings) {
+    let client = Client::new();
+
+    loop {
+        sleep(CHECK_INTERVAL).await;
+
+        let rpcs: Vec<(Rpc, Sla)> = rpc_list
+            .read()
+            .unwrap()
+            .iter()
+            .filter_map(|rpc| rpc.sla.map(|sla| (rpc.clone(), sla)))
+            .collect();
+
+        for (rpc, sla) in rpcs {
+            let stats = rpc.sla_stats();
+            let latency = stats.percentile(sla.percentile, settings.window, settings.min_samples);
+            let transition = match transition(&sla, stats.is_breached(), latency) {
+                Some(transition) => transition,
+                None => continue,
+            };
+
+            match transition {
+                Transition::Breached(latency) => {
+                    stats.set_share(settings.share);
+                    println!(
+                        "\x1b[93mWrn:\x1b[0m {} is breaching its SLA of {}, at {}ms! Cutting its traffic to {}%.",
+                        rpc.name,
+                        sla,
+                        latency.as_millis(),
+                        settings.share * 100.0
+                    );
+                }
+                Transition::Recovered(latency) => {
+                    stats.set_share(1.0);
+                    match latency {
+                        Some(latency) => {
+                            println!(
+                                "\x1b[35mInfo:\x1b[0m {} is meeting its SLA of {} again, at {}ms. Restoring its traffic.",
+                                rpc.name,
+                                sla,
+                                latency.as_millis()
+                            )
+                        }
+                        None => {
+                            println!(
+                                "\x1b[35mInfo:\x1b[0m Not enough requests to check the SLA of {}. Restoring its traffic.",
+                                rpc.name
+                            )
+                        }
+                    }
+                }
+            }
+
+            if let Some(webhook) = &settings.alert_webhook {
+                send_alert(&client, webhook, &rpc, &sla, transition, settings.share).await;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_transition() {
+        let sla = Sla {
+            percentile: 95.0,
+            max_latency: Duration::from_millis(250),
+        };
+        let slow = Some(Duration::from_millis(400));
+        let fast = Some(Duration::from_millis(100));
+
+        assert_eq!(
+            transition(&sla, false, slow),
+            Some(Transition::Breached(Duration::from_millis(400)))
+        );
+        assert_eq!(transition(&sla, true, slow), None);
+        assert_eq!(transition(&sla, false, fast), None);
+        assert_eq!(
+            transition(&sla, true, fast),
+            Some(Transition::Recovered(fast))
+        );
+
+        // Not enough data
+        assert_eq!(transition(&sla, false, None), None);
+        assert_eq!(
+            transition(&sla, true, None),
+            Some(Transition::Recovered(None))
+        );
+    }
+}
@@ -0,0 +1,335 @@
+// Verify that backup RPCs would actually work if we had to fail over to them.
+//
+// Head checks only tell us a node answers `eth_blockNumber`. RPCs tagged as standbys
+// also get a small suite of requests like the ones clients send every `interval`,
+// for a recent block, and each one has to come back in time, without an error and,
+// for the head, close enough to the pool. Their failover readiness is the share of
+// checks that passed over the last `window` rounds.
+use crate::{
+    config::types::StandbySettings,
+    rpc::types::Protocol,
+    Rpc,
+};
+
+use std::{
+    collections::{
+        BTreeMap,
+        VecDeque,
+    },
+    sync::{
+        Arc,
+        Mutex,
+        RwLock,
+    },
+    time::{
+        Duration,
+        SystemTime,
+        UNIX_EPOCH,
+    },
+};
+
+use serde_json::{
+    json,
+    Value,
+};
+use tokio::{
+    sync::watch,
+    time::{
+        sleep,
+        timeout,
+    },
+};
+
+// Blocks below the head we query, so standbys that are a block behind still have it
+const DEPTH: u64 = 2;
+
+// Any address works, we only care that the node can answer
+const EVM_ADDRESS: &str = "0x0000000000000000000000000000000000000000";
+const SOLANA_ADDRESS: &str = "11111111111111111111111111111111";
+
+// One round of checks against a standby
+#[derive(Debug, Clone, PartialEq, Default)]
+struct Round {
+    at: u64,
+    passed: usize,
+    total: usize,
+    // Method -> why it failed
+    failures: BTreeMap<String, String>,
+}
+
+// Requests like the ones clients send, for `height`
+fn suite(protocol: Protocol, height: u64) -> Vec<Value> {
+    let requests = match protocol {
+        Protocol::Evm => {
+            let block = format!("{:#x}", height);
+            vec![
+                json!({"method": "eth_blockNumber", "params": []}),
+                json!({"method": "eth_chainId", "params": []}),
+                json!({"method": "eth_getBlockByNumber", "params": [block, false]}),
+                json!({"method": "eth_getBalance", "params": [EVM_ADDRESS, block]}),
+                json!({"method": "eth_getTransactionCount", "params": [EVM_ADDRESS, block]}),
+                json!({"method": "eth_getLogs", "params": [{"fromBlock": block, "toBlock": block}]}),
+                json!({"method": "eth_gasPrice", "params": []}),
+            ]
+        }
+        Protocol::Solana => {
+            vec![
+                json!({"method": "getSlot", "params": []}),
+                json!({"method": "getLatestBlockhash", "params": []}),
+                json!({"method": "getBalance", "params": [SOLANA_ADDRESS]}),
+                json!({"method": "getBlockHeight", "params": []}),
+            ]
+        }
+        Protocol::Bitcoin => {
+            vec![
+                json!({"method": "getblockcount", "params": []}),
+                json!({"method": "getbestblockhash", "params": []}),
+                json!({"method": "getblockhash", "params": [height]}),
+                json!({"method": "getblockchaininfo", "params": []}),
+            ]
+        }
+    };
+
+    requests
+        .into_iter()
+        .map(|mut tx| {
+            tx["id"] = 1.into();
+            tx["jsonrpc"] = "2.0".into();
+            tx
+        })
+        .collect()
+}
+
+// Why `rx` isn't a response we could give a client, if it isn't
+fn check_response(rx: &[u8], method: &str, head: u64, head_tolerance: u64) -> Option<String> {
+    let rx: Value = match serde_json::from_slice(rx) {
+        Ok(rx) => rx,
+        Err(err) => return Some(format!("invalid response: {}", err)),
+    };
+    if !rx["error"].is_null() {
+        return Some(format!("error: {}", rx["error"]));
+    }
+    if rx["result"].is_null() {
+        return Some("no result".to_string());
+    }
+
+    let reported = match method {
+        "eth_blockNumber" => {
+            rx["result"]
+                .as_str()
+                .and_then(|number| u64::from_str_radix(number.trim_start_matches("0x"), 16).ok())
+        }
+        "getblockcount" => rx["result"].as_u64(),
+        _ => return None,
+    };
+    match reported {
+        Some(reported) if head > 0 && reported + head_tolerance < head => {
+            Some(format!("head {} is behind {}", reported, head))
+        }
+        Some(_) => None,
+        None => Some("invalid head".to_string()),
+    }
+}
+
+// Run the suite against `rpc`, with `head` being the head of the pool
+async fn verify(rpc: &Rpc, head: u64, ttl: Duration) -> Round {
+    let protocol = rpc.protocol;
+    let requests = suite(protocol, head.saturating_sub(DEPTH));
+    let mut round = Round {
+        total: requests.len(),
+        ..Default::default()
+    };
+
+    for tx in requests {
+        let method = tx["method"].as_str().unwrap_or_default().to_string();
+        let failure = match timeout(ttl, rpc.send_request(tx)).await {
+            Ok(Ok(rx)) => check_response(&rx, &method, head, protocol.head_tolerance()),
+            Ok(Err(err)) => Some(err.to_string()),
+            Err(_) => Some("timed out".to_string()),
+        };
+        match failure {
+            Some(failure) => {
+                round.failures.insert(method, failure);
+            }
+            None => round.passed += 1,
+        }
+    }
+
+    round
+}
+
+// Recent rounds of every standby
+#[derive(Debug, Default)]
+pub struct StandbyTracker {
+    window: usize,
+    rounds: Mutex<BTreeMap<String, VecDeque<Round>>>,
+}
+
+impl StandbyTracker {
+    pub fn new(settings: &StandbySettings) -> Self {
+        Self {
+            window: settings.window.max(1),
+            rounds: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    fn record(&self, rpc: &str, round: Round) {
+        let mut rounds = self.rounds.lock().unwrap();
+        let rounds = rounds.entry(rpc.to_string()).or_default();
+        if rounds.len() == self.window {
+            rounds.pop_front();
+        }
+        rounds.push_back(round);
+    }
+
+    // Forget standbys that aren't in `names` anymore
+    fn retain(&self, names: &[String]) {
+        self.rounds
+            .lock()
+            .unwrap()
+            .retain(|name, _| names.contains(name));
+    }
+
+    // Share of checks that passed over the window, None if we haven't checked `rpc` yet
+    pub fn readiness(&self, rpc: &str) -> Option<f64> {
+        let rounds = self.rounds.lock().unwrap();
+        let rounds = rounds.get(rpc)?;
+        let total: usize = rounds.iter().map(|round| round.total).sum();
+        let passed: usize = rounds.iter().map(|round| round.passed).sum();
+        (total > 0).then(|| passed as f64 / total as f64)
+    }
+
+    // Readiness of every standby, and what failed in its last round
+    pub fn report(&self) -> Value {
+        let names: Vec<String> = self.rounds.lock().unwrap().keys().cloned().collect();
+        let standbys: Vec<Value> = names
+            .iter()
+            .map(|name| {
+                let last = self.rounds.lock().unwrap()[name].back().cloned();
+                json!({
+                    "rpc": name,
+                    "readiness": self.readiness(name),
+                    "lastChecked": last.as_ref().map(|round| round.at),
+                    "lastFailures": last.map(|round| round.failures).unwrap_or_default(),
+                })
+            })
+            .collect();
+
+        standbys.into()
+    }
+}
+
+fn unix_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+// Verify every RPC tagged with `settings.tag` every `settings.interval`, in the
+// active pool or not
+pub async fn verify_standbys(
+    rpc_list: Arc<RwLock<Vec<Rpc>>>,
+    poverty_list: Arc<RwLock<Vec<Rpc>>>,
+    blocknum_rx: watch::Receiver<u64>,
+    tracker: Arc<StandbyTracker>,
+    settings: StandbySettings,
+    ttl: Duration,
+) {
+    loop {
+        sleep(settings.interval).await;
+
+        let standbys: Vec<Rpc> = rpc_list
+            .read()
+            .unwrap()
+            .iter()
+            .chain(poverty_list.read().unwrap().iter())
+            .filter(|rpc| rpc.tags.contains(&settings.tag))
+            .cloned()
+            .collect();
+        let names: Vec<String> = standbys.iter().map(|rpc| rpc.name.clone()).collect();
+        tracker.retain(&names);
+
+        let head = *blocknum_rx.borrow();
+        for rpc in standbys {
+            let round = Round {
+                at: unix_secs(),
+                ..verify(&rpc, head, ttl).await
+            };
+            if !round.failures.is_empty() {
+                println!(
+                    "\x1b[93mWrn:\x1b[0m Standby {} failed {}/{} readiness checks: {:?}",
+                    rpc.name,
+                    round.failures.len(),
+                    round.total,
+                    round.failures
+                );
+            }
+            tracker.record(&rpc.name, round);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_response() {
+        let ok = br#"{"jsonrpc":"2.0","id":1,"result":"0x64"}"#;
+        assert_eq!(check_response(ok, "eth_blockNumber", 100, 0), None);
+        assert_eq!(check_response(ok, "eth_getBalance", 1000, 0), None);
+        assert_eq!(
+            check_response(ok, "eth_blockNumber", 102, 1),
+            Some("head 100 is behind 102".to_string())
+        );
+        // Without a head of the pool we can't tell how far behind it is
+        assert_eq!(check_response(ok, "eth_blockNumber", 0, 0), None);
+
+        let error =
+            br#"{"jsonrpc":"2.0","id":1,"error":{"code":-32000,"message":"missing trie node"}}"#;
+        assert!(check_response(error, "eth_getBalance", 100, 0)
+            .unwrap()
+            .contains("missing trie node"));
+        let null = br#"{"jsonrpc":"2.0","id":1,"result":null}"#;
+        assert_eq!(
+            check_response(null, "eth_getBlockByNumber", 100, 0),
+            Some("no result".to_string())
+        );
+        assert!(check_response(b"<html>", "eth_chainId", 100, 0).is_some());
+    }
+
+    #[test]
+    fn test_readiness() {
+        let tracker = StandbyTracker::new(&StandbySettings {
+            window: 2,
+            ..Default::default()
+        });
+        assert_eq!(tracker.readiness("backup"), None);
+
+        let round = |passed| {
+            Round {
+                passed,
+                total: 4,
+                ..Default::default()
+            }
+        };
+        tracker.record("backup", round(0));
+        tracker.record("backup", round(2));
+        assert_eq!(tracker.readiness("backup"), Some(0.25));
+        // Only the last `window` rounds count
+        tracker.record("backup", round(4));
+        assert_eq!(tracker.readiness("backup"), Some(0.75));
+
+        tracker.retain(&[]);
+        assert_eq!(tracker.readiness("backup"), None);
+        assert_eq!(tracker.report(), json!([]));
+    }
+
+    #[test]
+    fn test_suite() {
+        let suite = suite(Protocol::Evm, 0x10);
+        assert!(suite.iter().all(|tx| tx["jsonrpc"] == "2.0"));
+        assert_eq!(suite[2]["params"][0], "0x10");
+    }
+}
@@ -0,0 +1,209 @@
+// Tombstones of cached responses that got reorged out.
+//
+// Responses for reorged blocks can't be served anymore, but what we served before the
+// reorg is what you want to look at when something built on it went wrong. With a
+// retention set, reorged entries get moved out of the cache into their own tree instead
+// of being deleted, so they're misses like before but stay around for `retention`.
+//
+// How many entries reorgs invalidated, by how deep the reorg was, is kept for metrics
+// whether or not tombstones are kept.
+use std::{
+    cmp::Reverse,
+    collections::BTreeMap,
+    sync::{
+        atomic::{
+            AtomicU64,
+            Ordering,
+        },
+        Mutex,
+    },
+    time::{
+        Duration,
+        SystemTime,
+        UNIX_EPOCH,
+    },
+};
+
+use sled::{
+    Batch,
+    Db,
+    IVec,
+};
+
+pub const TOMBSTONE_TREE: &str = "blutgang_tombstones";
+
+// Reorg depth -> entries invalidated by reorgs that deep, since we started
+static INVALIDATED: Mutex<BTreeMap<u64, u64>> = Mutex::new(BTreeMap::new());
+
+// Tombstones we had after the last time we added or purged some
+static TOMBSTONES: AtomicU64 = AtomicU64::new(0);
+
+pub fn invalidated() -> BTreeMap<u64, u64> {
+    INVALIDATED
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .clone()
+}
+
+pub fn tombstones() -> u64 {
+    TOMBSTONES.load(Ordering::Relaxed)
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+// When the entry was reorged out, in seconds since the epoch, how deep the reorg was
+// and what we had cached
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tombstone {
+    pub buried: u64,
+    pub depth: u64,
+    pub data: IVec,
+}
+
+impl Tombstone {
+    fn encode(&self) -> Vec<u8> {
+        let mut value = Vec::with_capacity(16 + self.data.len());
+        value.extend_from_slice(&self.buried.to_be_bytes());
+        value.extend_from_slice(&self.depth.to_be_bytes());
+        value.extend_from_slice(&self.data);
+        value
+    }
+
+    fn decode(value: &[u8]) -> Option<Self> {
+        Some(Self {
+            buried: u64::from_be_bytes(value.get(..8)?.try_into().ok()?),
+            depth: u64::from_be_bytes(value.get(8..16)?.try_into().ok()?),
+            data: IVec::from(&value[16..]),
+        })
+    }
+}
+
+// Remove the entries under `keys` from the cache for a reorg `depth` blocks deep,
+// keeping them as tombstones if `keep` is set. Returns how many we had cached.
+pub fn bury(cache: &Db, keys: &[Vec<u8>], depth: u64, keep: bool) -> Result<u64, sled::Error> {
+    let tombstones = cache.open_tree(TOMBSTONE_TREE)?;
+    let buried = now();
+
+    let mut removed = Batch::default();
+    let mut kept = Batch::default();
+    let mut count = 0;
+    for key in keys {
+        let data = match cache.get(key)? {
+            Some(data) => data,
+            None => continue,
+        };
+        if keep {
+            let tombstone = Tombstone {
+                buried,
+                depth,
+                data,
+            };
+            kept.insert(key.as_slice(), tombstone.encode());
+        }
+        removed.remove(key.as_slice());
+        count += 1;
+    }
+
+    tombstones.apply_batch(kept)?;
+    cache.apply_batch(removed)?;
+    TOMBSTONES.store(tombstones.len() as u64, Ordering::Relaxed);
+
+    if count > 0 {
+        let mut invalidated = INVALIDATED.lock().unwrap_or_else(|e| e.into_inner());
+        *invalidated.entry(depth).or_default() += count;
+    }
+    Ok(count)
+}
+
+// Up to `limit` of the newest tombstones, with the keys they were cached under
+pub fn list(cache: &Db, limit: usize) -> Result<Vec<(IVec, Tombstone)>, sled::Error> {
+    let mut tombstones = Vec::new();
+    for entry in cache.open_tree(TOMBSTONE_TREE)?.iter() {
+        let (key, value) = entry?;
+        if let Some(tombstone) = Tombstone::decode(&value) {
+            tombstones.push((key, tombstone));
+        }
+    }
+
+    tombstones.sort_by_key(|(_, tombstone)| Reverse(tombstone.buried));
+    tombstones.truncate(limit);
+    Ok(tombstones)
+}
+
+// Remove tombstones older than `retention`. Returns how many we removed.
+pub fn purge(cache: &Db, retention: Duration) -> Result<usize, sled::Error> {
+    let tombstones = cache.open_tree(TOMBSTONE_TREE)?;
+    let oldest = now().saturating_sub(retention.as_secs());
+
+    let mut expired = Batch::default();
+    let mut count = 0;
+    for entry in tombstones.iter() {
+        let (key, value) = entry?;
+        // Ones we can't read are no use to anyone
+        if Tombstone::decode(&value).map_or(true, |tombstone| tombstone.buried < oldest) {
+            expired.remove(key);
+            count += 1;
+        }
+    }
+
+    tombstones.apply_batch(expired)?;
+    TOMBSTONES.store(tombstones.len() as u64, Ordering::Relaxed);
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get(cache: &Db, key: &[u8]) -> Option<Tombstone> {
+        list(cache, usize::MAX)
+            .unwrap()
+            .into_iter()
+            .find_map(|(buried_key, tombstone)| (buried_key == key).then_some(tombstone))
+    }
+
+    #[test]
+    fn test_bury() {
+        let cache = sled::Config::new().temporary(true).open().unwrap();
+        cache.insert("a", "old").unwrap();
+        cache.insert("b", "older").unwrap();
+        let before = invalidated().get(&3).copied().unwrap_or_default();
+
+        // Keys we don't have cached don't count
+        let keys = [b"a".to_vec(), b"c".to_vec()];
+        assert_eq!(bury(&cache, &keys, 3, true).unwrap(), 1);
+        assert!(cache.get("a").unwrap().is_none());
+        let tombstone = get(&cache, b"a").unwrap();
+        assert_eq!(tombstone.depth, 3);
+        assert_eq!(&tombstone.data[..], b"old");
+
+        assert_eq!(bury(&cache, &[b"b".to_vec()], 3, false).unwrap(), 1);
+        assert!(cache.get("b").unwrap().is_none());
+        assert!(get(&cache, b"b").is_none());
+
+        assert!(invalidated()[&3] >= before + 2);
+    }
+
+    #[test]
+    fn test_purge() {
+        let cache = sled::Config::new().temporary(true).open().unwrap();
+        let tombstones = cache.open_tree(TOMBSTONE_TREE).unwrap();
+        for (key, buried) in [("old", now() - 120), ("new", now())] {
+            let tombstone = Tombstone {
+                buried,
+                depth: 1,
+                data: IVec::from("rx"),
+            };
+            tombstones.insert(key, tombstone.encode()).unwrap();
+        }
+
+        assert_eq!(purge(&cache, Duration::from_secs(60)).unwrap(), 1);
+        assert!(get(&cache, b"old").is_none());
+        assert!(get(&cache, b"new").is_some());
+    }
+}
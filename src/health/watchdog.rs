@@ -0,0 +1,379 @@
+// Watches our own subsystems and acts when one of them gets stuck.
+//
+// Failures in background tasks are easy to miss: the head tracker can stop without
+// anything on the request path noticing, and we'd keep serving stale data from the
+// cache. The watchdog checks that the head keeps advancing, that the cache still
+// takes writes, and that at least one RPC passes its health checks.
+use crate::{
+    config::types::WatchdogSettings,
+    Rpc,
+};
+
+use std::{
+    fmt,
+    sync::{
+        Arc,
+        Mutex,
+        RwLock,
+    },
+    time::{
+        Duration,
+        Instant,
+    },
+};
+
+use reqwest::Client;
+use serde_json::json;
+use sled::Db;
+use tokio::{
+    sync::watch,
+    task::JoinHandle,
+    time::sleep,
+};
+
+// Tree we write to when checking the cache
+pub const WATCHDOG_TREE: &str = "blutgang_watchdog";
+
+pub const READY_PATH: &str = "/ready";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchdogAction {
+    Log,
+    // POST to `alert_webhook`
+    Alert,
+    // Answer 503 at `/ready`, so orchestrators stop sending us traffic
+    Unready,
+    // Restart the task behind the subsystem, if it has one
+    Restart,
+}
+
+impl WatchdogAction {
+    pub fn from_config(action: &str) -> Option<Self> {
+        match action {
+            "log" => Some(WatchdogAction::Log),
+            "alert" => Some(WatchdogAction::Alert),
+            "unready" => Some(WatchdogAction::Unready),
+            "restart" => Some(WatchdogAction::Restart),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Subsystem {
+    HeadTracker,
+    Cache,
+    Upstreams,
+}
+
+impl fmt::Display for Subsystem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Subsystem::HeadTracker => write!(f, "head_tracker"),
+            Subsystem::Cache => write!(f, "cache"),
+            Subsystem::Upstreams => write!(f, "upstreams"),
+        }
+    }
+}
+
+// Readiness as decided by the watchdog
+#[derive(Debug, Default)]
+pub struct Watchdog {
+    // Stuck subsystems that made us unready
+    unready: Mutex<Vec<Subsystem>>,
+}
+
+impl Watchdog {
+    // Body and whether we're ready, for `/ready`
+    pub fn readiness(&self) -> (bool, String) {
+        let unready = self.unready.lock().unwrap();
+        match unready.is_empty() {
+            true => (true, "ready".to_string()),
+            false => {
+                let stuck: Vec<String> = unready.iter().map(|s| s.to_string()).collect();
+                (false, format!("stuck: {}", stuck.join(", ")))
+            }
+        }
+    }
+
+    fn set_unready(&self, subsystem: Subsystem, unready: bool) {
+        let mut guard = self.unready.lock().unwrap();
+        guard.retain(|stuck| *stuck != subsystem);
+        if unready {
+            guard.push(subsystem);
+        }
+    }
+}
+
+// A background task the watchdog can restart
+pub struct Supervised {
+    spawn: Box<dyn Fn() -> JoinHandle<()> + Send + Sync>,
+    handle: Mutex<JoinHandle<()>>,
+}
+
+impl Supervised {
+    pub fn spawn(spawn: impl Fn() -> JoinHandle<()> + Send + Sync + 'static) -> Self {
+        let handle = Mutex::new(spawn());
+        Self {
+            spawn: Box::new(spawn),
+            handle,
+        }
+    }
+
+    fn restart(&self) {
+        let mut handle = self.handle.lock().unwrap();
+        handle.abort();
+        *handle = (self.spawn)();
+    }
+}
+
+// Tracks when the head last advanced
+#[derive(Debug)]
+struct HeadProgress {
+    head: u64,
+    since: Instant,
+}
+
+impl HeadProgress {
+    fn new(now: Instant) -> Self {
+        Self {
+            head: 0,
+            since: now,
+        }
+    }
+
+    // Returns for how long the head has been stuck, if it has been for longer than `timeout`
+    fn observe(&mut self, head: u64, now: Instant, timeout: Duration) -> Option<Duration> {
+        if head != self.head {
+            self.head = head;
+            self.since = now;
+            return None;
+        }
+
+        let stuck = now.duration_since(self.since);
+        (stuck >= timeout).then_some(stuck)
+    }
+}
+
+// Every RPC failed its last health check, so we have nothing to send requests to
+fn upstreams_down(rpc_list: &RwLock<Vec<Rpc>>, poverty_list: &RwLock<Vec<Rpc>>) -> Option<String> {
+    let failing = poverty_list.read().unwrap().len();
+    (rpc_list.read().unwrap().is_empty() && failing > 0)
+        .then(|| format!("all {} RPCs are failing their health checks", failing))
+}
+
+// Write to the cache and flush it, so we know writes make it to disk
+async fn probe_cache(cache: &Db) -> Result<(), sled::Error> {
+    let tree = cache.open_tree(WATCHDOG_TREE)?;
+    tree.insert(b"probe", b"ok")?;
+    tree.flush_async().await?;
+    tree.remove(b"probe")?;
+    Ok(())
+}
+
+async fn send_alert(client: &Client, webhook: &str, subsystem: Subsystem, reason: Option<&str>) {
+    let alert = json!({
+        "source": "blutgang",
+        "alert": match reason {
+            Some(_) => "subsystem_stuck",
+            None => "subsystem_recovered",
+        },
+        "subsystem": subsystem.to_string(),
+        "reason": reason,
+    });
+
+    if let Err(err) = client.post(webhook).json(&alert).send().await {
+        println!("\x1b[31mErr:\x1b[0m Could not send watchdog alert: {}", err);
+    }
+}
+
+// Take the configured actions for `subsystem` getting stuck, or recovering if `reason` is None
+async fn act(
+    watchdog: &Watchdog,
+    settings: &WatchdogSettings,
+    client: &Client,
+    subsystem: Subsystem,
+    reason: Option<&str>,
+    task: Option<&Supervised>,
+) {
+    for action in &settings.actions {
+        match (action, reason) {
+            (WatchdogAction::Log, Some(reason)) => {
+                println!(
+                    "\x1b[31mErr:\x1b[0m Watchdog: {} is stuck, {}!",
+                    subsystem, reason
+                );
+            }
+            (WatchdogAction::Log, None) => {
+                println!("\x1b[35mInfo:\x1b[0m Watchdog: {} recovered.", subsystem);
+            }
+            (WatchdogAction::Alert, reason) => {
+                if let Some(webhook) = &settings.alert_webhook {
+                    send_alert(client, webhook, subsystem, reason).await;
+                }
+            }
+            (WatchdogAction::Unready, reason) => watchdog.set_unready(subsystem, reason.is_some()),
+            (WatchdogAction::Restart, Some(_)) => {
+                match task {
+                    Some(task) => {
+                        println!("\x1b[93mWrn:\x1b[0m Watchdog: Restarting {}.", subsystem);
+                        task.restart();
+                    }
+                    None => {
+                        println!(
+                            "\x1b[93mWrn:\x1b[0m Watchdog: {} can't be restarted.",
+                            subsystem
+                        );
+                    }
+                }
+            }
+            (WatchdogAction::Restart, None) => {}
+        }
+    }
+}
+
+// Check our subsystems every `settings.interval`, acting once when they get stuck
+// and once when they recover.
+//
+// The head tracker and upstreams are only checked if health checks are enabled,
+// `health_check` being the task doing them.
+pub async fn watchdog(
+    watchdog: Arc<Watchdog>,
+    settings: WatchdogSettings,
+    cache: Arc<Db>,
+    rpc_list: Arc<RwLock<Vec<Rpc>>>,
+    poverty_list: Arc<RwLock<Vec<Rpc>>>,
+    blocknum_rx: watch::Receiver<u64>,
+    health_check: Option<Supervised>,
+) {
+    let client = Client::new();
+    let mut head = HeadProgress::new(Instant::now());
+    let mut stuck: Vec<Subsystem> = Vec::new();
+
+    loop {
+        sleep(settings.interval).await;
+
+        let mut checks = Vec::new();
+        if health_check.is_some() {
+            let stalled =
+                head.observe(*blocknum_rx.borrow(), Instant::now(), settings.head_timeout);
+            checks.push((
+                Subsystem::HeadTracker,
+                stalled.map(|stalled| {
+                    format!("head has been at {} for {}s", head.head, stalled.as_secs())
+                }),
+            ));
+            checks.push((
+                Subsystem::Upstreams,
+                upstreams_down(&rpc_list, &poverty_list),
+            ));
+        }
+        checks.push((
+            Subsystem::Cache,
+            probe_cache(&cache)
+                .await
+                .err()
+                .map(|err| format!("writes are failing: {}", err)),
+        ));
+
+        for (subsystem, reason) in checks {
+            let was_stuck = stuck.contains(&subsystem);
+            if was_stuck == reason.is_some() {
+                continue;
+            }
+
+            let task = match subsystem {
+                Subsystem::HeadTracker | Subsystem::Upstreams => health_check.as_ref(),
+                Subsystem::Cache => None,
+            };
+            act(
+                &watchdog,
+                &settings,
+                &client,
+                subsystem,
+                reason.as_deref(),
+                task,
+            )
+            .await;
+
+            match reason {
+                Some(_) => stuck.push(subsystem),
+                None => stuck.retain(|s| *s != subsystem),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_head_progress() {
+        let start = Instant::now();
+        let timeout = Duration::from_secs(60);
+        let mut head = HeadProgress::new(start);
+
+        assert_eq!(
+            head.observe(10, start + Duration::from_secs(10), timeout),
+            None
+        );
+        assert_eq!(
+            head.observe(10, start + Duration::from_secs(30), timeout),
+            None
+        );
+        assert_eq!(
+            head.observe(10, start + Duration::from_secs(70), timeout),
+            Some(Duration::from_secs(60))
+        );
+
+        // Advancing again
+        assert_eq!(
+            head.observe(11, start + Duration::from_secs(80), timeout),
+            None
+        );
+    }
+
+    #[test]
+    fn test_upstreams_down() {
+        let rpc = Rpc::default();
+        let empty = RwLock::new(Vec::new());
+        let some = RwLock::new(vec![rpc]);
+
+        assert!(upstreams_down(&empty, &some).is_some());
+        assert!(upstreams_down(&some, &some).is_none());
+        // Nothing configured isn't the watchdog's problem
+        assert!(upstreams_down(&empty, &empty).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_readiness() {
+        let watchdog = Watchdog::default();
+        let settings = WatchdogSettings {
+            actions: vec![WatchdogAction::Unready],
+            ..Default::default()
+        };
+        let client = Client::new();
+
+        act(
+            &watchdog,
+            &settings,
+            &client,
+            Subsystem::Cache,
+            Some("broken"),
+            None,
+        )
+        .await;
+        assert_eq!(watchdog.readiness(), (false, "stuck: cache".to_string()));
+
+        act(&watchdog, &settings, &client, Subsystem::Cache, None, None).await;
+        assert_eq!(watchdog.readiness(), (true, "ready".to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_probe_cache() {
+        let cache = sled::Config::new().temporary(true).open().unwrap();
+        probe_cache(&cache).await.unwrap();
+        assert!(cache.open_tree(WATCHDOG_TREE).unwrap().is_empty());
+    }
+}
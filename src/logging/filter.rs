@@ -0,0 +1,203 @@
+// Log level filters and request log sampling that can be changed at runtime.
+//
+// Logging is done with `println!` all over the place, so the filter is global
+// instead of being passed around with everything else.
+use crate::logging::format::LogFormat;
+
+use std::{
+    fmt,
+    sync::{
+        atomic::{
+            AtomicU64,
+            Ordering,
+        },
+        RwLock,
+    },
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LogLevel {
+    Off,
+    Error,
+    Warn,
+    Info,
+    Debug,
+}
+
+impl LogLevel {
+    pub fn from_config(level: &str) -> Option<Self> {
+        match level.to_lowercase().as_str() {
+            "off" => Some(LogLevel::Off),
+            "error" => Some(LogLevel::Error),
+            "warn" => Some(LogLevel::Warn),
+            "info" => Some(LogLevel::Info),
+            "debug" => Some(LogLevel::Debug),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for LogLevel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let level = match self {
+            LogLevel::Off => "off",
+            LogLevel::Error => "error",
+            LogLevel::Warn => "warn",
+            LogLevel::Info => "info",
+            LogLevel::Debug => "debug",
+        };
+        write!(f, "{}", level)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LogFilter {
+    pub default: LogLevel,
+    // Per-module overrides, e.g. `health` or `balancer`
+    pub modules: Vec<(String, LogLevel)>,
+    // Share of per-request log lines we print, between 0 and 1
+    pub sample_rate: f64,
+    // Lines that go through `log_line` and request logs use this
+    pub format: LogFormat,
+}
+
+impl LogFilter {
+    pub const fn new() -> Self {
+        Self {
+            default: LogLevel::Info,
+            modules: Vec::new(),
+            sample_rate: 1.0,
+            format: LogFormat::Text,
+        }
+    }
+
+    // Parse filters formatted like `info,health=debug,balancer=warn`
+    pub fn parse(filter: &str) -> Option<Self> {
+        let mut log_filter = Self::new();
+
+        for directive in filter.split(',').map(str::trim).filter(|d| !d.is_empty()) {
+            match directive.split_once('=') {
+                Some((module, level)) => {
+                    log_filter.set_module(module.trim(), LogLevel::from_config(level.trim())?)
+                }
+                None => log_filter.default = LogLevel::from_config(directive)?,
+            }
+        }
+
+        Some(log_filter)
+    }
+
+    pub fn set_module(&mut self, module: &str, level: LogLevel) {
+        match self.modules.iter_mut().find(|(name, _)| name == module) {
+            Some((_, current)) => *current = level,
+            None => self.modules.push((module.to_string(), level)),
+        }
+    }
+
+    pub fn level(&self, module: &str) -> LogLevel {
+        self.modules
+            .iter()
+            .find(|(name, _)| name == module)
+            .map(|(_, level)| *level)
+            .unwrap_or(self.default)
+    }
+}
+
+impl Default for LogFilter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Display for LogFilter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.default)?;
+        for (module, level) in &self.modules {
+            write!(f, ",{}={}", module, level)?;
+        }
+        Ok(())
+    }
+}
+
+static FILTER: RwLock<LogFilter> = RwLock::new(LogFilter::new());
+static SAMPLE_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+pub fn log_filter() -> LogFilter {
+    FILTER.read().unwrap_or_else(|e| e.into_inner()).clone()
+}
+
+pub fn set_log_filter(filter: LogFilter) {
+    *FILTER.write().unwrap_or_else(|e| e.into_inner()) = filter;
+}
+
+// Change the level of a single module, or the default one if `module` is None
+pub fn set_log_level(module: Option<&str>, level: LogLevel) {
+    let mut filter = FILTER.write().unwrap_or_else(|e| e.into_inner());
+    match module {
+        Some(module) => filter.set_module(module, level),
+        None => filter.default = level,
+    }
+}
+
+pub fn set_sample_rate(sample_rate: f64) {
+    FILTER
+        .write()
+        .unwrap_or_else(|e| e.into_inner())
+        .sample_rate = sample_rate.clamp(0.0, 1.0);
+}
+
+// Should we print a `level` message from `module`
+pub fn log_enabled(module: &str, level: LogLevel) -> bool {
+    level != LogLevel::Off
+        && FILTER
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .level(module)
+            >= level
+}
+
+// Same as `log_enabled`, but only lets through `sample_rate` of the messages.
+// Used for lines we print for every request.
+pub fn log_sampled(module: &str, level: LogLevel) -> bool {
+    let sample_rate = {
+        let filter = FILTER.read().unwrap_or_else(|e| e.into_inner());
+        if level == LogLevel::Off || filter.level(module) < level {
+            return false;
+        }
+        filter.sample_rate
+    };
+
+    is_sampled(SAMPLE_COUNTER.fetch_add(1, Ordering::Relaxed), sample_rate)
+}
+
+// Spreads sampled messages evenly, e.g. every 4th one with a rate of 0.25
+pub fn is_sampled(count: u64, sample_rate: f64) -> bool {
+    ((count + 1) as f64 * sample_rate).floor() > (count as f64 * sample_rate).floor()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_filter() {
+        let filter = LogFilter::parse("warn, health=debug,balancer=off").unwrap();
+        assert_eq!(filter.default, LogLevel::Warn);
+        assert_eq!(filter.level("health"), LogLevel::Debug);
+        assert_eq!(filter.level("balancer"), LogLevel::Off);
+        assert_eq!(filter.level("admin"), LogLevel::Warn);
+        assert_eq!(filter.to_string(), "warn,health=debug,balancer=off");
+
+        assert_eq!(LogFilter::parse("").unwrap(), LogFilter::new());
+        assert_eq!(LogFilter::parse("loud"), None);
+        assert_eq!(LogFilter::parse("health=loud"), None);
+    }
+
+    #[test]
+    fn test_is_sampled() {
+        let sampled = (0..100).filter(|count| is_sampled(*count, 0.25)).count();
+        assert_eq!(sampled, 25);
+        assert!((0..100).all(|count| is_sampled(count, 1.0)));
+        assert!(!(0..100).any(|count| is_sampled(count, 0.0)));
+    }
+}
@@ -0,0 +1,269 @@
+// How log lines look, and the line we log for every request.
+//
+// Text is what we've always printed. JSON puts one object on each line, with a
+// timestamp, level and module, so Loki, ELK and friends can ingest it without parsing
+// rules. Every request gets an id that goes in its log line and the `x-request-id`
+// response header, so a client reporting a problem can tell us which request it was.
+use crate::logging::filter::{
+    log_filter,
+    log_sampled,
+    LogLevel,
+};
+
+use std::{
+    fmt,
+    sync::{
+        atomic::{
+            AtomicU64,
+            Ordering,
+        },
+        OnceLock,
+    },
+    time::{
+        Duration,
+        SystemTime,
+        UNIX_EPOCH,
+    },
+};
+
+use chrono::Utc;
+use hyper::HeaderMap;
+use serde_json::{
+    json,
+    Map,
+    Value,
+};
+
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+// Longest request id we accept from clients
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LogFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+impl LogFormat {
+    pub fn from_config(format: &str) -> Option<Self> {
+        match format.to_lowercase().as_str() {
+            "text" => Some(LogFormat::Text),
+            "json" => Some(LogFormat::Json),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for LogFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LogFormat::Text => write!(f, "text"),
+            LogFormat::Json => write!(f, "json"),
+        }
+    }
+}
+
+fn text_prefix(level: LogLevel) -> &'static str {
+    match level {
+        LogLevel::Error => "\x1b[31mErr:\x1b[0m ",
+        LogLevel::Warn => "\x1b[93mWrn:\x1b[0m ",
+        LogLevel::Info => "\x1b[35mInfo:\x1b[0m ",
+        LogLevel::Debug | LogLevel::Off => "",
+    }
+}
+
+// The members of `fields` go after the timestamp, level and module
+fn json_line(level: LogLevel, module: &str, fields: Value) -> String {
+    let mut line = Map::new();
+    line.insert("timestamp".to_string(), Utc::now().to_rfc3339().into());
+    line.insert("level".to_string(), level.to_string().into());
+    line.insert("module".to_string(), module.into());
+    if let Value::Object(fields) = fields {
+        line.extend(fields);
+    }
+    Value::Object(line).to_string()
+}
+
+fn format_line(format: LogFormat, level: LogLevel, module: &str, message: &str) -> String {
+    match format {
+        LogFormat::Text => format!("{}{}", text_prefix(level), message),
+        LogFormat::Json => json_line(level, module, json!({ "message": message })),
+    }
+}
+
+// Print `message` in the format we're configured with. Callers check if it's enabled.
+pub fn log_line(level: LogLevel, module: &str, message: &str) {
+    println!(
+        "{}",
+        format_line(log_filter().format, level, module, message)
+    );
+}
+
+// Generated ids start with when we started, so they don't repeat across restarts
+fn id_prefix() -> u32 {
+    static PREFIX: OnceLock<u32> = OnceLock::new();
+    *PREFIX.get_or_init(|| {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs() as u32
+    })
+}
+
+static NEXT_REQUEST_ID: AtomicU64 = AtomicU64::new(0);
+
+// Id of an incoming request, the one the client or a proxy in front of us sent if any
+pub fn request_id(headers: &HeaderMap) -> String {
+    let sent = headers
+        .get(REQUEST_ID_HEADER)
+        .and_then(|id| id.to_str().ok())
+        .filter(|id| {
+            !id.is_empty()
+                && id.len() <= MAX_REQUEST_ID_LEN
+                && id.bytes().all(|byte| byte.is_ascii_graphic())
+        });
+
+    match sent {
+        Some(id) => id.to_string(),
+        None => {
+            format!(
+                "{:08x}{:08x}",
+                id_prefix(),
+                NEXT_REQUEST_ID.fetch_add(1, Ordering::Relaxed)
+            )
+        }
+    }
+}
+
+// What happened to a single JSON-RPC request
+#[derive(Debug, Clone, PartialEq)]
+pub struct RequestLog<'a> {
+    pub id: &'a str,
+    pub method: &'a str,
+    // None if it was cached, or went to several RPCs at once
+    pub upstream: Option<&'a str>,
+    pub cached: bool,
+    pub retries: u32,
+    pub latency: Duration,
+    pub error: bool,
+}
+
+impl RequestLog<'_> {
+    fn line(&self, format: LogFormat) -> String {
+        let latency_ms = self.latency.as_secs_f64() * 1000.0;
+        match format {
+            LogFormat::Text => {
+                format!(
+                    "{}Request {}: {} from {}, {} retries, {:.2}ms{}",
+                    text_prefix(LogLevel::Info),
+                    self.id,
+                    self.method,
+                    match (self.cached, self.upstream) {
+                        (true, _) => "cache",
+                        (false, Some(upstream)) => upstream,
+                        (false, None) => "several RPCs",
+                    },
+                    self.retries,
+                    latency_ms,
+                    if self.error { ", errored" } else { "" },
+                )
+            }
+            LogFormat::Json => {
+                let fields = json!({
+                    "request_id": self.id,
+                    "method": self.method,
+                    "upstream": self.upstream,
+                    "cache": if self.cached { "hit" } else { "miss" },
+                    "retries": self.retries,
+                    "latency_ms": latency_ms,
+                    "error": self.error,
+                });
+                json_line(LogLevel::Info, "request", fields)
+            }
+        }
+    }
+}
+
+// Print the line for a request, if request logs are enabled and it's sampled
+pub fn log_request(request: &RequestLog) {
+    if log_sampled("request", LogLevel::Info) {
+        println!("{}", request.line(log_filter().format));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request() -> RequestLog<'static> {
+        RequestLog {
+            id: "abc",
+            method: "eth_call",
+            upstream: Some("llama"),
+            cached: false,
+            retries: 1,
+            latency: Duration::from_micros(12_500),
+            error: false,
+        }
+    }
+
+    #[test]
+    fn test_text_line() {
+        assert_eq!(
+            request().line(LogFormat::Text),
+            "\x1b[35mInfo:\x1b[0m Request abc: eth_call from llama, 1 retries, 12.50ms"
+        );
+        let cached = RequestLog {
+            cached: true,
+            upstream: None,
+            error: true,
+            ..request()
+        };
+        assert!(cached
+            .line(LogFormat::Text)
+            .ends_with("from cache, 1 retries, 12.50ms, errored"));
+        assert_eq!(
+            format_line(LogFormat::Text, LogLevel::Warn, "balancer", "slow"),
+            "\x1b[93mWrn:\x1b[0m slow"
+        );
+    }
+
+    #[test]
+    fn test_json_line() {
+        let line: Value = serde_json::from_str(&request().line(LogFormat::Json)).unwrap();
+        assert_eq!(line["level"], "info");
+        assert_eq!(line["module"], "request");
+        assert_eq!(line["request_id"], "abc");
+        assert_eq!(line["upstream"], "llama");
+        assert_eq!(line["cache"], "miss");
+        assert_eq!(line["retries"], 1);
+        assert_eq!(line["latency_ms"], 12.5);
+        assert!(line["timestamp"].is_string());
+
+        let line: Value = serde_json::from_str(&format_line(
+            LogFormat::Json,
+            LogLevel::Warn,
+            "balancer",
+            "An RPC request has timed out",
+        ))
+        .unwrap();
+        assert_eq!(line["level"], "warn");
+        assert_eq!(line["message"], "An RPC request has timed out");
+    }
+
+    #[test]
+    fn test_request_id() {
+        let mut headers = HeaderMap::new();
+        let first = request_id(&headers);
+        let second = request_id(&headers);
+        assert_eq!(first.len(), 16);
+        assert_ne!(first, second);
+
+        headers.insert(REQUEST_ID_HEADER, "from-proxy".parse().unwrap());
+        assert_eq!(request_id(&headers), "from-proxy");
+        headers.insert(REQUEST_ID_HEADER, "has spaces".parse().unwrap());
+        assert_ne!(request_id(&headers), "has spaces");
+    }
+}
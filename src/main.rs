@@ -1,36 +1,181 @@
 mod admin;
+mod anomaly;
 mod balancer;
+mod bitcoin;
 mod config;
+mod gas;
 mod health;
+mod logging;
+mod metrics;
+mod ratelimit;
 mod rpc;
+mod slo;
+mod solana;
+mod subscriptions;
+mod tls;
+mod wallet;
+mod websocket;
 
 use crate::{
-    admin::listener::listen_for_admin_requests,
-    balancer::accept_http::accept_request,
+    admin::{
+        listener::{
+            listen_for_admin_requests,
+            Trackers,
+        },
+        status::print_status,
+    },
+    anomaly::{
+        alert::anomaly_alerts,
+        types::AnomalyDetector,
+    },
+    balancer::{
+        accept_http::ConnectionParams,
+        admission::TinyLfu,
+        block_index::INDEX_TREE,
+        chains::{
+            check_chains,
+            serve_routed,
+            ChainRouter,
+        },
+        coalesce::Coalescer,
+        codec::{
+            bound_cache_size,
+            rebuild_dictionaries,
+            CacheCodec,
+            CACHE_SIZE_INTERVAL,
+            DICTIONARY_TREE,
+        },
+        dedup::{
+            BlobStore,
+            BLOB_TREE,
+        },
+        filters::FilterTracker,
+        head_gate::HeadGate,
+        inclusion::{
+            track_inclusion,
+            InclusionTracker,
+        },
+        pacing::BackfillPacer,
+        schema::SchemaChecker,
+        send_queue::SendQueue,
+        static_responses::verify,
+        tx_queue::{
+            flush_tx_queue,
+            TxQueue,
+        },
+        tx_tracker::TxTracker,
+        upstream_batch::UpstreamBatcher,
+    },
     config::{
         cache_setup::setup_data,
         cli_args::create_match,
+        reload::watch_config,
+        schedule::run_schedule,
         types::Settings,
     },
+    gas::{
+        poll::poll_fees,
+        types::FeeOracle,
+    },
     health::{
         check::health_check,
+        divergence::detect_divergence,
+        drift::track_drift,
+        families::check_families,
+        handshake::measure_handshakes,
         head_cache::manage_cache,
+        prewarm::prewarm_connections,
+        probe::run_probes,
+        reorg::detect_reorgs,
         safe_block::NamedBlocknumbers,
+        self_test::self_test,
+        sla::enforce_slas,
+        standby::{
+            verify_standbys,
+            StandbyTracker,
+        },
+        tombstone::TOMBSTONE_TREE,
+        watchdog::{
+            watchdog,
+            Supervised,
+            Watchdog,
+        },
+    },
+    logging::filter::{
+        log_sampled,
+        set_log_filter,
+        LogLevel,
+    },
+    metrics::{
+        cache_usage::CacheUsage,
+        histogram::LatencyHistograms,
+        prometheus::listen_for_metrics,
+        store::{
+            flush_events,
+            EventStore,
+            EVENT_TREE,
+        },
+        talkers::TopTalkers,
+    },
+    ratelimit::{
+        persist::{
+            load_rate_limits,
+            persist_rate_limits,
+            RATELIMIT_TREE,
+        },
+        types::RateLimiter,
+    },
+    rpc::types::{
+        Protocol,
+        Rpc,
+    },
+    slo::{
+        alert::slo_alerts,
+        types::SloTracker,
+    },
+    solana::batch::signature_status_batcher,
+    subscriptions::{
+        finalized::finalized_heads,
+        sse::{
+            publish_events,
+            EventHub,
+        },
+        types::SubscriptionData,
+    },
+    tls::acceptor::{
+        watch_certificate,
+        CertAcceptor,
+    },
+    wallet::{
+        manager::{
+            manage_transactions,
+            TxManager,
+        },
+        types::Wallet,
+    },
+    websocket::{
+        hub::SubscriptionHub,
+        listener::listen_for_websockets,
     },
-    rpc::types::Rpc,
 };
 
 use std::{
     collections::BTreeMap,
+    net::SocketAddr,
     println,
     sync::{
         Arc,
         RwLock,
     },
+    time::Duration,
 };
 
 use tokio::net::TcpListener;
-use tokio::sync::watch;
+use tokio::sync::{
+    mpsc,
+    watch,
+    Semaphore,
+};
 
 use hyper::{
     server::conn::http1,
@@ -42,34 +187,107 @@ use hyper_util_blutgang::rt::TokioIo;
 #[global_allocator]
 static ALLOC: jemallocator::Jemalloc = jemallocator::Jemalloc;
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    // Get all the cli args amd set them
-    let config = Arc::new(RwLock::new(Settings::new(create_match()).await));
-
+// Start everything the chain of `config` needs, from its health checks to its cache,
+// and return what its requests get served with.
+//
+// Each chain only shares our listener with the others. `path` is where it's served
+// on it, empty for the chain of our own config.
+async fn start_chain(
+    config: Arc<RwLock<Settings>>,
+    addr: SocketAddr,
+    path: &str,
+    tls: Option<Arc<CertAcceptor>>,
+) -> Result<ConnectionParams, Box<dyn std::error::Error>> {
     // Copy the configuration values we need
-    let (addr_clone, do_clear_clone, health_check_clone, admin_enabled_clone) = {
+    let (do_clear_clone, health_check_clone, admin_enabled_clone) = {
         let config_guard = config.read().unwrap();
         (
-            config_guard.address,
             config_guard.do_clear,
             config_guard.health_check,
             config_guard.admin.enabled,
         )
     };
 
+    // Hardened mode rate limits each client
+    let rate_limiter = {
+        let config_guard = config.read().unwrap();
+        if config_guard.hardened.enabled {
+            Some(Arc::new(RateLimiter::new(
+                config_guard.hardened.requests_per_second,
+                config_guard.hardened.burst,
+                config_guard.hardened.daily_quota,
+            )))
+        } else {
+            None
+        }
+    };
+
+    // Concurrency limit for heavy simulation/tracing methods
+    let heavy_semaphore = Arc::new(Semaphore::new(config.read().unwrap().heavy_concurrency));
+
+    // Track SLOs and alert if the error budget burns too fast
+    let slo = {
+        let slo_settings = config.read().unwrap().slo.clone();
+        slo_settings.enabled.then(|| {
+            let slo = Arc::new(SloTracker::new(
+                slo_settings.target,
+                slo_settings.window_days,
+            ));
+            let slo_alert = Arc::clone(&slo);
+            tokio::task::spawn(async move {
+                slo_alerts(
+                    slo_alert,
+                    slo_settings.burn_rate_threshold,
+                    slo_settings.alert_webhook,
+                )
+                .await;
+            });
+            slo
+        })
+    };
+
     // Make the list a rwlock
     let rpc_list_rwlock = Arc::new(RwLock::new(config.read().unwrap().rpc_list.clone()));
 
+    // Check the RPCs are on the same chain before we answer for them
+    let static_responses = {
+        let (enabled, protocol, ttl) = {
+            let config_guard = config.read().unwrap();
+            (
+                config_guard.static_responses,
+                config_guard.protocol(),
+                config_guard.ttl,
+            )
+        };
+        match (enabled, protocol) {
+            (true, Protocol::Evm) => {
+                let rpc_list = rpc_list_rwlock.read().unwrap().clone();
+                let ttl = Duration::from_millis(ttl.try_into().unwrap());
+                let responses = verify(&rpc_list, ttl)
+                    .await
+                    .unwrap_or_else(|err| panic!("\x1b[31mErr:\x1b[0m {}", err));
+                Some(Arc::new(responses))
+            }
+            (true, _) => {
+                println!("\x1b[93mWrn:\x1b[0m static_responses is only supported on EVM chains");
+                None
+            }
+            (false, _) => None,
+        }
+    };
+
     // Create/Open sled DB
     let cache = Arc::new(config.read().unwrap().sled_config.open().unwrap());
 
     // Cache for storing querries near the tip
-    let head_cache = Arc::new(RwLock::new(BTreeMap::<u64, Vec<String>>::new()));
+    let head_cache = Arc::new(RwLock::new(BTreeMap::<u64, Vec<Vec<u8>>>::new()));
 
     // Clear database if specified
     if do_clear_clone {
         cache.clear().unwrap();
+        cache.open_tree(BLOB_TREE)?.clear()?;
+        cache.open_tree(INDEX_TREE)?.clear()?;
+        cache.open_tree(TOMBSTONE_TREE)?.clear()?;
         println!("\x1b[93mWrn:\x1b[0m All data cleared from the database.");
     }
     // Insert data about blutgang and our settings into the DB
@@ -77,9 +295,36 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     // Print any relevant warnings about a misconfigured DB. Check docs for more
     setup_data(Arc::clone(&cache));
 
-    // We create a TcpListener and bind it to 127.0.0.1:3000
-    let listener = TcpListener::bind(addr_clone).await?;
-    println!("\x1b[35mInfo:\x1b[0m Bound to: {}", addr_clone);
+    // Restore rate limit state so restarting doesn't refill everyones quota
+    if let Some(rate_limiter) = &rate_limiter {
+        let ratelimit_tree = cache.open_tree(RATELIMIT_TREE)?;
+        load_rate_limits(rate_limiter, &ratelimit_tree)?;
+
+        let rate_limiter = Arc::clone(rate_limiter);
+        tokio::task::spawn(async move {
+            if let Err(err) = persist_rate_limits(rate_limiter, ratelimit_tree).await {
+                println!("\x1b[31mErr:\x1b[0m Could not persist rate limits: {}", err);
+            }
+        });
+    }
+
+    // Keep request summaries and health events on disk if enabled
+    let event_store = {
+        let settings = config.read().unwrap().event_store.clone();
+        match settings.enabled {
+            true => {
+                let store = Arc::new(EventStore::new(cache.open_tree(EVENT_TREE)?, &settings));
+                let flushed = Arc::clone(&store);
+                tokio::task::spawn(async move {
+                    if let Err(err) = flush_events(flushed).await {
+                        println!("\x1b[31mErr:\x1b[0m Could not write events: {}", err);
+                    }
+                });
+                Some(store)
+            }
+            false => None,
+        }
+    };
 
     // Spawn a thread for the health check
     //
@@ -90,31 +335,455 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let (finalized_tx, finalized_rx) = watch::channel(0);
     let finalized_rx_arc = Arc::new(finalized_rx);
 
-    if health_check_clone {
+    let health_task = health_check_clone.then(|| {
         let rpc_list_health = Arc::clone(&rpc_list_rwlock);
         let poverty_list_health = Arc::clone(&rpc_poverty_list);
         let named_blocknumbers_health = Arc::clone(&named_blocknumbers);
         let config_health = Arc::clone(&config);
+        let event_store_health = event_store.clone();
+        let blocknum_tx = Arc::new(blocknum_tx);
+        let finalized_tx = Arc::new(finalized_tx);
+
+        // Also check every RPC over IPv4 and IPv6, if it has both
+        tokio::task::spawn(check_families(
+            Arc::clone(&rpc_list_rwlock),
+            Arc::clone(&rpc_poverty_list),
+            Arc::clone(&config),
+        ));
+
+        // Supervised, so the watchdog can restart it
+        Supervised::spawn(move || {
+            let rpc_list = Arc::clone(&rpc_list_health);
+            let poverty_list = Arc::clone(&poverty_list_health);
+            let named_blocknumbers = Arc::clone(&named_blocknumbers_health);
+            let config = Arc::clone(&config_health);
+            let event_store = event_store_health.clone();
+            let blocknum_tx = Arc::clone(&blocknum_tx);
+            let finalized_tx = Arc::clone(&finalized_tx);
+            tokio::task::spawn(async move {
+                if let Err(err) = health_check(
+                    rpc_list,
+                    poverty_list,
+                    &blocknum_tx,
+                    &finalized_tx,
+                    &named_blocknumbers,
+                    &config,
+                    event_store,
+                )
+                .await
+                {
+                    println!("\x1b[31mErr:\x1b[0m Health check stopped: {}", err);
+                }
+            })
+        })
+    });
+
+    // Keep connections to RPCs open. Runs even if no RPC is prewarmed yet,
+    // so prewarming can be turned on by reloading the config.
+    let prewarm_interval = config.read().unwrap().prewarm_interval;
+    if prewarm_interval > 0 {
+        tokio::task::spawn(prewarm_connections(
+            Arc::clone(&rpc_list_rwlock),
+            Duration::from_millis(prewarm_interval),
+        ));
+    }
+
+    // Time connecting to RPCs, so their metrics show where requests spend their time
+    let handshake_interval = config.read().unwrap().handshake_interval;
+    if handshake_interval > 0 {
+        tokio::task::spawn(measure_handshakes(
+            Arc::clone(&rpc_list_rwlock),
+            Duration::from_millis(handshake_interval),
+        ));
+    }
 
+    // Follow fees for `blutgang_suggestFees` if enabled
+    let fee_oracle = {
+        let config_guard = config.read().unwrap();
+        (config_guard.fee_oracle_interval > 0).then(|| {
+            let oracle = Arc::new(FeeOracle::new(config_guard.fee_oracle_blocks));
+            tokio::task::spawn(poll_fees(
+                Arc::clone(&rpc_list_rwlock),
+                Arc::clone(&oracle),
+                Duration::from_millis(config_guard.fee_oracle_interval),
+            ));
+            oracle
+        })
+    };
+
+    // Pause and unpause RPCs according to the schedule
+    let schedule = config.read().unwrap().schedule.clone();
+    if !schedule.is_empty() {
+        let rpc_list_schedule = Arc::clone(&rpc_list_rwlock);
+        let poverty_list_schedule = Arc::clone(&rpc_poverty_list);
+
+        tokio::task::spawn(run_schedule(
+            schedule,
+            rpc_list_schedule,
+            poverty_list_schedule,
+        ));
+    }
+
+    // Keep per-client stats if enabled
+    let talkers = {
+        let config_guard = config.read().unwrap();
+        config_guard.top_talkers.then(|| {
+            Arc::new(TopTalkers::new(
+                config_guard.top_talkers_max_clients,
+                config_guard.top_talkers_export,
+            ))
+        })
+    };
+
+    // Count cache hits and misses per client if enabled
+    let cache_usage = config
+        .read()
+        .unwrap()
+        .consumer_cache_stats
+        .then(|| Arc::new(CacheUsage::default()));
+
+    // Check that backup RPCs would hold up if we failed over to them, if enabled
+    let standby = {
+        let settings = config.read().unwrap().standby.clone();
+        settings.enabled.then(|| {
+            let tracker = Arc::new(StandbyTracker::new(&settings));
+            let ttl = config.read().unwrap().ttl;
+            tokio::task::spawn(verify_standbys(
+                Arc::clone(&rpc_list_rwlock),
+                Arc::clone(&rpc_poverty_list),
+                blocknum_rx.clone(),
+                Arc::clone(&tracker),
+                settings,
+                Duration::from_millis(ttl.try_into().unwrap()),
+            ));
+            tracker
+        })
+    };
+
+    // Only measure request latencies if we serve them
+    let metrics = config
+        .read()
+        .unwrap()
+        .metrics
+        .then(|| Arc::new(LatencyHistograms::default()));
+
+    // Fan out upstream subscriptions to WebSocket clients if enabled
+    let websocket_settings = config.read().unwrap().websocket.clone();
+    if websocket_settings.enabled {
+        let hub = Arc::new(SubscriptionHub::new(
+            Arc::clone(&rpc_list_rwlock),
+            Duration::from_millis(config.read().unwrap().ttl.try_into().unwrap()),
+            (*finalized_rx_arc).clone(),
+        ));
+        let tls_websocket = tls.clone();
+        let path = path.to_string();
         tokio::task::spawn(async move {
-            let _ = health_check(
-                rpc_list_health,
-                poverty_list_health,
-                &blocknum_tx,
-                finalized_tx,
-                &named_blocknumbers_health,
-                &config_health,
+            if let Err(err) = listen_for_websockets(
+                hub,
+                websocket_settings.address,
+                addr,
+                &path,
+                websocket_settings.max_subscriptions,
+                tls_websocket,
             )
-            .await;
+            .await
+            {
+                println!("\x1b[31mErr:\x1b[0m WebSocket listener stopped: {}", err);
+            }
         });
     }
 
+    // Spawn a thread for the `finalizedHeads` subscription
+    let sub_data = Arc::new(SubscriptionData::new());
+    let sub_data_finalized = Arc::clone(&sub_data);
+    let finalized_rx_sub = (*finalized_rx_arc).clone();
+    tokio::task::spawn(async move {
+        finalized_heads(finalized_rx_sub, sub_data_finalized).await;
+    });
+
+    // Share new blocks with SSE streams
+    let events = Arc::new(EventHub::default());
+    tokio::task::spawn(publish_events(
+        Arc::clone(&events),
+        Arc::clone(&rpc_list_rwlock),
+        blocknum_rx.clone(),
+        Duration::from_millis(config.read().unwrap().ttl.try_into().unwrap()),
+    ));
+
+    // Spawn a thread for batching `getSignatureStatuses` calls in Solana mode
+    let signature_batcher = if config.read().unwrap().protocol() == Protocol::Solana {
+        let (batch_tx, batch_rx) = mpsc::channel(1024);
+        let rpc_list_batcher = Arc::clone(&rpc_list_rwlock);
+        let ttl = config.read().unwrap().ttl;
+        tokio::task::spawn(async move {
+            signature_status_batcher(batch_rx, rpc_list_batcher, ttl).await;
+        });
+        Some(batch_tx)
+    } else {
+        None
+    };
+
+    // Flag clients whose usage suddenly changes
+    let anomaly = {
+        let anomaly_settings = config.read().unwrap().anomaly.clone();
+        anomaly_settings.enabled.then(|| {
+            let webhook = anomaly_settings.alert_webhook.clone();
+            let (anomaly, alerts_rx) = AnomalyDetector::new(anomaly_settings);
+            tokio::task::spawn(anomaly_alerts(alerts_rx, webhook));
+            Arc::new(anomaly)
+        })
+    };
+
+    // Cut the traffic of RPCs breaching their response time SLA.
+    // Always running, since reloading the config can add SLAs.
+    tokio::task::spawn(enforce_slas(
+        Arc::clone(&rpc_list_rwlock),
+        config.read().unwrap().sla.clone(),
+    ));
+
+    // Mark RPCs failing their probes as degraded. Same as SLAs, reloading can add probes.
+    tokio::task::spawn(run_probes(
+        Arc::clone(&rpc_list_rwlock),
+        blocknum_rx.clone(),
+        Duration::from_millis(config.read().unwrap().ttl.try_into().unwrap()),
+    ));
+
+    // Alert on RPCs changing clients or modules if enabled
+    let (drift_interval, drift_alert_webhook, ttl) = {
+        let config_guard = config.read().unwrap();
+        (
+            config_guard.drift_interval,
+            config_guard.drift_alert_webhook.clone(),
+            config_guard.ttl,
+        )
+    };
+    if drift_interval > 0 {
+        tokio::task::spawn(track_drift(
+            Arc::clone(&rpc_list_rwlock),
+            Arc::clone(&rpc_poverty_list),
+            Duration::from_secs(drift_interval),
+            Duration::from_millis(ttl.try_into().unwrap()),
+            drift_alert_webhook,
+        ));
+    }
+
+    // Reload the config on SIGHUP, and when it changes if enabled
+    let reload_interval = config.read().unwrap().reload_interval;
+    tokio::task::spawn(watch_config(
+        Arc::clone(&rpc_list_rwlock),
+        Arc::clone(&rpc_poverty_list),
+        Arc::clone(&config),
+        Duration::from_secs(reload_interval),
+    ));
+
+    // Catch RPCs serving a different chain than the rest if enabled.
+    // Needs the head from health checks.
+    let (divergence_settings, protocol) = {
+        let config_guard = config.read().unwrap();
+        (config_guard.divergence.clone(), config_guard.protocol())
+    };
+    if divergence_settings.enabled && health_check_clone && protocol == Protocol::Evm {
+        tokio::task::spawn(detect_divergence(
+            Arc::clone(&rpc_list_rwlock),
+            Arc::clone(&rpc_poverty_list),
+            blocknum_rx.clone(),
+            divergence_settings,
+            Duration::from_millis(ttl.try_into().unwrap()),
+        ));
+    }
+
+    // Catch reorgs that don't make the head go back if enabled.
+    // Needs the head from health checks.
+    let reorg_settings = config.read().unwrap().reorg.clone();
+    if reorg_settings.enabled && health_check_clone && protocol == Protocol::Evm {
+        tokio::task::spawn(detect_reorgs(
+            Arc::clone(&rpc_list_rwlock),
+            blocknum_rx.clone(),
+            Arc::clone(&head_cache),
+            Arc::clone(&cache),
+            reorg_settings,
+            Duration::from_millis(ttl.try_into().unwrap()),
+        ));
+    }
+
+    // Act on our own subsystems getting stuck if enabled
+    let watchdog = {
+        let watchdog_settings = config.read().unwrap().watchdog.clone();
+        watchdog_settings.enabled.then(|| {
+            let state = Arc::new(Watchdog::default());
+            tokio::task::spawn(watchdog(
+                Arc::clone(&state),
+                watchdog_settings,
+                Arc::clone(&cache),
+                Arc::clone(&rpc_list_rwlock),
+                Arc::clone(&rpc_poverty_list),
+                blocknum_rx.clone(),
+                health_task,
+            ));
+            state
+        })
+    };
+
+    // Resubmit transactions that ask for it until they're included
+    let tx_manager = {
+        let tx_manager_settings = config.read().unwrap().tx_manager.clone();
+        tx_manager_settings
+            .enabled
+            .then(|| Arc::new(TxManager::new(tx_manager_settings)))
+    };
+
+    // Sign and send transactions ourselves if the wallet is enabled
+    let wallet = {
+        let wallet_settings = config.read().unwrap().wallet.clone();
+        wallet_settings.enabled.then(|| {
+            println!(
+                "\x1b[35mInfo:\x1b[0m Wallet enabled, signing with {}",
+                wallet_settings.signer
+            );
+            let wallet = Wallet::new(wallet_settings);
+            match &tx_manager {
+                Some(manager) => Arc::new(wallet.with_manager(Arc::clone(manager))),
+                None => Arc::new(wallet),
+            }
+        })
+    };
+    if let Some(manager) = &tx_manager {
+        tokio::task::spawn(manage_transactions(
+            Arc::clone(manager),
+            wallet.clone(),
+            Arc::clone(&rpc_list_rwlock),
+            Duration::from_millis(ttl.try_into().unwrap()),
+        ));
+    }
+
+    // Negative cache for transaction lookups, and where our transactions are known
+    let tx_tracker = {
+        let config_guard = config.read().unwrap();
+        let negative_cache_ttl = config_guard.negative_cache_ttl;
+        (negative_cache_ttl > 0 || config_guard.read_your_writes)
+            .then(|| Arc::new(TxTracker::new(Duration::from_millis(negative_cache_ttl))))
+    };
+
+    // Which RPC has which filter
+    let filters = Arc::new(FilterTracker::default());
+
+    // Share upstream calls between identical requests for the head if enabled
+    let (coalesce_window, single_flight) = {
+        let config_guard = config.read().unwrap();
+        (config_guard.coalesce_window, config_guard.single_flight)
+    };
+    let coalescer = (coalesce_window > 0 || single_flight).then(|| {
+        Arc::new(Coalescer::new(
+            Duration::from_millis(coalesce_window),
+            single_flight,
+        ))
+    });
+
+    // Keep `latest` from going backwards for clients if enabled
+    let head_gate = config
+        .read()
+        .unwrap()
+        .head_gating
+        .then(|| Arc::new(HeadGate::default()));
+
+    // Check sampled responses against the schema of their method if enabled
+    let schema_sample_rate = config.read().unwrap().schema_sample_rate;
+    let schema =
+        (schema_sample_rate > 0.0).then(|| Arc::new(SchemaChecker::new(schema_sample_rate)));
+
+    // Queue for transactions sent while every RPC is down
+    let tx_queue = if config.read().unwrap().tx_queue {
+        let tx_queue = Arc::new(TxQueue::new(Arc::clone(&cache))?);
+        tokio::task::spawn(flush_tx_queue(
+            Arc::clone(&tx_queue),
+            Arc::clone(&rpc_list_rwlock),
+            Duration::from_millis(ttl.try_into().unwrap()),
+        ));
+        Some(tx_queue)
+    } else {
+        None
+    };
+
+    // Compress cached responses if enabled
+    let codec = {
+        let config_guard = config.read().unwrap();
+        let interval = config_guard.cache_dictionary_interval;
+        // Blobs are always needed to read entries that were deduplicated before
+        let blobs = BlobStore::new(cache.open_tree(BLOB_TREE)?);
+        let mut codec = CacheCodec::new(config_guard.cache_compression, interval > 0)
+            .with_blobs(blobs, config_guard.cache_dedup);
+        if config_guard.cache_max_entries > 0 {
+            codec = codec.with_admission(TinyLfu::new(
+                config_guard.cache_max_entries,
+                config_guard.cache_admission_window,
+                config_guard.cache_admission,
+            ));
+        }
+        let codec = Arc::new(codec);
+
+        // Entries from previous runs count towards the limit too
+        let evicted = codec.track_cached(&cache)?;
+        if evicted > 0 {
+            println!(
+                "\x1b[35mInfo:\x1b[0m Evicted {} cache entries over cache_max_entries",
+                evicted
+            );
+        }
+
+        // Dictionaries from previous runs are needed to read entries compressed with them
+        let dictionary_tree = cache.open_tree(DICTIONARY_TREE)?;
+        codec.load_dictionaries(&dictionary_tree)?;
+
+        if interval > 0 && config_guard.cache_compression > 0 {
+            let codec = Arc::clone(&codec);
+            tokio::task::spawn(async move {
+                if let Err(err) =
+                    rebuild_dictionaries(codec, dictionary_tree, Duration::from_secs(interval))
+                        .await
+                {
+                    println!(
+                        "\x1b[31mErr:\x1b[0m Could not save cache compression dictionary: {}",
+                        err
+                    );
+                }
+            });
+        }
+
+        let max_size = config_guard.cache_max_size * 1024 * 1024;
+        if max_size > 0 {
+            let codec = Arc::clone(&codec);
+            let cache = Arc::clone(&cache);
+            tokio::task::spawn(async move {
+                if let Err(err) =
+                    bound_cache_size(codec, cache, max_size, CACHE_SIZE_INTERVAL).await
+                {
+                    println!(
+                        "\x1b[31mErr:\x1b[0m Could not evict entries over cache_max_size: {}",
+                        err
+                    );
+                }
+            });
+        }
+
+        codec
+    };
+
     // Spawn a thread for the admin namespace if enabled
     if admin_enabled_clone {
         let rpc_list_admin = Arc::clone(&rpc_list_rwlock);
         let poverty_list_admin = Arc::clone(&rpc_poverty_list);
         let cache_admin = Arc::clone(&cache);
         let config_admin = Arc::clone(&config);
+        let trackers_admin = Trackers {
+            slo: slo.clone(),
+            talkers: talkers.clone(),
+            rate_limiter: rate_limiter.clone(),
+            latency: metrics.clone(),
+            events: event_store.clone(),
+            standby: standby.clone(),
+            codec: Some(Arc::clone(&codec)),
+            schema: schema.clone(),
+        };
         tokio::task::spawn(async move {
             println!("\x1b[35mInfo:\x1b[0m Admin namespace enabled, accepting admin methods at admin port");
             let _ = listen_for_admin_requests(
@@ -122,53 +791,271 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 poverty_list_admin,
                 cache_admin,
                 config_admin,
+                trackers_admin,
             )
             .await;
         });
     }
 
+    // Coalesce requests to the same RPC into batches if enabled
+    let upstream_batcher = {
+        let config_guard = config.read().unwrap();
+        (config_guard.upstream_batch_size > 1).then(|| {
+            UpstreamBatcher::new(
+                config_guard.upstream_batch_size,
+                Duration::from_millis(config_guard.upstream_batch_window),
+                config_guard.upstream_batch_failure,
+            )
+        })
+    };
+
+    // Serve per-RPC stats to Prometheus on their own port if enabled
+    let prometheus_settings = config.read().unwrap().prometheus.clone();
+    if prometheus_settings.enabled {
+        let rpc_list_metrics = Arc::clone(&rpc_list_rwlock);
+        let poverty_list_metrics = Arc::clone(&rpc_poverty_list);
+        let cache_stats = Arc::clone(codec.stats());
+        let rate_limiter_metrics = rate_limiter.clone();
+        let finalized_rx_metrics = Arc::clone(&finalized_rx_arc);
+        tokio::task::spawn(async move {
+            if let Err(err) = listen_for_metrics(
+                prometheus_settings.address,
+                rpc_list_metrics,
+                poverty_list_metrics,
+                cache_stats,
+                rate_limiter_metrics,
+                finalized_rx_metrics,
+            )
+            .await
+            {
+                println!("\x1b[31mErr:\x1b[0m Metrics listener stopped: {}", err);
+            }
+        });
+    }
+
+    // Limit requests in flight to each RPC if enabled
+    let send_queue = {
+        let config_guard = config.read().unwrap();
+        (config_guard.upstream_max_in_flight > 0).then(|| {
+            Arc::new(SendQueue::new(
+                config_guard.upstream_max_in_flight,
+                config_guard.upstream_max_queued,
+            ))
+        })
+    };
+
+    // Pace backfill requests to stay under the rate limits of RPCs if enabled
+    let backfill_pacer = {
+        let config_guard = config.read().unwrap();
+        let backfill = &config_guard.backfill;
+        backfill
+            .enabled
+            .then(|| Arc::new(BackfillPacer::new(backfill.rate, backfill.min_rate)))
+    };
+
+    // Time how long broadcast transactions take to get included if enabled
+    let (track, ttl) = {
+        let config_guard = config.read().unwrap();
+        (config_guard.track_inclusion, config_guard.ttl)
+    };
+    let inclusion = track.then(|| {
+        let tracker = Arc::new(InclusionTracker::default());
+        tokio::task::spawn(track_inclusion(
+            Arc::clone(&tracker),
+            Arc::clone(&rpc_list_rwlock),
+            blocknum_rx.clone(),
+            Duration::from_millis(ttl.try_into().unwrap()),
+        ));
+        tracker
+    });
+
     // Spawn a thread for the head cache
     let head_cache_clone = Arc::clone(&head_cache);
     let cache_clone = Arc::clone(&cache);
     let finalized_rxclone = Arc::clone(&finalized_rx_arc);
+    let blocknum_rx_cache = blocknum_rx.clone();
+    let tombstone_retention = Duration::from_secs(config.read().unwrap().reorg.tombstone_retention);
     tokio::task::spawn(async move {
         let _ = manage_cache(
             &head_cache_clone,
-            blocknum_rx,
+            blocknum_rx_cache,
             finalized_rxclone,
             &cache_clone,
+            tombstone_retention,
         )
         .await;
     });
 
+    Ok(ConnectionParams {
+        rpc_list_rwlock,
+        finalized_rx: finalized_rx_arc,
+        blocknum_rx,
+        named_numbers: named_blocknumbers,
+        head_cache,
+        sub_data,
+        events,
+        cache,
+        config,
+        rate_limiter,
+        heavy_semaphore,
+        signature_batcher,
+        upstream_batcher,
+        slo,
+        tx_tracker,
+        filters,
+        static_responses,
+        coalescer,
+        head_gate,
+        schema,
+        tx_queue,
+        codec,
+        send_queue,
+        backfill_pacer,
+        anomaly,
+        wallet,
+        tx_manager,
+        fee_oracle,
+        inclusion,
+        metrics,
+        talkers,
+        cache_usage,
+        watchdog,
+        event_store,
+    })
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // `blutgang status` only talks to an instance that's already running
+    let matches = create_match().get_matches();
+    if let Some(("status", status)) = matches.subcommand() {
+        let url = status.get_one::<String>("url").unwrap();
+        let key = status.get_one::<String>("key").map(String::as_str);
+        std::process::exit(if print_status(url, key).await { 0 } else { 1 });
+    }
+
+    // Get all the cli args amd set them
+    let config = Arc::new(RwLock::new(Settings::new(matches).await));
+    set_log_filter(config.read().unwrap().log_filter.clone());
+
+    // We create a TcpListener and bind it to 127.0.0.1:3000
+    //
+    // The self-test uses any free port so it can run next to a live instance
+    let (addr, self_test_enabled) = {
+        let config_guard = config.read().unwrap();
+        (config_guard.address, config_guard.self_test)
+    };
+    let listener = if self_test_enabled {
+        TcpListener::bind(SocketAddr::new(addr.ip(), 0)).await?
+    } else {
+        TcpListener::bind(addr).await?
+    };
+    let addr = listener.local_addr()?;
+    println!("\x1b[35mInfo:\x1b[0m Bound to: {}", addr);
+
+    // Terminate TLS ourselves if enabled, on both the HTTP and WebSocket listeners
+    let tls_settings = config.read().unwrap().tls.clone();
+    let tls = tls_settings.enabled.then(|| {
+        let acceptor = CertAcceptor::new(&tls_settings).unwrap_or_else(|err| {
+            panic!(
+                "\x1b[31mErr:\x1b[0m Could not load TLS certificate: {}",
+                err
+            )
+        });
+        Arc::new(acceptor)
+    });
+    if let Some(tls) = &tls {
+        tokio::task::spawn(watch_certificate(
+            Arc::clone(tls),
+            tls_settings.reload_interval,
+        ));
+    }
+
+    // Start the chain of our own config, then every other chain we serve
+    let default = start_chain(Arc::clone(&config), addr, "", tls.clone()).await?;
+    let routes = config.read().unwrap().chains.clone();
+    let mut chain_settings = Vec::new();
+    for route in routes {
+        let mut settings = Settings::reload(&route.config)
+            .await
+            .map_err(|err| format!("Could not load the config of chain {}: {}", route.name, err))?;
+        settings.config_path = Some(route.config.clone());
+        chain_settings.push((route, settings));
+    }
+    // The main chain already opened its DB and listeners, but isn't serving yet
+    {
+        let named: Vec<(&str, &Settings)> = chain_settings
+            .iter()
+            .map(|(route, settings)| (route.name.as_str(), settings))
+            .collect();
+        check_chains(&config.read().unwrap(), &named);
+    }
+
+    let mut chains = Vec::new();
+    for (route, settings) in chain_settings {
+        println!(
+            "\x1b[35mInfo:\x1b[0m Serving chain {} at {}",
+            route.name, route.path
+        );
+
+        let config = Arc::new(RwLock::new(settings));
+        let params = start_chain(config, addr, &route.path, tls.clone()).await?;
+        chains.push((route, params));
+    }
+    let router = Arc::new(ChainRouter::new(default.clone(), chains));
+
+    // Run the self-test against ourselves and exit with the result
+    if self_test_enabled {
+        let rpc_list_test = default.rpc_list_rwlock.read().unwrap().clone();
+        let protocol = config.read().unwrap().protocol();
+        let cache_test = Arc::clone(&default.cache);
+        let tls_test = tls.is_some();
+        tokio::task::spawn(async move {
+            let passed = self_test(addr, rpc_list_test, protocol, &cache_test, tls_test).await;
+            let _ = cache_test.flush_async().await;
+            std::process::exit(if passed { 0 } else { 1 });
+        });
+    }
+
     // We start a loop to continuously accept incoming connections
     loop {
         let (stream, socketaddr) = listener.accept().await?;
-        println!("\x1b[35mInfo:\x1b[0m Connection from: {}", socketaddr);
-
-        // Use an adapter to access something implementing `tokio::io` traits as if they implement
-        // `hyper::rt` IO traits.
-        let io = TokioIo::new(stream);
+        if log_sampled("balancer", LogLevel::Info) {
+            println!("\x1b[35mInfo:\x1b[0m Connection from: {}", socketaddr);
+        }
 
-        // Clone the shared `rpc_list_rwlock` and cache for use in the closure
-        let rpc_list_rwlock_clone = Arc::clone(&rpc_list_rwlock);
-        let cache_clone = Arc::clone(&cache);
-        let head_cache_clone = Arc::clone(&head_cache);
-        let finalized_rx_clone = Arc::clone(&finalized_rx_arc);
-        let named_blocknumbers_clone = Arc::clone(&named_blocknumbers);
-        let config_clone = Arc::clone(&config);
+        let router = Arc::clone(&router);
 
         // Spawn a tokio task to serve multiple connections concurrently
+        //
+        // Use an adapter to access something implementing `tokio::io` traits as if they implement
+        // `hyper::rt` IO traits.
+        let tls = tls.clone();
         tokio::task::spawn(async move {
-            accept!(
-                io,
-                &rpc_list_rwlock_clone,
-                &cache_clone,
-                &finalized_rx_clone,
-                &named_blocknumbers_clone,
-                &head_cache_clone,
-                &config_clone
-            );
+            match tls {
+                // The handshake happens here so slow clients don't hold up the accept loop,
+                // and times out so they don't hold connections open forever
+                Some(tls) => {
+                    match tls.accept(stream).await {
+                        Ok(stream) => {
+                            let io = TokioIo::new(stream);
+                            accept!(io, &router, socketaddr);
+                        }
+                        Err(err) => {
+                            if log_sampled("balancer", LogLevel::Info) {
+                                println!(
+                                    "\x1b[93mWrn:\x1b[0m TLS handshake with {} failed: {}",
+                                    socketaddr, err
+                                );
+                            }
+                        }
+                    }
+                }
+                None => {
+                    let io = TokioIo::new(stream);
+                    accept!(io, &router, socketaddr);
+                }
+            }
         });
     }
 }
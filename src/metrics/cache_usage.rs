@@ -0,0 +1,210 @@
+// How cache-friendly each consumer's requests are.
+//
+// Hits and misses are counted per client and method, so clients can call
+// `blutgang_cacheStats` to see their own and find out which of their calls never get
+// cached. Clients are identified by their API key, or IP if they don't send one, and
+// only ever see their own stats. Requests no RPC could answer aren't counted.
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::Instant,
+};
+
+use serde_json::{
+    json,
+    Value,
+};
+
+pub const METHOD: &str = "blutgang_cacheStats";
+
+// Clients we keep stats for, the one we haven't seen the longest is forgotten to make room
+const MAX_CLIENTS: usize = 10_000;
+
+// Methods we keep apart per client, the rest are counted together
+const MAX_METHODS: usize = 256;
+const OTHER_METHODS: &str = "other";
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Counts {
+    hits: u64,
+    misses: u64,
+}
+
+impl Counts {
+    fn record(&mut self, hit: bool) {
+        match hit {
+            true => self.hits += 1,
+            false => self.misses += 1,
+        }
+    }
+
+    fn to_json(self) -> Value {
+        json!({
+            "hits": self.hits,
+            "misses": self.misses,
+            "hit_rate": match self.hits + self.misses {
+                0 => 0.0,
+                total => self.hits as f64 / total as f64,
+            },
+        })
+    }
+}
+
+#[derive(Debug)]
+struct ClientUsage {
+    total: Counts,
+    methods: HashMap<String, Counts>,
+    seen: Instant,
+}
+
+#[derive(Debug, Default)]
+pub struct CacheUsage {
+    clients: Mutex<HashMap<String, ClientUsage>>,
+}
+
+impl CacheUsage {
+    // Count a request of `client` for `method` that was served from the cache if `hit`
+    pub fn record(&self, client: &str, method: &str, hit: bool) {
+        let mut clients = self.clients.lock().unwrap_or_else(|e| e.into_inner());
+        if !clients.contains_key(client) && clients.len() >= MAX_CLIENTS {
+            let stalest = clients
+                .iter()
+                .min_by_key(|(_, usage)| usage.seen)
+                .map(|(client, _)| client.clone());
+            if let Some(stalest) = stalest {
+                clients.remove(&stalest);
+            }
+        }
+
+        let usage = clients.entry(client.to_string()).or_insert_with(|| {
+            ClientUsage {
+                total: Counts::default(),
+                methods: HashMap::new(),
+                seen: Instant::now(),
+            }
+        });
+        usage.total.record(hit);
+        usage.seen = Instant::now();
+
+        let method = match usage.methods.contains_key(method) || usage.methods.len() < MAX_METHODS {
+            true => method,
+            false => OTHER_METHODS,
+        };
+        usage
+            .methods
+            .entry(method.to_string())
+            .or_default()
+            .record(hit);
+    }
+
+    // Stats of `client`, with methods that miss the most first
+    pub fn report(&self, client: &str) -> Value {
+        let clients = self.clients.lock().unwrap_or_else(|e| e.into_inner());
+        let usage = match clients.get(client) {
+            Some(usage) => usage,
+            None => return json!({"total": Counts::default().to_json(), "methods": []}),
+        };
+
+        let mut methods: Vec<(&String, &Counts)> = usage.methods.iter().collect();
+        methods.sort_unstable_by(|a, b| b.1.misses.cmp(&a.1.misses).then(a.0.cmp(b.0)));
+        let methods: Vec<Value> = methods
+            .into_iter()
+            .map(|(method, counts)| {
+                let mut stats = counts.to_json();
+                stats["method"] = method.clone().into();
+                stats
+            })
+            .collect();
+
+        json!({
+            "total": usage.total.to_json(),
+            "methods": methods,
+        })
+    }
+}
+
+// Respond to `blutgang_cacheStats` with the stats of `client`.
+//
+// Returns None if `tx` is some other method.
+pub fn execute_cache_stats_method(
+    tx: &Value,
+    usage: Option<&CacheUsage>,
+    client: &str,
+) -> Option<Value> {
+    if tx["method"] != METHOD {
+        return None;
+    }
+
+    Some(match usage {
+        Some(usage) => {
+            json!({
+                "id": tx["id"],
+                "jsonrpc": "2.0",
+                "result": usage.report(client),
+            })
+        }
+        None => {
+            json!({
+                "id": tx["id"],
+                "jsonrpc": "2.0",
+                "error": {
+                    "code": -32000,
+                    "message": "Cache stats are disabled, set `consumer_cache_stats` to enable them",
+                },
+            })
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cache_usage() {
+        let usage = CacheUsage::default();
+        usage.record("indexer", "eth_getLogs", false);
+        usage.record("indexer", "eth_getLogs", false);
+        usage.record("indexer", "eth_chainId", true);
+        usage.record("wallet", "eth_chainId", true);
+
+        let report = usage.report("indexer");
+        assert_eq!(report["total"]["hits"], 1);
+        assert_eq!(report["total"]["misses"], 2);
+        // Most misses first
+        assert_eq!(report["methods"][0]["method"], "eth_getLogs");
+        assert_eq!(report["methods"][0]["hit_rate"], 0.0);
+        assert_eq!(report["methods"][1]["hit_rate"], 1.0);
+
+        // Only your own
+        assert_eq!(usage.report("wallet")["total"]["hits"], 1);
+        assert_eq!(usage.report("unknown")["total"]["hits"], 0);
+    }
+
+    #[test]
+    fn test_max_methods() {
+        let usage = CacheUsage::default();
+        for i in 0..MAX_METHODS + 10 {
+            usage.record("indexer", &format!("method_{}", i), false);
+        }
+
+        let report = usage.report("indexer");
+        assert_eq!(report["methods"].as_array().unwrap().len(), MAX_METHODS + 1);
+        assert_eq!(report["total"]["misses"], MAX_METHODS + 10);
+    }
+
+    #[test]
+    fn test_execute_cache_stats_method() {
+        let usage = CacheUsage::default();
+        usage.record("indexer", "eth_chainId", true);
+
+        let tx = json!({"id": 1, "jsonrpc": "2.0", "method": METHOD});
+        let rx = execute_cache_stats_method(&tx, Some(&usage), "indexer").unwrap();
+        assert_eq!(rx["id"], 1);
+        assert_eq!(rx["result"]["total"]["hits"], 1);
+
+        assert!(execute_cache_stats_method(&tx, None, "indexer").unwrap()["error"].is_object());
+        let tx = json!({"id": 1, "jsonrpc": "2.0", "method": "eth_chainId"});
+        assert!(execute_cache_stats_method(&tx, Some(&usage), "indexer").is_none());
+    }
+}
@@ -0,0 +1,320 @@
+// Request latency histograms, exported in the OpenMetrics text format.
+//
+// Every bucket keeps the most recent request that landed in it and was part of a
+// sampled trace as an exemplar. Grafana shows exemplars on latency panels, so you
+// can go from a slow bucket straight to the trace of a request that was in it.
+//
+// There's also a histogram for every method and RPC pair, so a method that's only slow
+// on one provider stands out. Those don't keep exemplars, there's a lot more of them.
+use crate::metrics::talkers::TopTalkers;
+
+use serde_json::{
+    json,
+    Value,
+};
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    sync::Mutex,
+    time::{
+        Duration,
+        SystemTime,
+        UNIX_EPOCH,
+    },
+};
+
+pub const PATH: &str = "/metrics";
+
+// Label of requests no RPC served, mostly cache hits
+pub const NO_UPSTREAM: &str = "none";
+
+pub const CONTENT_TYPE: &str = "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
+// Upper bounds of the buckets in seconds, +Inf being implied
+const BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+// Methods come from clients, so past this many everything new is labeled `OTHER_METHOD`
+const MAX_METHODS: usize = 256;
+
+const OTHER_METHOD: &str = "other";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Exemplar {
+    pub trace_id: String,
+    // In seconds
+    pub value: f64,
+    // Unix time in seconds
+    pub timestamp: f64,
+}
+
+#[derive(Debug, Clone, Default)]
+struct Histogram {
+    // Not cumulative, the last one is +Inf
+    counts: [u64; BUCKETS.len() + 1],
+    exemplars: [Option<Exemplar>; BUCKETS.len() + 1],
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn record(&mut self, value: f64, trace_id: Option<&str>) {
+        let bucket = BUCKETS
+            .iter()
+            .position(|bound| value <= *bound)
+            .unwrap_or(BUCKETS.len());
+
+        self.counts[bucket] += 1;
+        self.sum += value;
+        self.count += 1;
+
+        if let Some(trace_id) = trace_id {
+            let timestamp = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs_f64();
+            self.exemplars[bucket] = Some(Exemplar {
+                trace_id: trace_id.to_string(),
+                value,
+                timestamp,
+            });
+        }
+    }
+
+    // Upper bound of the bucket the `q` quantile falls in, None if it's +Inf
+    fn quantile(&self, q: f64) -> Option<f64> {
+        let rank = (q * self.count as f64).ceil() as u64;
+        let mut cumulative = 0;
+        for (bucket, count) in self.counts.iter().enumerate() {
+            cumulative += count;
+            if cumulative >= rank.max(1) {
+                return BUCKETS.get(bucket).copied();
+            }
+        }
+        None
+    }
+
+    // `labels` are written as is, and have to be escaped already
+    fn write(&self, out: &mut String, name: &str, labels: &str) {
+        let mut cumulative = 0;
+        for (bucket, count) in self.counts.iter().enumerate() {
+            cumulative += count;
+            let le = match BUCKETS.get(bucket) {
+                Some(bound) => bound.to_string(),
+                None => "+Inf".to_string(),
+            };
+            let _ = write!(
+                out,
+                "{}_bucket{{{},le=\"{}\"}} {}",
+                name, labels, le, cumulative
+            );
+            if let Some(exemplar) = &self.exemplars[bucket] {
+                let _ = write!(
+                    out,
+                    " # {{trace_id=\"{}\"}} {} {}",
+                    exemplar.trace_id, exemplar.value, exemplar.timestamp
+                );
+            }
+            out.push('\n');
+        }
+        let _ = writeln!(out, "{}_sum{{{}}} {}", name, labels, self.sum);
+        let _ = writeln!(out, "{}_count{{{}}} {}", name, labels, self.count);
+    }
+}
+
+// Latency of the requests we served, labeled by the RPC that served them
+#[derive(Debug, Default)]
+pub struct LatencyHistograms {
+    histograms: Mutex<BTreeMap<String, Histogram>>,
+    // Keyed by method, then by RPC
+    by_method: Mutex<BTreeMap<String, BTreeMap<String, Histogram>>>,
+}
+
+impl LatencyHistograms {
+    // Record a request that took `elapsed`, with the trace it belongs to if it was sampled
+    pub fn record(&self, upstream: &str, elapsed: Duration, trace_id: Option<&str>) {
+        let mut histograms = self.histograms.lock().unwrap();
+        if !histograms.contains_key(upstream) {
+            histograms.insert(upstream.to_string(), Histogram::default());
+        }
+        histograms
+            .get_mut(upstream)
+            .unwrap()
+            .record(elapsed.as_secs_f64(), trace_id);
+    }
+
+    // Record a request for `method` that `upstream` took `elapsed` to serve
+    pub fn record_method(&self, method: &str, upstream: &str, elapsed: Duration) {
+        let mut by_method = self.by_method.lock().unwrap();
+        let method = match by_method.contains_key(method) || by_method.len() < MAX_METHODS {
+            true => method,
+            false => OTHER_METHOD,
+        };
+        by_method
+            .entry(method.to_string())
+            .or_default()
+            .entry(upstream.to_string())
+            .or_default()
+            .record(elapsed.as_secs_f64(), None);
+    }
+
+    // Count, mean and quantiles of every method and RPC pair, optionally only for `method`.
+    //
+    // Quantiles are the upper bounds of the buckets they fall in, null if above all of them.
+    pub fn method_stats(&self, method: Option<&str>) -> Value {
+        let by_method = self.by_method.lock().unwrap();
+        let stats: Vec<Value> = by_method
+            .iter()
+            .filter(|(name, _)| method.map_or(true, |method| method == name.as_str()))
+            .flat_map(|(method, upstreams)| {
+                upstreams.iter().map(move |(upstream, histogram)| {
+                    json!({
+                        "method": method,
+                        "upstream": upstream,
+                        "count": histogram.count,
+                        "mean": histogram.sum / histogram.count as f64,
+                        "p50": histogram.quantile(0.5),
+                        "p90": histogram.quantile(0.9),
+                        "p99": histogram.quantile(0.99),
+                    })
+                })
+            })
+            .collect();
+        Value::Array(stats)
+    }
+
+    // Every histogram in the OpenMetrics text format, exemplars included,
+    // followed by the client series if we track top talkers
+    pub fn to_openmetrics(&self, talkers: Option<&TopTalkers>) -> String {
+        let name = "blutgang_request_duration_seconds";
+        let mut out = String::new();
+        let _ = writeln!(out, "# TYPE {} histogram", name);
+        let _ = writeln!(out, "# UNIT {} seconds", name);
+        let _ = writeln!(
+            out,
+            "# HELP {} Time it took to serve requests, by the RPC that served them.",
+            name
+        );
+
+        for (upstream, histogram) in self.histograms.lock().unwrap().iter() {
+            let labels = format!("upstream=\"{}\"", escape_label(upstream));
+            histogram.write(&mut out, name, &labels);
+        }
+
+        let name = "blutgang_method_duration_seconds";
+        let _ = writeln!(out, "# TYPE {} histogram", name);
+        let _ = writeln!(out, "# UNIT {} seconds", name);
+        let _ = writeln!(
+            out,
+            "# HELP {} Time it took to serve requests, by method and the RPC that served them.",
+            name
+        );
+        for (method, upstreams) in self.by_method.lock().unwrap().iter() {
+            for (upstream, histogram) in upstreams {
+                let labels = format!(
+                    "method=\"{}\",upstream=\"{}\"",
+                    escape_label(method),
+                    escape_label(upstream)
+                );
+                histogram.write(&mut out, name, &labels);
+            }
+        }
+
+        if let Some(talkers) = talkers {
+            talkers.write_openmetrics(&mut out);
+        }
+
+        out.push_str("# EOF\n");
+        out
+    }
+}
+
+pub fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_openmetrics() {
+        let histograms = LatencyHistograms::default();
+        histograms.record("llama", Duration::from_millis(3), None);
+        histograms.record(
+            "llama",
+            Duration::from_millis(400),
+            Some("4bf92f3577b34da6"),
+        );
+        histograms.record("llama", Duration::from_secs(60), None);
+
+        let out = histograms.to_openmetrics(None);
+        assert!(out.contains(
+            "blutgang_request_duration_seconds_bucket{upstream=\"llama\",le=\"0.005\"} 1\n"
+        ));
+        assert!(out.contains(
+            "blutgang_request_duration_seconds_bucket{upstream=\"llama\",le=\"0.5\"} 2 # {trace_id=\"4bf92f3577b34da6\"} 0.4 "
+        ));
+        assert!(out.contains(
+            "blutgang_request_duration_seconds_bucket{upstream=\"llama\",le=\"+Inf\"} 3\n"
+        ));
+        assert!(out.contains("blutgang_request_duration_seconds_count{upstream=\"llama\"} 3\n"));
+        assert!(out.ends_with("# EOF\n"));
+    }
+
+    #[test]
+    fn test_method_histograms() {
+        let histograms = LatencyHistograms::default();
+        histograms.record_method("eth_getLogs", "llama", Duration::from_millis(3));
+        histograms.record_method("eth_getLogs", "llama", Duration::from_millis(4));
+        histograms.record_method("eth_getLogs", "llama", Duration::from_secs(3));
+        histograms.record_method("eth_getLogs", "drpc", Duration::from_millis(40));
+        histograms.record_method("eth_call", "llama", Duration::from_millis(20));
+
+        let out = histograms.to_openmetrics(None);
+        assert!(out.contains("# TYPE blutgang_method_duration_seconds histogram\n"));
+        assert!(out.contains(
+            "blutgang_method_duration_seconds_bucket{method=\"eth_getLogs\",upstream=\"llama\",le=\"0.005\"} 2\n"
+        ));
+        assert!(out.contains(
+            "blutgang_method_duration_seconds_count{method=\"eth_getLogs\",upstream=\"drpc\"} 1\n"
+        ));
+
+        let stats = histograms.method_stats(Some("eth_getLogs"));
+        assert_eq!(stats.as_array().unwrap().len(), 2);
+        assert_eq!(stats[0]["upstream"], "drpc");
+        assert_eq!(stats[1]["count"], 3);
+        assert_eq!(stats[1]["p50"], 0.005);
+        assert_eq!(stats[1]["p99"], 5.0);
+        assert_eq!(histograms.method_stats(None).as_array().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn test_max_methods() {
+        let histograms = LatencyHistograms::default();
+        for i in 0..MAX_METHODS + 10 {
+            histograms.record_method(&format!("method_{}", i), "llama", Duration::from_millis(1));
+        }
+        histograms.record_method("method_0", "llama", Duration::from_millis(1));
+
+        let by_method = histograms.by_method.lock().unwrap();
+        assert_eq!(by_method.len(), MAX_METHODS + 1);
+        assert_eq!(by_method[OTHER_METHOD]["llama"].count, 10);
+        assert_eq!(by_method["method_0"]["llama"].count, 2);
+    }
+
+    #[test]
+    fn test_latest_exemplar() {
+        let mut histogram = Histogram::default();
+        histogram.record(0.2, Some("first"));
+        histogram.record(0.15, Some("second"));
+        histogram.record(0.12, None);
+
+        assert_eq!(histogram.exemplars[5].as_ref().unwrap().trace_id, "second");
+        assert_eq!(histogram.counts[5], 3);
+    }
+}
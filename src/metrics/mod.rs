@@ -0,0 +1,6 @@
+pub mod cache_usage;
+pub mod histogram;
+pub mod prometheus;
+pub mod store;
+pub mod talkers;
+pub mod trace;
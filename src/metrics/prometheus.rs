@@ -0,0 +1,512 @@
+// Per-RPC stats in the Prometheus text format, served on their own port.
+//
+// Counters come from the connection stats every RPC keeps, latency quantiles from the
+// samples its moving average is computed over, and health from which list it's in.
+use crate::{
+    balancer::panic_guard::panics,
+    health::tombstone::{
+        invalidated,
+        tombstones,
+    },
+    metrics::histogram::escape_label,
+    ratelimit::types::RateLimiter,
+    rpc::timing::Phase,
+    Rpc,
+};
+
+use std::{
+    convert::Infallible,
+    fmt::Write,
+    net::SocketAddr,
+    sync::{
+        atomic::{
+            AtomicU64,
+            Ordering,
+        },
+        Arc,
+        RwLock,
+    },
+};
+
+use http_body_util::Full;
+use hyper::{
+    body::Bytes,
+    server::conn::http1,
+    service::service_fn,
+    Method,
+    Request,
+    StatusCode,
+};
+use hyper_util_blutgang::rt::TokioIo;
+use tokio::{
+    net::TcpListener,
+    sync::watch,
+};
+
+pub const PATH: &str = "/metrics";
+
+pub const CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+// Quantiles of the latency samples we export
+const QUANTILES: [f64; 3] = [0.5, 0.9, 0.99];
+
+// Requests answered from the cache, and the ones we had to send upstream.
+// Also what we evicted to keep the cache bounded, and how big it was last we checked.
+#[derive(Debug, Default)]
+pub struct CacheStats {
+    hits: AtomicU64,
+    misses: AtomicU64,
+    evictions: AtomicU64,
+    evicted_bytes: AtomicU64,
+    size: AtomicU64,
+}
+
+impl CacheStats {
+    pub fn record(&self, hit: bool) {
+        match hit {
+            true => self.hits.fetch_add(1, Ordering::Relaxed),
+            false => self.misses.fetch_add(1, Ordering::Relaxed),
+        };
+    }
+
+    pub fn hits(&self) -> u64 {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    pub fn misses(&self) -> u64 {
+        self.misses.load(Ordering::Relaxed)
+    }
+
+    pub fn record_eviction(&self, bytes: usize) {
+        self.evictions.fetch_add(1, Ordering::Relaxed);
+        self.evicted_bytes
+            .fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub fn evictions(&self) -> u64 {
+        self.evictions.load(Ordering::Relaxed)
+    }
+
+    pub fn evicted_bytes(&self) -> u64 {
+        self.evicted_bytes.load(Ordering::Relaxed)
+    }
+
+    pub fn set_size(&self, size: u64) {
+        self.size.store(size, Ordering::Relaxed);
+    }
+
+    // Bytes of cached responses, as of the last time we counted
+    pub fn size(&self) -> u64 {
+        self.size.load(Ordering::Relaxed)
+    }
+}
+
+// Nearest rank quantile of already sorted samples
+fn quantile(sorted: &[f64], q: f64) -> f64 {
+    let rank = (q * sorted.len() as f64).ceil() as usize;
+    sorted[rank.saturating_sub(1).min(sorted.len() - 1)]
+}
+
+// Name, help and value of a per-RPC metric
+type RpcMetric = (&'static str, &'static str, fn(&Rpc) -> f64);
+
+fn write_header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+// Stats of every RPC, healthy or not, of the cache, and of the rate limiter if there is one
+pub fn render(
+    rpc_list: &[Rpc],
+    poverty_list: &[Rpc],
+    cache: &CacheStats,
+    rate_limiter: Option<&RateLimiter>,
+    finalized: u64,
+) -> String {
+    let rpcs: Vec<(&Rpc, bool)> = rpc_list
+        .iter()
+        .map(|rpc| (rpc, true))
+        .chain(poverty_list.iter().map(|rpc| (rpc, false)))
+        .collect();
+    let label = |rpc: &Rpc| escape_label(&rpc.name);
+
+    let mut out = String::new();
+    let counters: [RpcMetric; 7] = [
+        (
+            "blutgang_upstream_requests_total",
+            "Requests sent to the RPC, health checks included.",
+            |rpc| rpc.pool_stats().requests() as f64,
+        ),
+        (
+            "blutgang_upstream_errors_total",
+            "Requests to the RPC that failed or timed out.",
+            |rpc| rpc.pool_stats().errors() as f64,
+        ),
+        (
+            "blutgang_upstream_failed_head_checks_total",
+            "Health checks the RPC didn't report a head for.",
+            |rpc| rpc.pool_stats().failed_head_checks() as f64,
+        ),
+        (
+            "blutgang_upstream_in_flight",
+            "Requests to the RPC waiting for a response.",
+            |rpc| rpc.pool_stats().in_flight() as f64,
+        ),
+        (
+            "blutgang_upstream_warm_connections",
+            "Connections to the RPC the last prewarm found open.",
+            |rpc| rpc.pool_stats().warm() as f64,
+        ),
+        (
+            "blutgang_upstream_head",
+            "Head the RPC reported in the last health check.",
+            |rpc| rpc.pool_stats().head() as f64,
+        ),
+        (
+            "blutgang_upstream_latency_window",
+            "Samples the latency moving average of the RPC is over.",
+            |rpc| rpc.status.ma_length() as f64,
+        ),
+    ];
+    for (name, help, value) in counters {
+        let kind = match name.ends_with("_total") {
+            true => "counter",
+            false => "gauge",
+        };
+        write_header(&mut out, name, kind, help);
+        for (rpc, _) in &rpcs {
+            let _ = writeln!(
+                out,
+                "{}{{upstream=\"{}\"}} {}",
+                name,
+                label(rpc),
+                value(rpc)
+            );
+        }
+    }
+
+    let name = "blutgang_upstream_healthy";
+    write_header(
+        &mut out,
+        name,
+        "gauge",
+        "1 if the RPC is in use, 0 if it's in the poverty list.",
+    );
+    for (rpc, healthy) in &rpcs {
+        let _ = writeln!(
+            out,
+            "{}{{upstream=\"{}\"}} {}",
+            name,
+            label(rpc),
+            *healthy as u8
+        );
+    }
+
+    let name = "blutgang_upstream_latency_seconds";
+    write_header(
+        &mut out,
+        name,
+        "summary",
+        "Latency of the RPC over the samples of its moving average.",
+    );
+    for (rpc, _) in &rpcs {
+        // Latencies are kept in ns
+        let mut samples: Vec<f64> = rpc
+            .status
+            .latency_data
+            .iter()
+            .map(|latency| latency / 1e9)
+            .collect();
+        if samples.is_empty() {
+            continue;
+        }
+        samples.sort_unstable_by(f64::total_cmp);
+
+        for q in QUANTILES {
+            let _ = writeln!(
+                out,
+                "{}{{upstream=\"{}\",quantile=\"{}\"}} {}",
+                name,
+                label(rpc),
+                q,
+                quantile(&samples, q)
+            );
+        }
+        let _ = writeln!(
+            out,
+            "{}_sum{{upstream=\"{}\"}} {}",
+            name,
+            label(rpc),
+            samples.iter().sum::<f64>()
+        );
+        let _ = writeln!(
+            out,
+            "{}_count{{upstream=\"{}\"}} {}",
+            name,
+            label(rpc),
+            samples.len()
+        );
+    }
+
+    let name = "blutgang_upstream_phase_seconds";
+    write_header(
+        &mut out,
+        name,
+        "summary",
+        "Time spent in each phase of requests to the RPC.",
+    );
+    for (rpc, _) in &rpcs {
+        for phase in Phase::ALL {
+            let stats = rpc.timings().get(phase);
+            if stats.count == 0 {
+                continue;
+            }
+            let _ = writeln!(
+                out,
+                "{}_sum{{upstream=\"{}\",phase=\"{}\"}} {}",
+                name,
+                label(rpc),
+                phase,
+                stats.sum
+            );
+            let _ = writeln!(
+                out,
+                "{}_count{{upstream=\"{}\",phase=\"{}\"}} {}",
+                name,
+                label(rpc),
+                phase,
+                stats.count
+            );
+        }
+    }
+
+    let (hits, misses) = (cache.hits(), cache.misses());
+    write_header(
+        &mut out,
+        "blutgang_cache_hits_total",
+        "counter",
+        "Requests answered from the cache.",
+    );
+    let _ = writeln!(out, "blutgang_cache_hits_total {}", hits);
+    write_header(
+        &mut out,
+        "blutgang_cache_misses_total",
+        "counter",
+        "Requests we had to send to an RPC.",
+    );
+    let _ = writeln!(out, "blutgang_cache_misses_total {}", misses);
+    write_header(
+        &mut out,
+        "blutgang_cache_hit_ratio",
+        "gauge",
+        "Share of requests answered from the cache since we started.",
+    );
+    let ratio = match hits + misses {
+        0 => 0.0,
+        total => hits as f64 / total as f64,
+    };
+    let _ = writeln!(out, "blutgang_cache_hit_ratio {}", ratio);
+    write_header(
+        &mut out,
+        "blutgang_cache_evictions_total",
+        "counter",
+        "Entries evicted to keep the cache under its entry or size limit.",
+    );
+    let _ = writeln!(out, "blutgang_cache_evictions_total {}", cache.evictions());
+    write_header(
+        &mut out,
+        "blutgang_cache_size_bytes",
+        "gauge",
+        "Size of the cached responses, as of the last time we counted.",
+    );
+    let _ = writeln!(out, "blutgang_cache_size_bytes {}", cache.size());
+
+    write_header(
+        &mut out,
+        "blutgang_request_panics_total",
+        "counter",
+        "Requests that panicked and got an internal error instead.",
+    );
+    let _ = writeln!(out, "blutgang_request_panics_total {}", panics());
+
+    write_header(
+        &mut out,
+        "blutgang_reorg_invalidated_total",
+        "counter",
+        "Cached responses reorgs made us drop, by how many blocks the reorg replaced.",
+    );
+    for (depth, count) in invalidated() {
+        let _ = writeln!(
+            out,
+            "blutgang_reorg_invalidated_total{{depth=\"{}\"}} {}",
+            depth, count
+        );
+    }
+    write_header(
+        &mut out,
+        "blutgang_reorg_tombstones",
+        "gauge",
+        "Reorged responses kept around as tombstones.",
+    );
+    let _ = writeln!(out, "blutgang_reorg_tombstones {}", tombstones());
+
+    write_header(
+        &mut out,
+        "blutgang_finalized_block",
+        "gauge",
+        "Latest finalized block, responses for blocks up to it are cached for good.",
+    );
+    let _ = writeln!(out, "blutgang_finalized_block {}", finalized);
+
+    if let Some(rate_limiter) = rate_limiter {
+        let (rate_limited, quota_exceeded) = rate_limiter.rejections();
+        write_header(
+            &mut out,
+            "blutgang_rate_limited_total",
+            "counter",
+            "Requests turned away by the rate limiter, by why.",
+        );
+        let _ = writeln!(
+            out,
+            "blutgang_rate_limited_total{{reason=\"rate\"}} {}",
+            rate_limited
+        );
+        let _ = writeln!(
+            out,
+            "blutgang_rate_limited_total{{reason=\"quota\"}} {}",
+            quota_exceeded
+        );
+        write_header(
+            &mut out,
+            "blutgang_rate_limited_clients",
+            "gauge",
+            "Clients the rate limiter is tracking.",
+        );
+        let _ = writeln!(
+            out,
+            "blutgang_rate_limited_clients {}",
+            rate_limiter.clients()
+        );
+    }
+
+    out
+}
+
+// Serve `render` at `PATH` on its own port, so it can be scraped without exposing it
+// wherever the RPC endpoint is exposed
+pub async fn listen_for_metrics(
+    address: SocketAddr,
+    rpc_list: Arc<RwLock<Vec<Rpc>>>,
+    poverty_list: Arc<RwLock<Vec<Rpc>>>,
+    cache: Arc<CacheStats>,
+    rate_limiter: Option<Arc<RateLimiter>>,
+    finalized_rx: Arc<watch::Receiver<u64>>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let listener = TcpListener::bind(address).await?;
+    println!("\x1b[35mInfo:\x1b[0m Bound metrics to: {}", address);
+
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let io = TokioIo::new(stream);
+
+        let rpc_list = Arc::clone(&rpc_list);
+        let poverty_list = Arc::clone(&poverty_list);
+        let cache = Arc::clone(&cache);
+        let rate_limiter = rate_limiter.clone();
+        let finalized_rx = Arc::clone(&finalized_rx);
+        tokio::task::spawn(async move {
+            let service = service_fn(|tx: Request<hyper::body::Incoming>| {
+                let response = if tx.method() == Method::GET && tx.uri().path() == PATH {
+                    let body = render(
+                        &rpc_list.read().unwrap(),
+                        &poverty_list.read().unwrap(),
+                        &cache,
+                        rate_limiter.as_deref(),
+                        *finalized_rx.borrow(),
+                    );
+                    hyper::Response::builder()
+                        .header("Content-Type", CONTENT_TYPE)
+                        .body(Full::new(Bytes::from(body)))
+                } else {
+                    hyper::Response::builder()
+                        .status(StatusCode::NOT_FOUND)
+                        .body(Full::new(Bytes::new()))
+                };
+                async move { Ok::<_, Infallible>(response.unwrap()) }
+            });
+
+            if let Err(err) = http1::Builder::new().serve_connection(io, service).await {
+                println!("error serving metrics connection: {:?}", err);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quantile() {
+        let samples = [1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0, 9.0, 10.0];
+        assert_eq!(quantile(&samples, 0.5), 5.0);
+        assert_eq!(quantile(&samples, 0.9), 9.0);
+        assert_eq!(quantile(&samples, 0.99), 10.0);
+        assert_eq!(quantile(&[3.0], 0.5), 3.0);
+    }
+
+    #[test]
+    fn test_render() {
+        let mut healthy = Rpc::default();
+        healthy.name = "llama".to_string();
+        healthy.status.latency_data = vec![10e6, 20e6, 30e6].into();
+        healthy
+            .timings()
+            .record(Phase::Connect, std::time::Duration::from_millis(5));
+        let mut behind = Rpc::default();
+        behind.name = "my-reth".to_string();
+
+        healthy.pool_stats().start_request().succeed();
+        drop(behind.pool_stats().start_request());
+        behind.pool_stats().record_head(0);
+
+        let cache = CacheStats::default();
+        cache.record(true);
+        cache.record(true);
+        cache.record(true);
+        cache.record(false);
+
+        let rate_limiter = RateLimiter::new(0.0, 1.0, 0);
+        assert!(rate_limiter.check("client", 1).is_ok());
+        assert!(rate_limiter.check("client", 1).is_err());
+
+        let out = render(&[healthy], &[behind], &cache, Some(&rate_limiter), 1234);
+        assert!(out.contains("# TYPE blutgang_upstream_requests_total counter\n"));
+        assert!(out.contains("blutgang_upstream_requests_total{upstream=\"llama\"} 1\n"));
+        assert!(out.contains("blutgang_upstream_errors_total{upstream=\"llama\"} 0\n"));
+        assert!(out.contains("blutgang_upstream_errors_total{upstream=\"my-reth\"} 1\n"));
+        assert!(
+            out.contains("blutgang_upstream_failed_head_checks_total{upstream=\"my-reth\"} 1\n")
+        );
+        assert!(out.contains("blutgang_upstream_healthy{upstream=\"llama\"} 1\n"));
+        assert!(out.contains("blutgang_upstream_healthy{upstream=\"my-reth\"} 0\n"));
+        assert!(out.contains(
+            "blutgang_upstream_latency_seconds{upstream=\"llama\",quantile=\"0.5\"} 0.02\n"
+        ));
+        assert!(out.contains("blutgang_upstream_latency_seconds_count{upstream=\"llama\"} 3\n"));
+        // No samples, no latency
+        assert!(!out.contains("blutgang_upstream_latency_seconds_count{upstream=\"my-reth\"}"));
+        assert!(out.contains(
+            "blutgang_upstream_phase_seconds_count{upstream=\"llama\",phase=\"connect\"} 1\n"
+        ));
+        assert!(!out.contains("phase=\"tls\""));
+        assert!(out.contains("blutgang_cache_hit_ratio 0.75\n"));
+        assert!(out.contains("blutgang_cache_evictions_total 0\n"));
+        assert!(out.contains("# TYPE blutgang_request_panics_total counter\n"));
+        assert!(out.contains("# TYPE blutgang_reorg_invalidated_total counter\n"));
+        assert!(out.contains("blutgang_rate_limited_total{reason=\"rate\"} 1\n"));
+        assert!(out.contains("blutgang_rate_limited_total{reason=\"quota\"} 0\n"));
+        assert!(out.contains("blutgang_rate_limited_clients 1\n"));
+        assert!(out.contains("blutgang_finalized_block 1234\n"));
+    }
+}
@@ -0,0 +1,368 @@
+// Request summaries and health events kept on disk, for deployments without a metrics
+// stack to scrape us.
+//
+// Events go to their own sled tree next to the cache, keyed by when they happened so
+// the admin namespace can aggregate a time range without reading everything. They're
+// buffered in memory and written in batches, so requests don't wait on disk, and
+// anything older than `retention` gets dropped when we write.
+use crate::config::types::EventStoreSettings;
+
+use std::{
+    collections::BTreeMap,
+    sync::{
+        atomic::{
+            AtomicU32,
+            Ordering,
+        },
+        Arc,
+        Mutex,
+    },
+    time::{
+        Duration,
+        SystemTime,
+        UNIX_EPOCH,
+    },
+};
+
+use chrono::{
+    TimeZone,
+    Utc,
+};
+use serde::{
+    Deserialize,
+    Serialize,
+};
+use serde_json::{
+    json,
+    Value,
+};
+use sled::{
+    Batch,
+    Tree,
+};
+use tokio::time::sleep;
+
+// Name of the sled tree we keep events in
+pub const EVENT_TREE: &str = "blutgang_events";
+
+// How often buffered events get written to disk
+const FLUSH_INTERVAL: Duration = Duration::from_secs(5);
+
+// Events we buffer before dropping new ones, in case the disk can't keep up
+const MAX_PENDING: usize = 100_000;
+
+const HOUR_MS: u64 = 3_600_000;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Event {
+    Request {
+        method: String,
+        upstream: String,
+        duration_ms: u64,
+        error: bool,
+    },
+    // An RPC leaving or rejoining the active pool
+    Health {
+        rpc: String,
+        healthy: bool,
+    },
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+fn to_rfc3339(ms: u64) -> String {
+    Utc.timestamp_millis_opt(ms as i64)
+        .single()
+        .map(|time| time.to_rfc3339())
+        .unwrap_or_default()
+}
+
+#[derive(Debug)]
+pub struct EventStore {
+    tree: Tree,
+    retention: Duration,
+    pending: Mutex<Vec<(u64, Event)>>,
+    // Tells apart events recorded in the same millisecond
+    seq: AtomicU32,
+}
+
+impl EventStore {
+    pub fn new(tree: Tree, settings: &EventStoreSettings) -> Self {
+        Self {
+            tree,
+            retention: settings.retention,
+            pending: Mutex::new(Vec::new()),
+            seq: AtomicU32::new(0),
+        }
+    }
+
+    fn push(&self, at: u64, event: Event) {
+        let mut pending = self.pending.lock().unwrap();
+        if pending.len() < MAX_PENDING {
+            pending.push((at, event));
+        }
+    }
+
+    pub fn record_request(&self, method: &str, upstream: &str, duration: Duration, error: bool) {
+        let event = Event::Request {
+            method: method.to_string(),
+            upstream: upstream.to_string(),
+            duration_ms: duration.as_millis() as u64,
+            error,
+        };
+        self.push(now_ms(), event);
+    }
+
+    pub fn record_health(&self, rpc: &str, healthy: bool) {
+        let event = Event::Health {
+            rpc: rpc.to_string(),
+            healthy,
+        };
+        self.push(now_ms(), event);
+    }
+
+    fn key(&self, at: u64) -> [u8; 12] {
+        let mut key = [0; 12];
+        key[..8].copy_from_slice(&at.to_be_bytes());
+        key[8..].copy_from_slice(&self.seq.fetch_add(1, Ordering::Relaxed).to_be_bytes());
+        key
+    }
+
+    // Write buffered events to disk and drop the ones older than our retention
+    fn flush_at(&self, now: u64) -> Result<(), sled::Error> {
+        let pending = std::mem::take(&mut *self.pending.lock().unwrap());
+        let mut batch = Batch::default();
+        for (at, event) in pending {
+            batch.insert(&self.key(at), serde_json::to_vec(&event).unwrap());
+        }
+
+        let cutoff = now.saturating_sub(self.retention.as_millis() as u64);
+        for key in self.tree.range(..cutoff.to_be_bytes()).keys() {
+            batch.remove(key?);
+        }
+
+        self.tree.apply_batch(batch)
+    }
+
+    pub fn flush(&self) -> Result<(), sled::Error> {
+        self.flush_at(now_ms())
+    }
+
+    // Every event since `since`, oldest first. Entries we can't read are skipped.
+    fn events_since(&self, since: u64) -> Result<Vec<(u64, Event)>, sled::Error> {
+        let mut events = Vec::new();
+        for entry in self.tree.range(since.to_be_bytes()..) {
+            let (key, value) = entry?;
+            let at = u64::from_be_bytes(key[..8].try_into().unwrap_or_default());
+            if let Ok(event) = serde_json::from_slice(&value) {
+                events.push((at, event));
+            }
+        }
+        Ok(events)
+    }
+
+    fn requests_per_hour_at(&self, now: u64, hours: u64) -> Result<Value, sled::Error> {
+        self.flush_at(now)?;
+        let since = (now / HOUR_MS).saturating_sub(hours.saturating_sub(1)) * HOUR_MS;
+
+        // Hour -> (requests, errors)
+        let mut by_hour: BTreeMap<u64, (u64, u64)> = BTreeMap::new();
+        for (at, event) in self.events_since(since)? {
+            if let Event::Request { error, .. } = event {
+                let counts = by_hour.entry(at / HOUR_MS * HOUR_MS).or_default();
+                counts.0 += 1;
+                counts.1 += error as u64;
+            }
+        }
+
+        let hours: Vec<Value> = by_hour
+            .into_iter()
+            .map(|(hour, (requests, errors))| {
+                json!({
+                    "hour": to_rfc3339(hour),
+                    "requests": requests,
+                    "errors": errors,
+                })
+            })
+            .collect();
+        Ok(hours.into())
+    }
+
+    // Requests and errors for each hour of the last `hours`, oldest first
+    pub fn requests_per_hour(&self, hours: u64) -> Result<Value, sled::Error> {
+        self.requests_per_hour_at(now_ms(), hours)
+    }
+
+    fn error_rates_at(&self, now: u64, hours: u64) -> Result<Value, sled::Error> {
+        self.flush_at(now)?;
+        let since = now.saturating_sub(hours.saturating_mul(HOUR_MS));
+
+        // Upstream -> (requests, errors, total duration)
+        let mut by_upstream: BTreeMap<String, (u64, u64, u64)> = BTreeMap::new();
+        for (_, event) in self.events_since(since)? {
+            if let Event::Request {
+                upstream,
+                duration_ms,
+                error,
+                ..
+            } = event
+            {
+                let counts = by_upstream.entry(upstream).or_default();
+                counts.0 += 1;
+                counts.1 += error as u64;
+                counts.2 += duration_ms;
+            }
+        }
+
+        let upstreams: Vec<Value> = by_upstream
+            .into_iter()
+            .map(|(upstream, (requests, errors, duration_ms))| {
+                json!({
+                    "upstream": upstream,
+                    "requests": requests,
+                    "errors": errors,
+                    "errorRate": errors as f64 / requests as f64,
+                    "meanLatencyMs": duration_ms as f64 / requests as f64,
+                })
+            })
+            .collect();
+        Ok(upstreams.into())
+    }
+
+    // Requests, errors and the error rate of each upstream over the last `hours`
+    pub fn error_rates(&self, hours: u64) -> Result<Value, sled::Error> {
+        self.error_rates_at(now_ms(), hours)
+    }
+
+    fn health_events_at(&self, now: u64, hours: u64) -> Result<Value, sled::Error> {
+        self.flush_at(now)?;
+        let since = now.saturating_sub(hours.saturating_mul(HOUR_MS));
+
+        let events: Vec<Value> = self
+            .events_since(since)?
+            .into_iter()
+            .rev()
+            .filter_map(|(at, event)| {
+                match event {
+                    Event::Health { rpc, healthy } => {
+                        Some(json!({
+                            "time": to_rfc3339(at),
+                            "rpc": rpc,
+                            "healthy": healthy,
+                        }))
+                    }
+                    Event::Request { .. } => None,
+                }
+            })
+            .collect();
+        Ok(events.into())
+    }
+
+    // RPCs leaving and rejoining the active pool over the last `hours`, newest first
+    pub fn health_events(&self, hours: u64) -> Result<Value, sled::Error> {
+        self.health_events_at(now_ms(), hours)
+    }
+}
+
+// Periodically write buffered events to disk
+pub async fn flush_events(store: Arc<EventStore>) -> Result<(), sled::Error> {
+    loop {
+        sleep(FLUSH_INTERVAL).await;
+        store.flush()?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn store() -> EventStore {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let settings = EventStoreSettings {
+            enabled: true,
+            retention: Duration::from_millis(48 * HOUR_MS),
+        };
+        EventStore::new(db.open_tree(EVENT_TREE).unwrap(), &settings)
+    }
+
+    fn request(upstream: &str, error: bool) -> Event {
+        Event::Request {
+            method: "eth_call".to_string(),
+            upstream: upstream.to_string(),
+            duration_ms: 10,
+            error,
+        }
+    }
+
+    #[test]
+    fn test_requests_per_hour() {
+        let store = store();
+        let now = 100 * HOUR_MS + 5;
+        store.push(now - HOUR_MS, request("llama", false));
+        store.push(now - HOUR_MS, request("llama", true));
+        store.push(now, request("infura", false));
+        // Outside the window
+        store.push(now - 3 * HOUR_MS, request("llama", false));
+
+        let hours = store.requests_per_hour_at(now, 2).unwrap();
+        assert_eq!(hours.as_array().unwrap().len(), 2);
+        assert_eq!(hours[0]["requests"], 2);
+        assert_eq!(hours[0]["errors"], 1);
+        assert_eq!(hours[0]["hour"], to_rfc3339(99 * HOUR_MS));
+        assert_eq!(hours[1]["requests"], 1);
+        assert_eq!(hours[1]["errors"], 0);
+    }
+
+    #[test]
+    fn test_error_rates_and_health() {
+        let store = store();
+        let now = 100 * HOUR_MS;
+        for error in [false, false, false, true] {
+            store.push(now - 1, request("llama", error));
+        }
+        store.push(now - 1, request("infura", false));
+        store.push(
+            now - 2,
+            Event::Health {
+                rpc: "llama".to_string(),
+                healthy: false,
+            },
+        );
+        store.push(
+            now - 1,
+            Event::Health {
+                rpc: "llama".to_string(),
+                healthy: true,
+            },
+        );
+
+        let rates = store.error_rates_at(now, 1).unwrap();
+        assert_eq!(rates[0]["upstream"], "infura");
+        assert_eq!(rates[0]["errorRate"], 0.0);
+        assert_eq!(rates[1]["upstream"], "llama");
+        assert_eq!(rates[1]["requests"], 4);
+        assert_eq!(rates[1]["errorRate"], 0.25);
+
+        let health = store.health_events_at(now, 1).unwrap();
+        assert_eq!(health[0]["healthy"], true);
+        assert_eq!(health[1]["healthy"], false);
+    }
+
+    #[test]
+    fn test_retention() {
+        let store = store();
+        store.push(HOUR_MS, request("llama", false));
+        store.push(50 * HOUR_MS, request("llama", false));
+        store.flush_at(50 * HOUR_MS).unwrap();
+        assert_eq!(store.tree.len(), 2);
+
+        store.flush_at(60 * HOUR_MS).unwrap();
+        assert_eq!(store.tree.len(), 1);
+    }
+}
@@ -0,0 +1,316 @@
+// Per-client request stats, to find out who's generating the load.
+//
+// Clients are identified by their API key, or IP if they don't send one. Totals are
+// kept since a client first showed up, and its request rate over the last window so
+// the client melting the endpoint right now stands out from one that's been busy all week.
+use crate::metrics::histogram::escape_label;
+
+use std::{
+    collections::HashMap,
+    fmt::Write,
+    sync::Mutex,
+    time::{
+        Duration,
+        Instant,
+    },
+};
+
+use serde_json::{
+    json,
+    Value,
+};
+
+// Rates are computed over windows this long
+const WINDOW: Duration = Duration::from_secs(60);
+
+// Methods we list per client, the busiest ones
+const TOP_METHODS: usize = 5;
+
+// Label of requests without a method, i.e. batches and GETs
+const NO_METHOD: &str = "none";
+
+#[derive(Debug, Clone, Default)]
+struct ClientStats {
+    requests: u64,
+    errors: u64,
+    bytes_in: u64,
+    bytes_out: u64,
+    methods: HashMap<String, u64>,
+    // Requests in the current window
+    recent: u64,
+    // Requests per second in the last full window
+    rate: f64,
+}
+
+impl ClientStats {
+    // Count of `by`, how we rank clients
+    fn score(&self, by: &str) -> f64 {
+        match by {
+            "requests" => self.requests as f64,
+            "errors" => self.errors as f64,
+            "bytes" => (self.bytes_in + self.bytes_out) as f64,
+            _ => self.rate,
+        }
+    }
+
+    fn to_json(&self, client: &str) -> Value {
+        let mut methods: Vec<(&String, &u64)> = self.methods.iter().collect();
+        methods.sort_unstable_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+        let methods: serde_json::Map<String, Value> = methods
+            .into_iter()
+            .take(TOP_METHODS)
+            .map(|(method, count)| (method.clone(), (*count).into()))
+            .collect();
+
+        json!({
+            "client": client,
+            "requests": self.requests,
+            "errors": self.errors,
+            "error_rate": match self.requests {
+                0 => 0.0,
+                requests => self.errors as f64 / requests as f64,
+            },
+            "bytes_in": self.bytes_in,
+            "bytes_out": self.bytes_out,
+            "requests_per_second": self.rate,
+            "methods": methods,
+        })
+    }
+}
+
+#[derive(Debug)]
+struct Clients {
+    stats: HashMap<String, ClientStats>,
+    window_start: Instant,
+}
+
+#[derive(Debug)]
+pub struct TopTalkers {
+    // Clients we keep stats for, the least busy one is forgotten to make room
+    max_clients: usize,
+    // Clients we export metric series for
+    export: usize,
+    clients: Mutex<Clients>,
+}
+
+impl TopTalkers {
+    pub fn new(max_clients: usize, export: usize) -> Self {
+        Self {
+            max_clients,
+            export,
+            clients: Mutex::new(Clients {
+                stats: HashMap::new(),
+                window_start: Instant::now(),
+            }),
+        }
+    }
+
+    fn rotate(clients: &mut Clients, now: Instant) {
+        let elapsed = now.duration_since(clients.window_start);
+        if elapsed < WINDOW {
+            return;
+        }
+
+        for stats in clients.stats.values_mut() {
+            stats.rate = stats.recent as f64 / elapsed.as_secs_f64();
+            stats.recent = 0;
+        }
+        clients.window_start = now;
+    }
+
+    fn client<'a>(&self, clients: &'a mut Clients, client: &str) -> &'a mut ClientStats {
+        if !clients.stats.contains_key(client) {
+            if clients.stats.len() >= self.max_clients {
+                let quietest = clients
+                    .stats
+                    .iter()
+                    .min_by(|a, b| {
+                        a.1.rate
+                            .total_cmp(&b.1.rate)
+                            .then(a.1.requests.cmp(&b.1.requests))
+                    })
+                    .map(|(client, _)| client.clone());
+                if let Some(quietest) = quietest {
+                    clients.stats.remove(&quietest);
+                }
+            }
+            clients
+                .stats
+                .insert(client.to_string(), ClientStats::default());
+        }
+
+        clients.stats.get_mut(client).unwrap()
+    }
+
+    // Count a request to `method` from `client`
+    pub fn record_method(&self, client: &str, method: Option<&str>) {
+        let mut clients = self.clients.lock().unwrap();
+        let stats = self.client(&mut clients, client);
+        *stats
+            .methods
+            .entry(method.unwrap_or(NO_METHOD).to_string())
+            .or_insert(0) += 1;
+    }
+
+    // Count a request `client` sent and how we answered it
+    pub fn record(&self, client: &str, bytes_in: u64, bytes_out: u64, error: bool) {
+        let mut clients = self.clients.lock().unwrap();
+        Self::rotate(&mut clients, Instant::now());
+
+        let stats = self.client(&mut clients, client);
+        stats.requests += 1;
+        stats.recent += 1;
+        stats.bytes_in += bytes_in;
+        stats.bytes_out += bytes_out;
+        if error {
+            stats.errors += 1;
+        }
+    }
+
+    // The `limit` busiest clients by `by`, one of rate/requests/errors/bytes
+    pub fn top(&self, limit: usize, by: &str) -> Value {
+        let mut clients = self.clients.lock().unwrap();
+        Self::rotate(&mut clients, Instant::now());
+
+        let mut ranked: Vec<(&String, &ClientStats)> = clients.stats.iter().collect();
+        ranked.sort_unstable_by(|a, b| b.1.score(by).total_cmp(&a.1.score(by)).then(a.0.cmp(b.0)));
+
+        ranked
+            .into_iter()
+            .take(limit)
+            .map(|(client, stats)| stats.to_json(client))
+            .collect()
+    }
+
+    // Counters of the busiest clients by request count, in the OpenMetrics text format.
+    //
+    // Only the top few are exported so scrapes don't grow with the number of clients.
+    pub fn write_openmetrics(&self, out: &mut String) {
+        let top = self.top(self.export, "requests");
+        let top = top.as_array().unwrap();
+
+        let series = [
+            (
+                "blutgang_client_requests",
+                "Requests of the busiest clients.",
+                "requests",
+            ),
+            (
+                "blutgang_client_errors",
+                "Requests of the busiest clients we answered with an error status.",
+                "errors",
+            ),
+            (
+                "blutgang_client_received_bytes",
+                "Request bytes received from the busiest clients.",
+                "bytes_in",
+            ),
+            (
+                "blutgang_client_sent_bytes",
+                "Response bytes sent to the busiest clients.",
+                "bytes_out",
+            ),
+        ];
+        for (name, help, field) in series {
+            let _ = writeln!(out, "# TYPE {} counter", name);
+            let _ = writeln!(out, "# HELP {} {}", name, help);
+            for client in top {
+                let _ = writeln!(
+                    out,
+                    "{}_total{{client=\"{}\"}} {}",
+                    name,
+                    escape_label(client["client"].as_str().unwrap_or_default()),
+                    client[field]
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_top() {
+        let talkers = TopTalkers::new(100, 10);
+        for _ in 0..3 {
+            talkers.record_method("indexer", Some("eth_getLogs"));
+            talkers.record("indexer", 100, 1000, false);
+        }
+        talkers.record_method("indexer", Some("eth_blockNumber"));
+        talkers.record("indexer", 100, 10, true);
+        talkers.record_method("127.0.0.1", None);
+        talkers.record("127.0.0.1", 5000, 50, false);
+
+        let top = talkers.top(10, "requests");
+        assert_eq!(top[0]["client"], "indexer");
+        assert_eq!(top[0]["requests"], 4);
+        assert_eq!(top[0]["errors"], 1);
+        assert_eq!(top[0]["error_rate"], 0.25);
+        assert_eq!(top[0]["bytes_out"], 3010);
+        assert_eq!(top[0]["methods"]["eth_getLogs"], 3);
+        assert_eq!(top[1]["methods"][NO_METHOD], 1);
+
+        let top = talkers.top(1, "bytes");
+        assert_eq!(top.as_array().unwrap().len(), 1);
+        assert_eq!(top[0]["client"], "127.0.0.1");
+    }
+
+    #[test]
+    fn test_rate() {
+        let talkers = TopTalkers::new(100, 10);
+        for _ in 0..120 {
+            talkers.record("indexer", 0, 0, false);
+        }
+
+        let now = Instant::now();
+        {
+            let mut clients = talkers.clients.lock().unwrap();
+            clients.window_start = now - WINDOW;
+            TopTalkers::rotate(&mut clients, now);
+            let rate = clients.stats["indexer"].rate;
+            assert!((1.9..=2.0).contains(&rate), "{}", rate);
+        }
+
+        // Clients that went quiet drop to 0 after the next window
+        let mut clients = talkers.clients.lock().unwrap();
+        clients.window_start = now - WINDOW;
+        TopTalkers::rotate(&mut clients, now);
+        assert_eq!(clients.stats["indexer"].rate, 0.0);
+    }
+
+    #[test]
+    fn test_max_clients() {
+        let talkers = TopTalkers::new(2, 10);
+        talkers.record("busy", 0, 0, false);
+        talkers.record("busy", 0, 0, false);
+        talkers.record("quiet", 0, 0, false);
+        talkers.record("new", 0, 0, false);
+
+        let top = talkers.top(10, "requests");
+        let clients: Vec<&str> = top
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|client| client["client"].as_str().unwrap())
+            .collect();
+        assert_eq!(clients, ["busy", "new"]);
+    }
+
+    #[test]
+    fn test_openmetrics() {
+        let talkers = TopTalkers::new(100, 1);
+        talkers.record("indexer", 10, 20, true);
+        talkers.record("indexer", 10, 20, false);
+        talkers.record("wallet", 10, 20, false);
+
+        let mut out = String::new();
+        talkers.write_openmetrics(&mut out);
+        assert!(out.contains("# TYPE blutgang_client_requests counter\n"));
+        assert!(out.contains("blutgang_client_requests_total{client=\"indexer\"} 2\n"));
+        assert!(out.contains("blutgang_client_errors_total{client=\"indexer\"} 1\n"));
+        assert!(out.contains("blutgang_client_sent_bytes_total{client=\"indexer\"} 40\n"));
+        assert!(!out.contains("wallet"));
+    }
+}
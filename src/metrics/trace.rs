@@ -0,0 +1,75 @@
+// W3C trace context, so metrics can point to the traces of the requests they measured.
+//
+// We don't trace requests ourselves, we just pick up the trace id the client
+// (or a proxy in front of us) sent in the `traceparent` header.
+use hyper::HeaderMap;
+
+// Trace id of the request, if it's part of a trace that was sampled.
+//
+// Unsampled traces never make it to the tracing backend, so linking to them is useless.
+pub fn sampled_trace_id(headers: &HeaderMap) -> Option<String> {
+    let traceparent = headers.get("traceparent")?.to_str().ok()?;
+
+    // version-trace_id-parent_id-flags
+    let mut parts = traceparent.trim().split('-');
+    let version = parts.next()?;
+    let trace_id = parts.next()?;
+    let parent_id = parts.next()?;
+    let flags = parts.next()?;
+
+    let is_hex = |part: &str, len: usize| {
+        part.len() == len && part.bytes().all(|byte| byte.is_ascii_hexdigit())
+    };
+    if !is_hex(version, 2)
+        || version == "ff"
+        || !is_hex(trace_id, 32)
+        || !is_hex(parent_id, 16)
+        || !is_hex(flags, 2)
+    {
+        return None;
+    }
+    // All zeroes is an invalid trace id
+    if trace_id.bytes().all(|byte| byte == b'0') {
+        return None;
+    }
+
+    let sampled = u8::from_str_radix(flags, 16).ok()? & 1 == 1;
+    sampled.then(|| trace_id.to_lowercase())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(traceparent: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert("traceparent", traceparent.parse().unwrap());
+        headers
+    }
+
+    #[test]
+    fn test_sampled_trace_id() {
+        assert_eq!(
+            sampled_trace_id(&headers(
+                "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01"
+            )),
+            Some("4bf92f3577b34da6a3ce929d0e0e4736".to_string())
+        );
+
+        // Not sampled
+        assert_eq!(
+            sampled_trace_id(&headers(
+                "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-00"
+            )),
+            None
+        );
+        assert_eq!(
+            sampled_trace_id(&headers(
+                "00-00000000000000000000000000000000-00f067aa0ba902b7-01"
+            )),
+            None
+        );
+        assert_eq!(sampled_trace_id(&headers("00-4bf92f35-00f067aa-01")), None);
+        assert_eq!(sampled_trace_id(&HeaderMap::new()), None);
+    }
+}
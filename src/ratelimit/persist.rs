@@ -0,0 +1,111 @@
+use crate::ratelimit::types::{
+    PersistedBucket,
+    RateLimiter,
+};
+
+use std::{
+    sync::Arc,
+    time::Duration,
+};
+
+use sled::{
+    Batch,
+    Tree,
+};
+use tokio::time::sleep;
+
+// Name of the sled tree we keep rate limit state in.
+//
+// Separate from the cache so clearing it doesn't refill everyones quota.
+pub const RATELIMIT_TREE: &str = "blutgang_ratelimit";
+
+// How often we write the rate limiter state to disk
+const PERSIST_INTERVAL: Duration = Duration::from_secs(5);
+
+// Load persisted rate limit state into `rate_limiter`
+pub fn load_rate_limits(rate_limiter: &RateLimiter, tree: &Tree) -> Result<(), sled::Error> {
+    let mut persisted = Vec::new();
+
+    for entry in tree.iter() {
+        let (key, value) = entry?;
+        let key = String::from_utf8_lossy(&key).to_string();
+
+        // Skip entries we can't parse instead of refusing to start
+        match serde_json::from_slice::<PersistedBucket>(&value) {
+            Ok(state) => persisted.push((key, state)),
+            Err(_) => {
+                println!(
+                    "\x1b[93mWrn:\x1b[0m Dropping unreadable rate limit entry for {}",
+                    key
+                )
+            }
+        }
+    }
+
+    println!(
+        "\x1b[35mInfo:\x1b[0m Loaded rate limit state for {} clients",
+        persisted.len()
+    );
+    rate_limiter.restore(persisted);
+
+    Ok(())
+}
+
+// Replace the persisted state with the current state of `rate_limiter`.
+//
+// Clients that are no longer tracked get removed, so stale state expires on its own.
+pub fn save_rate_limits(rate_limiter: &RateLimiter, tree: &Tree) -> Result<(), sled::Error> {
+    let snapshot = rate_limiter.snapshot();
+    let mut batch = Batch::default();
+
+    for key in tree.iter().keys() {
+        batch.remove(key?);
+    }
+    for (key, state) in snapshot {
+        batch.insert(key.as_bytes(), serde_json::to_vec(&state).unwrap());
+    }
+
+    tree.apply_batch(batch)?;
+    tree.flush()?;
+
+    Ok(())
+}
+
+// Periodically persist the rate limiter state
+pub async fn persist_rate_limits(
+    rate_limiter: Arc<RateLimiter>,
+    tree: Tree,
+) -> Result<(), sled::Error> {
+    loop {
+        sleep(PERSIST_INTERVAL).await;
+        rate_limiter.prune();
+        save_rate_limits(&rate_limiter, &tree)?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_save_load() {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let tree = db.open_tree(RATELIMIT_TREE).unwrap();
+
+        let rate_limiter = RateLimiter::new(0.0, 2.0, 0);
+        assert!(rate_limiter.check("client", 1).is_ok());
+        assert!(rate_limiter.check("client", 1).is_ok());
+        assert!(rate_limiter.check("client", 1).is_err());
+        save_rate_limits(&rate_limiter, &tree).unwrap();
+
+        let restarted = RateLimiter::new(0.0, 2.0, 0);
+        load_rate_limits(&restarted, &tree).unwrap();
+        assert!(restarted.check("client", 1).is_err());
+        assert!(restarted.check("other_client", 1).is_ok());
+
+        // Saving drops clients we no longer track
+        let empty = RateLimiter::new(0.0, 2.0, 0);
+        save_rate_limits(&empty, &tree).unwrap();
+        assert_eq!(tree.len(), 0);
+    }
+}
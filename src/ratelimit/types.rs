@@ -0,0 +1,474 @@
+use serde::{
+    Deserialize,
+    Serialize,
+};
+use serde_json::{
+    json,
+    Value,
+};
+use std::{
+    collections::{
+        HashMap,
+        VecDeque,
+    },
+    sync::{
+        atomic::{
+            AtomicU64,
+            Ordering,
+        },
+        Mutex,
+    },
+    time::{
+        Duration,
+        Instant,
+        SystemTime,
+        UNIX_EPOCH,
+    },
+};
+
+// Clients we track at most, the one we started tracking first is forgotten to make room
+const MAX_CLIENTS: usize = 10_000;
+
+const SECONDS_PER_DAY: u64 = 86_400;
+
+// Current unix time in milliseconds
+pub fn unix_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+// Days since the unix epoch, quotas reset at midnight UTC
+fn unix_day(unix_millis: u64) -> u64 {
+    unix_millis / 1000 / SECONDS_PER_DAY
+}
+
+#[derive(Debug, Clone)]
+pub struct TokenBucket {
+    tokens: f64,
+    last_refill: Instant,
+    // Requests made on `quota_day`
+    quota_used: u64,
+    quota_day: u64,
+    // When we started tracking the client, see `Buckets::order`
+    seq: u64,
+}
+
+impl TokenBucket {
+    fn new(burst: f64) -> Self {
+        Self {
+            tokens: burst,
+            last_refill: Instant::now(),
+            quota_used: 0,
+            quota_day: unix_day(unix_millis()),
+            seq: 0,
+        }
+    }
+
+    // Add the tokens accumulated since the last refill, capped at `burst`
+    fn refill(&mut self, requests_per_second: f64, burst: f64) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * requests_per_second).min(burst);
+        self.last_refill = now;
+
+        let today = unix_day(unix_millis());
+        if self.quota_day != today {
+            self.quota_day = today;
+            self.quota_used = 0;
+        }
+    }
+
+    // A full bucket with no quota used is the same as not tracking the client at all.
+    // Without a `daily_quota`, what they used doesn't matter.
+    fn is_idle(&self, burst: f64, daily_quota: u64) -> bool {
+        self.tokens >= burst && (daily_quota == 0 || self.quota_used == 0)
+    }
+}
+
+// Bucket state that survives restarts.
+//
+// `Instant`s are meaningless across restarts, so we store when it was saved instead.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PersistedBucket {
+    pub tokens: f64,
+    pub saved_at: u64,
+    pub quota_used: u64,
+    pub quota_day: u64,
+}
+
+// Why a client got turned away
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Limited {
+    // Out of tokens, has to slow down
+    Rate,
+    // Used up its requests for the day
+    Quota,
+}
+
+// Buckets of the clients we track, capped at `MAX_CLIENTS`
+#[derive(Debug, Default)]
+struct Buckets {
+    buckets: HashMap<String, TokenBucket>,
+    // Clients in the order we started tracking them. Entries whose `seq` doesn't match the
+    // bucket anymore were pruned and are skipped.
+    order: VecDeque<(u64, String)>,
+    next_seq: u64,
+}
+
+impl Buckets {
+    // Track `bucket` for `key`, forgetting the oldest clients if we're full
+    fn insert(&mut self, key: &str, mut bucket: TokenBucket) -> &mut TokenBucket {
+        while self.buckets.len() >= MAX_CLIENTS {
+            let (seq, oldest) = match self.order.pop_front() {
+                Some(oldest) => oldest,
+                None => break,
+            };
+            if self
+                .buckets
+                .get(&oldest)
+                .is_some_and(|bucket| bucket.seq == seq)
+            {
+                self.buckets.remove(&oldest);
+            }
+        }
+
+        bucket.seq = self.next_seq;
+        self.next_seq += 1;
+        self.order.push_back((bucket.seq, key.to_string()));
+        self.buckets.entry(key.to_string()).or_insert(bucket)
+    }
+
+    fn get_or_insert(&mut self, key: &str, burst: f64) -> &mut TokenBucket {
+        if !self.buckets.contains_key(key) {
+            return self.insert(key, TokenBucket::new(burst));
+        }
+        self.buckets.get_mut(key).unwrap()
+    }
+
+    // Forget clients whose bucket is idle
+    fn prune(&mut self, requests_per_second: f64, burst: f64, daily_quota: u64) {
+        self.buckets.retain(|_, bucket| {
+            bucket.refill(requests_per_second, burst);
+            !bucket.is_idle(burst, daily_quota)
+        });
+        let buckets = &self.buckets;
+        self.order
+            .retain(|(seq, key)| buckets.get(key).is_some_and(|bucket| bucket.seq == *seq));
+    }
+}
+
+// Token bucket rate limiter keyed by client (IP or API key)
+//
+// Optionally also enforces a daily quota per client.
+#[derive(Debug)]
+pub struct RateLimiter {
+    requests_per_second: f64,
+    burst: f64,
+    // Max requests per client per day, 0 means unlimited
+    daily_quota: u64,
+    buckets: Mutex<Buckets>,
+    // Requests turned away since startup
+    rate_limited: AtomicU64,
+    quota_exceeded: AtomicU64,
+}
+
+impl RateLimiter {
+    pub fn new(requests_per_second: f64, burst: f64, daily_quota: u64) -> Self {
+        Self {
+            requests_per_second,
+            burst,
+            daily_quota,
+            buckets: Mutex::new(Buckets::default()),
+            rate_limited: AtomicU64::new(0),
+            quota_exceeded: AtomicU64::new(0),
+        }
+    }
+
+    // Take `weight` tokens from `key`'s bucket, one per request, or say why the client is
+    // limited.
+    //
+    // Batches bigger than the burst would never fit, so they get through if the bucket is
+    // full and leave it in debt until it refills.
+    pub fn check(&self, key: &str, weight: u64) -> Result<(), Limited> {
+        let mut buckets = self.buckets.lock().unwrap_or_else(|e| e.into_inner());
+        let bucket = buckets.get_or_insert(key, self.burst);
+        bucket.refill(self.requests_per_second, self.burst);
+
+        // Quota first, a client that's out for the day shouldn't be told to retry soon
+        if self.daily_quota != 0 && bucket.quota_used + weight > self.daily_quota {
+            self.quota_exceeded.fetch_add(1, Ordering::Relaxed);
+            return Err(Limited::Quota);
+        }
+        if bucket.tokens < (weight as f64).min(self.burst) {
+            self.rate_limited.fetch_add(1, Ordering::Relaxed);
+            return Err(Limited::Rate);
+        }
+
+        bucket.tokens -= weight as f64;
+        bucket.quota_used += weight;
+        Ok(())
+    }
+
+    // Requests turned away for going too fast and for being out of quota
+    pub fn rejections(&self) -> (u64, u64) {
+        (
+            self.rate_limited.load(Ordering::Relaxed),
+            self.quota_exceeded.load(Ordering::Relaxed),
+        )
+    }
+
+    // Clients we currently track, idle ones might not be pruned yet
+    pub fn clients(&self) -> usize {
+        self.buckets
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .buckets
+            .len()
+    }
+
+    // Forget idle clients. Done when we persist, not for every request.
+    pub fn prune(&self) {
+        self.buckets
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .prune(self.requests_per_second, self.burst, self.daily_quota);
+    }
+
+    // Settings, rejections and the `top` clients that used the most of their quota today
+    pub fn report(&self, top: usize) -> Value {
+        let mut buckets = self.buckets.lock().unwrap_or_else(|e| e.into_inner());
+        let mut clients: Vec<(&String, &mut TokenBucket)> = buckets.buckets.iter_mut().collect();
+        for (_, bucket) in clients.iter_mut() {
+            bucket.refill(self.requests_per_second, self.burst);
+        }
+        let tracked = clients.len();
+        clients.sort_by(|a, b| b.1.quota_used.cmp(&a.1.quota_used).then(a.0.cmp(b.0)));
+
+        let top: Vec<Value> = clients
+            .iter()
+            .filter(|(_, bucket)| !bucket.is_idle(self.burst, self.daily_quota))
+            .take(top)
+            .map(|(client, bucket)| {
+                json!({
+                    "client": client,
+                    "tokens": bucket.tokens,
+                    "quotaUsed": bucket.quota_used,
+                    "quotaLeft": (self.daily_quota != 0)
+                        .then(|| self.daily_quota.saturating_sub(bucket.quota_used)),
+                })
+            })
+            .collect();
+        let (rate_limited, quota_exceeded) = self.rejections();
+
+        json!({
+            "requestsPerSecond": self.requests_per_second,
+            "burst": self.burst,
+            "dailyQuota": (self.daily_quota != 0).then_some(self.daily_quota),
+            "clients": tracked,
+            "rateLimited": rate_limited,
+            "quotaExceeded": quota_exceeded,
+            "top": top,
+        })
+    }
+
+    // Export the state of every client that isn't idle
+    pub fn snapshot(&self) -> Vec<(String, PersistedBucket)> {
+        let mut buckets = self.buckets.lock().unwrap_or_else(|e| e.into_inner());
+        let saved_at = unix_millis();
+
+        buckets
+            .buckets
+            .iter_mut()
+            .filter_map(|(key, bucket)| {
+                bucket.refill(self.requests_per_second, self.burst);
+                if bucket.is_idle(self.burst, self.daily_quota) {
+                    return None;
+                }
+
+                Some((
+                    key.clone(),
+                    PersistedBucket {
+                        tokens: bucket.tokens,
+                        saved_at,
+                        quota_used: bucket.quota_used,
+                        quota_day: bucket.quota_day,
+                    },
+                ))
+            })
+            .collect()
+    }
+
+    // Load previously persisted state.
+    //
+    // Buckets refill for the time blutgang was down, and quotas from previous days are dropped.
+    pub fn restore(&self, persisted: Vec<(String, PersistedBucket)>) {
+        let mut buckets = self.buckets.lock().unwrap_or_else(|e| e.into_inner());
+        let now_millis = unix_millis();
+        let now = Instant::now();
+
+        for (key, state) in persisted {
+            let downtime = Duration::from_millis(now_millis.saturating_sub(state.saved_at));
+            let mut bucket = TokenBucket {
+                tokens: state.tokens.min(self.burst),
+                last_refill: now.checked_sub(downtime).unwrap_or(now),
+                quota_used: state.quota_used,
+                quota_day: state.quota_day,
+                seq: 0,
+            };
+            bucket.refill(self.requests_per_second, self.burst);
+
+            if !bucket.is_idle(self.burst, self.daily_quota) {
+                buckets.insert(&key, bucket);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_burst_then_limit() {
+        let limiter = RateLimiter::new(0.0, 3.0, 0);
+
+        assert!(limiter.check("127.0.0.1", 1).is_ok());
+        assert!(limiter.check("127.0.0.1", 1).is_ok());
+        assert!(limiter.check("127.0.0.1", 1).is_ok());
+        assert!(limiter.check("127.0.0.1", 1).is_err());
+
+        // Other clients have their own bucket
+        assert!(limiter.check("127.0.0.2", 1).is_ok());
+    }
+
+    #[test]
+    fn test_refill() {
+        let limiter = RateLimiter::new(1000.0, 1.0, 0);
+
+        assert!(limiter.check("client", 1).is_ok());
+        std::thread::sleep(std::time::Duration::from_millis(5));
+        assert!(limiter.check("client", 1).is_ok());
+    }
+
+    #[test]
+    fn test_weighted() {
+        let limiter = RateLimiter::new(0.0, 3.0, 0);
+
+        assert!(limiter.check("client", 2).is_ok());
+        assert_eq!(limiter.check("client", 2), Err(Limited::Rate));
+        assert!(limiter.check("client", 1).is_ok());
+
+        // Bigger than the burst, only from a full bucket and then it's in debt
+        assert!(limiter.check("other", 5).is_ok());
+        assert_eq!(limiter.check("other", 1), Err(Limited::Rate));
+    }
+
+    #[test]
+    fn test_max_clients() {
+        let limiter = RateLimiter::new(0.0, 1.0, 0);
+        for i in 0..MAX_CLIENTS + 10 {
+            assert!(limiter.check(&i.to_string(), 1).is_ok());
+        }
+        assert_eq!(limiter.clients(), MAX_CLIENTS);
+
+        // The clients we started tracking first are forgotten
+        assert!(limiter.check("0", 1).is_ok());
+        assert!(limiter.check(&(MAX_CLIENTS + 9).to_string(), 1).is_err());
+    }
+
+    #[test]
+    fn test_prune() {
+        let limiter = RateLimiter::new(1000.0, 1.0, 0);
+        assert!(limiter.check("idle", 1).is_ok());
+        std::thread::sleep(std::time::Duration::from_millis(5));
+        limiter.prune();
+        assert_eq!(limiter.clients(), 0);
+
+        // Clients pruned and tracked again aren't forgotten for their old entry
+        assert!(limiter.check("idle", 1).is_ok());
+        let mut buckets = limiter.buckets.lock().unwrap();
+        assert_eq!(buckets.order.len(), 1);
+        buckets.prune(0.0, 1.0, 0);
+        assert_eq!(buckets.order.len(), 1);
+    }
+
+    #[test]
+    fn test_daily_quota() {
+        let limiter = RateLimiter::new(1000.0, 1000.0, 2);
+
+        assert!(limiter.check("client", 1).is_ok());
+        assert!(limiter.check("client", 1).is_ok());
+        assert_eq!(limiter.check("client", 1), Err(Limited::Quota));
+    }
+
+    #[test]
+    fn test_report() {
+        let limiter = RateLimiter::new(0.0, 2.0, 3);
+        assert!(limiter.check("a", 1).is_ok());
+        assert!(limiter.check("a", 1).is_ok());
+        assert_eq!(limiter.check("a", 1), Err(Limited::Rate));
+        assert!(limiter.check("b", 1).is_ok());
+        assert_eq!(limiter.rejections(), (1, 0));
+
+        let report = limiter.report(1);
+        assert_eq!(report["clients"], 2);
+        assert_eq!(report["rateLimited"], 1);
+        assert_eq!(report["dailyQuota"], 3);
+        assert_eq!(
+            report["top"],
+            json!([{"client": "a", "tokens": 0.0, "quotaUsed": 2, "quotaLeft": 1}])
+        );
+    }
+
+    #[test]
+    fn test_snapshot_restore() {
+        let limiter = RateLimiter::new(0.0, 3.0, 5);
+        assert!(limiter.check("client", 1).is_ok());
+        assert!(limiter.check("client", 1).is_ok());
+
+        let snapshot = limiter.snapshot();
+        assert_eq!(snapshot.len(), 1);
+        assert_eq!(snapshot[0].1.quota_used, 2);
+
+        // A restarted limiter picks up where we left off
+        let restarted = RateLimiter::new(0.0, 3.0, 5);
+        restarted.restore(snapshot);
+        assert!(restarted.check("client", 1).is_ok());
+        assert!(restarted.check("client", 1).is_err());
+    }
+
+    #[test]
+    fn test_restore_expired() {
+        let limiter = RateLimiter::new(0.0, 3.0, 5);
+        let today = unix_day(unix_millis());
+
+        limiter.restore(vec![
+            // Quota from yesterday with a full bucket is dropped
+            (
+                "yesterday".to_string(),
+                PersistedBucket {
+                    tokens: 3.0,
+                    saved_at: unix_millis(),
+                    quota_used: 5,
+                    quota_day: today - 1,
+                },
+            ),
+            // Exhausted quota from today is kept
+            (
+                "today".to_string(),
+                PersistedBucket {
+                    tokens: 3.0,
+                    saved_at: unix_millis(),
+                    quota_used: 5,
+                    quota_day: today,
+                },
+            ),
+        ]);
+
+        assert_eq!(limiter.snapshot().len(), 1);
+        assert!(limiter.check("yesterday", 1).is_ok());
+        assert!(limiter.check("today", 1).is_err());
+    }
+}
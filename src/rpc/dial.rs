@@ -0,0 +1,277 @@
+// Which address family we connect to RPCs over.
+//
+// reqwest already does Happy Eyeballs: it connects to the addresses of the family that
+// comes first, and if that takes longer than 300ms it starts racing the other family.
+// We resolve hostnames ourselves so we decide which family goes first.
+//
+// With `auto` we go by how each family did in the last health checks, so hosts whose
+// IPv6 (or IPv4) is degraded don't make every new connection wait for the fallback.
+use std::{
+    net::{
+        IpAddr,
+        Ipv4Addr,
+        Ipv6Addr,
+        SocketAddr,
+    },
+    sync::{
+        Arc,
+        Mutex,
+    },
+    time::Instant,
+};
+
+use crate::rpc::timing::{
+    Phase,
+    TimingStats,
+};
+
+use hyper_0_14::client::connect::dns::Name;
+use reqwest::dns::{
+    Addrs,
+    Resolve,
+    Resolving,
+};
+use serde_json::{
+    json,
+    Value,
+};
+
+// Failed checks in a row after which we consider a family unhealthy
+const FAILURE_THRESHOLD: u32 = 3;
+
+// How much slower a family has to be before we prefer the other one
+const LATENCY_FACTOR: f64 = 1.5;
+
+// Weight of new latency samples
+const LATENCY_ALPHA: f64 = 0.3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Family {
+    V4,
+    V6,
+}
+
+impl Family {
+    pub fn of(addr: &SocketAddr) -> Self {
+        match addr {
+            SocketAddr::V4(_) => Family::V4,
+            SocketAddr::V6(_) => Family::V6,
+        }
+    }
+
+    // Binding to this makes the connector only dial addresses of this family
+    pub fn unspecified(self) -> IpAddr {
+        match self {
+            Family::V4 => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            Family::V6 => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum IpPreference {
+    V4,
+    V6,
+    // Whichever family is healthier, or the order the system resolver gives us
+    #[default]
+    Auto,
+}
+
+impl IpPreference {
+    pub fn from_config(preference: &str) -> Option<Self> {
+        match preference {
+            "v4" => Some(IpPreference::V4),
+            "v6" => Some(IpPreference::V6),
+            "auto" => Some(IpPreference::Auto),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+struct FamilyStatus {
+    successes: u64,
+    failures: u64,
+    consecutive_failures: u32,
+    // Moving average in ms, 0 until the first success
+    latency: f64,
+}
+
+impl FamilyStatus {
+    fn healthy(&self) -> bool {
+        self.consecutive_failures < FAILURE_THRESHOLD
+    }
+
+    fn to_json(&self) -> Value {
+        json!({
+            "healthy": self.healthy(),
+            "successes": self.successes,
+            "failures": self.failures,
+            "latency": self.latency,
+        })
+    }
+}
+
+// Health of each family for a single RPC, shared by all of its clones
+#[derive(Debug, Default)]
+pub struct FamilyHealth {
+    // Indexed by `Family as usize`
+    status: Mutex<[FamilyStatus; 2]>,
+}
+
+impl FamilyHealth {
+    pub fn record_success(&self, family: Family, latency: f64) {
+        let mut status = self.status.lock().unwrap_or_else(|e| e.into_inner());
+        let status = &mut status[family as usize];
+
+        status.successes += 1;
+        status.consecutive_failures = 0;
+        status.latency = match status.latency {
+            0.0 => latency,
+            average => average * (1.0 - LATENCY_ALPHA) + latency * LATENCY_ALPHA,
+        };
+    }
+
+    pub fn record_failure(&self, family: Family) {
+        let mut status = self.status.lock().unwrap_or_else(|e| e.into_inner());
+        let status = &mut status[family as usize];
+
+        status.failures += 1;
+        status.consecutive_failures += 1;
+    }
+
+    // Family we should connect over first, None if we have no reason to prefer either
+    pub fn preferred(&self) -> Option<Family> {
+        let status = self.status.lock().unwrap_or_else(|e| e.into_inner());
+        let (v4, v6) = (&status[Family::V4 as usize], &status[Family::V6 as usize]);
+
+        match (v4.healthy(), v6.healthy()) {
+            (true, false) => Some(Family::V4),
+            (false, true) => Some(Family::V6),
+            _ if v4.latency == 0.0 || v6.latency == 0.0 => None,
+            _ if v4.latency * LATENCY_FACTOR < v6.latency => Some(Family::V4),
+            _ if v6.latency * LATENCY_FACTOR < v4.latency => Some(Family::V6),
+            _ => None,
+        }
+    }
+
+    // Only includes families we checked
+    pub fn to_json(&self) -> Value {
+        let status = self.status.lock().unwrap_or_else(|e| e.into_inner());
+        let mut families = json!({});
+        for (family, name) in [(Family::V4, "v4"), (Family::V6, "v6")] {
+            let status = &status[family as usize];
+            if status.successes + status.failures > 0 {
+                families[name] = status.to_json();
+            }
+        }
+
+        families
+    }
+}
+
+// Put addresses of `family` first, keeping the order within each family
+pub fn order_addrs(addrs: Vec<SocketAddr>, family: Option<Family>) -> Vec<SocketAddr> {
+    let family = match family {
+        Some(family) => family,
+        None => return addrs,
+    };
+
+    let (mut preferred, fallback): (Vec<_>, Vec<_>) = addrs
+        .into_iter()
+        .partition(|addr| Family::of(addr) == family);
+    preferred.extend(fallback);
+    preferred
+}
+
+// Resolves RPC hostnames with the family we prefer first
+#[derive(Debug, Clone)]
+pub struct Dialer {
+    preference: IpPreference,
+    health: Arc<FamilyHealth>,
+    timings: Arc<TimingStats>,
+}
+
+impl Dialer {
+    pub fn new(
+        preference: IpPreference,
+        health: Arc<FamilyHealth>,
+        timings: Arc<TimingStats>,
+    ) -> Self {
+        Self {
+            preference,
+            health,
+            timings,
+        }
+    }
+
+    fn preferred(&self) -> Option<Family> {
+        match self.preference {
+            IpPreference::V4 => Some(Family::V4),
+            IpPreference::V6 => Some(Family::V6),
+            IpPreference::Auto => self.health.preferred(),
+        }
+    }
+}
+
+impl Resolve for Dialer {
+    fn resolve(&self, name: Name) -> Resolving {
+        let family = self.preferred();
+        let timings = Arc::clone(&self.timings);
+        Box::pin(async move {
+            // The connector sets the port
+            let resolving = Instant::now();
+            let addrs: Vec<SocketAddr> =
+                tokio::net::lookup_host((name.as_str(), 0)).await?.collect();
+            timings.record(Phase::Dns, resolving.elapsed());
+            let addrs: Addrs = Box::new(order_addrs(addrs, family).into_iter());
+            Ok(addrs)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addrs() -> Vec<SocketAddr> {
+        vec![
+            "[2001:db8::1]:0".parse().unwrap(),
+            "192.0.2.1:0".parse().unwrap(),
+            "[2001:db8::2]:0".parse().unwrap(),
+            "192.0.2.2:0".parse().unwrap(),
+        ]
+    }
+
+    #[test]
+    fn test_order_addrs() {
+        let ordered = order_addrs(addrs(), Some(Family::V4));
+        assert_eq!(ordered[0], "192.0.2.1:0".parse().unwrap());
+        assert_eq!(ordered[1], "192.0.2.2:0".parse().unwrap());
+        assert_eq!(ordered[2], "[2001:db8::1]:0".parse().unwrap());
+
+        assert_eq!(order_addrs(addrs(), None), addrs());
+    }
+
+    #[test]
+    fn test_preferred_family() {
+        let health = FamilyHealth::default();
+        assert_eq!(health.preferred(), None);
+
+        // Similar latency, no preference
+        health.record_success(Family::V4, 10.0);
+        health.record_success(Family::V6, 12.0);
+        assert_eq!(health.preferred(), None);
+
+        // IPv6 keeps failing
+        for _ in 0..FAILURE_THRESHOLD {
+            health.record_failure(Family::V6);
+        }
+        assert_eq!(health.preferred(), Some(Family::V4));
+
+        // Back, but a lot slower
+        health.record_success(Family::V6, 100.0);
+        assert_eq!(health.preferred(), Some(Family::V4));
+        assert_eq!(health.to_json()["v6"]["healthy"], true);
+    }
+}
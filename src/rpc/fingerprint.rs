@@ -0,0 +1,202 @@
+// What software an RPC runs, and how that changed over time.
+//
+// Providers move endpoints between clients without telling anyone, and a geth
+// endpoint turning into an erigon one can change results in subtle ways.
+use std::{
+    collections::{
+        BTreeMap,
+        VecDeque,
+    },
+    sync::Mutex,
+    time::{
+        SystemTime,
+        UNIX_EPOCH,
+    },
+};
+
+use serde_json::{
+    json,
+    Value,
+};
+
+// Changes we keep per RPC
+const MAX_CHANGES: usize = 32;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Fingerprint {
+    // None if the RPC doesn't tell
+    pub client_version: Option<String>,
+    // Module -> version, empty if the RPC doesn't support `rpc_modules`
+    pub modules: BTreeMap<String, String>,
+}
+
+impl Fingerprint {
+    pub fn to_json(&self) -> Value {
+        json!({
+            "client_version": self.client_version,
+            "modules": self.modules,
+        })
+    }
+
+    // Human readable list of what's different in `new`
+    pub fn describe_changes(&self, new: &Fingerprint) -> Vec<String> {
+        let mut changes = Vec::new();
+        if self.client_version != new.client_version {
+            changes.push(format!(
+                "client version {} -> {}",
+                self.client_version.as_deref().unwrap_or("unknown"),
+                new.client_version.as_deref().unwrap_or("unknown")
+            ));
+        }
+
+        for (module, version) in &self.modules {
+            match new.modules.get(module) {
+                None => changes.push(format!("module {} removed", module)),
+                Some(new_version) if new_version != version => {
+                    changes.push(format!("module {} {} -> {}", module, version, new_version))
+                }
+                _ => {}
+            }
+        }
+        for (module, version) in &new.modules {
+            if !self.modules.contains_key(module) {
+                changes.push(format!("module {} {} added", module, version));
+            }
+        }
+
+        changes
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FingerprintChange {
+    // Unix time in seconds
+    pub at: u64,
+    pub from: Fingerprint,
+    pub to: Fingerprint,
+}
+
+#[derive(Debug, Default)]
+struct History {
+    current: Option<Fingerprint>,
+    // Unix time in seconds
+    checked_at: Option<u64>,
+    // Oldest first
+    changes: VecDeque<FingerprintChange>,
+}
+
+// Fingerprints of an RPC, shared between clones
+#[derive(Debug, Default)]
+pub struct FingerprintHistory {
+    history: Mutex<History>,
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+impl FingerprintHistory {
+    // Record what the RPC runs now. Returns the change if it's different from
+    // last time, the first fingerprint never being a change.
+    pub fn record(&self, fingerprint: Fingerprint) -> Option<FingerprintChange> {
+        let mut history = self.history.lock().unwrap();
+        let at = now();
+        history.checked_at = Some(at);
+
+        let previous = history.current.replace(fingerprint.clone())?;
+        if previous == fingerprint {
+            return None;
+        }
+
+        let change = FingerprintChange {
+            at,
+            from: previous,
+            to: fingerprint,
+        };
+        if history.changes.len() == MAX_CHANGES {
+            history.changes.pop_front();
+        }
+        history.changes.push_back(change.clone());
+
+        Some(change)
+    }
+
+    pub fn to_json(&self) -> Value {
+        let history = self.history.lock().unwrap();
+        let changes: Vec<Value> = history
+            .changes
+            .iter()
+            .map(|change| {
+                json!({
+                    "at": change.at,
+                    "from": change.from.to_json(),
+                    "to": change.to.to_json(),
+                    "changes": change.from.describe_changes(&change.to),
+                })
+            })
+            .collect();
+
+        json!({
+            "current": history.current.as_ref().map(Fingerprint::to_json),
+            "checked_at": history.checked_at,
+            "changes": changes,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fingerprint(client_version: &str, modules: &[(&str, &str)]) -> Fingerprint {
+        Fingerprint {
+            client_version: Some(client_version.to_string()),
+            modules: modules
+                .iter()
+                .map(|(module, version)| (module.to_string(), version.to_string()))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_record() {
+        let history = FingerprintHistory::default();
+        let geth = fingerprint("Geth/v1.13.5", &[("eth", "1.0"), ("debug", "1.0")]);
+
+        assert_eq!(history.record(geth.clone()), None);
+        assert_eq!(history.record(geth.clone()), None);
+
+        let erigon = fingerprint("erigon/2.55.1", &[("eth", "1.0"), ("trace", "1.0")]);
+        let change = history.record(erigon.clone()).unwrap();
+        assert_eq!((&change.from, &change.to), (&geth, &erigon));
+        assert_eq!(
+            change.from.describe_changes(&change.to),
+            [
+                "client version Geth/v1.13.5 -> erigon/2.55.1",
+                "module debug removed",
+                "module trace 1.0 added",
+            ]
+        );
+
+        let json = history.to_json();
+        assert_eq!(json["current"]["client_version"], "erigon/2.55.1");
+        assert_eq!(json["changes"].as_array().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_max_changes() {
+        let history = FingerprintHistory::default();
+        for i in 0..=MAX_CHANGES + 1 {
+            history.record(fingerprint(&format!("v{}", i), &[]));
+        }
+
+        let json = history.to_json();
+        let changes = json["changes"].as_array().unwrap();
+        assert_eq!(changes.len(), MAX_CHANGES);
+        // Oldest ones are gone
+        assert_eq!(changes[0]["from"]["client_version"], "v1");
+    }
+}
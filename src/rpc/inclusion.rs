@@ -0,0 +1,96 @@
+// How long transactions we broadcast through an RPC take to get included.
+//
+// RPCs that propagate transactions well get them to block builders sooner, so comparing
+// these between RPCs tells operators which ones to broadcast through.
+use std::{
+    collections::VecDeque,
+    sync::Mutex,
+    time::Duration,
+};
+
+use serde_json::{
+    json,
+    Value,
+};
+
+// Inclusion times we keep for the median
+const SAMPLES: usize = 256;
+
+#[derive(Debug, Default)]
+struct Inclusions {
+    included: u64,
+    // Never seen in a block before we stopped looking
+    dropped: u64,
+    total_ms: u64,
+    // Most recent inclusion times in ms, oldest first
+    samples: VecDeque<u64>,
+}
+
+// Inclusion stats of a single RPC, shared by all of its clones
+#[derive(Debug, Default)]
+pub struct InclusionStats {
+    inclusions: Mutex<Inclusions>,
+}
+
+impl InclusionStats {
+    pub fn record_included(&self, elapsed: Duration) {
+        let elapsed = elapsed.as_millis() as u64;
+        let mut inclusions = self.inclusions.lock().unwrap();
+        inclusions.included += 1;
+        inclusions.total_ms += elapsed;
+        if inclusions.samples.len() == SAMPLES {
+            inclusions.samples.pop_front();
+        }
+        inclusions.samples.push_back(elapsed);
+    }
+
+    pub fn record_dropped(&self) {
+        self.inclusions.lock().unwrap().dropped += 1;
+    }
+
+    // Median of the recent inclusion times in ms
+    pub fn median_ms(&self) -> Option<u64> {
+        let inclusions = self.inclusions.lock().unwrap();
+        let mut samples: Vec<u64> = inclusions.samples.iter().copied().collect();
+        samples.sort_unstable();
+        samples.get(samples.len() / 2).copied()
+    }
+
+    pub fn to_json(&self) -> Value {
+        let median_ms = self.median_ms();
+        let inclusions = self.inclusions.lock().unwrap();
+        json!({
+            "included": inclusions.included,
+            "dropped": inclusions.dropped,
+            "average_ms": (inclusions.included > 0).then(|| inclusions.total_ms / inclusions.included),
+            "median_ms": median_ms,
+            "fastest_ms": inclusions.samples.iter().min(),
+            "slowest_ms": inclusions.samples.iter().max(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_inclusion_stats() {
+        let stats = InclusionStats::default();
+        assert_eq!(stats.median_ms(), None);
+        assert_eq!(stats.to_json()["average_ms"], Value::Null);
+
+        for ms in [3000, 1000, 2000, 12000] {
+            stats.record_included(Duration::from_millis(ms));
+        }
+        stats.record_dropped();
+
+        assert_eq!(stats.median_ms(), Some(3000));
+        let json = stats.to_json();
+        assert_eq!(json["included"], 4);
+        assert_eq!(json["dropped"], 1);
+        assert_eq!(json["average_ms"], 4500);
+        assert_eq!(json["fastest_ms"], 1000);
+        assert_eq!(json["slowest_ms"], 12000);
+    }
+}
@@ -1,2 +1,13 @@
+pub mod dial;
 pub mod error;
+pub mod fingerprint;
+pub mod inclusion;
+pub mod keccak;
+pub mod pool;
+pub mod probe;
+pub mod responses;
+pub mod signer;
+pub mod sla;
+pub mod timing;
 pub mod types;
+pub mod window;
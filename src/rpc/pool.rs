@@ -0,0 +1,171 @@
+// Connections to RPCs are kept open between requests, so only the first request over
+// a connection pays for the TCP and TLS handshakes.
+//
+// Idle connections get closed after a while though, so after a quiet period the next
+// requests would all have to connect again. Prewarming keeps a few connections per RPC
+// open by sending cheap requests over them at the same time every so often.
+//
+// We use native-tls, which doesn't resume TLS sessions on its own, so keeping the
+// connections themselves open is what saves us the handshakes.
+use crate::rpc::dial::{
+    Dialer,
+    Family,
+};
+
+use std::{
+    sync::{
+        atomic::{
+            AtomicU64,
+            AtomicUsize,
+            Ordering,
+        },
+        Arc,
+    },
+    time::Duration,
+};
+
+use reqwest::Client;
+use serde_json::{
+    json,
+    Value,
+};
+
+// How long idle connections are kept around
+const IDLE_TIMEOUT: Duration = Duration::from_secs(90);
+
+// So idle connections aren't dropped by NATs and load balancers in between
+const TCP_KEEPALIVE: Duration = Duration::from_secs(30);
+
+// Client an RPC sends its requests with. With `family` set it only connects over that family.
+pub fn build_client(dialer: Dialer, family: Option<Family>) -> Client {
+    Client::builder()
+        .pool_idle_timeout(IDLE_TIMEOUT)
+        .tcp_keepalive(TCP_KEEPALIVE)
+        .dns_resolver(Arc::new(dialer))
+        .local_address(family.map(Family::unspecified))
+        .build()
+        .unwrap_or_default()
+}
+
+// Connection and request stats of a single RPC, shared by all of its clones
+#[derive(Debug, Default)]
+pub struct PoolStats {
+    in_flight: AtomicUsize,
+    requests: AtomicU64,
+    // Requests that failed or were given up on before we got a response
+    errors: AtomicU64,
+    // Head reported by the last health check, 0 if it failed
+    head: AtomicU64,
+    failed_head_checks: AtomicU64,
+    // Connections the last prewarm opened or found open
+    warm: AtomicUsize,
+    prewarms: AtomicU64,
+    prewarm_failures: AtomicU64,
+}
+
+// Counts a request as in flight until dropped, and as an error unless it succeeded
+#[derive(Debug)]
+pub struct InFlight<'a> {
+    stats: &'a PoolStats,
+    succeeded: bool,
+}
+
+impl InFlight<'_> {
+    pub fn succeed(&mut self) {
+        self.succeeded = true;
+    }
+}
+
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        self.stats.in_flight.fetch_sub(1, Ordering::Relaxed);
+        if !self.succeeded {
+            self.stats.errors.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+impl PoolStats {
+    pub fn start_request(&self) -> InFlight<'_> {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+        self.in_flight.fetch_add(1, Ordering::Relaxed);
+        InFlight {
+            stats: self,
+            succeeded: false,
+        }
+    }
+
+    pub fn record_head(&self, head: u64) {
+        self.head.store(head, Ordering::Relaxed);
+        if head == 0 {
+            self.failed_head_checks.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.load(Ordering::Relaxed)
+    }
+
+    pub fn requests(&self) -> u64 {
+        self.requests.load(Ordering::Relaxed)
+    }
+
+    pub fn errors(&self) -> u64 {
+        self.errors.load(Ordering::Relaxed)
+    }
+
+    pub fn head(&self) -> u64 {
+        self.head.load(Ordering::Relaxed)
+    }
+
+    pub fn failed_head_checks(&self) -> u64 {
+        self.failed_head_checks.load(Ordering::Relaxed)
+    }
+
+    pub fn warm(&self) -> usize {
+        self.warm.load(Ordering::Relaxed)
+    }
+
+    pub fn record_prewarm(&self, warm: usize, failed: usize) {
+        self.warm.store(warm, Ordering::Relaxed);
+        self.prewarms.fetch_add(1, Ordering::Relaxed);
+        self.prewarm_failures
+            .fetch_add(failed as u64, Ordering::Relaxed);
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "in_flight": self.in_flight.load(Ordering::Relaxed),
+            "requests": self.requests.load(Ordering::Relaxed),
+            "errors": self.errors.load(Ordering::Relaxed),
+            "warm": self.warm.load(Ordering::Relaxed),
+            "prewarms": self.prewarms.load(Ordering::Relaxed),
+            "prewarm_failures": self.prewarm_failures.load(Ordering::Relaxed),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pool_stats() {
+        let stats = PoolStats::default();
+
+        let mut first = stats.start_request();
+        let second = stats.start_request();
+        assert_eq!(stats.to_json()["in_flight"], 2);
+        first.succeed();
+        drop(first);
+        drop(second);
+
+        stats.record_prewarm(3, 1);
+        let stats = stats.to_json();
+        assert_eq!(stats["in_flight"], 0);
+        assert_eq!(stats["requests"], 2);
+        assert_eq!(stats["errors"], 1);
+        assert_eq!(stats["warm"], 3);
+        assert_eq!(stats["prewarm_failures"], 1);
+    }
+}
@@ -0,0 +1,211 @@
+// Probes the health check subsystem runs against RPCs, e.g. whether they're syncing.
+//
+// Head checks take RPCs that fall behind or stop answering out of the pool. Probes catch
+// the ones that answer but shouldn't be trusted with much, like nodes that are still
+// syncing or have lost their peers. Those are marked as degraded, and only get requests
+// when no other RPC can take them.
+use crate::rpc::{
+    error::RpcError,
+    types::{
+        Protocol,
+        Rpc,
+    },
+};
+
+use std::{
+    fmt,
+    time::Duration,
+};
+
+use serde_json::{
+    json,
+    Value,
+};
+use tokio::time::timeout;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Probe {
+    // Not more than `max_lag` blocks behind the best head
+    BlockNumber,
+    // Not syncing
+    Syncing,
+    // At least `min_peers` peers
+    PeerCount,
+}
+
+impl Probe {
+    pub fn from_config(probe: &str) -> Option<Self> {
+        match probe {
+            "block_number" => Some(Probe::BlockNumber),
+            "syncing" => Some(Probe::Syncing),
+            "peer_count" => Some(Probe::PeerCount),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Probe {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let probe = match self {
+            Probe::BlockNumber => "block_number",
+            Probe::Syncing => "syncing",
+            Probe::PeerCount => "peer_count",
+        };
+        write!(f, "{}", probe)
+    }
+}
+
+// How an RPC gets probed
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProbeConfig {
+    pub interval: Duration,
+    pub probes: Vec<Probe>,
+    pub max_lag: u64,
+    pub min_peers: u64,
+}
+
+impl Default for ProbeConfig {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(10),
+            probes: vec![Probe::BlockNumber, Probe::Syncing, Probe::PeerCount],
+            max_lag: 5,
+            min_peers: 1,
+        }
+    }
+}
+
+// What a probe found out
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProbeResult {
+    Head(u64),
+    Syncing(bool),
+    // None if the RPC can't tell us
+    Peers(Option<u64>),
+}
+
+fn parse_u64(value: &Value) -> Option<u64> {
+    match value {
+        Value::String(hex) => u64::from_str_radix(hex.trim_start_matches("0x"), 16).ok(),
+        value => value.as_u64(),
+    }
+}
+
+async fn call(rpc: &Rpc, method: &str, ttl: Duration) -> Result<Value, RpcError> {
+    let tx = json!({
+        "id": 1,
+        "jsonrpc": "2.0",
+        "method": method,
+        "params": [],
+    });
+    let rx = timeout(ttl, rpc.send_request(tx))
+        .await
+        .map_err(|_| RpcError::Unresponsive)??;
+    let mut rx: Value = serde_json::from_slice(&rx)
+        .map_err(|err| RpcError::InvalidResponse(format!("error: {}", err)))?;
+
+    match rx["error"].is_null() {
+        true => Ok(rx["result"].take()),
+        false => Err(RpcError::InvalidResponse(rx["error"].to_string())),
+    }
+}
+
+// Run `probe` against `rpc`, in whatever dialect it speaks
+pub async fn run_probe(rpc: &Rpc, probe: Probe, ttl: Duration) -> Result<ProbeResult, RpcError> {
+    let result = match (probe, rpc.protocol) {
+        (Probe::BlockNumber, _) => {
+            let head = timeout(ttl, rpc.block_number())
+                .await
+                .map_err(|_| RpcError::Unresponsive)??;
+            ProbeResult::Head(head)
+        }
+        // `false`, or an object with the sync progress
+        (Probe::Syncing, Protocol::Evm) => {
+            ProbeResult::Syncing(call(rpc, "eth_syncing", ttl).await? != Value::Bool(false))
+        }
+        (Probe::Syncing, Protocol::Solana) => {
+            match call(rpc, "getHealth", ttl).await {
+                Ok(_) => ProbeResult::Syncing(false),
+                // Answers with a "node is behind" error while catching up
+                Err(RpcError::InvalidResponse(err)) if err.contains("-32005") => {
+                    ProbeResult::Syncing(true)
+                }
+                Err(err) => return Err(err),
+            }
+        }
+        (Probe::Syncing, Protocol::Bitcoin) => {
+            let info = call(rpc, "getblockchaininfo", ttl).await?;
+            ProbeResult::Syncing(info["initialblockdownload"].as_bool().unwrap_or(false))
+        }
+        (Probe::PeerCount, Protocol::Evm) => {
+            ProbeResult::Peers(parse_u64(&call(rpc, "net_peerCount", ttl).await?))
+        }
+        (Probe::PeerCount, Protocol::Bitcoin) => {
+            ProbeResult::Peers(parse_u64(&call(rpc, "getconnectioncount", ttl).await?))
+        }
+        (Probe::PeerCount, Protocol::Solana) => ProbeResult::Peers(None),
+    };
+
+    Ok(result)
+}
+
+impl ProbeConfig {
+    // Why `results` make an RPC degraded, with `best_head` being the highest head we know of.
+    // Empty if they don't.
+    pub fn degraded_by(&self, results: &[ProbeResult], best_head: u64) -> Vec<String> {
+        results
+            .iter()
+            .filter_map(|result| {
+                match *result {
+                    ProbeResult::Head(head) if head + self.max_lag < best_head => {
+                        Some(format!("{} blocks behind", best_head - head))
+                    }
+                    ProbeResult::Syncing(true) => Some("syncing".to_string()),
+                    ProbeResult::Peers(Some(peers)) if peers < self.min_peers => {
+                        Some(format!("{} peers", peers))
+                    }
+                    _ => None,
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_degraded_by() {
+        let config = ProbeConfig::default();
+        let healthy = [
+            ProbeResult::Head(100),
+            ProbeResult::Syncing(false),
+            ProbeResult::Peers(Some(25)),
+        ];
+        assert!(config.degraded_by(&healthy, 105).is_empty());
+
+        let degraded = [
+            ProbeResult::Head(90),
+            ProbeResult::Syncing(true),
+            ProbeResult::Peers(Some(0)),
+        ];
+        assert_eq!(
+            config.degraded_by(&degraded, 100),
+            vec!["10 blocks behind", "syncing", "0 peers"]
+        );
+        // RPCs that can't tell us their peers aren't degraded for it
+        assert!(config
+            .degraded_by(&[ProbeResult::Peers(None)], 0)
+            .is_empty());
+    }
+
+    #[test]
+    fn test_from_config() {
+        for probe in [Probe::BlockNumber, Probe::Syncing, Probe::PeerCount] {
+            assert_eq!(Probe::from_config(&probe.to_string()), Some(probe));
+        }
+        assert_eq!(Probe::from_config("uptime"), None);
+        assert_eq!(parse_u64(&json!("0x19")), Some(25));
+    }
+}
@@ -0,0 +1,118 @@
+// Latency and size of the most recent responses from an RPC.
+//
+// The moving average we balance on only covers the last `ma_length` requests, and says
+// nothing about the tail or how much data goes through an RPC. We keep the last
+// `SAMPLES` responses in a ring buffer and work percentiles and throughput out of those,
+// so operators can see what a good `ma_length` or weight for an RPC would be.
+use std::{
+    collections::VecDeque,
+    sync::Mutex,
+    time::{
+        Duration,
+        Instant,
+    },
+};
+
+use serde_json::{
+    json,
+    Value,
+};
+
+// Responses we keep the latency and size of
+const SAMPLES: usize = 1024;
+
+const PERCENTILES: [(&str, f64); 3] = [("p50", 0.5), ("p95", 0.95), ("p99", 0.99)];
+
+#[derive(Debug, Clone, Copy)]
+struct Sample {
+    at: Instant,
+    latency: Duration,
+    bytes: usize,
+}
+
+// Response stats of a single RPC, shared by all of its clones
+#[derive(Debug, Default)]
+pub struct ResponseStats {
+    // Oldest first
+    samples: Mutex<VecDeque<Sample>>,
+}
+
+// Value at the `q` quantile of `sorted`
+fn percentile<T: Copy>(sorted: &[T], q: f64) -> Option<T> {
+    let rank = (q * sorted.len() as f64).ceil() as usize;
+    sorted.get(rank.saturating_sub(1)).copied()
+}
+
+impl ResponseStats {
+    pub fn record(&self, latency: Duration, bytes: usize) {
+        let mut samples = self.samples.lock().unwrap_or_else(|e| e.into_inner());
+        if samples.len() == SAMPLES {
+            samples.pop_front();
+        }
+        samples.push_back(Sample {
+            at: Instant::now(),
+            latency,
+            bytes,
+        });
+    }
+
+    // Percentiles of latency in ms and size in bytes, and requests and bytes per second
+    // since the oldest response we have
+    pub fn to_json(&self) -> Value {
+        let samples = self.samples.lock().unwrap_or_else(|e| e.into_inner());
+        let mut latencies: Vec<Duration> = samples.iter().map(|sample| sample.latency).collect();
+        let mut sizes: Vec<usize> = samples.iter().map(|sample| sample.bytes).collect();
+        latencies.sort_unstable();
+        sizes.sort_unstable();
+
+        let mut latency_ms = json!({});
+        let mut size_bytes = json!({});
+        for (name, q) in PERCENTILES {
+            latency_ms[name] = json!(percentile(&latencies, q).map(|l| l.as_micros() as f64 / 1e3));
+            size_bytes[name] = json!(percentile(&sizes, q));
+        }
+
+        let elapsed = samples
+            .front()
+            .map(|oldest| oldest.at.elapsed().as_secs_f64())
+            .unwrap_or_default();
+        let throughput = |total: f64| (elapsed > 0.0).then(|| total / elapsed);
+
+        json!({
+            "samples": samples.len(),
+            "latency_ms": latency_ms,
+            "size_bytes": size_bytes,
+            "requests_per_second": throughput(samples.len() as f64),
+            "bytes_per_second": throughput(sizes.iter().sum::<usize>() as f64),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_response_stats() {
+        let stats = ResponseStats::default();
+        assert_eq!(stats.to_json()["samples"], 0);
+        assert!(stats.to_json()["latency_ms"]["p50"].is_null());
+
+        for ms in 1..=100 {
+            stats.record(Duration::from_millis(ms), ms as usize * 10);
+        }
+        let json = stats.to_json();
+        assert_eq!(json["samples"], 100);
+        assert_eq!(json["latency_ms"]["p50"], 50.0);
+        assert_eq!(json["latency_ms"]["p99"], 99.0);
+        assert_eq!(json["size_bytes"]["p95"], 950);
+        assert!(json["bytes_per_second"].as_f64().unwrap() > 0.0);
+
+        // Only the most recent ones are kept
+        for _ in 0..SAMPLES {
+            stats.record(Duration::from_millis(1), 1);
+        }
+        assert_eq!(stats.to_json()["samples"], SAMPLES);
+        assert_eq!(stats.to_json()["latency_ms"]["p99"], 1.0);
+    }
+}
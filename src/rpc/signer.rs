@@ -0,0 +1,375 @@
+// Some providers, mostly enterprise gateways, want every request signed, e.g. with
+// an HMAC of the body in a header. RPCs can have a signer that adds these headers
+// to every request we send them.
+//
+// Signers implement `RequestSigner`, so schemes that aren't HMAC can be added by
+// implementing it and adding them to `signer_from_config`.
+//
+// Execution clients want a JWT instead, signed with the secret they share with their
+// consensus client. `JwtSigner` does that for RPCs with a `jwt_secret`.
+use std::{
+    fmt,
+    sync::Arc,
+    time::{
+        SystemTime,
+        UNIX_EPOCH,
+    },
+};
+
+use base64::{
+    engine::general_purpose::STANDARD,
+    Engine,
+};
+use jsonwebtoken::{
+    encode,
+    EncodingKey,
+    Header,
+};
+use ring::hmac;
+use serde::Serialize;
+
+// What gets signed
+#[derive(Debug, Clone, Copy)]
+pub struct SignRequest<'a> {
+    pub method: &'a str,
+    pub path: &'a str,
+    pub body: &'a [u8],
+}
+
+pub trait RequestSigner: fmt::Debug + Send + Sync {
+    // Headers to add to the request
+    fn sign(&self, request: &SignRequest) -> Result<Vec<(String, String)>, String>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Encoding {
+    #[default]
+    Hex,
+    Base64,
+}
+
+impl Encoding {
+    fn encode(&self, bytes: &[u8]) -> String {
+        match self {
+            Encoding::Hex => bytes.iter().map(|byte| format!("{:02x}", byte)).collect(),
+            Encoding::Base64 => STANDARD.encode(bytes),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TimestampUnit {
+    #[default]
+    Seconds,
+    Millis,
+}
+
+// HMAC of a payload built from `payload`, where `{timestamp}`, `{method}`, `{path}`
+// and `{body}` are replaced with the ones of the request
+pub struct HmacSigner {
+    key: hmac::Key,
+    // Hash of the secret, so we can tell when it changes without keeping it around in the clear
+    fingerprint: String,
+    header: String,
+    encoding: Encoding,
+    payload: String,
+    timestamp_header: Option<String>,
+    timestamp_unit: TimestampUnit,
+    // Static headers sent along with the signature, e.g. an API key id
+    headers: Vec<(String, String)>,
+}
+
+impl fmt::Debug for HmacSigner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HmacSigner")
+            .field("algorithm", &self.key.algorithm())
+            .field("fingerprint", &self.fingerprint)
+            .field("header", &self.header)
+            .field("encoding", &self.encoding)
+            .field("payload", &self.payload)
+            .field("timestamp_header", &self.timestamp_header)
+            .field("timestamp_unit", &self.timestamp_unit)
+            .field("headers", &self.headers)
+            .finish()
+    }
+}
+
+impl HmacSigner {
+    pub fn new(algorithm: hmac::Algorithm, secret: &[u8], header: String) -> Self {
+        Self {
+            key: hmac::Key::new(algorithm, secret),
+            fingerprint: blake3::hash(secret).to_hex()[..16].to_string(),
+            header,
+            encoding: Encoding::default(),
+            payload: "{body}".to_string(),
+            timestamp_header: None,
+            timestamp_unit: TimestampUnit::default(),
+            headers: Vec::new(),
+        }
+    }
+
+    fn payload(&self, request: &SignRequest, timestamp: &str) -> Vec<u8> {
+        let mut payload = Vec::with_capacity(self.payload.len() + request.body.len());
+        let mut rest = self.payload.as_str();
+
+        while let Some(start) = rest.find('{') {
+            payload.extend_from_slice(&rest.as_bytes()[..start]);
+            rest = &rest[start..];
+
+            let end = match rest.find('}') {
+                Some(end) => end,
+                None => break,
+            };
+            match &rest[..=end] {
+                "{timestamp}" => payload.extend_from_slice(timestamp.as_bytes()),
+                "{method}" => payload.extend_from_slice(request.method.as_bytes()),
+                "{path}" => payload.extend_from_slice(request.path.as_bytes()),
+                "{body}" => payload.extend_from_slice(request.body),
+                other => payload.extend_from_slice(other.as_bytes()),
+            }
+            rest = &rest[end + 1..];
+        }
+        payload.extend_from_slice(rest.as_bytes());
+
+        payload
+    }
+
+    fn timestamp(&self) -> String {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        match self.timestamp_unit {
+            TimestampUnit::Seconds => now.as_secs().to_string(),
+            TimestampUnit::Millis => now.as_millis().to_string(),
+        }
+    }
+
+    fn sign_at(&self, request: &SignRequest, timestamp: &str) -> Vec<(String, String)> {
+        let signature = hmac::sign(&self.key, &self.payload(request, timestamp));
+
+        let mut headers = self.headers.clone();
+        headers.push((
+            self.header.clone(),
+            self.encoding.encode(signature.as_ref()),
+        ));
+        if let Some(timestamp_header) = &self.timestamp_header {
+            headers.push((timestamp_header.clone(), timestamp.to_string()));
+        }
+
+        headers
+    }
+}
+
+impl RequestSigner for HmacSigner {
+    fn sign(&self, request: &SignRequest) -> Result<Vec<(String, String)>, String> {
+        Ok(self.sign_at(request, &self.timestamp()))
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct Claims {
+    // Execution clients reject tokens issued more than a minute away from their clock
+    iat: u64,
+}
+
+// `Authorization: Bearer` JWT the way the Engine API wants it, HS256 with an `iat` claim.
+// Every request gets a fresh token, so they never get too old to be accepted.
+pub struct JwtSigner {
+    key: EncodingKey,
+    fingerprint: String,
+}
+
+impl fmt::Debug for JwtSigner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("JwtSigner")
+            .field("fingerprint", &self.fingerprint)
+            .finish()
+    }
+}
+
+impl JwtSigner {
+    pub fn new(secret: &[u8]) -> Self {
+        Self {
+            key: EncodingKey::from_secret(secret),
+            fingerprint: blake3::hash(secret).to_hex()[..16].to_string(),
+        }
+    }
+
+    fn sign_at(&self, iat: u64) -> Result<Vec<(String, String)>, String> {
+        let token = encode(&Header::default(), &Claims { iat }, &self.key)
+            .map_err(|err| err.to_string())?;
+        Ok(vec![(
+            "Authorization".to_string(),
+            format!("Bearer {}", token),
+        )])
+    }
+}
+
+impl RequestSigner for JwtSigner {
+    fn sign(&self, _request: &SignRequest) -> Result<Vec<(String, String)>, String> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        self.sign_at(now.as_secs())
+    }
+}
+
+// Secrets are 32 bytes of hex, like the `jwt.hex` files execution clients write
+pub fn parse_jwt_secret(secret: &str) -> Result<Vec<u8>, String> {
+    let secret = secret.trim();
+    let hex = secret.strip_prefix("0x").unwrap_or(secret);
+    // Also keeps the byte offsets below on char boundaries
+    if !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err("secret is not hex".to_string());
+    }
+    if hex.len() != 64 {
+        return Err(format!(
+            "expected 32 bytes of hex, got {} characters",
+            hex.len()
+        ));
+    }
+
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16))
+        .collect::<Result<Vec<u8>, _>>()
+        .map_err(|_| "secret is not hex".to_string())
+}
+
+// Everything a signer can be configured with, from the `signer` table of an RPC
+#[derive(Debug, Clone, Default)]
+pub struct SignerConfig {
+    pub kind: String,
+    pub secret: String,
+    pub header: String,
+    pub encoding: Option<String>,
+    pub payload: Option<String>,
+    pub timestamp_header: Option<String>,
+    pub timestamp_unit: Option<String>,
+    pub headers: Vec<(String, String)>,
+}
+
+pub fn signer_from_config(config: SignerConfig) -> Result<Arc<dyn RequestSigner>, String> {
+    let algorithm = match config.kind.as_str() {
+        "hmac-sha256" => hmac::HMAC_SHA256,
+        "hmac-sha384" => hmac::HMAC_SHA384,
+        "hmac-sha512" => hmac::HMAC_SHA512,
+        kind => return Err(format!("unknown signer: {}", kind)),
+    };
+    if config.header.is_empty() {
+        return Err("signer needs a header to put the signature in".to_string());
+    }
+
+    let mut signer = HmacSigner::new(algorithm, config.secret.as_bytes(), config.header);
+    signer.encoding = match config.encoding.as_deref() {
+        None | Some("hex") => Encoding::Hex,
+        Some("base64") => Encoding::Base64,
+        Some(encoding) => return Err(format!("unknown signature encoding: {}", encoding)),
+    };
+    signer.timestamp_unit = match config.timestamp_unit.as_deref() {
+        None | Some("s") => TimestampUnit::Seconds,
+        Some("ms") => TimestampUnit::Millis,
+        Some(unit) => return Err(format!("unknown timestamp unit: {}", unit)),
+    };
+    if let Some(payload) = config.payload {
+        signer.payload = payload;
+    }
+    signer.timestamp_header = config.timestamp_header;
+    signer.headers = config.headers;
+
+    Ok(Arc::new(signer))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const REQUEST: SignRequest = SignRequest {
+        method: "POST",
+        path: "/v1/rpc",
+        body: b"what do ya want for nothing?",
+    };
+
+    #[test]
+    fn test_hmac_signer() {
+        // RFC 4231 test case 2
+        let signer = HmacSigner::new(hmac::HMAC_SHA256, b"Jefe", "X-Signature".to_string());
+        assert_eq!(
+            signer.sign_at(&REQUEST, "0"),
+            vec![(
+                "X-Signature".to_string(),
+                "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843".to_string()
+            )]
+        );
+    }
+
+    #[test]
+    fn test_payload() {
+        let mut signer = HmacSigner::new(hmac::HMAC_SHA256, b"key", "X-Signature".to_string());
+        signer.payload = "{timestamp}.{method} {path}:{body}{unknown".to_string();
+        assert_eq!(
+            signer.payload(&REQUEST, "1700000000"),
+            b"1700000000.POST /v1/rpc:what do ya want for nothing?{unknown".to_vec()
+        );
+    }
+
+    #[test]
+    fn test_jwt_signer() {
+        use jsonwebtoken::{
+            decode,
+            DecodingKey,
+            Validation,
+        };
+
+        let secret = parse_jwt_secret(&format!("0x{}\n", "ab".repeat(32))).unwrap();
+        let signer = JwtSigner::new(&secret);
+        let headers = signer.sign_at(1700000000).unwrap();
+        assert_eq!(headers[0].0, "Authorization");
+
+        let token = headers[0].1.strip_prefix("Bearer ").unwrap();
+        let mut validation = Validation::default();
+        validation.validate_exp = false;
+        validation.required_spec_claims.clear();
+        let claims =
+            decode::<serde_json::Value>(token, &DecodingKey::from_secret(&secret), &validation)
+                .unwrap()
+                .claims;
+        assert_eq!(claims, serde_json::json!({"iat": 1700000000}));
+
+        assert!(!format!("{:?}", signer).contains("abab"));
+        assert!(parse_jwt_secret("abcd").is_err());
+        assert!(parse_jwt_secret(&"zz".repeat(32)).is_err());
+        // 64 bytes, but not 64 chars
+        assert!(parse_jwt_secret(&format!("{}é", "a".repeat(62))).is_err());
+    }
+
+    #[test]
+    fn test_signer_from_config() {
+        let signer = signer_from_config(SignerConfig {
+            kind: "hmac-sha512".to_string(),
+            secret: "secret".to_string(),
+            header: "X-Signature".to_string(),
+            encoding: Some("base64".to_string()),
+            timestamp_header: Some("X-Timestamp".to_string()),
+            headers: vec![("X-Key-Id".to_string(), "blutgang".to_string())],
+            ..Default::default()
+        })
+        .unwrap();
+
+        let headers = signer.sign(&REQUEST).unwrap();
+        let names: Vec<&str> = headers.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, vec!["X-Key-Id", "X-Signature", "X-Timestamp"]);
+        // 64 bytes of base64
+        assert_eq!(headers[1].1.len(), 88);
+
+        // Secrets don't end up in logs
+        assert!(!format!("{:?}", signer).contains("secret"));
+
+        assert!(signer_from_config(SignerConfig {
+            kind: "rot13".to_string(),
+            header: "X-Signature".to_string(),
+            ..Default::default()
+        })
+        .is_err());
+    }
+}
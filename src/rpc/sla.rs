@@ -0,0 +1,158 @@
+// Response time SLAs of RPCs, e.g. p95 under 250ms.
+//
+// RPCs in breach of their SLA over a sustained window only get a share of the requests
+// they'd normally get, until they recover. That's what operators otherwise do by hand,
+// lowering `max_consecutive` or commenting out slow providers.
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{
+            AtomicU64,
+            Ordering,
+        },
+        Mutex,
+    },
+    time::{
+        Duration,
+        Instant,
+    },
+};
+
+// Latencies we keep at most per RPC, so busy RPCs don't use unbounded memory
+const MAX_SAMPLES: usize = 4096;
+
+// Not in breach, every request is admitted
+const ADMIT_ALL: u64 = 0;
+// In breach with a share of 0, only used if nothing else is available
+const ADMIT_NONE: u64 = u64::MAX;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Sla {
+    // Percentile the SLA applies to, e.g. 95.0 for p95
+    pub percentile: f64,
+    pub max_latency: Duration,
+}
+
+impl std::fmt::Display for Sla {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "p{} < {}ms",
+            self.percentile,
+            self.max_latency.as_millis()
+        )
+    }
+}
+
+// Latency samples and breach state of a single RPC, shared by all of its clones
+#[derive(Debug, Default)]
+pub struct SlaStats {
+    // (when, latency), oldest first
+    samples: Mutex<VecDeque<(Instant, Duration)>>,
+    // Admit one in this many requests, or ADMIT_ALL/ADMIT_NONE
+    admit_every: AtomicU64,
+    considered: AtomicU64,
+}
+
+impl SlaStats {
+    pub fn record(&self, latency: Duration) {
+        let mut samples = self.samples.lock().unwrap();
+        if samples.len() == MAX_SAMPLES {
+            samples.pop_front();
+        }
+        samples.push_back((Instant::now(), latency));
+    }
+
+    // Latency at `percentile` over the last `window`, if we have at least `min_samples`
+    pub fn percentile(
+        &self,
+        percentile: f64,
+        window: Duration,
+        min_samples: usize,
+    ) -> Option<Duration> {
+        let mut samples = self.samples.lock().unwrap();
+        while samples
+            .front()
+            .is_some_and(|(when, _)| when.elapsed() > window)
+        {
+            samples.pop_front();
+        }
+        if samples.is_empty() || samples.len() < min_samples {
+            return None;
+        }
+
+        let mut latencies: Vec<Duration> = samples.iter().map(|(_, latency)| *latency).collect();
+        latencies.sort_unstable();
+        let rank = (percentile / 100.0 * latencies.len() as f64).ceil() as usize;
+        Some(latencies[rank.clamp(1, latencies.len()) - 1])
+    }
+
+    pub fn is_breached(&self) -> bool {
+        self.admit_every.load(Ordering::Relaxed) != ADMIT_ALL
+    }
+
+    // Only admit `share` of the requests from now on, 1.0 meaning all of them
+    pub fn set_share(&self, share: f64) {
+        let admit_every = match share {
+            share if share >= 1.0 => ADMIT_ALL,
+            share if share <= 0.0 => ADMIT_NONE,
+            share => (1.0 / share).round() as u64,
+        };
+        self.admit_every.store(admit_every, Ordering::Relaxed);
+    }
+
+    // Returns true if the request we're picking an RPC for may go to this one
+    pub fn admits(&self) -> bool {
+        match self.admit_every.load(Ordering::Relaxed) {
+            ADMIT_ALL => true,
+            ADMIT_NONE => false,
+            every => self.considered.fetch_add(1, Ordering::Relaxed) % every == 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_percentile() {
+        let stats = SlaStats::default();
+        let window = Duration::from_secs(60);
+        assert_eq!(stats.percentile(95.0, window, 1), None);
+
+        for ms in 1..=100 {
+            stats.record(Duration::from_millis(ms));
+        }
+        assert_eq!(
+            stats.percentile(95.0, window, 10),
+            Some(Duration::from_millis(95))
+        );
+        assert_eq!(
+            stats.percentile(50.0, window, 10),
+            Some(Duration::from_millis(50))
+        );
+        assert_eq!(stats.percentile(95.0, window, 101), None);
+
+        // Everything fell out of the window
+        assert_eq!(stats.percentile(95.0, Duration::ZERO, 1), None);
+    }
+
+    #[test]
+    fn test_admits() {
+        let stats = SlaStats::default();
+        assert!(!stats.is_breached());
+        assert!((0..10).all(|_| stats.admits()));
+
+        stats.set_share(0.25);
+        assert!(stats.is_breached());
+        assert_eq!((0..100).filter(|_| stats.admits()).count(), 25);
+
+        stats.set_share(0.0);
+        assert!(!stats.admits());
+
+        stats.set_share(1.0);
+        assert!(!stats.is_breached());
+        assert!(stats.admits());
+    }
+}
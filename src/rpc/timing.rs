@@ -0,0 +1,124 @@
+// Where the time of requests to an RPC goes.
+//
+// A slow RPC can be slow to reach or slow to execute, and the latency we balance on
+// can't tell the two apart. So we time each phase on its own: resolving the hostname,
+// the TCP connect and TLS handshake, waiting for the first byte of the response, and
+// downloading the rest of it. The first byte is mostly the node executing the request,
+// it only includes connecting for requests that needed a new connection.
+//
+// reqwest doesn't tell us when it connects, so connects and handshakes are measured
+// on connections of their own every `handshake_interval`.
+use std::{
+    fmt,
+    sync::Mutex,
+    time::Duration,
+};
+
+use serde_json::{
+    json,
+    Value,
+};
+
+// Weight of new samples in the moving averages
+const ALPHA: f64 = 0.2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    Dns,
+    Connect,
+    Tls,
+    FirstByte,
+    Body,
+}
+
+impl Phase {
+    pub const ALL: [Phase; 5] = [
+        Phase::Dns,
+        Phase::Connect,
+        Phase::Tls,
+        Phase::FirstByte,
+        Phase::Body,
+    ];
+}
+
+impl fmt::Display for Phase {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let phase = match self {
+            Phase::Dns => "dns",
+            Phase::Connect => "connect",
+            Phase::Tls => "tls",
+            Phase::FirstByte => "first_byte",
+            Phase::Body => "body",
+        };
+        write!(f, "{}", phase)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct PhaseStats {
+    pub count: u64,
+    // In seconds
+    pub sum: f64,
+    pub average: f64,
+}
+
+// Time spent in each phase for a single RPC, shared by all of its clones
+#[derive(Debug, Default)]
+pub struct TimingStats {
+    // Indexed by `Phase as usize`
+    phases: Mutex<[PhaseStats; 5]>,
+}
+
+impl TimingStats {
+    pub fn record(&self, phase: Phase, time: Duration) {
+        let mut phases = self.phases.lock().unwrap_or_else(|e| e.into_inner());
+        let stats = &mut phases[phase as usize];
+
+        let secs = time.as_secs_f64();
+        stats.average = match stats.count {
+            0 => secs,
+            _ => stats.average * (1.0 - ALPHA) + secs * ALPHA,
+        };
+        stats.count += 1;
+        stats.sum += secs;
+    }
+
+    pub fn get(&self, phase: Phase) -> PhaseStats {
+        self.phases.lock().unwrap_or_else(|e| e.into_inner())[phase as usize]
+    }
+
+    // Averages in ms, only for phases we timed
+    pub fn to_json(&self) -> Value {
+        let mut timings = json!({});
+        for phase in Phase::ALL {
+            let stats = self.get(phase);
+            if stats.count > 0 {
+                timings[phase.to_string()] = json!(stats.average * 1000.0);
+            }
+        }
+
+        timings
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record() {
+        let timings = TimingStats::default();
+        timings.record(Phase::Connect, Duration::from_millis(10));
+        timings.record(Phase::Connect, Duration::from_millis(20));
+
+        let connect = timings.get(Phase::Connect);
+        assert_eq!(connect.count, 2);
+        assert!((connect.sum - 0.03).abs() < 1e-9);
+        assert!((connect.average - 0.012).abs() < 1e-9);
+
+        assert_eq!(timings.get(Phase::Tls), PhaseStats::default());
+        let json = timings.to_json();
+        assert!((json["connect"].as_f64().unwrap() - 12.0).abs() < 1e-6);
+        assert!(json.get("tls").is_none());
+    }
+}
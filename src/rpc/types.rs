@@ -1,11 +1,64 @@
-use crate::rpc::error::RpcError;
-use reqwest::Client;
+use crate::{
+    logging::filter::{
+        log_enabled,
+        LogLevel,
+    },
+    rpc::{
+        dial::{
+            Dialer,
+            Family,
+            FamilyHealth,
+            IpPreference,
+        },
+        error::RpcError,
+        fingerprint::FingerprintHistory,
+        inclusion::InclusionStats,
+        pool::{
+            build_client,
+            PoolStats,
+        },
+        probe::ProbeConfig,
+        responses::ResponseStats,
+        signer::{
+            RequestSigner,
+            SignRequest,
+        },
+        sla::{
+            Sla,
+            SlaStats,
+        },
+        timing::{
+            Phase,
+            TimingStats,
+        },
+        window::WindowConfig,
+    },
+};
+use hyper::body::Bytes;
+use reqwest::{
+    header::CONTENT_TYPE,
+    Client,
+};
+use std::{
+    collections::VecDeque,
+    fs,
+    path::PathBuf,
+    sync::{
+        Arc,
+        OnceLock,
+        RwLock,
+    },
+    time::{
+        Duration,
+        Instant,
+    },
+};
+use tokio::time::timeout;
 
 use serde_json::{
     json,
     Value,
 };
-use simd_json;
 
 // All as floats so we have an easier time getting averages, stats and terminology copied from flood.
 #[derive(Debug, Clone, Default)]
@@ -17,33 +70,163 @@ pub struct Status {
 
     // The latency is a moving average of the last n calls
     pub latency: f64,
-    pub latency_data: Vec<f64>,
+    // Ring buffer of the latencies the average is over, oldest first
+    pub latency_data: VecDeque<f64>,
     ma_length: f64,
-    // ???
-    // pub throughput: f64,
+    // If set, `ma_length` adapts to how volatile latency is instead of staying put
+    pub window: Option<WindowConfig>,
+
+    // Share of requests `weighted_latency` sends here, recomputed on every health check
+    pub weight: f64,
+    // Weight built up since this RPC was last picked by `weighted_latency`
+    pub credit: f64,
+    // Latency and size of recent responses, shared by all clones of the RPC
+    pub responses: Arc<ResponseStats>,
 }
 
 unsafe impl Sync for Status {}
 
+impl Status {
+    // How many samples the moving average is over right now
+    pub fn ma_length(&self) -> usize {
+        self.ma_length as usize
+    }
+}
+
+// JSON-RPC dialect spoken by the RPC
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Protocol {
+    #[default]
+    Evm,
+    Solana,
+    Bitcoin,
+}
+
+impl Protocol {
+    // How many blocks/slots an RPC can be behind the highest head
+    // before we consider it to be falling behind
+    pub fn head_tolerance(&self) -> u64 {
+        match self {
+            Protocol::Evm => 0,
+            // Solana produces a slot every ~400ms, so heads rarely line up exactly
+            Protocol::Solana => 32,
+            // Blocks can take a while to propagate between nodes
+            Protocol::Bitcoin => 1,
+        }
+    }
+}
+
+// Confirmations after which we consider a Bitcoin block final
+const BITCOIN_CONFIRMATIONS: u64 = 6;
+
+// How we authenticate with the RPC
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RpcAuth {
+    Basic { username: String, password: String },
+    // Bitcoin Core `.cookie` file. Read on every request since it changes when the node restarts.
+    Cookie(PathBuf),
+}
+
+impl RpcAuth {
+    // Get the username and password
+    fn credentials(&self) -> Result<(String, String), RpcError> {
+        match self {
+            RpcAuth::Basic { username, password } => Ok((username.clone(), password.clone())),
+            RpcAuth::Cookie(path) => {
+                let cookie = fs::read_to_string(path).map_err(|err| {
+                    RpcError::InvalidResponse(format!("error: Could not read cookie: {}", err))
+                })?;
+                match cookie.trim().split_once(':') {
+                    Some((username, password)) => Ok((username.to_string(), password.to_string())),
+                    None => {
+                        Err(RpcError::InvalidResponse(
+                            "error: Malformed cookie file".to_string(),
+                        ))
+                    }
+                }
+            }
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Rpc {
-    pub url: String,    // url of the rpc we're forwarding requests to.
-    client: Client,     // Reqwest client
+    pub name: String, // name of the rpc, same as its config table. defaults to the url.
+    pub url: String,  // url of the rpc we're forwarding requests to.
+    pub ws_url: Option<String>, // websocket url, for subscriptions we fan out
+    client: Client,   // Reqwest client
     pub status: Status, // stores stats related to the rpc.
     pub max_consecutive: u32,
     pub consecutive: u32,
+    pub supports_state_overrides: bool, // if the rpc accepts `eth_call` state/block overrides
+    pub archive: bool,                  // if the rpc has archive state, used for heavy methods
+    pub tags: Vec<String>,              // matched against `[routing]` rules
+    pub protocol: Protocol,             // json-rpc dialect, used for head tracking
+    pub auth: Option<RpcAuth>,          // credentials sent with every request
+    pub cost: f64,                      // cost per request, 0 for self hosted nodes
+    pub cost_override: Option<f64>,     // cost set by the active schedule rules
+    pub paused: bool,                   // turned off by the active schedule rules
+    pub quarantined: bool,              // on a different chain than the pool, kept in poverty
+    pub prewarm: usize,                 // connections we keep open, 0 to not prewarm
+    pub max_per_second: f64,            // requests per second the provider allows, 0 if unknown
+    pub probes: Option<ProbeConfig>,    // how the health check subsystem probes it, if at all
+    pub degraded: bool,                 // failing its probes, only used if nothing else can be
+    pool: Arc<PoolStats>,               // connection stats, shared between clones
+    inclusion: Arc<InclusionStats>,     // time-to-inclusion of transactions we broadcast
+    pub sla: Option<Sla>,               // response time SLA, enforced by `enforce_slas`
+    sla_stats: Arc<SlaStats>,           // latencies and breach state for the SLA
+    fingerprints: Arc<FingerprintHistory>, // client versions and modules we've seen
+    pub ip_preference: IpPreference,    // address family we connect over first
+    families: Arc<FamilyHealth>,        // health of each address family
+    family_clients: Arc<OnceLock<[Client; 2]>>, // clients for checking each family
+    timings: Arc<TimingStats>,          // time spent in each phase of requests
+    pub signer: Option<Arc<dyn RequestSigner>>, // signs requests for providers that want it
 }
 
 unsafe impl Sync for Rpc {}
 
 impl Default for Rpc {
     fn default() -> Self {
+        let families = Arc::new(FamilyHealth::default());
+        let timings = Arc::new(TimingStats::default());
         Self {
+            name: "".to_string(),
             url: "".to_string(),
-            client: Client::new(),
+            ws_url: None,
+            client: build_client(
+                Dialer::new(
+                    IpPreference::default(),
+                    Arc::clone(&families),
+                    Arc::clone(&timings),
+                ),
+                None,
+            ),
             status: Status::default(),
             max_consecutive: 0,
             consecutive: 0,
+            supports_state_overrides: true,
+            archive: false,
+            tags: Vec::new(),
+            protocol: Protocol::default(),
+            auth: None,
+            cost: 0.0,
+            cost_override: None,
+            paused: false,
+            quarantined: false,
+            prewarm: 0,
+            max_per_second: 0.0,
+            probes: None,
+            degraded: false,
+            pool: Arc::new(PoolStats::default()),
+            inclusion: Arc::new(InclusionStats::default()),
+            sla: None,
+            sla_stats: Arc::new(SlaStats::default()),
+            fingerprints: Arc::new(FingerprintHistory::default()),
+            ip_preference: IpPreference::default(),
+            families,
+            family_clients: Arc::new(OnceLock::new()),
+            timings,
+            signer: None,
         }
     }
 }
@@ -51,24 +234,164 @@ impl Default for Rpc {
 // implement new for rpc
 impl Rpc {
     pub fn new(url: String, max_consecutive: u32, ma_length: f64) -> Self {
+        let families = Arc::new(FamilyHealth::default());
+        let timings = Arc::new(TimingStats::default());
         Self {
+            name: url.clone(),
             url,
-            client: Client::new(),
+            ws_url: None,
+            client: build_client(
+                Dialer::new(
+                    IpPreference::default(),
+                    Arc::clone(&families),
+                    Arc::clone(&timings),
+                ),
+                None,
+            ),
             status: Status {
                 ma_length,
                 ..Default::default()
             },
             max_consecutive,
             consecutive: 0,
+            supports_state_overrides: true,
+            archive: false,
+            tags: Vec::new(),
+            protocol: Protocol::default(),
+            auth: None,
+            cost: 0.0,
+            cost_override: None,
+            paused: false,
+            quarantined: false,
+            prewarm: 0,
+            max_per_second: 0.0,
+            probes: None,
+            degraded: false,
+            pool: Arc::new(PoolStats::default()),
+            inclusion: Arc::new(InclusionStats::default()),
+            sla: None,
+            sla_stats: Arc::new(SlaStats::default()),
+            fingerprints: Arc::new(FingerprintHistory::default()),
+            ip_preference: IpPreference::default(),
+            families,
+            family_clients: Arc::new(OnceLock::new()),
+            timings,
+            signer: None,
         }
     }
 
+    pub fn pool_stats(&self) -> &PoolStats {
+        &self.pool
+    }
+
+    pub fn inclusion_stats(&self) -> &Arc<InclusionStats> {
+        &self.inclusion
+    }
+
+    pub fn sla_stats(&self) -> &SlaStats {
+        &self.sla_stats
+    }
+
+    pub fn fingerprints(&self) -> &FingerprintHistory {
+        &self.fingerprints
+    }
+
+    // Returns true if we can send the request we're picking an RPC for here.
+    // RPCs in breach of their SLA only admit a share of requests.
+    pub fn sla_admits(&self) -> bool {
+        self.sla.is_none() || self.sla_stats.admits()
+    }
+
+    pub fn sla_breached(&self) -> bool {
+        self.sla.is_some() && self.sla_stats.is_breached()
+    }
+
+    pub fn family_health(&self) -> &FamilyHealth {
+        &self.families
+    }
+
+    pub fn timings(&self) -> &TimingStats {
+        &self.timings
+    }
+
+    fn dialer(&self, preference: IpPreference) -> Dialer {
+        Dialer::new(
+            preference,
+            Arc::clone(&self.families),
+            Arc::clone(&self.timings),
+        )
+    }
+
+    // Change which address family we connect over first. Drops open connections.
+    pub fn set_ip_preference(&mut self, preference: IpPreference) {
+        self.ip_preference = preference;
+        self.client = build_client(self.dialer(preference), None);
+    }
+
+    // Get the block number over `family` only, to see how healthy it is
+    pub async fn check_family(&self, family: Family) -> Result<u64, RpcError> {
+        let clients = self.family_clients.get_or_init(|| {
+            [Family::V4, Family::V6]
+                .map(|family| build_client(self.dialer(IpPreference::Auto), Some(family)))
+        });
+
+        self.block_number_with(&clients[family as usize]).await
+    }
+
     // Generic fn to send rpc
-    pub async fn send_request(&self, tx: Value) -> Result<String, crate::rpc::types::RpcError> {
-        #[cfg(feature = "debug-verbose")]
-        println!("Sending request: {}", tx.clone());
+    pub async fn send_request(&self, tx: Value) -> Result<Bytes, crate::rpc::types::RpcError> {
+        self.send_raw(Bytes::from(serde_json::to_vec(&tx).unwrap()))
+            .await
+    }
+
+    // Send an already serialized request and return the raw response body.
+    //
+    // Used in the hot path so we serialize requests only once and never copy responses.
+    pub async fn send_raw(&self, tx: Bytes) -> Result<Bytes, crate::rpc::types::RpcError> {
+        self.send_raw_with(&self.client, tx).await
+    }
 
-        let response = match self.client.post(&self.url).json(&tx).send().await {
+    async fn send_raw_with(
+        &self,
+        client: &Client,
+        tx: Bytes,
+    ) -> Result<Bytes, crate::rpc::types::RpcError> {
+        if cfg!(feature = "debug-verbose") || log_enabled("rpc", LogLevel::Debug) {
+            println!("Sending request: {}", String::from_utf8_lossy(&tx));
+        }
+
+        let mut in_flight = self.pool.start_request();
+        let signature = match &self.signer {
+            Some(signer) => {
+                let path = url::Url::parse(&self.url)
+                    .map(|url| url.path().to_string())
+                    .unwrap_or_default();
+                let request = SignRequest {
+                    method: "POST",
+                    path: &path,
+                    body: &tx,
+                };
+                signer.sign(&request).map_err(|err| {
+                    RpcError::InvalidResponse(format!("error: Could not sign request: {}", err))
+                })?
+            }
+            None => Vec::new(),
+        };
+
+        let mut request = client
+            .post(&self.url)
+            .header(CONTENT_TYPE, "application/json")
+            .body(tx);
+        for (name, value) in signature {
+            request = request.header(name, value);
+        }
+        if let Some(auth) = &self.auth {
+            let (username, password) = auth.credentials()?;
+            request = request.basic_auth(username, Some(password));
+        }
+
+        let sent = Instant::now();
+        let response = match request.send().await {
             Ok(response) => response,
             Err(err) => {
                 return Err(crate::rpc::types::RpcError::InvalidResponse(
@@ -76,28 +399,62 @@ impl Rpc {
                 ))
             }
         };
+        self.timings.record(Phase::FirstByte, sent.elapsed());
+
+        let headers_received = Instant::now();
+        let rx = response
+            .bytes()
+            .await
+            .map_err(|err| crate::rpc::types::RpcError::InvalidResponse(err.to_string()))?;
+        self.timings.record(Phase::Body, headers_received.elapsed());
+        self.status.responses.record(sent.elapsed(), rx.len());
 
-        #[cfg(feature = "debug-verbose")]
-        {
-            let a = response.text().await.unwrap();
-            println!("response: {}", a);
-            return Ok(a);
+        if cfg!(feature = "debug-verbose") || log_enabled("rpc", LogLevel::Debug) {
+            println!("response: {}", String::from_utf8_lossy(&rx));
         }
 
-        #[cfg(not(feature = "debug-verbose"))]
-        Ok(response.text().await.unwrap())
+        in_flight.succeed();
+        Ok(rx)
     }
 
     // Request blocknumber and return its value
+    //
+    // For Solana this is the latest confirmed slot, for Bitcoin the block height
     pub async fn block_number(&self) -> Result<u64, crate::rpc::types::RpcError> {
-        let request = json!({
-            "method": "eth_blockNumber".to_string(),
-            "params": serde_json::Value::Null,
-            "id": 1,
-            "jsonrpc": "2.0".to_string(),
-        });
+        self.block_number_with(&self.client).await
+    }
 
-        let number = self.send_request(request).await?;
+    async fn block_number_with(&self, client: &Client) -> Result<u64, crate::rpc::types::RpcError> {
+        let request = match self.protocol {
+            Protocol::Evm => {
+                json!({
+                    "method": "eth_blockNumber".to_string(),
+                    "params": serde_json::Value::Null,
+                    "id": 1,
+                    "jsonrpc": "2.0".to_string(),
+                })
+            }
+            Protocol::Solana => {
+                json!({
+                    "method": "getSlot".to_string(),
+                    "params": [{"commitment": "confirmed"}],
+                    "id": 1,
+                    "jsonrpc": "2.0".to_string(),
+                })
+            }
+            Protocol::Bitcoin => {
+                json!({
+                    "method": "getblockcount".to_string(),
+                    "params": [],
+                    "id": 1,
+                    "jsonrpc": "1.0".to_string(),
+                })
+            }
+        };
+
+        let number = self
+            .send_raw_with(client, Bytes::from(serde_json::to_vec(&request).unwrap()))
+            .await?;
         let return_number = extract_number(&number)?;
 
         Ok(return_number)
@@ -105,6 +462,25 @@ impl Rpc {
 
     // Get the latest finalized block
     pub async fn get_finalized_block(&self) -> Result<u64, crate::rpc::types::RpcError> {
+        if self.protocol == Protocol::Solana {
+            let request = json!({
+                "method": "getSlot".to_string(),
+                "params": [{"commitment": "finalized"}],
+                "id": 1,
+                "jsonrpc": "2.0".to_string(),
+            });
+
+            return extract_number(&self.send_request(request).await?);
+        }
+
+        // Bitcoin has no finality, so we wait for enough confirmations instead
+        if self.protocol == Protocol::Bitcoin {
+            return Ok(self
+                .block_number()
+                .await?
+                .saturating_sub(BITCOIN_CONFIRMATIONS));
+        }
+
         let request = json!({
             "method": "eth_getBlockByNumber".to_string(),
             "params": ["finalized", false],
@@ -112,8 +488,8 @@ impl Rpc {
             "jsonrpc": "2.0".to_string(),
         });
 
-        let number: Value =
-            unsafe { simd_json::serde::from_str(&mut self.send_request(request).await?).unwrap() };
+        let number: Value = serde_json::from_slice(&self.send_request(request).await?)
+            .map_err(|err| RpcError::InvalidResponse(err.to_string()))?;
         let number = &number["result"]["number"];
 
         let number = match number.as_str() {
@@ -133,27 +509,44 @@ impl Rpc {
         Ok(return_number)
     }
 
+    // Cost of a request, taking the schedule into account
+    pub fn effective_cost(&self) -> f64 {
+        self.cost_override.unwrap_or(self.cost)
+    }
+
     // Update the latency of the last n calls.
     // We don't do it within send_request because we might kill it if it times out.
     pub fn update_latency(&mut self, latest: f64) {
-        // If we have data >= to ma_length, remove the first one in line
-        if self.status.latency_data.len() >= self.status.ma_length as usize {
-            self.status.latency_data.remove(0);
+        if let Some(window) = self.status.window {
+            self.status.ma_length =
+                window.next_length(self.status.ma_length as usize, self.status.latency, latest)
+                    as f64;
+        }
+
+        // Make room for the latest one, dropping the oldest samples first.
+        // The window can shrink by more than one, so this isn't always just one.
+        let keep = (self.status.ma_length as usize).saturating_sub(1);
+        let len = self.status.latency_data.len();
+        if len > keep {
+            self.status.latency_data.drain(..len - keep);
         }
 
         // Update latency
-        self.status.latency_data.push(latest);
+        self.status.latency_data.push_back(latest);
         self.status.latency =
             self.status.latency_data.iter().sum::<f64>() / self.status.latency_data.len() as f64;
     }
 }
 
 // Take in the result of eth_getBlockByNumber, and extract the block number
-fn extract_number(rx: &str) -> Result<u64, RpcError> {
-    // TODO: maybe this is too slow?
-    let mut rx = rx.to_string();
+fn extract_number(rx: &[u8]) -> Result<u64, RpcError> {
+    let json: Value =
+        serde_json::from_slice(rx).map_err(|err| RpcError::InvalidResponse(err.to_string()))?;
 
-    let json: Value = unsafe { simd_json::serde::from_str(&mut rx).unwrap() };
+    // Solana and Bitcoin return plain integers
+    if let Some(number) = json["result"].as_u64() {
+        return Ok(number);
+    }
 
     let number = match json["result"].as_str() {
         Some(number) => number,
@@ -164,8 +557,36 @@ fn extract_number(rx: &str) -> Result<u64, RpcError> {
         }
     };
 
-    let number = hex_to_decimal(number).unwrap();
-    Ok(number)
+    hex_to_decimal(number).map_err(|err| RpcError::InvalidResponse(err.to_string()))
+}
+
+// `result` of `tx` from the first RPC that has one, for requests we make in the background
+pub async fn first_result(
+    rpc_list: &Arc<RwLock<Vec<Rpc>>>,
+    tx: &Value,
+    ttl: Duration,
+) -> Option<Value> {
+    let rpcs: Vec<Rpc> = rpc_list
+        .read()
+        .unwrap()
+        .iter()
+        .filter(|rpc| !rpc.paused)
+        .cloned()
+        .collect();
+
+    for rpc in rpcs {
+        let rx = match timeout(ttl, rpc.send_request(tx.clone())).await {
+            Ok(Ok(rx)) => rx,
+            _ => continue,
+        };
+        if let Ok(mut rx) = serde_json::from_slice::<Value>(&rx) {
+            if !rx["result"].is_null() {
+                return Some(rx["result"].take());
+            }
+        }
+    }
+
+    None
 }
 
 pub fn hex_to_decimal(hex_string: &str) -> Result<u64, std::num::ParseIntError> {
@@ -178,3 +599,68 @@ pub fn hex_to_decimal(hex_string: &str) -> Result<u64, std::num::ParseIntError>
 
     u64::from_str_radix(hex_string, 16)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_extract_number() {
+        assert_eq!(
+            extract_number(br#"{"jsonrpc":"2.0","id":1,"result":"0x10"}"#).unwrap(),
+            16
+        );
+        assert_eq!(
+            extract_number(br#"{"jsonrpc":"2.0","id":1,"result":16}"#).unwrap(),
+            16
+        );
+        assert!(extract_number(br#"{"jsonrpc":"2.0","id":1,"result":null}"#).is_err());
+        assert!(extract_number(b"Unauthorized").is_err());
+        assert!(extract_number(br#"{"jsonrpc":"2.0","id":1,"result":"0xnope"}"#).is_err());
+    }
+
+    #[test]
+    fn test_cookie_credentials() {
+        let path = std::env::temp_dir().join("blutgang_test_cookie");
+        fs::write(&path, "__cookie__:hunter2\n").unwrap();
+
+        let auth = RpcAuth::Cookie(path.clone());
+        assert_eq!(
+            auth.credentials().unwrap(),
+            ("__cookie__".to_string(), "hunter2".to_string())
+        );
+
+        fs::write(&path, "garbage").unwrap();
+        assert!(auth.credentials().is_err());
+
+        fs::remove_file(&path).unwrap();
+        assert!(auth.credentials().is_err());
+    }
+
+    #[test]
+    fn test_update_latency() {
+        let mut rpc = Rpc::new("http://localhost".to_string(), 1, 4.0);
+        for latency in [10.0, 10.0, 10.0, 10.0, 10.0] {
+            rpc.update_latency(latency);
+        }
+        assert_eq!(rpc.status.latency_data.len(), 4);
+        assert_eq!(rpc.status.ma_length(), 4);
+
+        rpc.status.window = Some(WindowConfig {
+            min_length: 2,
+            max_length: 6,
+            volatility: 0.5,
+        });
+        for _ in 0..4 {
+            rpc.update_latency(10.0);
+        }
+        assert_eq!(rpc.status.ma_length(), 6);
+        assert_eq!(rpc.status.latency_data.len(), 6);
+
+        // A spike halves the window, so the average catches up quickly
+        rpc.update_latency(100.0);
+        assert_eq!(rpc.status.ma_length(), 3);
+        assert_eq!(rpc.status.latency_data, vec![10.0, 10.0, 100.0]);
+        assert_eq!(rpc.status.latency, 40.0);
+    }
+}
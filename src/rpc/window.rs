@@ -0,0 +1,55 @@
+// Window of the latency moving average, for RPCs we let it adapt on.
+//
+// A long window keeps a single slow request from moving an RPC down the list, but it
+// also takes a long time to notice an RPC that really did get slow. So every time a
+// sample lands far off the average we halve the window, dropping the old samples that
+// don't describe the RPC anymore, and while samples stay close we grow it back one at
+// a time so the ordering doesn't flap.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WindowConfig {
+    pub min_length: usize,
+    pub max_length: usize,
+    // How far off the average a sample has to be, as a fraction of it, to count as volatile
+    pub volatility: f64,
+}
+
+impl Default for WindowConfig {
+    fn default() -> Self {
+        Self {
+            min_length: 3,
+            max_length: 50,
+            volatility: 0.5,
+        }
+    }
+}
+
+impl WindowConfig {
+    // Length of the window after a `latest` sample comes in, with `average` being the
+    // moving average before it
+    pub fn next_length(&self, length: usize, average: f64, latest: f64) -> usize {
+        let volatile = average > 0.0 && (latest - average).abs() / average > self.volatility;
+        let length = match volatile {
+            true => length / 2,
+            false => length + 1,
+        };
+        length.clamp(self.min_length.max(1), self.max_length.max(1))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_next_length() {
+        let config = WindowConfig::default();
+        // Stable samples grow the window up to the max
+        assert_eq!(config.next_length(10, 100.0, 110.0), 11);
+        assert_eq!(config.next_length(50, 100.0, 90.0), 50);
+        // Volatile ones halve it down to the min
+        assert_eq!(config.next_length(10, 100.0, 300.0), 5);
+        assert_eq!(config.next_length(4, 100.0, 20.0), 3);
+        // No average yet, nothing to be volatile against
+        assert_eq!(config.next_length(0, 0.0, 100.0), 3);
+    }
+}
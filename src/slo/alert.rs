@@ -0,0 +1,100 @@
+use crate::slo::types::{
+    SloTracker,
+    LONG_WINDOW,
+    SHORT_WINDOW,
+};
+
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{
+        Duration,
+        Instant,
+    },
+};
+
+use reqwest::Client;
+use serde_json::json;
+use tokio::time::sleep;
+
+// How often we check burn rates
+const CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+// Don't alert for the same endpoint/upstream more than once per hour
+const ALERT_COOLDOWN: Duration = Duration::from_secs(3600);
+
+// Returns the endpoints/upstreams whose budget is burning faster than `threshold`.
+//
+// Both the short and long window have to be above the threshold, so we alert
+// quickly on real incidents, but stop once they're resolved.
+fn burning(slo: &SloTracker, threshold: f64) -> Vec<(String, f64, f64)> {
+    let short: HashMap<String, f64> = slo.burn_rates(SHORT_WINDOW).into_iter().collect();
+
+    slo.burn_rates(LONG_WINDOW)
+        .into_iter()
+        .filter_map(|(key, long)| {
+            let short = *short.get(&key)?;
+            (long > threshold && short > threshold).then_some((key, short, long))
+        })
+        .collect()
+}
+
+// Check if any error budget is burning too fast and alert if it is.
+//
+// Alerts are always printed, and sent to `webhook` if set.
+pub async fn slo_alerts(slo: Arc<SloTracker>, threshold: f64, webhook: Option<String>) {
+    let client = Client::new();
+    let mut last_alert: HashMap<String, Instant> = HashMap::new();
+
+    loop {
+        sleep(CHECK_INTERVAL).await;
+
+        for (key, short, long) in burning(&slo, threshold) {
+            if last_alert
+                .get(&key)
+                .is_some_and(|last| last.elapsed() < ALERT_COOLDOWN)
+            {
+                continue;
+            }
+            last_alert.insert(key.clone(), Instant::now());
+
+            println!(
+                "\x1b[93mWrn:\x1b[0m Error budget for {} is burning fast! 5m: {:.2}x, 1h: {:.2}x",
+                key, short, long
+            );
+
+            if let Some(webhook) = &webhook {
+                let alert = json!({
+                    "source": "blutgang",
+                    "alert": "slo_burn_rate",
+                    "key": key,
+                    "burn_rate_5m": short,
+                    "burn_rate_1h": long,
+                    "threshold": threshold,
+                });
+
+                if let Err(err) = client.post(webhook).json(&alert).send().await {
+                    println!("\x1b[31mErr:\x1b[0m Could not send SLO alert: {}", err);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_burning() {
+        let slo = SloTracker::new(0.99, 30);
+        for i in 0..100 {
+            slo.record_endpoint("default", i % 2 == 0);
+            slo.record_upstream("healthy", true);
+        }
+
+        let burning = burning(&slo, 14.4);
+        assert_eq!(burning.len(), 1);
+        assert_eq!(burning[0].0, "endpoint:default");
+    }
+}
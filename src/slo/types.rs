@@ -0,0 +1,238 @@
+use serde_json::{
+    json,
+    Map,
+    Value,
+};
+use std::{
+    collections::{
+        HashMap,
+        VecDeque,
+    },
+    sync::Mutex,
+    time::{
+        SystemTime,
+        UNIX_EPOCH,
+    },
+};
+
+// Windows we report burn rates for, in minutes
+pub const SHORT_WINDOW: u64 = 5;
+pub const LONG_WINDOW: u64 = 60;
+const REPORTED_WINDOWS: [(&str, u64); 3] = [("5m", SHORT_WINDOW), ("1h", LONG_WINDOW), ("6h", 360)];
+
+// Current minute since the unix epoch
+fn unix_minute() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+        / 60
+}
+
+// Request outcomes bucketed by minute. Minutes without requests aren't stored.
+#[derive(Debug, Default)]
+struct SloWindow {
+    // (minute, total, errors), oldest first
+    buckets: VecDeque<(u64, u64, u64)>,
+}
+
+impl SloWindow {
+    fn record(&mut self, minute: u64, success: bool, window: u64) {
+        match self.buckets.back_mut() {
+            Some((last, total, errors)) if *last == minute => {
+                *total += 1;
+                *errors += !success as u64;
+            }
+            _ => self.buckets.push_back((minute, 1, !success as u64)),
+        }
+
+        // Drop everything that fell out of the SLO window
+        while let Some((oldest, _, _)) = self.buckets.front() {
+            if *oldest + window > minute {
+                break;
+            }
+            self.buckets.pop_front();
+        }
+    }
+
+    // Total requests and errors over the last `minutes`
+    fn counts(&self, now: u64, minutes: u64) -> (u64, u64) {
+        self.buckets
+            .iter()
+            .rev()
+            .take_while(|(minute, _, _)| minute + minutes > now)
+            .fold((0, 0), |(total, errors), (_, t, e)| (total + t, errors + e))
+    }
+}
+
+// Tracks success rate SLOs for endpoints and upstreams.
+//
+// Burn rate is how fast we're using up the error budget, 1.0 meaning we'd use
+// up exactly the entire budget over the SLO window.
+#[derive(Debug)]
+pub struct SloTracker {
+    // Target success rate, e.g. 0.999
+    target: f64,
+    // SLO window in minutes
+    window: u64,
+    endpoints: Mutex<HashMap<String, SloWindow>>,
+    upstreams: Mutex<HashMap<String, SloWindow>>,
+}
+
+impl SloTracker {
+    pub fn new(target: f64, window_days: u64) -> Self {
+        Self {
+            target,
+            window: window_days * 24 * 60,
+            endpoints: Mutex::new(HashMap::new()),
+            upstreams: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn record_endpoint(&self, endpoint: &str, success: bool) {
+        self.record(&self.endpoints, endpoint, success, unix_minute());
+    }
+
+    pub fn record_upstream(&self, upstream: &str, success: bool) {
+        self.record(&self.upstreams, upstream, success, unix_minute());
+    }
+
+    fn record(
+        &self,
+        map: &Mutex<HashMap<String, SloWindow>>,
+        key: &str,
+        success: bool,
+        minute: u64,
+    ) {
+        let mut map = map.lock().unwrap_or_else(|e| e.into_inner());
+        match map.get_mut(key) {
+            Some(window) => window.record(minute, success, self.window),
+            None => {
+                let mut window = SloWindow::default();
+                window.record(minute, success, self.window);
+                map.insert(key.to_string(), window);
+            }
+        }
+    }
+
+    fn burn_rate(&self, total: u64, errors: u64) -> f64 {
+        if total == 0 {
+            return 0.0;
+        }
+
+        let error_rate = errors as f64 / total as f64;
+        error_rate / (1.0 - self.target)
+    }
+
+    // Burn rates of every endpoint and upstream over `minutes`
+    pub fn burn_rates(&self, minutes: u64) -> Vec<(String, f64)> {
+        let now = unix_minute();
+        let mut burn_rates = Vec::new();
+
+        for (prefix, map) in [("endpoint", &self.endpoints), ("upstream", &self.upstreams)] {
+            let map = map.lock().unwrap_or_else(|e| e.into_inner());
+            for (key, window) in map.iter() {
+                let (total, errors) = window.counts(now, minutes);
+                burn_rates.push((format!("{}:{}", prefix, key), self.burn_rate(total, errors)));
+            }
+        }
+
+        burn_rates
+    }
+
+    fn report_window(&self, window: &SloWindow, now: u64) -> Value {
+        let (total, errors) = window.counts(now, self.window);
+        let success_rate = if total == 0 {
+            1.0
+        } else {
+            1.0 - errors as f64 / total as f64
+        };
+
+        let mut burn_rates = Map::new();
+        for (name, minutes) in REPORTED_WINDOWS {
+            let (total, errors) = window.counts(now, minutes);
+            burn_rates.insert(name.to_string(), json!(self.burn_rate(total, errors)));
+        }
+        burn_rates.insert(
+            format!("{}d", self.window / 24 / 60),
+            json!(self.burn_rate(total, errors)),
+        );
+
+        json!({
+            "requests": total,
+            "errors": errors,
+            "success_rate": success_rate,
+            "budget_remaining": 1.0 - self.burn_rate(total, errors),
+            "burn_rate": burn_rates,
+        })
+    }
+
+    // Report the SLO status of every endpoint and upstream
+    pub fn report(&self) -> Value {
+        let now = unix_minute();
+        let mut report = Map::new();
+
+        for (name, map) in [
+            ("endpoints", &self.endpoints),
+            ("upstreams", &self.upstreams),
+        ] {
+            let map = map.lock().unwrap_or_else(|e| e.into_inner());
+            let windows: Map<String, Value> = map
+                .iter()
+                .map(|(key, window)| (key.clone(), self.report_window(window, now)))
+                .collect();
+            report.insert(name.to_string(), Value::Object(windows));
+        }
+
+        report.insert("target".to_string(), json!(self.target));
+        Value::Object(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_window_counts() {
+        let mut window = SloWindow::default();
+        window.record(100, true, 60);
+        window.record(100, false, 60);
+        window.record(130, true, 60);
+
+        assert_eq!(window.counts(130, 60), (3, 1));
+        assert_eq!(window.counts(130, 5), (1, 0));
+
+        // Minute 100 falls out of the window
+        window.record(160, true, 60);
+        assert_eq!(window.buckets.len(), 2);
+        assert_eq!(window.counts(160, 60), (2, 0));
+    }
+
+    #[test]
+    fn test_burn_rate() {
+        let slo = SloTracker::new(0.99, 30);
+        assert_eq!(slo.burn_rate(0, 0), 0.0);
+        assert!((slo.burn_rate(100, 1) - 1.0).abs() < 1e-9);
+        assert!((slo.burn_rate(100, 10) - 10.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_report() {
+        let slo = SloTracker::new(0.9, 30);
+        for i in 0..10 {
+            slo.record_endpoint("default", i != 0);
+            slo.record_upstream("node", true);
+        }
+
+        let report = slo.report();
+        let endpoint = &report["endpoints"]["default"];
+        assert_eq!(endpoint["requests"], 10);
+        assert_eq!(endpoint["errors"], 1);
+        assert!((endpoint["burn_rate"]["1h"].as_f64().unwrap() - 1.0).abs() < 1e-9);
+        assert_eq!(report["upstreams"]["node"]["errors"], 0);
+
+        let burn_rates = slo.burn_rates(LONG_WINDOW);
+        assert_eq!(burn_rates.len(), 2);
+    }
+}
@@ -0,0 +1,226 @@
+use crate::{
+    balancer::selection::select::pick,
+    Rpc,
+};
+
+use std::{
+    collections::HashMap,
+    sync::{
+        Arc,
+        RwLock,
+    },
+    time::Duration,
+};
+
+use serde_json::{
+    json,
+    Value,
+};
+use tokio::{
+    sync::{
+        mpsc,
+        oneshot,
+    },
+    time::{
+        timeout,
+        Instant,
+    },
+};
+
+// Max amount of signatures RPCs accept in a single `getSignatureStatuses` call
+const MAX_SIGNATURES: usize = 256;
+
+// How long we wait for more requests before sending a batch
+const BATCH_WINDOW: Duration = Duration::from_millis(5);
+
+// A `getSignatureStatuses` request waiting to be batched
+#[derive(Debug)]
+pub struct StatusRequest {
+    pub signatures: Vec<Value>,
+    // Config object, e.g. `{"searchTransactionHistory": true}`
+    pub config: Value,
+    // `result` of the request, None if the batch failed
+    pub reply: oneshot::Sender<Option<Value>>,
+}
+
+// Returns a StatusRequest if `tx` is a `getSignatureStatuses` call we can batch
+pub fn status_request(tx: &Value) -> Option<(StatusRequest, oneshot::Receiver<Option<Value>>)> {
+    if tx["method"].as_str()? != "getSignatureStatuses" {
+        return None;
+    }
+
+    let signatures = tx["params"][0].as_array()?.clone();
+    if signatures.is_empty() || signatures.len() > MAX_SIGNATURES {
+        return None;
+    }
+
+    let (reply, reply_rx) = oneshot::channel();
+    let request = StatusRequest {
+        signatures,
+        config: tx["params"][1].clone(),
+        reply,
+    };
+
+    Some((request, reply_rx))
+}
+
+// Collect `getSignatureStatuses` calls made at around the same time
+// and send them upstream as one request.
+pub async fn signature_status_batcher(
+    mut batch_rx: mpsc::Receiver<StatusRequest>,
+    rpc_list: Arc<RwLock<Vec<Rpc>>>,
+    ttl: u128,
+) {
+    while let Some(first) = batch_rx.recv().await {
+        // Requests can only be merged if they have the same config
+        let mut groups: HashMap<String, Vec<StatusRequest>> = HashMap::new();
+        let mut total = first.signatures.len();
+        groups
+            .entry(first.config.to_string())
+            .or_default()
+            .push(first);
+
+        let deadline = Instant::now() + BATCH_WINDOW;
+        while total < MAX_SIGNATURES {
+            match tokio::time::timeout_at(deadline, batch_rx.recv()).await {
+                Ok(Some(request)) => {
+                    total += request.signatures.len();
+                    groups
+                        .entry(request.config.to_string())
+                        .or_default()
+                        .push(request);
+                }
+                _ => break,
+            }
+        }
+
+        for (_, group) in groups {
+            for chunk in split_chunks(group) {
+                let rpc_list = Arc::clone(&rpc_list);
+                tokio::task::spawn(async move {
+                    send_batch(chunk, &rpc_list, ttl).await;
+                });
+            }
+        }
+    }
+}
+
+// Split requests into chunks that don't go over MAX_SIGNATURES
+fn split_chunks(requests: Vec<StatusRequest>) -> Vec<Vec<StatusRequest>> {
+    let mut chunks = Vec::new();
+    let mut chunk: Vec<StatusRequest> = Vec::new();
+    let mut len = 0;
+
+    for request in requests {
+        if len + request.signatures.len() > MAX_SIGNATURES {
+            chunks.push(std::mem::take(&mut chunk));
+            len = 0;
+        }
+        len += request.signatures.len();
+        chunk.push(request);
+    }
+    if !chunk.is_empty() {
+        chunks.push(chunk);
+    }
+
+    chunks
+}
+
+// Send a merged `getSignatureStatuses` request and hand every caller its part of the result
+async fn send_batch(chunk: Vec<StatusRequest>, rpc_list: &Arc<RwLock<Vec<Rpc>>>, ttl: u128) {
+    let signatures: Vec<Value> = chunk
+        .iter()
+        .flat_map(|request| request.signatures.iter().cloned())
+        .collect();
+
+    let mut params = vec![json!(signatures)];
+    if !chunk[0].config.is_null() {
+        params.push(chunk[0].config.clone());
+    }
+
+    let tx = json!({
+        "id": 1,
+        "jsonrpc": "2.0",
+        "method": "getSignatureStatuses",
+        "params": params,
+    });
+
+    let (rpc, position) = pick(&mut rpc_list.write().unwrap());
+    let result = match position {
+        Some(_) => {
+            match timeout(Duration::from_millis(ttl as u64), rpc.send_request(tx)).await {
+                Ok(Ok(rx)) => serde_json::from_slice::<Value>(&rx).ok(),
+                _ => None,
+            }
+        }
+        None => None,
+    };
+
+    let result = result.and_then(|rx| {
+        let value = rx["result"]["value"].as_array()?.clone();
+        (value.len() == signatures.len()).then(|| (rx["result"]["context"].clone(), value))
+    });
+
+    let (context, value) = match result {
+        Some(result) => result,
+        None => {
+            for request in chunk {
+                let _ = request.reply.send(None);
+            }
+            return;
+        }
+    };
+
+    let mut offset = 0;
+    for request in chunk {
+        let len = request.signatures.len();
+        let _ = request.reply.send(Some(json!({
+            "context": context,
+            "value": value[offset..offset + len],
+        })));
+        offset += len;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_status_request() {
+        let tx = json!({"id": 1, "jsonrpc": "2.0", "method": "getSlot", "params": []});
+        assert!(status_request(&tx).is_none());
+
+        let tx =
+            json!({"id": 1, "jsonrpc": "2.0", "method": "getSignatureStatuses", "params": [[]]});
+        assert!(status_request(&tx).is_none());
+
+        let tx = json!({
+            "id": 1,
+            "jsonrpc": "2.0",
+            "method": "getSignatureStatuses",
+            "params": [["a", "b"], {"searchTransactionHistory": true}]
+        });
+        let (request, _) = status_request(&tx).unwrap();
+        assert_eq!(request.signatures.len(), 2);
+        assert_eq!(request.config, json!({"searchTransactionHistory": true}));
+    }
+
+    #[test]
+    fn test_split_chunks() {
+        let requests: Vec<StatusRequest> = [200, 100, 156, 10]
+            .iter()
+            .map(|&len| {
+                StatusRequest {
+                    signatures: vec![json!("sig"); len],
+                    config: Value::Null,
+                    reply: oneshot::channel().0,
+                }
+            })
+            .collect();
+
+        let chunks = split_chunks(requests);
+        let lens: Vec<usize> = chunks.iter().map(|chunk| chunk.len()).collect();
+        assert_eq!(lens, vec![1, 2, 1]);
+    }
+}
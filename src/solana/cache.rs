@@ -0,0 +1,90 @@
+use serde_json::Value;
+
+// Get the commitment level of a Solana request.
+//
+// The commitment is passed in a config object that is always the last param.
+// If none is specified, RPCs default to `finalized`.
+fn get_commitment(tx: &Value) -> &str {
+    tx["params"]
+        .as_array()
+        .and_then(|params| params.last())
+        .and_then(|config| config["commitment"].as_str())
+        .unwrap_or("finalized")
+}
+
+// Return the slot a Solana request is cached under or None if we shouldn't cache it.
+//
+// Slots above finalized get invalidated when they reorg so they can be cached
+// with `confirmed` commitment. Requests that don't reference a slot can only be
+// cached once they are finalized, so they use slot 0.
+pub fn get_slot_from_request(tx: &Value) -> Option<u64> {
+    let commitment = get_commitment(tx);
+    if commitment == "processed" {
+        return None;
+    }
+
+    match tx["method"].as_str()? {
+        "getGenesisHash" => Some(0),
+        "getBlock" | "getBlockTime" => tx["params"][0].as_u64(),
+        // Only cache ranges with an explicit end slot, else they depend on the head
+        "getBlocks" => tx["params"][1].as_u64(),
+        "getTransaction" if commitment == "finalized" => Some(0),
+        _ => None,
+    }
+}
+
+// Returns true if the result of a Solana request can be cached.
+//
+// Blocks and transactions that don't exist (yet) return null, and skipped slots return errors.
+pub fn cache_solana_result(rx: &[u8]) -> bool {
+    match serde_json::from_slice::<Value>(rx) {
+        Ok(rx) => rx.get("error").is_none() && !rx["result"].is_null(),
+        Err(_) => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_get_slot_from_request() {
+        let tx = json!({"id": 1, "jsonrpc": "2.0", "method": "getBlock", "params": [430]});
+        assert_eq!(get_slot_from_request(&tx), Some(430));
+
+        let tx = json!({"id": 1, "jsonrpc": "2.0", "method": "getBlock", "params": [430, {"commitment": "confirmed"}]});
+        assert_eq!(get_slot_from_request(&tx), Some(430));
+
+        let tx = json!({"id": 1, "jsonrpc": "2.0", "method": "getBlock", "params": [430, {"commitment": "processed"}]});
+        assert_eq!(get_slot_from_request(&tx), None);
+
+        let tx = json!({"id": 1, "jsonrpc": "2.0", "method": "getBlocks", "params": [5]});
+        assert_eq!(get_slot_from_request(&tx), None);
+
+        let tx = json!({"id": 1, "jsonrpc": "2.0", "method": "getBlocks", "params": [5, 10]});
+        assert_eq!(get_slot_from_request(&tx), Some(10));
+
+        let tx = json!({"id": 1, "jsonrpc": "2.0", "method": "getTransaction", "params": ["sig"]});
+        assert_eq!(get_slot_from_request(&tx), Some(0));
+
+        let tx = json!({"id": 1, "jsonrpc": "2.0", "method": "getTransaction", "params": ["sig", {"commitment": "confirmed"}]});
+        assert_eq!(get_slot_from_request(&tx), None);
+
+        let tx = json!({"id": 1, "jsonrpc": "2.0", "method": "getSlot", "params": []});
+        assert_eq!(get_slot_from_request(&tx), None);
+    }
+
+    #[test]
+    fn test_cache_solana_result() {
+        assert!(cache_solana_result(
+            br#"{"jsonrpc":"2.0","id":1,"result":1574721591}"#
+        ));
+        assert!(!cache_solana_result(
+            br#"{"jsonrpc":"2.0","id":1,"result":null}"#
+        ));
+        assert!(!cache_solana_result(
+            br#"{"jsonrpc":"2.0","id":1,"error":{"code":-32007,"message":"Slot 430 was skipped"}}"#
+        ));
+    }
+}
@@ -0,0 +1,44 @@
+use crate::subscriptions::types::{
+    SubscriptionData,
+    SubscriptionKind,
+};
+
+use serde_json::{
+    json,
+    Value,
+};
+use std::sync::Arc;
+
+use tokio::sync::watch;
+use tokio_stream::{
+    wrappers::WatchStream,
+    StreamExt,
+};
+
+// Emit a `finalizedHeads` event every time the finalized block advances
+pub async fn finalized_heads(finalized_rx: watch::Receiver<u64>, sub_data: Arc<SubscriptionData>) {
+    follow_finalized(finalized_rx, |event| {
+        sub_data.broadcast(SubscriptionKind::FinalizedHeads, event)
+    })
+    .await;
+}
+
+// Call `publish` with a `finalizedHeads` event every time the finalized block advances
+pub async fn follow_finalized(finalized_rx: watch::Receiver<u64>, mut publish: impl FnMut(Value)) {
+    let mut last_finalized = 0;
+    let mut finalized_stream = WatchStream::new(finalized_rx.clone());
+
+    while finalized_stream.next().await.is_some() {
+        let finalized = *finalized_rx.borrow();
+
+        // Only notify when finality moves forward
+        if finalized <= last_finalized {
+            continue;
+        }
+        last_finalized = finalized;
+
+        publish(json!({
+            "number": format!("{:#x}", finalized),
+        }));
+    }
+}
@@ -0,0 +1,99 @@
+use crate::subscriptions::types::{
+    SubscriptionData,
+    SubscriptionKind,
+};
+
+use serde_json::{
+    json,
+    Value,
+};
+
+// Build a JSON-RPC error response for subscription methods
+fn subscription_error(id: &Value, message: &str) -> Value {
+    json!({
+        "id": id,
+        "jsonrpc": "2.0",
+        "error": {
+            "code": -32602,
+            "message": message,
+        },
+    })
+}
+
+// Handle subscription methods over HTTP.
+//
+// Since plain HTTP can't push, subscriptions work similarly to filters:
+// `blutgang_subscribe` returns an id, and `blutgang_getSubscriptionChanges`
+// returns all events that happened since the last poll.
+//
+// Subscriptions belong to `client`, which can only have so many.
+//
+// Returns None if `tx` is not a subscription method.
+pub fn execute_subscription_method(
+    tx: &Value,
+    sub_data: &SubscriptionData,
+    client: &str,
+) -> Option<Value> {
+    let id = &tx["id"];
+    let param = tx["params"][0].as_str();
+
+    let result = match tx["method"].as_str()? {
+        "blutgang_subscribe" => {
+            let kind = match param.and_then(SubscriptionKind::from_param) {
+                Some(kind) => kind,
+                None => return Some(subscription_error(id, "Unsupported subscription type")),
+            };
+            match sub_data.subscribe(kind, client) {
+                Some(sub_id) => json!(sub_id),
+                None => return Some(subscription_error(id, "Too many subscriptions")),
+            }
+        }
+        "blutgang_getSubscriptionChanges" => {
+            match param.and_then(|sub_id| sub_data.poll(sub_id)) {
+                Some(events) => json!(events),
+                None => return Some(subscription_error(id, "Subscription not found")),
+            }
+        }
+        "blutgang_unsubscribe" => json!(param.is_some_and(|sub_id| sub_data.unsubscribe(sub_id))),
+        _ => return None,
+    };
+
+    Some(json!({
+        "id": id,
+        "jsonrpc": "2.0",
+        "result": result,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_execute_subscription_method() {
+        let sub_data = SubscriptionData::new();
+
+        let tx = json!({"id": 1, "jsonrpc": "2.0", "method": "eth_blockNumber", "params": []});
+        assert!(execute_subscription_method(&tx, &sub_data, "client").is_none());
+
+        let tx =
+            json!({"id": 1, "jsonrpc": "2.0", "method": "blutgang_subscribe", "params": ["nope"]});
+        let rx = execute_subscription_method(&tx, &sub_data, "client").unwrap();
+        assert!(rx["error"].is_object());
+
+        let tx = json!({"id": 1, "jsonrpc": "2.0", "method": "blutgang_subscribe", "params": ["finalizedHeads"]});
+        let rx = execute_subscription_method(&tx, &sub_data, "client").unwrap();
+        let sub_id = rx["result"].as_str().unwrap().to_string();
+
+        sub_data.broadcast(SubscriptionKind::FinalizedHeads, json!({"number": "0x10"}));
+
+        let tx = json!({"id": 2, "jsonrpc": "2.0", "method": "blutgang_getSubscriptionChanges", "params": [sub_id]});
+        let rx = execute_subscription_method(&tx, &sub_data, "client").unwrap();
+        assert_eq!(rx["id"], 2);
+        assert_eq!(rx["result"][0]["number"], "0x10");
+
+        let tx = json!({"id": 3, "jsonrpc": "2.0", "method": "blutgang_unsubscribe", "params": [sub_id]});
+        let rx = execute_subscription_method(&tx, &sub_data, "client").unwrap();
+        assert_eq!(rx["result"], true);
+    }
+}
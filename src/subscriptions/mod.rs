@@ -0,0 +1,4 @@
+pub mod finalized;
+pub mod methods;
+pub mod sse;
+pub mod types;
@@ -0,0 +1,428 @@
+// Server-Sent Events stream of new heads and logs.
+//
+// `GET /events?streams=newHeads,logs&filter={...}` streams events over a plain HTTP
+// response, which gets through proxies and serverless platforms that block WebSockets.
+// `filter` is an `eth_getLogs` style address/topics filter, URL encoded.
+//
+// Every new block is fetched once and shared with all streams. Logs are fetched for
+// the whole block, only if a stream wants them, and filtered for each stream here.
+use crate::{
+    rpc::types::first_result,
+    Rpc,
+};
+
+use std::{
+    sync::{
+        atomic::{
+            AtomicUsize,
+            Ordering,
+        },
+        Arc,
+        RwLock,
+    },
+    time::Duration,
+};
+
+use hyper::body::Bytes;
+use serde_json::{
+    json,
+    Value,
+};
+use tokio::{
+    sync::{
+        broadcast,
+        mpsc,
+        watch,
+    },
+    time::interval,
+};
+
+pub const PATH: &str = "/events";
+
+// Comment we send every so often so proxies don't close idle streams
+const KEEPALIVE: Duration = Duration::from_secs(15);
+
+// Blocks a slow stream can fall behind by before it misses some
+const BACKLOG: usize = 16;
+
+// Blocks we publish at most when the head jumps ahead
+const MAX_BLOCKS: u64 = 8;
+
+// Which logs a stream wants, same as the filter of `eth_getLogs`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LogFilter {
+    // Empty for any address
+    addresses: Vec<String>,
+    // None for any topic at that position
+    topics: Vec<Option<Vec<String>>>,
+}
+
+// String or array of strings, lowercased
+fn one_or_many(value: &Value, what: &str) -> Result<Vec<String>, String> {
+    match value {
+        Value::String(value) => Ok(vec![value.to_lowercase()]),
+        Value::Array(values) => {
+            values
+                .iter()
+                .map(|value| {
+                    value
+                        .as_str()
+                        .map(str::to_lowercase)
+                        .ok_or_else(|| format!("invalid {}", what))
+                })
+                .collect()
+        }
+        _ => Err(format!("invalid {}", what)),
+    }
+}
+
+impl LogFilter {
+    pub fn from_value(filter: &Value) -> Result<Self, String> {
+        let addresses = match &filter["address"] {
+            Value::Null => Vec::new(),
+            address => one_or_many(address, "address")?,
+        };
+        let topics = match &filter["topics"] {
+            Value::Null => Vec::new(),
+            Value::Array(topics) => {
+                topics
+                    .iter()
+                    .map(|topic| {
+                        match topic {
+                            Value::Null => Ok(None),
+                            topic => one_or_many(topic, "topic").map(Some),
+                        }
+                    })
+                    .collect::<Result<_, _>>()?
+            }
+            _ => return Err("invalid topics".to_string()),
+        };
+
+        Ok(Self { addresses, topics })
+    }
+
+    pub fn matches(&self, log: &Value) -> bool {
+        let address = log["address"].as_str().unwrap_or_default().to_lowercase();
+        if !self.addresses.is_empty() && !self.addresses.contains(&address) {
+            return false;
+        }
+
+        let topics = log["topics"]
+            .as_array()
+            .map(Vec::as_slice)
+            .unwrap_or_default();
+        self.topics.iter().enumerate().all(|(position, wanted)| {
+            match wanted {
+                None => true,
+                Some(wanted) => {
+                    topics
+                        .get(position)
+                        .and_then(Value::as_str)
+                        .is_some_and(|topic| wanted.contains(&topic.to_lowercase()))
+                }
+            }
+        })
+    }
+}
+
+// What a stream wants to get
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct StreamRequest {
+    pub heads: bool,
+    // None if the stream doesn't want logs
+    pub logs: Option<LogFilter>,
+}
+
+pub fn parse_query(query: Option<&str>) -> Result<StreamRequest, String> {
+    let mut streams = "newHeads".to_string();
+    let mut filter = LogFilter::default();
+
+    for (key, value) in url::form_urlencoded::parse(query.unwrap_or_default().as_bytes()) {
+        match key.as_ref() {
+            "streams" => streams = value.into_owned(),
+            "filter" => {
+                let value: Value =
+                    serde_json::from_str(&value).map_err(|_| "filter is not JSON".to_string())?;
+                filter = LogFilter::from_value(&value)?;
+            }
+            key => return Err(format!("unknown parameter: {}", key)),
+        }
+    }
+
+    let mut request = StreamRequest::default();
+    for stream in streams.split(',') {
+        match stream {
+            "newHeads" => request.heads = true,
+            "logs" => request.logs = Some(filter.clone()),
+            stream => return Err(format!("unknown stream: {}", stream)),
+        }
+    }
+
+    Ok(request)
+}
+
+// A new block, and all of its logs if a stream wanted them
+#[derive(Debug)]
+pub struct BlockEvents {
+    pub head: Value,
+    pub logs: Option<Vec<Value>>,
+}
+
+fn event(name: &str, id: &Value, data: &Value) -> Bytes {
+    match id.as_str() {
+        Some(id) => format!("event: {}\nid: {}\ndata: {}\n\n", name, id, data).into(),
+        None => format!("event: {}\ndata: {}\n\n", name, data).into(),
+    }
+}
+
+impl StreamRequest {
+    // Events of `block` this stream wants, already formatted
+    fn events(&self, block: &BlockEvents) -> Vec<Bytes> {
+        let mut events = Vec::new();
+        let number = &block.head["number"];
+
+        if self.heads {
+            events.push(event("newHeads", number, &block.head));
+        }
+        if let (Some(filter), Some(logs)) = (&self.logs, &block.logs) {
+            for log in logs.iter().filter(|log| filter.matches(log)) {
+                events.push(event("logs", number, log));
+            }
+        }
+
+        events
+    }
+}
+
+// Streams that want logs, so we only fetch them if needed
+#[derive(Debug)]
+struct LogStream(Arc<AtomicUsize>);
+
+impl Drop for LogStream {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+#[derive(Debug)]
+pub struct EventHub {
+    blocks: broadcast::Sender<Arc<BlockEvents>>,
+    log_streams: Arc<AtomicUsize>,
+}
+
+impl Default for EventHub {
+    fn default() -> Self {
+        Self {
+            blocks: broadcast::channel(BACKLOG).0,
+            log_streams: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+}
+
+impl EventHub {
+    pub fn publish(&self, block: BlockEvents) {
+        let _ = self.blocks.send(Arc::new(block));
+    }
+
+    fn has_streams(&self) -> bool {
+        self.blocks.receiver_count() > 0
+    }
+
+    fn wants_logs(&self) -> bool {
+        self.log_streams.load(Ordering::Relaxed) > 0
+    }
+
+    // Start a stream, events come out of the returned channel until it's dropped
+    pub fn stream(&self, request: StreamRequest) -> mpsc::Receiver<Bytes> {
+        let mut blocks = self.blocks.subscribe();
+        let log_stream = request.logs.is_some().then(|| {
+            self.log_streams.fetch_add(1, Ordering::Relaxed);
+            LogStream(Arc::clone(&self.log_streams))
+        });
+        let (events_tx, events_rx) = mpsc::channel(BACKLOG);
+
+        tokio::task::spawn(async move {
+            let _log_stream = log_stream;
+            let mut keepalive = interval(KEEPALIVE);
+
+            loop {
+                let events = tokio::select! {
+                    block = blocks.recv() => {
+                        match block {
+                            Ok(block) => request.events(&block),
+                            Err(broadcast::error::RecvError::Lagged(missed)) => {
+                                vec![format!(": missed {} blocks\n\n", missed).into()]
+                            }
+                            Err(broadcast::error::RecvError::Closed) => return,
+                        }
+                    }
+                    _ = keepalive.tick() => vec![Bytes::from_static(b": keepalive\n\n")],
+                    // The client went away
+                    _ = events_tx.closed() => return,
+                };
+
+                for event in events {
+                    if events_tx.send(event).await.is_err() {
+                        return;
+                    }
+                }
+            }
+        });
+
+        events_rx
+    }
+}
+
+// Fetch every new block, and its logs if wanted, and publish them to the streams
+pub async fn publish_events(
+    hub: Arc<EventHub>,
+    rpc_list: Arc<RwLock<Vec<Rpc>>>,
+    mut blocknum_rx: watch::Receiver<u64>,
+    ttl: Duration,
+) {
+    let mut last = *blocknum_rx.borrow();
+    while blocknum_rx.changed().await.is_ok() {
+        let head = *blocknum_rx.borrow_and_update();
+        if head <= last || !hub.has_streams() {
+            last = head;
+            continue;
+        }
+
+        let first = (last + 1).max(head.saturating_sub(MAX_BLOCKS - 1));
+        for number in first..=head {
+            let tx = json!({
+                "id": 1,
+                "jsonrpc": "2.0",
+                "method": "eth_getBlockByNumber",
+                "params": [format!("{:#x}", number), false],
+            });
+            let mut block = match first_result(&rpc_list, &tx, ttl).await {
+                Some(block) => block,
+                None => {
+                    println!(
+                        "\x1b[93mWrn:\x1b[0m Could not get block {} for event streams!",
+                        number
+                    );
+                    continue;
+                }
+            };
+            // Same as `newHeads`, just the header
+            block
+                .as_object_mut()
+                .map(|block| block.remove("transactions"));
+
+            let logs = match hub.wants_logs() {
+                true => {
+                    let tx = json!({
+                        "id": 1,
+                        "jsonrpc": "2.0",
+                        "method": "eth_getLogs",
+                        "params": [{"blockHash": block["hash"]}],
+                    });
+                    match first_result(&rpc_list, &tx, ttl).await {
+                        Some(Value::Array(logs)) => Some(logs),
+                        _ => {
+                            println!(
+                                "\x1b[93mWrn:\x1b[0m Could not get the logs of block {} for event streams!",
+                                number
+                            );
+                            None
+                        }
+                    }
+                }
+                false => None,
+            };
+
+            hub.publish(BlockEvents { head: block, logs });
+        }
+
+        last = head;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TRANSFER: &str = "0xddf252ad1be2c89b69c2b068fc378daa952ba7f163c4a11628f55a4df523b3ef";
+
+    fn log(address: &str, topics: &[&str]) -> Value {
+        json!({"address": address, "topics": topics, "data": "0x"})
+    }
+
+    #[test]
+    fn test_log_filter() {
+        let filter = LogFilter::from_value(&json!({
+            "address": ["0xAA", "0xbb"],
+            "topics": [TRANSFER, null, ["0x01", "0x02"]],
+        }))
+        .unwrap();
+
+        assert!(filter.matches(&log("0xaa", &[TRANSFER, "0x09", "0x02"])));
+        assert!(!filter.matches(&log("0xcc", &[TRANSFER, "0x09", "0x02"])));
+        assert!(!filter.matches(&log("0xbb", &[TRANSFER, "0x09", "0x03"])));
+        // Not enough topics
+        assert!(!filter.matches(&log("0xbb", &[TRANSFER])));
+
+        assert!(LogFilter::default().matches(&log("0xcc", &[])));
+        assert!(LogFilter::from_value(&json!({"topics": "0x01"})).is_err());
+    }
+
+    #[test]
+    fn test_parse_query() {
+        assert_eq!(
+            parse_query(None),
+            Ok(StreamRequest {
+                heads: true,
+                logs: None
+            })
+        );
+
+        let request =
+            parse_query(Some("streams=logs&filter=%7B%22address%22%3A%220xaa%22%7D")).unwrap();
+        assert!(!request.heads);
+        assert!(request.logs.unwrap().matches(&log("0xAA", &[])));
+
+        assert!(parse_query(Some("streams=pendingTransactions")).is_err());
+        assert!(parse_query(Some("filter=nope")).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_stream() {
+        let hub = EventHub::default();
+        let mut heads = hub.stream(StreamRequest {
+            heads: true,
+            logs: None,
+        });
+        let mut logs = hub.stream(parse_query(Some("streams=logs")).unwrap());
+        assert!(hub.has_streams());
+        assert!(hub.wants_logs());
+
+        // The first tick of the keepalive is right away
+        assert_eq!(heads.recv().await.unwrap(), ": keepalive\n\n");
+        assert_eq!(logs.recv().await.unwrap(), ": keepalive\n\n");
+
+        hub.publish(BlockEvents {
+            head: json!({"number": "0x10"}),
+            logs: Some(vec![log("0xaa", &[])]),
+        });
+        assert_eq!(
+            heads.recv().await.unwrap(),
+            "event: newHeads\nid: 0x10\ndata: {\"number\":\"0x10\"}\n\n"
+        );
+        assert_eq!(
+            logs.recv().await.unwrap(),
+            "event: logs\nid: 0x10\ndata: {\"address\":\"0xaa\",\"data\":\"0x\",\"topics\":[]}\n\n"
+        );
+
+        // Streams clean up after themselves once the client is gone
+        drop(logs);
+        hub.publish(BlockEvents {
+            head: json!({"number": "0x11"}),
+            logs: None,
+        });
+        heads.recv().await.unwrap();
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert!(!hub.wants_logs());
+    }
+}
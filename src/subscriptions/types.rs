@@ -0,0 +1,253 @@
+use serde_json::Value;
+
+use std::{
+    collections::{
+        HashMap,
+        VecDeque,
+    },
+    sync::{
+        atomic::{
+            AtomicU64,
+            Ordering,
+        },
+        RwLock,
+    },
+    time::{
+        Duration,
+        Instant,
+        SystemTime,
+        UNIX_EPOCH,
+    },
+};
+
+// Maximum amount of undelivered events we hold per subscription.
+// Once full, we drop the oldest ones first.
+const MAX_PENDING_EVENTS: usize = 1024;
+
+// Subscriptions that haven't been polled for this long get removed,
+// same as filters on most nodes.
+const SUBSCRIPTION_TIMEOUT: Duration = Duration::from_secs(300);
+
+// Subscriptions a single client can have, and all clients together
+const MAX_CLIENT_SUBSCRIPTIONS: usize = 64;
+const MAX_SUBSCRIPTIONS: usize = 10_000;
+
+static NONCE: AtomicU64 = AtomicU64::new(0);
+
+// New id for a subscription. Hashed so they can't be guessed and drained by other clients.
+pub fn subscription_id() -> String {
+    let nonce = NONCE.fetch_add(1, Ordering::Relaxed);
+    let time = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+    let hash = blake3::hash(format!("{}{}", nonce, time).as_bytes());
+    format!("0x{}", &hash.to_hex()[..32])
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SubscriptionKind {
+    FinalizedHeads,
+}
+
+impl SubscriptionKind {
+    pub fn from_param(param: &str) -> Option<Self> {
+        match param {
+            "finalizedHeads" => Some(SubscriptionKind::FinalizedHeads),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Subscription {
+    kind: SubscriptionKind,
+    // API key or IP of the client that subscribed
+    client: String,
+    events: VecDeque<Value>,
+    last_polled: Instant,
+}
+
+// Holds all active subscriptions and the events they have yet to receive
+#[derive(Debug, Default)]
+pub struct SubscriptionData {
+    subscriptions: RwLock<HashMap<String, Subscription>>,
+}
+
+impl SubscriptionData {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Create a new subscription for `client` and return its id.
+    //
+    // Returns None if the client or everyone together already have too many.
+    pub fn subscribe(&self, kind: SubscriptionKind, client: &str) -> Option<String> {
+        let mut subscriptions = self.subscriptions.write().unwrap();
+        subscriptions.retain(|_, sub| sub.last_polled.elapsed() < SUBSCRIPTION_TIMEOUT);
+
+        let subscribed = subscriptions
+            .values()
+            .filter(|sub| sub.client == client)
+            .count();
+        if subscriptions.len() >= MAX_SUBSCRIPTIONS || subscribed >= MAX_CLIENT_SUBSCRIPTIONS {
+            return None;
+        }
+
+        let id = subscription_id();
+        subscriptions.insert(
+            id.clone(),
+            Subscription {
+                kind,
+                client: client.to_string(),
+                events: VecDeque::new(),
+                last_polled: Instant::now(),
+            },
+        );
+
+        Some(id)
+    }
+
+    // Remove subscription, returns false if it didn't exist
+    pub fn unsubscribe(&self, id: &str) -> bool {
+        self.subscriptions.write().unwrap().remove(id).is_some()
+    }
+
+    // Return all events since the last poll, None if the subscription doesn't exist
+    pub fn poll(&self, id: &str) -> Option<Vec<Value>> {
+        let mut subscriptions = self.subscriptions.write().unwrap();
+        let subscription = subscriptions
+            .get_mut(id)
+            .filter(|sub| sub.last_polled.elapsed() < SUBSCRIPTION_TIMEOUT)?;
+
+        subscription.last_polled = Instant::now();
+        Some(subscription.events.drain(..).collect())
+    }
+
+    // Push an event to every subscription of `kind`.
+    //
+    // Also removes subscriptions nobody is polling anymore.
+    pub fn broadcast(&self, kind: SubscriptionKind, event: Value) {
+        let mut subscriptions = self.subscriptions.write().unwrap();
+        subscriptions.retain(|_, sub| sub.last_polled.elapsed() < SUBSCRIPTION_TIMEOUT);
+
+        for subscription in subscriptions.values_mut() {
+            if subscription.kind != kind {
+                continue;
+            }
+
+            if subscription.events.len() >= MAX_PENDING_EVENTS {
+                subscription.events.pop_front();
+            }
+            subscription.events.push_back(event.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_subscribe_poll_unsubscribe() {
+        let sub_data = SubscriptionData::new();
+        let id = sub_data
+            .subscribe(SubscriptionKind::FinalizedHeads, "client")
+            .unwrap();
+        let other = sub_data
+            .subscribe(SubscriptionKind::FinalizedHeads, "client")
+            .unwrap();
+        assert_ne!(id, other);
+
+        sub_data.broadcast(SubscriptionKind::FinalizedHeads, json!({"number": "0x1"}));
+        sub_data.broadcast(SubscriptionKind::FinalizedHeads, json!({"number": "0x2"}));
+
+        let events = sub_data.poll(&id).unwrap();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[1]["number"], "0x2");
+
+        // Polling drains the events
+        assert!(sub_data.poll(&id).unwrap().is_empty());
+        // Other subscriptions are unaffected
+        assert_eq!(sub_data.poll(&other).unwrap().len(), 2);
+
+        assert!(sub_data.unsubscribe(&id));
+        assert!(!sub_data.unsubscribe(&id));
+        assert!(sub_data.poll(&id).is_none());
+    }
+
+    #[test]
+    fn test_pending_events_capped() {
+        let sub_data = SubscriptionData::new();
+        let id = sub_data
+            .subscribe(SubscriptionKind::FinalizedHeads, "client")
+            .unwrap();
+
+        for i in 0..MAX_PENDING_EVENTS + 10 {
+            sub_data.broadcast(SubscriptionKind::FinalizedHeads, json!(i));
+        }
+
+        let events = sub_data.poll(&id).unwrap();
+        assert_eq!(events.len(), MAX_PENDING_EVENTS);
+        assert_eq!(events[0], json!(10));
+    }
+
+    #[test]
+    fn test_subscriptions_capped() {
+        let sub_data = SubscriptionData::new();
+        for _ in 0..MAX_CLIENT_SUBSCRIPTIONS {
+            assert!(sub_data
+                .subscribe(SubscriptionKind::FinalizedHeads, "client")
+                .is_some());
+        }
+        assert!(sub_data
+            .subscribe(SubscriptionKind::FinalizedHeads, "client")
+            .is_none());
+
+        // Others can still subscribe
+        let id = sub_data
+            .subscribe(SubscriptionKind::FinalizedHeads, "other")
+            .unwrap();
+        assert!(sub_data.unsubscribe(&id));
+
+        // Until everyone together hits the limit
+        let mut subscriptions = sub_data.subscriptions.write().unwrap();
+        for i in MAX_CLIENT_SUBSCRIPTIONS..MAX_SUBSCRIPTIONS {
+            let subscription = Subscription {
+                kind: SubscriptionKind::FinalizedHeads,
+                client: format!("client{}", i),
+                events: VecDeque::new(),
+                last_polled: Instant::now(),
+            };
+            subscriptions.insert(subscription_id(), subscription);
+        }
+        drop(subscriptions);
+        assert!(sub_data
+            .subscribe(SubscriptionKind::FinalizedHeads, "other")
+            .is_none());
+    }
+
+    #[test]
+    fn test_stale_subscriptions_pruned() {
+        let sub_data = SubscriptionData::new();
+        let id = sub_data
+            .subscribe(SubscriptionKind::FinalizedHeads, "client")
+            .unwrap();
+        sub_data
+            .subscriptions
+            .write()
+            .unwrap()
+            .get_mut(&id)
+            .unwrap()
+            .last_polled = Instant::now().checked_sub(SUBSCRIPTION_TIMEOUT).unwrap();
+
+        // Gone as soon as it times out, and doesn't count towards the limit anymore
+        assert!(sub_data.poll(&id).is_none());
+        let other = sub_data
+            .subscribe(SubscriptionKind::FinalizedHeads, "client")
+            .unwrap();
+        assert_eq!(sub_data.subscriptions.read().unwrap().len(), 1);
+        assert!(sub_data.poll(&other).is_some());
+    }
+}
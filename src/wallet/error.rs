@@ -0,0 +1,46 @@
+// Errors
+use crate::rpc::error::RpcError;
+use std::error::Error;
+
+#[derive(Debug, PartialEq)]
+pub enum WalletError {
+    InvalidTransaction(String),
+    UnknownAccount(String),
+    // An RPC we needed to fill in the transaction failed
+    Upstream(String),
+    Signer(String),
+}
+
+impl WalletError {
+    // JSON-RPC error code we respond with
+    pub fn code(&self) -> i64 {
+        match self {
+            WalletError::InvalidTransaction(_) => -32602,
+            WalletError::UnknownAccount(_) => -32000,
+            WalletError::Upstream(_) | WalletError::Signer(_) => -32603,
+        }
+    }
+}
+
+impl std::fmt::Display for WalletError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            WalletError::InvalidTransaction(reason) => write!(f, "Invalid transaction: {}", reason),
+            WalletError::UnknownAccount(account) => {
+                write!(f, "No key for {} in the wallet", account)
+            }
+            WalletError::Upstream(reason) => {
+                write!(f, "Could not fill in the transaction: {}", reason)
+            }
+            WalletError::Signer(reason) => write!(f, "Could not sign the transaction: {}", reason),
+        }
+    }
+}
+
+impl Error for WalletError {}
+
+impl From<RpcError> for WalletError {
+    fn from(error: RpcError) -> Self {
+        WalletError::Signer(error.to_string())
+    }
+}
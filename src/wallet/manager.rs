@@ -0,0 +1,436 @@
+// Resubmitting transactions with higher fees until they're included.
+//
+// Only transactions that ask for it are managed. Ones the wallet sends with
+// `{"resubmit": true}` get their fees bumped and are signed again. We can't sign raw
+// transactions, so clients sending them have to give us replacements, signed with the
+// same nonce and higher fees, as `{"replacements": [..]}`. Every `interval` a transaction
+// isn't included, its next version is sent. Any version getting included is fine.
+use crate::{
+    balancer::quorum::quorum_rpcs,
+    config::types::TxManagerSettings,
+    wallet::types::{
+        quantity,
+        to_quantity,
+        Upstream,
+        Wallet,
+    },
+    Rpc,
+};
+
+use std::{
+    collections::VecDeque,
+    sync::{
+        Arc,
+        Mutex,
+        RwLock,
+    },
+    time::{
+        Duration,
+        Instant,
+    },
+};
+
+use serde_json::{
+    json,
+    Value,
+};
+use tokio::time::{
+    sleep,
+    timeout,
+};
+
+// How often we check if managed transactions were included
+const CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Resubmission {
+    // Bumped and signed again by the wallet, this is the last version we sent
+    Signed(Value),
+    // Signed by the client, sent in order
+    Replacements(VecDeque<String>),
+}
+
+#[derive(Debug)]
+struct Managed {
+    // Every version we sent
+    hashes: Vec<String>,
+    sent: Instant,
+    bumps: u32,
+    resubmission: Resubmission,
+}
+
+// Returns true if `tx` asks the wallet to resubmit it until it's included
+pub fn wants_resubmit(tx: &Value) -> bool {
+    tx["params"][1]["resubmit"] == true
+}
+
+// Take the replacements out of an `eth_sendRawTransaction`, the RPCs don't know about them
+pub fn take_replacements(tx: &mut Value) -> Option<VecDeque<String>> {
+    let replacements: VecDeque<String> = tx["params"][1]["replacements"]
+        .as_array()?
+        .iter()
+        .filter_map(|raw| raw.as_str().map(str::to_string))
+        .collect();
+    tx["params"].as_array_mut()?.truncate(1);
+
+    Some(replacements)
+}
+
+// `filled` with its fees raised by `percent`, None if they can't go up or would pass `max_fee`
+pub fn bump_fees(filled: &Value, percent: u64, max_fee: Option<u64>) -> Option<Value> {
+    // Rounded up, so small fees still go up
+    let bump = |fee: u64| {
+        fee.checked_mul(100 + percent)
+            .map(|fee| fee / 100 + u64::from(fee % 100 != 0))
+    };
+    let allowed = |fee: u64| max_fee.map_or(true, |max_fee| fee <= max_fee);
+
+    let mut bumped = filled.clone();
+    match quantity(&filled["maxFeePerGas"]) {
+        Some(fee_cap) => {
+            let new_cap = bump(fee_cap).filter(|cap| *cap > fee_cap && allowed(*cap))?;
+            let tip = quantity(&filled["maxPriorityFeePerGas"]).unwrap_or(0);
+            bumped["maxFeePerGas"] = to_quantity(new_cap);
+            bumped["maxPriorityFeePerGas"] = to_quantity(bump(tip)?.min(new_cap));
+        }
+        None => {
+            let gas_price = quantity(&filled["gasPrice"])?;
+            let new_price =
+                bump(gas_price).filter(|price| *price > gas_price && allowed(*price))?;
+            bumped["gasPrice"] = to_quantity(new_price);
+        }
+    }
+
+    Some(bumped)
+}
+
+#[derive(Debug)]
+pub struct TxManager {
+    settings: TxManagerSettings,
+    managed: Mutex<Vec<Managed>>,
+}
+
+impl TxManager {
+    pub fn new(settings: TxManagerSettings) -> Self {
+        Self {
+            settings,
+            managed: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub fn track(&self, hash: &str, resubmission: Resubmission) {
+        self.managed.lock().unwrap().push(Managed {
+            hashes: vec![hash.to_lowercase()],
+            sent: Instant::now(),
+            bumps: 0,
+            resubmission,
+        });
+    }
+
+    // Track the transaction an `eth_sendRawTransaction` response is for
+    pub fn track_sent(&self, rx: &[u8], replacements: VecDeque<String>) {
+        let rx: Value = match serde_json::from_slice(rx) {
+            Ok(rx) => rx,
+            Err(_) => return,
+        };
+        if let Some(hash) = rx["result"].as_str() {
+            self.track(hash, Resubmission::Replacements(replacements));
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.managed.lock().unwrap().len()
+    }
+
+    // Send the next version of `managed`, returns its hash
+    async fn resubmit<U: Upstream>(
+        &self,
+        managed: &mut Managed,
+        upstream: &U,
+        wallet: Option<&Wallet>,
+    ) -> Result<Option<String>, String> {
+        let raw = match &mut managed.resubmission {
+            Resubmission::Signed(filled) => {
+                let wallet = wallet.ok_or("the wallet is disabled")?;
+                let bumped = match bump_fees(
+                    filled,
+                    self.settings.bump_percent,
+                    self.settings.max_fee_per_gas,
+                ) {
+                    Some(bumped) => bumped,
+                    None => return Ok(None),
+                };
+                let raw = wallet.sign(&bumped).await.map_err(|err| err.to_string())?;
+                *filled = bumped;
+                raw
+            }
+            Resubmission::Replacements(replacements) => {
+                match replacements.pop_front() {
+                    Some(raw) => raw,
+                    None => return Ok(None),
+                }
+            }
+        };
+
+        let hash = upstream
+            .call("eth_sendRawTransaction", json!([raw]))
+            .await?;
+        Ok(hash.as_str().map(str::to_lowercase))
+    }
+
+    // Check on every managed transaction, sending the next version of the ones that
+    // weren't included for too long
+    pub async fn tick<U: Upstream>(&self, upstream: &U, wallet: Option<&Wallet>, now: Instant) {
+        let managed = std::mem::take(&mut *self.managed.lock().unwrap());
+        let mut kept = Vec::with_capacity(managed.len());
+
+        for mut tx in managed {
+            if included(&tx.hashes, upstream).await {
+                continue;
+            }
+            if now.duration_since(tx.sent) < self.settings.interval {
+                kept.push(tx);
+                continue;
+            }
+            if tx.bumps >= self.settings.max_bumps {
+                println!(
+                    "\x1b[93mWrn:\x1b[0m Giving up on {} after {} replacements.",
+                    tx.hashes[0], tx.bumps
+                );
+                continue;
+            }
+
+            match self.resubmit(&mut tx, upstream, wallet).await {
+                Ok(Some(hash)) => {
+                    tx.hashes.push(hash);
+                }
+                Ok(None) => {
+                    println!(
+                        "\x1b[93mWrn:\x1b[0m Can't replace {} anymore, giving up on it.",
+                        tx.hashes[0]
+                    );
+                    continue;
+                }
+                // Something else with the same nonce made it in
+                Err(err) if err.contains("nonce too low") => continue,
+                Err(err) => {
+                    println!(
+                        "\x1b[93mWrn:\x1b[0m Could not replace {}: {}",
+                        tx.hashes[0], err
+                    );
+                }
+            }
+            // Failed attempts count too, so we don't keep retrying forever
+            tx.bumps += 1;
+            tx.sent = now;
+            kept.push(tx);
+        }
+
+        // Transactions tracked while we were busy are in there already
+        self.managed.lock().unwrap().extend(kept);
+    }
+}
+
+// Returns true if any of `hashes` has a receipt
+async fn included<U: Upstream>(hashes: &[String], upstream: &U) -> bool {
+    for hash in hashes {
+        if let Ok(receipt) = upstream
+            .call("eth_getTransactionReceipt", json!([hash]))
+            .await
+        {
+            if !receipt.is_null() {
+                return true;
+            }
+        }
+    }
+    false
+}
+
+// Talks straight to the RPCs, fastest first, since we're not handling a request
+struct RpcUpstream {
+    rpc_list: Arc<RwLock<Vec<Rpc>>>,
+    ttl: Duration,
+}
+
+impl Upstream for RpcUpstream {
+    async fn call(&self, method: &str, params: Value) -> Result<Value, String> {
+        let tx = json!({"id": 1, "jsonrpc": "2.0", "method": method, "params": params});
+        let rpcs = quorum_rpcs(&self.rpc_list.read().unwrap());
+
+        for rpc in rpcs {
+            let rx = match timeout(self.ttl, rpc.send_request(tx.clone())).await {
+                Ok(Ok(rx)) => rx,
+                _ => continue,
+            };
+            let mut rx: Value = match serde_json::from_slice(&rx) {
+                Ok(rx) => rx,
+                Err(_) => continue,
+            };
+
+            return match rx["error"].is_null() {
+                true => Ok(rx["result"].take()),
+                false => {
+                    Err(rx["error"]["message"]
+                        .as_str()
+                        .unwrap_or("unknown error")
+                        .to_string())
+                }
+            };
+        }
+        Err("no RPC available".to_string())
+    }
+}
+
+// Keep managed transactions going until they're included or we give up on them
+pub async fn manage_transactions(
+    manager: Arc<TxManager>,
+    wallet: Option<Arc<Wallet>>,
+    rpc_list: Arc<RwLock<Vec<Rpc>>>,
+    ttl: Duration,
+) {
+    let upstream = RpcUpstream { rpc_list, ttl };
+    loop {
+        sleep(CHECK_INTERVAL).await;
+        if manager.len() == 0 {
+            continue;
+        }
+        manager
+            .tick(&upstream, wallet.as_deref(), Instant::now())
+            .await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    // Has receipts for `included`, and remembers what was sent
+    #[derive(Default)]
+    struct MockUpstream {
+        included: Mutex<HashSet<String>>,
+        sent: Mutex<Vec<String>>,
+    }
+
+    impl Upstream for MockUpstream {
+        async fn call(&self, method: &str, params: Value) -> Result<Value, String> {
+            let param = params[0].as_str().unwrap_or_default().to_string();
+            match method {
+                "eth_getTransactionReceipt" => {
+                    match self.included.lock().unwrap().contains(&param) {
+                        true => Ok(json!({"status": "0x1"})),
+                        false => Ok(Value::Null),
+                    }
+                }
+                "eth_sendRawTransaction" => {
+                    self.sent.lock().unwrap().push(param.clone());
+                    Ok(json!(format!("{}hash", param)))
+                }
+                _ => Err("unexpected method".to_string()),
+            }
+        }
+    }
+
+    fn manager() -> TxManager {
+        TxManager::new(TxManagerSettings {
+            enabled: true,
+            interval: Duration::from_secs(10),
+            max_bumps: 2,
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn test_bump_fees() {
+        let filled = json!({"maxFeePerGas": "0x64", "maxPriorityFeePerGas": "0xa", "nonce": "0x1"});
+        let bumped = bump_fees(&filled, 15, None).unwrap();
+        assert_eq!(bumped["maxFeePerGas"], "0x73");
+        // 11.5 rounds up
+        assert_eq!(bumped["maxPriorityFeePerGas"], "0xc");
+        assert_eq!(bumped["nonce"], "0x1");
+        assert_eq!(bump_fees(&filled, 15, Some(0x70)), None);
+
+        let bumped = bump_fees(&json!({"gasPrice": "0x64"}), 10, None).unwrap();
+        assert_eq!(bumped["gasPrice"], "0x6e");
+
+        // Nothing to bump
+        assert_eq!(bump_fees(&json!({"gasPrice": "0x0"}), 10, None), None);
+        assert_eq!(bump_fees(&json!({}), 10, None), None);
+    }
+
+    #[test]
+    fn test_flags() {
+        let tx = json!({"method": "eth_sendTransaction", "params": [{}, {"resubmit": true}]});
+        assert!(wants_resubmit(&tx));
+        assert!(!wants_resubmit(&json!({"params": [{}]})));
+
+        let mut tx = json!({"method": "eth_sendRawTransaction", "params": ["0x1", {"replacements": ["0x2", "0x3"]}]});
+        assert_eq!(
+            take_replacements(&mut tx),
+            Some(VecDeque::from(["0x2".to_string(), "0x3".to_string()]))
+        );
+        assert_eq!(tx["params"], json!(["0x1"]));
+
+        let mut tx = json!({"method": "eth_sendRawTransaction", "params": ["0x1"]});
+        assert_eq!(take_replacements(&mut tx), None);
+    }
+
+    #[tokio::test]
+    async fn test_replacements() {
+        let manager = manager();
+        let upstream = MockUpstream::default();
+        manager.track(
+            "0x1hash",
+            Resubmission::Replacements(VecDeque::from([
+                "0x2".to_string(),
+                "0x3".to_string(),
+                "0x4".to_string(),
+            ])),
+        );
+        let start = Instant::now();
+
+        // Not late yet
+        manager.tick(&upstream, None, start).await;
+        assert!(upstream.sent.lock().unwrap().is_empty());
+
+        manager
+            .tick(&upstream, None, start + Duration::from_secs(11))
+            .await;
+        assert_eq!(*upstream.sent.lock().unwrap(), ["0x2"]);
+
+        // The first version making it in is fine too
+        upstream
+            .included
+            .lock()
+            .unwrap()
+            .insert("0x1hash".to_string());
+        manager
+            .tick(&upstream, None, start + Duration::from_secs(22))
+            .await;
+        assert_eq!(manager.len(), 0);
+        assert_eq!(*upstream.sent.lock().unwrap(), ["0x2"]);
+    }
+
+    #[tokio::test]
+    async fn test_max_bumps() {
+        let manager = manager();
+        let upstream = MockUpstream::default();
+        manager.track(
+            "0x1hash",
+            Resubmission::Replacements(VecDeque::from([
+                "0x2".to_string(),
+                "0x3".to_string(),
+                "0x4".to_string(),
+            ])),
+        );
+        let start = Instant::now();
+
+        for late in 1..=3 {
+            manager
+                .tick(&upstream, None, start + Duration::from_secs(11 * late))
+                .await;
+        }
+        assert_eq!(*upstream.sent.lock().unwrap(), ["0x2", "0x3"]);
+        assert_eq!(manager.len(), 0);
+    }
+}
@@ -0,0 +1,4 @@
+pub mod error;
+pub mod manager;
+pub mod nonce;
+pub mod types;
@@ -0,0 +1,96 @@
+// Nonces of the accounts we send transactions from.
+//
+// We hand out nonces ourselves instead of asking for the pending nonce every time, so
+// transactions sent right after each other don't end up with the same one because the
+// first one didn't reach the mempool of the RPC we ask yet.
+use std::{
+    collections::HashMap,
+    future::Future,
+};
+
+use tokio::sync::Mutex;
+
+#[derive(Debug, Default)]
+pub struct NonceManager {
+    // Next nonce of each account
+    next: Mutex<HashMap<String, u64>>,
+}
+
+impl NonceManager {
+    // Reserve the next nonce of `account`. `pending` gets the pending nonce in case
+    // we don't know the account yet or lost track of it.
+    pub async fn reserve<F, E>(&self, account: &str, pending: F) -> Result<u64, E>
+    where
+        F: Future<Output = Result<u64, E>>,
+    {
+        // Held while we ask for the pending nonce, so we only ask once per account
+        let mut next = self.next.lock().await;
+        let nonce = match next.get(account) {
+            Some(nonce) => *nonce,
+            None => pending.await?,
+        };
+        next.insert(account.to_string(), nonce + 1);
+
+        Ok(nonce)
+    }
+
+    // Give back `nonce` if it wasn't used. Only works if it's the last one we handed out,
+    // otherwise we'd have a gap, so we start over from the pending nonce instead.
+    pub async fn release(&self, account: &str, nonce: u64) {
+        let mut next = self.next.lock().await;
+        match next.get(account) {
+            Some(next_nonce) if *next_nonce == nonce + 1 => {
+                next.insert(account.to_string(), nonce);
+            }
+            _ => {
+                next.remove(account);
+            }
+        }
+    }
+
+    // Start over from the pending nonce, e.g. after the RPC rejected one of ours
+    pub async fn forget(&self, account: &str) {
+        self.next.lock().await.remove(account);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn pending(nonce: u64) -> Result<u64, ()> {
+        Ok(nonce)
+    }
+
+    #[tokio::test]
+    async fn test_reserve() {
+        let nonces = NonceManager::default();
+
+        assert_eq!(nonces.reserve("0xa", pending(5)).await, Ok(5));
+        // We count ourselves from here on
+        assert_eq!(nonces.reserve("0xa", pending(5)).await, Ok(6));
+        assert_eq!(nonces.reserve("0xb", pending(0)).await, Ok(0));
+
+        // Failing to get the pending nonce doesn't reserve anything
+        assert_eq!(nonces.reserve("0xc", async { Err(()) }).await, Err(()));
+        assert_eq!(nonces.reserve("0xc", pending(1)).await, Ok(1));
+    }
+
+    #[tokio::test]
+    async fn test_release() {
+        let nonces = NonceManager::default();
+        let first = nonces.reserve("0xa", pending(5)).await.unwrap();
+        let second = nonces.reserve("0xa", pending(5)).await.unwrap();
+
+        // Last one, can be handed out again
+        nonces.release("0xa", second).await;
+        assert_eq!(nonces.reserve("0xa", pending(9)).await, Ok(6));
+
+        // Would leave a gap, start over from the pending nonce
+        nonces.release("0xa", first).await;
+        assert_eq!(nonces.reserve("0xa", pending(5)).await, Ok(5));
+
+        nonces.forget("0xa").await;
+        assert_eq!(nonces.reserve("0xa", pending(7)).await, Ok(7));
+    }
+}
@@ -0,0 +1,571 @@
+// Local wallet for trusted internal deployments.
+//
+// With `[wallet]` enabled we answer `eth_accounts`, `eth_signTransaction` and
+// `eth_sendTransaction` ourselves, for clients with one of its API keys. Transactions get
+// their chain id, nonce, gas and fees filled in from the RPCs we balance between, and are
+// then signed by an external signer like Web3Signer or Clef. We don't store keys ourselves:
+// the signer keeps them, in keystore files or an HSM over PKCS#11, so they never end up in
+// our memory. Signed transactions are broadcast through the pool like any
+// `eth_sendRawTransaction`.
+use crate::{
+    balancer::hardened::is_listed_key,
+    config::types::WalletSettings,
+    rpc::types::hex_to_decimal,
+    wallet::{
+        error::WalletError,
+        manager::{
+            wants_resubmit,
+            Resubmission,
+            TxManager,
+        },
+        nonce::NonceManager,
+    },
+    Rpc,
+};
+
+use std::{
+    future::Future,
+    sync::Arc,
+};
+
+use serde_json::{
+    json,
+    Value,
+};
+use tokio::sync::OnceCell;
+
+pub const WALLET_METHODS: [&str; 3] =
+    ["eth_accounts", "eth_signTransaction", "eth_sendTransaction"];
+
+// How we talk to the RPCs while filling in transactions
+pub trait Upstream {
+    // Returns the `result` of the response, or the error message
+    fn call(
+        &self,
+        method: &str,
+        params: Value,
+    ) -> impl Future<Output = Result<Value, String>> + Send;
+}
+
+pub fn quantity(value: &Value) -> Option<u64> {
+    value.as_str().and_then(|value| hex_to_decimal(value).ok())
+}
+
+pub fn to_quantity(value: u64) -> Value {
+    format!("{:#x}", value).into()
+}
+
+async fn call_quantity<U: Upstream>(
+    upstream: &U,
+    method: &str,
+    params: Value,
+) -> Result<u64, WalletError> {
+    let result = upstream
+        .call(method, params)
+        .await
+        .map_err(WalletError::Upstream)?;
+    quantity(&result).ok_or_else(|| WalletError::Upstream(format!("invalid {} response", method)))
+}
+
+#[derive(Debug)]
+pub struct Wallet {
+    settings: WalletSettings,
+    signer: Rpc,
+    nonces: NonceManager,
+    accounts: OnceCell<Vec<String>>,
+    chain_id: OnceCell<u64>,
+    // Only present if the tx manager is enabled
+    manager: Option<Arc<TxManager>>,
+}
+
+impl Wallet {
+    pub fn new(settings: WalletSettings) -> Self {
+        let mut signer = Rpc::new(settings.signer.clone(), 0, 0.0);
+        signer.name = "wallet signer".to_string();
+
+        Self {
+            settings,
+            signer,
+            nonces: NonceManager::default(),
+            accounts: OnceCell::new(),
+            chain_id: OnceCell::new(),
+            manager: None,
+        }
+    }
+
+    // Only clients with one of the wallet's API keys can use it
+    pub fn allows(&self, api_key: Option<&str>) -> bool {
+        api_key.is_some_and(|key| is_listed_key(key, &self.settings.keys))
+    }
+
+    // Let transactions we send ask to be resubmitted until they're included
+    pub fn with_manager(mut self, manager: Arc<TxManager>) -> Self {
+        self.manager = Some(manager);
+        self
+    }
+
+    // All the accounts the signer has keys for
+    async fn signer_accounts(&self) -> Result<Vec<String>, WalletError> {
+        let rx = self
+            .signer
+            .send_request(
+                json!({"id": 1, "jsonrpc": "2.0", "method": "eth_accounts", "params": []}),
+            )
+            .await?;
+        let rx: Value =
+            serde_json::from_slice(&rx).map_err(|err| WalletError::Signer(err.to_string()))?;
+        let accounts = rx["result"]
+            .as_array()
+            .ok_or_else(|| WalletError::Signer(format!("no accounts: {}", rx["error"])))?;
+
+        Ok(accounts
+            .iter()
+            .filter_map(|account| account.as_str())
+            .map(|account| account.to_lowercase())
+            .collect())
+    }
+
+    // Accounts from the config, or the ones of the signer if there are none
+    async fn accounts(&self) -> Result<&[String], WalletError> {
+        let accounts = self
+            .accounts
+            .get_or_try_init(|| {
+                async {
+                    match self.settings.accounts.is_empty() {
+                        true => self.signer_accounts().await,
+                        false => Ok(self.settings.accounts.clone()),
+                    }
+                }
+            })
+            .await?;
+
+        Ok(accounts)
+    }
+
+    async fn chain_id<U: Upstream>(&self, upstream: &U) -> Result<u64, WalletError> {
+        self.chain_id
+            .get_or_try_init(|| call_quantity(upstream, "eth_chainId", json!([])))
+            .await
+            .copied()
+    }
+
+    // Fill in everything the transaction in `tx` is missing.
+    //
+    // With `reserve` we take the next nonce of the account, and return it so it can be
+    // given back if the transaction never makes it out.
+    pub async fn fill<U: Upstream>(
+        &self,
+        tx: &Value,
+        upstream: &U,
+        reserve: bool,
+    ) -> Result<(Value, Option<u64>), WalletError> {
+        let mut filled = tx["params"][0].clone();
+        if !filled.is_object() {
+            return Err(WalletError::InvalidTransaction(
+                "expected a transaction object".to_string(),
+            ));
+        }
+
+        let from = filled["from"]
+            .as_str()
+            .ok_or_else(|| WalletError::InvalidTransaction("missing from".to_string()))?
+            .to_lowercase();
+        if !self.accounts().await?.contains(&from) {
+            return Err(WalletError::UnknownAccount(from));
+        }
+        filled["from"] = from.clone().into();
+
+        let chain_id = self.chain_id(upstream).await?;
+        match quantity(&filled["chainId"]) {
+            Some(id) if id != chain_id => {
+                return Err(WalletError::InvalidTransaction(format!(
+                    "chainId {:#x} doesn't match the chain, which is {:#x}",
+                    id, chain_id
+                )))
+            }
+            _ => filled["chainId"] = to_quantity(chain_id),
+        }
+
+        if filled["gas"].is_null() {
+            let estimate =
+                call_quantity(upstream, "eth_estimateGas", json!([filled, "pending"])).await?;
+            let gas = (estimate as f64 * self.settings.gas_multiplier).ceil() as u64;
+            filled["gas"] = to_quantity(gas);
+        }
+
+        // EIP-1559 fees if the chain has a base fee, a legacy gas price otherwise
+        if filled["gasPrice"].is_null() && filled["maxFeePerGas"].is_null() {
+            let block = upstream
+                .call("eth_getBlockByNumber", json!(["latest", false]))
+                .await
+                .map_err(WalletError::Upstream)?;
+            match quantity(&block["baseFeePerGas"]) {
+                Some(base_fee) => {
+                    let tip = match quantity(&filled["maxPriorityFeePerGas"]) {
+                        Some(tip) => tip,
+                        None => {
+                            call_quantity(upstream, "eth_maxPriorityFeePerGas", json!([])).await?
+                        }
+                    };
+                    // Stays valid even if the base fee keeps rising for a few blocks
+                    filled["maxPriorityFeePerGas"] = to_quantity(tip);
+                    filled["maxFeePerGas"] = to_quantity(base_fee * 2 + tip);
+                    filled["type"] = "0x2".into();
+                }
+                None => {
+                    filled["gasPrice"] =
+                        to_quantity(call_quantity(upstream, "eth_gasPrice", json!([])).await?);
+                }
+            }
+        }
+
+        // Nonce last, so failing to get anything else doesn't use one up
+        let mut reserved = None;
+        if filled["nonce"].is_null() {
+            let pending = call_quantity(
+                upstream,
+                "eth_getTransactionCount",
+                json!([from, "pending"]),
+            );
+            let nonce = match reserve {
+                true => {
+                    let nonce = self.nonces.reserve(&from, pending).await?;
+                    reserved = Some(nonce);
+                    nonce
+                }
+                false => pending.await?,
+            };
+            filled["nonce"] = to_quantity(nonce);
+        }
+
+        Ok((filled, reserved))
+    }
+
+    // Have the signer sign `filled`, returns the raw transaction
+    pub async fn sign(&self, filled: &Value) -> Result<String, WalletError> {
+        let rx = self
+            .signer
+            .send_request(json!({
+                "id": 1,
+                "jsonrpc": "2.0",
+                "method": "eth_signTransaction",
+                "params": [filled],
+            }))
+            .await?;
+        let rx: Value =
+            serde_json::from_slice(&rx).map_err(|err| WalletError::Signer(err.to_string()))?;
+
+        // Web3Signer returns the raw transaction, Clef an object containing it
+        match (&rx["result"], &rx["result"]["raw"]) {
+            (Value::String(raw), _) | (_, Value::String(raw)) => Ok(raw.clone()),
+            _ => Err(WalletError::Signer(rx["error"].to_string())),
+        }
+    }
+
+    async fn sign_transaction<U: Upstream>(
+        &self,
+        tx: &Value,
+        upstream: &U,
+    ) -> Result<Value, WalletError> {
+        let (filled, _) = self.fill(tx, upstream, false).await?;
+        let raw = self.sign(&filled).await?;
+
+        Ok(json!({"raw": raw, "tx": filled}))
+    }
+
+    async fn send_transaction<U: Upstream>(
+        &self,
+        tx: &Value,
+        upstream: &U,
+    ) -> Result<Value, WalletError> {
+        let manager = match (wants_resubmit(tx), &self.manager) {
+            (true, Some(manager)) => Some(manager),
+            (true, None) => {
+                return Err(WalletError::InvalidTransaction(
+                    "resubmit needs the tx manager to be enabled".to_string(),
+                ))
+            }
+            (false, _) => None,
+        };
+
+        let (filled, reserved) = self.fill(tx, upstream, true).await?;
+        let from = filled["from"].as_str().unwrap_or_default();
+
+        let raw = match self.sign(&filled).await {
+            Ok(raw) => raw,
+            Err(err) => {
+                if let Some(nonce) = reserved {
+                    self.nonces.release(from, nonce).await;
+                }
+                return Err(err);
+            }
+        };
+
+        match upstream.call("eth_sendRawTransaction", json!([raw])).await {
+            Ok(hash) => {
+                if let (Some(manager), Some(sent)) = (manager, hash.as_str()) {
+                    manager.track(sent, Resubmission::Signed(filled.clone()));
+                }
+                Ok(hash)
+            }
+            Err(err) => {
+                // We don't know if our nonce was the problem, so ask again next time
+                self.nonces.forget(from).await;
+                Err(WalletError::Upstream(err))
+            }
+        }
+    }
+
+    // Respond to one of `WALLET_METHODS`
+    pub async fn execute<U: Upstream>(&self, tx: &Value, upstream: &U) -> Value {
+        let result = match tx["method"].as_str() {
+            Some("eth_accounts") => self.accounts().await.map(|accounts| json!(accounts)),
+            Some("eth_signTransaction") => self.sign_transaction(tx, upstream).await,
+            Some("eth_sendTransaction") => self.send_transaction(tx, upstream).await,
+            _ => {
+                Err(WalletError::InvalidTransaction(
+                    "unknown method".to_string(),
+                ))
+            }
+        };
+
+        match result {
+            Ok(result) => json!({"id": tx["id"], "jsonrpc": "2.0", "result": result}),
+            Err(err) => {
+                json!({
+                    "id": tx["id"],
+                    "jsonrpc": "2.0",
+                    "error": {
+                        "code": err.code(),
+                        "message": err.to_string(),
+                    },
+                })
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    // Responds like a London chain, and remembers what it was asked
+    #[derive(Default)]
+    struct MockUpstream {
+        calls: Mutex<Vec<String>>,
+        // Reject the transactions we broadcast
+        reject: bool,
+    }
+
+    impl MockUpstream {
+        fn count(&self, method: &str) -> usize {
+            let calls = self.calls.lock().unwrap();
+            calls.iter().filter(|call| *call == method).count()
+        }
+    }
+
+    impl Upstream for MockUpstream {
+        async fn call(&self, method: &str, params: Value) -> Result<Value, String> {
+            self.calls.lock().unwrap().push(method.to_string());
+            match method {
+                "eth_sendRawTransaction" if self.reject => Err("nonce too low".to_string()),
+                // The hash is the raw transaction, so we can see what got signed
+                "eth_sendRawTransaction" => Ok(params[0].clone()),
+                "eth_chainId" => Ok(json!("0x1")),
+                "eth_estimateGas" => Ok(json!("0x5208")),
+                "eth_getBlockByNumber" => Ok(json!({"number": "0x10", "baseFeePerGas": "0x64"})),
+                "eth_maxPriorityFeePerGas" => Ok(json!("0xa")),
+                "eth_getTransactionCount" => Ok(json!("0x7")),
+                _ => Err("unexpected method".to_string()),
+            }
+        }
+    }
+
+    // Signer that signs transactions as `0xsigned<nonce>`
+    async fn signer() -> String {
+        use http_body_util::{
+            BodyExt,
+            Full,
+        };
+        use hyper::{
+            body::Bytes,
+            server::conn::http1,
+            service::service_fn,
+        };
+        use hyper_util_blutgang::rt::TokioIo;
+        use std::convert::Infallible;
+        use tokio::net::TcpListener;
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::task::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                let service = service_fn(|req: hyper::Request<hyper::body::Incoming>| {
+                    async move {
+                        let body = req.into_body().collect().await.unwrap().to_bytes();
+                        let tx: Value = serde_json::from_slice(&body).unwrap();
+                        let nonce = quantity(&tx["params"][0]["nonce"]).unwrap();
+                        let rx = json!({"id": tx["id"], "result": format!("0xsigned{}", nonce)});
+                        Ok::<_, Infallible>(hyper::Response::new(Full::new(Bytes::from(
+                            rx.to_string(),
+                        ))))
+                    }
+                });
+                tokio::task::spawn(
+                    http1::Builder::new().serve_connection(TokioIo::new(stream), service),
+                );
+            }
+        });
+
+        format!("http://{}", address)
+    }
+
+    const ACCOUNT: &str = "0x00000000000000000000000000000000000000aa";
+
+    fn wallet() -> Wallet {
+        Wallet::new(WalletSettings {
+            enabled: true,
+            accounts: vec![ACCOUNT.to_string()],
+            keys: vec!["ops".to_string()],
+            ..Default::default()
+        })
+    }
+
+    fn wallet_with_signer(signer: String) -> Wallet {
+        Wallet::new(WalletSettings {
+            signer,
+            ..wallet().settings
+        })
+    }
+
+    fn request(tx: Value) -> Value {
+        json!({"id": 1, "jsonrpc": "2.0", "method": "eth_sendTransaction", "params": [tx]})
+    }
+
+    #[tokio::test]
+    async fn test_fill() {
+        let wallet = wallet();
+        let upstream = MockUpstream::default();
+        let tx = request(
+            json!({"from": ACCOUNT.to_uppercase().replace("0X", "0x"), "to": "0xbb", "value": "0x1"}),
+        );
+
+        let (filled, reserved) = wallet.fill(&tx, &upstream, true).await.unwrap();
+        assert_eq!(filled["from"], ACCOUNT);
+        assert_eq!(filled["chainId"], "0x1");
+        // 21000 * 1.2
+        assert_eq!(filled["gas"], "0x6270");
+        assert_eq!(filled["maxPriorityFeePerGas"], "0xa");
+        assert_eq!(filled["maxFeePerGas"], "0xd2");
+        assert_eq!(filled["nonce"], "0x7");
+        assert_eq!(reserved, Some(7));
+
+        // The next one gets the next nonce without asking
+        let (filled, _) = wallet.fill(&tx, &upstream, true).await.unwrap();
+        assert_eq!(filled["nonce"], "0x8");
+        let calls = upstream.calls.lock().unwrap();
+        assert_eq!(
+            calls
+                .iter()
+                .filter(|method| *method == "eth_getTransactionCount")
+                .count(),
+            1
+        );
+    }
+
+    #[tokio::test]
+    async fn test_fill_keeps_fields() {
+        let wallet = wallet();
+        let upstream = MockUpstream::default();
+        let tx = request(json!({
+            "from": ACCOUNT,
+            "to": "0xbb",
+            "gas": "0x1",
+            "gasPrice": "0x2",
+            "nonce": "0x3",
+        }));
+
+        let (filled, reserved) = wallet.fill(&tx, &upstream, true).await.unwrap();
+        assert_eq!(filled["gas"], "0x1");
+        assert_eq!(filled["gasPrice"], "0x2");
+        assert_eq!(filled["nonce"], "0x3");
+        assert!(filled["maxFeePerGas"].is_null());
+        assert_eq!(reserved, None);
+        assert_eq!(*upstream.calls.lock().unwrap(), vec!["eth_chainId"]);
+    }
+
+    #[tokio::test]
+    async fn test_fill_errors() {
+        let wallet = wallet();
+        let upstream = MockUpstream::default();
+
+        let tx = request(json!({"from": "0x00000000000000000000000000000000000000cc"}));
+        assert!(matches!(
+            wallet.fill(&tx, &upstream, true).await,
+            Err(WalletError::UnknownAccount(_))
+        ));
+
+        let tx = request(json!({"from": ACCOUNT, "chainId": "0x2"}));
+        assert!(matches!(
+            wallet.fill(&tx, &upstream, true).await,
+            Err(WalletError::InvalidTransaction(_))
+        ));
+
+        let rx = wallet.execute(&request(json!("0x")), &upstream).await;
+        assert_eq!(rx["error"]["code"], -32602);
+        assert_eq!(rx["id"], 1);
+    }
+
+    #[test]
+    fn test_allows() {
+        let wallet = wallet();
+        assert!(wallet.allows(Some("ops")));
+        assert!(!wallet.allows(Some("op")));
+        assert!(!wallet.allows(None));
+    }
+
+    #[tokio::test]
+    async fn test_send_transaction_nonces() {
+        let wallet = wallet_with_signer(signer().await);
+        let upstream = MockUpstream::default();
+        let tx = request(json!({"from": ACCOUNT, "to": "0xbb"}));
+
+        // Sent one after the other, each with the next nonce
+        assert_eq!(wallet.execute(&tx, &upstream).await["result"], "0xsigned7");
+        assert_eq!(wallet.execute(&tx, &upstream).await["result"], "0xsigned8");
+        assert_eq!(upstream.count("eth_getTransactionCount"), 1);
+
+        // Rejected, so we ask for the pending nonce again
+        let rejecting = MockUpstream {
+            reject: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            wallet.execute(&tx, &rejecting).await["error"]["code"],
+            -32603
+        );
+        assert_eq!(wallet.execute(&tx, &upstream).await["result"], "0xsigned7");
+        assert_eq!(upstream.count("eth_getTransactionCount"), 2);
+    }
+
+    #[tokio::test]
+    async fn test_signer_failure_releases_nonce() {
+        // Nothing listens there
+        let wallet = wallet_with_signer("http://127.0.0.1:1".to_string());
+        let upstream = MockUpstream::default();
+        let tx = request(json!({"from": ACCOUNT, "to": "0xbb"}));
+
+        assert_eq!(
+            wallet.execute(&tx, &upstream).await["error"]["code"],
+            -32603
+        );
+        assert_eq!(upstream.count("eth_sendRawTransaction"), 0);
+
+        // The nonce wasn't used, so the next transaction gets it
+        let (filled, _) = wallet.fill(&tx, &upstream, true).await.unwrap();
+        assert_eq!(filled["nonce"], "0x7");
+        assert_eq!(upstream.count("eth_getTransactionCount"), 1);
+    }
+}
@@ -0,0 +1,254 @@
+// WebSocket messages and their framing (RFC 6455).
+//
+// Only what JSON-RPC needs: text and binary messages, fragmented or not, pings,
+// pongs and closes. No extensions, so frames are never compressed.
+use std::io;
+
+use ring::rand::{
+    SecureRandom,
+    SystemRandom,
+};
+use tokio::io::{
+    AsyncRead,
+    AsyncReadExt,
+    AsyncWrite,
+    AsyncWriteExt,
+};
+
+// Largest message we accept, big `logs` notifications stay well below this
+pub const MAX_MESSAGE_SIZE: usize = 16 * 1024 * 1024;
+
+const OP_CONTINUATION: u8 = 0x0;
+const OP_TEXT: u8 = 0x1;
+const OP_BINARY: u8 = 0x2;
+const OP_CLOSE: u8 = 0x8;
+const OP_PING: u8 = 0x9;
+const OP_PONG: u8 = 0xa;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Message {
+    Text(String),
+    Binary(Vec<u8>),
+    Ping(Vec<u8>),
+    Pong(Vec<u8>),
+    Close,
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+// Read a single frame, returning if it's the last of its message, its opcode and payload
+async fn read_frame<R: AsyncRead + Unpin>(reader: &mut R) -> io::Result<(bool, u8, Vec<u8>)> {
+    let mut header = [0; 2];
+    reader.read_exact(&mut header).await?;
+    let fin = header[0] & 0x80 != 0;
+    let opcode = header[0] & 0x0f;
+    let masked = header[1] & 0x80 != 0;
+
+    let len = match header[1] & 0x7f {
+        126 => reader.read_u16().await? as u64,
+        127 => reader.read_u64().await?,
+        len => len as u64,
+    };
+    if len > MAX_MESSAGE_SIZE as u64 {
+        return Err(invalid("frame too large"));
+    }
+
+    let mut mask = [0; 4];
+    if masked {
+        reader.read_exact(&mut mask).await?;
+    }
+
+    let mut payload = vec![0; len as usize];
+    reader.read_exact(&mut payload).await?;
+    if masked {
+        apply_mask(&mut payload, mask);
+    }
+
+    Ok((fin, opcode, payload))
+}
+
+fn apply_mask(payload: &mut [u8], mask: [u8; 4]) {
+    for (i, byte) in payload.iter_mut().enumerate() {
+        *byte ^= mask[i % 4];
+    }
+}
+
+// Reads messages, putting fragmented ones back together
+#[derive(Debug)]
+pub struct MessageReader<R> {
+    reader: R,
+    // Opcode and data of the message we got some fragments of
+    partial: Option<(u8, Vec<u8>)>,
+}
+
+impl<R: AsyncRead + Unpin> MessageReader<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            partial: None,
+        }
+    }
+
+    // Read the next message. Returns None once the other side closed the connection.
+    pub async fn next(&mut self) -> io::Result<Option<Message>> {
+        loop {
+            let (fin, opcode, payload) = match read_frame(&mut self.reader).await {
+                Ok(frame) => frame,
+                Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+                Err(err) => return Err(err),
+            };
+
+            // Control frames can come in between the fragments of a message
+            match opcode {
+                OP_CLOSE => return Ok(Some(Message::Close)),
+                OP_PING => return Ok(Some(Message::Ping(payload))),
+                OP_PONG => return Ok(Some(Message::Pong(payload))),
+                OP_TEXT | OP_BINARY if self.partial.is_none() => {
+                    self.partial = Some((opcode, payload))
+                }
+                OP_CONTINUATION => {
+                    let (_, data) = self
+                        .partial
+                        .as_mut()
+                        .ok_or_else(|| invalid("continuation without a message"))?;
+                    if data.len() + payload.len() > MAX_MESSAGE_SIZE {
+                        return Err(invalid("message too large"));
+                    }
+                    data.extend_from_slice(&payload);
+                }
+                _ => return Err(invalid("unexpected opcode")),
+            }
+
+            if fin {
+                return match self.partial.take() {
+                    Some((OP_TEXT, data)) => {
+                        String::from_utf8(data)
+                            .map(|text| Some(Message::Text(text)))
+                            .map_err(|_| invalid("text message is not UTF-8"))
+                    }
+                    Some((_, data)) => Ok(Some(Message::Binary(data))),
+                    None => Err(invalid("unexpected opcode")),
+                };
+            }
+        }
+    }
+}
+
+// Frame `message` as a single frame. Clients have to mask what they send, servers must not.
+pub fn encode(message: &Message, mask: Option<[u8; 4]>) -> Vec<u8> {
+    let (opcode, payload) = match message {
+        Message::Text(text) => (OP_TEXT, text.as_bytes()),
+        Message::Binary(data) => (OP_BINARY, data.as_slice()),
+        Message::Ping(data) => (OP_PING, data.as_slice()),
+        Message::Pong(data) => (OP_PONG, data.as_slice()),
+        Message::Close => (OP_CLOSE, [].as_slice()),
+    };
+
+    let mut frame = Vec::with_capacity(payload.len() + 14);
+    frame.push(0x80 | opcode);
+
+    let mask_bit = if mask.is_some() { 0x80 } else { 0 };
+    match payload.len() {
+        len if len < 126 => frame.push(mask_bit | len as u8),
+        len if len <= u16::MAX as usize => {
+            frame.push(mask_bit | 126);
+            frame.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+        len => {
+            frame.push(mask_bit | 127);
+            frame.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+    }
+
+    let start = frame.len();
+    match mask {
+        Some(mask) => {
+            frame.extend_from_slice(&mask);
+            frame.extend_from_slice(payload);
+            apply_mask(&mut frame[start + 4..], mask);
+        }
+        None => frame.extend_from_slice(payload),
+    }
+
+    frame
+}
+
+// Send `message`, masked with a random key if we're the client
+pub async fn write_message<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    message: &Message,
+    client: bool,
+) -> io::Result<()> {
+    let mask = match client {
+        true => {
+            let mut mask = [0; 4];
+            SystemRandom::new()
+                .fill(&mut mask)
+                .map_err(|_| io::Error::new(io::ErrorKind::Other, "could not generate a mask"))?;
+            Some(mask)
+        }
+        false => None,
+    };
+
+    writer.write_all(&encode(message, mask)).await?;
+    writer.flush().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_roundtrip() {
+        let messages = [
+            Message::Text(r#"{"jsonrpc":"2.0","id":1,"method":"eth_subscribe"}"#.to_string()),
+            Message::Text("a".repeat(300)),
+            Message::Binary(vec![7; 70_000]),
+            Message::Ping(b"ping".to_vec()),
+            Message::Close,
+        ];
+
+        for message in messages {
+            for mask in [None, Some([1, 2, 3, 4])] {
+                let frame = encode(&message, mask);
+                let read = MessageReader::new(frame.as_slice()).next().await.unwrap();
+                assert_eq!(read, Some(message.clone()));
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_fragmented() {
+        // "Hel", a ping in between, then "lo"
+        let mut frames = vec![0x01, 0x03];
+        frames.extend_from_slice(b"Hel");
+        frames.extend_from_slice(&[0x89, 0x00]);
+        frames.extend_from_slice(&[0x80, 0x02]);
+        frames.extend_from_slice(b"lo");
+        let mut reader = MessageReader::new(frames.as_slice());
+
+        assert_eq!(
+            reader.next().await.unwrap(),
+            Some(Message::Ping(Vec::new()))
+        );
+        assert_eq!(
+            reader.next().await.unwrap(),
+            Some(Message::Text("Hello".to_string()))
+        );
+        assert_eq!(reader.next().await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_invalid() {
+        // Continuation without a message to continue
+        let mut reader = MessageReader::new([0x80, 0x00].as_slice());
+        assert!(reader.next().await.is_err());
+
+        // Larger than we accept
+        let mut frame = vec![0x82, 127];
+        frame.extend_from_slice(&(MAX_MESSAGE_SIZE as u64 + 1).to_be_bytes());
+        assert!(MessageReader::new(frame.as_slice()).next().await.is_err());
+    }
+}
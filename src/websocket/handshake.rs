@@ -0,0 +1,270 @@
+// The HTTP upgrade a WebSocket connection starts with, as the server for our
+// clients and as the client for upstream subscriptions.
+use std::io;
+
+use base64::{
+    engine::general_purpose::STANDARD,
+    Engine,
+};
+use ring::{
+    digest::{
+        digest,
+        SHA1_FOR_LEGACY_USE_ONLY,
+    },
+    rand::{
+        SecureRandom,
+        SystemRandom,
+    },
+};
+use tokio::{
+    io::{
+        AsyncBufReadExt,
+        AsyncRead,
+        AsyncWrite,
+        AsyncWriteExt,
+        BufReader,
+    },
+    net::TcpStream,
+};
+use tokio_native_tls::{
+    native_tls,
+    TlsConnector,
+};
+use url::{
+    Position,
+    Url,
+};
+
+// Appended to the key of the client before hashing, from the RFC
+const GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+// Longest request or response head we read
+const MAX_HEAD_SIZE: usize = 16 * 1024;
+
+// Anything we can speak WebSockets over
+pub trait Stream: AsyncRead + AsyncWrite + Unpin + Send {}
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Stream for T {}
+
+// Request or status line, and headers with lowercase names
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Head {
+    pub start: String,
+    pub headers: Vec<(String, String)>,
+}
+
+impl Head {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    // Returns true if the comma separated `name` header contains `token`
+    fn has_token(&self, name: &str, token: &str) -> bool {
+        self.header(name).is_some_and(|value| {
+            value
+                .split(',')
+                .any(|value| value.trim().eq_ignore_ascii_case(token))
+        })
+    }
+}
+
+pub fn parse_head(head: &str) -> Option<Head> {
+    let mut lines = head.split("\r\n");
+    let start = lines.next()?.to_string();
+
+    let headers = lines
+        .filter(|line| !line.is_empty())
+        .map(|line| {
+            let (name, value) = line.split_once(':')?;
+            Some((name.trim().to_lowercase(), value.trim().to_string()))
+        })
+        .collect::<Option<_>>()?;
+
+    Some(Head { start, headers })
+}
+
+async fn read_head<S: AsyncRead + Unpin>(stream: &mut BufReader<S>) -> io::Result<Head> {
+    let mut head = String::new();
+    loop {
+        let read = stream.read_line(&mut head).await?;
+        if read == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        if head.len() > MAX_HEAD_SIZE {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "head too large"));
+        }
+        if head.ends_with("\r\n\r\n") {
+            break;
+        }
+    }
+
+    parse_head(&head).ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "malformed head"))
+}
+
+// What we answer a client with `key` in `Sec-WebSocket-Accept`
+pub fn accept_key(key: &str) -> String {
+    STANDARD.encode(digest(
+        &SHA1_FOR_LEGACY_USE_ONLY,
+        format!("{}{}", key, GUID).as_bytes(),
+    ))
+}
+
+// Check the upgrade request on `stream` and switch protocols.
+//
+// Returns the request head, clients we can't upgrade get a 400.
+pub async fn accept<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut BufReader<S>,
+) -> Result<Head, String> {
+    let head = read_head(stream).await.map_err(|err| err.to_string())?;
+
+    let key = match head.header("sec-websocket-key") {
+        Some(key)
+            if head.start.starts_with("GET ")
+                && head.has_token("upgrade", "websocket")
+                && head.has_token("connection", "upgrade")
+                && head.header("sec-websocket-version") == Some("13") =>
+        {
+            key
+        }
+        _ => {
+            let _ = stream
+                .write_all(
+                    b"HTTP/1.1 400 Bad Request\r\nConnection: close\r\nContent-Length: 0\r\n\r\n",
+                )
+                .await;
+            return Err("not a WebSocket upgrade".to_string());
+        }
+    };
+
+    let response = format!(
+        "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+        accept_key(key)
+    );
+    stream
+        .write_all(response.as_bytes())
+        .await
+        .map_err(|err| err.to_string())?;
+
+    Ok(head)
+}
+
+// Open a WebSocket connection to `url`. Credentials in the url are sent as basic auth.
+pub async fn connect(url: &str) -> Result<BufReader<Box<dyn Stream>>, String> {
+    let url = Url::parse(url).map_err(|err| err.to_string())?;
+    let host = url.host_str().ok_or("url has no host")?.to_string();
+    let port = url.port_or_known_default().ok_or("url has no port")?;
+
+    let tcp = TcpStream::connect((host.as_str(), port))
+        .await
+        .map_err(|err| err.to_string())?;
+    let stream: Box<dyn Stream> = match url.scheme() {
+        "ws" => Box::new(tcp),
+        "wss" => {
+            let connector = native_tls::TlsConnector::new().map_err(|err| err.to_string())?;
+            let tls = TlsConnector::from(connector)
+                .connect(&host, tcp)
+                .await
+                .map_err(|err| err.to_string())?;
+            Box::new(tls)
+        }
+        scheme => return Err(format!("unsupported scheme: {}", scheme)),
+    };
+    let mut stream = BufReader::new(stream);
+
+    let mut key = [0; 16];
+    SystemRandom::new()
+        .fill(&mut key)
+        .map_err(|_| "could not generate a key")?;
+    let key = STANDARD.encode(key);
+
+    let path = match url.query() {
+        Some(query) => format!("{}?{}", url.path(), query),
+        None => url.path().to_string(),
+    };
+    let mut request = format!(
+        "GET {} HTTP/1.1\r\nHost: {}\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Key: {}\r\nSec-WebSocket-Version: 13\r\n",
+        path,
+        &url[Position::BeforeHost..Position::AfterPort],
+        key
+    );
+    if !url.username().is_empty() {
+        let credentials = format!("{}:{}", url.username(), url.password().unwrap_or_default());
+        request.push_str(&format!(
+            "Authorization: Basic {}\r\n",
+            STANDARD.encode(credentials)
+        ));
+    }
+    request.push_str("\r\n");
+    stream
+        .write_all(request.as_bytes())
+        .await
+        .map_err(|err| err.to_string())?;
+
+    let head = read_head(&mut stream)
+        .await
+        .map_err(|err| err.to_string())?;
+    if !head.start.starts_with("HTTP/1.1 101") {
+        return Err(format!("upgrade refused: {}", head.start));
+    }
+    if head.header("sec-websocket-accept") != Some(accept_key(&key).as_str()) {
+        return Err("invalid Sec-WebSocket-Accept".to_string());
+    }
+
+    Ok(stream)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::duplex;
+
+    #[test]
+    fn test_accept_key() {
+        // Example from the RFC
+        assert_eq!(
+            accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+    }
+
+    #[test]
+    fn test_parse_head() {
+        let head =
+            parse_head("GET /ws HTTP/1.1\r\nHost: localhost\r\nX-Api-Key: abc\r\n\r\n").unwrap();
+        assert_eq!(head.start, "GET /ws HTTP/1.1");
+        assert_eq!(head.header("x-api-key"), Some("abc"));
+        assert_eq!(head.header("upgrade"), None);
+
+        assert_eq!(parse_head("GET / HTTP/1.1\r\nnot a header\r\n\r\n"), None);
+    }
+
+    #[tokio::test]
+    async fn test_accept() {
+        let (mut client, server) = duplex(4096);
+        let mut server = BufReader::new(server);
+
+        client
+            .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\nConnection: keep-alive, Upgrade\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n\r\n")
+            .await
+            .unwrap();
+        assert!(accept(&mut server).await.is_ok());
+
+        let response = read_head(&mut BufReader::new(client)).await.unwrap();
+        assert_eq!(response.start, "HTTP/1.1 101 Switching Protocols");
+        assert_eq!(
+            response.header("sec-websocket-accept"),
+            Some("s3pPLMBiTxaQ9kYGzzhZRbK+xOo=")
+        );
+
+        // Plain HTTP requests aren't upgraded
+        let (mut client, server) = duplex(4096);
+        let mut server = BufReader::new(server);
+        client
+            .write_all(b"POST / HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .await
+            .unwrap();
+        assert!(accept(&mut server).await.is_err());
+    }
+}
@@ -0,0 +1,458 @@
+// Fan out upstream `eth_subscribe` subscriptions to our clients.
+//
+// However many clients subscribe to the same thing, we only subscribe to it once,
+// on the fastest RPC with a `ws_url`. If that RPC drops the subscription, we
+// subscribe again on the next one, and clients keep their subscription ids.
+// Events that happen in between are missed.
+//
+// `finalizedHeads` isn't a node subscription, we follow the finalized block ourselves.
+use crate::{
+    subscriptions::{
+        finalized::follow_finalized,
+        types::subscription_id,
+    },
+    websocket::{
+        frame::{
+            write_message,
+            Message,
+            MessageReader,
+        },
+        handshake::connect,
+    },
+    Rpc,
+};
+
+use std::{
+    collections::HashMap,
+    sync::{
+        Arc,
+        Mutex,
+        RwLock,
+    },
+    time::Duration,
+};
+
+use serde_json::{
+    json,
+    Value,
+};
+use tokio::{
+    sync::{
+        broadcast,
+        oneshot,
+        watch,
+    },
+    time::{
+        sleep,
+        timeout,
+    },
+};
+
+// Subscriptions we can fan out, `syncing` and friends are per node
+pub const KINDS: [&str; 4] = [
+    "newHeads",
+    "logs",
+    "newPendingTransactions",
+    FINALIZED_HEADS,
+];
+
+const FINALIZED_HEADS: &str = "finalizedHeads";
+
+// Events a slow client can fall behind by before it misses some
+const BACKLOG: usize = 256;
+
+// How long we wait before subscribing again after losing a subscription
+const RETRY: Duration = Duration::from_secs(1);
+
+// One upstream subscription and how many clients share it
+#[derive(Debug)]
+struct Shared {
+    events: broadcast::Sender<Value>,
+    clients: usize,
+    // Dropping this ends the upstream subscription
+    _stop: oneshot::Sender<()>,
+}
+
+#[derive(Debug)]
+pub struct SubscriptionHub {
+    // `eth_subscribe` params as a string -> subscription
+    subscriptions: Mutex<HashMap<String, Shared>>,
+    rpc_list: Arc<RwLock<Vec<Rpc>>>,
+    ttl: Duration,
+    finalized_rx: watch::Receiver<u64>,
+}
+
+// Key of the shared subscription for `params`, or why we can't subscribe with them
+pub fn subscription_key(params: &Value) -> Result<String, String> {
+    match params[0].as_str() {
+        Some(kind) if KINDS.contains(&kind) => Ok(params.to_string()),
+        Some(kind) => Err(format!("Unsupported subscription type: {}", kind)),
+        None => Err("Missing subscription type".to_string()),
+    }
+}
+
+impl SubscriptionHub {
+    pub fn new(
+        rpc_list: Arc<RwLock<Vec<Rpc>>>,
+        ttl: Duration,
+        finalized_rx: watch::Receiver<u64>,
+    ) -> Self {
+        Self {
+            subscriptions: Mutex::new(HashMap::new()),
+            rpc_list,
+            ttl,
+            finalized_rx,
+        }
+    }
+
+    // New id for a client subscription. Hashed so they can't be guessed.
+    pub fn new_id(&self) -> String {
+        subscription_id()
+    }
+
+    // Join the subscription under `key`, subscribing upstream with `params` if
+    // we aren't already. Events come out of the returned receiver.
+    pub fn subscribe(&self, key: &str, params: &Value) -> broadcast::Receiver<Value> {
+        let mut subscriptions = self.subscriptions.lock().unwrap();
+        if let Some(shared) = subscriptions.get_mut(key) {
+            shared.clients += 1;
+            return shared.events.subscribe();
+        }
+
+        let (events, events_rx) = broadcast::channel(BACKLOG);
+        let (stop, stop_rx) = oneshot::channel();
+        match params[0].as_str() {
+            Some(FINALIZED_HEADS) => {
+                tokio::task::spawn(finalized_subscription(
+                    self.finalized_rx.clone(),
+                    events.clone(),
+                    stop_rx,
+                ));
+            }
+            _ => {
+                tokio::task::spawn(upstream_subscription(
+                    Arc::clone(&self.rpc_list),
+                    params.clone(),
+                    events.clone(),
+                    stop_rx,
+                    self.ttl,
+                ));
+            }
+        }
+        subscriptions.insert(
+            key.to_string(),
+            Shared {
+                events,
+                clients: 1,
+                _stop: stop,
+            },
+        );
+
+        events_rx
+    }
+
+    // Leave the subscription under `key`, the last client to leave ends it upstream
+    pub fn unsubscribe(&self, key: &str) {
+        let mut subscriptions = self.subscriptions.lock().unwrap();
+        if let Some(shared) = subscriptions.get_mut(key) {
+            shared.clients -= 1;
+            if shared.clients == 0 {
+                subscriptions.remove(key);
+            }
+        }
+    }
+
+    pub fn upstream_subscriptions(&self) -> usize {
+        self.subscriptions.lock().unwrap().len()
+    }
+}
+
+// Fastest RPC that can take subscriptions, that we didn't already fail on
+fn pick_rpc(rpc_list: &RwLock<Vec<Rpc>>, failed: &[String]) -> Option<(String, String)> {
+    rpc_list
+        .read()
+        .unwrap()
+        .iter()
+        .filter(|rpc| !rpc.paused && !failed.contains(&rpc.name))
+        .filter_map(|rpc| Some((rpc, rpc.ws_url.clone()?)))
+        .min_by(|(a, _), (b, _)| a.status.latency.total_cmp(&b.status.latency))
+        .map(|(rpc, ws_url)| (rpc.name.clone(), ws_url))
+}
+
+// Subscribe with `params` on `url` and publish what we get to `events`.
+// Only returns once the subscription is gone.
+async fn follow(
+    url: &str,
+    params: &Value,
+    events: &broadcast::Sender<Value>,
+    ttl: Duration,
+) -> Result<(), String> {
+    let stream = timeout(ttl, connect(url))
+        .await
+        .map_err(|_| "timed out connecting".to_string())??;
+    let (reader, mut writer) = tokio::io::split(stream);
+    let mut reader = MessageReader::new(reader);
+
+    let tx = json!({
+        "id": 1,
+        "jsonrpc": "2.0",
+        "method": "eth_subscribe",
+        "params": params,
+    });
+    write_message(&mut writer, &Message::Text(tx.to_string()), true)
+        .await
+        .map_err(|err| err.to_string())?;
+
+    let mut subscription: Option<String> = None;
+    loop {
+        let message = match subscription {
+            Some(_) => reader.next().await,
+            None => {
+                timeout(ttl, reader.next())
+                    .await
+                    .map_err(|_| "timed out subscribing".to_string())?
+            }
+        };
+
+        let rx: Value = match message.map_err(|err| err.to_string())? {
+            Some(Message::Text(text)) => {
+                serde_json::from_str(&text).map_err(|err| err.to_string())?
+            }
+            Some(Message::Binary(data)) => {
+                serde_json::from_slice(&data).map_err(|err| err.to_string())?
+            }
+            Some(Message::Ping(data)) => {
+                write_message(&mut writer, &Message::Pong(data), true)
+                    .await
+                    .map_err(|err| err.to_string())?;
+                continue;
+            }
+            Some(Message::Pong(_)) => continue,
+            Some(Message::Close) | None => return Err("connection closed".to_string()),
+        };
+
+        match &subscription {
+            None if rx["id"] == 1 => {
+                subscription = match rx["result"].as_str() {
+                    Some(id) => Some(id.to_string()),
+                    None => return Err(format!("subscription refused: {}", rx["error"])),
+                };
+            }
+            Some(id)
+                if rx["method"] == "eth_subscription" && rx["params"]["subscription"] == *id =>
+            {
+                // Nobody might be listening for a moment, between clients
+                let _ = events.send(rx["params"]["result"].clone());
+            }
+            _ => {}
+        }
+    }
+}
+
+// Publish the finalized heads to `events` until `stop` is dropped
+async fn finalized_subscription(
+    finalized_rx: watch::Receiver<u64>,
+    events: broadcast::Sender<Value>,
+    stop: oneshot::Receiver<()>,
+) {
+    let publish = |event| {
+        let _ = events.send(event);
+    };
+    tokio::select! {
+        _ = follow_finalized(finalized_rx, publish) => {},
+        _ = stop => {},
+    }
+}
+
+// Keep a subscription with `params` open upstream until `stop` is dropped
+async fn upstream_subscription(
+    rpc_list: Arc<RwLock<Vec<Rpc>>>,
+    params: Value,
+    events: broadcast::Sender<Value>,
+    mut stop: oneshot::Receiver<()>,
+    ttl: Duration,
+) {
+    let mut failed: Vec<String> = Vec::new();
+    let mut warned = false;
+
+    loop {
+        let (name, url) = match pick_rpc(&rpc_list, &failed) {
+            Some(rpc) => rpc,
+            None => {
+                // Tried them all, start over
+                if failed.is_empty() && !warned {
+                    println!(
+                        "\x1b[93mWrn:\x1b[0m No RPC with a ws_url to subscribe to {} on!",
+                        params
+                    );
+                    warned = true;
+                }
+                failed.clear();
+                tokio::select! {
+                    _ = sleep(RETRY) => continue,
+                    _ = &mut stop => return,
+                }
+            }
+        };
+
+        warned = false;
+
+        // Dropping `follow` closes the connection, which ends the subscription upstream
+        let result = tokio::select! {
+            result = follow(&url, &params, &events, ttl) => result,
+            _ = &mut stop => return,
+        };
+        if let Err(err) = result {
+            println!(
+                "\x1b[93mWrn:\x1b[0m Lost subscription to {} on {}: {}",
+                params, name, err
+            );
+        }
+        failed.push(name);
+
+        tokio::select! {
+            _ = sleep(RETRY) => {}
+            _ = &mut stop => return,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rpc(name: &str, ws_url: Option<&str>, latency: f64) -> Rpc {
+        let mut rpc = Rpc::default();
+        rpc.name = name.to_string();
+        rpc.ws_url = ws_url.map(str::to_string);
+        rpc.status.latency = latency;
+        rpc
+    }
+
+    #[test]
+    fn test_subscription_key() {
+        assert_eq!(
+            subscription_key(&json!(["newHeads"])),
+            Ok(r#"["newHeads"]"#.to_string())
+        );
+        assert!(subscription_key(&json!(["logs", {"address": "0xaa"}])).is_ok());
+        assert!(subscription_key(&json!(["finalizedHeads"])).is_ok());
+        assert!(subscription_key(&json!(["syncing"])).is_err());
+        assert!(subscription_key(&json!([])).is_err());
+    }
+
+    #[test]
+    fn test_pick_rpc() {
+        let rpc_list = RwLock::new(vec![
+            rpc("http_only", None, 1.0),
+            rpc("slow", Some("ws://slow"), 20.0),
+            rpc("fast", Some("ws://fast"), 10.0),
+        ]);
+
+        assert_eq!(
+            pick_rpc(&rpc_list, &[]),
+            Some(("fast".to_string(), "ws://fast".to_string()))
+        );
+        assert_eq!(
+            pick_rpc(&rpc_list, &["fast".to_string()]),
+            Some(("slow".to_string(), "ws://slow".to_string()))
+        );
+        assert_eq!(
+            pick_rpc(&rpc_list, &["fast".to_string(), "slow".to_string()]),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn test_shared_subscriptions() {
+        let hub = SubscriptionHub::new(
+            Arc::new(RwLock::new(Vec::new())),
+            Duration::from_secs(1),
+            watch::channel(0).1,
+        );
+        let heads = subscription_key(&json!(["newHeads"])).unwrap();
+        let logs = subscription_key(&json!(["logs", {}])).unwrap();
+
+        let _a = hub.subscribe(&heads, &json!(["newHeads"]));
+        let _b = hub.subscribe(&heads, &json!(["newHeads"]));
+        let _c = hub.subscribe(&logs, &json!(["logs", {}]));
+        assert_eq!(hub.upstream_subscriptions(), 2);
+
+        hub.unsubscribe(&heads);
+        assert_eq!(hub.upstream_subscriptions(), 2);
+        hub.unsubscribe(&heads);
+        hub.unsubscribe(&logs);
+        assert_eq!(hub.upstream_subscriptions(), 0);
+
+        assert_ne!(hub.new_id(), hub.new_id());
+    }
+
+    #[tokio::test]
+    async fn test_finalized_heads() {
+        let (finalized_tx, finalized_rx) = watch::channel(0);
+        let hub = SubscriptionHub::new(
+            Arc::new(RwLock::new(Vec::new())),
+            Duration::from_secs(1),
+            finalized_rx,
+        );
+        let key = subscription_key(&json!(["finalizedHeads"])).unwrap();
+        let mut events = hub.subscribe(&key, &json!(["finalizedHeads"]));
+
+        // Served locally, without any node to subscribe to
+        finalized_tx.send(10).unwrap();
+        let event = tokio::time::timeout(Duration::from_secs(1), events.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(event, json!({"number": "0xa"}));
+        hub.unsubscribe(&key);
+    }
+
+    #[tokio::test]
+    async fn test_fan_out() {
+        use crate::websocket::handshake::accept;
+        use tokio::{
+            io::BufReader,
+            net::TcpListener,
+        };
+
+        // Upstream that accepts one subscription and sends a single event
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+        tokio::task::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut stream = BufReader::new(stream);
+            accept(&mut stream).await.unwrap();
+            let (reader, mut writer) = tokio::io::split(stream);
+
+            let tx = match MessageReader::new(reader).next().await.unwrap() {
+                Some(Message::Text(text)) => serde_json::from_str::<Value>(&text).unwrap(),
+                message => panic!("unexpected message: {:?}", message),
+            };
+            assert_eq!(tx["method"], "eth_subscribe");
+
+            for rx in [
+                json!({"id": tx["id"], "jsonrpc": "2.0", "result": "0xabc"}),
+                json!({"jsonrpc": "2.0", "method": "eth_subscription", "params": {
+                    "subscription": "0xabc",
+                    "result": {"number": "0x10"},
+                }}),
+            ] {
+                write_message(&mut writer, &Message::Text(rx.to_string()), false)
+                    .await
+                    .unwrap();
+            }
+            // Stay connected
+            sleep(Duration::from_secs(10)).await;
+        });
+
+        let rpc_list = Arc::new(RwLock::new(vec![rpc("upstream", Some(&url), 1.0)]));
+        let hub = SubscriptionHub::new(rpc_list, Duration::from_secs(1), watch::channel(0).1);
+        let key = subscription_key(&json!(["newHeads"])).unwrap();
+        let mut a = hub.subscribe(&key, &json!(["newHeads"]));
+        let mut b = hub.subscribe(&key, &json!(["newHeads"]));
+
+        assert_eq!(a.recv().await.unwrap(), json!({"number": "0x10"}));
+        assert_eq!(b.recv().await.unwrap(), json!({"number": "0x10"}));
+    }
+}
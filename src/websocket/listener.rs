@@ -0,0 +1,451 @@
+// WebSocket listener for clients.
+//
+// `eth_subscribe` and `eth_unsubscribe` are handled here, through the hub. Everything
+// else is relayed to our own HTTP listener, so it goes through the cache, rate limits
+// and everything else regular requests do.
+use crate::{
+    logging::filter::{
+        log_sampled,
+        LogLevel,
+    },
+    tls::acceptor::CertAcceptor,
+    websocket::{
+        frame::{
+            write_message,
+            Message,
+            MessageReader,
+        },
+        handshake::accept,
+        hub::{
+            subscription_key,
+            SubscriptionHub,
+        },
+    },
+};
+
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::Arc,
+};
+
+use reqwest::Client;
+use serde_json::{
+    json,
+    Value,
+};
+use tokio::{
+    io::{
+        AsyncRead,
+        AsyncWrite,
+        BufReader,
+    },
+    net::TcpListener,
+    sync::{
+        broadcast,
+        mpsc,
+    },
+    task::JoinHandle,
+};
+
+// Messages we queue for a client before we stop reading its requests
+const BACKLOG: usize = 256;
+
+fn ws_error(id: &Value, code: i64, message: &str) -> Value {
+    json!({
+        "id": id,
+        "jsonrpc": "2.0",
+        "error": {
+            "code": code,
+            "message": message,
+        },
+    })
+}
+
+// Where we relay requests that aren't subscriptions to
+#[derive(Debug, Clone)]
+struct Relay {
+    client: Client,
+    url: String,
+}
+
+impl Relay {
+    async fn send(&self, tx: &Value, api_key: Option<&str>) -> Value {
+        let mut request = self.client.post(&self.url).json(tx);
+        if let Some(api_key) = api_key {
+            request = request.header("x-api-key", api_key);
+        }
+
+        let rx = match request.send().await {
+            Ok(rx) => rx.json::<Value>().await,
+            Err(err) => Err(err),
+        };
+        rx.unwrap_or_else(|err| ws_error(&tx["id"], -32603, &err.to_string()))
+    }
+}
+
+// Subscriptions of a single client
+struct Connection {
+    hub: Arc<SubscriptionHub>,
+    // Client subscription id -> hub key and the task forwarding its events
+    subscriptions: HashMap<String, (String, JoinHandle<()>)>,
+    max_subscriptions: usize,
+    outgoing: mpsc::Sender<Message>,
+}
+
+impl Connection {
+    // Subscribe to `params`, answering request `id` before the first event goes out
+    async fn subscribe(&mut self, id: &Value, params: &Value) -> Option<Value> {
+        let key = match subscription_key(params) {
+            Ok(key) => key,
+            Err(err) => return Some(ws_error(id, -32602, &err)),
+        };
+        if self.subscriptions.len() >= self.max_subscriptions {
+            return Some(ws_error(id, -32005, "Too many subscriptions"));
+        }
+
+        let events = self.hub.subscribe(&key, params);
+        let subscription = self.hub.new_id();
+        let rx = json!({
+            "id": id,
+            "jsonrpc": "2.0",
+            "result": subscription,
+        });
+        if self
+            .outgoing
+            .send(Message::Text(rx.to_string()))
+            .await
+            .is_err()
+        {
+            self.hub.unsubscribe(&key);
+            return None;
+        }
+
+        let forward = tokio::task::spawn(forward_events(
+            subscription.clone(),
+            events,
+            self.outgoing.clone(),
+        ));
+        self.subscriptions.insert(subscription, (key, forward));
+
+        None
+    }
+
+    fn unsubscribe(&mut self, subscription: &str) -> bool {
+        match self.subscriptions.remove(subscription) {
+            Some((key, forward)) => {
+                forward.abort();
+                self.hub.unsubscribe(&key);
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Answer `tx`, None if we already did
+    async fn handle(&mut self, tx: Value, relay: &Relay, api_key: Option<&str>) -> Option<Value> {
+        let id = tx["id"].clone();
+        match tx["method"].as_str() {
+            Some("eth_subscribe") => self.subscribe(&id, &tx["params"]).await,
+            Some("eth_unsubscribe") => {
+                let unsubscribed = tx["params"][0]
+                    .as_str()
+                    .is_some_and(|subscription| self.unsubscribe(subscription));
+                Some(json!({
+                    "id": id,
+                    "jsonrpc": "2.0",
+                    "result": unsubscribed,
+                }))
+            }
+            _ => Some(relay.send(&tx, api_key).await),
+        }
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        for (key, forward) in self.subscriptions.values() {
+            forward.abort();
+            self.hub.unsubscribe(key);
+        }
+    }
+}
+
+// Send events of the hub to the client as `eth_subscription` notifications
+async fn forward_events(
+    subscription: String,
+    mut events: broadcast::Receiver<Value>,
+    outgoing: mpsc::Sender<Message>,
+) {
+    loop {
+        let event = match events.recv().await {
+            Ok(event) => event,
+            Err(broadcast::error::RecvError::Lagged(missed)) => {
+                println!(
+                    "\x1b[93mWrn:\x1b[0m WebSocket client too slow, missed {} events",
+                    missed
+                );
+                continue;
+            }
+            Err(broadcast::error::RecvError::Closed) => return,
+        };
+
+        let notification = json!({
+            "jsonrpc": "2.0",
+            "method": "eth_subscription",
+            "params": {
+                "subscription": subscription,
+                "result": event,
+            },
+        });
+        if outgoing
+            .send(Message::Text(notification.to_string()))
+            .await
+            .is_err()
+        {
+            return;
+        }
+    }
+}
+
+async fn serve_connection<S>(
+    stream: S,
+    socketaddr: SocketAddr,
+    hub: Arc<SubscriptionHub>,
+    relay: Relay,
+    max_subscriptions: usize,
+) where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let mut stream = BufReader::new(stream);
+    let head = match accept(&mut stream).await {
+        Ok(head) => head,
+        Err(err) => {
+            println!("\x1b[93mWrn:\x1b[0m Could not accept WebSocket: {}", err);
+            return;
+        }
+    };
+    let api_key = head.header("x-api-key").map(str::to_string);
+
+    let (reader, mut writer) = tokio::io::split(stream);
+    let mut reader = MessageReader::new(reader);
+    let (outgoing, mut outgoing_rx) = mpsc::channel::<Message>(BACKLOG);
+
+    let writer = tokio::task::spawn(async move {
+        while let Some(message) = outgoing_rx.recv().await {
+            if write_message(&mut writer, &message, false).await.is_err()
+                || message == Message::Close
+            {
+                return;
+            }
+        }
+    });
+
+    let mut connection = Connection {
+        hub: Arc::clone(&hub),
+        subscriptions: HashMap::new(),
+        max_subscriptions,
+        outgoing: outgoing.clone(),
+    };
+
+    loop {
+        let tx = match reader.next().await {
+            Ok(Some(Message::Text(text))) => serde_json::from_str::<Value>(&text),
+            Ok(Some(Message::Binary(data))) => serde_json::from_slice::<Value>(&data),
+            Ok(Some(Message::Ping(data))) => {
+                let _ = outgoing.send(Message::Pong(data)).await;
+                continue;
+            }
+            Ok(Some(Message::Pong(_))) => continue,
+            Ok(Some(Message::Close)) | Ok(None) | Err(_) => break,
+        };
+
+        let rx = match tx {
+            Ok(tx) => connection.handle(tx, &relay, api_key.as_deref()).await,
+            Err(_) => Some(ws_error(&Value::Null, -32700, "Parse error")),
+        };
+        if let Some(rx) = rx {
+            if outgoing.send(Message::Text(rx.to_string())).await.is_err() {
+                break;
+            }
+        }
+    }
+
+    drop(connection);
+    let _ = outgoing.send(Message::Close).await;
+    drop(outgoing);
+    let _ = writer.await;
+
+    if log_sampled("websocket", LogLevel::Info) {
+        println!(
+            "\x1b[35mInfo:\x1b[0m WebSocket client {} disconnected, {} upstream subscriptions open",
+            socketaddr,
+            hub.upstream_subscriptions()
+        );
+    }
+}
+
+// Accept WebSocket clients on `address`, relaying their regular requests to
+// our HTTP listener at `http_address`, under `http_path` for chains other than the main one.
+//
+// With `tls`, clients connect over WSS, and our HTTP listener is behind TLS too.
+pub async fn listen_for_websockets(
+    hub: Arc<SubscriptionHub>,
+    address: SocketAddr,
+    http_address: SocketAddr,
+    http_path: &str,
+    max_subscriptions: usize,
+    tls: Option<Arc<CertAcceptor>>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let listener = TcpListener::bind(address).await?;
+    println!(
+        "\x1b[35mInfo:\x1b[0m Bound WebSocket listener to: {}",
+        listener.local_addr()?
+    );
+
+    // The certificate is for our public hostname, not the address we relay to
+    let relay = Relay {
+        client: Client::builder()
+            .danger_accept_invalid_certs(tls.is_some())
+            .build()?,
+        url: format!(
+            "{}://{}{}",
+            if tls.is_some() { "https" } else { "http" },
+            http_address,
+            http_path
+        ),
+    };
+
+    loop {
+        let (stream, socketaddr) = listener.accept().await?;
+        if log_sampled("websocket", LogLevel::Info) {
+            println!(
+                "\x1b[35mInfo:\x1b[0m WebSocket connection from: {}",
+                socketaddr
+            );
+        }
+        let (hub, relay) = (Arc::clone(&hub), relay.clone());
+        match &tls {
+            Some(tls) => {
+                let tls = Arc::clone(tls);
+                tokio::task::spawn(async move {
+                    match tls.accept(stream).await {
+                        Ok(stream) => {
+                            serve_connection(stream, socketaddr, hub, relay, max_subscriptions)
+                                .await
+                        }
+                        Err(err) => {
+                            println!(
+                                "\x1b[93mWrn:\x1b[0m TLS handshake with {} failed: {}",
+                                socketaddr, err
+                            );
+                        }
+                    }
+                });
+            }
+            None => {
+                tokio::task::spawn(serve_connection(
+                    stream,
+                    socketaddr,
+                    hub,
+                    relay,
+                    max_subscriptions,
+                ));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        sync::RwLock,
+        time::Duration,
+    };
+
+    fn connection(max_subscriptions: usize) -> (Connection, mpsc::Receiver<Message>) {
+        let hub = Arc::new(SubscriptionHub::new(
+            Arc::new(RwLock::new(Vec::new())),
+            Duration::from_secs(1),
+            tokio::sync::watch::channel(0).1,
+        ));
+        let (outgoing, outgoing_rx) = mpsc::channel(BACKLOG);
+        let connection = Connection {
+            hub,
+            subscriptions: HashMap::new(),
+            max_subscriptions,
+            outgoing,
+        };
+        (connection, outgoing_rx)
+    }
+
+    fn text(message: Message) -> Value {
+        match message {
+            Message::Text(text) => serde_json::from_str(&text).unwrap(),
+            message => panic!("not text: {:?}", message),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_subscribe() {
+        let (mut connection, mut outgoing_rx) = connection(1);
+        let relay = Relay {
+            client: Client::new(),
+            url: "http://127.0.0.1:0".to_string(),
+        };
+
+        let tx =
+            json!({"id": 1, "jsonrpc": "2.0", "method": "eth_subscribe", "params": ["newHeads"]});
+        assert_eq!(connection.handle(tx, &relay, None).await, None);
+        let rx = text(outgoing_rx.recv().await.unwrap());
+        let subscription = rx["result"].as_str().unwrap().to_string();
+
+        let tx =
+            json!({"id": 2, "jsonrpc": "2.0", "method": "eth_subscribe", "params": ["newHeads"]});
+        let rx = connection.handle(tx, &relay, None).await.unwrap();
+        assert_eq!(rx["error"]["message"], "Too many subscriptions");
+
+        let tx = json!({"id": 3, "jsonrpc": "2.0", "method": "eth_unsubscribe", "params": [subscription]});
+        let rx = connection.handle(tx, &relay, None).await.unwrap();
+        assert_eq!(rx["result"], true);
+        assert_eq!(connection.hub.upstream_subscriptions(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_forward_events() {
+        let (events, events_rx) = broadcast::channel(BACKLOG);
+        let (outgoing, mut outgoing_rx) = mpsc::channel(BACKLOG);
+        tokio::task::spawn(forward_events("0x01".to_string(), events_rx, outgoing));
+
+        events.send(json!({"number": "0x10"})).unwrap();
+        let notification = text(outgoing_rx.recv().await.unwrap());
+        assert_eq!(notification["method"], "eth_subscription");
+        assert_eq!(notification["params"]["subscription"], "0x01");
+        assert_eq!(notification["params"]["result"]["number"], "0x10");
+
+        // Ends with the subscription
+        drop(events);
+        assert_eq!(outgoing_rx.recv().await, None);
+    }
+
+    #[tokio::test]
+    async fn test_drop_unsubscribes() {
+        let (mut connection, _outgoing_rx) = connection(4);
+        let relay = Relay {
+            client: Client::new(),
+            url: "http://127.0.0.1:0".to_string(),
+        };
+        let hub = Arc::clone(&connection.hub);
+
+        for params in [json!(["newHeads"]), json!(["logs", {}])] {
+            let tx =
+                json!({"id": 1, "jsonrpc": "2.0", "method": "eth_subscribe", "params": params});
+            connection.handle(tx, &relay, None).await;
+        }
+        assert_eq!(hub.upstream_subscriptions(), 2);
+
+        drop(connection);
+        assert_eq!(hub.upstream_subscriptions(), 0);
+    }
+}
@@ -0,0 +1,4 @@
+pub mod frame;
+pub mod handshake;
+pub mod hub;
+pub mod listener;